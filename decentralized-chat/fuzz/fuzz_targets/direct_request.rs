@@ -0,0 +1,22 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use libp2p_demo::{
+    envelope::{self, Opened},
+    message::DirectRequest,
+};
+
+// Direct requests are parsed as JSON by the request-response codec before
+// the node opens their envelope.
+fuzz_target!(|data: &[u8]| {
+    let Ok(value) = serde_json::from_slice(data) else {
+        return;
+    };
+    if let Ok(Opened::Known(request)) = envelope::open::<DirectRequest>(value) {
+        if let Some(chat_message) = request.chat_message() {
+            let _ = chat_message.verify_signature();
+            let _ = (chat_message.sender(), chat_message.short_id());
+            let _ = chat_message.display_text();
+        }
+    }
+});
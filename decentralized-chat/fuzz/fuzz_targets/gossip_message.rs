@@ -0,0 +1,19 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use libp2p_demo::{
+    envelope::{self, Opened},
+    message::GossipMessage,
+};
+
+// Gossip arrives as the raw bytes of a gossipsub message.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(Opened::Known(GossipMessage::Chat(chat_message))) =
+        envelope::open_slice::<GossipMessage>(data)
+    {
+        let _ = chat_message.verify_signature();
+        let _ = chat_message.is_expired(u64::MAX);
+        let _ = (chat_message.sender(), chat_message.short_id());
+        let _ = chat_message.display_text();
+    }
+});
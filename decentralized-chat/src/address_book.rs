@@ -0,0 +1,135 @@
+use libp2p::{Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fs, io, path::Path};
+
+/// Most addresses kept for each peer; the oldest are dropped past it.
+pub const MAX_ADDRESSES: usize = 8;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub addresses: Vec<Multiaddr>,
+    /// Unix timestamp (seconds) of the last time the peer was discovered.
+    pub last_seen: u64,
+    /// Only heard of through peer exchange, so `last_seen` is the sharer's
+    /// word rather than ours.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub learned: bool,
+}
+
+/// Addresses of every peer we have learned about, persisted across restarts.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct AddressBook {
+    entries: HashMap<PeerId, Entry>,
+    /// The key fingerprint of each peer marked with `/verify`. Kept when the
+    /// peer's addresses are pruned.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    verified: HashMap<PeerId, String>,
+}
+
+impl AddressBook {
+    /// Loads the book from `path`, starting empty if the file does not exist yet.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        match fs::read(path) {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(AddressBook::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, serde_json::to_vec_pretty(self)?)
+    }
+
+    /// Records `address` for `peer`, seen at `now`. Returns whether the
+    /// address is new, and so whether the book is worth saving.
+    pub fn record(&mut self, peer: PeerId, address: Multiaddr, now: u64) -> bool {
+        let entry = self.entries.entry(peer).or_insert_with(|| Entry {
+            addresses: Vec::new(),
+            last_seen: now,
+            learned: false,
+        });
+
+        entry.last_seen = now;
+        entry.learned = false;
+        // A known address moves up to be the newest.
+        let new = match entry.addresses.iter().position(|known| *known == address) {
+            Some(index) => {
+                entry.addresses.remove(index);
+                false
+            }
+            None => true,
+        };
+        entry.addresses.push(address);
+        if entry.addresses.len() > MAX_ADDRESSES {
+            entry.addresses.remove(0);
+        }
+        new
+    }
+
+    /// Records that `peer`, if known, was still there at `now`.
+    pub fn seen(&mut self, peer: &PeerId, now: u64) {
+        if let Some(entry) = self.entries.get_mut(peer) {
+            entry.last_seen = entry.last_seen.max(now);
+            entry.learned = false;
+        }
+    }
+
+    /// Adds the addresses of `other` to the entry for `peer`, keeping the most
+    /// recent `last_seen` of the two. A learned `other` doesn't make a peer we
+    /// have seen ourselves look any more recent.
+    pub fn merge(&mut self, peer: PeerId, other: &Entry) {
+        let entry = self.entries.entry(peer).or_insert_with(|| Entry {
+            addresses: Vec::new(),
+            last_seen: other.last_seen,
+            learned: other.learned,
+        });
+
+        // Learned addresses only take spare room beside ones we've seen.
+        let evict = !other.learned || entry.learned;
+        if evict {
+            entry.last_seen = entry.last_seen.max(other.last_seen);
+            entry.learned = other.learned;
+        }
+        for address in &other.addresses {
+            if entry.addresses.contains(address) {
+                continue;
+            }
+            if entry.addresses.len() >= MAX_ADDRESSES {
+                if !evict {
+                    break;
+                }
+                entry.addresses.remove(0);
+            }
+            entry.addresses.push(address.clone());
+        }
+    }
+
+    /// Drops every entry not seen within the last `max_age` seconds, except
+    /// those of peers `keep` holds on to however old.
+    pub fn prune(&mut self, max_age: u64, now: u64, keep: impl Fn(&PeerId) -> bool) {
+        self.entries
+            .retain(|peer, entry| keep(peer) || now.saturating_sub(entry.last_seen) <= max_age);
+    }
+
+    pub fn get(&self, peer: &PeerId) -> Option<&Entry> {
+        self.entries.get(peer)
+    }
+
+    /// Marks `peer` as verified to hold the key with `fingerprint`.
+    pub fn verify(&mut self, peer: PeerId, fingerprint: String) {
+        self.verified.insert(peer, fingerprint);
+    }
+
+    /// The fingerprint `peer` was verified with, if it was.
+    pub fn verified(&self, peer: &PeerId) -> Option<&str> {
+        self.verified.get(peer).map(String::as_str)
+    }
+
+    /// Entries ordered from most to least recently seen, those seen ourselves
+    /// before learned ones however recent those claim to be.
+    pub fn most_recent(&self) -> Vec<(&PeerId, &Entry)> {
+        let mut entries: Vec<_> = self.entries.iter().collect();
+        entries.sort_by_key(|(_, entry)| (entry.learned, std::cmp::Reverse(entry.last_seen)));
+        entries
+    }
+}
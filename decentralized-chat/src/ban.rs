@@ -0,0 +1,101 @@
+use libp2p::PeerId;
+use std::{
+    collections::HashMap,
+    fmt,
+    time::{Duration, Instant},
+};
+
+/// Something a peer sent that a well-behaved peer never would.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    MalformedRequest,
+    MalformedResponse,
+    MalformedGossip,
+    /// An edit or delete of a message the peer didn't write.
+    UnauthorizedChange,
+    /// A chat message whose signature doesn't match its claimed author.
+    BadSignature,
+    /// A chat message sent by a peer other than its author, or signed for
+    /// another room or peer.
+    Misattributed,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::MalformedRequest => write!(f, "malformed request"),
+            Violation::MalformedResponse => write!(f, "malformed response"),
+            Violation::MalformedGossip => write!(f, "malformed gossip message"),
+            Violation::UnauthorizedChange => write!(f, "change to another peer's message"),
+            Violation::BadSignature => write!(f, "badly signed message"),
+            Violation::Misattributed => write!(f, "misattributed message"),
+        }
+    }
+}
+
+/// A peer that crossed the violation threshold and should be blocked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ban {
+    pub peer: PeerId,
+    pub violations: u32,
+    /// The violation that pushed the peer over the threshold.
+    pub last: Violation,
+}
+
+#[derive(Debug)]
+struct Record {
+    count: u32,
+    last_violation: Instant,
+}
+
+/// Counts violations per peer. A peer's count is forgotten once it has gone
+/// `cooldown` without a violation.
+#[derive(Debug)]
+pub struct ViolationTracker {
+    threshold: u32,
+    cooldown: Duration,
+    records: HashMap<PeerId, Record>,
+}
+
+impl ViolationTracker {
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        ViolationTracker {
+            threshold,
+            cooldown,
+            records: HashMap::new(),
+        }
+    }
+
+    /// Counts a violation by `peer`, returning a ban once the peer reaches the
+    /// threshold. The count starts over after a ban.
+    pub fn record(&mut self, peer: PeerId, violation: Violation, now: Instant) -> Option<Ban> {
+        let record = self.records.entry(peer).or_insert(Record {
+            count: 0,
+            last_violation: now,
+        });
+        if now.duration_since(record.last_violation) >= self.cooldown {
+            record.count = 0;
+        }
+        record.count += 1;
+        record.last_violation = now;
+
+        if record.count < self.threshold {
+            return None;
+        }
+
+        let violations = record.count;
+        self.records.remove(&peer);
+        Some(Ban {
+            peer,
+            violations,
+            last: violation,
+        })
+    }
+
+    /// Forgets peers that have behaved for at least the cooldown.
+    pub fn prune(&mut self, now: Instant) {
+        let cooldown = self.cooldown;
+        self.records
+            .retain(|_, record| now.duration_since(record.last_violation) < cooldown);
+    }
+}
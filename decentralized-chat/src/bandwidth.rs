@@ -0,0 +1,177 @@
+use libp2p::{
+    core::upgrade::{InboundConnectionUpgrade, OutboundConnectionUpgrade, UpgradeInfo},
+    futures::{future::BoxFuture, AsyncRead, AsyncWrite, FutureExt, TryFutureExt},
+    metrics::Registry,
+    PeerId,
+};
+use prometheus_client::{
+    encoding::{EncodeLabelSet, EncodeLabelValue},
+    metrics::{counter::Counter, family::Family},
+};
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// Name of the per-peer byte counters in the metrics registry; the text
+/// format adds `_total`.
+pub const PEER_BANDWIDTH_METRIC: &str = "chat_peer_bandwidth_bytes";
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct PeerLabels {
+    peer: String,
+    direction: Direction,
+}
+
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, EncodeLabelValue)]
+enum Direction {
+    Inbound,
+    Outbound,
+}
+
+/// Counts the bytes each peer's connections carry, by wrapping the secured
+/// stream of every connection once the handshake names the peer. The
+/// counters are atomics taken when the connection is made, so counting a
+/// read or write costs one relaxed add. Bytes of the handshake and of the
+/// encryption itself aren't counted; the muxer's framing is.
+#[derive(Debug, Clone, Default)]
+pub struct PeerBandwidth {
+    counters: Family<PeerLabels, Counter>,
+}
+
+impl PeerBandwidth {
+    /// Starts counting, exposed in `registry` as `PEER_BANDWIDTH_METRIC`.
+    pub fn register(registry: &mut Registry) -> Self {
+        let bandwidth = PeerBandwidth::default();
+        registry.register(
+            PEER_BANDWIDTH_METRIC,
+            "Bytes sent and received over each peer's connections",
+            bandwidth.counters.clone(),
+        );
+        bandwidth
+    }
+
+    /// Wraps the security upgrade `inner` so the connections it secures are
+    /// counted.
+    pub fn upgrade<U>(&self, inner: U) -> CountBandwidth<U> {
+        CountBandwidth {
+            inner,
+            bandwidth: self.clone(),
+        }
+    }
+
+    fn wrap<S>(&self, peer: PeerId, stream: S) -> Counted<S> {
+        let counter = |direction| {
+            self.counters
+                .get_or_create(&PeerLabels {
+                    peer: peer.to_string(),
+                    direction,
+                })
+                .clone()
+        };
+        Counted {
+            inner: stream,
+            inbound: counter(Direction::Inbound),
+            outbound: counter(Direction::Outbound),
+        }
+    }
+}
+
+/// A security upgrade whose streams are counted by a `PeerBandwidth`.
+#[derive(Debug, Clone)]
+pub struct CountBandwidth<U> {
+    inner: U,
+    bandwidth: PeerBandwidth,
+}
+
+impl<U: UpgradeInfo> UpgradeInfo for CountBandwidth<U> {
+    type Info = U::Info;
+    type InfoIter = U::InfoIter;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        self.inner.protocol_info()
+    }
+}
+
+impl<T, U, S> InboundConnectionUpgrade<T> for CountBandwidth<U>
+where
+    U: InboundConnectionUpgrade<T, Output = (PeerId, S)>,
+    U::Future: Send + 'static,
+{
+    type Output = (PeerId, Counted<S>);
+    type Error = U::Error;
+    type Future = BoxFuture<'static, Result<Self::Output, Self::Error>>;
+
+    fn upgrade_inbound(self, socket: T, info: Self::Info) -> Self::Future {
+        let bandwidth = self.bandwidth;
+        self.inner
+            .upgrade_inbound(socket, info)
+            .map_ok(move |(peer, stream)| (peer, bandwidth.wrap(peer, stream)))
+            .boxed()
+    }
+}
+
+impl<T, U, S> OutboundConnectionUpgrade<T> for CountBandwidth<U>
+where
+    U: OutboundConnectionUpgrade<T, Output = (PeerId, S)>,
+    U::Future: Send + 'static,
+{
+    type Output = (PeerId, Counted<S>);
+    type Error = U::Error;
+    type Future = BoxFuture<'static, Result<Self::Output, Self::Error>>;
+
+    fn upgrade_outbound(self, socket: T, info: Self::Info) -> Self::Future {
+        let bandwidth = self.bandwidth;
+        self.inner
+            .upgrade_outbound(socket, info)
+            .map_ok(move |(peer, stream)| (peer, bandwidth.wrap(peer, stream)))
+            .boxed()
+    }
+}
+
+/// A secured stream adding what it reads and writes to its peer's counters.
+#[derive(Debug)]
+pub struct Counted<S> {
+    inner: S,
+    inbound: Counter,
+    outbound: Counter,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Counted<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(len)) = poll {
+            this.inbound.inc_by(len as u64);
+        }
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Counted<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let poll = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(len)) = poll {
+            this.outbound.inc_by(len as u64);
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
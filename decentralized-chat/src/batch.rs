@@ -0,0 +1,106 @@
+use crate::message::ChatMessage;
+use libp2p::PeerId;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// Coalesces the direct messages sent to one peer in quick succession into
+/// a single `DirectRequest::Batch`, so a script sending hundreds of them
+/// doesn't open a stream for each. Only `/msg`s are batched: anything else,
+/// and any message to a peer that had nothing sent to it within the window,
+/// goes out at once.
+#[derive(Debug)]
+pub struct Batcher {
+    window: Duration,
+    max_messages: usize,
+    peers: HashMap<PeerId, Waiting>,
+}
+
+#[derive(Debug)]
+struct Waiting {
+    /// Oldest first.
+    messages: Vec<ChatMessage>,
+    /// When the last request went to the peer.
+    last_sent: Option<Instant>,
+}
+
+impl Waiting {
+    /// Whether nothing went to the peer within `window` of `now`.
+    fn quiet(&self, now: Instant, window: Duration) -> bool {
+        self.last_sent
+            .is_none_or(|last| now.duration_since(last) >= window)
+    }
+}
+
+impl Batcher {
+    /// Sends a batch at most `window` after the previous request to the
+    /// same peer, or as soon as it holds `max_messages`. A zero window, or
+    /// a limit of one message, turns batching off.
+    pub fn new(window: Duration, max_messages: usize) -> Self {
+        Batcher {
+            window,
+            max_messages,
+            peers: HashMap::new(),
+        }
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Whether no message is waiting for its batch.
+    pub fn is_empty(&self) -> bool {
+        self.peers
+            .values()
+            .all(|waiting| waiting.messages.is_empty())
+    }
+
+    /// Adds `message` for `peer`. Returns the messages to send `peer` now,
+    /// in order: `message` alone when nothing went to the peer within the
+    /// window, the whole batch once it is full, and nothing otherwise.
+    pub fn push(&mut self, peer: PeerId, message: ChatMessage, now: Instant) -> Vec<ChatMessage> {
+        let window = self.window;
+        let waiting = self.peers.entry(peer).or_insert(Waiting {
+            messages: Vec::new(),
+            last_sent: None,
+        });
+        if waiting.messages.is_empty() && waiting.quiet(now, window) {
+            waiting.last_sent = Some(now);
+            return vec![message];
+        }
+
+        waiting.messages.push(message);
+        if waiting.messages.len() >= self.max_messages {
+            waiting.last_sent = Some(now);
+            return std::mem::take(&mut waiting.messages);
+        }
+        Vec::new()
+    }
+
+    /// The batches whose window has passed by `now`, by recipient. Peers
+    /// with nothing waiting for a whole window are forgotten.
+    pub fn due(&mut self, now: Instant) -> Vec<(PeerId, Vec<ChatMessage>)> {
+        let window = self.window;
+        let mut due = Vec::new();
+        self.peers.retain(|peer, waiting| {
+            if !waiting.quiet(now, window) {
+                return true;
+            }
+            if waiting.messages.is_empty() {
+                return false;
+            }
+            waiting.last_sent = Some(now);
+            due.push((*peer, std::mem::take(&mut waiting.messages)));
+            true
+        });
+        due
+    }
+
+    /// Drops the messages waiting for `peer`. Returns how many there were.
+    pub fn cancel_peer(&mut self, peer: &PeerId) -> usize {
+        self.peers
+            .remove(peer)
+            .map_or(0, |waiting| waiting.messages.len())
+    }
+}
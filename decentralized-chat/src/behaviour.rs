@@ -0,0 +1,304 @@
+use crate::{
+    bandwidth::PeerBandwidth,
+    config::{Config, Muxer, Security},
+    security::{ConnectionSecurity, SecurityProtocol},
+};
+use libp2p::{
+    allow_block_list, connection_limits,
+    core::{transport::MemoryTransport, upgrade::Version},
+    gossipsub, identify, identity, kad, mdns,
+    metrics::Registry,
+    noise, rendezvous,
+    request_response::{self, ProtocolSupport, ResponseChannel},
+    swarm::{behaviour::toggle::Toggle, NetworkBehaviour},
+    tcp, tls, upnp, yamux, StreamProtocol, Swarm, SwarmBuilder, Transport,
+};
+use libp2p_mplex::MplexConfig;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{error::Error, fmt, time::Duration};
+
+/// The direct request-response protocol.
+pub const CHAT_PROTOCOL: StreamProtocol = StreamProtocol::new("/chat/json/1.0.0");
+
+/// `CHAT_PROTOCOL` by another name, offered by peers that read compressed
+/// envelopes unless `config.protocol.compression` turns it off. Peers that
+/// list it in identify are sent payloads over
+/// `config.protocol.compress_above_bytes` compressed.
+pub const COMPRESSED_CHAT_PROTOCOL: StreamProtocol = StreamProtocol::new("/chat/json/1.0.0/zstd");
+
+/// The protocol's unversioned name from before it was versioned, still
+/// spoken unless `config.protocol.legacy` turns it off.
+pub const LEGACY_CHAT_PROTOCOL: StreamProtocol = StreamProtocol::new("/my-json-protocol");
+
+/// Streams allowed at once per mplex connection. Each behaviour keeps one or
+/// two open, and request-response one per request in flight.
+const MPLEX_MAX_STREAMS: usize = 128;
+
+/// Frames mplex buffers per stream before resetting it, twice its default.
+const MPLEX_MAX_BUFFERED_FRAMES: usize = 64;
+
+/// Version of the direct protocol a peer speaks, as learned from identify.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProtocolVersion {
+    /// `/my-json-protocol`. These peers predate sequence numbers and reject
+    /// signatures that cover one.
+    Legacy,
+    /// `/chat/json/1.0.0`.
+    V1,
+}
+
+impl ProtocolVersion {
+    /// The newest version among the protocols a peer supports, if any.
+    pub fn newest(protocols: &[StreamProtocol]) -> Option<ProtocolVersion> {
+        protocols
+            .iter()
+            .filter_map(|protocol| {
+                if *protocol == CHAT_PROTOCOL {
+                    Some(ProtocolVersion::V1)
+                } else if *protocol == LEGACY_CHAT_PROTOCOL {
+                    Some(ProtocolVersion::Legacy)
+                } else {
+                    None
+                }
+            })
+            .max()
+    }
+}
+
+impl fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolVersion::Legacy => write!(f, "legacy"),
+            ProtocolVersion::V1 => write!(f, "1.0.0"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Request {
+    pub data: Value,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Response {
+    pub data: Value,
+}
+
+#[derive(NetworkBehaviour)]
+pub struct CustomBehaviour {
+    pub request_response: request_response::json::Behaviour<Request, Response>,
+    pub mdns: Toggle<mdns::tokio::Behaviour>,
+    pub gossipsub: gossipsub::Behaviour,
+    pub identify: identify::Behaviour,
+    pub block_list: allow_block_list::Behaviour<allow_block_list::BlockedPeers>,
+    pub connection_limits: connection_limits::Behaviour,
+    pub rendezvous: rendezvous::client::Behaviour,
+    /// Only started when `config.rendezvous.server` is set.
+    pub rendezvous_server: Toggle<rendezvous::server::Behaviour>,
+    pub kademlia: kad::Behaviour<kad::store::MemoryStore>,
+    /// Only started when `config.swarm.upnp` is set.
+    pub upnp: Toggle<upnp::tokio::Behaviour>,
+    /// Carries payloads too large for a request, see `stream::Streams`.
+    pub stream: libp2p_stream::Behaviour,
+    /// Which protocol secured each connection, as the transport recorded it.
+    pub security: ConnectionSecurity,
+}
+
+impl CustomBehaviour {
+    /// Builds the chat behaviour for `key`. mDNS and UPnP are only started
+    /// when `local_network` is set, so swarms that don't touch the network
+    /// can skip them, and then only if `config.mdns` and `config.swarm` turn
+    /// them on.
+    pub fn new(
+        key: &identity::Keypair,
+        config: &Config,
+        local_network: bool,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        // Protocols are offered in order, so peers that know the versioned
+        // one pick it.
+        let mut protocols = Vec::new();
+        if config.protocol.compression {
+            protocols.push((COMPRESSED_CHAT_PROTOCOL, ProtocolSupport::Full));
+        }
+        protocols.push((CHAT_PROTOCOL, ProtocolSupport::Full));
+        if config.protocol.legacy {
+            protocols.push((LEGACY_CHAT_PROTOCOL, ProtocolSupport::Full));
+        }
+        let request_response_behaviour =
+            request_response::json::Behaviour::<Request, Response>::new(
+                protocols,
+                request_response::Config::default().with_request_timeout(Duration::from_secs(
+                    config.protocol.request_timeout_secs,
+                )),
+            );
+
+        let mdns_behaviour = if local_network && config.mdns.enabled {
+            Some(mdns::tokio::Behaviour::new(
+                config.mdns.build()?,
+                key.public().to_peer_id(),
+            )?)
+        } else {
+            None
+        };
+
+        let gossipsub_behaviour = gossipsub::Behaviour::new(
+            gossipsub::MessageAuthenticity::Signed(key.clone()),
+            config.gossipsub.build()?,
+        )?;
+
+        let identify_behaviour = identify::Behaviour::new(identify::Config::new(
+            "/decentralized-chat/1.0.0".to_string(),
+            key.public(),
+        ));
+
+        Ok(CustomBehaviour {
+            request_response: request_response_behaviour,
+            mdns: mdns_behaviour.into(),
+            gossipsub: gossipsub_behaviour,
+            identify: identify_behaviour,
+            block_list: allow_block_list::Behaviour::default(),
+            connection_limits: connection_limits::Behaviour::new(config.connection_limits.build()?),
+            rendezvous: rendezvous::client::Behaviour::new(key.clone()),
+            rendezvous_server: config
+                .rendezvous
+                .server
+                .then(|| rendezvous::server::Behaviour::new(rendezvous::server::Config::default()))
+                .into(),
+            kademlia: kad::Behaviour::with_config(
+                key.public().to_peer_id(),
+                kad::store::MemoryStore::new(key.public().to_peer_id()),
+                config.dht.build(),
+            ),
+            upnp: (local_network && config.swarm.upnp)
+                .then(upnp::tokio::Behaviour::default)
+                .into(),
+            stream: libp2p_stream::Behaviour::new(),
+            security: ConnectionSecurity::default(),
+        })
+    }
+
+    /// Answers a direct request on `channel`. Returns `false`, with the
+    /// response dropped, if the requester disconnected or gave up waiting
+    /// since; that is no reason to stop.
+    pub fn respond(&mut self, channel: ResponseChannel<Response>, response: Response) -> bool {
+        self.request_response
+            .send_response(channel, response)
+            .is_ok()
+    }
+}
+
+/// Builds the swarm used by the chat node: TCP and WebSocket (`/ws`, plus
+/// `/wss` for dialing) secured with `config.swarm.security()`, limited to
+/// `config.swarm.network_id` with noise, and multiplexed
+/// with yamux, DNS resolution of `/dns*` addresses, and mDNS discovery and
+/// UPnP port mapping unless `config.mdns` and `config.swarm` turn them off. Idle connections are closed after
+/// `config.swarm`'s timeout. Bytes sent and received are counted in
+/// `registry`, by transport and by peer, and the protocol that secured each
+/// connection is kept by the `security` behaviour.
+pub async fn build_swarm(
+    keypair: identity::Keypair,
+    config: &Config,
+    registry: &mut Registry,
+) -> Result<Swarm<CustomBehaviour>, Box<dyn Error>> {
+    let peer_bandwidth = PeerBandwidth::register(registry);
+    let security = ConnectionSecurity::default();
+    let handshakes = security.handshakes();
+    let noise = |key: &identity::Keypair| {
+        noise_config(key, config)
+            .map(|u| handshakes.record(SecurityProtocol::Noise, peer_bandwidth.upgrade(u)))
+    };
+    let tls = |key: &identity::Keypair| {
+        tls::Config::new(key)
+            .map(|u| handshakes.record(SecurityProtocol::Tls, peer_bandwidth.upgrade(u)))
+    };
+    // Each security and muxer upgrade gives the builder a type of its own,
+    // so the chain is spelled out once per pair. Given two security upgrades,
+    // peers negotiate one of them, ours proposed in order.
+    macro_rules! build_with {
+        ($security:expr, $muxer:expr) => {
+            SwarmBuilder::with_existing_identity(keypair)
+                .with_tokio()
+                .with_tcp(tcp::Config::default(), $security, $muxer)?
+                .with_dns()?
+                .with_websocket($security, $muxer)
+                .await?
+                .with_bandwidth_metrics(registry)
+                .with_behaviour(|key| {
+                    let mut behaviour = CustomBehaviour::new(key, config, true)?;
+                    behaviour.security = security;
+                    Ok(behaviour)
+                })?
+                .with_swarm_config(|cfg| {
+                    cfg.with_idle_connection_timeout(config.swarm.idle_timeout())
+                })
+                .build()
+        };
+    }
+
+    let swarm = match (config.swarm.security(), config.swarm.muxer) {
+        (Security::Noise, Muxer::Yamux) => build_with!(noise, yamux::Config::default),
+        (Security::Noise, Muxer::Mplex) => build_with!(noise, mplex_config),
+        (Security::Tls, Muxer::Yamux) => build_with!(tls, yamux::Config::default),
+        (Security::Tls, Muxer::Mplex) => build_with!(tls, mplex_config),
+        (Security::Both, Muxer::Yamux) => build_with!((noise, tls), yamux::Config::default),
+        (Security::Both, Muxer::Mplex) => build_with!((noise, tls), mplex_config),
+    };
+    Ok(swarm)
+}
+
+/// Noise for `key`, with `config.swarm.network_id` as the handshake
+/// prologue. Both sides hash the prologue into the handshake, so it only
+/// completes between nodes with the same one.
+pub fn noise_config(
+    key: &identity::Keypair,
+    config: &Config,
+) -> Result<noise::Config, noise::Error> {
+    let noise = noise::Config::new(key)?;
+    Ok(match &config.swarm.network_id {
+        Some(network_id) => noise.with_prologue(network_id.as_bytes().to_vec()),
+        None => noise,
+    })
+}
+
+/// Mplex with room for bursts of gossip on a stream whose reader is slow,
+/// since mplex resets such a stream rather than exerting backpressure.
+fn mplex_config() -> MplexConfig {
+    let mut config = MplexConfig::new();
+    config
+        .set_max_num_streams(MPLEX_MAX_STREAMS)
+        .set_max_buffer_size(MPLEX_MAX_BUFFERED_FRAMES);
+    config
+}
+
+/// Builds a swarm over the in-process memory transport, for tests. Peers are
+/// reached by dialing `/memory/<n>` addresses; mDNS and UPnP are disabled
+/// and the default config is used.
+pub fn build_test_swarm(
+    keypair: identity::Keypair,
+) -> Result<Swarm<CustomBehaviour>, Box<dyn Error>> {
+    build_test_swarm_with_config(keypair, &Config::default())
+}
+
+/// Like `build_test_swarm`, with the behaviour configured from `config`.
+pub fn build_test_swarm_with_config(
+    keypair: identity::Keypair,
+    config: &Config,
+) -> Result<Swarm<CustomBehaviour>, Box<dyn Error>> {
+    let swarm = SwarmBuilder::with_existing_identity(keypair)
+        .with_tokio()
+        .with_other_transport(|key| {
+            Ok::<_, Box<dyn Error + Send + Sync>>(
+                MemoryTransport::default()
+                    .upgrade(Version::V1)
+                    .authenticate(noise_config(key, config)?)
+                    .multiplex(yamux::Config::default()),
+            )
+        })?
+        .with_behaviour(|key| CustomBehaviour::new(key, config, false))?
+        .with_swarm_config(|cfg| cfg.with_idle_connection_timeout(Duration::from_secs(10)))
+        .build();
+
+    Ok(swarm)
+}
@@ -0,0 +1,393 @@
+use crate::{
+    behaviour::{
+        build_swarm, build_test_swarm, CustomBehaviour, CustomBehaviourEvent, Request, Response,
+    },
+    config::{BatchConfig, Config},
+    envelope::{self, Opened},
+    message::{ChatMessage, DirectRequest, DirectResponse, GossipMessage, MessageId},
+};
+use libp2p::{
+    futures::StreamExt,
+    gossipsub, identity,
+    metrics::Registry,
+    request_response::{self, OutboundRequestId},
+    swarm::SwarmEvent,
+    Multiaddr, PeerId, Swarm,
+};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    time::{Duration, Instant},
+};
+
+const BENCH_TOPIC: &str = "bench";
+
+/// Gives up on a run once no message has arrived for this long.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy)]
+pub struct BenchConfig {
+    /// Number of messages published in the burst.
+    pub messages: usize,
+    /// Size of each message's text in bytes.
+    pub size: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct BenchReport {
+    pub config: BenchConfig,
+    /// Time from the first publish until the last message arrived, or for
+    /// direct messages was acknowledged.
+    pub elapsed: Duration,
+    pub p50: Duration,
+    pub p99: Duration,
+}
+
+impl BenchReport {
+    pub fn messages_per_sec(&self) -> f64 {
+        self.config.messages as f64 / self.elapsed.as_secs_f64()
+    }
+
+    pub fn bytes_per_sec(&self) -> f64 {
+        (self.config.messages * self.config.size) as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} messages of {} bytes in {:.3}s",
+            self.config.messages,
+            self.config.size,
+            self.elapsed.as_secs_f64()
+        )?;
+        writeln!(
+            f,
+            "throughput: {:.0} msg/s, {:.2} MiB/s",
+            self.messages_per_sec(),
+            self.bytes_per_sec() / (1024.0 * 1024.0)
+        )?;
+        write!(
+            f,
+            "latency: p50 {:.3}ms, p99 {:.3}ms",
+            self.p50.as_secs_f64() * 1000.0,
+            self.p99.as_secs_f64() * 1000.0
+        )
+    }
+}
+
+/// Connects two swarms over TCP loopback, publishes a burst of chat messages
+/// from one to the other over gossipsub and measures how fast they arrive.
+/// Both swarms are built from `node_config`, so tuning can be compared.
+pub async fn run(config: BenchConfig, node_config: &Config) -> Result<BenchReport, Box<dyn Error>> {
+    if config.messages == 0 {
+        return Err("the benchmark needs at least one message".into());
+    }
+
+    let topic = gossipsub::IdentTopic::new(BENCH_TOPIC);
+    let mut receiver = build_swarm(
+        identity::Keypair::generate_ed25519(),
+        node_config,
+        &mut Registry::default(),
+    )
+    .await?;
+    let mut sender = build_swarm(
+        identity::Keypair::generate_ed25519(),
+        node_config,
+        &mut Registry::default(),
+    )
+    .await?;
+    receiver.behaviour_mut().gossipsub.subscribe(&topic)?;
+    sender.behaviour_mut().gossipsub.subscribe(&topic)?;
+
+    receiver.listen_on("/ip4/127.0.0.1/tcp/0".parse::<Multiaddr>()?)?;
+    let address = loop {
+        if let SwarmEvent::NewListenAddr { address, .. } = receiver.select_next_some().await {
+            break address;
+        }
+    };
+    sender.dial(address)?;
+    wait_for_subscription(&mut sender, &mut receiver).await?;
+
+    let text = "x".repeat(config.size);
+    let payloads = (0..config.messages)
+        .map(|_| {
+            let chat_message = ChatMessage::new(*sender.local_peer_id(), text.clone());
+            (
+                chat_message.id,
+                envelope::seal(&GossipMessage::Chat(Box::new(chat_message))).to_string(),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let started = Instant::now();
+    let mut sent_at = HashMap::<MessageId, Instant>::with_capacity(config.messages);
+    for (id, payload) in payloads {
+        sender
+            .behaviour_mut()
+            .gossipsub
+            .publish(topic.clone(), payload)?;
+        sent_at.insert(id, Instant::now());
+    }
+
+    let mut latencies = Vec::with_capacity(config.messages);
+    while latencies.len() < config.messages {
+        let event = tokio::select! {
+            event = receiver.select_next_some() => event,
+            _ = sender.select_next_some() => continue,
+            _ = tokio::time::sleep(IDLE_TIMEOUT) => {
+                return Err(format!(
+                    "only {} of {} messages arrived",
+                    latencies.len(),
+                    config.messages
+                )
+                .into());
+            }
+        };
+
+        if let SwarmEvent::Behaviour(CustomBehaviourEvent::Gossipsub(gossipsub::Event::Message {
+            message,
+            ..
+        })) = event
+        {
+            if let Ok(Opened::Known(GossipMessage::Chat(chat_message))) =
+                envelope::open_slice(&message.data)
+            {
+                if let Some(sent) = sent_at.remove(&chat_message.id) {
+                    latencies.push(sent.elapsed());
+                }
+            }
+        }
+    }
+    let elapsed = started.elapsed();
+
+    latencies.sort();
+    Ok(BenchReport {
+        config,
+        elapsed,
+        p50: percentile(&latencies, 50),
+        p99: percentile(&latencies, 99),
+    })
+}
+
+/// Connects two swarms over the memory transport and sends a burst of direct
+/// messages from one to the other, coalesced as `batch` says, measuring how
+/// fast they are acknowledged. Run with a zero `window_ms` for a baseline of
+/// one request per message.
+pub async fn run_direct(
+    config: BenchConfig,
+    batch: &BatchConfig,
+) -> Result<BenchReport, Box<dyn Error>> {
+    if config.messages == 0 {
+        return Err("the benchmark needs at least one message".into());
+    }
+
+    let mut receiver = build_test_swarm(identity::Keypair::generate_ed25519())?;
+    let mut sender = build_test_swarm(identity::Keypair::generate_ed25519())?;
+    receiver.listen_on("/memory/0".parse::<Multiaddr>()?)?;
+    let address = loop {
+        if let SwarmEvent::NewListenAddr { address, .. } = receiver.select_next_some().await {
+            break address;
+        }
+    };
+    let receiver_id = *receiver.local_peer_id();
+    sender.dial(address)?;
+    wait_for_connection(&mut sender, &mut receiver).await?;
+
+    let text = "x".repeat(config.size);
+    let chat_messages = (0..config.messages)
+        .map(|_| ChatMessage::new(*sender.local_peer_id(), text.clone()))
+        .collect::<Vec<_>>();
+
+    let mut batcher = batch.batcher();
+    let mut flush_interval = tokio::time::interval(batcher.window().max(Duration::from_millis(1)));
+    let started = Instant::now();
+    let mut sent_at = HashMap::<MessageId, Instant>::with_capacity(config.messages);
+    let mut in_flight = HashMap::new();
+    for chat_message in chat_messages {
+        sent_at.insert(chat_message.id, Instant::now());
+        let messages = batcher.push(receiver_id, chat_message, Instant::now());
+        send_messages(&mut sender, &mut in_flight, receiver_id, messages);
+    }
+
+    let mut latencies = Vec::with_capacity(config.messages);
+    while latencies.len() < config.messages {
+        let event = tokio::select! {
+            event = sender.select_next_some() => event,
+            event = receiver.select_next_some() => {
+                answer(&mut receiver, event);
+                continue;
+            }
+            _ = flush_interval.tick(), if !batcher.is_empty() => {
+                for (peer, messages) in batcher.due(Instant::now()) {
+                    send_messages(&mut sender, &mut in_flight, peer, messages);
+                }
+                continue;
+            }
+            _ = tokio::time::sleep(IDLE_TIMEOUT) => {
+                return Err(format!(
+                    "only {} of {} messages were acknowledged",
+                    latencies.len(),
+                    config.messages
+                )
+                .into());
+            }
+        };
+
+        let response = match event {
+            SwarmEvent::Behaviour(CustomBehaviourEvent::RequestResponse(
+                request_response::Event::Message {
+                    message:
+                        request_response::Message::Response {
+                            request_id,
+                            response,
+                        },
+                    ..
+                },
+            )) => {
+                in_flight.remove(&request_id);
+                response
+            }
+            // Past as many requests as the muxer has streams for, they fail
+            // and are sent again, as the node retries them.
+            SwarmEvent::Behaviour(CustomBehaviourEvent::RequestResponse(
+                request_response::Event::OutboundFailure { request_id, .. },
+            )) => {
+                for chat_message in in_flight.remove(&request_id).unwrap_or_default() {
+                    let messages = batcher.push(receiver_id, chat_message, Instant::now());
+                    send_messages(&mut sender, &mut in_flight, receiver_id, messages);
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        let ids = match envelope::open::<DirectResponse>(response.data) {
+            Ok(Opened::Known(DirectResponse::Ack { id })) => vec![id],
+            Ok(Opened::Known(DirectResponse::Acks { ids })) => ids,
+            _ => Vec::new(),
+        };
+        for id in ids {
+            if let Some(sent) = sent_at.remove(&id) {
+                latencies.push(sent.elapsed());
+            }
+        }
+    }
+    let elapsed = started.elapsed();
+
+    latencies.sort();
+    Ok(BenchReport {
+        config,
+        elapsed,
+        p50: percentile(&latencies, 50),
+        p99: percentile(&latencies, 99),
+    })
+}
+
+/// Sends `messages` to `peer` in one request, as the node does, and keeps
+/// them in `in_flight` until it is answered.
+fn send_messages(
+    swarm: &mut Swarm<CustomBehaviour>,
+    in_flight: &mut HashMap<OutboundRequestId, Vec<ChatMessage>>,
+    peer: PeerId,
+    messages: Vec<ChatMessage>,
+) {
+    let direct_request = match messages.as_slice() {
+        [] => return,
+        [chat_message] => DirectRequest::Message(chat_message.clone()),
+        _ => DirectRequest::Batch {
+            messages: messages.clone(),
+        },
+    };
+    let request_id = swarm.behaviour_mut().request_response.send_request(
+        &peer,
+        Request {
+            data: envelope::seal(&direct_request),
+        },
+    );
+    in_flight.insert(request_id, messages);
+}
+
+/// Acknowledges the direct messages the benchmark's receiver is sent.
+fn answer(receiver: &mut Swarm<CustomBehaviour>, event: SwarmEvent<CustomBehaviourEvent>) {
+    let SwarmEvent::Behaviour(CustomBehaviourEvent::RequestResponse(
+        request_response::Event::Message {
+            message:
+                request_response::Message::Request {
+                    request, channel, ..
+                },
+            ..
+        },
+    )) = event
+    else {
+        return;
+    };
+    let response = match envelope::open::<DirectRequest>(request.data) {
+        Ok(Opened::Known(DirectRequest::Message(chat_message))) => DirectResponse::Ack {
+            id: chat_message.id,
+        },
+        Ok(Opened::Known(DirectRequest::Batch { messages })) => DirectResponse::Acks {
+            ids: messages.iter().map(|message| message.id).collect(),
+        },
+        _ => return,
+    };
+    receiver.behaviour_mut().respond(
+        channel,
+        Response {
+            data: envelope::seal(&response),
+        },
+    );
+}
+
+/// Drives both swarms until the sender has a connection to the receiver.
+async fn wait_for_connection(
+    sender: &mut Swarm<CustomBehaviour>,
+    receiver: &mut Swarm<CustomBehaviour>,
+) -> Result<(), Box<dyn Error>> {
+    tokio::time::timeout(IDLE_TIMEOUT, async {
+        loop {
+            tokio::select! {
+                event = sender.select_next_some() => {
+                    if let SwarmEvent::ConnectionEstablished { .. } = event {
+                        return;
+                    }
+                }
+                _ = receiver.select_next_some() => {}
+            }
+        }
+    })
+    .await
+    .map_err(|_| "the benchmark peers did not connect in time".into())
+}
+
+/// Drives both swarms until the sender sees the receiver join the topic, so
+/// the burst isn't published before there is anyone to deliver it to.
+async fn wait_for_subscription(
+    sender: &mut Swarm<CustomBehaviour>,
+    receiver: &mut Swarm<CustomBehaviour>,
+) -> Result<(), Box<dyn Error>> {
+    tokio::time::timeout(IDLE_TIMEOUT, async {
+        loop {
+            tokio::select! {
+                event = sender.select_next_some() => {
+                    if let SwarmEvent::Behaviour(CustomBehaviourEvent::Gossipsub(
+                        gossipsub::Event::Subscribed { .. },
+                    )) = event
+                    {
+                        return;
+                    }
+                }
+                _ = receiver.select_next_some() => {}
+            }
+        }
+    })
+    .await
+    .map_err(|_| "the benchmark peers did not connect in time".into())
+}
+
+/// The `p`th percentile of already sorted samples.
+fn percentile(sorted: &[Duration], p: usize) -> Duration {
+    let index = (sorted.len() - 1) * p / 100;
+    sorted[index]
+}
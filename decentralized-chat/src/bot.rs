@@ -0,0 +1,112 @@
+use crate::{command::Command, event::ChatEvent, handle::ChatHandle, message::ChatMessage};
+use async_trait::async_trait;
+use std::sync::Arc;
+use tokio::{
+    sync::{broadcast::error::RecvError, Semaphore},
+    task::JoinHandle,
+};
+
+/// Handler calls allowed to run at once; further messages wait for one to
+/// finish.
+const MAX_RUNNING: usize = 16;
+
+/// Reacts to incoming messages, e.g. to answer commands like `!ping` or to
+/// bridge a room to another service.
+#[async_trait]
+pub trait MessageHandler: Send + Sync + 'static {
+    /// Called for every message received in one of our rooms or directly,
+    /// once it is stored. The text returned is sent back where the message
+    /// came from: its room, or its author for direct messages. `ctx` runs
+    /// any other command on the node.
+    async fn on_message(&self, message: &ChatMessage, ctx: &ChatHandle) -> Option<String>;
+}
+
+/// Runs `handler` on every message `handle`'s node receives from now on,
+/// until the node shuts down. Each call runs on a task of its own, so a
+/// slow handler never holds up the node, and one that panics is logged and
+/// kept for the next message.
+pub fn spawn_handler(handle: ChatHandle, handler: impl MessageHandler) -> JoinHandle<()> {
+    let handler = Arc::new(handler);
+    let running = Arc::new(Semaphore::new(MAX_RUNNING));
+    let mut events = handle.events();
+
+    tokio::spawn(async move {
+        loop {
+            let message = match events.recv().await {
+                Ok(ChatEvent::MessageReceived { message }) => message,
+                Ok(_) => continue,
+                Err(RecvError::Lagged(missed)) => {
+                    println!("Message handler fell behind and missed {} events", missed);
+                    continue;
+                }
+                Err(RecvError::Closed) => return,
+            };
+
+            let Ok(permit) = running.clone().acquire_owned().await else {
+                return;
+            };
+            let (handler, handle) = (handler.clone(), handle.clone());
+            tokio::spawn(async move {
+                let call = tokio::spawn(respond(handler, handle, message));
+                if let Err(e) = call.await {
+                    if e.is_panic() {
+                        println!(
+                            "Message handler panicked: {}",
+                            panic_message(e.into_panic())
+                        );
+                    }
+                }
+                drop(permit);
+            });
+        }
+    })
+}
+
+async fn respond(handler: Arc<impl MessageHandler>, handle: ChatHandle, message: ChatMessage) {
+    let Some(text) = handler.on_message(&message, &handle).await else {
+        return;
+    };
+    let command = match message.room {
+        Some(room) => Command::Send {
+            text,
+            room: Some(room),
+            expires_in: None,
+        },
+        None => Command::Msg {
+            peer: message.peer_id,
+            text,
+        },
+    };
+    if let Err(e) = handle.execute(command).await {
+        println!("Failed to send the message handler's reply: {}", e);
+    }
+}
+
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "unknown cause".to_string(),
+        },
+    }
+}
+
+/// Answers `!ping` with `pong` and `!echo <text>` with the text; set by
+/// `--ping-bot`.
+#[derive(Debug, Default)]
+pub struct PingBot;
+
+#[async_trait]
+impl MessageHandler for PingBot {
+    async fn on_message(&self, message: &ChatMessage, _ctx: &ChatHandle) -> Option<String> {
+        let text = message.message.trim();
+        if text == "!ping" {
+            return Some("pong".to_string());
+        }
+        text.strip_prefix("!echo ")
+            .map(str::trim)
+            .filter(|echoed| !echoed.is_empty())
+            .map(str::to_string)
+    }
+}
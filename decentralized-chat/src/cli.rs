@@ -0,0 +1,399 @@
+use clap::{Parser, Subcommand};
+use libp2p::Multiaddr;
+use libp2p_demo::{
+    config::{parse_idle_timeout, GossipPreset, Muxer, Security},
+    dial::{check_dial_address, check_external_address, check_listen_address, parse_multiaddr},
+    key::KeyType,
+    profile::{self, DEFAULT_PROFILE},
+    rendezvous::server_peer_id,
+    search::parse_date,
+};
+use std::path::PathBuf;
+
+#[derive(Debug, Parser)]
+#[command(about = "Decentralized peer to peer chat built on libp2p")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<CliCommand>,
+
+    /// Identity to run as. Each profile has its own data directory, in
+    /// ~/.decentralized-chat/profiles/<NAME>, holding its identity key,
+    /// config, history and address book. Profiles listen on random ports, so
+    /// several can run at once.
+    #[arg(long, value_name = "NAME", default_value = DEFAULT_PROFILE, value_parser = parse_profile_name)]
+    pub profile: String,
+
+    /// Directory holding persistent node state such as the address book,
+    /// instead of the profile's.
+    #[arg(long, value_name = "DIR", conflicts_with = "profile")]
+    pub data_dir: Option<PathBuf>,
+
+    /// JSON config file with tuning parameters. Defaults to config.json in the
+    /// data directory; a missing file means library defaults.
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Type of identity key generated on first start: ed25519, secp256k1,
+    /// ecdsa (P-256) or rsa. RSA keys can't be generated and must be put in
+    /// the data directory's identity.key as PKCS#8. A stored key of another
+    /// type is an error.
+    #[arg(long, value_name = "TYPE")]
+    pub key_type: Option<KeyType>,
+
+    /// How connections are secured: noise, tls (TLS 1.3), or both, to agree
+    /// with each peer on one it speaks. Peers with none in common fail the
+    /// handshake. Defaults to both, or noise with --network-id. Overrides
+    /// swarm.security in the config file.
+    #[arg(long, value_name = "PROTOCOL")]
+    pub security: Option<Security>,
+
+    /// Identifies the deployment this node belongs to. It is mixed into the
+    /// noise handshake, so only nodes given the same id can connect to each
+    /// other. Needs noise security, so only noise is offered unless
+    /// --security asks for TLS, which is refused. Overrides swarm.network_id
+    /// in the config file.
+    #[arg(long, value_name = "ID", value_parser = parse_network_id)]
+    pub network_id: Option<String>,
+
+    /// How streams are multiplexed: yamux, or mplex for peers that only speak
+    /// that. Mplex has no backpressure and resets streams whose reader falls
+    /// behind, so prefer yamux where peers support it. Peers must use the same
+    /// or the connection fails. Overrides swarm.muxer in the config file.
+    #[arg(long, value_name = "MUXER")]
+    pub muxer: Option<Muxer>,
+
+    /// Gossipsub tuning to start from: lan for a few peers on one network
+    /// (small meshes, a 500ms heartbeat, messages flooded to every peer), or
+    /// wan for larger public rooms (wider meshes, messages left to the mesh).
+    /// Values set in the gossipsub section of the config file still apply on
+    /// top. Overrides gossipsub.preset in the config file.
+    #[arg(long, value_name = "PRESET")]
+    pub gossip_preset: Option<GossipPreset>,
+
+    /// Address to listen on; may be repeated. Defaults to every IPv4 and IPv6
+    /// interface on a random TCP port plus a WebSocket listener on --ws-port,
+    /// re-opened if they close.
+    #[arg(long = "listen", value_name = "MULTIADDR", value_parser = parse_listen_address)]
+    pub listen: Vec<Multiaddr>,
+
+    /// TCP port of the default WebSocket listener, for browser peers. 0 picks
+    /// a random port.
+    #[arg(
+        long,
+        value_name = "PORT",
+        default_value_t = 0,
+        conflicts_with = "listen"
+    )]
+    pub ws_port: u16,
+
+    /// Peer to connect to on startup, e.g.
+    /// /dns4/chat.example.com/tcp/4001/p2p/<peer id>; may be repeated.
+    #[arg(long = "dial", value_name = "MULTIADDR", value_parser = parse_dial_address)]
+    pub dial: Vec<Multiaddr>,
+
+    /// Print the best address to dial us at as a QR code once listening, as
+    /// /share does; another node can pass it to --dial.
+    #[arg(long)]
+    pub print_qr: bool,
+
+    /// Rendezvous server to register with and discover the peers of our
+    /// rooms through, e.g. /dns4/meet.example.com/tcp/4001/p2p/<peer id>; may
+    /// be repeated. Without --external-address, we register the addresses
+    /// peers observe for us.
+    #[arg(
+        long = "rendezvous",
+        value_name = "MULTIADDR",
+        value_parser = parse_rendezvous_address
+    )]
+    pub rendezvous: Vec<Multiaddr>,
+
+    /// Serve as a rendezvous point where other nodes register and discover
+    /// each other.
+    #[arg(long)]
+    pub rendezvous_server: bool,
+
+    /// Public address peers can reach us on, such as a manually forwarded
+    /// port, e.g. /ip4/203.0.113.7/tcp/4001; may be repeated. It is
+    /// advertised to peers alongside the listen addresses.
+    #[arg(
+        long = "external-address",
+        value_name = "MULTIADDR",
+        value_parser = parse_external_address
+    )]
+    pub external_address: Vec<Multiaddr>,
+
+    /// Don't discover peers over mDNS; rely on --dial and the address book.
+    #[arg(long)]
+    pub no_mdns: bool,
+
+    /// Don't ask the gateway to forward our TCP listen ports over UPnP; rely
+    /// on --external-address or a manually forwarded port.
+    #[arg(long)]
+    pub no_upnp: bool,
+
+    /// Don't ring the terminal bell when a message mentions our nickname.
+    #[arg(long)]
+    pub no_bell: bool,
+
+    /// Show peer ids in full in logs and next to messages, instead of their
+    /// first and last few characters. /peers and /known always show them in
+    /// full.
+    #[arg(long)]
+    pub full_ids: bool,
+
+    /// Don't show desktop notifications for direct messages and mentions.
+    /// Builds without the `notifications` feature never show any.
+    #[arg(long)]
+    pub no_notifications: bool,
+
+    /// Answer `!ping` with `pong` and `!echo <text>` with the text, in the
+    /// room or direct conversation they were sent in.
+    #[arg(long)]
+    pub ping_bot: bool,
+
+    /// Hold direct messages that other peers address to offline peers, and
+    /// deliver them when those peers connect.
+    #[arg(long)]
+    pub forward: bool,
+
+    /// Seconds before a connection with no activity is closed, or `none` (or
+    /// 0) to keep it open. Overrides the config file; defaults to 10.
+    #[arg(long, value_name = "SECS", value_parser = parse_idle_timeout)]
+    pub idle_timeout: Option<u64>,
+
+    /// Messages held in memory across all rooms; older ones are only in the
+    /// history file. Overrides the config file; defaults to 10000.
+    #[arg(long, value_name = "N", value_parser = parse_history_limit)]
+    pub history_limit: Option<usize>,
+
+    /// Most connections kept open in both directions combined; overrides
+    /// connection_limits.max_established in the config.
+    #[arg(long, value_name = "N", value_parser = parse_connection_limit)]
+    pub max_connections: Option<u32>,
+
+    /// Most connections kept open to any one peer; overrides
+    /// connection_limits.max_established_per_peer in the config.
+    #[arg(long, value_name = "N", value_parser = parse_connection_limit)]
+    pub max_connections_per_peer: Option<u32>,
+
+    /// Forget address book entries not seen for this many seconds.
+    #[arg(long, value_name = "SECS", default_value_t = 7 * 24 * 60 * 60)]
+    pub peer_max_age: u64,
+
+    /// Accept newline-delimited JSON commands on a Unix domain socket at this
+    /// path, and stream events to clients that subscribe, such as `attach`.
+    /// Only our user may connect.
+    #[arg(long, visible_alias = "ipc-socket", value_name = "PATH")]
+    pub control_socket: Option<PathBuf>,
+
+    /// Serve the HTTP API on this address, e.g. 127.0.0.1:8080. Requests
+    /// need the bearer token printed at startup.
+    #[cfg(feature = "http-api")]
+    #[arg(long, value_name = "ADDR")]
+    pub http_addr: Option<std::net::SocketAddr>,
+
+    /// Serve JSON-RPC 2.0 on this address, e.g. 127.0.0.1:8545, with the
+    /// methods sendMessage, listPeers, joinRoom, getHistory and dial. Calls
+    /// need the bearer token set as rpc.token in the config file, or else
+    /// the one printed at startup.
+    #[cfg(feature = "http-api")]
+    #[arg(long, value_name = "ADDR")]
+    pub rpc_addr: Option<std::net::SocketAddr>,
+
+    /// Serve browser frontends over WebSocket on this address, e.g.
+    /// 127.0.0.1:8546. They connect to the URL printed at startup, which
+    /// carries a token, get every event as JSON and send messages as this
+    /// node. web/index.html is such a frontend.
+    #[cfg(feature = "web-ui")]
+    #[arg(long, value_name = "ADDR")]
+    pub web_ui_addr: Option<std::net::SocketAddr>,
+
+    /// Run headless, e.g. as a relay or archive on a server: stdin isn't
+    /// read, so commands only come through --control-socket, and the node
+    /// runs until it gets SIGINT or SIGTERM, e.g. from `stop`. Only one
+    /// daemon may run on a data directory; it holds daemon.pid there.
+    #[arg(long, conflicts_with = "bench_mode")]
+    pub daemon: bool,
+
+    /// Append the daemon's output to this file instead of the terminal.
+    #[arg(long, value_name = "FILE", requires = "daemon")]
+    pub log_file: Option<PathBuf>,
+
+    /// Measure gossipsub throughput between two local swarms, then exit. Build
+    /// with --release for meaningful numbers.
+    #[arg(long)]
+    pub bench_mode: bool,
+
+    /// Number of messages published by --bench-mode.
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1000,
+        requires = "bench_mode"
+    )]
+    pub bench_messages: usize,
+
+    /// Size in bytes of each message published by --bench-mode.
+    #[arg(
+        long,
+        value_name = "BYTES",
+        default_value_t = 256,
+        requires = "bench_mode"
+    )]
+    pub bench_size: usize,
+
+    /// Make --bench-mode compare direct messages between two in-memory
+    /// swarms instead, sent one request each and then batched as the config
+    /// says.
+    #[arg(long, requires = "bench_mode")]
+    pub bench_batching: bool,
+}
+
+/// One-off tasks run instead of starting the node.
+#[derive(Debug, Subcommand)]
+pub enum CliCommand {
+    /// Write the message history and address book to a JSON file.
+    Export {
+        #[arg(long, value_name = "FILE")]
+        out: PathBuf,
+
+        /// Only export messages from this room.
+        #[arg(long)]
+        room: Option<String>,
+
+        /// Only export messages sent on or after this day (YYYY-MM-DD, UTC).
+        #[arg(long, value_name = "DATE", value_parser = parse_since)]
+        since: Option<u64>,
+    },
+    /// Merge a file written by `export` into the local history. A running node
+    /// picks the imported messages up on its next start.
+    Import {
+        #[arg(long = "in", value_name = "FILE")]
+        input: PathBuf,
+    },
+    /// Connect to the control socket of a running node, e.g. one started
+    /// with --daemon: print its events and run the lines typed here on it.
+    Attach {
+        #[arg(value_name = "SOCKET")]
+        socket: PathBuf,
+    },
+    /// Shut down the daemon running on the data directory, e.g. one started
+    /// with --daemon, the same way as Ctrl-C.
+    Stop,
+    /// Create the identity key of the data directory from a seed phrase,
+    /// which can make the same key again if the directory is lost.
+    Keygen {
+        /// Generate a new 24-word phrase, print it and write its key.
+        #[arg(long, required_unless_present = "recover", conflicts_with = "recover")]
+        mnemonic: bool,
+
+        /// Ask for a phrase printed by `keygen --mnemonic` and write the key
+        /// it makes.
+        #[arg(long)]
+        recover: bool,
+
+        /// Type of key made from the phrase: ed25519 (the default),
+        /// secp256k1 or ecdsa. Recovering needs the type the phrase was
+        /// made for.
+        #[arg(long, value_name = "TYPE")]
+        key_type: Option<KeyType>,
+    },
+    /// Manage the profiles in ~/.decentralized-chat/profiles.
+    Profiles {
+        #[command(subcommand)]
+        command: ProfilesCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ProfilesCommand {
+    /// Show every profile, and which have a daemon running.
+    List,
+    /// Make a new profile with a fresh identity.
+    Create {
+        #[arg(value_name = "NAME", value_parser = parse_profile_name)]
+        name: String,
+    },
+    /// Remove a profile with its identity key and history, for good.
+    Delete {
+        #[arg(value_name = "NAME", value_parser = parse_profile_name)]
+        name: String,
+
+        /// Confirm the deletion.
+        #[arg(long)]
+        yes: bool,
+    },
+}
+
+impl Cli {
+    /// The directory node state is kept in: --data-dir, or the profile's.
+    pub fn data_dir(&self) -> PathBuf {
+        self.data_dir
+            .clone()
+            .unwrap_or_else(|| profile::dir(&state_dir(), &self.profile))
+    }
+}
+
+fn parse_profile_name(name: &str) -> Result<String, String> {
+    profile::check_name(name)?;
+    Ok(name.to_string())
+}
+
+fn parse_since(date: &str) -> Result<u64, String> {
+    parse_date(date).ok_or_else(|| format!("invalid date: {} (expected YYYY-MM-DD)", date))
+}
+
+fn parse_history_limit(limit: &str) -> Result<usize, String> {
+    match limit.parse() {
+        Ok(0) => Err("the history limit must be at least 1".to_string()),
+        Ok(limit) => Ok(limit),
+        Err(_) => Err(format!("invalid number: {}", limit)),
+    }
+}
+
+fn parse_connection_limit(limit: &str) -> Result<u32, String> {
+    match limit.parse() {
+        Ok(0) => Err("the connection limit must be at least 1".to_string()),
+        Ok(limit) => Ok(limit),
+        Err(_) => Err(format!("invalid number: {}", limit)),
+    }
+}
+
+fn parse_network_id(network_id: &str) -> Result<String, String> {
+    if network_id.is_empty() {
+        return Err("the network id must not be empty".to_string());
+    }
+    Ok(network_id.to_string())
+}
+
+fn parse_external_address(address: &str) -> Result<Multiaddr, String> {
+    let address = parse_multiaddr(address)?;
+    check_external_address(&address)?;
+    Ok(address)
+}
+
+fn parse_listen_address(address: &str) -> Result<Multiaddr, String> {
+    let address = parse_multiaddr(address)?;
+    check_listen_address(&address)?;
+    Ok(address)
+}
+
+fn parse_dial_address(address: &str) -> Result<Multiaddr, String> {
+    let address = parse_multiaddr(address)?;
+    check_dial_address(&address)?;
+    Ok(address)
+}
+
+fn parse_rendezvous_address(address: &str) -> Result<Multiaddr, String> {
+    let address = parse_dial_address(address)?;
+    server_peer_id(&address)?;
+    Ok(address)
+}
+
+/// Where the profiles are kept.
+pub fn state_dir() -> PathBuf {
+    std::env::var_os("HOME")
+        .map(PathBuf::from)
+        .unwrap_or_default()
+        .join(".decentralized-chat")
+}
@@ -0,0 +1,975 @@
+use crate::{
+    address_book::Entry,
+    behaviour::ProtocolVersion,
+    delivery::DeliveryStatus,
+    message::{unix_now, ChatMessage, MessageId},
+    peer_id::short_peer_id,
+    peer_profile::PeerProfile,
+    room_settings::Notify,
+    search::{format_timestamp, SearchHit},
+    security::SecurityProtocol,
+    share::qr_code,
+    stats::{format_bytes, Bandwidth, Limits, Stats},
+};
+use libp2p::{Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt, path::PathBuf};
+
+/// An action requested by the user, either typed on stdin or sent over the
+/// control socket.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Command {
+    /// Publishes a chat message to `room`, or the current room if unset.
+    Send {
+        text: String,
+        #[serde(default)]
+        room: Option<String>,
+        /// Seconds until the message expires.
+        #[serde(default)]
+        expires_in: Option<u64>,
+    },
+    /// Publishes a chat message replying to the message with this id prefix,
+    /// in the room it was sent to.
+    Reply {
+        message_id: String,
+        text: String,
+    },
+    React {
+        message_id: String,
+        emoji: String,
+    },
+    /// Replaces the text of one of our messages with this id prefix.
+    Edit {
+        message_id: String,
+        text: String,
+    },
+    /// Replaces one of our messages with a tombstone.
+    Delete {
+        message_id: String,
+    },
+    /// Shows a message together with its replies.
+    Thread {
+        message_id: String,
+    },
+    Join {
+        room: String,
+    },
+    /// Joins the private room of an invite from `/invite`, once its
+    /// signature and expiry check out.
+    JoinInvite {
+        token: String,
+    },
+    /// Makes an invite to the private room `room`, valid for `expires_in`
+    /// seconds or a day. With `new`, `room` is first made private and
+    /// joined, unless it has members who would be cut off.
+    Invite {
+        room: String,
+        #[serde(default)]
+        expires_in: Option<u64>,
+        #[serde(default)]
+        new: bool,
+    },
+    /// Unsubscribes from a room other than the current one.
+    Leave {
+        room: String,
+    },
+    /// Lists the rooms we are in.
+    Rooms,
+    /// Lists the members of `room`, or the current room if unset.
+    Members {
+        #[serde(default)]
+        room: Option<String>,
+    },
+    /// Stops showing a room's messages as they arrive, or all but those
+    /// mentioning us.
+    Mute {
+        room: String,
+        #[serde(default)]
+        mentions_only: bool,
+    },
+    /// Shows every message of a room again.
+    Unmute {
+        room: String,
+    },
+    /// A page of `limit` messages of `room`, or the current room if unset,
+    /// oldest first. Page 0 holds the latest messages, page 1 the ones
+    /// before, and so on. With `peer` or `since` set, only the messages from
+    /// that peer, or sent from then on, are counted.
+    History {
+        #[serde(default)]
+        room: Option<String>,
+        #[serde(default)]
+        peer: Option<PeerId>,
+        #[serde(default)]
+        since: Option<u64>,
+        limit: usize,
+        #[serde(default)]
+        page: usize,
+    },
+    /// The latest messages held in memory that mention us, in any room or
+    /// directly, oldest first.
+    Mentions,
+    /// Forgets the messages of `room`, or of every room and every direct
+    /// message if unset, and with `disk` removes them from the history file
+    /// too. Until `confirmed`, only says what would be cleared.
+    Clear {
+        #[serde(default)]
+        room: Option<String>,
+        #[serde(default)]
+        disk: bool,
+        #[serde(default)]
+        confirmed: bool,
+    },
+    /// Searches the local history for `term`.
+    Search {
+        term: String,
+        /// Treat `term` as a regular expression instead of a plain substring.
+        #[serde(default)]
+        regex: bool,
+        /// Only match messages sent before this unix timestamp.
+        #[serde(default)]
+        before: Option<u64>,
+        /// Only match messages sent at or after this unix timestamp.
+        #[serde(default)]
+        after: Option<u64>,
+    },
+    /// Sends a direct message to a single peer.
+    Msg {
+        peer: PeerId,
+        text: String,
+    },
+    Block {
+        peer: PeerId,
+    },
+    Unblock {
+        peer: PeerId,
+    },
+    Nick {
+        name: String,
+    },
+    /// Turns expansion of `:shortcode:` emoji in outgoing messages on or off.
+    NoEmoji,
+    /// Turns do not disturb on or off. While on, notifications and mention
+    /// highlights are off and direct messages are answered with
+    /// `away_message`.
+    DoNotDisturb {
+        enabled: bool,
+        #[serde(default)]
+        away_message: Option<String>,
+    },
+    /// Shows whether do not disturb is on.
+    Status,
+    /// Connects to a peer, with or without a `/p2p/<peer id>` suffix.
+    Dial {
+        address: Multiaddr,
+    },
+    Peers,
+    /// Shows the fingerprint of our key, or of a connected peer's.
+    Fingerprint {
+        #[serde(default)]
+        peer: Option<PeerId>,
+    },
+    /// Marks a connected peer as verified, once its fingerprint was compared
+    /// out of band.
+    Verify {
+        peer: PeerId,
+    },
+    /// Asks a peer for the peers it has seen recently.
+    PeerExchange {
+        peer: PeerId,
+    },
+    Addrs,
+    /// Shows the best address to dial us at as a QR code, or every one.
+    Share {
+        #[serde(default)]
+        all: bool,
+    },
+    Known,
+    /// Adds a peer to the contacts, which are dialed on startup, shown by
+    /// `nickname` if set.
+    AddFriend {
+        peer: PeerId,
+        #[serde(default)]
+        nickname: Option<String>,
+    },
+    RemoveFriend {
+        peer: PeerId,
+    },
+    /// Lists the contacts and whether they are connected.
+    Friends,
+    /// Shows the status and avatar we share.
+    Profile,
+    /// Sets the status line we share, or clears it if unset.
+    SetStatus {
+        #[serde(default)]
+        status: Option<String>,
+    },
+    /// Sets the avatar we share to the PNG file at `path`, or clears it if
+    /// unset.
+    SetAvatar {
+        #[serde(default)]
+        path: Option<PathBuf>,
+    },
+    /// Shows everything we know about a peer.
+    Whois {
+        peer: PeerId,
+    },
+    /// Shows open connections against the connection limits.
+    Limits,
+    /// Shows the bytes moved with each peer, or starts counting them afresh.
+    Bandwidth {
+        #[serde(default)]
+        reset: bool,
+    },
+    /// The node's metrics in the Prometheus text format.
+    Metrics,
+    /// Reports the node's counters.
+    Stats {
+        /// Print the report as JSON at the prompt. Control socket replies are
+        /// always JSON.
+        #[serde(default)]
+        json: bool,
+    },
+    Help {
+        /// The command to describe; lists every command when unset.
+        #[serde(default)]
+        topic: Option<String>,
+    },
+    /// Closes every connection and stops the node.
+    Quit,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConnectedPeer {
+    pub peer_id: PeerId,
+    /// Unset until the peer has identified itself.
+    pub protocol: Option<ProtocolVersion>,
+    /// What secures our connections to the peer, each protocol once. Empty
+    /// if the transport doesn't say.
+    pub security: Vec<SecurityProtocol>,
+    /// The nickname we gave the peer as a contact, or else the one it last
+    /// announced or sent a message with.
+    pub nickname: Option<String>,
+    /// Whether the peer was marked with `/verify`.
+    pub verified: bool,
+    /// Bytes moved with the peer since `/bw reset`.
+    pub bandwidth: Bandwidth,
+    pub peering: Peering,
+}
+
+/// How gossipsub treats a connected peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Peering {
+    /// Sent messages when it is in the mesh of their room.
+    Mesh,
+    /// Sent every message, as an explicit peer found through discovery.
+    Explicit,
+    /// An explicit peer from the config, kept connected.
+    Pinned,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PeerTraffic {
+    pub peer_id: PeerId,
+    #[serde(flatten)]
+    pub bandwidth: Bandwidth,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Friend {
+    pub peer_id: PeerId,
+    pub nickname: Option<String>,
+    pub online: bool,
+}
+
+/// An avatar as `/profile` and `/whois` describe it, without the image.
+#[derive(Debug, Serialize)]
+pub struct Avatar {
+    pub bytes: usize,
+    pub hash: String,
+}
+
+impl Avatar {
+    pub fn of(profile: &PeerProfile) -> Option<Avatar> {
+        Some(Avatar {
+            bytes: profile.avatar.as_ref()?.len(),
+            hash: profile.avatar_hash()?,
+        })
+    }
+}
+
+impl fmt::Display for Avatar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} PNG, sha256 {}",
+            format_bytes(self.bytes as u64),
+            self.hash
+        )
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Whois {
+    pub peer_id: PeerId,
+    /// The nickname the peer last announced or sent a message with.
+    pub nickname: Option<String>,
+    pub friend: bool,
+    /// The nickname we gave the peer as a contact.
+    pub friend_nickname: Option<String>,
+    pub online: bool,
+    /// Unset until the peer has identified itself.
+    pub protocol: Option<ProtocolVersion>,
+    /// Whether the peer was marked with `/verify`.
+    pub verified: bool,
+    /// Unix timestamp (seconds) of the last time the peer was discovered.
+    pub last_seen: Option<u64>,
+    /// Unset until the peer's profile was fetched.
+    pub status: Option<String>,
+    pub avatar: Option<Avatar>,
+    /// Whether the peer's profile was fetched, even if it is empty.
+    pub profile_known: bool,
+    /// Whether the profile was requested just now, to show in a later
+    /// `/whois`.
+    pub fetching: bool,
+}
+
+impl fmt::Display for Whois {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut lines = vec![format!(
+            "{}{}",
+            self.peer_id,
+            if self.verified { " ✓" } else { "" }
+        )];
+        if let Some(nickname) = &self.nickname {
+            lines.push(format!("  nickname: {}", nickname));
+        }
+        match (self.friend, &self.friend_nickname) {
+            (true, Some(nickname)) => lines.push(format!("  friend, as {}", nickname)),
+            (true, None) => lines.push("  friend".to_string()),
+            (false, _) => {}
+        }
+        match (self.online, self.last_seen) {
+            (true, _) => lines.push(format!(
+                "  online, {}",
+                self.protocol
+                    .map_or("protocol unknown".to_string(), |version| version
+                        .to_string())
+            )),
+            (false, Some(last_seen)) => lines.push(format!(
+                "  offline, last seen {}",
+                format_timestamp(last_seen)
+            )),
+            (false, None) => lines.push("  offline, never seen".to_string()),
+        }
+        if self.profile_known {
+            lines.push(format!(
+                "  status: {}",
+                self.status.as_deref().unwrap_or("(none)")
+            ));
+            lines.push(format!(
+                "  avatar: {}",
+                self.avatar
+                    .as_ref()
+                    .map_or("(none)".to_string(), Avatar::to_string)
+            ));
+        } else if self.fetching {
+            lines.push("  profile: fetching, run /whois again in a moment".to_string());
+        } else {
+            lines.push("  profile: unknown until the peer connects".to_string());
+        }
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RoomSummary {
+    pub room: String,
+    pub current: bool,
+    /// Whether we hold a key for the room, from `/invite --new` or an invite.
+    pub private: bool,
+    /// Other members the DHT knows of, as of the last lookup.
+    pub providers: usize,
+    pub notify: Notify,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RoomMember {
+    pub peer_id: PeerId,
+    /// The nickname we gave the peer as a contact, or else the one it last
+    /// announced or sent a message with.
+    pub nickname: Option<String>,
+    /// Whether the peer is connected and in the room, or sent its last
+    /// heartbeat recently.
+    pub online: bool,
+    /// Unix time we first saw the peer in the room.
+    pub joined_at: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KnownPeer {
+    pub peer_id: PeerId,
+    #[serde(flatten)]
+    pub entry: Entry,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Reply {
+    Sent {
+        id: MessageId,
+    },
+    Deleted {
+        id: MessageId,
+    },
+    /// The rendered message after a change was applied to it.
+    Updated {
+        message: String,
+    },
+    Thread {
+        messages: Vec<String>,
+    },
+    Joined {
+        room: String,
+    },
+    JoinedByInvite {
+        room: String,
+        inviter: PeerId,
+    },
+    /// A token to hand to whoever is invited, who joins with it.
+    Invite {
+        room: String,
+        token: String,
+        expires_at: u64,
+    },
+    Left {
+        room: String,
+    },
+    Rooms {
+        rooms: Vec<RoomSummary>,
+    },
+    /// Longest in the room first.
+    Members {
+        room: String,
+        members: Vec<RoomMember>,
+    },
+    /// The room's notification setting after `/mute` or `/unmute`.
+    RoomNotify {
+        room: String,
+        notify: Notify,
+    },
+    History {
+        room: String,
+        messages: Vec<ChatMessage>,
+        /// Position of the first message among all of the room's, from 1.
+        first: usize,
+        /// Messages the room has in all.
+        total: usize,
+        /// The status of those of the messages that are ours and still in
+        /// memory.
+        #[serde(skip_serializing_if = "HashMap::is_empty")]
+        statuses: HashMap<MessageId, DeliveryStatus>,
+    },
+    Mentions {
+        messages: Vec<ChatMessage>,
+    },
+    /// What `/clear` would remove, asking to run it again confirmed.
+    ConfirmClear {
+        room: Option<String>,
+        /// Messages held in memory that would be cleared.
+        messages: usize,
+        disk: bool,
+    },
+    Cleared {
+        room: Option<String>,
+        messages: usize,
+        /// Messages removed from the history file, if it was cleared too.
+        removed_from_disk: Option<usize>,
+    },
+    Search {
+        hits: Vec<SearchHit>,
+        /// Whether there was any history to search at all.
+        history_empty: bool,
+    },
+    Blocked {
+        peer: PeerId,
+    },
+    Unblocked {
+        peer: PeerId,
+    },
+    NickChanged {
+        name: String,
+    },
+    EmojiExpansion {
+        enabled: bool,
+    },
+    DoNotDisturb {
+        enabled: bool,
+        away_message: Option<String>,
+    },
+    Status {
+        do_not_disturb: bool,
+        away_message: Option<String>,
+    },
+    Help {
+        text: String,
+    },
+    /// The dial was started; its outcome is printed when it is known.
+    Dialing {
+        address: Multiaddr,
+    },
+    Peers {
+        peers: Vec<ConnectedPeer>,
+    },
+    /// `peer` is unset for our own key.
+    Fingerprint {
+        peer: Option<PeerId>,
+        fingerprint: String,
+        verified: bool,
+    },
+    Verified {
+        peer: PeerId,
+        fingerprint: String,
+    },
+    /// The request was sent; what the peer shares is printed when it answers.
+    ExchangingPeers {
+        peer: PeerId,
+    },
+    Addrs {
+        addrs: Vec<Multiaddr>,
+    },
+    /// The addresses to dial us at, best first.
+    Share {
+        addrs: Vec<Multiaddr>,
+        all: bool,
+    },
+    Known {
+        peers: Vec<KnownPeer>,
+    },
+    FriendAdded {
+        peer: PeerId,
+        nickname: Option<String>,
+        /// Unset when the peer already was a contact and only its nickname
+        /// changed.
+        new: bool,
+    },
+    FriendRemoved {
+        peer: PeerId,
+    },
+    Friends {
+        friends: Vec<Friend>,
+    },
+    /// Our profile, as peers are sent it.
+    Profile {
+        status: Option<String>,
+        avatar: Option<Avatar>,
+    },
+    Whois(Box<Whois>),
+    Stats(Box<Stats>),
+    Limits(Limits),
+    /// Most bytes moved first.
+    Bandwidth {
+        peers: Vec<PeerTraffic>,
+    },
+    BandwidthReset,
+    Metrics {
+        text: String,
+    },
+    Quitting,
+}
+
+impl fmt::Display for Reply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reply::Sent { id } => write!(f, "Sent [{}]", &id.simple().to_string()[..8]),
+            Reply::Deleted { id } => write!(f, "Deleted [{}]", &id.simple().to_string()[..8]),
+            Reply::Updated { message } => write!(f, "{}", message),
+            Reply::Thread { messages } => write!(f, "{}", messages.join("\n")),
+            Reply::Joined { room } => write!(f, "Joined {}", room),
+            Reply::JoinedByInvite { room, inviter } => write!(
+                f,
+                "Joined private room {} on an invite from {}",
+                room,
+                short_peer_id(inviter)
+            ),
+            Reply::Invite {
+                room,
+                token,
+                expires_at,
+            } => write!(
+                f,
+                "Invite to {}, valid until {}; they join with\n/join --invite {}",
+                room,
+                format_timestamp(*expires_at),
+                token
+            ),
+            Reply::Left { room } => write!(f, "Left {}", room),
+            Reply::Rooms { rooms } => {
+                let rooms: Vec<String> = rooms
+                    .iter()
+                    .map(|summary| {
+                        let notify = match summary.notify {
+                            Notify::All => String::new(),
+                            notify => format!(" ({})", notify),
+                        };
+                        format!(
+                            "#{}  {} providers{}{}{}",
+                            summary.room,
+                            summary.providers,
+                            if summary.private { " (private)" } else { "" },
+                            if summary.current { " (current)" } else { "" },
+                            notify
+                        )
+                    })
+                    .collect();
+                write!(f, "{}", rooms.join("\n"))
+            }
+            Reply::Members { room, members } if members.is_empty() => {
+                write!(f, "No one else is known to be in #{}", room)
+            }
+            Reply::Members { members, .. } => {
+                let members: Vec<String> = members
+                    .iter()
+                    .map(|member| {
+                        let status = if member.online { "online" } else { "offline" };
+                        let joined = format!("joined {}", format_timestamp(member.joined_at));
+                        match &member.nickname {
+                            Some(nickname) => {
+                                format!("{}  {}  {}  {}", nickname, member.peer_id, status, joined)
+                            }
+                            None => format!("{}  {}  {}", member.peer_id, status, joined),
+                        }
+                    })
+                    .collect();
+                write!(f, "{}", members.join("\n"))
+            }
+            Reply::RoomNotify {
+                room,
+                notify: Notify::All,
+            } => write!(f, "Showing every message of #{}", room),
+            Reply::RoomNotify {
+                room,
+                notify: Notify::MentionsOnly,
+            } => write!(f, "Only showing messages of #{} that mention you", room),
+            Reply::RoomNotify {
+                room,
+                notify: Notify::Muted,
+            } => write!(
+                f,
+                "Muted #{}; its messages are still kept in the history",
+                room
+            ),
+            Reply::History { room, messages, .. } if messages.is_empty() => {
+                write!(f, "No messages in #{}", room)
+            }
+            Reply::History {
+                messages,
+                first,
+                total,
+                statuses,
+                ..
+            } => {
+                let lines: Vec<String> = messages
+                    .iter()
+                    .map(|message| {
+                        let mut line = format!(
+                            "[{}] {} {}: {}",
+                            message.short_id(),
+                            format_timestamp(message.timestamp),
+                            message.sender(),
+                            message.display_text()
+                        );
+                        if let Some(status) = statuses.get(&message.id) {
+                            line.push_str(&format!(" {}", status.glyph()));
+                        }
+                        line
+                    })
+                    .collect();
+                write!(
+                    f,
+                    "{}\nshowing {}–{} of {}",
+                    lines.join("\n"),
+                    first,
+                    first + messages.len() - 1,
+                    total
+                )
+            }
+            Reply::Mentions { messages } if messages.is_empty() => {
+                write!(f, "No messages mention you")
+            }
+            Reply::Mentions { messages } => {
+                let lines: Vec<String> = messages
+                    .iter()
+                    .map(|message| {
+                        format!(
+                            "[{}] {} {}: {}",
+                            message.short_id(),
+                            message
+                                .room
+                                .as_ref()
+                                .map_or("(direct)".to_string(), |room| format!("#{}", room)),
+                            message.sender(),
+                            message.display_text()
+                        )
+                    })
+                    .collect();
+                write!(f, "{}", lines.join("\n"))
+            }
+            Reply::ConfirmClear {
+                room,
+                messages,
+                disk,
+            } => {
+                let (what, args) = match room {
+                    Some(room) => (format!("#{}", room), format!(" {}", room)),
+                    None => ("every room and direct chat".to_string(), String::new()),
+                };
+                write!(
+                    f,
+                    "This clears the {} messages of {} held in memory",
+                    messages, what
+                )?;
+                if *disk {
+                    write!(f, " and removes all of them from the history file for good")?;
+                }
+                write!(
+                    f,
+                    "; run /clear{}{} --yes to confirm",
+                    args,
+                    if *disk { " --disk" } else { "" }
+                )
+            }
+            Reply::Cleared {
+                room,
+                messages,
+                removed_from_disk,
+            } => {
+                match room {
+                    Some(room) => write!(f, "Cleared {} messages of #{}", messages, room)?,
+                    None => write!(f, "Cleared {} messages", messages)?,
+                }
+                match removed_from_disk {
+                    Some(removed) => write!(f, " and {} from the history file", removed),
+                    None => write!(f, "; the history file still has them"),
+                }
+            }
+            Reply::Search {
+                history_empty: true,
+                ..
+            } => write!(f, "No messages in history to search"),
+            Reply::Search { hits, .. } if hits.is_empty() => write!(f, "No messages match"),
+            Reply::Search { hits, .. } => {
+                let lines: Vec<String> = hits
+                    .iter()
+                    .map(|hit| {
+                        format!(
+                            "[{}] {} {}{}: {}",
+                            &hit.id.simple().to_string()[..8],
+                            format_timestamp(hit.timestamp),
+                            hit.room
+                                .as_ref()
+                                .map(|room| format!("#{} ", room))
+                                .unwrap_or_default(),
+                            hit.sender,
+                            hit.excerpt
+                        )
+                    })
+                    .collect();
+                write!(f, "{}", lines.join("\n"))
+            }
+            Reply::Blocked { peer } => write!(f, "Blocked {}", short_peer_id(peer)),
+            Reply::Unblocked { peer } => write!(f, "Unblocked {}", short_peer_id(peer)),
+            Reply::NickChanged { name } => write!(f, "You are now known as {}", name),
+            Reply::EmojiExpansion { enabled: true } => {
+                write!(f, "Emoji shortcodes will be expanded")
+            }
+            Reply::EmojiExpansion { enabled: false } => {
+                write!(f, "Emoji shortcodes will be sent as typed")
+            }
+            Reply::DoNotDisturb {
+                enabled: true,
+                away_message,
+            } => {
+                write!(f, "Do not disturb: notifications and highlights are off")?;
+                match away_message {
+                    Some(away_message) => {
+                        write!(f, "; direct messages are answered with {:?}", away_message)
+                    }
+                    None => write!(f, "; direct messages get an auto-reply"),
+                }
+            }
+            Reply::DoNotDisturb { enabled: false, .. } => {
+                write!(f, "Notifications and highlights are back on")
+            }
+            Reply::Status {
+                do_not_disturb: false,
+                ..
+            } => write!(f, "Status: available"),
+            Reply::Status {
+                away_message: Some(away_message),
+                ..
+            } => write!(f, "Status: do not disturb ({:?})", away_message),
+            Reply::Status { .. } => write!(f, "Status: do not disturb"),
+            Reply::Help { text } => write!(f, "{}", text),
+            Reply::Dialing { address } => write!(f, "Dialing {}", address),
+            Reply::Peers { peers } if peers.is_empty() => write!(f, "No connected peers"),
+            Reply::Peers { peers } => {
+                let peers: Vec<String> = peers
+                    .iter()
+                    .map(|peer| {
+                        let mut protocol = peer
+                            .protocol
+                            .map_or("unknown".to_string(), |version| version.to_string());
+                        if !peer.security.is_empty() {
+                            let security: Vec<String> =
+                                peer.security.iter().map(ToString::to_string).collect();
+                            protocol = format!("{} over {}", protocol, security.join(" and "));
+                        }
+                        let verified = if peer.verified { " ✓" } else { "" };
+                        let peering = match peer.peering {
+                            Peering::Mesh => "",
+                            Peering::Explicit => " (explicit)",
+                            Peering::Pinned => " (pinned)",
+                        };
+                        match &peer.nickname {
+                            Some(nickname) => format!(
+                                "{}{}{}  {}  {}  {}",
+                                peer.peer_id, verified, peering, protocol, peer.bandwidth, nickname
+                            ),
+                            None => format!(
+                                "{}{}{}  {}  {}",
+                                peer.peer_id, verified, peering, protocol, peer.bandwidth
+                            ),
+                        }
+                    })
+                    .collect();
+                write!(f, "{}", peers.join("\n"))
+            }
+            Reply::Fingerprint {
+                peer: None,
+                fingerprint,
+                ..
+            } => write!(f, "Your fingerprint: {}", fingerprint),
+            Reply::Fingerprint {
+                peer: Some(peer),
+                fingerprint,
+                verified,
+            } => write!(
+                f,
+                "{}{}: {}",
+                short_peer_id(peer),
+                if *verified { " ✓" } else { "" },
+                fingerprint
+            ),
+            Reply::Verified { peer, fingerprint } => write!(
+                f,
+                "Marked {} as verified with fingerprint {}",
+                short_peer_id(peer),
+                fingerprint
+            ),
+            Reply::ExchangingPeers { peer } => {
+                write!(f, "Asking {} for peers", short_peer_id(peer))
+            }
+            Reply::Addrs { addrs } => {
+                let addrs: Vec<String> = addrs.iter().map(Multiaddr::to_string).collect();
+                write!(f, "{}", addrs.join("\n"))
+            }
+            Reply::Share { addrs, .. } if addrs.is_empty() => {
+                write!(f, "No address to share yet; we aren't listening anywhere")
+            }
+            Reply::Share { addrs, all } => {
+                let best = addrs[0].to_string();
+                match qr_code(&best) {
+                    Ok(code) => writeln!(f, "{}", code)?,
+                    Err(e) => writeln!(f, "(no QR code: {})", e)?,
+                }
+                write!(f, "{}", best)?;
+                if *all {
+                    for address in &addrs[1..] {
+                        write!(f, "\n{}", address)?;
+                    }
+                }
+                Ok(())
+            }
+            Reply::Known { peers } => {
+                let now = unix_now();
+                let mut lines = Vec::new();
+                for KnownPeer { peer_id, entry } in peers {
+                    lines.push(format!(
+                        "{} (seen {}s ago)",
+                        peer_id,
+                        now.saturating_sub(entry.last_seen)
+                    ));
+                    for address in &entry.addresses {
+                        lines.push(format!("    {}", address));
+                    }
+                }
+                write!(f, "{}", lines.join("\n"))
+            }
+            Reply::FriendAdded {
+                peer,
+                nickname,
+                new,
+            } => {
+                let action = if *new { "Added" } else { "Updated" };
+                match nickname {
+                    Some(nickname) => write!(
+                        f,
+                        "{} {} as {} in your friends",
+                        action,
+                        short_peer_id(peer),
+                        nickname
+                    ),
+                    None => write!(f, "{} {} in your friends", action, short_peer_id(peer)),
+                }
+            }
+            Reply::FriendRemoved { peer } => {
+                write!(f, "Removed {} from your friends", short_peer_id(peer))
+            }
+            Reply::Friends { friends } if friends.is_empty() => {
+                write!(f, "No friends yet; add one with /addfriend")
+            }
+            Reply::Friends { friends } => {
+                let friends: Vec<String> = friends
+                    .iter()
+                    .map(|friend| {
+                        let status = if friend.online { "online" } else { "offline" };
+                        match &friend.nickname {
+                            Some(nickname) => {
+                                format!("{}  {}  {}", nickname, friend.peer_id, status)
+                            }
+                            None => format!("{}  {}", friend.peer_id, status),
+                        }
+                    })
+                    .collect();
+                write!(f, "{}", friends.join("\n"))
+            }
+            Reply::Profile { status, avatar } => write!(
+                f,
+                "Status: {}\nAvatar: {}",
+                status.as_deref().unwrap_or("(none)"),
+                avatar
+                    .as_ref()
+                    .map_or("(none)".to_string(), Avatar::to_string)
+            ),
+            Reply::Whois(whois) => write!(f, "{}", whois),
+            Reply::Stats(stats) => write!(f, "{}", stats),
+            Reply::Limits(limits) => write!(f, "{}", limits),
+            Reply::Bandwidth { peers } if peers.is_empty() => {
+                write!(f, "Nothing sent or received since the last reset")
+            }
+            Reply::Bandwidth { peers } => {
+                let peers: Vec<String> = peers
+                    .iter()
+                    .map(|peer| format!("{}  {}", short_peer_id(&peer.peer_id), peer.bandwidth))
+                    .collect();
+                write!(f, "{}", peers.join("\n"))
+            }
+            Reply::BandwidthReset => write!(f, "Per-peer bandwidth counters reset"),
+            Reply::Metrics { text } => write!(f, "{}", text.trim_end()),
+            Reply::Quitting => write!(f, "Shutting down"),
+        }
+    }
+}
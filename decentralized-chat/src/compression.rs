@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read};
+
+/// zstd's default level, which already shrinks chat JSON several times
+/// over without slowing the event loop.
+const LEVEL: i32 = 3;
+
+/// Most bytes a compressed request or response may inflate to: as much as
+/// the JSON codec reads uncompressed. Anything larger is refused before it
+/// is all in memory, however small it was on the wire.
+pub const MAX_DECOMPRESSED_LEN: usize = 10 * 1024 * 1024;
+
+/// How a payload is compressed, named in the envelope's `content_encoding`
+/// and only used with peers that offer `COMPRESSED_CHAT_PROTOCOL`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentEncoding {
+    Zstd,
+}
+
+/// The sizes of a payload sent compressed, for `/stats`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compressed {
+    pub original_bytes: usize,
+    pub compressed_bytes: usize,
+}
+
+pub fn compress(bytes: &[u8]) -> io::Result<Vec<u8>> {
+    zstd::bulk::compress(bytes, LEVEL)
+}
+
+/// Decompresses `bytes`, failing once the output passes `max_len` rather
+/// than inflating a zip bomb to the end.
+pub fn decompress(bytes: &[u8], max_len: usize) -> io::Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    zstd::stream::read::Decoder::new(bytes)?
+        .take(max_len as u64 + 1)
+        .read_to_end(&mut decompressed)?;
+    if decompressed.len() > max_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("payload decompresses to more than {} bytes", max_len),
+        ));
+    }
+    Ok(decompressed)
+}
@@ -0,0 +1,1273 @@
+use crate::{
+    batch::Batcher,
+    dht::KAD_PROTOCOL,
+    dial::check_dial_address,
+    filter::{check_word, ContentFilter},
+    key_pin::{AddressPattern, KeyPins},
+    members::RoomMembers,
+    retry::RetryPolicy,
+    store::{DEFAULT_HISTORY_LIMIT, DEFAULT_ROOM_CAPACITY},
+};
+use libp2p::{
+    connection_limits::ConnectionLimits, gossipsub, kad, mdns, multiaddr::Protocol, rendezvous,
+    Multiaddr, PeerId,
+};
+use serde::Deserialize;
+use std::{error::Error, fmt, fs, io, path::Path, str::FromStr, time::Duration};
+
+/// Name of the config file inside the data directory.
+pub const CONFIG_FILE: &str = "config.json";
+
+/// Settings read from the config file. Every field is optional; anything left
+/// out keeps the library default.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub swarm: SwarmConfig,
+    pub protocol: ProtocolConfig,
+    pub gossipsub: GossipsubConfig,
+    pub peering: PeeringConfig,
+    pub key_pins: KeyPinsConfig,
+    pub mdns: MdnsConfig,
+    pub rendezvous: RendezvousConfig,
+    pub dht: DhtConfig,
+    pub connection_limits: ConnectionLimitsConfig,
+    pub auto_ban: AutoBanConfig,
+    pub rate_limit: RateLimitConfig,
+    pub peer_exchange: PeerExchangeConfig,
+    pub flood: FloodConfig,
+    pub replay: ReplayConfig,
+    pub store_forward: StoreForwardConfig,
+    pub retry: RetryConfig,
+    pub history: HistoryConfig,
+    pub display: DisplayConfig,
+    pub filter: FilterConfig,
+    pub batch: BatchConfig,
+    pub rpc: RpcConfig,
+    pub members: MembersConfig,
+}
+
+impl Config {
+    /// Loads and validates the config at `path`, using the defaults if the
+    /// file does not exist.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let config: Config = match fs::read(path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map_err(|e| format!("invalid config {}: {}", path.display(), e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Config::default(),
+            Err(e) => return Err(e.into()),
+        };
+
+        config
+            .swarm
+            .check()
+            .map_err(|e| format!("invalid swarm config in {}: {}", path.display(), e))?;
+        config
+            .gossipsub
+            .build()
+            .map_err(|e| format!("invalid gossipsub config in {}: {}", path.display(), e))?;
+        config
+            .peering
+            .check()
+            .map_err(|e| format!("invalid peering config in {}: {}", path.display(), e))?;
+        config
+            .key_pins
+            .build()
+            .map_err(|e| format!("invalid key_pins config in {}: {}", path.display(), e))?;
+        config
+            .mdns
+            .build()
+            .map_err(|e| format!("invalid mdns config in {}: {}", path.display(), e))?;
+        config
+            .rendezvous
+            .check()
+            .map_err(|e| format!("invalid rendezvous config in {}: {}", path.display(), e))?;
+        config
+            .dht
+            .check()
+            .map_err(|e| format!("invalid dht config in {}: {}", path.display(), e))?;
+        config.connection_limits.build().map_err(|e| {
+            format!(
+                "invalid connection_limits config in {}: {}",
+                path.display(),
+                e
+            )
+        })?;
+        if config.protocol.request_timeout_secs == 0 {
+            return Err(format!(
+                "invalid protocol config in {}: request_timeout_secs must be at least 1",
+                path.display()
+            )
+            .into());
+        }
+        if config.history.max_messages_per_room == 0 {
+            return Err(format!(
+                "invalid history config in {}: max_messages_per_room must be at least 1",
+                path.display()
+            )
+            .into());
+        }
+        if config.history.max_messages == 0 {
+            return Err(format!(
+                "invalid history config in {}: max_messages must be at least 1",
+                path.display()
+            )
+            .into());
+        }
+        config
+            .rate_limit
+            .check()
+            .map_err(|e| format!("invalid rate_limit config in {}: {}", path.display(), e))?;
+        if config.peer_exchange.min_request_interval_secs == 0 {
+            return Err(format!(
+                "invalid peer_exchange config in {}: min_request_interval_secs must be at least 1",
+                path.display()
+            )
+            .into());
+        }
+        config
+            .flood
+            .check()
+            .map_err(|e| format!("invalid flood config in {}: {}", path.display(), e))?;
+        if config.replay.window == 0 {
+            return Err(format!(
+                "invalid replay config in {}: window must be at least 1",
+                path.display()
+            )
+            .into());
+        }
+        config
+            .store_forward
+            .check()
+            .map_err(|e| format!("invalid store_forward config in {}: {}", path.display(), e))?;
+        config
+            .retry
+            .check()
+            .map_err(|e| format!("invalid retry config in {}: {}", path.display(), e))?;
+        if config.auto_ban.threshold == 0 {
+            return Err(format!(
+                "invalid auto_ban config in {}: threshold must be at least 1",
+                path.display()
+            )
+            .into());
+        }
+        config
+            .filter
+            .check()
+            .map_err(|e| format!("invalid filter config in {}: {}", path.display(), e))?;
+        config
+            .batch
+            .check()
+            .map_err(|e| format!("invalid batch config in {}: {}", path.display(), e))?;
+        config
+            .rpc
+            .check()
+            .map_err(|e| format!("invalid rpc config in {}: {}", path.display(), e))?;
+        config
+            .members
+            .check()
+            .map_err(|e| format!("invalid members config in {}: {}", path.display(), e))?;
+
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SwarmConfig {
+    /// Seconds a connection with nothing to do is kept open; 0 keeps idle
+    /// connections open forever. Also set by `--idle-timeout`.
+    pub idle_timeout_secs: u64,
+    /// Unset, both are offered, or noise alone when `network_id` is set.
+    /// Also set by `--security`.
+    pub security: Option<Security>,
+    /// Also set by `--muxer`.
+    pub muxer: Muxer,
+    /// Mixed into the noise handshake as its prologue, so only nodes given
+    /// the same id complete it; nodes of other deployments fail to connect.
+    /// Unset, the prologue is empty. Needs noise security, which it is
+    /// unless `security` says otherwise. Also set by `--network-id`.
+    pub network_id: Option<String>,
+    /// Whether our TCP listen ports are mapped on the gateway with UPnP, so
+    /// peers outside the network can dial us. Also turned off by
+    /// `--no-upnp`.
+    pub upnp: bool,
+}
+
+impl Default for SwarmConfig {
+    fn default() -> Self {
+        SwarmConfig {
+            idle_timeout_secs: 10,
+            security: None,
+            muxer: Muxer::default(),
+            network_id: None,
+            upnp: true,
+        }
+    }
+}
+
+/// How TCP and WebSocket connections are encrypted and authenticated. Peers
+/// agree on one of those both offer, or fail the handshake if there is none.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Security {
+    Noise,
+    /// TLS 1.3, for networks that block handshakes they don't recognize.
+    Tls,
+    /// Noise or TLS, whichever the peer speaks. Noise is proposed first when
+    /// we dial, so peers that speak both use it.
+    Both,
+}
+
+impl fmt::Display for Security {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Security::Noise => write!(f, "noise"),
+            Security::Tls => write!(f, "tls"),
+            Security::Both => write!(f, "both"),
+        }
+    }
+}
+
+impl FromStr for Security {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "noise" => Ok(Security::Noise),
+            "tls" => Ok(Security::Tls),
+            "both" => Ok(Security::Both),
+            _ => Err(format!(
+                "unknown security protocol {} (expected noise, tls or both)",
+                name
+            )),
+        }
+    }
+}
+
+/// How streams are multiplexed over a TCP or WebSocket connection. Only one
+/// is offered, so peers using the other fail the upgrade.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Muxer {
+    #[default]
+    Yamux,
+    /// For peers that only speak mplex. It has no flow control: a stream
+    /// whose reader falls behind is reset rather than slowed down.
+    Mplex,
+}
+
+impl fmt::Display for Muxer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Muxer::Yamux => write!(f, "yamux"),
+            Muxer::Mplex => write!(f, "mplex"),
+        }
+    }
+}
+
+impl FromStr for Muxer {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "yamux" => Ok(Muxer::Yamux),
+            "mplex" => Ok(Muxer::Mplex),
+            _ => Err(format!("unknown muxer {} (expected yamux or mplex)", name)),
+        }
+    }
+}
+
+impl SwarmConfig {
+    pub fn check(&self) -> Result<(), String> {
+        if self
+            .network_id
+            .as_ref()
+            .is_some_and(|network_id| network_id.is_empty())
+        {
+            return Err("network_id must not be empty; leave it out to go without".to_string());
+        }
+        // A peer of another network could otherwise get in over TLS, whose
+        // handshake has no prologue.
+        if self.network_id.is_some() && self.security() != Security::Noise {
+            return Err(format!(
+                "network_id needs noise security alone, but security is {}",
+                self.security()
+            ));
+        }
+        Ok(())
+    }
+
+    /// The security offered: `security` if set, or else both unless a
+    /// `network_id` needs noise alone.
+    pub fn security(&self) -> Security {
+        match (self.security, &self.network_id) {
+            (Some(security), _) => security,
+            (None, Some(_)) => Security::Noise,
+            (None, None) => Security::Both,
+        }
+    }
+
+    /// The idle timeout to hand to the swarm, where "never" is the longest
+    /// duration there is.
+    pub fn idle_timeout(&self) -> Duration {
+        match self.idle_timeout_secs {
+            0 => Duration::MAX,
+            secs => Duration::from_secs(secs),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProtocolConfig {
+    /// Whether the unversioned `/my-json-protocol` is still spoken next to
+    /// `/chat/json/1.0.0`, for peers that haven't upgraded.
+    pub legacy: bool,
+    /// Seconds to wait for a peer to answer a direct request before giving
+    /// up on it.
+    pub request_timeout_secs: u64,
+    /// Whether direct requests and responses are compressed for peers that
+    /// can read them so.
+    pub compression: bool,
+    /// Bytes of JSON above which a payload is compressed.
+    pub compress_above_bytes: usize,
+}
+
+impl Default for ProtocolConfig {
+    fn default() -> Self {
+        ProtocolConfig {
+            legacy: true,
+            request_timeout_secs: 10,
+            compression: true,
+            compress_above_bytes: 1024,
+        }
+    }
+}
+
+/// Parses an `--idle-timeout` value: a number of seconds, or `none` (like 0)
+/// to never close idle connections.
+pub fn parse_idle_timeout(value: &str) -> Result<u64, String> {
+    if value.eq_ignore_ascii_case("none") {
+        return Ok(0);
+    }
+    value
+        .parse()
+        .map_err(|_| format!("invalid idle timeout: {} (expected seconds or none)", value))
+}
+
+/// Fewest bytes `max_transmit_size` may be: below this even a short signed
+/// chat message can't be published.
+pub const MIN_TRANSMIT_SIZE: usize = 1024;
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GossipsubConfig {
+    /// Bundle of values to start from; the fields set here override it. Also
+    /// set by `--gossip-preset`.
+    pub preset: Option<GossipPreset>,
+    /// Target number of peers in each topic mesh.
+    pub mesh_n: Option<usize>,
+    /// Fewest mesh peers before more are grafted.
+    pub mesh_n_low: Option<usize>,
+    /// Most mesh peers before some are pruned.
+    pub mesh_n_high: Option<usize>,
+    pub heartbeat_interval_ms: Option<u64>,
+    /// Number of heartbeats a published message is kept for.
+    pub history_length: Option<usize>,
+    /// Number of those heartbeats whose messages are gossiped to peers
+    /// outside the mesh. At most `history_length`.
+    pub history_gossip: Option<usize>,
+    /// Whether our own messages go to every peer in the room rather than
+    /// only to the mesh. Faster to arrive, at the cost of bandwidth.
+    pub flood_publish: Option<bool>,
+    /// Largest gossipsub message, in bytes, sent or accepted.
+    pub max_transmit_size: Option<usize>,
+    /// Which messages gossipsub lets through to us and on to other peers.
+    pub validation: Validation,
+}
+
+/// How gossipsub checks the author, sequence number and signature it
+/// carries on every message. We always publish with
+/// `MessageAuthenticity::Signed`, so our own messages pass either mode;
+/// the mode only decides what we take from others.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Validation {
+    /// Messages need an author, a sequence number and a valid signature by
+    /// the author; unsigned and anonymous ones are dropped. Gossipsub then
+    /// holds every message back from other peers until the chat has checked
+    /// its content and signature too, so what we reject isn't relayed.
+    #[default]
+    Strict,
+    /// Unsigned messages and those without an author are let through too,
+    /// for peers publishing anonymously; signatures that are there are still
+    /// checked. Messages are relayed as soon as gossipsub took them in.
+    Permissive,
+}
+
+impl fmt::Display for Validation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Validation::Strict => write!(f, "strict"),
+            Validation::Permissive => write!(f, "permissive"),
+        }
+    }
+}
+
+impl GossipsubConfig {
+    /// Builds the gossipsub config, checking the combination of values first
+    /// so mistakes are reported by name.
+    pub fn build(&self) -> Result<gossipsub::Config, String> {
+        let defaults = gossipsub::Config::default();
+        let preset = self.preset.map(GossipPreset::config).unwrap_or_default();
+        let mesh_n = self.mesh_n.or(preset.mesh_n).unwrap_or(defaults.mesh_n());
+        let mesh_n_low = self
+            .mesh_n_low
+            .or(preset.mesh_n_low)
+            .unwrap_or(defaults.mesh_n_low());
+        let mesh_n_high = self
+            .mesh_n_high
+            .or(preset.mesh_n_high)
+            .unwrap_or(defaults.mesh_n_high());
+        let heartbeat_interval = self
+            .heartbeat_interval_ms
+            .or(preset.heartbeat_interval_ms)
+            .map(Duration::from_millis)
+            .unwrap_or(defaults.heartbeat_interval());
+        let history_length = self
+            .history_length
+            .or(preset.history_length)
+            .unwrap_or(defaults.history_length());
+        let history_gossip = self
+            .history_gossip
+            .or(preset.history_gossip)
+            .unwrap_or(defaults.history_gossip());
+        let flood_publish = self
+            .flood_publish
+            .or(preset.flood_publish)
+            .unwrap_or(defaults.flood_publish());
+        let max_transmit_size = self
+            .max_transmit_size
+            .or(preset.max_transmit_size)
+            .unwrap_or(defaults.max_transmit_size());
+
+        if mesh_n == 0 {
+            return Err("mesh_n must be at least 1".to_string());
+        }
+        if !(mesh_n_low <= mesh_n && mesh_n <= mesh_n_high) {
+            return Err(format!(
+                "mesh_n_low <= mesh_n <= mesh_n_high must hold, but got {} <= {} <= {}",
+                mesh_n_low, mesh_n, mesh_n_high
+            ));
+        }
+        if heartbeat_interval.is_zero() {
+            return Err("heartbeat_interval_ms must be greater than 0".to_string());
+        }
+        if history_gossip == 0 {
+            return Err("history_gossip must be at least 1".to_string());
+        }
+        if history_length < history_gossip {
+            return Err(format!(
+                "history_length must be at least history_gossip ({}), but got {}",
+                history_gossip, history_length
+            ));
+        }
+        if max_transmit_size < MIN_TRANSMIT_SIZE {
+            return Err(format!(
+                "max_transmit_size must be at least {} bytes, but got {}",
+                MIN_TRANSMIT_SIZE, max_transmit_size
+            ));
+        }
+
+        // The outbound quota isn't configurable, so shrink it to fit small meshes
+        // rather than rejecting them.
+        let mesh_outbound_min = defaults.mesh_outbound_min().min(mesh_n_low).min(mesh_n / 2);
+
+        let mut builder = gossipsub::ConfigBuilder::default();
+        builder
+            .mesh_n(mesh_n)
+            .mesh_n_low(mesh_n_low)
+            .mesh_n_high(mesh_n_high)
+            .mesh_outbound_min(mesh_outbound_min)
+            .heartbeat_interval(heartbeat_interval)
+            .history_length(history_length)
+            .history_gossip(history_gossip)
+            .flood_publish(flood_publish)
+            .max_transmit_size(max_transmit_size);
+        match self.validation {
+            Validation::Strict => builder
+                .validation_mode(gossipsub::ValidationMode::Strict)
+                .validate_messages(),
+            Validation::Permissive => {
+                builder.validation_mode(gossipsub::ValidationMode::Permissive)
+            }
+        };
+        builder.build().map_err(|e| e.to_string())
+    }
+}
+
+/// Gossipsub values tuned for a kind of network, picked with
+/// `--gossip-preset` or `gossipsub.preset`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GossipPreset {
+    /// A handful of peers on one network: small meshes, a fast heartbeat
+    /// and flood publishing, since bandwidth is cheap and latency matters.
+    Lan,
+    /// Larger public rooms over the internet: wider meshes for resilience
+    /// against churn, and messages left to the mesh and gossip rather than
+    /// flooded to every peer.
+    Wan,
+}
+
+impl GossipPreset {
+    /// The values of the preset as a config; what it leaves unset keeps the
+    /// library default.
+    pub fn config(self) -> GossipsubConfig {
+        match self {
+            GossipPreset::Lan => GossipsubConfig {
+                mesh_n: Some(4),
+                mesh_n_low: Some(2),
+                mesh_n_high: Some(8),
+                heartbeat_interval_ms: Some(500),
+                flood_publish: Some(true),
+                ..Default::default()
+            },
+            GossipPreset::Wan => GossipsubConfig {
+                mesh_n: Some(8),
+                mesh_n_low: Some(6),
+                mesh_n_high: Some(12),
+                heartbeat_interval_ms: Some(1000),
+                history_length: Some(6),
+                history_gossip: Some(3),
+                flood_publish: Some(false),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl fmt::Display for GossipPreset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GossipPreset::Lan => write!(f, "lan"),
+            GossipPreset::Wan => write!(f, "wan"),
+        }
+    }
+}
+
+impl FromStr for GossipPreset {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "lan" => Ok(GossipPreset::Lan),
+            "wan" => Ok(GossipPreset::Wan),
+            _ => Err(format!(
+                "unknown gossip preset {} (expected lan or wan)",
+                name
+            )),
+        }
+    }
+}
+
+/// Peers gossipsub sends every message to, whatever its mesh looks like.
+/// Meshes of two or three peers form slowly or not at all without them.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PeeringConfig {
+    /// Peers kept connected: dialed at startup and redialed whenever they
+    /// drop, whether or not anything discovers them.
+    pub pinned: Vec<PinnedPeer>,
+    /// While fewer peers than this are connected and explicit, the peers
+    /// mDNS finds are made explicit too, until they disconnect. 0 leaves
+    /// mDNS peers to the mesh.
+    pub explicit_below: usize,
+}
+
+impl Default for PeeringConfig {
+    fn default() -> Self {
+        PeeringConfig {
+            pinned: Vec::new(),
+            explicit_below: 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PinnedPeer {
+    pub peer_id: PeerId,
+    pub address: Multiaddr,
+}
+
+impl PeeringConfig {
+    pub fn check(&self) -> Result<(), String> {
+        for (index, pinned) in self.pinned.iter().enumerate() {
+            check_dial_address(&pinned.address)
+                .map_err(|e| format!("can't dial pinned peer {}: {}", pinned.peer_id, e))?;
+            if let Some(Protocol::P2p(peer)) = pinned.address.iter().last() {
+                if peer != pinned.peer_id {
+                    return Err(format!(
+                        "the address of pinned peer {} is for {}",
+                        pinned.peer_id, peer
+                    ));
+                }
+            }
+            if self.pinned[..index]
+                .iter()
+                .any(|earlier| earlier.peer_id == pinned.peer_id)
+            {
+                return Err(format!("peer {} is pinned twice", pinned.peer_id));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Who may answer at certain addresses. A connection we dial to an address
+/// a pin matches is closed, with a warning, unless the peer authenticated
+/// as the pinned one.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyPinsConfig {
+    pub pins: Vec<KeyPin>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeyPin {
+    /// A multiaddr whose values may be `*`, such as `/ip4/192.0.2.1/tcp/*`,
+    /// matching the addresses that start with it.
+    pub address: String,
+    pub peer_id: PeerId,
+}
+
+impl KeyPinsConfig {
+    pub fn build(&self) -> Result<KeyPins, String> {
+        let mut pins = Vec::new();
+        for pin in &self.pins {
+            let pattern: AddressPattern = pin.address.parse()?;
+            if let Some(peer) = pattern.peer_id().filter(|peer| *peer != pin.peer_id) {
+                return Err(format!(
+                    "{} is pinned to {}, but names {}",
+                    pattern, pin.peer_id, peer
+                ));
+            }
+            pins.push((pattern, pin.peer_id));
+        }
+        Ok(KeyPins::new(pins))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MdnsConfig {
+    /// Whether peers on the local network are discovered over mDNS. Also
+    /// turned off by `--no-mdns`.
+    pub enabled: bool,
+    /// How often the network is queried for peers.
+    pub query_interval_ms: Option<u64>,
+    /// How long peers may keep our advertised addresses.
+    pub ttl_secs: Option<u64>,
+}
+
+impl Default for MdnsConfig {
+    fn default() -> Self {
+        MdnsConfig {
+            enabled: true,
+            query_interval_ms: None,
+            ttl_secs: None,
+        }
+    }
+}
+
+impl MdnsConfig {
+    pub fn build(&self) -> Result<mdns::Config, String> {
+        let defaults = mdns::Config::default();
+        let query_interval = self
+            .query_interval_ms
+            .map(Duration::from_millis)
+            .unwrap_or(defaults.query_interval);
+        let ttl = self
+            .ttl_secs
+            .map(Duration::from_secs)
+            .unwrap_or(defaults.ttl);
+
+        if query_interval.is_zero() {
+            return Err("query_interval_ms must be greater than 0".to_string());
+        }
+        if ttl.is_zero() {
+            return Err("ttl_secs must be greater than 0".to_string());
+        }
+
+        Ok(mdns::Config {
+            query_interval,
+            ttl,
+            ..defaults
+        })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RendezvousConfig {
+    /// Whether the node serves as a rendezvous point for others. Also turned
+    /// on by `--rendezvous-server`.
+    pub server: bool,
+    /// Seconds a registration with a server lasts. It is renewed halfway.
+    pub ttl_secs: u64,
+    /// How often servers are asked about peers that registered since.
+    pub discover_interval_secs: u64,
+}
+
+impl Default for RendezvousConfig {
+    fn default() -> Self {
+        RendezvousConfig {
+            server: false,
+            ttl_secs: rendezvous::DEFAULT_TTL,
+            discover_interval_secs: 60,
+        }
+    }
+}
+
+impl RendezvousConfig {
+    pub fn check(&self) -> Result<(), String> {
+        // Servers refuse registrations outside of these bounds.
+        if !(rendezvous::MIN_TTL..=rendezvous::MAX_TTL).contains(&self.ttl_secs) {
+            return Err(format!(
+                "ttl_secs must be between {} and {}, but got {}",
+                rendezvous::MIN_TTL,
+                rendezvous::MAX_TTL,
+                self.ttl_secs
+            ));
+        }
+        if self.discover_interval_secs == 0 {
+            return Err("discover_interval_secs must be greater than 0".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Finding room members through provider records in the Kademlia DHT.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DhtConfig {
+    /// Seconds other peers keep our provider records. They are republished
+    /// halfway through, so they don't run out while we are in the room.
+    pub provider_ttl_secs: u64,
+    /// Seconds between lookups of the other providers of each room.
+    pub lookup_interval_secs: u64,
+}
+
+impl Default for DhtConfig {
+    fn default() -> Self {
+        DhtConfig {
+            provider_ttl_secs: 48 * 60 * 60,
+            lookup_interval_secs: 60,
+        }
+    }
+}
+
+impl DhtConfig {
+    pub fn check(&self) -> Result<(), String> {
+        if self.provider_ttl_secs < 2 {
+            return Err(format!(
+                "provider_ttl_secs must be at least 2, but got {}",
+                self.provider_ttl_secs
+            ));
+        }
+        if self.lookup_interval_secs == 0 {
+            return Err("lookup_interval_secs must be greater than 0".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn build(&self) -> kad::Config {
+        let ttl = Duration::from_secs(self.provider_ttl_secs);
+        let mut config = kad::Config::default();
+        config
+            .set_protocol_names(vec![KAD_PROTOCOL])
+            .set_provider_record_ttl(Some(ttl))
+            .set_provider_publication_interval(Some(ttl / 2));
+        config
+    }
+}
+
+/// Caps on the connections the node keeps open. Connections over a cap are
+/// refused as they come in. The defaults suit a personal node; a relay or
+/// rendezvous server that many peers stay connected to wants more, e.g.
+/// `max_established` 1000, `max_established_per_peer` 4,
+/// `reserved_outbound` 50 and `max_pending_incoming` 128.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConnectionLimitsConfig {
+    /// Established connections in both directions combined.
+    pub max_established: u32,
+    pub max_established_per_peer: u32,
+    /// Of `max_established`, connections kept free for the ones we dial, so
+    /// new inbound connections are denied before we lose the ability to
+    /// reach the peers we have messages for.
+    pub reserved_outbound: u32,
+    /// Inbound connections still being negotiated; unlimited if unset.
+    pub max_pending_incoming: Option<u32>,
+    /// Dials in progress; unlimited if unset.
+    pub max_pending_outgoing: Option<u32>,
+}
+
+impl Default for ConnectionLimitsConfig {
+    fn default() -> Self {
+        ConnectionLimitsConfig {
+            max_established: 64,
+            max_established_per_peer: 2,
+            reserved_outbound: 8,
+            max_pending_incoming: None,
+            max_pending_outgoing: None,
+        }
+    }
+}
+
+impl ConnectionLimitsConfig {
+    pub fn build(&self) -> Result<ConnectionLimits, String> {
+        if self.max_established == 0 {
+            return Err("max_established must be at least 1".to_string());
+        }
+        if self.max_established_per_peer == 0 {
+            return Err("max_established_per_peer must be at least 1".to_string());
+        }
+        if self.reserved_outbound >= self.max_established {
+            return Err(format!(
+                "reserved_outbound must be less than max_established ({}), but got {}",
+                self.max_established, self.reserved_outbound
+            ));
+        }
+
+        Ok(ConnectionLimits::default()
+            .with_max_established(Some(self.max_established))
+            .with_max_established_incoming(Some(self.max_established - self.reserved_outbound))
+            .with_max_established_per_peer(Some(self.max_established_per_peer))
+            .with_max_pending_incoming(self.max_pending_incoming)
+            .with_max_pending_outgoing(self.max_pending_outgoing))
+    }
+}
+
+/// When misbehaving peers are blocked automatically.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AutoBanConfig {
+    /// Violations after which a peer is blocked.
+    pub threshold: u32,
+    /// Seconds without a violation after which a peer's count is reset.
+    pub cooldown_secs: u64,
+}
+
+impl Default for AutoBanConfig {
+    fn default() -> Self {
+        AutoBanConfig {
+            threshold: 5,
+            cooldown_secs: 10 * 60,
+        }
+    }
+}
+
+/// How many messages each peer may send us. Applies to gossip by author and
+/// to direct requests by sender.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// Sustained messages per second.
+    pub messages_per_sec: f64,
+    /// Messages a quiet peer may send at once.
+    pub burst: u32,
+    /// Seconds a peer may stay over its limit before it is blocked.
+    pub block_after_secs: u64,
+    /// Seconds a peer blocked for flooding stays blocked.
+    pub block_secs: u64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            messages_per_sec: 10.0,
+            burst: 30,
+            block_after_secs: 60,
+            block_secs: 10 * 60,
+        }
+    }
+}
+
+impl RateLimitConfig {
+    pub fn check(&self) -> Result<(), String> {
+        if !(self.messages_per_sec > 0.0 && self.messages_per_sec.is_finite()) {
+            return Err(format!(
+                "messages_per_sec must be greater than 0, but got {}",
+                self.messages_per_sec
+            ));
+        }
+        if self.burst == 0 {
+            return Err("burst must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Sharing the peers we know with the peers we connect to.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PeerExchangeConfig {
+    /// Seconds a peer must wait between requests, past an initial two, before
+    /// we share peers with it again; it gets an empty list until then.
+    pub min_request_interval_secs: u64,
+    /// Peers learned through exchange are dialed after a random delay of up
+    /// to this many seconds.
+    pub max_dial_delay_secs: u64,
+    /// Peers learned through exchange are only dialed while we are connected
+    /// to fewer peers than this. They are always kept in the address book.
+    pub target_peers: usize,
+}
+
+impl Default for PeerExchangeConfig {
+    fn default() -> Self {
+        PeerExchangeConfig {
+            min_request_interval_secs: 30,
+            max_dial_delay_secs: 10,
+            target_peers: 8,
+        }
+    }
+}
+
+/// When to shed broadcasts because of how many messages arrive from all
+/// peers together, however few each of them sends.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FloodConfig {
+    /// Messages per second, averaged over the window, above which gossip is
+    /// dropped. Direct messages are always handled.
+    pub shed_above_per_sec: f64,
+    /// Messages per second below which gossip is handled again.
+    pub recover_below_per_sec: f64,
+    /// Seconds the rate is averaged over.
+    pub window_secs: u64,
+}
+
+impl Default for FloodConfig {
+    fn default() -> Self {
+        FloodConfig {
+            shed_above_per_sec: 200.0,
+            recover_below_per_sec: 100.0,
+            window_secs: 5,
+        }
+    }
+}
+
+impl FloodConfig {
+    pub fn check(&self) -> Result<(), String> {
+        if !(self.shed_above_per_sec > 0.0 && self.shed_above_per_sec.is_finite()) {
+            return Err(format!(
+                "shed_above_per_sec must be greater than 0, but got {}",
+                self.shed_above_per_sec
+            ));
+        }
+        if !(self.recover_below_per_sec > 0.0
+            && self.recover_below_per_sec <= self.shed_above_per_sec)
+        {
+            return Err(format!(
+                "recover_below_per_sec must be greater than 0 and at most shed_above_per_sec ({}), but got {}",
+                self.shed_above_per_sec, self.recover_below_per_sec
+            ));
+        }
+        if self.window_secs == 0 {
+            return Err("window_secs must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReplayConfig {
+    /// How far behind a sender's latest sequence number a message may be and
+    /// still be accepted, for messages delivered out of order.
+    pub window: u64,
+}
+
+impl Default for ReplayConfig {
+    fn default() -> Self {
+        ReplayConfig { window: 64 }
+    }
+}
+
+/// Limits on the messages held for offline peers, when forwarding is turned
+/// on with `--forward`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StoreForwardConfig {
+    /// Messages held for each offline peer; further ones are refused.
+    pub max_per_target: usize,
+    /// Messages held from each author, for whichever peers.
+    pub max_per_sender: usize,
+    /// Messages held in all.
+    pub max_messages: usize,
+    /// Seconds a message is held before it is dropped undelivered. Also how
+    /// long we ask forwarders to hold our own messages. At most 30 days.
+    pub ttl_secs: u64,
+}
+
+/// Longest `store_forward.ttl_secs` may be.
+const MAX_STORE_FORWARD_TTL_SECS: u64 = 30 * 24 * 60 * 60;
+
+impl Default for StoreForwardConfig {
+    fn default() -> Self {
+        StoreForwardConfig {
+            max_per_target: 32,
+            max_per_sender: 64,
+            max_messages: 1024,
+            ttl_secs: 24 * 60 * 60,
+        }
+    }
+}
+
+impl StoreForwardConfig {
+    pub fn check(&self) -> Result<(), String> {
+        for (name, limit) in [
+            ("max_per_target", self.max_per_target),
+            ("max_per_sender", self.max_per_sender),
+            ("max_messages", self.max_messages),
+        ] {
+            if limit == 0 {
+                return Err(format!("{} must be at least 1", name));
+            }
+        }
+        if self.ttl_secs == 0 || self.ttl_secs > MAX_STORE_FORWARD_TTL_SECS {
+            return Err(format!(
+                "ttl_secs must be greater than 0 and at most {}, but got {}",
+                MAX_STORE_FORWARD_TTL_SECS, self.ttl_secs
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// How direct messages that weren't answered are sent again.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryConfig {
+    /// Attempts at a direct message, the first one included, before it is
+    /// reported as failed. 1 turns retries off.
+    pub max_attempts: u32,
+    /// Seconds to wait before each retry, the first entry before the second
+    /// attempt. The last entry is repeated for any further attempts.
+    pub backoff_secs: Vec<u64>,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            max_attempts: 3,
+            backoff_secs: vec![2, 10],
+        }
+    }
+}
+
+impl RetryConfig {
+    pub fn check(&self) -> Result<(), String> {
+        if self.max_attempts == 0 {
+            return Err("max_attempts must be at least 1".to_string());
+        }
+        if self.max_attempts > 1 && self.backoff_secs.is_empty() {
+            return Err("backoff_secs must not be empty when retrying".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.max_attempts,
+            backoff: self
+                .backoff_secs
+                .iter()
+                .map(|secs| Duration::from_secs(*secs))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    /// Messages of each room kept in memory. Older ones stay in the history
+    /// file only.
+    pub max_messages_per_room: usize,
+    /// Messages of all rooms together kept in memory. Also set by
+    /// `--history-limit`.
+    pub max_messages: usize,
+    pub deleted_messages: DeletedMessages,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        HistoryConfig {
+            max_messages_per_room: DEFAULT_ROOM_CAPACITY,
+            max_messages: DEFAULT_HISTORY_LIMIT,
+            deleted_messages: DeletedMessages::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisplayConfig {
+    /// Incoming messages are printed at most this often, in batches, while
+    /// they arrive in bursts. 0 prints each one as it arrives.
+    pub flush_interval_ms: u64,
+}
+
+impl Default for DisplayConfig {
+    fn default() -> Self {
+        DisplayConfig {
+            flush_interval_ms: 50,
+        }
+    }
+}
+
+/// How deleted messages are shown. Either way the tombstone keeps its place
+/// in the history.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeletedMessages {
+    /// Shown as `[deleted]`.
+    #[default]
+    Tombstone,
+    /// Not shown at all.
+    Hide,
+}
+
+/// Words hidden from incoming messages, such as profanity or spam keywords.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilterConfig {
+    /// Matched as whole words, ignoring case.
+    pub blocked_words: Vec<String>,
+    pub action: FilterAction,
+}
+
+impl FilterConfig {
+    pub fn check(&self) -> Result<(), String> {
+        self.blocked_words
+            .iter()
+            .try_for_each(|word| check_word(word))
+    }
+
+    pub fn filter(&self) -> ContentFilter {
+        ContentFilter::new(&self.blocked_words, self.action)
+    }
+}
+
+/// What is done with an incoming message containing a blocked word.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterAction {
+    /// Shown with each blocked word replaced by `*`.
+    #[default]
+    Mask,
+    /// Not shown, stored or notified at all. Words edited into a message
+    /// already shown are masked.
+    Drop,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BatchConfig {
+    /// Milliseconds direct messages to a peer are held after the last
+    /// request to it, to go out together. 0 sends each on its own.
+    pub window_ms: u64,
+    /// Most messages in one batch; a full batch goes out at once.
+    pub max_messages: usize,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        BatchConfig {
+            window_ms: 50,
+            max_messages: 32,
+        }
+    }
+}
+
+impl BatchConfig {
+    pub fn check(&self) -> Result<(), String> {
+        if self.max_messages == 0 {
+            return Err("max_messages must be at least 1".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn batcher(&self) -> Batcher {
+        Batcher::new(Duration::from_millis(self.window_ms), self.max_messages)
+    }
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RpcConfig {
+    /// Bearer token JSON-RPC calls on `--rpc-addr` must carry. Without one,
+    /// a new token is made up on every start and printed.
+    pub token: Option<String>,
+}
+
+impl RpcConfig {
+    pub fn check(&self) -> Result<(), String> {
+        if self
+            .token
+            .as_ref()
+            .is_some_and(|token| token.trim().is_empty())
+        {
+            return Err("token must not be empty; leave it out to go without".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// How the members of each room are tracked for `/members`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MembersConfig {
+    /// Seconds between the presence heartbeats we send to our rooms. A
+    /// member that missed one shows as offline.
+    pub heartbeat_secs: u64,
+    /// Seconds after which a member we aren't connected to and haven't
+    /// heard from is taken to have left.
+    pub stale_after_secs: u64,
+}
+
+impl Default for MembersConfig {
+    fn default() -> Self {
+        MembersConfig {
+            heartbeat_secs: 30,
+            stale_after_secs: 120,
+        }
+    }
+}
+
+impl MembersConfig {
+    pub fn check(&self) -> Result<(), String> {
+        if self.heartbeat_secs == 0 {
+            return Err("heartbeat_secs must be at least 1".to_string());
+        }
+        if self.stale_after_secs <= self.heartbeat_secs {
+            return Err(format!(
+                "stale_after_secs must be greater than heartbeat_secs ({}), but got {}",
+                self.heartbeat_secs, self.stale_after_secs
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn members(&self) -> RoomMembers {
+        RoomMembers::new(
+            Duration::from_secs(self.heartbeat_secs),
+            Duration::from_secs(self.stale_after_secs),
+        )
+    }
+}
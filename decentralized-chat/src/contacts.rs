@@ -0,0 +1,86 @@
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fs, io, path::Path};
+
+/// Name of the file inside the data directory holding the contacts added
+/// with `/addfriend`.
+pub const CONTACTS_FILE: &str = "contacts.json";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Contact {
+    /// The name we know the peer by, shown instead of the one it announces.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nickname: Option<String>,
+    /// Unix timestamp (seconds) at which the peer was added.
+    pub added_at: u64,
+}
+
+/// The peers added with `/addfriend`, persisted across restarts and dialed
+/// on startup.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Contacts {
+    contacts: HashMap<PeerId, Contact>,
+}
+
+impl Contacts {
+    /// Loads the contacts from `path`, starting with none if the file does
+    /// not exist yet.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        match fs::read(path) {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Contacts::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, serde_json::to_vec_pretty(self)?)
+    }
+
+    /// Adds `peer`, or replaces the nickname of a peer already added.
+    /// Returns whether it is new.
+    pub fn add(&mut self, peer: PeerId, nickname: Option<String>, now: u64) -> bool {
+        match self.contacts.get_mut(&peer) {
+            Some(contact) => {
+                contact.nickname = nickname;
+                false
+            }
+            None => {
+                self.contacts.insert(
+                    peer,
+                    Contact {
+                        nickname,
+                        added_at: now,
+                    },
+                );
+                true
+            }
+        }
+    }
+
+    pub fn remove(&mut self, peer: &PeerId) -> Option<Contact> {
+        self.contacts.remove(peer)
+    }
+
+    pub fn get(&self, peer: &PeerId) -> Option<&Contact> {
+        self.contacts.get(peer)
+    }
+
+    /// The nickname we gave `peer`, if it is a contact with one.
+    pub fn nickname(&self, peer: &PeerId) -> Option<&str> {
+        self.contacts.get(peer)?.nickname.as_deref()
+    }
+
+    /// Every contact, named ones first by name, then the rest by peer id.
+    pub fn sorted(&self) -> Vec<(&PeerId, &Contact)> {
+        let mut contacts: Vec<_> = self.contacts.iter().collect();
+        contacts.sort_by_cached_key(|(peer, contact)| {
+            (
+                contact.nickname.is_none(),
+                contact.nickname.clone(),
+                peer.to_string(),
+            )
+        });
+        contacts
+    }
+}
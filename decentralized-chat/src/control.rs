@@ -0,0 +1,245 @@
+use crate::{
+    command::{Command, Reply},
+    event::ChatEvent,
+    handle::ChatHandle,
+    parser,
+};
+use serde_json::{json, Value};
+use std::{
+    fs, io,
+    os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    sync::{broadcast, broadcast::error::RecvError, oneshot},
+};
+
+/// A command received over the control socket, with the channel its reply is
+/// sent back on.
+pub type ControlRequest = (Command, oneshot::Sender<Result<Reply, String>>);
+
+/// A Unix domain socket accepting newline-delimited JSON commands, each
+/// answered with a line of `{"ok": .., "reply"|"error": ..}`. A connection
+/// that sends `{"command": "subscribe"}` also gets every `ChatEvent` from
+/// then on, each on a line of its own. Only our user may connect, and the
+/// socket file is removed when this is dropped.
+pub struct ControlSocket {
+    path: PathBuf,
+}
+
+impl ControlSocket {
+    pub fn bind(path: PathBuf, handle: ChatHandle) -> io::Result<Self> {
+        remove_stale_socket(&path)?;
+
+        let listener = bind_private(&path)?;
+        tokio::spawn(accept_connections(listener, handle));
+
+        Ok(ControlSocket { path })
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Binds a socket at `path` that others can't connect to at any moment: it
+/// is bound inside a new directory only we may enter, narrowed to 0600
+/// there, and only then linked at `path`, which fails rather than replace
+/// whatever appeared there meanwhile.
+fn bind_private(path: &Path) -> io::Result<UnixListener> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    // Kept short, as socket paths are limited to about a hundred bytes.
+    let staging = parent.join(format!(".{:08x}", rand::random::<u32>()));
+    fs::DirBuilder::new().mode(0o700).create(&staging)?;
+    let staged = staging.join("s");
+    let bound = UnixListener::bind(&staged).and_then(|listener| {
+        fs::set_permissions(&staged, fs::Permissions::from_mode(0o600))?;
+        fs::hard_link(&staged, path)?;
+        Ok(listener)
+    });
+    let _ = fs::remove_file(&staged);
+    let _ = fs::remove_dir(&staging);
+    bound
+}
+
+/// Removes a socket file left behind by a node that did not shut down cleanly,
+/// refusing to touch one that is still being served, or anything that isn't
+/// a socket.
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    let file_type = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata.file_type(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if !file_type.is_socket() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        ));
+    }
+
+    if std::os::unix::net::UnixStream::connect(path).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("control socket {} is already in use", path.display()),
+        ));
+    }
+
+    fs::remove_file(path)
+}
+
+async fn accept_connections(listener: UnixListener, handle: ChatHandle) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let handle = handle.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_connection(stream, handle).await {
+                        println!("Control connection failed: {}", e);
+                    }
+                });
+            }
+            Err(e) => println!("Failed to accept control connection: {}", e),
+        }
+    }
+}
+
+async fn handle_connection(stream: UnixStream, handle: ChatHandle) -> io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    let mut events: Option<broadcast::Receiver<ChatEvent>> = None;
+
+    loop {
+        let line = tokio::select! {
+            line = lines.next_line() => match line? {
+                Some(line) => line,
+                None => return Ok(()),
+            },
+            event = next_event(&mut events) => {
+                match event {
+                    Ok(event) => {
+                        let line = serde_json::to_string(&event).expect("events serialize");
+                        writer.write_all(format!("{}\n", line).as_bytes()).await?;
+                    }
+                    // A client that falls behind misses the oldest events.
+                    Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => events = None,
+                }
+                continue;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = if is_subscribe(&line) {
+            events.get_or_insert_with(|| handle.events());
+            json!({ "ok": true, "reply": { "type": "subscribed" } })
+        } else {
+            let result = match serde_json::from_str::<Command>(&line) {
+                Ok(command) => handle.execute(command).await,
+                Err(e) => Err(format!("Invalid command: {}", e)),
+            };
+            match result {
+                Ok(reply) => json!({ "ok": true, "reply": reply }),
+                Err(error) => json!({ "ok": false, "error": error }),
+            }
+        };
+        writer
+            .write_all(format!("{}\n", response).as_bytes())
+            .await?;
+    }
+}
+
+/// The next event for a subscribed connection; never finishes for others.
+async fn next_event(
+    events: &mut Option<broadcast::Receiver<ChatEvent>>,
+) -> Result<ChatEvent, RecvError> {
+    match events {
+        Some(events) => events.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Whether `line` asks for events, which goes no further than the socket.
+fn is_subscribe(line: &str) -> bool {
+    serde_json::from_str::<Value>(line)
+        .is_ok_and(|request| request.get("command") == Some(&json!("subscribe")))
+}
+
+/// Attaches to the control socket of the node at `path`, as `attach` does:
+/// every event the node broadcasts is printed, and every line of `input` is
+/// run on the node like a line typed at its prompt, so plain text is sent to
+/// its current room. Returns once the node has answered everything in
+/// `input` after it ends, or the node goes away.
+pub async fn attach(path: &Path, input: impl AsyncBufRead + Unpin) -> io::Result<()> {
+    let stream = UnixStream::connect(path).await.map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("can't connect to control socket {}: {}", path.display(), e),
+        )
+    })?;
+    let (reader, mut writer) = stream.into_split();
+    let mut responses = BufReader::new(reader).lines();
+    let mut input = input.lines();
+    let mut input_open = true;
+
+    writer.write_all(b"{\"command\":\"subscribe\"}\n").await?;
+    loop {
+        tokio::select! {
+            line = input.next_line(), if input_open => {
+                let Some(line) = line? else {
+                    // The node hangs up once it has answered the rest.
+                    input_open = false;
+                    writer.shutdown().await?;
+                    continue;
+                };
+                match parser::parse(&line) {
+                    Ok(Some(command)) => {
+                        let request = serde_json::to_string(&command).expect("commands serialize");
+                        writer.write_all(format!("{}\n", request).as_bytes()).await?;
+                    }
+                    Ok(None) => {}
+                    Err(e) => println!("{}", e),
+                }
+            }
+            response = responses.next_line() => {
+                let Some(response) = response? else {
+                    if input_open {
+                        println!("The node closed the control socket");
+                    }
+                    return Ok(());
+                };
+                print_response(&response);
+            }
+        }
+    }
+}
+
+/// Prints an event or reply read from the control socket. Sends and the
+/// subscription are only acknowledged, so they are left out.
+fn print_response(response: &str) {
+    if let Ok(event) = serde_json::from_str::<ChatEvent>(response) {
+        println!("{}", event);
+        return;
+    }
+    let Ok(response) = serde_json::from_str::<Value>(response) else {
+        println!("Unreadable response from the node: {}", response);
+        return;
+    };
+    if let Some(error) = response.get("error").and_then(Value::as_str) {
+        println!("{}", error);
+        return;
+    }
+    let reply = &response["reply"];
+    if !matches!(reply["type"].as_str(), Some("sent" | "subscribed")) {
+        println!("{}", reply);
+    }
+}
@@ -0,0 +1,128 @@
+use std::{
+    fs::{self, File, OpenOptions, TryLockError},
+    io::{self, Read, Write},
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+/// The file in the data directory holding the process id of the daemon
+/// running on it.
+pub const PID_FILE: &str = "daemon.pid";
+
+/// The PID file of a running daemon. It stays locked for as long as the
+/// daemon runs, which refuses a second daemon on the same data directory and
+/// tells a file left by a crashed one from a live one. The file is removed
+/// when this is dropped.
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+    _file: File,
+}
+
+impl PidFile {
+    /// Locks the PID file at `path` and writes our process id into it.
+    pub fn create(path: PathBuf) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let pid = read_pid(&mut file)
+                    .map_or_else(|_| "unknown pid".to_string(), |pid| format!("pid {}", pid));
+                return Err(io::Error::new(
+                    io::ErrorKind::ResourceBusy,
+                    format!(
+                        "another daemon ({}) is already running on this data directory; \
+                         stop it first",
+                        pid
+                    ),
+                ));
+            }
+            Err(TryLockError::Error(e)) => return Err(e),
+        }
+
+        file.set_len(0)?;
+        writeln!(file, "{}", std::process::id())?;
+        Ok(PidFile { path, _file: file })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// The process id of the daemon holding the PID file at `path`.
+pub fn running_pid(path: &Path) -> io::Result<u32> {
+    let no_daemon = || {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no daemon is running ({} is not held)", path.display()),
+        )
+    };
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(no_daemon()),
+        Err(e) => return Err(e),
+    };
+    // A file we can lock was left by a daemon that is gone.
+    if file.try_lock_shared().is_ok() {
+        return Err(no_daemon());
+    }
+    read_pid(&mut file)
+}
+
+fn read_pid(file: &mut File) -> io::Result<u32> {
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    contents.trim().parse().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid PID file contents: {:?}", contents.trim()),
+        )
+    })
+}
+
+/// Sends SIGTERM to the daemon holding the PID file at `path` and waits up
+/// to `timeout` for it to shut down. Returns the daemon's process id.
+pub fn stop(path: &Path, timeout: Duration) -> io::Result<u32> {
+    let pid = running_pid(path)?;
+    let pid_t = libc::pid_t::try_from(pid)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "pid out of range"))?;
+    // Safety: kill only takes plain integers.
+    if unsafe { libc::kill(pid_t, libc::SIGTERM) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let deadline = Instant::now() + timeout;
+    while running_pid(path).is_ok() {
+        if Instant::now() >= deadline {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("daemon (pid {}) did not shut down in time", pid),
+            ));
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    Ok(pid)
+}
+
+/// Appends everything the process prints, and its errors, to `path` instead
+/// of the terminal.
+pub fn redirect_output(path: &Path) -> io::Result<()> {
+    let log = OpenOptions::new().create(true).append(true).open(path)?;
+    for target in [libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+        // Safety: both descriptors stay open; `log` is only closed after
+        // having been duplicated onto them.
+        if unsafe { libc::dup2(log.as_raw_fd(), target) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
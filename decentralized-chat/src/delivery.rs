@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+
+/// How far one of our own messages got on its way to its readers, shown
+/// next to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    /// Not handed to the network yet: a direct message waiting for a
+    /// connection or for its next attempt.
+    Pending,
+    /// Published to the room, sent to the peer, or held by forwarders.
+    Sent,
+    /// The recipient acknowledged the direct message.
+    Delivered,
+    /// The recipient reported reading the message. Nothing sends read
+    /// receipts yet.
+    Read,
+    /// Every attempt at a direct message failed and no forwarder holds it.
+    Failed,
+}
+
+impl DeliveryStatus {
+    /// The status once `next` is reported. Once delivered a message stays
+    /// so, whatever a late failure or retry says, and a failed one only
+    /// recovers if it turns out to have been delivered after all.
+    pub fn advance(self, next: DeliveryStatus) -> DeliveryStatus {
+        use DeliveryStatus::*;
+        match (self, next) {
+            (Read, _) => Read,
+            (Delivered, Pending | Sent | Failed) => Delivered,
+            (Failed, Pending | Sent) => Failed,
+            (_, next) => next,
+        }
+    }
+
+    /// The glyph shown after the message.
+    pub fn glyph(self) -> &'static str {
+        match self {
+            DeliveryStatus::Pending => "…",
+            DeliveryStatus::Sent => "✓",
+            DeliveryStatus::Delivered => "✓✓",
+            DeliveryStatus::Read => "👁",
+            DeliveryStatus::Failed => "✗",
+        }
+    }
+}
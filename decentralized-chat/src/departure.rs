@@ -0,0 +1,59 @@
+use libp2p::PeerId;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// Peers whose last connection closed, held for a grace period before they
+/// count as gone. A peer that connects again within it, as it does when a
+/// muxer restarts or a pinned peer is redialed, never counts as having left,
+/// so nothing shows it going offline and coming back.
+#[derive(Debug)]
+pub struct Departures {
+    grace: Duration,
+    /// When each peer's last connection closed, and why.
+    closed: HashMap<PeerId, (Instant, String)>,
+}
+
+impl Departures {
+    pub fn new(grace: Duration) -> Self {
+        Departures {
+            grace,
+            closed: HashMap::new(),
+        }
+    }
+
+    /// Whether no peer is within its grace period.
+    pub fn is_empty(&self) -> bool {
+        self.closed.is_empty()
+    }
+
+    /// Records that the last connection to `peer` closed at `now`, for
+    /// `reason`.
+    pub fn closed(&mut self, peer: PeerId, reason: String, now: Instant) {
+        self.closed.insert(peer, (now, reason));
+    }
+
+    /// Records that `peer` connected again at `now`. Returns whether that
+    /// was within its grace period, so the peer never counted as gone.
+    pub fn reconnected(&mut self, peer: &PeerId, now: Instant) -> bool {
+        self.closed
+            .remove(peer)
+            .is_some_and(|(at, _)| now.duration_since(at) < self.grace)
+    }
+
+    /// The peers that stayed away for their whole grace period by `now`,
+    /// with why their last connection closed. They are forgotten.
+    pub fn due(&mut self, now: Instant) -> Vec<(PeerId, String)> {
+        let grace = self.grace;
+        let mut due = Vec::new();
+        self.closed.retain(|peer, (at, reason)| {
+            if now.duration_since(*at) < grace {
+                return true;
+            }
+            due.push((*peer, std::mem::take(reason)));
+            false
+        });
+        due
+    }
+}
@@ -0,0 +1,90 @@
+use libp2p::{
+    kad::{QueryId, RecordKey},
+    PeerId, StreamProtocol,
+};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+
+/// The Kademlia protocol, kept apart from other applications' DHTs.
+pub const KAD_PROTOCOL: StreamProtocol = StreamProtocol::new("/decentralized-chat/kad/1.0.0");
+
+/// The DHT key under which the members of `room` announce themselves as
+/// providers.
+pub fn room_key(room: &str) -> RecordKey {
+    RecordKey::new(&Sha256::digest(format!("chat-room:{}", room)))
+}
+
+/// What the DHT told us about the rooms we are in.
+#[derive(Debug, Default)]
+pub struct RoomProviders {
+    /// Providers found by the last finished lookup of each room.
+    known: HashMap<String, HashSet<PeerId>>,
+    /// Lookups in progress, with the room and the providers found so far.
+    lookups: HashMap<QueryId, (String, HashSet<PeerId>)>,
+    /// Rooms announced while no DHT peer was known, which reached nobody.
+    unannounced: HashSet<String>,
+}
+
+impl RoomProviders {
+    /// Records that `query` looks up the providers of `room`.
+    pub fn looking_up(&mut self, query: QueryId, room: String) {
+        self.lookups.insert(query, (room, HashSet::new()));
+    }
+
+    /// Records `providers` found by `query`, returning its room and the ones
+    /// not known from earlier lookups. Unknown queries aren't room lookups.
+    pub fn found(
+        &mut self,
+        query: QueryId,
+        providers: impl IntoIterator<Item = PeerId>,
+    ) -> Option<(String, Vec<PeerId>)> {
+        let (room, found) = self.lookups.get_mut(&query)?;
+        let known = self.known.get(room.as_str());
+        let new = providers
+            .into_iter()
+            .filter(|provider| found.insert(*provider))
+            .filter(|provider| !known.is_some_and(|known| known.contains(provider)))
+            .collect();
+        Some((room.clone(), new))
+    }
+
+    /// Records that `query` finished, replacing what we knew about its room
+    /// with what it found.
+    pub fn finished(&mut self, query: QueryId) {
+        if let Some((room, found)) = self.lookups.remove(&query) {
+            self.known.insert(room, found);
+        }
+    }
+
+    /// Providers of `room` other than us, as of the last finished lookup.
+    pub fn count(&self, room: &str) -> usize {
+        self.known.get(room).map_or(0, HashSet::len)
+    }
+
+    /// Records that `room` was announced before any DHT peer was known, so it
+    /// is announced again once there is one.
+    pub fn unannounced(&mut self, room: String) {
+        self.unannounced.insert(room);
+    }
+
+    /// Rooms still to be announced, which are then taken as announced.
+    pub fn take_unannounced(&mut self) -> Vec<String> {
+        self.unannounced.drain().collect()
+    }
+
+    /// Forgets everything about `room`, which we left.
+    pub fn left(&mut self, room: &str) {
+        self.known.remove(room);
+        self.lookups
+            .retain(|_, (lookup_room, _)| lookup_room != room);
+        self.unannounced.remove(room);
+    }
+
+    /// Stops counting `peer`, which left, as a member of any room until a
+    /// lookup finds it again.
+    pub fn departed(&mut self, peer: &PeerId) {
+        for providers in self.known.values_mut() {
+            providers.remove(peer);
+        }
+    }
+}
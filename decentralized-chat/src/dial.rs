@@ -0,0 +1,314 @@
+use either::Either;
+use libp2p::{
+    core::upgrade::NegotiationError,
+    dns::{ResolveError, ResolveErrorKind},
+    multiaddr::Protocol,
+    noise,
+    swarm::{ConnectionError, DialError},
+    tls, Multiaddr, TransportError,
+};
+use std::{error::Error, io};
+
+/// The DNS name that has to be resolved to dial `address`, if any.
+pub fn hostname(address: &Multiaddr) -> Option<String> {
+    address.iter().find_map(|protocol| match protocol {
+        Protocol::Dns(host)
+        | Protocol::Dns4(host)
+        | Protocol::Dns6(host)
+        | Protocol::Dnsaddr(host) => Some(host.to_string()),
+        _ => None,
+    })
+}
+
+/// Checks that an address given to `--external-address` names a concrete,
+/// routable host and a port, so advertising it can actually help peers.
+pub fn check_external_address(address: &Multiaddr) -> Result<(), String> {
+    let mut host = false;
+    let mut port = false;
+    for protocol in address.iter() {
+        match protocol {
+            Protocol::Ip4(ip) if ip.is_unspecified() => {
+                return Err(format!("{} is not a concrete address", ip))
+            }
+            Protocol::Ip6(ip) if ip.is_unspecified() => {
+                return Err(format!("{} is not a concrete address", ip))
+            }
+            Protocol::Ip4(_)
+            | Protocol::Ip6(_)
+            | Protocol::Dns(_)
+            | Protocol::Dns4(_)
+            | Protocol::Dns6(_) => host = true,
+            Protocol::Tcp(0) | Protocol::Udp(0) => {
+                return Err("port 0 is not a concrete port".to_string())
+            }
+            Protocol::Tcp(_) | Protocol::Udp(_) => port = true,
+            _ => {}
+        }
+    }
+
+    match (host, port) {
+        (true, true) => Ok(()),
+        (false, _) => Err("expected an IP address or DNS name".to_string()),
+        (true, false) => Err("expected a TCP or UDP port".to_string()),
+    }
+}
+
+/// Parses a multiaddr typed by the user, hinting at the syntax when it is
+/// written some other way, such as `192.0.2.1:4001`.
+pub fn parse_multiaddr(address: &str) -> Result<Multiaddr, String> {
+    address.parse().map_err(|_| {
+        if address.starts_with('/') {
+            format!("invalid multiaddr: {}", address)
+        } else {
+            format!(
+                "invalid multiaddr: {} (multiaddrs start with /, e.g. /ip4/192.0.2.1/tcp/4001)",
+                address
+            )
+        }
+    })
+}
+
+/// Checks that `address` can be dialed with the transports the node has:
+/// TCP, optionally under `/ws` or `/wss`, to an IP or DNS host, optionally
+/// followed by the peer's `/p2p` id.
+pub fn check_dial_address(address: &Multiaddr) -> Result<(), String> {
+    check_transports(address, false)
+}
+
+/// Checks that `address` can be listened on with the transports the node
+/// has: TCP, optionally under `/ws`, on an IP address.
+pub fn check_listen_address(address: &Multiaddr) -> Result<(), String> {
+    check_transports(address, true)
+}
+
+fn check_transports(address: &Multiaddr, listen: bool) -> Result<(), String> {
+    let mut protocols = address.iter().peekable();
+
+    match protocols.next() {
+        None => return Err("the address is empty".to_string()),
+        Some(Protocol::Ip4(ip)) if !listen && ip.is_unspecified() => {
+            return Err(format!("can't dial {}; use the peer's own IP", ip))
+        }
+        Some(Protocol::Ip6(ip)) if !listen && ip.is_unspecified() => {
+            return Err(format!("can't dial {}; use the peer's own IP", ip))
+        }
+        Some(Protocol::Ip4(_) | Protocol::Ip6(_)) => {}
+        Some(Protocol::Dns(_) | Protocol::Dns4(_) | Protocol::Dns6(_) | Protocol::Dnsaddr(_))
+            if listen =>
+        {
+            return Err("can't listen on a DNS name; use an IP such as /ip4/0.0.0.0".to_string())
+        }
+        // The TXT records behind a dnsaddr name hold the rest of the address.
+        Some(Protocol::Dnsaddr(_)) => {
+            return check_peer_id(protocols.next(), protocols.next(), listen)
+        }
+        Some(Protocol::Dns(_) | Protocol::Dns4(_) | Protocol::Dns6(_)) => {}
+        Some(Protocol::P2p(_)) => {
+            return Err("missing host: put /ip4, /ip6 or /dns4 and a port before /p2p".to_string())
+        }
+        Some(other) => {
+            return Err(format!(
+                "expected an IP address or DNS name first, not /{}",
+                other.tag()
+            ))
+        }
+    }
+
+    match protocols.next() {
+        Some(Protocol::Tcp(0)) if !listen => return Err("can't dial port 0".to_string()),
+        Some(Protocol::Tcp(_)) => {}
+        Some(Protocol::Udp(_)) => {
+            return match protocols.next() {
+                Some(Protocol::Quic | Protocol::QuicV1) => Err(
+                    "QUIC address, but the QUIC transport is not enabled; use a /tcp address"
+                        .to_string(),
+                ),
+                _ => Err("UDP is not supported; use a /tcp address".to_string()),
+            }
+        }
+        None => return Err("missing transport: add /tcp/<port> after the host".to_string()),
+        Some(other) => {
+            return Err(format!(
+                "/{} is not a supported transport; use /tcp/<port>",
+                other.tag()
+            ))
+        }
+    }
+
+    match protocols.peek() {
+        Some(Protocol::Wss(_)) if listen => {
+            return Err(
+                "can't listen on /wss; TLS is only supported for dialing, use /ws".to_string(),
+            )
+        }
+        Some(Protocol::Ws(_) | Protocol::Wss(_)) => {
+            protocols.next();
+        }
+        _ => {}
+    }
+
+    check_peer_id(protocols.next(), protocols.next(), listen)
+}
+
+/// Checks what follows the transport: nothing, or a `/p2p` id to dial.
+fn check_peer_id(
+    next: Option<Protocol<'_>>,
+    after: Option<Protocol<'_>>,
+    listen: bool,
+) -> Result<(), String> {
+    match (next, after) {
+        (None, _) => Ok(()),
+        (Some(Protocol::P2p(_)), _) if listen => {
+            Err("can't listen on an address with a /p2p peer id".to_string())
+        }
+        (Some(Protocol::P2p(_)), None) => Ok(()),
+        (Some(Protocol::P2p(_)), Some(Protocol::P2pCircuit)) | (Some(Protocol::P2pCircuit), _) => {
+            Err(
+                "relayed addresses are not supported; the relay transport is not enabled"
+                    .to_string(),
+            )
+        }
+        (Some(Protocol::P2p(_)), Some(other)) => Err(format!(
+            "unexpected /{} after the /p2p peer id",
+            other.tag()
+        )),
+        (Some(Protocol::Ws(_) | Protocol::Wss(_)), _) => {
+            Err("/ws and /wss go directly after the /tcp port".to_string())
+        }
+        (Some(other), _) => Err(format!("unexpected /{}", other.tag())),
+    }
+}
+
+/// Whether `address` starts with an IP that can be reached from the
+/// internet, as opposed to a loopback, private or link-local one.
+pub fn is_public(address: &Multiaddr) -> bool {
+    match address.iter().next() {
+        Some(Protocol::Ip4(ip)) => {
+            !(ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_unspecified())
+        }
+        Some(Protocol::Ip6(ip)) => {
+            !(ip.is_loopback()
+                || ip.is_unique_local()
+                || ip.is_unicast_link_local()
+                || ip.is_unspecified())
+        }
+        _ => false,
+    }
+}
+
+/// Describes why a dial failed, listing each address that was tried.
+pub fn describe_dial_error(error: &DialError) -> String {
+    match error {
+        DialError::Transport(attempts) => attempts
+            .iter()
+            .map(|(address, error)| {
+                format!("{}: {}", address, describe_transport_error(address, error))
+            })
+            .collect::<Vec<_>>()
+            .join("; "),
+        DialError::WrongPeerId { obtained, .. } => format!(
+            "{} answered instead of the peer dialed; its key changed, or someone else \
+             holds the address",
+            obtained
+        ),
+        error => error.to_string(),
+    }
+}
+
+/// Whether a dial reached the peer but failed the security handshake, or the
+/// peer turned out to be another one, rather than not reaching it at all. A
+/// key that doesn't match is a security problem, not a network one.
+pub fn is_security_failure(error: &DialError) -> bool {
+    match error {
+        DialError::WrongPeerId { .. } => true,
+        DialError::Transport(attempts) => attempts
+            .iter()
+            .any(|(_, error)| is_handshake_failure(error)),
+        _ => false,
+    }
+}
+
+/// Reports why dialing `target` failed, telling a failed handshake apart
+/// from a peer that couldn't be reached.
+pub fn report_dial_error(target: &str, error: &DialError) -> String {
+    if is_security_failure(error) {
+        format!(
+            "Reached {} but the handshake failed: {}",
+            target,
+            describe_dial_error(error)
+        )
+    } else {
+        format!("Failed to dial {}: {}", target, describe_dial_error(error))
+    }
+}
+
+/// Describes why a connection closed, given its `cause`; none means either
+/// side closed it on purpose.
+pub fn describe_close(cause: Option<&ConnectionError>) -> String {
+    match cause {
+        None => "connection closed".to_string(),
+        Some(ConnectionError::KeepAliveTimeout) => "idle for too long".to_string(),
+        Some(ConnectionError::IO(e)) => e.to_string(),
+    }
+}
+
+/// Describes why `address` could not be dialed or listened on. A failed DNS
+/// lookup names the host that could not be resolved.
+pub fn describe_transport_error(address: &Multiaddr, error: &TransportError<io::Error>) -> String {
+    // `TransportError` displays nothing for errors raised by the transport
+    // itself, so look inside.
+    let TransportError::Other(error) = error else {
+        return error.to_string();
+    };
+    if handshake_failed(error) {
+        return format!(
+            "{}; the peer may use another security protocol, muxer or network, both \
+             sides need a --security in common (noise, tls or both), the same --muxer \
+             (yamux or mplex) and the same --network-id",
+            error
+        );
+    }
+
+    let resolve_error = find_in_chain::<ResolveError>(error);
+    match (hostname(address), resolve_error) {
+        (Some(host), Some(resolve_error)) => match resolve_error.kind() {
+            ResolveErrorKind::NoRecordsFound { .. } => {
+                format!("could not resolve {}: no such host", host)
+            }
+            _ => format!("could not resolve {}: {}", host, resolve_error),
+        },
+        _ => error.to_string(),
+    }
+}
+
+/// Whether `error` comes from negotiating or running the security handshake,
+/// or negotiating the muxer, typically because the peer uses noise and we
+/// TLS, or mplex and we yamux.
+pub fn is_handshake_failure(error: &TransportError<io::Error>) -> bool {
+    matches!(error, TransportError::Other(error) if handshake_failed(error))
+}
+
+fn handshake_failed(error: &io::Error) -> bool {
+    find_in_chain::<NegotiationError>(error).is_some()
+        || find_in_chain::<noise::Error>(error).is_some()
+        || find_in_chain::<tls::UpgradeError>(error).is_some()
+        // Offering both, the error is wrapped in an `Either` that skips
+        // over it when asked for its source.
+        || find_in_chain::<Either<noise::Error, tls::UpgradeError>>(error).is_some()
+}
+
+/// The swarm wraps the resolver's and the handshake's errors in a few layers
+/// of its own, some of them `io::Error`s, so search the whole chain.
+fn find_in_chain<T: Error + 'static>(error: &io::Error) -> Option<&T> {
+    let mut current: Option<&(dyn Error + 'static)> = error.get_ref().map(|inner| inner as _);
+    while let Some(error) = current {
+        if let Some(found) = error.downcast_ref::<T>() {
+            return Some(found);
+        }
+        current = match error.downcast_ref::<io::Error>() {
+            Some(io_error) => io_error.get_ref().map(|inner| inner as _),
+            None => error.source(),
+        };
+    }
+    None
+}
@@ -0,0 +1,89 @@
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+/// Name of the file inside the data directory holding the do-not-disturb
+/// mode, so it stays on across restarts.
+pub const DND_FILE: &str = "dnd.json";
+
+/// How long after an auto-reply the same sender gets another one.
+pub const AUTO_REPLY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Sent back when do not disturb is on without an away message.
+pub const DEFAULT_AWAY_MESSAGE: &str = "I'm not available right now";
+
+/// The mode set with `/dnd`: notifications and highlights are off, and
+/// direct messages are answered with the away message.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DoNotDisturb {
+    enabled: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    away_message: Option<String>,
+    /// When each sender was last auto-replied to. Forgotten on restart, so a
+    /// sender may get one reply more.
+    #[serde(skip)]
+    replied: HashMap<PeerId, Instant>,
+}
+
+impl DoNotDisturb {
+    /// Loads the mode from `path`, starting with it off if the file does not
+    /// exist yet.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        match fs::read(path) {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(DoNotDisturb::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, serde_json::to_vec_pretty(self)?)
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn away_message(&self) -> Option<&str> {
+        self.away_message.as_deref()
+    }
+
+    /// Turns the mode on, replacing any previous away message. Every sender
+    /// gets an auto-reply again.
+    pub fn turn_on(&mut self, away_message: Option<String>) {
+        self.enabled = true;
+        self.away_message = away_message;
+        self.replied.clear();
+    }
+
+    pub fn turn_off(&mut self) {
+        self.enabled = false;
+        self.away_message = None;
+        self.replied.clear();
+    }
+
+    /// The text to auto-reply to a direct message from `peer` with, if the
+    /// mode is on and `peer` wasn't replied to within `AUTO_REPLY_INTERVAL`.
+    /// Counts the reply as sent.
+    pub fn auto_reply(&mut self, peer: PeerId, now: Instant) -> Option<String> {
+        if !self.enabled {
+            return None;
+        }
+        self.replied
+            .retain(|_, at| now.duration_since(*at) < AUTO_REPLY_INTERVAL);
+        if self.replied.contains_key(&peer) {
+            return None;
+        }
+        self.replied.insert(peer, now);
+        Some(
+            self.away_message
+                .clone()
+                .unwrap_or_else(|| DEFAULT_AWAY_MESSAGE.to_string()),
+        )
+    }
+}
@@ -0,0 +1,84 @@
+/// Shortcodes understood in outgoing messages, sorted by name so they can be
+/// binary searched.
+const SHORTCODES: &[(&str, &str)] = &[
+    ("+1", "👍"),
+    ("-1", "👎"),
+    ("100", "💯"),
+    ("angry", "😠"),
+    ("blush", "😊"),
+    ("bug", "🐛"),
+    ("clap", "👏"),
+    ("cold_sweat", "😰"),
+    ("confused", "😕"),
+    ("cool", "😎"),
+    ("cry", "😢"),
+    ("eyes", "👀"),
+    ("fire", "🔥"),
+    ("grin", "😁"),
+    ("heart", "❤️"),
+    ("joy", "😂"),
+    ("laughing", "😆"),
+    ("ok_hand", "👌"),
+    ("party", "🥳"),
+    ("pray", "🙏"),
+    ("rocket", "🚀"),
+    ("see_no_evil", "🙈"),
+    ("shrug", "🤷"),
+    ("skull", "💀"),
+    ("smile", "😄"),
+    ("smiley", "😃"),
+    ("sob", "😭"),
+    ("sparkles", "✨"),
+    ("star", "⭐"),
+    ("sunglasses", "😎"),
+    ("sweat_smile", "😅"),
+    ("tada", "🎉"),
+    ("thinking", "🤔"),
+    ("thumbsdown", "👎"),
+    ("thumbsup", "👍"),
+    ("upside_down", "🙃"),
+    ("warning", "⚠️"),
+    ("wave", "👋"),
+    ("wink", "😉"),
+    ("x", "❌"),
+    ("zap", "⚡"),
+];
+
+/// The emoji for a shortcode name, given without the surrounding colons.
+pub fn lookup(name: &str) -> Option<&'static str> {
+    SHORTCODES
+        .binary_search_by(|(code, _)| (*code).cmp(name))
+        .ok()
+        .map(|index| SHORTCODES[index].1)
+}
+
+/// Replaces every known `:name:` shortcode in `text` with its emoji. Unknown
+/// shortcodes and stray colons are left as they are.
+pub fn expand_shortcodes(text: &str) -> String {
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find(':') {
+        expanded.push_str(&rest[..start]);
+        let after_colon = &rest[start + 1..];
+
+        let emoji = after_colon
+            .find(':')
+            .and_then(|end| Some((end, lookup(&after_colon[..end])?)));
+        match emoji {
+            Some((end, emoji)) => {
+                expanded.push_str(emoji);
+                rest = &after_colon[end + 1..];
+            }
+            // The colon may still close or open another shortcode, so only
+            // step past this one.
+            None => {
+                expanded.push(':');
+                rest = after_colon;
+            }
+        }
+    }
+
+    expanded.push_str(rest);
+    expanded
+}
@@ -0,0 +1,147 @@
+use crate::compression::{self, Compressed, ContentEncoding, MAX_DECOMPRESSED_LEN};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::{
+    de::{DeserializeOwned, Error as _},
+    Deserialize, Serialize,
+};
+use serde_json::{json, Value};
+
+/// Version written into the envelopes this node sends.
+pub const ENVELOPE_VERSION: u32 = 1;
+
+/// Version reported for messages sent bare, without an envelope, by peers
+/// that predate it.
+pub const BARE_VERSION: u32 = 0;
+
+/// A message family sent on the wire, tagged by `kind`.
+pub trait Kinds {
+    /// Every `kind` this node can read.
+    const KINDS: &'static [&'static str];
+}
+
+/// How every message is framed on the wire. The payload holds the message's
+/// fields, so a newer peer can add some without older ones failing to read
+/// it, and an older peer can tell a kind it doesn't know from a broken
+/// message.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Envelope {
+    pub version: u32,
+    pub kind: String,
+    /// Set when `payload` is the base64 of its compressed JSON rather than
+    /// the JSON itself.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_encoding: Option<ContentEncoding>,
+    #[serde(default)]
+    pub payload: Value,
+}
+
+/// A message read off the wire.
+#[derive(Debug, PartialEq)]
+pub enum Opened<T> {
+    Known(T),
+    /// A kind added after this node was built.
+    Unknown {
+        version: u32,
+        kind: String,
+    },
+}
+
+/// Wraps `message` in an envelope.
+pub fn seal<T: Serialize + Kinds>(message: &T) -> Value {
+    let Ok(Value::Object(mut fields)) = serde_json::to_value(message) else {
+        unreachable!("wire messages serialize to tagged objects");
+    };
+    let kind = match fields.remove("kind") {
+        Some(Value::String(kind)) => kind,
+        _ => unreachable!("wire messages are tagged with a kind"),
+    };
+    json!(Envelope {
+        version: ENVELOPE_VERSION,
+        kind,
+        content_encoding: None,
+        payload: Value::Object(fields),
+    })
+}
+
+/// Compresses the payload of an envelope from `seal` if its JSON is longer
+/// than `threshold` bytes and compressing saves space. Only for peers that
+/// offer `COMPRESSED_CHAT_PROTOCOL`; `open` undoes it.
+pub fn compress(envelope: &mut Value, threshold: usize) -> Option<Compressed> {
+    let payload = envelope.get_mut("payload")?;
+    let json = serde_json::to_vec(payload).ok()?;
+    if json.len() <= threshold {
+        return None;
+    }
+    let encoded = BASE64.encode(compression::compress(&json).ok()?);
+    if encoded.len() >= json.len() {
+        return None;
+    }
+    let compressed = Compressed {
+        original_bytes: json.len(),
+        compressed_bytes: encoded.len(),
+    };
+    *payload = Value::String(encoded);
+    envelope["content_encoding"] = json!(ContentEncoding::Zstd);
+    Some(compressed)
+}
+
+/// The JSON of a payload sent with `encoding`.
+fn decode_payload(
+    encoding: Option<ContentEncoding>,
+    payload: Value,
+) -> Result<Value, serde_json::Error> {
+    match encoding {
+        None => Ok(payload),
+        Some(ContentEncoding::Zstd) => {
+            let Value::String(encoded) = payload else {
+                return Err(serde_json::Error::custom(
+                    "compressed payload is not a string",
+                ));
+            };
+            let compressed = BASE64.decode(encoded).map_err(serde_json::Error::custom)?;
+            let json = compression::decompress(&compressed, MAX_DECOMPRESSED_LEN)
+                .map_err(serde_json::Error::custom)?;
+            serde_json::from_slice(&json)
+        }
+    }
+}
+
+/// Reads a message, either in an envelope or sent bare by an older peer.
+/// Fields the node doesn't know are ignored, and so are kinds it doesn't
+/// know, which are returned as `Opened::Unknown` instead of an error. A
+/// compressed payload is decompressed, up to `MAX_DECOMPRESSED_LEN`.
+pub fn open<T: DeserializeOwned + Kinds>(value: Value) -> Result<Opened<T>, serde_json::Error> {
+    let (version, kind, payload) = match value {
+        Value::Object(ref fields)
+            if fields.contains_key("version") && fields.contains_key("payload") =>
+        {
+            let envelope: Envelope = serde_json::from_value(value)?;
+            let payload = decode_payload(envelope.content_encoding, envelope.payload)?;
+            (envelope.version, envelope.kind, payload)
+        }
+        bare => match bare.get("kind").and_then(Value::as_str) {
+            Some(kind) => (BARE_VERSION, kind.to_string(), bare),
+            // Let serde describe what is missing.
+            None => return serde_json::from_value(bare).map(Opened::Known),
+        },
+    };
+
+    if !T::KINDS.contains(&kind.as_str()) {
+        return Ok(Opened::Unknown { version, kind });
+    }
+
+    let mut fields = match payload {
+        Value::Object(fields) => fields,
+        Value::Null => Default::default(),
+        other => return serde_json::from_value(other).map(Opened::Known),
+    };
+    fields.insert("kind".to_string(), Value::String(kind));
+    serde_json::from_value(Value::Object(fields)).map(Opened::Known)
+}
+
+/// Reads a message from the bytes of a gossipsub message.
+pub fn open_slice<T: DeserializeOwned + Kinds>(
+    bytes: &[u8],
+) -> Result<Opened<T>, serde_json::Error> {
+    open(serde_json::from_slice(bytes)?)
+}
@@ -0,0 +1,155 @@
+use crate::{
+    message::{ChatMessage, MessageId},
+    peer_id::short_peer_id,
+};
+use libp2p::{Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Something that happened on the node, broadcast to everyone holding a
+/// `ChatHandle` as it happens.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ChatEvent {
+    /// A chat message arrived, in one of our rooms or directly.
+    MessageReceived { message: ChatMessage },
+    /// A message that arrived mentions our nickname or peer id. Sent after
+    /// its `MessageReceived`, unless its room is muted.
+    Mentioned { message: ChatMessage },
+    /// The messages of `room`, or of every room and direct chat if unset,
+    /// were cleared with `/clear`; frontends should drop them from view.
+    HistoryCleared { room: Option<String> },
+    /// We have a first connection to a peer, unless it is reconnecting
+    /// within the grace period of a departure that wasn't reported.
+    PeerOnline { peer: PeerId },
+    /// Our last connection to a peer closed, and it didn't connect again
+    /// within a grace period of a few seconds.
+    PeerOffline { peer: PeerId },
+    /// `peer` joined one of our rooms, or we learned it is in one.
+    MemberJoined { room: String, peer: PeerId },
+    /// `peer` left one of our rooms, or wasn't heard from there for a while.
+    MemberLeft { room: String, peer: PeerId },
+    /// `peer` acknowledged the direct message `id`, either from us or held
+    /// for it by a forwarder.
+    DeliveryConfirmed { peer: PeerId, id: MessageId },
+    /// Every attempt at sending the direct message `id` to `peer` failed.
+    DeliveryFailed {
+        peer: PeerId,
+        id: MessageId,
+        attempts: u32,
+    },
+    /// A peer went over its inbound rate limit and its messages are being
+    /// dropped.
+    PeerThrottled { peer: PeerId },
+    /// A peer we verified with `/verify` presented a different public key.
+    /// Its peer id is derived from the key, so this should never happen;
+    /// treat it as someone impersonating the peer.
+    KeyMismatch {
+        peer: PeerId,
+        expected: String,
+        actual: String,
+    },
+    /// We dialed `address`, which is pinned to `expected` in the config's
+    /// key_pins, and `actual` authenticated instead. The connection was
+    /// closed: someone else holds the address or is intercepting it.
+    KeyPinMismatch {
+        address: Multiaddr,
+        expected: PeerId,
+        actual: PeerId,
+    },
+    /// A peer sent a kind of message this node doesn't know, most likely
+    /// because it runs a newer version. The message was ignored.
+    UnknownMessage {
+        peer: PeerId,
+        version: u32,
+        kind: String,
+    },
+}
+
+impl fmt::Display for ChatEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChatEvent::MessageReceived { message } => {
+                match &message.room {
+                    Some(room) => write!(f, "#{} ", room)?,
+                    None => write!(f, "(direct) ")?,
+                }
+                write!(
+                    f,
+                    "[{}] {}: {}",
+                    message.short_id(),
+                    message.sender(),
+                    message.display_text()
+                )
+            }
+            ChatEvent::Mentioned { message } => {
+                write!(f, "{} mentioned you", message.sender())?;
+                if let Some(room) = &message.room {
+                    write!(f, " in #{}", room)?;
+                }
+                write!(f, ": {}", message.display_text())
+            }
+            ChatEvent::HistoryCleared { room: Some(room) } => {
+                write!(f, "Cleared the messages of #{}", room)
+            }
+            ChatEvent::HistoryCleared { room: None } => write!(f, "Cleared every message"),
+            ChatEvent::PeerOnline { peer } => write!(f, "{} connected", short_peer_id(peer)),
+            ChatEvent::PeerOffline { peer } => write!(f, "{} disconnected", short_peer_id(peer)),
+            ChatEvent::MemberJoined { room, peer } => {
+                write!(f, "{} joined #{}", short_peer_id(peer), room)
+            }
+            ChatEvent::MemberLeft { room, peer } => {
+                write!(f, "{} left #{}", short_peer_id(peer), room)
+            }
+            ChatEvent::DeliveryConfirmed { peer, id } => write!(
+                f,
+                "{} received [{}]",
+                short_peer_id(peer),
+                &id.simple().to_string()[..8]
+            ),
+            ChatEvent::DeliveryFailed { peer, id, attempts } => write!(
+                f,
+                "[{}] could not be delivered to {} in {} attempts",
+                &id.simple().to_string()[..8],
+                short_peer_id(peer),
+                attempts
+            ),
+            ChatEvent::PeerThrottled { peer } => {
+                write!(f, "{} is over the rate limit", short_peer_id(peer))
+            }
+            ChatEvent::KeyMismatch {
+                peer,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "WARNING: verified peer {} presented the key {} instead of {}; \
+                 someone may be impersonating it",
+                short_peer_id(peer),
+                actual,
+                expected
+            ),
+            ChatEvent::KeyPinMismatch {
+                address,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "WARNING: {} answered at {}, which is pinned to {}; disconnected. \
+                 Someone else may hold the address or be intercepting it",
+                actual, address, expected
+            ),
+            ChatEvent::UnknownMessage {
+                peer,
+                version,
+                kind,
+            } => write!(
+                f,
+                "{} sent a {} message (version {}) this node doesn't know",
+                short_peer_id(peer),
+                kind,
+                version
+            ),
+        }
+    }
+}
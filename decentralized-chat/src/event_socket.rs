@@ -0,0 +1,78 @@
+use crate::{event::ChatEvent, handle::NODE_STOPPED};
+use std::{future::Future, time::Duration};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// Events a client may leave unread before it is disconnected.
+pub const CLIENT_BACKLOG: usize = 64;
+
+/// How long a client gets to take a frame before it is taken for gone.
+pub const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Close code for a client that can't keep up: try again later.
+const CLOSE_AGAIN: u16 = 1013;
+
+/// Close code for a node that has stopped: going away.
+const CLOSE_AWAY: u16 = 1001;
+
+/// What a client sent.
+pub enum Received {
+    Text(String),
+    /// Anything but text, such as a ping, which is answered for us.
+    Other,
+    /// The client closed the socket or it failed.
+    Gone,
+}
+
+/// A WebSocket as `stream_events` drives it, whichever library serves it.
+pub trait EventSocket: Send {
+    fn receive(&mut self) -> impl Future<Output = Received> + Send;
+
+    /// Sends a text frame, returning false once the client has gone.
+    fn send_text(&mut self, text: String) -> impl Future<Output = bool> + Send;
+
+    fn close(self, code: u16, reason: &'static str) -> impl Future<Output = ()> + Send;
+}
+
+/// Streams `events` to one client as JSON text frames until it goes away,
+/// answering each text frame it sends with what `reply` makes of it. Every
+/// client reads the broadcast on its own, so a slow one holds up neither the
+/// node nor the other clients; once it falls `CLIENT_BACKLOG` events behind,
+/// or doesn't take a frame within `SEND_TIMEOUT`, it is disconnected instead.
+pub async fn stream_events<S, R, F>(
+    mut events: broadcast::Receiver<ChatEvent>,
+    mut socket: S,
+    mut reply: R,
+) where
+    S: EventSocket,
+    R: FnMut(String) -> F + Send,
+    F: Future<Output = String> + Send,
+{
+    loop {
+        let frame = tokio::select! {
+            event = events.recv() => match event {
+                Ok(_) | Err(RecvError::Lagged(_)) if events.len() >= CLIENT_BACKLOG => {
+                    close(socket, CLOSE_AGAIN, "too many unread events").await;
+                    return;
+                }
+                Ok(event) => serde_json::to_string(&event).expect("events serialize"),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => {
+                    close(socket, CLOSE_AWAY, NODE_STOPPED).await;
+                    return;
+                }
+            },
+            received = socket.receive() => match received {
+                Received::Text(text) => reply(text).await,
+                Received::Other => continue,
+                Received::Gone => return,
+            },
+        };
+        if tokio::time::timeout(SEND_TIMEOUT, socket.send_text(frame)).await != Ok(true) {
+            return;
+        }
+    }
+}
+
+async fn close(socket: impl EventSocket, code: u16, reason: &'static str) {
+    let _ = tokio::time::timeout(SEND_TIMEOUT, socket.close(code, reason)).await;
+}
@@ -0,0 +1,33 @@
+use crate::message::{ChatMessage, MessageId};
+use libp2p::futures::StreamExt;
+use std::time::Duration;
+use tokio_util::time::DelayQueue;
+
+/// Longest a removal is scheduled ahead, within what `DelayQueue` can
+/// hold. A message due later stays until the node restarts, when it is
+/// scheduled again from what is left of its life.
+const MAX_SCHEDULED: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// When each stored message that expires is to be removed, for the event
+/// loop to wait on. Must be used inside a Tokio runtime.
+#[derive(Debug, Default)]
+pub struct Expiries {
+    queue: DelayQueue<MessageId>,
+}
+
+impl Expiries {
+    /// Schedules the removal of `message`, taken in at `now`, if it expires.
+    pub fn schedule(&mut self, message: &ChatMessage, now: u64) {
+        if let Some(time_to_live) = message.time_to_live(now) {
+            if time_to_live <= MAX_SCHEDULED {
+                self.queue.insert(message.id, time_to_live);
+            }
+        }
+    }
+
+    /// Waits for the next message due to be removed. Resolves to None at
+    /// once while none is scheduled.
+    pub async fn next(&mut self) -> Option<MessageId> {
+        self.queue.next().await.map(|expired| expired.into_inner())
+    }
+}
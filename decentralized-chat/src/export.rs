@@ -0,0 +1,166 @@
+use crate::{
+    address_book::AddressBook,
+    command::KnownPeer,
+    history,
+    message::{unix_now, ChatMessage, MessageId},
+};
+use serde::Deserialize;
+use std::{
+    collections::HashSet,
+    error::Error,
+    fs,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+/// Schema version written to exports. Imports refuse any other version.
+pub const EXPORT_VERSION: u32 = 1;
+
+/// Which messages end up in an export.
+#[derive(Debug, Default, Clone)]
+pub struct ExportFilter {
+    pub room: Option<String>,
+    /// Only messages sent at or after this unix timestamp.
+    pub since: Option<u64>,
+}
+
+impl ExportFilter {
+    fn matches(&self, message: &ChatMessage) -> bool {
+        self.room
+            .as_ref()
+            .is_none_or(|room| message.room.as_ref() == Some(room))
+            && self.since.is_none_or(|since| message.timestamp >= since)
+    }
+}
+
+/// Writes the unexpired messages in the history at `history_path` that pass
+/// `filter`, together with the address book, as one JSON document. Messages are copied
+/// one at a time, so the history is never held in memory. A message changed
+/// since the history was last compacted is exported once per copy; import keeps
+/// the last. Returns how many messages were exported.
+pub fn export(
+    history_path: &Path,
+    address_book: &AddressBook,
+    filter: &ExportFilter,
+    out: impl Write,
+) -> io::Result<usize> {
+    let peers: Vec<KnownPeer> = address_book
+        .most_recent()
+        .into_iter()
+        .map(|(peer_id, entry)| KnownPeer {
+            peer_id: *peer_id,
+            entry: entry.clone(),
+        })
+        .collect();
+
+    let mut out = BufWriter::new(out);
+    write!(
+        out,
+        "{{\"version\":{},\"exported_at\":{},\"peers\":",
+        EXPORT_VERSION,
+        unix_now()
+    )?;
+    serde_json::to_writer(&mut out, &peers)?;
+    out.write_all(b",\"messages\":[")?;
+
+    let now = unix_now();
+    let mut exported = 0;
+    for message in history::read(history_path)? {
+        let message = message?;
+        if message.is_expired(now) || !filter.matches(&message) {
+            continue;
+        }
+
+        if exported > 0 {
+            out.write_all(b",")?;
+        }
+        out.write_all(b"\n")?;
+        serde_json::to_writer(&mut out, &message)?;
+        exported += 1;
+    }
+
+    out.write_all(b"\n]}\n")?;
+    out.flush()?;
+    Ok(exported)
+}
+
+#[derive(Debug, Deserialize)]
+struct Header {
+    version: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportDocument {
+    peers: Vec<KnownPeer>,
+    messages: Vec<ChatMessage>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImportSummary {
+    pub imported: usize,
+    /// Messages skipped because the history already had their id.
+    pub duplicates: usize,
+    /// Messages skipped because they expired since they were exported.
+    pub expired: usize,
+    pub peers: usize,
+}
+
+/// Merges an export into the history at `history_path` and into
+/// `address_book`. Messages whose id is already in the history, or that have
+/// expired, are skipped; if the export holds several copies of an id, the
+/// last one wins.
+pub fn import(
+    input: &Path,
+    history_path: &Path,
+    address_book: &mut AddressBook,
+) -> Result<ImportSummary, Box<dyn Error>> {
+    let contents = fs::read(input)?;
+
+    // Check the version on its own first, so a newer export fails with a
+    // clear message rather than whatever field happened to change.
+    let Header { version } = serde_json::from_slice(&contents)
+        .map_err(|e| format!("{} is not a chat export: {}", input.display(), e))?;
+    if version != EXPORT_VERSION {
+        return Err(format!(
+            "{} uses export schema version {}, but only version {} is supported",
+            input.display(),
+            version,
+            EXPORT_VERSION
+        )
+        .into());
+    }
+
+    let document: ExportDocument = serde_json::from_slice(&contents)
+        .map_err(|e| format!("{} is not a valid chat export: {}", input.display(), e))?;
+
+    let existing = history::read(history_path)?
+        .map(|message| message.map(|message| message.id))
+        .collect::<io::Result<HashSet<MessageId>>>()?;
+    let now = unix_now();
+    let (expired, unexpired): (Vec<&ChatMessage>, Vec<&ChatMessage>) = document
+        .messages
+        .iter()
+        .partition(|message| message.is_expired(now));
+    let new_messages: Vec<&ChatMessage> = unexpired
+        .iter()
+        .copied()
+        .filter(|message| !existing.contains(&message.id))
+        .collect();
+    history::append(history_path, new_messages.iter().copied())?;
+    let imported = new_messages
+        .iter()
+        .map(|message| message.id)
+        .collect::<HashSet<_>>()
+        .len();
+
+    for KnownPeer { peer_id, entry } in &document.peers {
+        address_book.merge(*peer_id, entry);
+    }
+
+    Ok(ImportSummary {
+        imported,
+        duplicates: unexpired.len() - new_messages.len(),
+        expired: expired.len(),
+        peers: document.peers.len(),
+    })
+}
@@ -0,0 +1,108 @@
+use crate::{config::FilterAction, message::ChatMessage};
+use std::collections::HashSet;
+
+/// Words hidden from incoming messages, from the `filter` section of the
+/// config. Words match whole and ignoring case, so blocking `ass` leaves
+/// `class` and `Assess` alone but catches `ASS!`.
+#[derive(Debug, Default, Clone)]
+pub struct ContentFilter {
+    /// Lowercased.
+    words: HashSet<String>,
+    action: FilterAction,
+}
+
+impl ContentFilter {
+    pub fn new(words: impl IntoIterator<Item = impl AsRef<str>>, action: FilterAction) -> Self {
+        ContentFilter {
+            words: words
+                .into_iter()
+                .map(|word| word.as_ref().to_lowercase())
+                .collect(),
+            action,
+        }
+    }
+
+    /// Whether `text` contains a blocked word.
+    pub fn matches(&self, text: &str) -> bool {
+        !self.blocked(text).is_empty()
+    }
+
+    /// Whether an incoming message is dropped unseen rather than shown
+    /// masked.
+    pub fn drops(&self, message: &ChatMessage) -> bool {
+        self.action == FilterAction::Drop && self.matches(message.text())
+    }
+
+    /// `text` with every blocked word replaced by as many `*` as it has
+    /// characters.
+    pub fn filter_content(&self, text: &str) -> String {
+        let mut filtered = String::with_capacity(text.len());
+        let mut end = 0;
+        for (start, word) in self.blocked(text) {
+            filtered.push_str(&text[end..start]);
+            filtered.extend(std::iter::repeat_n('*', word.chars().count()));
+            end = start + word.len();
+        }
+        filtered.push_str(&text[end..]);
+        filtered
+    }
+
+    /// Masks the text of a copy of `message` kept for display, edits
+    /// included.
+    pub fn filter_message(&self, message: &mut ChatMessage) {
+        message.message = self.filter_content(&message.message);
+        for edit in &mut message.edits {
+            *edit = self.filter_content(edit);
+        }
+    }
+
+    /// The blocked words of `text`, with their byte offsets.
+    fn blocked<'a>(&self, text: &'a str) -> Vec<(usize, &'a str)> {
+        if self.words.is_empty() {
+            return Vec::new();
+        }
+        words(text)
+            .into_iter()
+            .filter(|(_, word)| self.words.contains(&word.to_lowercase()))
+            .collect()
+    }
+}
+
+/// Checks that `word` can be blocked: a single word, since the filter only
+/// ever compares whole words.
+pub fn check_word(word: &str) -> Result<(), String> {
+    if word.is_empty() {
+        return Err("blocked words must not be empty".to_string());
+    }
+    if let Some(c) = word.chars().find(|c| !is_word_char(*c)) {
+        return Err(format!(
+            "invalid character {:?} in blocked word {:?} (use letters, digits and _)",
+            c, word
+        ));
+    }
+    Ok(())
+}
+
+/// The runs of word characters in `text`, with their byte offsets.
+fn words(text: &str) -> Vec<(usize, &str)> {
+    let mut words = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices() {
+        match (is_word_char(c), start) {
+            (true, None) => start = Some(i),
+            (false, Some(first)) => {
+                words.push((first, &text[first..i]));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(first) = start {
+        words.push((first, &text[first..]));
+    }
+    words
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
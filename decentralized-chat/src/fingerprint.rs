@@ -0,0 +1,56 @@
+use libp2p::{identity::PublicKey, PeerId};
+use sha2::{Digest, Sha256};
+
+/// Multihash code of the identity hash, used by peer ids that embed their
+/// public key.
+const IDENTITY_MULTIHASH: u64 = 0x00;
+
+/// Leading bytes of the digest shown: 160 bits, which read out as 32
+/// characters.
+const FINGERPRINT_BYTES: usize = 20;
+
+const GROUP_LEN: usize = 4;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// A short rendering of `key` for two people to compare out of band, e.g.
+/// `MZXW 6YTB OI3D ...`: the base32 of its SHA-256, in groups of four.
+pub fn fingerprint(key: &PublicKey) -> String {
+    let digest = Sha256::digest(key.encode_protobuf());
+    let encoded = base32(&digest[..FINGERPRINT_BYTES]);
+    encoded
+        .as_bytes()
+        .chunks(GROUP_LEN)
+        .map(|group| String::from_utf8_lossy(group).into_owned())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The public key `peer` is derived from, for peer ids that embed it, which
+/// is every Ed25519 and Secp256k1 one. RSA and ECDSA keys are too long to
+/// embed and have to be learned from identify or a signed message.
+pub fn embedded_key(peer: &PeerId) -> Option<PublicKey> {
+    let multihash = peer.as_ref();
+    if multihash.code() != IDENTITY_MULTIHASH {
+        return None;
+    }
+    PublicKey::try_decode_protobuf(multihash.digest()).ok()
+}
+
+/// RFC 4648 base32, without padding.
+fn base32(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for &byte in bytes {
+        buffer = (buffer << 8) | u32::from(byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    encoded
+}
@@ -0,0 +1,94 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// How much a message matters when the node is flooded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// Direct requests, which were meant for us alone and are always handled.
+    Direct,
+    /// Gossip, which is shed while the node is flooded.
+    Broadcast,
+}
+
+/// What to do with a message that just arrived.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    Accept,
+    /// Drop the message to shed load.
+    Shed,
+}
+
+/// Watches the rate of messages arriving from all peers together. When it
+/// goes over `shed_above` per second, broadcasts are dropped until it falls
+/// below `recover_below` again, however many peers the storm comes from.
+#[derive(Debug)]
+pub struct FloodGuard {
+    shed_above: f64,
+    recover_below: f64,
+    window: Duration,
+    /// Messages counted in each second of the window, oldest first, by the
+    /// second they started in.
+    seconds: VecDeque<(Instant, u64)>,
+    shedding: bool,
+}
+
+impl FloodGuard {
+    pub fn new(shed_above: f64, recover_below: f64, window: Duration) -> Self {
+        FloodGuard {
+            shed_above,
+            recover_below,
+            window,
+            seconds: VecDeque::new(),
+            shedding: false,
+        }
+    }
+
+    /// Counts a message of `priority` arriving at `now`.
+    pub fn admit(&mut self, priority: Priority, now: Instant) -> Admission {
+        match self.seconds.back_mut() {
+            Some((start, count)) if now.duration_since(*start) < Duration::from_secs(1) => {
+                *count += 1;
+            }
+            _ => self.seconds.push_back((now, 1)),
+        }
+
+        let rate = self.rate(now);
+        if !self.shedding && rate > self.shed_above {
+            self.shedding = true;
+        }
+        self.recover(now);
+
+        match priority {
+            Priority::Broadcast if self.shedding => Admission::Shed,
+            _ => Admission::Accept,
+        }
+    }
+
+    /// Messages per second over the window ending at `now`.
+    pub fn rate(&mut self, now: Instant) -> f64 {
+        while let Some((start, _)) = self.seconds.front() {
+            if now.duration_since(*start) < self.window {
+                break;
+            }
+            self.seconds.pop_front();
+        }
+        let total: u64 = self.seconds.iter().map(|(_, count)| count).sum();
+        total as f64 / self.window.as_secs_f64()
+    }
+
+    /// Stops shedding once the rate has fallen below `recover_below`, also
+    /// when no messages arrive at all. Returns whether it did just now.
+    pub fn recover(&mut self, now: Instant) -> bool {
+        if self.shedding && self.rate(now) < self.recover_below {
+            self.shedding = false;
+            return true;
+        }
+        false
+    }
+
+    pub fn shedding(&self) -> bool {
+        self.shedding
+    }
+}
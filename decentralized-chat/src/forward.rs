@@ -0,0 +1,151 @@
+use crate::message::ChatMessage;
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt};
+
+/// A direct message held for a peer that was offline when it was sent.
+#[derive(Debug, Clone)]
+pub struct StoredMessage {
+    pub message: ChatMessage,
+    /// Unix timestamp after which the message is dropped undelivered.
+    pub expires_at: u64,
+}
+
+/// Why a forwarder refused to hold a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StoreError {
+    /// The node wasn't started with `--forward`.
+    NotForwarding,
+    /// The target already has `limit` messages waiting.
+    Full {
+        limit: usize,
+    },
+    /// `limit` messages by the same author are already held.
+    SenderFull {
+        limit: usize,
+    },
+    /// The forwarder already holds as many messages as it takes, `limit`.
+    StoreFull {
+        limit: usize,
+    },
+    Expired,
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::NotForwarding => write!(f, "not a forwarder"),
+            StoreError::Full { limit } => {
+                write!(f, "{} messages are already waiting for the peer", limit)
+            }
+            StoreError::SenderFull { limit } => {
+                write!(f, "{} of your messages are already held", limit)
+            }
+            StoreError::StoreFull { limit } => {
+                write!(f, "{} messages are already held for others", limit)
+            }
+            StoreError::Expired => write!(f, "the message has expired"),
+        }
+    }
+}
+
+/// Direct messages other peers asked us to deliver to offline peers once
+/// they connect.
+#[derive(Debug)]
+pub struct ForwardStore {
+    max_per_target: usize,
+    max_per_sender: usize,
+    /// Messages held for all targets together.
+    max_messages: usize,
+    /// Longest a message is held, however long its sender asked for.
+    max_ttl: u64,
+    messages: HashMap<PeerId, Vec<StoredMessage>>,
+}
+
+impl ForwardStore {
+    pub fn new(
+        max_per_target: usize,
+        max_per_sender: usize,
+        max_messages: usize,
+        max_ttl_secs: u64,
+    ) -> Self {
+        ForwardStore {
+            max_per_target,
+            max_per_sender,
+            max_messages,
+            max_ttl: max_ttl_secs,
+            messages: HashMap::new(),
+        }
+    }
+
+    /// Holds `message` for `target` until `expires_at`. A message already
+    /// held is accepted again without being stored twice.
+    pub fn store(
+        &mut self,
+        target: PeerId,
+        message: ChatMessage,
+        expires_at: u64,
+        now: u64,
+    ) -> Result<(), StoreError> {
+        if expires_at <= now {
+            return Err(StoreError::Expired);
+        }
+
+        let waiting = self.messages.get(&target).map_or(&[][..], Vec::as_slice);
+        if waiting.iter().any(|stored| stored.message.id == message.id) {
+            return Ok(());
+        }
+        if waiting.len() >= self.max_per_target {
+            return Err(StoreError::Full {
+                limit: self.max_per_target,
+            });
+        }
+        // The author signed the message, so one peer can't fill the store
+        // under many names, nor crowd out everyone else's.
+        let held = self.messages.values().flatten();
+        let by_sender = held
+            .clone()
+            .filter(|stored| stored.message.peer_id == message.peer_id)
+            .count();
+        if by_sender >= self.max_per_sender {
+            return Err(StoreError::SenderFull {
+                limit: self.max_per_sender,
+            });
+        }
+        if held.count() >= self.max_messages {
+            return Err(StoreError::StoreFull {
+                limit: self.max_messages,
+            });
+        }
+        self.messages
+            .entry(target)
+            .or_default()
+            .push(StoredMessage {
+                message,
+                expires_at: expires_at.min(now.saturating_add(self.max_ttl)),
+            });
+        Ok(())
+    }
+
+    /// Removes and returns the unexpired messages held for `target`, oldest
+    /// first.
+    pub fn take(&mut self, target: &PeerId, now: u64) -> Vec<StoredMessage> {
+        let mut waiting = self.messages.remove(target).unwrap_or_default();
+        waiting.retain(|stored| stored.expires_at > now);
+        waiting
+    }
+
+    /// Drops messages that expired before they could be delivered.
+    pub fn expire(&mut self, now: u64) {
+        self.messages.retain(|_, waiting| {
+            waiting.retain(|stored| stored.expires_at > now);
+            !waiting.is_empty()
+        });
+    }
+
+    /// Messages held for `target`.
+    pub fn waiting(&self, target: &PeerId) -> usize {
+        self.messages.get(target).map_or(0, Vec::len)
+    }
+}
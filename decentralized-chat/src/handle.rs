@@ -0,0 +1,93 @@
+use crate::{
+    command::{Command, Reply},
+    control::ControlRequest,
+    event::ChatEvent,
+    history::HistoryFilter,
+    message::ChatMessage,
+    stats::Stats,
+};
+use libp2p::futures::{stream, Stream};
+use tokio::sync::{broadcast, mpsc, oneshot};
+
+/// A cloneable handle for running commands on a node from other tasks. Each
+/// command is executed by the node's event loop, which answers through the
+/// handle once it is done.
+#[derive(Debug, Clone)]
+pub struct ChatHandle {
+    requests: mpsc::Sender<ControlRequest>,
+    events: broadcast::Sender<ChatEvent>,
+}
+
+impl ChatHandle {
+    /// Wraps the sending side of the channel the event loop reads commands
+    /// from, and of the channel it broadcasts events on.
+    pub fn new(
+        requests: mpsc::Sender<ControlRequest>,
+        events: broadcast::Sender<ChatEvent>,
+    ) -> Self {
+        ChatHandle { requests, events }
+    }
+
+    /// Receives every event broadcast from now on. A receiver that falls too
+    /// far behind skips the oldest events.
+    pub fn events(&self) -> broadcast::Receiver<ChatEvent> {
+        self.events.subscribe()
+    }
+
+    pub async fn execute(&self, command: Command) -> Result<Reply, String> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.requests
+            .send((command, reply_tx))
+            .await
+            .map_err(|_| NODE_STOPPED.to_string())?;
+        reply_rx.await.map_err(|_| NODE_STOPPED.to_string())?
+    }
+
+    /// A snapshot of the node's counters, as shown by `/stats`.
+    pub async fn stats(&self) -> Result<Stats, String> {
+        match self.execute(Command::Stats { json: false }).await? {
+            Reply::Stats(stats) => Ok(*stats),
+            reply => Err(format!("unexpected reply to stats: {}", reply)),
+        }
+    }
+
+    /// The messages matching `filter` in pages of `page_size`, as `/history`
+    /// shows them: the newest page first, each oldest first. Pages are only
+    /// asked for as the stream is read, and it ends with the oldest message
+    /// or the first error.
+    pub fn history(
+        &self,
+        filter: HistoryFilter,
+        page_size: usize,
+    ) -> impl Stream<Item = Result<Vec<ChatMessage>, String>> {
+        stream::unfold(Some((self.clone(), 0)), move |next| {
+            let filter = filter.clone();
+            async move {
+                let (handle, page) = next?;
+                let command = Command::History {
+                    room: Some(filter.room),
+                    peer: filter.peer,
+                    since: filter.since,
+                    limit: page_size,
+                    page,
+                };
+                match handle.execute(command).await {
+                    Ok(Reply::History { messages, .. }) if messages.is_empty() => None,
+                    Ok(Reply::History {
+                        messages, first, ..
+                    }) => {
+                        let next = (first > 1).then_some((handle, page + 1));
+                        Some((Ok(messages), next))
+                    }
+                    Ok(reply) => {
+                        Some((Err(format!("unexpected reply to history: {}", reply)), None))
+                    }
+                    Err(e) => Some((Err(e), None)),
+                }
+            }
+        })
+    }
+}
+
+/// The error every command gets once the event loop has stopped.
+pub const NODE_STOPPED: &str = "the node has shut down";
@@ -0,0 +1,546 @@
+use crate::{
+    address_book::AddressBook,
+    ban::Violation,
+    behaviour::CustomBehaviourEvent,
+    envelope::{self, Opened},
+    event::ChatEvent,
+    flood::Priority,
+    invite::decrypt_for_room,
+    members::RoomMembers,
+    message::{unix_now, ChatMessage, DirectRequest, GossipMessage, MessageId},
+    nickname::Nicknames,
+    peer_id::short_peer_id,
+    replay::ReplayGuard,
+    stats::Counters,
+    store::{Change, ChangeOutcome, MessageStore},
+};
+use libp2p::{
+    gossipsub::{self, MessageAcceptance},
+    mdns, Multiaddr, PeerId,
+};
+use std::{io, time::Instant};
+use tokio::sync::broadcast;
+
+/// Something a handler has the swarm do. Handlers don't touch the swarm
+/// themselves, so that a test can run them and look at what they asked for.
+#[derive(Debug)]
+pub enum Action {
+    /// Remember `address` as one `peer` can be dialed at.
+    AddAddress { peer: PeerId, address: Multiaddr },
+    /// Have gossipsub send `peer` every message, whatever the mesh.
+    AddExplicitPeer { peer: PeerId },
+    /// Send `request` to `peer` over the direct protocol.
+    SendRequest {
+        peer: PeerId,
+        request: Box<DirectRequest>,
+    },
+    /// Count `violation` against `peer`, who is banned once it has enough.
+    ReportViolation { peer: PeerId, violation: Violation },
+    /// Forget the profile `peer` shared and ask it for the new one.
+    RefreshProfile { peer: PeerId },
+    /// Tell gossipsub whether to relay the message `message_id`, which
+    /// `propagation_source` sent us.
+    ReportValidation {
+        message_id: gossipsub::MessageId,
+        propagation_source: PeerId,
+        acceptance: MessageAcceptance,
+    },
+}
+
+/// The node as the handlers below see it: the state they read and change,
+/// and a way to act on the swarm. The binary implements it over its state
+/// and swarm; `testing::TestHarness` implements it without a network.
+pub trait Node {
+    fn local_peer_id(&self) -> PeerId;
+
+    /// How many peers we are connected to.
+    fn connected_peers(&self) -> usize;
+
+    /// Peers found over mDNS are made explicit while fewer than this many
+    /// are connected, and fewer are explicit.
+    fn explicit_below(&self) -> usize;
+
+    /// How many peers gossipsub sends every message to, pinned ones among
+    /// them.
+    fn explicit_peers(&self) -> usize;
+
+    /// Whether gossipsub holds messages back until we report them valid,
+    /// as it does with strict validation.
+    fn validates_gossip(&self) -> bool;
+
+    /// Carries out `action`.
+    fn act(&mut self, action: Action);
+
+    /// Whether a message of `priority` that `peer` sent or relayed is
+    /// handled, given the peer's rate limit and the load of the node.
+    fn admit(&mut self, peer: PeerId, priority: Priority) -> bool;
+
+    fn messages(&mut self) -> &mut MessageStore;
+
+    /// The key of `room` if it is private, which its gossip is encrypted
+    /// with.
+    fn room_key(&self, room: &str) -> Option<[u8; 32]>;
+
+    fn nicknames(&mut self) -> &mut Nicknames;
+
+    fn address_book(&mut self) -> &mut AddressBook;
+
+    fn save_address_book(&mut self);
+
+    fn replays(&mut self) -> &mut ReplayGuard;
+
+    fn members(&mut self) -> &mut RoomMembers;
+
+    fn counters(&mut self) -> &mut Counters;
+
+    fn events(&self) -> &broadcast::Sender<ChatEvent>;
+
+    /// A new direct message from us to `peer`, signed.
+    fn direct_message(&mut self, peer: PeerId, text: String) -> Result<ChatMessage, String>;
+
+    /// Whether `text` mentions our nickname or peer id.
+    fn mentions_us(&self, text: &str) -> bool;
+
+    /// Stores a message and appends it to the history. Returns false if the
+    /// message was already known.
+    fn store_message(&mut self, chat_message: ChatMessage) -> bool;
+
+    /// Applies an edit or delete to a message that was evicted from memory.
+    fn apply_to_history(
+        &mut self,
+        author: PeerId,
+        target_id: MessageId,
+        change: Change,
+    ) -> io::Result<ChangeOutcome>;
+
+    /// Appends the current state of a stored message to the history.
+    fn persist(&mut self, id: &MessageId);
+
+    /// Shows a message from someone else as `line`, as it arrives.
+    fn print_incoming(&mut self, line: &str, chat_message: &ChatMessage);
+
+    /// Shows stored message `id` again after it changed, unless its room is
+    /// muted or it is hidden.
+    fn print_changed(&mut self, id: &MessageId);
+}
+
+/// Hands the behaviour events there are handlers for to them. Returns the
+/// others.
+pub fn handle(node: &mut impl Node, event: CustomBehaviourEvent) -> Option<CustomBehaviourEvent> {
+    match event {
+        CustomBehaviourEvent::Mdns(mdns::Event::Discovered(peers)) => {
+            peers_discovered(node, peers);
+            None
+        }
+        CustomBehaviourEvent::Gossipsub(gossipsub::Event::Message {
+            propagation_source,
+            message_id,
+            message,
+        }) => {
+            gossip_message(node, propagation_source, message_id, message);
+            None
+        }
+        CustomBehaviourEvent::Gossipsub(gossipsub::Event::Subscribed { peer_id, topic }) => {
+            peer_subscribed(node, peer_id, topic);
+            None
+        }
+        CustomBehaviourEvent::Gossipsub(gossipsub::Event::Unsubscribed { peer_id, topic }) => {
+            peer_unsubscribed(node, peer_id, topic);
+            None
+        }
+        event => Some(event),
+    }
+}
+
+/// Takes in peers found over mDNS: remembers their addresses, makes them
+/// explicit while we have few peers, and greets them. A peer found at
+/// several addresses is greeted once.
+pub fn peers_discovered(node: &mut impl Node, peers: Vec<(PeerId, Multiaddr)>) {
+    let mut discovered = Vec::new();
+    let mut changed = false;
+    for (peer, address) in peers {
+        node.act(Action::AddAddress {
+            peer,
+            address: address.clone(),
+        });
+        changed |= node
+            .address_book()
+            .record(peer, address.clone(), unix_now());
+        println!(
+            "Address {} added to the peer {}",
+            address,
+            node.local_peer_id()
+        );
+        if !discovered.contains(&peer) {
+            discovered.push(peer);
+        }
+    }
+
+    for peer in discovered {
+        println!("Peer {} discovered", short_peer_id(&peer));
+        if node.connected_peers().max(node.explicit_peers()) < node.explicit_below() {
+            node.act(Action::AddExplicitPeer { peer });
+        }
+
+        let text = format!("Hello I am {}", node.local_peer_id());
+        let chat_message = match node.direct_message(peer, text) {
+            Ok(chat_message) => chat_message,
+            Err(e) => {
+                println!("{}", e);
+                continue;
+            }
+        };
+        node.act(Action::SendRequest {
+            peer,
+            request: Box::new(DirectRequest::Greeting(chat_message)),
+        });
+    }
+
+    if changed {
+        node.save_address_book();
+    }
+}
+
+/// Takes in a message published in one of our rooms: a chat message, a
+/// nickname or profile announcement, or a change to an earlier message.
+/// When gossipsub waits for us to validate messages, tells it whether to
+/// relay this one.
+pub fn gossip_message(
+    node: &mut impl Node,
+    propagation_source: PeerId,
+    message_id: gossipsub::MessageId,
+    message: gossipsub::Message,
+) {
+    let acceptance = take_in_gossip(node, propagation_source, message);
+    if node.validates_gossip() {
+        node.act(Action::ReportValidation {
+            message_id,
+            propagation_source,
+            acceptance,
+        });
+    }
+}
+
+/// Does the work of `gossip_message`. Returns whether the message should be
+/// relayed: messages that are invalid or forged are rejected, which counts
+/// against the peer that relayed them; those we merely don't want, such as
+/// replays, are ignored.
+fn take_in_gossip(
+    node: &mut impl Node,
+    propagation_source: PeerId,
+    message: gossipsub::Message,
+) -> MessageAcceptance {
+    // Gossipsub signs every message, so `source` is the authenticated
+    // author; blame them rather than whoever relayed it.
+    let sender = message.source.unwrap_or(propagation_source);
+    if !node.admit(sender, Priority::Broadcast) {
+        return MessageAcceptance::Ignore;
+    }
+
+    // Gossip in a private room is encrypted with its key, so what doesn't
+    // decrypt was sent by someone who wasn't let in.
+    let data = match node.room_key(message.topic.as_str()) {
+        Some(key) => match decrypt_for_room(&key, &message.data) {
+            Some(data) => data,
+            None => return MessageAcceptance::Ignore,
+        },
+        None => message.data,
+    };
+
+    let gossip_message: GossipMessage = match envelope::open_slice(&data) {
+        Ok(Opened::Known(gossip_message)) => gossip_message,
+        // Newer peers may well understand it.
+        Ok(Opened::Unknown { version, kind }) => {
+            report_unknown(node.events(), sender, version, kind);
+            return MessageAcceptance::Accept;
+        }
+        Err(e) => {
+            println!(
+                "Invalid message from {}: {}",
+                short_peer_id(&propagation_source),
+                e
+            );
+            node.act(Action::ReportViolation {
+                peer: sender,
+                violation: Violation::MalformedGossip,
+            });
+            return MessageAcceptance::Reject;
+        }
+    };
+
+    let (target_id, change) = match gossip_message {
+        GossipMessage::Chat(mut chat_message) => {
+            if chat_message.is_expired(unix_now()) {
+                return MessageAcceptance::Ignore;
+            }
+            // Only the author may publish a message, and only in the room
+            // they signed it for. Anonymous gossip, allowed in permissive
+            // mode, has nothing but the signature to go on.
+            let room = message.topic.to_string();
+            if message
+                .source
+                .is_some_and(|source| source != chat_message.peer_id)
+                || chat_message.room.as_deref() != Some(room.as_str())
+            {
+                report_misattributed(node, sender, &chat_message);
+                return MessageAcceptance::Reject;
+            }
+            if !verify_signature(node, sender, &chat_message) {
+                return MessageAcceptance::Reject;
+            }
+            if !check_replay(node, &chat_message) {
+                return MessageAcceptance::Ignore;
+            }
+            if let Some(name) = &chat_message.nickname {
+                let _ = node.nicknames().record(sender, name);
+            }
+            if node.members().heard(&room, sender, Instant::now()) {
+                member_joined(node.events(), room.clone(), sender);
+            }
+            // What our filter drops others may still want to see.
+            if node.messages().filter().drops(&chat_message) {
+                return MessageAcceptance::Accept;
+            }
+            chat_message.mentions_me = node.mentions_us(&chat_message.message);
+
+            let id = chat_message.id;
+            if !node.store_message(*chat_message) {
+                return MessageAcceptance::Accept;
+            }
+            node.counters().message_received(Some(&room));
+            if let Some(chat_message) = node.messages().get(&id).cloned() {
+                let line = node.messages().format(&chat_message);
+                let _ = node.events().send(ChatEvent::MessageReceived {
+                    message: chat_message.clone(),
+                });
+                node.print_incoming(&line, &chat_message);
+            }
+            return MessageAcceptance::Accept;
+        }
+        GossipMessage::Nickname { name } => {
+            let Some(author) = message.source else {
+                return MessageAcceptance::Ignore;
+            };
+            let previous = node
+                .nicknames()
+                .get(&author)
+                .map_or_else(|| short_peer_id(&author), str::to_string);
+            match node.nicknames().record(author, &name) {
+                Ok(true) => println!("{} is now known as {}", previous, name),
+                Ok(false) => {}
+                Err(e) => {
+                    println!("Ignoring nickname from {}: {}", short_peer_id(&author), e);
+                    return MessageAcceptance::Reject;
+                }
+            }
+            return MessageAcceptance::Accept;
+        }
+        GossipMessage::ProfileUpdated => {
+            if let Some(author) = message.source {
+                node.act(Action::RefreshProfile { peer: author });
+            }
+            return MessageAcceptance::Accept;
+        }
+        GossipMessage::Joined => {
+            if let Some(author) = message.source {
+                let room = message.topic.to_string();
+                if node.members().heard(&room, author, Instant::now()) {
+                    member_joined(node.events(), room, author);
+                }
+            }
+            return MessageAcceptance::Accept;
+        }
+        GossipMessage::Left => {
+            if let Some(author) = message.source {
+                let room = message.topic.to_string();
+                if node.members().left(&room, &author) {
+                    member_left(node.events(), room, author);
+                }
+            }
+            return MessageAcceptance::Accept;
+        }
+        GossipMessage::Presence { rooms } => {
+            if let Some(author) = message.source {
+                let (joined, left) = node.members().presence(author, &rooms, Instant::now());
+                for room in joined {
+                    member_joined(node.events(), room, author);
+                }
+                for room in left {
+                    member_left(node.events(), room, author);
+                }
+            }
+            return MessageAcceptance::Accept;
+        }
+        GossipMessage::Edit {
+            target_id,
+            new_text,
+        } => (target_id, Change::Edit(new_text)),
+        GossipMessage::Delete { target_id } => (target_id, Change::Delete),
+        GossipMessage::Reaction { target_id, emoji } => (target_id, Change::React(emoji)),
+    };
+
+    // Only the author of a message may change it, so a change without one
+    // is of no use to anyone.
+    let Some(author) = message.source else {
+        return MessageAcceptance::Ignore;
+    };
+
+    // Reactions aren't persisted, so only edits and deletes are written back
+    // to the history.
+    let is_reaction = matches!(change, Change::React(_));
+    let outcome = match node.messages().apply(author, target_id, change.clone()) {
+        ChangeOutcome::Evicted if !is_reaction => node
+            .apply_to_history(author, target_id, change)
+            .unwrap_or_else(|e| {
+                println!("Failed to update {} in the history: {}", target_id, e);
+                ChangeOutcome::Evicted
+            }),
+        outcome => outcome,
+    };
+
+    match outcome {
+        ChangeOutcome::Applied => {
+            if !is_reaction {
+                node.persist(&target_id);
+            }
+            node.print_changed(&target_id);
+            MessageAcceptance::Accept
+        }
+        // We can't tell yet whether the change is the author's.
+        ChangeOutcome::Pending | ChangeOutcome::Evicted => MessageAcceptance::Accept,
+        ChangeOutcome::Rejected => {
+            println!(
+                "Ignoring change to {} from non-author {}",
+                target_id,
+                short_peer_id(&author)
+            );
+            node.act(Action::ReportViolation {
+                peer: author,
+                violation: Violation::UnauthorizedChange,
+            });
+            MessageAcceptance::Reject
+        }
+    }
+}
+
+/// Takes in a connected peer subscribing to the topic of a room.
+pub fn peer_subscribed(node: &mut impl Node, peer: PeerId, topic: gossipsub::TopicHash) {
+    let room = topic.to_string();
+    if node.members().subscribed(&room, peer, Instant::now()) {
+        member_joined(node.events(), room, peer);
+    }
+}
+
+/// Takes in a connected peer unsubscribing from the topic of a room.
+pub fn peer_unsubscribed(node: &mut impl Node, peer: PeerId, topic: gossipsub::TopicHash) {
+    let room = topic.to_string();
+    if node.members().left(&room, &peer) {
+        member_left(node.events(), room, peer);
+    }
+}
+
+/// Whether a received message carries a valid signature by its claimed
+/// author and isn't a replay. A bad signature is reported against `peer`, who
+/// delivered the message.
+pub fn verify(node: &mut impl Node, peer: PeerId, chat_message: &ChatMessage) -> bool {
+    verify_signature(node, peer, chat_message) && check_replay(node, chat_message)
+}
+
+/// Whether a direct message `peer` handed us is meant for `recipient` and,
+/// unless `peer` is a forwarder handing on someone else's, was written by
+/// `peer`. Messages from peers that predate recipients name no one.
+pub fn check_direct(
+    node: &mut impl Node,
+    peer: PeerId,
+    chat_message: &ChatMessage,
+    recipient: PeerId,
+    forwarded: bool,
+) -> bool {
+    if (forwarded || chat_message.peer_id == peer)
+        && chat_message
+            .recipient
+            .is_none_or(|named| named == recipient)
+    {
+        return true;
+    }
+    report_misattributed(node, peer, chat_message);
+    false
+}
+
+fn report_misattributed(node: &mut impl Node, peer: PeerId, chat_message: &ChatMessage) {
+    let violation = Violation::Misattributed;
+    println!(
+        "Discarding message {} from {}: {}",
+        chat_message.id,
+        short_peer_id(&peer),
+        violation
+    );
+    node.act(Action::ReportViolation { peer, violation });
+}
+
+fn verify_signature(node: &mut impl Node, peer: PeerId, chat_message: &ChatMessage) -> bool {
+    let Err(e) = chat_message.verify_signature() else {
+        return true;
+    };
+    println!(
+        "Discarding message {} from {}: {}",
+        chat_message.id,
+        short_peer_id(&peer),
+        e
+    );
+    node.act(Action::ReportViolation {
+        peer,
+        violation: Violation::BadSignature,
+    });
+    false
+}
+
+/// Whether a message whose signature checked out isn't a replay. Only the
+/// signature makes the sequence trustworthy, so check it first.
+fn check_replay(node: &mut impl Node, chat_message: &ChatMessage) -> bool {
+    let Some(sequence) = chat_message.sequence else {
+        return true;
+    };
+    match node.replays().check(chat_message.peer_id, sequence) {
+        Ok(()) => true,
+        Err(replay) => {
+            println!(
+                "Discarding message {} from {}: possible replay, {}",
+                chat_message.id,
+                short_peer_id(&chat_message.peer_id),
+                replay
+            );
+            false
+        }
+    }
+}
+
+/// Reports that `peer` is new in `room`.
+pub fn member_joined(events: &broadcast::Sender<ChatEvent>, room: String, peer: PeerId) {
+    let _ = events.send(ChatEvent::MemberJoined { room, peer });
+}
+
+/// Reports that `peer` is no longer in `room`.
+pub fn member_left(events: &broadcast::Sender<ChatEvent>, room: String, peer: PeerId) {
+    let _ = events.send(ChatEvent::MemberLeft { room, peer });
+}
+
+/// Reports a message of a kind this node doesn't know from `peer`.
+pub fn report_unknown(
+    events: &broadcast::Sender<ChatEvent>,
+    peer: PeerId,
+    version: u32,
+    kind: String,
+) {
+    println!(
+        "Ignoring a {} message from {} (envelope version {}); this node may be out of date",
+        kind,
+        short_peer_id(&peer),
+        version
+    );
+    let _ = events.send(ChatEvent::UnknownMessage {
+        peer,
+        version,
+        kind,
+    });
+}
@@ -0,0 +1,302 @@
+use crate::{
+    message::{ChatMessage, MessageId},
+    search::{SearchHit, SearchQuery, MAX_SEARCH_RESULTS},
+};
+use libp2p::PeerId;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+/// Name of the history file inside the data directory. It holds one JSON
+/// `ChatMessage` per line. The file is append-only while the node runs: a
+/// message that changes is written again, and the last copy of an id wins.
+pub const HISTORY_FILE: &str = "history.jsonl";
+
+/// Reads the history at `path` one message at a time. A missing file reads as
+/// an empty history.
+pub fn read(path: &Path) -> io::Result<impl Iterator<Item = io::Result<ChatMessage>>> {
+    let lines = match File::open(path) {
+        Ok(file) => Some(BufReader::new(file).lines()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e),
+    };
+
+    Ok(lines
+        .into_iter()
+        .flatten()
+        .enumerate()
+        .filter_map(|(index, line)| match line {
+            Ok(line) if line.trim().is_empty() => None,
+            Ok(line) => Some(serde_json::from_str(&line).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("history line {}: {}", index + 1, e),
+                )
+            })),
+            Err(e) => Some(Err(e)),
+        }))
+}
+
+pub fn load(path: &Path) -> io::Result<Vec<ChatMessage>> {
+    read(path)?.collect()
+}
+
+/// The latest copy of the message with `id`, if the history has one.
+pub fn find(path: &Path, id: &MessageId) -> io::Result<Option<ChatMessage>> {
+    let mut found = None;
+    for message in read(path)? {
+        let message = message?;
+        if message.id == *id {
+            found = Some(message);
+        }
+    }
+    Ok(found)
+}
+
+/// Which of a room's messages `/history` shows. The filters combine: a
+/// message must match every one that is set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryFilter {
+    pub room: String,
+    /// Only messages from this peer.
+    pub peer: Option<PeerId>,
+    /// Only messages sent at or after this unix timestamp.
+    pub since: Option<u64>,
+}
+
+impl HistoryFilter {
+    /// Every message of `room`.
+    pub fn room(room: impl Into<String>) -> Self {
+        HistoryFilter {
+            room: room.into(),
+            peer: None,
+            since: None,
+        }
+    }
+
+    /// Whether `message` passes every filter that is set.
+    pub fn matches(&self, message: &ChatMessage) -> bool {
+        message.room.as_deref() == Some(self.room.as_str())
+            && self.peer.is_none_or(|peer| message.peer_id == peer)
+            && self.since.is_none_or(|since| message.timestamp >= since)
+    }
+}
+
+/// Up to `limit` of the newest messages matching `filter` sent before
+/// `before`, oldest first. Used to page back through messages no longer held
+/// in memory.
+pub fn page_before(
+    path: &Path,
+    filter: &HistoryFilter,
+    before: u64,
+    limit: usize,
+) -> io::Result<Vec<ChatMessage>> {
+    let mut page = VecDeque::with_capacity(limit + 1);
+    for message in read(path)? {
+        let message = message?;
+        if !filter.matches(&message) || message.timestamp >= before {
+            continue;
+        }
+
+        page.retain(|older: &ChatMessage| older.id != message.id);
+        page.push_back(message);
+        if page.len() > limit {
+            page.pop_front();
+        }
+    }
+    Ok(page.into())
+}
+
+/// How many distinct messages matching `filter` were sent before `before`.
+pub fn count_before(path: &Path, filter: &HistoryFilter, before: u64) -> io::Result<usize> {
+    let mut ids = HashSet::new();
+    for message in read(path)? {
+        let message = message?;
+        if filter.matches(&message) && message.timestamp < before {
+            ids.insert(message.id);
+        }
+    }
+    Ok(ids.len())
+}
+
+/// A page of the messages matching a `HistoryFilter`, as shown by `/history`.
+#[derive(Debug)]
+pub struct Page {
+    /// Oldest first; empty if the page is past the oldest message.
+    pub messages: Vec<ChatMessage>,
+    /// Position of the first message among all those matching, oldest first
+    /// and counting from 1.
+    pub first: usize,
+    pub total: usize,
+}
+
+/// Page `page` of `limit` messages matching `filter`, counting back from the
+/// newest, so page 0 holds the latest ones. `in_memory` are the matching
+/// messages still held in memory, oldest first. When older ones were
+/// evicted, `evicted_to` is the history they are read back from.
+pub fn room_page(
+    evicted_to: Option<&Path>,
+    filter: &HistoryFilter,
+    in_memory: Vec<ChatMessage>,
+    page: usize,
+    limit: usize,
+) -> io::Result<Page> {
+    let before = in_memory
+        .first()
+        .map_or(u64::MAX, |message| message.timestamp);
+    let older = match evicted_to {
+        Some(path) => count_before(path, filter, before)?,
+        None => 0,
+    };
+    let total = older + in_memory.len();
+    let end = total.saturating_sub(page.saturating_mul(limit));
+    let start = end.saturating_sub(limit);
+
+    let mut messages = Vec::new();
+    if let Some(path) = evicted_to.filter(|_| start < older) {
+        messages = page_before(path, filter, before, older - start)?;
+        messages.truncate(end.min(older) - start);
+    }
+    messages.extend(
+        in_memory
+            .into_iter()
+            .skip(start.saturating_sub(older))
+            .take(end.saturating_sub(start.max(older))),
+    );
+    Ok(Page {
+        messages,
+        first: start + 1,
+        total,
+    })
+}
+
+/// Up to `MAX_SEARCH_RESULTS` messages in the history at `path` matching
+/// `query`, newest first, skipping those expired by `now`. Only the latest
+/// copy of each message is matched, and only hits are held in memory.
+pub fn search(path: &Path, query: &SearchQuery, now: u64) -> io::Result<Vec<SearchHit>> {
+    let mut hits = HashMap::new();
+    for message in read(path)? {
+        let message = message?;
+        match query.hit(&message).filter(|_| !message.is_expired(now)) {
+            Some(hit) => hits.insert(message.id, hit),
+            // An edit or delete may have taken away the match.
+            None => hits.remove(&message.id),
+        };
+    }
+
+    let mut hits: Vec<SearchHit> = hits.into_values().collect();
+    hits.sort_by_key(|hit| std::cmp::Reverse(hit.timestamp));
+    hits.truncate(MAX_SEARCH_RESULTS);
+    Ok(hits)
+}
+
+/// Rewrites the history with only the latest copy of each message, in the
+/// order the messages first appeared, dropping those expired by `now`. Only
+/// ids and the messages that changed are held in memory.
+pub fn compact(path: &Path, now: u64) -> io::Result<()> {
+    // First and last line of each id.
+    let mut positions = HashMap::<MessageId, (usize, usize)>::new();
+    for (index, message) in read(path)?.enumerate() {
+        positions
+            .entry(message?.id)
+            .and_modify(|(_, last)| *last = index)
+            .or_insert((index, index));
+    }
+
+    let mut changed = HashMap::new();
+    for (index, message) in read(path)?.enumerate() {
+        let message = message?;
+        if positions[&message.id] == (index, index) {
+            continue;
+        }
+        if positions[&message.id].1 == index {
+            changed.insert(message.id, message);
+        }
+    }
+
+    let partial = path.with_extension("jsonl.partial");
+    let mut writer = BufWriter::new(File::create(&partial)?);
+    for (index, message) in read(path)?.enumerate() {
+        let message = message?;
+        if positions[&message.id].0 != index {
+            continue;
+        }
+
+        let latest = changed.get(&message.id).unwrap_or(&message);
+        if !latest.is_expired(now) {
+            serde_json::to_writer(&mut writer, latest)?;
+            writer.write_all(b"\n")?;
+        }
+    }
+    writer.into_inner()?.sync_all()?;
+    fs::rename(partial, path)
+}
+
+/// Removes every message of `room`, or every message at all if unset, from
+/// the history at `path`, returning how many. Like `compact`, the rest is
+/// written next to the old file and renamed over it.
+pub fn clear(path: &Path, room: Option<&str>) -> io::Result<usize> {
+    remove_where(path, |message| {
+        room.is_none_or(|room| message.room.as_deref() == Some(room))
+    })
+}
+
+/// Removes every copy of the messages with `ids` from the history at `path`,
+/// returning how many of them it held. Used for messages that expired, which
+/// mustn't outlive their time on disk.
+pub fn remove(path: &Path, ids: &HashSet<MessageId>) -> io::Result<usize> {
+    remove_where(path, |message| ids.contains(&message.id))
+}
+
+fn remove_where(path: &Path, removed_if: impl Fn(&ChatMessage) -> bool) -> io::Result<usize> {
+    let mut removed = HashSet::new();
+    let partial = path.with_extension("jsonl.partial");
+    let mut writer = BufWriter::new(File::create(&partial)?);
+    for message in read(path)? {
+        let message = message?;
+        if removed_if(&message) {
+            removed.insert(message.id);
+            continue;
+        }
+        serde_json::to_writer(&mut writer, &message)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.into_inner()?.sync_all()?;
+    fs::rename(partial, path)?;
+    Ok(removed.len())
+}
+
+/// Replaces the history at `path` with `messages`. The file is written next to
+/// the old one and renamed over it, so a crash never leaves half a history.
+pub fn save<'a>(
+    path: &Path,
+    messages: impl IntoIterator<Item = &'a ChatMessage>,
+) -> io::Result<()> {
+    let partial = path.with_extension("jsonl.partial");
+    write_messages(File::create(&partial)?, messages)?;
+    fs::rename(partial, path)
+}
+
+/// Adds `messages` to the end of the history at `path`, creating it if needed.
+pub fn append<'a>(
+    path: &Path,
+    messages: impl IntoIterator<Item = &'a ChatMessage>,
+) -> io::Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    write_messages(file, messages)
+}
+
+fn write_messages<'a>(
+    file: File,
+    messages: impl IntoIterator<Item = &'a ChatMessage>,
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(file);
+    for message in messages {
+        serde_json::to_writer(&mut writer, message)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.into_inner()?.sync_all()
+}
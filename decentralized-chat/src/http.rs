@@ -0,0 +1,345 @@
+use crate::{
+    command::{Command, Reply},
+    event_socket::{self, EventSocket, Received},
+    handle::{ChatHandle, NODE_STOPPED},
+    message::MessageId,
+    token::same_token,
+};
+use axum::{
+    extract::{
+        rejection::{JsonRejection, QueryRejection},
+        ws::{
+            rejection::WebSocketUpgradeRejection, CloseFrame, Message, WebSocket, WebSocketUpgrade,
+        },
+        Query, Request, State,
+    },
+    http::{header, HeaderValue, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use libp2p::PeerId;
+use serde::Deserialize;
+use serde_json::json;
+use std::{borrow::Cow, io, net::SocketAddr, sync::Arc};
+use tokio::net::TcpListener;
+
+/// Messages returned by `GET /messages` unless `limit` says otherwise.
+const DEFAULT_LIMIT: usize = 50;
+
+/// Most messages `GET /messages` returns at once.
+const MAX_LIMIT: usize = 1000;
+
+/// Binds `addr` and serves the HTTP API there in the background, running
+/// every request through `handle`. Returns the address actually bound, which
+/// tells the port when `addr` asked for any.
+pub async fn serve(addr: SocketAddr, handle: ChatHandle, token: String) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind(addr).await?;
+    let bound = listener.local_addr()?;
+    let app = router(handle, token);
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            println!("HTTP API stopped: {}", e);
+        }
+    });
+    Ok(bound)
+}
+
+/// The API's routes, each requiring `Authorization: Bearer <token>`:
+///
+/// - `GET /peers`: the connected peers.
+/// - `GET /messages?room=<room>&limit=<n>`: the latest messages of a room.
+/// - `POST /messages`: sends `{"text": ..}` to a `"room"` or a `"peer"`.
+/// - `GET /status`: the node's counters, as shown by `/stats`.
+/// - `GET /metrics`: the node's metrics for Prometheus to scrape, bytes per
+///   peer among them.
+/// - `GET /ws`: a WebSocket streaming every event as a JSON text frame. Text
+///   frames sent to it are sent like `POST /messages` bodies, and each is
+///   answered with `{"id": ..}` or `{"error": ..}`.
+pub fn router(handle: ChatHandle, token: String) -> Router {
+    let api = Api {
+        handle,
+        token: token.into(),
+    };
+    Router::new()
+        .route("/peers", get(peers))
+        .route("/messages", get(messages).post(send))
+        .route("/status", get(status))
+        .route("/metrics", get(metrics))
+        .route("/ws", get(websocket))
+        .fallback(|| async { ApiError::new(StatusCode::NOT_FOUND, "no such endpoint") })
+        .route_layer(middleware::from_fn_with_state(api.clone(), authenticate))
+        .with_state(api)
+}
+
+#[derive(Clone)]
+struct Api {
+    handle: ChatHandle,
+    token: Arc<str>,
+}
+
+/// An error answered with `{"error": <message>}`.
+#[derive(Debug)]
+struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        ApiError {
+            status,
+            message: message.into(),
+        }
+    }
+
+    /// A command the node refused, most likely because of what was asked.
+    fn from_node(message: String) -> Self {
+        let status = if message == NODE_STOPPED {
+            StatusCode::SERVICE_UNAVAILABLE
+        } else {
+            StatusCode::BAD_REQUEST
+        };
+        ApiError::new(status, message)
+    }
+
+    fn unexpected(reply: Reply) -> Self {
+        ApiError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("unexpected reply: {}", reply),
+        )
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let mut response = (self.status, Json(json!({ "error": self.message }))).into_response();
+        if self.status == StatusCode::UNAUTHORIZED {
+            response
+                .headers_mut()
+                .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        }
+        response
+    }
+}
+
+async fn authenticate(State(api): State<Api>, request: Request, next: Next) -> Response {
+    let presented = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match presented {
+        Some(token) if same_token(token, &api.token) => next.run(request).await,
+        _ => {
+            ApiError::new(StatusCode::UNAUTHORIZED, "missing or wrong bearer token").into_response()
+        }
+    }
+}
+
+async fn peers(State(api): State<Api>) -> Result<impl IntoResponse, ApiError> {
+    match api.handle.execute(Command::Peers).await {
+        Ok(Reply::Peers { peers }) => Ok(Json(json!({ "peers": peers }))),
+        Ok(reply) => Err(ApiError::unexpected(reply)),
+        Err(e) => Err(ApiError::from_node(e)),
+    }
+}
+
+async fn status(State(api): State<Api>) -> Result<impl IntoResponse, ApiError> {
+    api.handle
+        .stats()
+        .await
+        .map(Json)
+        .map_err(ApiError::from_node)
+}
+
+async fn metrics(State(api): State<Api>) -> Result<impl IntoResponse, ApiError> {
+    match api.handle.execute(Command::Metrics).await {
+        Ok(Reply::Metrics { text }) => Ok((
+            [(
+                header::CONTENT_TYPE,
+                "application/openmetrics-text; version=1.0.0; charset=utf-8",
+            )],
+            text,
+        )),
+        Ok(reply) => Err(ApiError::unexpected(reply)),
+        Err(e) => Err(ApiError::from_node(e)),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MessagesQuery {
+    room: Option<String>,
+    limit: Option<usize>,
+}
+
+async fn messages(
+    State(api): State<Api>,
+    query: Result<Query<MessagesQuery>, QueryRejection>,
+) -> Result<impl IntoResponse, ApiError> {
+    let Query(query) = query.map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, e.body_text()))?;
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("limit must be between 1 and {}", MAX_LIMIT),
+        ));
+    }
+
+    let command = Command::History {
+        room: query.room,
+        peer: None,
+        since: None,
+        limit,
+        page: 0,
+    };
+    match api.handle.execute(command).await {
+        Ok(Reply::History { room, messages, .. }) => {
+            Ok(Json(json!({ "room": room, "messages": messages })))
+        }
+        Ok(reply) => Err(ApiError::unexpected(reply)),
+        Err(e) => Err(ApiError::from_node(e)),
+    }
+}
+
+/// What `POST /messages` sends: a chat message to one of our rooms, or a
+/// direct message to a peer.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SendBody {
+    room: Option<String>,
+    peer: Option<PeerId>,
+    text: String,
+}
+
+async fn send(
+    State(api): State<Api>,
+    body: Result<Json<SendBody>, JsonRejection>,
+) -> Result<impl IntoResponse, ApiError> {
+    let Json(body) = body.map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, e.body_text()))?;
+    let id = send_message(&api, body).await?;
+    Ok((StatusCode::CREATED, Json(json!({ "id": id }))))
+}
+
+async fn send_message(api: &Api, body: SendBody) -> Result<MessageId, ApiError> {
+    if body.text.trim().is_empty() {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "text is empty"));
+    }
+
+    let command = match (body.room, body.peer) {
+        (Some(room), None) => {
+            if !in_room(&api.handle, &room).await? {
+                return Err(ApiError::new(
+                    StatusCode::CONFLICT,
+                    format!("not in room {}; join it first", room),
+                ));
+            }
+            Command::Send {
+                text: body.text,
+                room: Some(room),
+                expires_in: None,
+            }
+        }
+        (None, Some(peer)) => {
+            if !knows_peer(&api.handle, &peer).await? {
+                return Err(ApiError::new(
+                    StatusCode::NOT_FOUND,
+                    format!("unknown peer {}", peer),
+                ));
+            }
+            Command::Msg {
+                peer,
+                text: body.text,
+            }
+        }
+        _ => {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "give either a room or a peer",
+            ))
+        }
+    };
+
+    match api.handle.execute(command).await {
+        Ok(Reply::Sent { id }) => Ok(id),
+        Ok(reply) => Err(ApiError::unexpected(reply)),
+        Err(e) => Err(ApiError::from_node(e)),
+    }
+}
+
+async fn websocket(
+    State(api): State<Api>,
+    upgrade: Result<WebSocketUpgrade, WebSocketUpgradeRejection>,
+) -> Result<Response, ApiError> {
+    let upgrade = upgrade.map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, e.body_text()))?;
+    Ok(upgrade.on_upgrade(move |socket| stream_events(api, socket)))
+}
+
+/// Runs one WebSocket client until it goes away.
+async fn stream_events(api: Api, socket: WebSocket) {
+    let events = api.handle.events();
+    event_socket::stream_events(events, socket, move |text| {
+        let api = api.clone();
+        async move {
+            let reply = match serde_json::from_str(&text) {
+                Ok(body) => match send_message(&api, body).await {
+                    Ok(id) => json!({ "id": id }),
+                    Err(e) => json!({ "error": e.message }),
+                },
+                Err(e) => json!({ "error": e.to_string() }),
+            };
+            reply.to_string()
+        }
+    })
+    .await;
+}
+
+impl EventSocket for WebSocket {
+    async fn receive(&mut self) -> Received {
+        match self.recv().await {
+            Some(Ok(Message::Text(text))) => Received::Text(text),
+            Some(Ok(Message::Close(_))) | Some(Err(_)) | None => Received::Gone,
+            Some(Ok(_)) => Received::Other,
+        }
+    }
+
+    async fn send_text(&mut self, text: String) -> bool {
+        self.send(Message::Text(text)).await.is_ok()
+    }
+
+    async fn close(mut self, code: u16, reason: &'static str) {
+        let frame = CloseFrame {
+            code,
+            reason: Cow::Borrowed(reason),
+        };
+        let _ = self.send(Message::Close(Some(frame))).await;
+    }
+}
+
+async fn in_room(handle: &ChatHandle, room: &str) -> Result<bool, ApiError> {
+    match handle.execute(Command::Rooms).await {
+        Ok(Reply::Rooms { rooms }) => Ok(rooms.iter().any(|summary| summary.room == room)),
+        Ok(reply) => Err(ApiError::unexpected(reply)),
+        Err(e) => Err(ApiError::from_node(e)),
+    }
+}
+
+/// Whether `peer` is connected or in the address book, so a message to it
+/// has a chance of arriving.
+async fn knows_peer(handle: &ChatHandle, peer: &PeerId) -> Result<bool, ApiError> {
+    match handle.execute(Command::Peers).await {
+        Ok(Reply::Peers { peers }) if peers.iter().any(|known| known.peer_id == *peer) => {
+            return Ok(true)
+        }
+        Ok(Reply::Peers { .. }) => {}
+        Ok(reply) => return Err(ApiError::unexpected(reply)),
+        Err(e) => return Err(ApiError::from_node(e)),
+    }
+    match handle.execute(Command::Known).await {
+        Ok(Reply::Known { peers }) => Ok(peers.iter().any(|known| known.peer_id == *peer)),
+        Ok(reply) => Err(ApiError::unexpected(reply)),
+        Err(e) => Err(ApiError::from_node(e)),
+    }
+}
@@ -0,0 +1,280 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64, Engine};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    ChaCha20Poly1305, Nonce,
+};
+use libp2p::{
+    identity::{Keypair, PublicKey},
+    PeerId,
+};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{collections::BTreeMap, fmt, fs, io, os::unix::fs::PermissionsExt, path::Path};
+
+/// Name of the file inside the data directory holding the secret of each
+/// private room.
+pub const ROOM_KEYS_FILE: &str = "room_keys.json";
+
+/// Seconds an invite is valid for when `/invite` isn't told otherwise.
+pub const DEFAULT_INVITE_SECS: u64 = 24 * 60 * 60;
+
+/// Most seconds an invite may be valid for.
+pub const MAX_INVITE_SECS: u64 = 365 * 24 * 60 * 60;
+
+/// Version written as the first byte of every invite.
+const INVITE_VERSION: u8 = 1;
+
+/// Prefixed to the signed bytes so an invite signature can't be passed off
+/// as a signature over anything else.
+const SIGNATURE_DOMAIN: &[u8] = b"decentralized-chat/invite/v1:";
+
+/// Prefixed to what a room key is hashed from.
+const KEY_DOMAIN: &[u8] = b"decentralized-chat/room-key/v1:";
+
+pub const SECRET_LEN: usize = 32;
+
+/// Bytes of the random nonce in front of each encrypted payload.
+const NONCE_LEN: usize = 12;
+
+/// What the members of a private room share, handed out in invites. The
+/// room key is derived from it and the room's name.
+pub type RoomSecret = [u8; SECRET_LEN];
+
+/// The key of private `room` with `secret`.
+pub fn room_key(room: &str, secret: &RoomSecret) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(KEY_DOMAIN);
+    hasher.update(room.as_bytes());
+    hasher.update([0]);
+    hasher.update(secret);
+    hasher.finalize().into()
+}
+
+/// Encrypts `plaintext` with the key of a private room, so that only its
+/// members can read it and only they can write what they will take in: a
+/// random nonce, then the ciphertext.
+pub fn encrypt_for_room(key: &[u8; 32], plaintext: &[u8]) -> Vec<u8> {
+    let mut nonce = [0; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let ciphertext = ChaCha20Poly1305::new(key.into())
+        .encrypt(Nonce::from_slice(&nonce), plaintext)
+        .expect("gossip payloads are far below the cipher's limit");
+    let mut encrypted = nonce.to_vec();
+    encrypted.extend(ciphertext);
+    encrypted
+}
+
+/// Decrypts what `encrypt_for_room` made with `key`. None if it was
+/// encrypted with another key, altered, or not encrypted at all.
+pub fn decrypt_for_room(key: &[u8; 32], encrypted: &[u8]) -> Option<Vec<u8>> {
+    if encrypted.len() < NONCE_LEN {
+        return None;
+    }
+    let (nonce, ciphertext) = encrypted.split_at(NONCE_LEN);
+    ChaCha20Poly1305::new(key.into())
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .ok()
+}
+
+/// An invite to a private room, as `/join --invite` redeems it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invite {
+    pub room: String,
+    pub secret: RoomSecret,
+    pub inviter: PeerId,
+    /// Unix time from which the invite is refused.
+    pub expires_at: u64,
+}
+
+/// Why an invite was refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InviteError {
+    /// The token isn't an invite at all, for the reason given.
+    Malformed(&'static str),
+    /// The invite is of a version this node doesn't know.
+    UnsupportedVersion(u8),
+    /// The signature doesn't match the rest of the invite: it was changed
+    /// after it was issued.
+    Tampered,
+    Expired {
+        expired_at: u64,
+    },
+}
+
+impl fmt::Display for InviteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InviteError::Malformed(reason) => write!(f, "not a valid invite: {}", reason),
+            InviteError::UnsupportedVersion(version) => write!(
+                f,
+                "invite version {} isn't supported; this node may be out of date",
+                version
+            ),
+            InviteError::Tampered => write!(
+                f,
+                "the invite's signature doesn't match; it was altered after it was issued"
+            ),
+            InviteError::Expired { expired_at } => {
+                write!(f, "the invite expired at {}", expired_at)
+            }
+        }
+    }
+}
+
+impl std::error::Error for InviteError {}
+
+impl Invite {
+    /// Encodes the invite as a token signed with `keypair`, which must be
+    /// the inviter's: its version, expiry, room, secret and the inviter's
+    /// public key, then a signature over all of them, in URL-safe base64.
+    pub fn issue(&self, keypair: &Keypair) -> Result<String, String> {
+        if keypair.public().to_peer_id() != self.inviter {
+            return Err("the invite must be signed by its inviter".to_string());
+        }
+        let room_len =
+            u8::try_from(self.room.len()).map_err(|_| "the room name is too long".to_string())?;
+        let public_key = keypair.public().encode_protobuf();
+
+        let mut bytes = vec![INVITE_VERSION];
+        bytes.extend(self.expires_at.to_be_bytes());
+        bytes.push(room_len);
+        bytes.extend(self.room.as_bytes());
+        bytes.extend(self.secret);
+        bytes.extend((public_key.len() as u16).to_be_bytes());
+        bytes.extend(public_key);
+        let signature = keypair
+            .sign(&signed_bytes(&bytes))
+            .map_err(|e| format!("Failed to sign the invite: {}", e))?;
+        bytes.extend(signature);
+        Ok(BASE64.encode(bytes))
+    }
+
+    /// Decodes `token` and checks that it was signed by its inviter and
+    /// hasn't expired by `now`.
+    pub fn redeem(token: &str, now: u64) -> Result<Invite, InviteError> {
+        let bytes = BASE64
+            .decode(token.trim())
+            .map_err(|_| InviteError::Malformed("not base64"))?;
+        let mut reader = Reader(&bytes);
+
+        let version = reader.take(1)?[0];
+        if version != INVITE_VERSION {
+            return Err(InviteError::UnsupportedVersion(version));
+        }
+        let expires_at = u64::from_be_bytes(reader.array()?);
+        let room_len = reader.take(1)?[0];
+        let room = std::str::from_utf8(reader.take(room_len.into())?)
+            .map_err(|_| InviteError::Malformed("the room name isn't UTF-8"))?
+            .to_string();
+        let secret: RoomSecret = reader.array()?;
+        let key_len = u16::from_be_bytes(reader.array()?);
+        let public_key = PublicKey::try_decode_protobuf(reader.take(key_len.into())?)
+            .map_err(|_| InviteError::Malformed("the inviter's key can't be read"))?;
+        let signed_len = bytes.len() - reader.0.len();
+        let signature = reader.0;
+        if signature.is_empty() {
+            return Err(InviteError::Malformed("it isn't signed"));
+        }
+
+        // The signature covers the expiry, so check it first: an invite whose
+        // expiry was pushed back is tampered, not valid.
+        if !public_key.verify(&signed_bytes(&bytes[..signed_len]), signature) {
+            return Err(InviteError::Tampered);
+        }
+        if expires_at <= now {
+            return Err(InviteError::Expired {
+                expired_at: expires_at,
+            });
+        }
+        Ok(Invite {
+            room,
+            secret,
+            inviter: public_key.to_peer_id(),
+            expires_at,
+        })
+    }
+}
+
+fn signed_bytes(invite: &[u8]) -> Vec<u8> {
+    let mut bytes = SIGNATURE_DOMAIN.to_vec();
+    bytes.extend(invite);
+    bytes
+}
+
+/// Reads the fields of an invite in order.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], InviteError> {
+        if self.0.len() < len {
+            return Err(InviteError::Malformed("it is cut short"));
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], InviteError> {
+        Ok(self.take(N)?.try_into().expect("took N bytes"))
+    }
+}
+
+/// The secrets of the private rooms we created an invite for or joined
+/// with one, persisted across restarts.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RoomKeys {
+    /// Base64 of each room's secret.
+    rooms: BTreeMap<String, String>,
+}
+
+impl RoomKeys {
+    /// Loads the secrets from `path`, starting without any if the file does
+    /// not exist yet.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        match fs::read(path) {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(RoomKeys::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Writes the secrets to `path`, readable by our user only.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+    }
+
+    pub fn is_private(&self, room: &str) -> bool {
+        self.rooms.contains_key(room)
+    }
+
+    pub fn secret(&self, room: &str) -> Option<RoomSecret> {
+        let secret = BASE64.decode(self.rooms.get(room)?).ok()?;
+        secret.try_into().ok()
+    }
+
+    /// The secret of `room`, made up first if it has none, which makes the
+    /// room private for us.
+    pub fn secret_or_new(&mut self, room: &str) -> RoomSecret {
+        if let Some(secret) = self.secret(room) {
+            return secret;
+        }
+        let mut secret = [0; SECRET_LEN];
+        rand::thread_rng().fill_bytes(&mut secret);
+        self.insert(room, secret);
+        secret
+    }
+
+    /// Sets the secret of `room`. Returns whether that changed it.
+    pub fn insert(&mut self, room: &str, secret: RoomSecret) -> bool {
+        let encoded = BASE64.encode(secret);
+        self.rooms.insert(room.to_string(), encoded.clone()) != Some(encoded)
+    }
+
+    /// The key of `room`, if it is private, which its gossip is encrypted
+    /// with.
+    pub fn key(&self, room: &str) -> Option<[u8; 32]> {
+        self.secret(room).map(|secret| room_key(room, &secret))
+    }
+}
@@ -0,0 +1,184 @@
+use bip39::{Language, Mnemonic, WordCount};
+use libp2p::identity::{self, Keypair};
+use std::{fmt, fs, io, path::Path, str::FromStr};
+
+/// Name of the node's identity key inside the data directory.
+pub const KEY_FILE: &str = "identity.key";
+
+/// Length of the seed phrases made by `keygen --mnemonic`, which hold 256
+/// bits of entropy.
+pub const MNEMONIC_WORDS: usize = 24;
+
+/// Kinds of identity key a node can use, chosen with `--key-type`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum KeyType {
+    #[default]
+    Ed25519,
+    Secp256k1,
+    /// ECDSA over the NIST P-256 curve.
+    Ecdsa,
+    /// libp2p can't generate or encode RSA keys, so these are only loaded
+    /// from a PKCS#8 file, e.g. from `openssl genrsa -out key.pem 2048` then
+    /// `openssl pkcs8 -in key.pem -topk8 -outform DER -nocrypt`.
+    Rsa,
+}
+
+impl KeyType {
+    pub fn of(keypair: &Keypair) -> KeyType {
+        match keypair.key_type() {
+            identity::KeyType::Ed25519 => KeyType::Ed25519,
+            identity::KeyType::Secp256k1 => KeyType::Secp256k1,
+            identity::KeyType::Ecdsa => KeyType::Ecdsa,
+            identity::KeyType::RSA => KeyType::Rsa,
+        }
+    }
+}
+
+impl FromStr for KeyType {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "ed25519" => Ok(KeyType::Ed25519),
+            "secp256k1" => Ok(KeyType::Secp256k1),
+            "ecdsa" => Ok(KeyType::Ecdsa),
+            "rsa" => Ok(KeyType::Rsa),
+            _ => Err(format!(
+                "unknown key type: {} (expected ed25519, secp256k1, ecdsa or rsa)",
+                name
+            )),
+        }
+    }
+}
+
+impl fmt::Display for KeyType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyType::Ed25519 => write!(f, "ed25519"),
+            KeyType::Secp256k1 => write!(f, "secp256k1"),
+            KeyType::Ecdsa => write!(f, "ecdsa"),
+            KeyType::Rsa => write!(f, "rsa"),
+        }
+    }
+}
+
+/// Generates a new key of `key_type`.
+pub fn generate(key_type: KeyType) -> Result<Keypair, String> {
+    match key_type {
+        KeyType::Ed25519 => Ok(Keypair::generate_ed25519()),
+        KeyType::Secp256k1 => Ok(Keypair::generate_secp256k1()),
+        KeyType::Ecdsa => Ok(Keypair::generate_ecdsa()),
+        KeyType::Rsa => Err(
+            "RSA keys can't be generated; write a PKCS#8 key to the key file instead".to_string(),
+        ),
+    }
+}
+
+/// A new random BIP-39 seed phrase of `MNEMONIC_WORDS` English words.
+pub fn generate_mnemonic() -> String {
+    Mnemonic::generate_in(Language::English, WordCount::Words24)
+        .expect("24 words is a valid BIP-39 length")
+        .to_string()
+}
+
+/// The key of `key_type` a seed phrase stands for. The phrase is turned
+/// into a seed as BIP-39 specifies, with PBKDF2-HMAC-SHA512 over 2048
+/// rounds, the salt "mnemonic" and no passphrase, and the first 32 bytes of
+/// the seed are the secret key: an ed25519 seed, or a secp256k1 or P-256
+/// scalar. The words are checked against their checksum, ignoring case and
+/// extra whitespace.
+pub fn from_mnemonic(phrase: &str, key_type: KeyType) -> Result<Keypair, String> {
+    let words: Vec<String> = phrase.split_whitespace().map(str::to_lowercase).collect();
+    if words.len() != MNEMONIC_WORDS {
+        return Err(format!(
+            "a seed phrase has {} words, not {}",
+            MNEMONIC_WORDS,
+            words.len()
+        ));
+    }
+    let mnemonic = Mnemonic::parse_in(Language::English, words.join(" "))
+        .map_err(|e| format!("invalid seed phrase: {}", e))?;
+    let mut secret = mnemonic.to_seed("")[..32].to_vec();
+    let keypair = match key_type {
+        KeyType::Ed25519 => Keypair::ed25519_from_bytes(&mut secret),
+        KeyType::Secp256k1 => identity::secp256k1::SecretKey::try_from_bytes(&mut secret)
+            .map(|secret| identity::secp256k1::Keypair::from(secret).into()),
+        KeyType::Ecdsa => identity::ecdsa::SecretKey::try_from_bytes(&secret)
+            .map(|secret| identity::ecdsa::Keypair::from(secret).into()),
+        KeyType::Rsa => return Err("RSA keys can't be made from a seed phrase".to_string()),
+    };
+    keypair.map_err(|e| e.to_string())
+}
+
+/// Writes `keypair` to a new key file at `path`, refusing to replace one.
+pub fn save(path: &Path, keypair: &Keypair) -> io::Result<()> {
+    let encoded = keypair
+        .to_protobuf_encoding()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    write_private(path, &encoded).map_err(|e| match e.kind() {
+        io::ErrorKind::AlreadyExists => io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!(
+                "{} already holds an identity key; move it away first",
+                path.display()
+            ),
+        ),
+        _ => e,
+    })
+}
+
+/// Decodes a key file: the protobuf encoding libp2p uses for every key type,
+/// or a PKCS#8 RSA key.
+pub fn decode(bytes: &[u8]) -> Result<Keypair, String> {
+    Keypair::from_protobuf_encoding(bytes).or_else(|protobuf_error| {
+        Keypair::rsa_from_pkcs8(&mut bytes.to_vec()).map_err(|rsa_error| {
+            format!(
+                "neither a libp2p key ({}) nor a PKCS#8 RSA key ({})",
+                protobuf_error, rsa_error
+            )
+        })
+    })
+}
+
+/// Loads the key at `path`, generating one of `key_type` (ed25519 if unset)
+/// the first time. A stored key of another type than the one asked for is
+/// an error rather than being silently replaced.
+pub fn load_or_generate(path: &Path, key_type: Option<KeyType>) -> io::Result<Keypair> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+
+    match fs::read(path) {
+        Ok(bytes) => {
+            let keypair = decode(&bytes)
+                .map_err(|e| invalid(format!("invalid key {}: {}", path.display(), e)))?;
+            let stored = KeyType::of(&keypair);
+            match key_type {
+                Some(key_type) if key_type != stored => Err(invalid(format!(
+                    "{} holds a {} key, not {}; move it away to generate a new one",
+                    path.display(),
+                    stored,
+                    key_type
+                ))),
+                _ => Ok(keypair),
+            }
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let keypair = generate(key_type.unwrap_or_default())
+                .map_err(|e| invalid(format!("{} ({})", e, path.display())))?;
+            save(path, &keypair)?;
+            Ok(keypair)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Writes `bytes` to a new file only the current user can read.
+fn write_private(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    use std::{io::Write, os::unix::fs::OpenOptionsExt};
+
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(bytes)
+}
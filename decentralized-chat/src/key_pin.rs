@@ -0,0 +1,154 @@
+use crate::event::ChatEvent;
+use libp2p::{
+    core::ConnectedPoint,
+    multiaddr::Protocol,
+    swarm::{ConnectionId, NetworkBehaviour},
+    Multiaddr, PeerId, Swarm,
+};
+use std::{fmt, str::FromStr};
+
+/// Protocols whose value a pattern may leave open with `*`.
+const WILDCARD_PROTOCOLS: &[&str] = &["ip4", "ip6", "dns", "dns4", "dns6", "dnsaddr", "tcp", "udp"];
+
+/// A multiaddr whose values may be `*`, such as `/ip4/192.0.2.1/tcp/*`. It
+/// matches the addresses that start with it, so a trailing `/ws` or `/p2p`
+/// id needn't be spelled out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressPattern {
+    pattern: String,
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Exact(Protocol<'static>),
+    /// Any value of the protocol with this tag.
+    Any(&'static str),
+}
+
+impl AddressPattern {
+    pub fn matches(&self, address: &Multiaddr) -> bool {
+        let mut protocols = address.iter();
+        self.segments.iter().all(|segment| {
+            protocols.next().is_some_and(|protocol| match segment {
+                Segment::Exact(expected) => *expected == protocol,
+                Segment::Any(tag) => protocol.tag() == *tag,
+            })
+        })
+    }
+
+    /// The peer id the pattern ends with, if it does.
+    pub fn peer_id(&self) -> Option<PeerId> {
+        match self.segments.last() {
+            Some(Segment::Exact(Protocol::P2p(peer))) => Some(*peer),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for AddressPattern {
+    type Err = String;
+
+    fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+        let Some(rest) = pattern.strip_prefix('/') else {
+            return Err(format!(
+                "invalid address pattern: {} (patterns start with /, e.g. /ip4/192.0.2.1/tcp/*)",
+                pattern
+            ));
+        };
+
+        let mut parts = rest.split('/').peekable();
+        let mut segments = Vec::new();
+        while let Some(name) = parts.next() {
+            if parts.next_if_eq(&"*").is_some() {
+                let tag = WILDCARD_PROTOCOLS
+                    .iter()
+                    .find(|tag| **tag == name)
+                    .ok_or_else(|| format!("invalid address pattern: /{} can't be *", name))?;
+                segments.push(Segment::Any(tag));
+                continue;
+            }
+            // The protocol alone, such as /ws, or with the value after it.
+            let address = match format!("/{}", name).parse::<Multiaddr>() {
+                Ok(address) => address,
+                Err(_) => {
+                    let value = parts.next().ok_or_else(|| {
+                        format!("invalid address pattern: /{} needs a value or *", name)
+                    })?;
+                    format!("/{}/{}", name, value)
+                        .parse()
+                        .map_err(|_| format!("invalid address pattern: /{}/{}", name, value))?
+                }
+            };
+            segments.extend(
+                address
+                    .iter()
+                    .map(|protocol| Segment::Exact(protocol.acquire())),
+            );
+        }
+
+        if segments.is_empty() {
+            return Err("the address pattern is empty".to_string());
+        }
+        Ok(AddressPattern {
+            pattern: pattern.to_string(),
+            segments,
+        })
+    }
+}
+
+impl fmt::Display for AddressPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.pattern)
+    }
+}
+
+/// The peer each address pattern is pinned to. Once we dial an address a
+/// pattern matches, only that peer may answer.
+#[derive(Debug, Clone, Default)]
+pub struct KeyPins {
+    pins: Vec<(AddressPattern, PeerId)>,
+}
+
+impl KeyPins {
+    pub fn new(pins: Vec<(AddressPattern, PeerId)>) -> Self {
+        KeyPins { pins }
+    }
+
+    /// Checks that `peer` answered at `address`: `Err` holds the peer of a
+    /// pin matching the address that isn't `peer`. Every matching pin has to
+    /// agree, so pins that contradict each other let no one through.
+    pub fn verify(&self, address: &Multiaddr, peer: &PeerId) -> Result<(), PeerId> {
+        match self
+            .pins
+            .iter()
+            .find(|(pattern, pinned)| pinned != peer && pattern.matches(address))
+        {
+            Some((_, pinned)) => Err(*pinned),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Checks a new connection against `pins`. Connections we dialed to a
+/// pinned address where another peer authenticated are closed at once, and
+/// the warning to report is returned. Connections peers dialed to us aren't
+/// checked: the address they come from says nothing about who they are.
+pub fn check_connection<B: NetworkBehaviour>(
+    swarm: &mut Swarm<B>,
+    pins: &KeyPins,
+    peer_id: PeerId,
+    connection_id: ConnectionId,
+    endpoint: &ConnectedPoint,
+) -> Option<ChatEvent> {
+    let ConnectedPoint::Dialer { address, .. } = endpoint else {
+        return None;
+    };
+    let expected = pins.verify(address, &peer_id).err()?;
+    swarm.close_connection(connection_id);
+    Some(ChatEvent::KeyPinMismatch {
+        address: address.clone(),
+        expected,
+        actual: peer_id,
+    })
+}
@@ -0,0 +1,66 @@
+pub mod address_book;
+pub mod ban;
+pub mod bandwidth;
+pub mod batch;
+pub mod behaviour;
+pub mod bench;
+pub mod bot;
+pub mod command;
+pub mod compression;
+pub mod config;
+pub mod contacts;
+pub mod control;
+pub mod daemon;
+pub mod delivery;
+pub mod departure;
+pub mod dht;
+pub mod dial;
+pub mod dnd;
+pub mod emoji;
+pub mod envelope;
+pub mod event;
+#[cfg(any(feature = "http-api", feature = "web-ui"))]
+pub mod event_socket;
+pub mod expiry;
+pub mod export;
+pub mod filter;
+pub mod fingerprint;
+pub mod flood;
+pub mod forward;
+pub mod handle;
+pub mod handler;
+pub mod history;
+#[cfg(feature = "http-api")]
+pub mod http;
+pub mod invite;
+pub mod key;
+pub mod key_pin;
+pub mod markdown;
+pub mod members;
+pub mod mention;
+pub mod message;
+pub mod nickname;
+pub mod notification;
+pub mod output;
+pub mod parser;
+pub mod peer_exchange;
+pub mod peer_id;
+pub mod peer_profile;
+pub mod profile;
+pub mod rate_limit;
+pub mod rendezvous;
+pub mod replay;
+pub mod retry;
+pub mod room_settings;
+#[cfg(feature = "http-api")]
+pub mod rpc;
+pub mod search;
+pub mod security;
+pub mod share;
+pub mod stats;
+pub mod store;
+pub mod stream;
+pub mod testing;
+pub mod token;
+#[cfg(feature = "web-ui")]
+pub mod web_ui;
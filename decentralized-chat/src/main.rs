@@ -1,121 +1,3622 @@
+mod cli;
+
+use clap::Parser;
+use cli::{Cli, CliCommand, ProfilesCommand};
 use libp2p::{
-    futures::StreamExt, gossipsub, identity, mdns, noise, request_response::{self, ProtocolSupport}, swarm::{NetworkBehaviour, SwarmEvent}, tcp, yamux, Multiaddr, PeerId, StreamProtocol, SwarmBuilder
+    connection_limits,
+    core::{transport::ListenerId, ConnectedPoint},
+    futures::StreamExt,
+    gossipsub, identify,
+    identity::{self, PublicKey},
+    kad, mdns,
+    metrics::Registry,
+    rendezvous::{self, Namespace},
+    request_response::{self, OutboundRequestId, ResponseChannel},
+    swarm::{
+        dial_opts::{DialOpts, PeerCondition},
+        ConnectionId, DialError, ListenError, SwarmEvent,
+    },
+    upnp, Multiaddr, PeerId, Swarm,
+};
+#[cfg(any(feature = "http-api", feature = "web-ui"))]
+use libp2p_demo::token;
+#[cfg(feature = "web-ui")]
+use libp2p_demo::web_ui;
+use libp2p_demo::{
+    address_book::AddressBook,
+    ban::{Violation, ViolationTracker},
+    batch::Batcher,
+    behaviour::{
+        self, CustomBehaviour, CustomBehaviourEvent, ProtocolVersion, Request, Response,
+        COMPRESSED_CHAT_PROTOCOL,
+    },
+    bench::{self, BenchConfig},
+    bot::{self, PingBot},
+    command::{
+        Avatar, Command, ConnectedPeer, Friend, KnownPeer, PeerTraffic, Peering, Reply, RoomMember,
+        RoomSummary, Whois,
+    },
+    config::{
+        BatchConfig, Config, ConnectionLimitsConfig, DeletedMessages, Validation, CONFIG_FILE,
+    },
+    contacts::{Contacts, CONTACTS_FILE},
+    control::{self, ControlRequest, ControlSocket},
+    daemon::{self, PidFile, PID_FILE},
+    delivery::DeliveryStatus,
+    departure::Departures,
+    dht::{self, RoomProviders, KAD_PROTOCOL},
+    dial::{
+        check_dial_address, describe_close, describe_dial_error, describe_transport_error,
+        is_handshake_failure, is_public, is_security_failure, report_dial_error,
+    },
+    dnd::{DoNotDisturb, DND_FILE},
+    emoji::expand_shortcodes,
+    envelope::{self, Kinds, Opened},
+    event::ChatEvent,
+    expiry::Expiries,
+    export::{self, ExportFilter},
+    fingerprint::{embedded_key, fingerprint},
+    flood::{Admission, FloodGuard, Priority},
+    forward::{ForwardStore, StoreError, StoredMessage},
+    handle::ChatHandle,
+    handler::{self, Action, Node},
+    history::{self, HistoryFilter, HISTORY_FILE},
+    invite::{
+        encrypt_for_room, Invite, RoomKeys, DEFAULT_INVITE_SECS, MAX_INVITE_SECS, ROOM_KEYS_FILE,
+    },
+    key::{self, KeyType, KEY_FILE},
+    key_pin::{self, KeyPins},
+    members::RoomMembers,
+    mention::{mentions, mentions_peer, Mentions},
+    message::{
+        unix_now, ChatMessage, DirectRequest, DirectResponse, GossipMessage, MessageId, Sequence,
+        MAX_EXPIRES_IN_SECS,
+    },
+    nickname::{validate_nickname, Nicknames},
+    notification::Notifier,
+    output::OutputBatch,
+    parser,
+    peer_exchange::{self, DialQueue, PeerRecord},
+    peer_id::{self, short_peer_id},
+    peer_profile::{
+        validate_avatar, validate_status, PeerProfile, ProfileCache, PROFILE_CACHE_DIR,
+        PUBLIC_PROFILE_FILE,
+    },
+    profile::{self, DEFAULT_PROFILE},
+    rate_limit::{Decision, RateLimiter},
+    rendezvous::{room_namespace, server_peer_id, Registrations},
+    replay::{ReplayGuard, SEQUENCES_FILE},
+    retry::{Failed, RetryQueue},
+    room_settings::{Notify, RoomSettings, ROOM_SETTINGS_FILE},
+    search::SearchQuery,
+    share::shareable_addresses,
+    stats::{self, Bandwidth, Counters, HistoryStats, Limits, NatStatus, PortMapping, Stats},
+    store::{apply_to_message, Change, ChangeOutcome, MessageStore},
+};
+#[cfg(feature = "http-api")]
+use libp2p_demo::{http, rpc};
+use serde::Serialize;
+use serde_json::json;
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{self, AsyncBufReadExt, BufReader},
+    signal::unix::{signal, SignalKind},
+    sync::{broadcast, mpsc},
 };
-use serde::{Deserialize, Serialize};
-use serde_json::{json, Value};
-use std::{error::Error, time::Duration};
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Request {
-    data: Value,
+const CHAT_TOPIC: &str = "chat";
+
+/// Listened on when no `--listen` address is given, together with a
+/// WebSocket listener on `--ws-port`.
+const DEFAULT_LISTEN_ADDRS: [&str; 2] = ["/ip4/0.0.0.0/tcp/0", "/ip6/::/tcp/0"];
+
+/// How long to wait before re-opening a default listener that closed.
+const RELISTEN_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often pinned peers that aren't connected are dialed again.
+const PINNED_REDIAL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long a peer has to reach us from the internet before we warn that the
+/// `--external-address` may not be forwarded.
+const EXTERNAL_ADDRESS_GRACE: Duration = Duration::from_secs(5 * 60);
+
+/// How long after its last connection closes a peer counts as gone, unless
+/// it connects again meanwhile.
+const DEPARTURE_GRACE: Duration = Duration::from_secs(10);
+
+/// How long connections get to close on the way out before they are dropped.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(2);
+
+/// Refused connections are printed at most this often.
+const DENIAL_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long `stop` waits for the daemon to shut down.
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Events kept for handles that are slow to receive them.
+const EVENT_BUFFER: usize = 256;
+
+/// How many messages `/mentions` lists.
+const RECENT_MENTIONS: usize = 20;
+
+/// How many address book entries are dialed on startup.
+const STARTUP_DIAL_LIMIT: usize = 8;
+
+/// Everything the event loop keeps between events, apart from the swarm itself.
+struct AppState {
+    keypair: identity::Keypair,
+    local_chat_messages: MessageStore,
+    /// When the stored messages that expire are to be removed.
+    expiries: Expiries,
+    /// Messages that expired since the history was last purged of them.
+    expired: HashSet<MessageId>,
+    listen_addrs: HashSet<Multiaddr>,
+    /// The address each open listener was asked to listen on.
+    listeners: HashMap<ListenerId, Multiaddr>,
+    /// Whether closed listeners are re-opened, which is only done for the
+    /// default ones.
+    relisten: bool,
+    /// Set by `/quit`; the event loop ends once it is.
+    quitting: bool,
+    /// Closed listeners waiting to be re-opened.
+    closed_listeners: Vec<Multiaddr>,
+    /// With `--print-qr`, the listeners that have yet to report an address
+    /// before the QR code is printed.
+    qr_pending: HashSet<ListenerId>,
+    /// The newest chat protocol each identified peer speaks.
+    peer_protocols: HashMap<PeerId, ProtocolVersion>,
+    /// Identified peers offering `COMPRESSED_CHAT_PROTOCOL`, unless
+    /// `config.protocol.compression` is off.
+    compressing_peers: HashSet<PeerId>,
+    /// Bytes of JSON above which payloads for `compressing_peers` are
+    /// compressed.
+    compress_above_bytes: usize,
+    /// Whether gossip is sent in envelopes, which legacy peers can't read.
+    seal_gossip: bool,
+    /// Dials started with `/dial`, whose outcome is still to be reported.
+    pending_dials: HashMap<ConnectionId, Multiaddr>,
+    /// Whether a peer outside our network has connected to us, showing that
+    /// an announced external address works.
+    reached_from_outside: bool,
+    port_mapping: PortMapping,
+    /// External addresses of the ports UPnP mapped on the gateway.
+    mapped_addrs: HashSet<Multiaddr>,
+    /// Rendezvous servers given with `--rendezvous`, by peer id.
+    rendezvous_servers: HashMap<PeerId, Multiaddr>,
+    /// Peers from `peering.pinned`, kept connected.
+    pinned_peers: HashMap<PeerId, Multiaddr>,
+    /// Who may answer at the addresses of the config's key_pins.
+    key_pins: KeyPins,
+    /// Connections closed for failing a key pin, whose peers never counted
+    /// as connected.
+    unpinned_connections: HashSet<ConnectionId>,
+    /// Peers whose last connection closed lately, who don't count as gone
+    /// yet.
+    departures: Departures,
+    /// Peers gossipsub sends every message to, pinned ones among them.
+    explicit_peers: HashSet<PeerId>,
+    /// Peers found over mDNS are made explicit while fewer than this many
+    /// are connected, and fewer are explicit.
+    explicit_below: usize,
+    /// Whether gossipsub holds each message back until we validated it, as
+    /// `gossipsub.validation` strict has it do.
+    validates_gossip: bool,
+    registrations: Registrations,
+    /// Seconds we ask rendezvous servers to keep our registrations.
+    rendezvous_ttl: u64,
+    /// Whether addresses peers observe for us are taken as external ones,
+    /// which registering with a rendezvous server needs.
+    confirm_observed_addrs: bool,
+    /// Whether we have said that registering is waiting on an external
+    /// address, which is only worth saying once.
+    reported_no_external_addr: bool,
+    /// Room members found in the DHT.
+    room_providers: RoomProviders,
+    /// The members of our rooms we saw subscribed or heard from.
+    members: RoomMembers,
+    address_book: AddressBook,
+    address_book_path: PathBuf,
+    /// Peers added with `/addfriend`.
+    contacts: Contacts,
+    contacts_path: PathBuf,
+    /// The status and avatar we share, set with `/profile`.
+    public_profile: PeerProfile,
+    public_profile_path: PathBuf,
+    /// The profiles peers shared with us.
+    peer_profiles: ProfileCache,
+    /// Peers whose profile was requested and hasn't arrived yet.
+    fetching_profiles: HashSet<PeerId>,
+    history_path: PathBuf,
+    current_room: gossipsub::IdentTopic,
+    /// Rooms muted with `/mute`.
+    room_settings: RoomSettings,
+    room_settings_path: PathBuf,
+    /// Secrets of the private rooms we invited to or joined by invite.
+    room_keys: RoomKeys,
+    room_keys_path: PathBuf,
+    nickname: Option<String>,
+    /// The nicknames other peers announced or sent messages with.
+    nicknames: Nicknames,
+    /// The keys connected peers presented in identify.
+    public_keys: HashMap<PeerId, PublicKey>,
+    /// Whether `:shortcode:` emoji are expanded in outgoing messages.
+    expand_emoji: bool,
+    deleted_messages: DeletedMessages,
+    mentions: Mentions,
+    /// Whether a terminal bell is rung when someone mentions us.
+    bell: bool,
+    /// Whether someone is typing at the prompt, which is reprinted after a
+    /// mention; unset with `--daemon`.
+    interactive: bool,
+    /// Shows desktop notifications for direct messages and mentions; unset
+    /// by `--no-notifications`.
+    notifier: Option<Notifier>,
+    /// Set with `/dnd`; silences notifications and highlights and answers
+    /// direct messages.
+    do_not_disturb: DoNotDisturb,
+    dnd_path: PathBuf,
+    output: OutputBatch,
+    violations: ViolationTracker,
+    /// Numbers the messages we send.
+    sequence: Sequence,
+    replays: ReplayGuard,
+    sequences_path: PathBuf,
+    rate_limiter: RateLimiter,
+    /// Peers we blocked, by hand or for misbehaving, which aren't shared in
+    /// peer exchanges.
+    blocked: HashSet<PeerId>,
+    /// Limits how often each peer may ask us for peers.
+    exchange_limiter: RateLimiter,
+    /// Peers asked for their peers since we connected to them.
+    exchanged: HashSet<PeerId>,
+    exchange_dials: DialQueue,
+    /// Peers learned through exchange are dialed while we have fewer
+    /// connected peers than this.
+    exchange_target_peers: usize,
+    flood_guard: FloodGuard,
+    /// Messages held for offline peers; set by `--forward`.
+    forward_store: Option<ForwardStore>,
+    /// Held messages on their way to their target, put back if delivery fails.
+    forwarding: HashMap<OutboundRequestId, (PeerId, StoredMessage)>,
+    /// Seconds we ask forwarders to hold our messages for offline peers.
+    forward_ttl: u64,
+    /// Our direct messages until they are answered or given up on.
+    retries: RetryQueue,
+    /// The messages each attempt at direct messages on its way carries: one,
+    /// or all those of a batch.
+    direct_messages: HashMap<OutboundRequestId, Vec<MessageId>>,
+    /// Direct messages held to go out with those sent just after them.
+    batcher: Batcher,
+    events: broadcast::Sender<ChatEvent>,
+    counters: Counters,
+    metrics: Registry,
+    /// The per-peer byte counters as of `/bw reset`.
+    bandwidth_baseline: HashMap<PeerId, Bandwidth>,
+    connection_limits: ConnectionLimitsConfig,
+    /// Connections refused over the limits since the last time that was
+    /// printed, and when that was.
+    unreported_denials: u64,
+    last_denial_report: Option<Instant>,
+}
+
+impl AppState {
+    /// A new message from us, carrying our nickname and the next sequence
+    /// number.
+    fn outgoing_message(&mut self, swarm: &Swarm<CustomBehaviour>, text: String) -> ChatMessage {
+        ChatMessage {
+            nickname: self.nickname.clone(),
+            sequence: Some(self.sequence.advance()),
+            ..ChatMessage::new(*swarm.local_peer_id(), self.outgoing_text(text))
+        }
+    }
+
+    /// A new message from us for `peer` alone. Peers not known to speak the
+    /// versioned protocol get no sequence number or recipient, since they
+    /// would count them against the signature.
+    fn outgoing_direct_message(
+        &mut self,
+        swarm: &Swarm<CustomBehaviour>,
+        peer: PeerId,
+        text: String,
+    ) -> ChatMessage {
+        let chat_message = self.outgoing_message(swarm, text);
+        match self.peer_protocols.get(&peer) {
+            Some(ProtocolVersion::V1) => ChatMessage {
+                recipient: Some(peer),
+                ..chat_message
+            },
+            Some(ProtocolVersion::Legacy) | None => ChatMessage {
+                sequence: None,
+                ..chat_message
+            },
+        }
+    }
+
+    /// Encodes a direct request or response for `peer`: in an envelope if it
+    /// speaks the versioned protocol, bare otherwise or until we know. Large
+    /// payloads are compressed for peers that can read them so.
+    fn encode_direct<T: Serialize + Kinds>(
+        &mut self,
+        peer: PeerId,
+        message: &T,
+    ) -> serde_json::Value {
+        match self.peer_protocols.get(&peer) {
+            Some(ProtocolVersion::V1) => {
+                let mut sealed = envelope::seal(message);
+                if self.compressing_peers.contains(&peer) {
+                    if let Some(compressed) =
+                        envelope::compress(&mut sealed, self.compress_above_bytes)
+                    {
+                        self.counters.payload_compressed(compressed);
+                    }
+                }
+                sealed
+            }
+            Some(ProtocolVersion::Legacy) | None => json!(message),
+        }
+    }
+
+    /// Text we are about to send, with emoji shortcodes expanded unless that
+    /// is turned off.
+    fn outgoing_text(&self, text: String) -> String {
+        if self.expand_emoji {
+            expand_shortcodes(&text)
+        } else {
+            text
+        }
+    }
+
+    /// Signs a message from `outgoing_message` once all its fields are set.
+    fn sign(&self, mut chat_message: ChatMessage) -> Result<ChatMessage, String> {
+        chat_message
+            .sign(&self.keypair)
+            .map_err(|e| format!("Failed to sign message: {}", e))?;
+        Ok(chat_message)
+    }
+
+    /// A copy of `message` for display, carrying the nickname we gave its
+    /// author if it is a contact, with blocked words masked.
+    fn named(&self, mut message: ChatMessage) -> ChatMessage {
+        if let Some(nickname) = self.contacts.nickname(&message.peer_id) {
+            message.nickname = Some(nickname.to_string());
+        }
+        self.local_chat_messages
+            .filter()
+            .filter_message(&mut message);
+        message
+    }
+
+    /// Stores a message and appends it to the history. Returns false if the
+    /// message was already known.
+    fn store_message(&mut self, chat_message: ChatMessage) -> bool {
+        let id = chat_message.id;
+        if !self.local_chat_messages.insert(chat_message) {
+            return false;
+        }
+
+        if let Some(stored) = self.local_chat_messages.get(&id) {
+            self.expiries.schedule(stored, unix_now());
+        }
+        self.persist(&id);
+        true
+    }
+
+    /// Appends the current state of a stored message to the history.
+    fn persist(&self, id: &MessageId) {
+        if let Some(chat_message) = self.local_chat_messages.get(id) {
+            if let Err(e) = history::append(&self.history_path, [chat_message]) {
+                println!("Failed to save message {}: {}", chat_message.short_id(), e);
+            }
+        }
+    }
+
+    /// Applies an edit or delete to a message that was evicted from memory by
+    /// appending the changed copy to the history.
+    fn apply_to_history(
+        &self,
+        author: PeerId,
+        target_id: MessageId,
+        change: Change,
+    ) -> io::Result<ChangeOutcome> {
+        let Some(mut chat_message) = history::find(&self.history_path, &target_id)? else {
+            return Ok(ChangeOutcome::Evicted);
+        };
+
+        let outcome = apply_to_message(&mut chat_message, author, change);
+        if outcome == ChangeOutcome::Applied {
+            history::append(&self.history_path, [&chat_message])?;
+            if let Some(line) = self.show(&chat_message) {
+                println!("{}", line);
+            }
+        }
+        Ok(outcome)
+    }
+
+    /// Resolves the prefix of a room message we are about to `action`, such
+    /// as react to, along with the topic of the room it was sent to.
+    fn room_message(
+        &self,
+        prefix: &str,
+        action: &str,
+    ) -> Result<(MessageId, gossipsub::IdentTopic), String> {
+        let id = self.local_chat_messages.resolve_prefix(prefix)?;
+        let chat_message = self
+            .local_chat_messages
+            .get(&id)
+            .ok_or_else(|| format!("No message matches {}", prefix))?;
+        // Whatever we publish would reach the whole room rather than the
+        // peer a direct message went to.
+        let Some(room) = &chat_message.room else {
+            return Err(format!("Can't {} a direct message", action));
+        };
+
+        Ok((id, gossipsub::IdentTopic::new(room)))
+    }
+
+    /// Resolves the prefix of a message we sent to a room that we are about
+    /// to `action`, such as edit, along with the room's topic.
+    fn own_room_message(
+        &self,
+        swarm: &Swarm<CustomBehaviour>,
+        prefix: &str,
+        action: &str,
+    ) -> Result<(MessageId, gossipsub::IdentTopic), String> {
+        let id = self.local_chat_messages.resolve_prefix(prefix)?;
+        let chat_message = self
+            .local_chat_messages
+            .get(&id)
+            .ok_or_else(|| format!("No message matches {}", prefix))?;
+        if chat_message.peer_id != *swarm.local_peer_id() {
+            return Err(format!("You can only {} your own messages", action));
+        }
+        if chat_message.deleted {
+            return Err("That message was deleted".to_string());
+        }
+        // Changes travel over gossip, so they would reach the whole room
+        // rather than the peer a direct message went to.
+        let Some(room) = &chat_message.room else {
+            return Err(format!("Can't {} a direct message", action));
+        };
+
+        Ok((id, gossipsub::IdentTopic::new(room)))
+    }
+
+    /// Prints a message from someone else as `line`, sending
+    /// `ChatEvent::Mentioned` if it mentions us and, unless do not disturb is
+    /// on, flagging it and ringing the bell, and shows a desktop notification
+    /// if they are turned on. Nothing is shown for
+    /// messages of a room muted with `/mute`; direct messages, without a
+    /// room, always are.
+    fn print_incoming(&mut self, line: &str, message: &ChatMessage) {
+        if !self.shows(message.room.as_deref(), message.mentions_me) {
+            return;
+        }
+
+        let direct = message.room.is_none();
+        if let Some(notifier) = &mut self.notifier {
+            if (direct || message.mentions_me) && !self.do_not_disturb.enabled() {
+                let sender = self.local_chat_messages.sender(message);
+                let text = self
+                    .local_chat_messages
+                    .filter()
+                    .filter_content(&message.message);
+                notifier.notify(message.peer_id, &sender, &text);
+            }
+        }
+
+        if message.mentions_me {
+            let _ = self.events.send(ChatEvent::Mentioned {
+                message: message.clone(),
+            });
+        }
+        if !message.mentions_me || self.do_not_disturb.enabled() {
+            self.print_batched(message.id, line.to_string());
+            return;
+        }
+
+        // A mention is shown at once, after whatever came before it.
+        self.flush_output();
+        self.mentions.received();
+        println!("(mention) {}", line);
+        if !self.interactive {
+            return;
+        }
+        if self.bell {
+            print!("\x07");
+        }
+        print!("{}", self.mentions.prompt());
+        let _ = std::io::Write::flush(&mut std::io::stdout());
+    }
+
+    fn mentions_us(&self, text: &str) -> bool {
+        self.nickname
+            .as_deref()
+            .is_some_and(|nickname| mentions(text, nickname))
+            || mentions_peer(text, &self.keypair.public().to_peer_id())
+    }
+
+    /// Whether messages of `room` are shown as they arrive, given whether
+    /// they mention us.
+    fn shows(&self, room: Option<&str>, mentioned: bool) -> bool {
+        room.is_none_or(|room| self.room_settings.get(room).shows(mentioned))
+    }
+
+    /// Sets which messages of `room` are shown and saves the setting.
+    fn set_notify(&mut self, room: String, notify: Notify) -> Result<Reply, String> {
+        if self.room_settings.set(&room, notify) {
+            self.room_settings
+                .save(&self.room_settings_path)
+                .map_err(|e| format!("Failed to save the room settings: {}", e))?;
+        }
+        Ok(Reply::RoomNotify { room, notify })
+    }
+
+    /// Prints the line showing message `id` with the next batch.
+    fn print_batched(&mut self, id: MessageId, line: String) {
+        let lines = self.output.push(id, line, Instant::now());
+        print_lines(&lines);
+    }
+
+    /// Prints every batched line now, ahead of other output.
+    fn flush_output(&mut self) {
+        let lines = self.output.take(Instant::now());
+        print_lines(&lines);
+    }
+
+    fn profile_reply(&self) -> Reply {
+        Reply::Profile {
+            status: self.public_profile.status.clone(),
+            avatar: Avatar::of(&self.public_profile),
+        }
+    }
+
+    /// Renders a message for the terminal, or `None` if it was deleted and
+    /// deleted messages are hidden.
+    fn show(&self, chat_message: &ChatMessage) -> Option<String> {
+        if chat_message.deleted && self.deleted_messages == DeletedMessages::Hide {
+            return None;
+        }
+        Some(self.local_chat_messages.format(chat_message))
+    }
+
+    fn stats(&self, swarm: &Swarm<CustomBehaviour>) -> Stats {
+        let gossipsub = &swarm.behaviour().gossipsub;
+        let mut listen_addrs: Vec<Multiaddr> = self.listen_addrs.iter().cloned().collect();
+        listen_addrs.sort_by_key(Multiaddr::to_string);
+        let external_addrs: Vec<Multiaddr> = swarm.external_addresses().cloned().collect();
+        let nat = if self.reached_from_outside {
+            NatStatus::Reachable
+        } else if external_addrs.is_empty() {
+            NatStatus::Unknown
+        } else {
+            NatStatus::Unconfirmed
+        };
+
+        Stats {
+            uptime_secs: self.counters.uptime(Instant::now()).as_secs(),
+            peer_id: *swarm.local_peer_id(),
+            listen_addrs,
+            external_addrs,
+            connected_peers: swarm.connected_peers().count(),
+            nat,
+            port_mapping: self.port_mapping,
+            denied_connections: self.counters.denied_connections(),
+            rooms: self.counters.rooms().clone(),
+            joined_rooms: gossipsub.topics().count(),
+            direct_messages: self.counters.direct(),
+            throttled_messages: self.counters.throttled_messages(),
+            shed_messages: self.counters.shed_messages(),
+            bandwidth: stats::bandwidth(&self.metrics),
+            pending_outbound_requests: self.counters.pending_requests(),
+            request_failures: self.counters.request_failures(),
+            compression: self.counters.compression(),
+            mesh_peers: gossipsub
+                .topics()
+                .map(|topic| (topic.to_string(), gossipsub.mesh_peers(topic).count()))
+                .collect(),
+            history: HistoryStats {
+                in_memory_messages: self.local_chat_messages.messages().len(),
+                memory_bytes: self.local_chat_messages.memory_usage(),
+                file_bytes: std::fs::metadata(&self.history_path)
+                    .map(|metadata| metadata.len())
+                    .unwrap_or(0),
+            },
+        }
+    }
+
+    /// The bytes moved with each peer since `/bw reset`.
+    fn peer_bandwidth(&self) -> HashMap<PeerId, Bandwidth> {
+        stats::peer_bandwidth(&self.metrics)
+            .into_iter()
+            .map(|(peer, bandwidth)| {
+                let baseline = self
+                    .bandwidth_baseline
+                    .get(&peer)
+                    .copied()
+                    .unwrap_or_default();
+                (peer, bandwidth.since(baseline))
+            })
+            .collect()
+    }
+
+    fn limits(&self, swarm: &Swarm<CustomBehaviour>) -> Limits {
+        let info = swarm.network_info();
+        let counters = info.connection_counters();
+        let limits = &self.connection_limits;
+        Limits {
+            established_incoming: counters.num_established_incoming(),
+            established_outgoing: counters.num_established_outgoing(),
+            max_established: limits.max_established,
+            max_established_incoming: limits.max_established - limits.reserved_outbound,
+            max_established_per_peer: limits.max_established_per_peer,
+            pending_incoming: counters.num_pending_incoming(),
+            pending_outgoing: counters.num_pending_outgoing(),
+            max_pending_incoming: limits.max_pending_incoming,
+            max_pending_outgoing: limits.max_pending_outgoing,
+            denied_connections: self.counters.denied_connections(),
+        }
+    }
+
+    /// Counts a connection refused over a limit, printing why at most once
+    /// every `DENIAL_REPORT_INTERVAL` so a busy node's output stays readable.
+    fn connection_denied(&mut self, cause: &connection_limits::Exceeded) {
+        self.counters.connection_denied();
+        self.unreported_denials += 1;
+
+        let now = Instant::now();
+        if self
+            .last_denial_report
+            .is_some_and(|last| now.duration_since(last) < DENIAL_REPORT_INTERVAL)
+        {
+            return;
+        }
+        println!(
+            "Refused {} connections over the limits ({}); see /limits",
+            self.unreported_denials, cause
+        );
+        self.unreported_denials = 0;
+        self.last_denial_report = Some(now);
+    }
+
+    fn block(&mut self, swarm: &mut Swarm<CustomBehaviour>, peer: PeerId) {
+        self.blocked.insert(peer);
+        self.retries.cancel_peer(&peer);
+        self.batcher.cancel_peer(&peer);
+        swarm.behaviour_mut().block_list.block_peer(peer);
+    }
+
+    fn unblock(&mut self, swarm: &mut Swarm<CustomBehaviour>, peer: PeerId) {
+        self.blocked.remove(&peer);
+        swarm.behaviour_mut().block_list.unblock_peer(peer);
+    }
+
+    /// Re-opens the listeners that closed, keeping any that fail for the next
+    /// attempt.
+    fn reopen_listeners(&mut self, swarm: &mut Swarm<CustomBehaviour>) {
+        for address in std::mem::take(&mut self.closed_listeners) {
+            match swarm.listen_on(address.clone()) {
+                Ok(listener_id) => {
+                    self.listeners.insert(listener_id, address);
+                }
+                Err(e) => {
+                    println!(
+                        "Failed to listen on {} again: {}",
+                        address,
+                        describe_transport_error(&address, &e)
+                    );
+                    self.closed_listeners.push(address);
+                }
+            }
+        }
+    }
+
+    fn share(&self, swarm: &Swarm<CustomBehaviour>, all: bool) -> Reply {
+        Reply::Share {
+            addrs: shareable_addresses(
+                *swarm.local_peer_id(),
+                swarm.external_addresses(),
+                &self.listen_addrs,
+            ),
+            all,
+        }
+    }
+
+    /// The public key of `peer`, from identify or its peer id.
+    fn public_key(&self, peer: &PeerId) -> Option<PublicKey> {
+        self.public_keys
+            .get(peer)
+            .cloned()
+            .or_else(|| embedded_key(peer))
+    }
+
+    /// The fingerprint of connected peer `peer`.
+    fn peer_fingerprint(
+        &self,
+        swarm: &Swarm<CustomBehaviour>,
+        peer: &PeerId,
+    ) -> Result<String, String> {
+        if !swarm.is_connected(peer) {
+            return Err(format!("{} is not connected", peer));
+        }
+        self.public_key(peer)
+            .map(|key| fingerprint(&key))
+            .ok_or_else(|| format!("{} has not identified itself yet", peer))
+    }
+
+    fn save_address_book(&self) {
+        if let Err(e) = self.address_book.save(&self.address_book_path) {
+            println!("Failed to save address book: {}", e);
+        }
+    }
+}
+
+/// The node as the handlers of `handler` see it, acting on the swarm.
+struct Live<'a> {
+    swarm: &'a mut Swarm<CustomBehaviour>,
+    state: &'a mut AppState,
+}
+
+impl<'a> Live<'a> {
+    fn new(swarm: &'a mut Swarm<CustomBehaviour>, state: &'a mut AppState) -> Self {
+        Live { swarm, state }
+    }
+}
+
+impl Node for Live<'_> {
+    fn local_peer_id(&self) -> PeerId {
+        *self.swarm.local_peer_id()
+    }
+
+    fn connected_peers(&self) -> usize {
+        self.swarm.network_info().num_peers()
+    }
+
+    fn explicit_below(&self) -> usize {
+        self.state.explicit_below
+    }
+
+    fn explicit_peers(&self) -> usize {
+        self.state.explicit_peers.len()
+    }
+
+    fn validates_gossip(&self) -> bool {
+        self.state.validates_gossip
+    }
+
+    fn act(&mut self, action: Action) {
+        let (swarm, state) = (&mut *self.swarm, &mut *self.state);
+        match action {
+            Action::AddAddress { peer, address } => {
+                swarm.add_peer_address(peer, address);
+            }
+            Action::AddExplicitPeer { peer } => add_explicit_peer(swarm, state, peer),
+            Action::SendRequest { peer, request } => {
+                send_direct(swarm, state, peer, *request);
+            }
+            Action::ReportViolation { peer, violation } => {
+                report_violation(swarm, state, peer, violation)
+            }
+            Action::RefreshProfile { peer } => {
+                if let Err(e) = state.peer_profiles.invalidate(&peer) {
+                    println!(
+                        "Failed to forget the profile of {}: {}",
+                        short_peer_id(&peer),
+                        e
+                    );
+                }
+                fetch_profile(swarm, state, peer);
+            }
+            Action::ReportValidation {
+                message_id,
+                propagation_source,
+                acceptance,
+            } => {
+                if let Err(e) = swarm
+                    .behaviour_mut()
+                    .gossipsub
+                    .report_message_validation_result(&message_id, &propagation_source, acceptance)
+                {
+                    println!("Failed to relay message {}: {}", message_id, e);
+                }
+            }
+        }
+    }
+
+    fn admit(&mut self, peer: PeerId, priority: Priority) -> bool {
+        within_rate_limit(self.swarm, self.state, peer) && within_load(self.state, priority)
+    }
+
+    fn messages(&mut self) -> &mut MessageStore {
+        &mut self.state.local_chat_messages
+    }
+
+    fn room_key(&self, room: &str) -> Option<[u8; 32]> {
+        self.state.room_keys.key(room)
+    }
+
+    fn nicknames(&mut self) -> &mut Nicknames {
+        &mut self.state.nicknames
+    }
+
+    fn address_book(&mut self) -> &mut AddressBook {
+        &mut self.state.address_book
+    }
+
+    fn save_address_book(&mut self) {
+        self.state.save_address_book();
+    }
+
+    fn replays(&mut self) -> &mut ReplayGuard {
+        &mut self.state.replays
+    }
+
+    fn members(&mut self) -> &mut RoomMembers {
+        &mut self.state.members
+    }
+
+    fn counters(&mut self) -> &mut Counters {
+        &mut self.state.counters
+    }
+
+    fn events(&self) -> &broadcast::Sender<ChatEvent> {
+        &self.state.events
+    }
+
+    fn direct_message(&mut self, peer: PeerId, text: String) -> Result<ChatMessage, String> {
+        let chat_message = self.state.outgoing_direct_message(self.swarm, peer, text);
+        self.state.sign(chat_message)
+    }
+
+    fn mentions_us(&self, text: &str) -> bool {
+        self.state.mentions_us(text)
+    }
+
+    fn store_message(&mut self, chat_message: ChatMessage) -> bool {
+        self.state.store_message(chat_message)
+    }
+
+    fn apply_to_history(
+        &mut self,
+        author: PeerId,
+        target_id: MessageId,
+        change: Change,
+    ) -> io::Result<ChangeOutcome> {
+        self.state.apply_to_history(author, target_id, change)
+    }
+
+    fn persist(&mut self, id: &MessageId) {
+        self.state.persist(id);
+    }
+
+    fn print_incoming(&mut self, line: &str, chat_message: &ChatMessage) {
+        self.state.print_incoming(line, chat_message);
+    }
+
+    fn print_changed(&mut self, id: &MessageId) {
+        let state = &mut *self.state;
+        if let Some(line) = state
+            .local_chat_messages
+            .get(id)
+            .filter(|chat_message| {
+                state.shows(chat_message.room.as_deref(), chat_message.mentions_me)
+            })
+            .and_then(|chat_message| state.show(chat_message))
+        {
+            state.print_batched(*id, line);
+        }
+    }
+}
+
+/// Writes `lines` to stdout in one go.
+fn print_lines(lines: &[String]) {
+    if lines.is_empty() {
+        return;
+    }
+    use std::io::Write;
+
+    let mut stdout = std::io::stdout().lock();
+    for line in lines {
+        let _ = writeln!(stdout, "{}", line);
+    }
+    let _ = stdout.flush();
+}
+
+/// Publishes to `topic`, in an envelope unless peers from before envelopes
+/// may be listening.
+fn publish(
+    swarm: &mut Swarm<CustomBehaviour>,
+    state: &AppState,
+    topic: &gossipsub::IdentTopic,
+    gossip_message: &GossipMessage,
+) -> Result<(), String> {
+    let data = match state.seal_gossip {
+        true => envelope::seal(gossip_message),
+        false => json!(gossip_message),
+    }
+    .to_string()
+    .into_bytes();
+    let data = match state.room_keys.key(topic.hash().as_str()) {
+        Some(key) => encrypt_for_room(&key, &data),
+        None => data,
+    };
+    swarm
+        .behaviour_mut()
+        .gossipsub
+        .publish(topic.clone(), data)
+        .map(|_| ())
+        .map_err(|e| format!("Failed to publish message: {}", e))
+}
+
+/// Sends a chat message directly to `peer`, tracking the request until it is
+/// answered.
+fn send_direct(
+    swarm: &mut Swarm<CustomBehaviour>,
+    state: &mut AppState,
+    peer: PeerId,
+    direct_request: DirectRequest,
+) -> OutboundRequestId {
+    let data = state.encode_direct(peer, &direct_request);
+    let request_id = swarm
+        .behaviour_mut()
+        .request_response
+        .send_request(&peer, Request { data });
+    state.counters.request_sent(request_id);
+    if direct_request.chat_message().is_some() {
+        state.counters.message_sent(None);
+    }
+    if let DirectRequest::Batch { messages } = &direct_request {
+        for _ in messages {
+            state.counters.message_sent(None);
+        }
+    }
+    request_id
+}
+
+/// Sends our direct message `chat_message` to `peer`, along with the others
+/// sent it within the batch window if the peer speaks the versioned
+/// protocol; legacy peers don't know batches.
+fn send_direct_message(
+    swarm: &mut Swarm<CustomBehaviour>,
+    state: &mut AppState,
+    peer: PeerId,
+    chat_message: ChatMessage,
+) {
+    let messages = match state.peer_protocols.get(&peer) {
+        Some(ProtocolVersion::V1) => state.batcher.push(peer, chat_message, Instant::now()),
+        Some(ProtocolVersion::Legacy) | None => vec![chat_message],
+    };
+    send_messages(swarm, state, peer, messages);
+}
+
+/// Sends our direct `messages` to `peer` in one request, as a batch if
+/// there are several, and tracks them until they are answered.
+fn send_messages(
+    swarm: &mut Swarm<CustomBehaviour>,
+    state: &mut AppState,
+    peer: PeerId,
+    mut messages: Vec<ChatMessage>,
+) {
+    let status = match swarm.is_connected(&peer) {
+        true => DeliveryStatus::Sent,
+        false => DeliveryStatus::Pending,
+    };
+    let ids: Vec<MessageId> = messages.iter().map(|message| message.id).collect();
+    for id in &ids {
+        state.local_chat_messages.set_status(*id, status);
+    }
+    let direct_request = match messages.len() {
+        0 => return,
+        1 => DirectRequest::Message(messages.remove(0)),
+        _ => DirectRequest::Batch { messages },
+    };
+    let request_id = send_direct(swarm, state, peer, direct_request);
+    state.direct_messages.insert(request_id, ids);
+}
+
+/// Publishes `announcement` in every room we are in. Peers from before
+/// envelopes would take it for a malformed message, so nothing is sent to
+/// them.
+fn announce(
+    swarm: &mut Swarm<CustomBehaviour>,
+    state: &mut AppState,
+    announcement: &GossipMessage,
+) {
+    if !state.seal_gossip {
+        return;
+    }
+    let topics: Vec<gossipsub::TopicHash> = swarm.behaviour().gossipsub.topics().cloned().collect();
+    for topic in topics {
+        // Rooms nobody else is in have no one to tell.
+        let _ = publish(
+            swarm,
+            state,
+            &gossipsub::IdentTopic::new(topic.as_str()),
+            announcement,
+        );
+    }
+}
+
+/// Subscribes to `room`, makes it the current room and lets others find us
+/// in it.
+fn join_room(
+    swarm: &mut Swarm<CustomBehaviour>,
+    state: &mut AppState,
+    room: String,
+) -> Result<Reply, String> {
+    let topic = gossipsub::IdentTopic::new(&room);
+    swarm
+        .behaviour_mut()
+        .gossipsub
+        .subscribe(&topic)
+        .map_err(|e| format!("Failed to join {}: {}", room, e))?;
+    state.members.join(&room);
+    if state.seal_gossip {
+        // Peers already in the room are told by our subscription too.
+        let _ = publish(swarm, state, &topic, &GossipMessage::Joined);
+    }
+    state.current_room = topic;
+    find_room_members(swarm, state, &room);
+    let servers: Vec<PeerId> = state
+        .rendezvous_servers
+        .keys()
+        .filter(|server| swarm.is_connected(server))
+        .copied()
+        .collect();
+    for server in servers {
+        meet_at(swarm, state, server, Some(&room));
+    }
+    Ok(Reply::Joined { room })
+}
+
+/// Saves our changed profile and tells the rooms, so peers fetch it again.
+fn update_profile(
+    swarm: &mut Swarm<CustomBehaviour>,
+    state: &mut AppState,
+) -> Result<Reply, String> {
+    state
+        .public_profile
+        .save(&state.public_profile_path)
+        .map_err(|e| format!("Failed to save your profile: {}", e))?;
+    announce(swarm, state, &GossipMessage::ProfileUpdated);
+    Ok(state.profile_reply())
+}
+
+/// Asks `peer` for its profile unless it is already being fetched or the
+/// peer is a connected legacy one, which doesn't have profiles. Returns
+/// whether a request was sent.
+fn fetch_profile(swarm: &mut Swarm<CustomBehaviour>, state: &mut AppState, peer: PeerId) -> bool {
+    if state.peer_protocols.get(&peer) == Some(&ProtocolVersion::Legacy)
+        || !swarm.is_connected(&peer)
+        || !state.fetching_profiles.insert(peer)
+    {
+        return false;
+    }
+    send_direct(swarm, state, peer, DirectRequest::Profile);
+    true
+}
+
+fn send_response(
+    swarm: &mut Swarm<CustomBehaviour>,
+    state: &mut AppState,
+    peer: PeerId,
+    channel: ResponseChannel<Response>,
+    response: DirectResponse,
+) {
+    let data = state.encode_direct(peer, &response);
+    if !swarm.behaviour_mut().respond(channel, Response { data }) {
+        println!(
+            "Dropped a response to {}: it disconnected or stopped waiting",
+            short_peer_id(&peer)
+        );
+    }
+}
+
+/// The connected peers that may hold direct messages for `target`: those
+/// that speak the versioned protocol.
+fn forwarders(swarm: &Swarm<CustomBehaviour>, state: &AppState, target: PeerId) -> Vec<PeerId> {
+    swarm
+        .connected_peers()
+        .filter(|peer| {
+            **peer != target && state.peer_protocols.get(peer) == Some(&ProtocolVersion::V1)
+        })
+        .copied()
+        .collect()
+}
+
+/// Hands a direct message for `target`, who isn't connected, to every
+/// connected peer that speaks the versioned protocol. Those started with
+/// `--forward` hold it until `target` connects. Returns how many peers it
+/// was handed to.
+fn store_with_forwarders(
+    swarm: &mut Swarm<CustomBehaviour>,
+    state: &mut AppState,
+    target: PeerId,
+    chat_message: &ChatMessage,
+) -> usize {
+    let forwarders = forwarders(swarm, state, target);
+    let expires_at = unix_now().saturating_add(state.forward_ttl);
+    for forwarder in &forwarders {
+        send_direct(
+            swarm,
+            state,
+            *forwarder,
+            DirectRequest::StoreForward {
+                target,
+                message: chat_message.clone(),
+                expires_at,
+            },
+        );
+    }
+    forwarders.len()
+}
+
+/// Answers a direct message from `peer` with the away message while do not
+/// disturb is on, once an hour at most. Peers on the legacy protocol don't
+/// know system messages and get none.
+fn send_auto_reply(swarm: &mut Swarm<CustomBehaviour>, state: &mut AppState, peer: PeerId) {
+    if state.peer_protocols.get(&peer) != Some(&ProtocolVersion::V1) {
+        return;
+    }
+    let Some(text) = state.do_not_disturb.auto_reply(peer, Instant::now()) else {
+        return;
+    };
+    let chat_message = state.outgoing_direct_message(swarm, peer, text);
+    match state.sign(chat_message) {
+        Ok(chat_message) => {
+            send_direct(
+                swarm,
+                state,
+                peer,
+                DirectRequest::System(chat_message.clone()),
+            );
+            let id = chat_message.id;
+            state.store_message(chat_message);
+            state
+                .local_chat_messages
+                .set_status(id, DeliveryStatus::Sent);
+        }
+        Err(e) => println!("Failed to sign the auto-reply: {}", e),
+    }
+}
+
+/// Removes the messages that expired since the last tick from the history,
+/// all in one rewrite. Any that can't be are left to the compaction and
+/// scheduling on the next start.
+fn purge_expired(state: &mut AppState) {
+    if state.expired.is_empty() {
+        return;
+    }
+    let expired = std::mem::take(&mut state.expired);
+    if let Err(e) = history::remove(&state.history_path, &expired) {
+        println!("Failed to remove expired messages from the history: {}", e);
+    }
+}
+
+/// Reports the peers that stayed disconnected for `DEPARTURE_GRACE` as gone
+/// and stops counting them as room members. The direct messages they never
+/// acknowledged are handed to forwarders, when any are connected, instead
+/// of being retried.
+fn peers_departed(swarm: &mut Swarm<CustomBehaviour>, state: &mut AppState) {
+    let departed = state.departures.due(Instant::now());
+    for (peer, reason) in &departed {
+        println!("Peer {} disconnected ({})", short_peer_id(peer), reason);
+        let _ = state.events.send(ChatEvent::PeerOffline { peer: *peer });
+        state.room_providers.departed(peer);
+        state.members.disconnected(peer);
+        state.address_book.seen(peer, unix_now());
+
+        if forwarders(swarm, state, *peer).is_empty() {
+            continue;
+        }
+        let unacknowledged = state.retries.take_peer(peer);
+        for chat_message in &unacknowledged {
+            store_with_forwarders(swarm, state, *peer, chat_message);
+            state
+                .local_chat_messages
+                .set_status(chat_message.id, DeliveryStatus::Sent);
+        }
+        if !unacknowledged.is_empty() {
+            println!(
+                "Handed {} unacknowledged messages for {} to forwarders",
+                unacknowledged.len(),
+                short_peer_id(peer)
+            );
+        }
+    }
+    if !departed.is_empty() {
+        state.save_address_book();
+    }
+}
+
+/// Sends the direct messages whose retry is due, dropping those deleted
+/// since.
+fn retry_direct_messages(swarm: &mut Swarm<CustomBehaviour>, state: &mut AppState) {
+    for (peer, chat_message) in state.retries.due(Instant::now()) {
+        let id = chat_message.id;
+        if state
+            .local_chat_messages
+            .get(&id)
+            .is_some_and(|stored| stored.deleted)
+        {
+            state.retries.cancel(&id);
+            continue;
+        }
+        send_direct_message(swarm, state, peer, chat_message);
+    }
+}
+
+/// Deals with a failed attempt at the direct message `id`: schedules the
+/// next one, or reports the message as failed once it had them all and
+/// leaves it to forwarders if the recipient is gone.
+fn direct_message_failed(
+    swarm: &mut Swarm<CustomBehaviour>,
+    state: &mut AppState,
+    id: MessageId,
+    error: &impl fmt::Display,
+) {
+    let short_id = &id.simple().to_string()[..8];
+    match state.retries.failed(&id, Instant::now()) {
+        None => {}
+        Some(Failed::Retrying { attempt, delay }) => {
+            state
+                .local_chat_messages
+                .set_status(id, DeliveryStatus::Pending);
+            println!(
+                "Message [{}] failed ({}); trying again in {}s (attempt {} of {})",
+                short_id,
+                error,
+                delay.as_secs(),
+                attempt,
+                state.retries.max_attempts()
+            )
+        }
+        Some(Failed::GaveUp {
+            peer,
+            message,
+            attempts,
+        }) => {
+            println!(
+                "Message [{}] to {} failed after {} attempts: {}",
+                short_id,
+                short_peer_id(&peer),
+                attempts,
+                error
+            );
+            let mut status = DeliveryStatus::Failed;
+            if !swarm.is_connected(&peer) {
+                let forwarders = store_with_forwarders(swarm, state, peer, &message);
+                if forwarders > 0 {
+                    println!("Handed [{}] to {} forwarders", short_id, forwarders);
+                    status = DeliveryStatus::Sent;
+                }
+            }
+            state.local_chat_messages.set_status(id, status);
+            let _ = state
+                .events
+                .send(ChatEvent::DeliveryFailed { peer, id, attempts });
+        }
+    }
+}
+
+/// Whether `chat_message` is one `peer` wrote for us and checks out.
+fn verify_direct(
+    swarm: &mut Swarm<CustomBehaviour>,
+    state: &mut AppState,
+    peer: PeerId,
+    chat_message: &ChatMessage,
+) -> bool {
+    let local_peer_id = *swarm.local_peer_id();
+    let mut node = Live::new(swarm, state);
+    handler::check_direct(&mut node, peer, chat_message, local_peer_id, false)
+        && handler::verify(&mut node, peer, chat_message)
+}
+
+/// Takes in the direct message `chat_message` from `peer`, unless the
+/// filter drops it. Either way it is to be acknowledged, or the sender would
+/// keep retrying.
+fn receive_direct_message(
+    swarm: &mut Swarm<CustomBehaviour>,
+    state: &mut AppState,
+    peer: PeerId,
+    mut chat_message: ChatMessage,
+) {
+    if state.local_chat_messages.filter().drops(&chat_message) {
+        return;
+    }
+    chat_message.mentions_me = state.mentions_us(&chat_message.message);
+    let line = format!(
+        "(direct) {}",
+        state.local_chat_messages.format(&chat_message)
+    );
+    // Also delivered by a forwarder, or sent again before our ack arrived.
+    if !state.store_message(chat_message.clone()) {
+        return;
+    }
+    let _ = state.events.send(ChatEvent::MessageReceived {
+        message: chat_message.clone(),
+    });
+    state.print_incoming(&line, &chat_message);
+    send_auto_reply(swarm, state, peer);
+}
+
+/// Delivers the messages held for `target`, who just identified itself.
+fn deliver_stored(swarm: &mut Swarm<CustomBehaviour>, state: &mut AppState, target: PeerId) {
+    let Some(forward_store) = &mut state.forward_store else {
+        return;
+    };
+    let stored_messages = forward_store.take(&target, unix_now());
+    if stored_messages.is_empty() {
+        return;
+    }
+
+    println!(
+        "Delivering {} held messages to {}",
+        stored_messages.len(),
+        short_peer_id(&target)
+    );
+    for stored in stored_messages {
+        let request_id = send_direct(
+            swarm,
+            state,
+            target,
+            DirectRequest::Forwarded(stored.message.clone()),
+        );
+        state.forwarding.insert(request_id, (target, stored));
+    }
+}
+
+/// Counts a violation by `peer` and blocks them once they cross the threshold.
+fn report_violation(
+    swarm: &mut Swarm<CustomBehaviour>,
+    state: &mut AppState,
+    peer: PeerId,
+    violation: Violation,
+) {
+    if let Some(ban) = state.violations.record(peer, violation, Instant::now()) {
+        println!(
+            "Auto-banned {} after {} violations (last: {})",
+            short_peer_id(&ban.peer),
+            ban.violations,
+            ban.last
+        );
+        state.block(swarm, ban.peer);
+    }
+}
+
+/// Whether a message from `peer` is within its rate limit. Otherwise it is
+/// counted and should be dropped, and the peer is blocked for a while if it
+/// keeps flooding us.
+fn within_rate_limit(
+    swarm: &mut Swarm<CustomBehaviour>,
+    state: &mut AppState,
+    peer: PeerId,
+) -> bool {
+    match state.rate_limiter.check(peer, Instant::now()) {
+        Decision::Allow => true,
+        Decision::Drop { first } => {
+            state.counters.message_throttled();
+            if first {
+                println!(
+                    "Dropping messages from {}: over the rate limit",
+                    short_peer_id(&peer)
+                );
+                let _ = state.events.send(ChatEvent::PeerThrottled { peer });
+            }
+            false
+        }
+        Decision::Block => {
+            state.counters.message_throttled();
+            println!("Blocked {} for flooding", short_peer_id(&peer));
+            state.block(swarm, peer);
+            false
+        }
+    }
+}
+
+/// Whether a message of `priority` should be handled given how many arrive
+/// from all peers together, warning when the node starts shedding load.
+fn within_load(state: &mut AppState, priority: Priority) -> bool {
+    let was_shedding = state.flood_guard.shedding();
+    let now = Instant::now();
+    let admission = state.flood_guard.admit(priority, now);
+    if state.flood_guard.shedding() && !was_shedding {
+        println!(
+            "Warning: {:.0} messages/s arriving; dropping broadcasts until it calms down",
+            state.flood_guard.rate(now)
+        );
+    } else if was_shedding && !state.flood_guard.shedding() {
+        println!("Message rate is back to normal; handling broadcasts again");
+    }
+    match admission {
+        Admission::Accept => true,
+        Admission::Shed => {
+            state.counters.message_shed();
+            false
+        }
+    }
+}
+
+/// Adds the peers `peer` shared to the address book, and queues dials to
+/// them while we have few connections.
+fn learn_peers(
+    swarm: &mut Swarm<CustomBehaviour>,
+    state: &mut AppState,
+    peer: PeerId,
+    peers: Vec<PeerRecord>,
+) {
+    let peers = peer_exchange::accept(peers, swarm.local_peer_id(), &state.blocked);
+    if peers.is_empty() {
+        return;
+    }
+    println!("{} shared {} peers", short_peer_id(&peer), peers.len());
+
+    let now = unix_now();
+    for record in &peers {
+        state.address_book.merge(record.peer_id, &record.entry(now));
+    }
+    state.save_address_book();
+
+    let mut free = state
+        .exchange_target_peers
+        .saturating_sub(swarm.connected_peers().count() + state.exchange_dials.len());
+    for record in peers {
+        if free == 0 {
+            break;
+        }
+        if !swarm.is_connected(&record.peer_id) {
+            state.exchange_dials.schedule(record, Instant::now());
+            free -= 1;
+        }
+    }
+}
+
+/// Dials the peers learned through exchange whose delay has passed.
+fn dial_exchanged_peers(swarm: &mut Swarm<CustomBehaviour>, state: &mut AppState) {
+    for record in state.exchange_dials.due(Instant::now()) {
+        if state.blocked.contains(&record.peer_id) {
+            continue;
+        }
+        let opts = DialOpts::peer_id(record.peer_id)
+            .addresses(record.addresses)
+            .condition(PeerCondition::DisconnectedAndNotDialing)
+            .build();
+        if let Err(e) = swarm.dial(opts) {
+            println!(
+                "Failed to dial {}: {}",
+                short_peer_id(&record.peer_id),
+                describe_dial_error(&e)
+            );
+        }
+    }
+}
+
+/// Announces us in the DHT as a provider for `room` and looks up the other
+/// providers, who are met in `handle_kad_event`.
+fn find_room_members(swarm: &mut Swarm<CustomBehaviour>, state: &mut AppState, room: &str) {
+    let key = dht::room_key(room);
+    let kademlia = &mut swarm.behaviour_mut().kademlia;
+    if let Err(e) = kademlia.start_providing(key.clone()) {
+        println!("Failed to announce {} in the DHT: {}", room, e);
+    }
+    // Without peers the record stays with us until the next republish.
+    if kademlia.kbuckets().all(|bucket| bucket.num_entries() == 0) {
+        state.room_providers.unannounced(room.to_string());
+    }
+    look_up_room(swarm, state, room);
+}
+
+fn look_up_room(swarm: &mut Swarm<CustomBehaviour>, state: &mut AppState, room: &str) {
+    let query = swarm
+        .behaviour_mut()
+        .kademlia
+        .get_providers(dht::room_key(room));
+    state.room_providers.looking_up(query, room.to_string());
+}
+
+fn handle_kad_event(swarm: &mut Swarm<CustomBehaviour>, state: &mut AppState, event: kad::Event) {
+    match event {
+        kad::Event::RoutingUpdated {
+            is_new_peer: true, ..
+        } => {
+            for room in state.room_providers.take_unannounced() {
+                if let Err(e) = swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .start_providing(dht::room_key(&room))
+                {
+                    println!("Failed to announce {} in the DHT: {}", room, e);
+                }
+                look_up_room(swarm, state, &room);
+            }
+        }
+        kad::Event::OutboundQueryProgressed {
+            id,
+            result: kad::QueryResult::GetProviders(result),
+            step,
+            ..
+        } => {
+            if let Ok(kad::GetProvidersOk::FoundProviders { providers, .. }) = result {
+                let local_peer_id = *swarm.local_peer_id();
+                let providers = providers
+                    .into_iter()
+                    .filter(|provider| *provider != local_peer_id);
+                if let Some((room, new)) = state.room_providers.found(id, providers) {
+                    for peer in new {
+                        if !swarm.is_connected(&peer) {
+                            println!("Found {} in {} through the DHT", short_peer_id(&peer), room);
+                            // Kademlia knows the addresses the lookup returned.
+                            if let Err(e) = swarm.dial(peer) {
+                                println!(
+                                    "Failed to dial {}: {}",
+                                    short_peer_id(&peer),
+                                    describe_dial_error(&e)
+                                );
+                            }
+                        }
+                        // Once connected, gossipsub meshes with it like any
+                        // other member; providers aren't made explicit, as
+                        // there is no telling how many a room has.
+                    }
+                }
+            }
+            if step.last {
+                state.room_providers.finished(id);
+            }
+        }
+        kad::Event::OutboundQueryProgressed {
+            result: kad::QueryResult::StartProviding(Err(e)),
+            ..
+        } => {
+            println!("Failed to announce a room in the DHT: {}", e);
+        }
+        _ => {}
+    }
+}
+
+/// Registers again with every connected rendezvous server, for registrations
+/// that may have been waiting on an external address.
+fn register_again(swarm: &mut Swarm<CustomBehaviour>, state: &mut AppState) {
+    let servers: Vec<PeerId> = state
+        .rendezvous_servers
+        .keys()
+        .filter(|server| swarm.is_connected(server))
+        .copied()
+        .collect();
+    for server in servers {
+        meet_at(swarm, state, server, None);
+    }
+}
+
+/// Reports how mapping our ports on the gateway went. The behaviour has the
+/// swarm confirm each mapped address as external by itself.
+fn handle_upnp_event(swarm: &mut Swarm<CustomBehaviour>, state: &mut AppState, event: upnp::Event) {
+    match event {
+        upnp::Event::NewExternalAddr(address) => {
+            println!("Gateway forwards {} to us over UPnP", address);
+            state.mapped_addrs.insert(address);
+            state.port_mapping = PortMapping::Mapped;
+            register_again(swarm, state);
+        }
+        upnp::Event::ExpiredExternalAddr(address) => {
+            println!("Gateway stopped forwarding {} to us", address);
+            state.mapped_addrs.remove(&address);
+            if state.mapped_addrs.is_empty() {
+                state.port_mapping = PortMapping::Expired;
+            }
+        }
+        upnp::Event::GatewayNotFound => {
+            println!("No UPnP gateway found; peers outside the network may not reach us");
+            state.port_mapping = PortMapping::NoGateway;
+        }
+        upnp::Event::NonRoutableGateway => {
+            println!("The UPnP gateway is behind another NAT; not mapping ports on it");
+            state.port_mapping = PortMapping::NonRoutableGateway;
+        }
+    }
+}
+
+/// Registers with the rendezvous server `server` in the namespace of `room`,
+/// or of every room we are in, and asks who else is there.
+fn meet_at(
+    swarm: &mut Swarm<CustomBehaviour>,
+    state: &mut AppState,
+    server: PeerId,
+    room: Option<&str>,
+) {
+    let rooms: Vec<String> = match room {
+        Some(room) => vec![room.to_string()],
+        None => swarm
+            .behaviour()
+            .gossipsub
+            .topics()
+            .map(|topic| topic.as_str().to_string())
+            .collect(),
+    };
+    for room in rooms {
+        let namespace = match room_namespace(&room) {
+            Ok(namespace) => namespace,
+            Err(e) => {
+                println!("Not using rendezvous for {}: {}", room, e);
+                continue;
+            }
+        };
+        register(swarm, state, server, namespace.clone());
+        let cookie = state.registrations.cookie(server, &namespace);
+        swarm
+            .behaviour_mut()
+            .rendezvous
+            .discover(Some(namespace), cookie, None, server);
+    }
+}
+
+fn register(
+    swarm: &mut Swarm<CustomBehaviour>,
+    state: &mut AppState,
+    server: PeerId,
+    namespace: Namespace,
+) {
+    let now = Instant::now();
+    match swarm.behaviour_mut().rendezvous.register(
+        namespace.clone(),
+        server,
+        Some(state.rendezvous_ttl),
+    ) {
+        Ok(()) => state.registrations.sent(server, namespace, now),
+        Err(rendezvous::client::RegisterError::NoExternalAddresses) => {
+            if !state.reported_no_external_addr && !state.confirm_observed_addrs {
+                state.reported_no_external_addr = true;
+                println!(
+                    "Not registering with rendezvous servers until we have an \
+                     external address; pass --external-address"
+                );
+            }
+            state.registrations.failed(server, namespace, now);
+        }
+        Err(e) => {
+            println!("Failed to register at {}: {}", short_peer_id(&server), e);
+            state.registrations.failed(server, namespace, now);
+        }
+    }
+}
+
+/// Runs on the rendezvous timer: reconnects to servers we lost, renews
+/// registrations that are due, and discovers newcomers.
+fn refresh_rendezvous(swarm: &mut Swarm<CustomBehaviour>, state: &mut AppState) {
+    for (server, namespace) in state.registrations.due(Instant::now()) {
+        if swarm.is_connected(&server) {
+            register(swarm, state, server, namespace);
+        }
+    }
+
+    let servers: Vec<(PeerId, Multiaddr)> = state
+        .rendezvous_servers
+        .iter()
+        .map(|(server, address)| (*server, address.clone()))
+        .collect();
+    for (server, address) in servers {
+        if !swarm.is_connected(&server) {
+            if let Err(e) = swarm.dial(address.clone()) {
+                println!(
+                    "Failed to dial rendezvous server {}: {}",
+                    address,
+                    describe_dial_error(&e)
+                );
+            }
+            continue;
+        }
+
+        let namespaces: Vec<Namespace> = swarm
+            .behaviour()
+            .gossipsub
+            .topics()
+            .filter_map(|topic| room_namespace(topic.as_str()).ok())
+            .collect();
+        for namespace in namespaces {
+            let cookie = state.registrations.cookie(server, &namespace);
+            swarm
+                .behaviour_mut()
+                .rendezvous
+                .discover(Some(namespace), cookie, None, server);
+        }
+    }
+}
+
+fn handle_rendezvous_event(
+    swarm: &mut Swarm<CustomBehaviour>,
+    state: &mut AppState,
+    event: rendezvous::client::Event,
+) {
+    match event {
+        rendezvous::client::Event::Registered {
+            rendezvous_node,
+            ttl,
+            namespace,
+        } => {
+            let now = Instant::now();
+            if state
+                .registrations
+                .registered(rendezvous_node, namespace.clone(), ttl, now)
+            {
+                println!(
+                    "Registered in {} at {} for {}s",
+                    namespace,
+                    short_peer_id(&rendezvous_node),
+                    ttl
+                );
+            }
+        }
+        rendezvous::client::Event::RegisterFailed {
+            rendezvous_node,
+            namespace,
+            error,
+        } => {
+            println!(
+                "Failed to register in {} at {}: {:?}",
+                namespace,
+                short_peer_id(&rendezvous_node),
+                error
+            );
+            state
+                .registrations
+                .failed(rendezvous_node, namespace, Instant::now());
+        }
+        rendezvous::client::Event::Discovered {
+            rendezvous_node,
+            registrations,
+            cookie,
+        } => {
+            state.registrations.set_cookie(rendezvous_node, cookie);
+            let local_peer_id = *swarm.local_peer_id();
+            let mut changed = false;
+            for registration in registrations {
+                let peer = registration.record.peer_id();
+                if peer == local_peer_id {
+                    continue;
+                }
+
+                let addresses = registration.record.addresses().to_vec();
+                let now = unix_now();
+                for address in &addresses {
+                    swarm.add_peer_address(peer, address.clone());
+                    changed |= state.address_book.record(peer, address.clone(), now);
+                }
+                if !swarm.is_connected(&peer) {
+                    println!(
+                        "Found {} in {} through {}",
+                        short_peer_id(&peer),
+                        registration.namespace,
+                        short_peer_id(&rendezvous_node)
+                    );
+                    let opts = DialOpts::peer_id(peer).addresses(addresses).build();
+                    if let Err(e) = swarm.dial(opts) {
+                        println!(
+                            "Failed to dial {}: {}",
+                            short_peer_id(&peer),
+                            describe_dial_error(&e)
+                        );
+                    }
+                }
+                add_explicit_peer(swarm, state, peer);
+            }
+            if changed {
+                state.save_address_book();
+            }
+        }
+        rendezvous::client::Event::DiscoverFailed {
+            rendezvous_node,
+            namespace,
+            error,
+        } => {
+            let namespace = namespace.map_or("all namespaces".to_string(), |ns| ns.to_string());
+            println!(
+                "Failed to discover peers in {} at {}: {:?}",
+                namespace,
+                short_peer_id(&rendezvous_node),
+                error
+            );
+        }
+        rendezvous::client::Event::Expired { peer } => remove_explicit_peer(swarm, state, peer),
+    }
+}
+
+/// Has gossipsub keep a connection to `peer` and send it every message,
+/// whatever the mesh looks like.
+fn add_explicit_peer(swarm: &mut Swarm<CustomBehaviour>, state: &mut AppState, peer: PeerId) {
+    swarm.behaviour_mut().gossipsub.add_explicit_peer(&peer);
+    state.explicit_peers.insert(peer);
+}
+
+/// Stops sending `peer` every message and redialing it, unless it is pinned.
+fn remove_explicit_peer(swarm: &mut Swarm<CustomBehaviour>, state: &mut AppState, peer: PeerId) {
+    if !state.pinned_peers.contains_key(&peer) && state.explicit_peers.remove(&peer) {
+        swarm.behaviour_mut().gossipsub.remove_explicit_peer(&peer);
+    }
+}
+
+/// Dials a pinned peer unless it is connected or being dialed already.
+fn dial_pinned(swarm: &mut Swarm<CustomBehaviour>, peer: PeerId, address: &Multiaddr) {
+    let opts = DialOpts::peer_id(peer)
+        .condition(PeerCondition::DisconnectedAndNotDialing)
+        .addresses(vec![address.clone()])
+        .build();
+    if let Err(e) = swarm.dial(opts) {
+        if !matches!(e, DialError::DialPeerConditionFalse(_)) {
+            println!(
+                "Failed to dial pinned peer {}: {}",
+                short_peer_id(&peer),
+                describe_dial_error(&e)
+            );
+        }
+    }
+}
+
+/// Runs on the pinned peer timer: dials those that dropped and didn't come
+/// back.
+fn redial_pinned(swarm: &mut Swarm<CustomBehaviour>, state: &AppState) {
+    for (peer, address) in &state.pinned_peers {
+        if !swarm.is_connected(peer) {
+            dial_pinned(swarm, *peer, address);
+        }
+    }
+}
+
+/// Runs a command on behalf of stdin or the control socket.
+fn execute_command(
+    swarm: &mut Swarm<CustomBehaviour>,
+    state: &mut AppState,
+    command: Command,
+) -> Result<Reply, String> {
+    match command {
+        Command::Send {
+            text,
+            room,
+            expires_in,
+        } => {
+            let topic = match room {
+                Some(room) => gossipsub::IdentTopic::new(room),
+                None => state.current_room.clone(),
+            };
+            // Commands from the control socket skip the parser's check.
+            if expires_in.is_some_and(|seconds| seconds > MAX_EXPIRES_IN_SECS) {
+                return Err(format!(
+                    "A message can expire in at most {} seconds",
+                    MAX_EXPIRES_IN_SECS
+                ));
+            }
+            let chat_message = ChatMessage {
+                room: Some(topic.to_string()),
+                ttl_secs: expires_in,
+                ..state.outgoing_message(swarm, text)
+            };
+            let chat_message = state.sign(chat_message)?;
+            let id = chat_message.id;
+
+            publish(
+                swarm,
+                state,
+                &topic,
+                &GossipMessage::Chat(Box::new(chat_message.clone())),
+            )?;
+            state.counters.message_sent(chat_message.room.as_deref());
+            state.store_message(chat_message);
+            state
+                .local_chat_messages
+                .set_status(id, DeliveryStatus::Sent);
+            Ok(Reply::Sent { id })
+        }
+        Command::Reply { message_id, text } => {
+            // Replies go where the message they answer was sent.
+            let (parent_id, topic) = state.room_message(&message_id, "reply to")?;
+            let chat_message = ChatMessage {
+                room: Some(topic.to_string()),
+                reply_to: Some(parent_id),
+                ..state.outgoing_message(swarm, text)
+            };
+            let chat_message = state.sign(chat_message)?;
+            let id = chat_message.id;
+
+            publish(
+                swarm,
+                state,
+                &topic,
+                &GossipMessage::Chat(Box::new(chat_message.clone())),
+            )?;
+            state.counters.message_sent(chat_message.room.as_deref());
+            state.store_message(chat_message);
+            state
+                .local_chat_messages
+                .set_status(id, DeliveryStatus::Sent);
+            Ok(Reply::Sent { id })
+        }
+        Command::Thread { message_id } => {
+            let id = state.local_chat_messages.resolve_prefix(&message_id)?;
+            let messages = state
+                .local_chat_messages
+                .thread(&id)
+                .into_iter()
+                .filter_map(|(depth, chat_message)| {
+                    let indent = "  ".repeat(depth);
+                    state.show(chat_message).map(|shown| {
+                        shown
+                            .lines()
+                            .map(|line| format!("{}{}", indent, line))
+                            .collect::<Vec<_>>()
+                            .join("\n")
+                    })
+                })
+                .collect();
+            Ok(Reply::Thread { messages })
+        }
+        Command::React { message_id, emoji } => {
+            let (target_id, topic) = state.room_message(&message_id, "react to")?;
+
+            let reaction = GossipMessage::Reaction {
+                target_id,
+                emoji: emoji.clone(),
+            };
+            publish(swarm, state, &topic, &reaction)?;
+
+            let local_peer_id = *swarm.local_peer_id();
+            state
+                .local_chat_messages
+                .apply(local_peer_id, target_id, Change::React(emoji));
+            let message = state
+                .local_chat_messages
+                .get(&target_id)
+                .map(|chat_message| state.local_chat_messages.format(chat_message))
+                .unwrap_or_default();
+            Ok(Reply::Updated { message })
+        }
+        Command::Edit { message_id, text } => {
+            let (target_id, topic) = state.own_room_message(swarm, &message_id, "edit")?;
+            let local_peer_id = *swarm.local_peer_id();
+
+            let new_text = state.outgoing_text(text);
+            publish(
+                swarm,
+                state,
+                &topic,
+                &GossipMessage::Edit {
+                    target_id,
+                    new_text: new_text.clone(),
+                },
+            )?;
+            state
+                .local_chat_messages
+                .apply(local_peer_id, target_id, Change::Edit(new_text));
+            state.persist(&target_id);
+
+            let message = state
+                .local_chat_messages
+                .get(&target_id)
+                .map(|chat_message| state.local_chat_messages.format(chat_message))
+                .unwrap_or_default();
+            Ok(Reply::Updated { message })
+        }
+        Command::Delete { message_id } => {
+            let (target_id, topic) = state.own_room_message(swarm, &message_id, "delete")?;
+            let local_peer_id = *swarm.local_peer_id();
+
+            publish(swarm, state, &topic, &GossipMessage::Delete { target_id })?;
+            state
+                .local_chat_messages
+                .apply(local_peer_id, target_id, Change::Delete);
+            state.persist(&target_id);
+            Ok(Reply::Deleted { id: target_id })
+        }
+        Command::History {
+            room,
+            peer,
+            since,
+            limit,
+            page,
+        } => {
+            if limit == 0 {
+                return Err("Can't show pages of 0 messages".to_string());
+            }
+            let filter = HistoryFilter {
+                room: room.unwrap_or_else(|| state.current_room.to_string()),
+                peer,
+                since,
+            };
+            let in_memory: Vec<ChatMessage> = state
+                .local_chat_messages
+                .messages()
+                .iter()
+                .filter(|message| filter.matches(message) && state.show(message).is_some())
+                .cloned()
+                .collect();
+            // Older messages are only in the history file.
+            let evicted_to = state
+                .local_chat_messages
+                .has_evicted()
+                .then_some(state.history_path.as_path());
+            let mut found = history::room_page(evicted_to, &filter, in_memory, page, limit)
+                .map_err(|e| format!("Failed to read the history: {}", e))?;
+            let room = filter.room;
+            let joined = swarm
+                .behaviour()
+                .gossipsub
+                .topics()
+                .any(|topic| topic.as_str() == room);
+            if found.total == 0 && !joined {
+                return Err(format!(
+                    "Unknown room #{}: not joined and nothing in its history",
+                    room
+                ));
+            }
+            if found.messages.is_empty() && found.total > 0 {
+                return Err(format!(
+                    "No page {} in #{}: its {} messages fill {} pages of {}",
+                    page + 1,
+                    room,
+                    found.total,
+                    found.total.div_ceil(limit),
+                    limit
+                ));
+            }
+
+            let now = unix_now();
+            found
+                .messages
+                .retain(|message| !message.is_expired(now) && state.show(message).is_some());
+            let statuses = found
+                .messages
+                .iter()
+                .filter_map(|message| {
+                    let status = state.local_chat_messages.status(&message.id)?;
+                    Some((message.id, status))
+                })
+                .collect();
+            Ok(Reply::History {
+                room,
+                statuses,
+                messages: found
+                    .messages
+                    .into_iter()
+                    .map(|message| state.named(message))
+                    .collect(),
+                first: found.first,
+                total: found.total,
+            })
+        }
+        Command::Mentions => {
+            let mut messages: Vec<ChatMessage> = state
+                .local_chat_messages
+                .messages()
+                .iter()
+                .rev()
+                .filter(|message| message.mentions_me && state.show(message).is_some())
+                .take(RECENT_MENTIONS)
+                .map(|message| state.named(message.clone()))
+                .collect();
+            messages.reverse();
+            Ok(Reply::Mentions { messages })
+        }
+        Command::Clear {
+            room,
+            disk,
+            confirmed,
+        } => {
+            if !confirmed {
+                let messages = state
+                    .local_chat_messages
+                    .messages()
+                    .iter()
+                    .filter(|message| {
+                        room.as_ref()
+                            .is_none_or(|room| message.room.as_ref() == Some(room))
+                    })
+                    .count();
+                return Ok(Reply::ConfirmClear {
+                    room,
+                    messages,
+                    disk,
+                });
+            }
+            let messages = state.local_chat_messages.clear(room.as_deref());
+            let removed_from_disk = disk
+                .then(|| history::clear(&state.history_path, room.as_deref()))
+                .transpose()
+                .map_err(|e| format!("Failed to clear the history file: {}", e))?;
+            let _ = state
+                .events
+                .send(ChatEvent::HistoryCleared { room: room.clone() });
+            Ok(Reply::Cleared {
+                room,
+                messages,
+                removed_from_disk,
+            })
+        }
+        Command::Search {
+            term,
+            regex,
+            before,
+            after,
+        } => {
+            let query = SearchQuery::new(&term, regex, before, after)?;
+            // Evicted messages are only in the history file.
+            if state.local_chat_messages.has_evicted() {
+                let hits = history::search(&state.history_path, &query, unix_now())
+                    .map_err(|e| format!("Failed to search the history: {}", e))?;
+                return Ok(Reply::Search {
+                    hits,
+                    history_empty: false,
+                });
+            }
+            Ok(Reply::Search {
+                hits: state.local_chat_messages.search(&query),
+                history_empty: state.local_chat_messages.messages().is_empty(),
+            })
+        }
+        Command::Join { room } => join_room(swarm, state, room),
+        Command::JoinInvite { token } => {
+            let invite = Invite::redeem(&token, unix_now())
+                .map_err(|e| format!("Can't join with that invite: {}", e))?;
+            if state.room_keys.insert(&invite.room, invite.secret) {
+                state
+                    .room_keys
+                    .save(&state.room_keys_path)
+                    .map_err(|e| format!("Failed to save the room key: {}", e))?;
+            }
+            join_room(swarm, state, invite.room.clone())?;
+            Ok(Reply::JoinedByInvite {
+                room: invite.room,
+                inviter: invite.inviter,
+            })
+        }
+        Command::Invite {
+            room,
+            expires_in,
+            new,
+        } => {
+            let topic = gossipsub::IdentTopic::new(&room);
+            let joined = swarm
+                .behaviour()
+                .gossipsub
+                .topics()
+                .any(|joined| *joined == topic.hash());
+            let private = state.room_keys.is_private(&room);
+            if new && private {
+                return Err(format!("{} is already private", room));
+            }
+            if !new && !private {
+                return Err(format!(
+                    "{} isn't private; /invite --new <room> makes a new private room",
+                    room
+                ));
+            }
+            if !new && !joined {
+                return Err(format!("Not in {}; /join it first", room));
+            }
+            // Those in the room without the key would be cut off, as would
+            // every peer yet to come for the room all nodes start in.
+            let has_members = room == CHAT_TOPIC
+                || state
+                    .members
+                    .members(&room, Instant::now())
+                    .is_some_and(|members| !members.is_empty());
+            if new && has_members {
+                return Err(format!(
+                    "{} has members who would be cut off; /invite --new a room of its own",
+                    room
+                ));
+            }
+            // Commands from the control socket skip the parser's check.
+            let expires_at = Some(expires_in.unwrap_or(DEFAULT_INVITE_SECS))
+                .filter(|seconds| *seconds <= MAX_INVITE_SECS)
+                .and_then(|seconds| unix_now().checked_add(seconds))
+                .ok_or_else(|| {
+                    format!(
+                        "An invite can be valid for at most {} seconds",
+                        MAX_INVITE_SECS
+                    )
+                })?;
+            let secret = state.room_keys.secret_or_new(&room);
+            if new {
+                state
+                    .room_keys
+                    .save(&state.room_keys_path)
+                    .map_err(|e| format!("Failed to save the room key: {}", e))?;
+                if !joined {
+                    join_room(swarm, state, room.clone())?;
+                }
+            }
+            let invite = Invite {
+                expires_at,
+                inviter: state.keypair.public().to_peer_id(),
+                room,
+                secret,
+            };
+            Ok(Reply::Invite {
+                token: invite.issue(&state.keypair)?,
+                room: invite.room,
+                expires_at: invite.expires_at,
+            })
+        }
+        Command::Leave { room } => {
+            if room == state.current_room.to_string() {
+                return Err(format!(
+                    "Can't leave the current room {}; /join another one first",
+                    room
+                ));
+            }
+            let topic = gossipsub::IdentTopic::new(&room);
+            let joined = swarm
+                .behaviour()
+                .gossipsub
+                .topics()
+                .any(|joined| *joined == topic.hash());
+            if joined && state.seal_gossip {
+                let _ = publish(swarm, state, &topic, &GossipMessage::Left);
+            }
+            let left = swarm
+                .behaviour_mut()
+                .gossipsub
+                .unsubscribe(&topic)
+                .map_err(|e| format!("Failed to leave {}: {}", room, e))?;
+            if !left {
+                return Err(format!("Not in {}", room));
+            }
+            state.members.leave(&room);
+
+            swarm
+                .behaviour_mut()
+                .kademlia
+                .stop_providing(&dht::room_key(&room));
+            state.room_providers.left(&room);
+            if let Ok(namespace) = room_namespace(&room) {
+                let servers: Vec<PeerId> = state
+                    .rendezvous_servers
+                    .keys()
+                    .filter(|server| swarm.is_connected(server))
+                    .copied()
+                    .collect();
+                for server in servers {
+                    swarm
+                        .behaviour_mut()
+                        .rendezvous
+                        .unregister(namespace.clone(), server);
+                }
+                state.registrations.left(&namespace);
+            }
+            Ok(Reply::Left { room })
+        }
+        Command::Rooms => {
+            let mut rooms: Vec<String> = swarm
+                .behaviour()
+                .gossipsub
+                .topics()
+                .map(|topic| topic.as_str().to_string())
+                .collect();
+            rooms.sort();
+            Ok(Reply::Rooms {
+                rooms: rooms
+                    .into_iter()
+                    .map(|room| RoomSummary {
+                        current: room == state.current_room.to_string(),
+                        private: state.room_keys.is_private(&room),
+                        providers: state.room_providers.count(&room),
+                        notify: state.room_settings.get(&room),
+                        room,
+                    })
+                    .collect(),
+            })
+        }
+        Command::Members { room } => {
+            let room = room.unwrap_or_else(|| state.current_room.to_string());
+            let members = state
+                .members
+                .members(&room, Instant::now())
+                .ok_or_else(|| format!("Not in {}", room))?;
+            Ok(Reply::Members {
+                members: members
+                    .into_iter()
+                    .map(|member| RoomMember {
+                        nickname: state
+                            .contacts
+                            .nickname(&member.peer)
+                            .or_else(|| state.nicknames.get(&member.peer))
+                            .map(str::to_string),
+                        peer_id: member.peer,
+                        online: member.online,
+                        joined_at: member.joined_at,
+                    })
+                    .collect(),
+                room,
+            })
+        }
+        Command::Mute {
+            room,
+            mentions_only,
+        } => {
+            let notify = if mentions_only {
+                Notify::MentionsOnly
+            } else {
+                Notify::Muted
+            };
+            state.set_notify(room, notify)
+        }
+        Command::Unmute { room } => state.set_notify(room, Notify::All),
+        Command::Msg { peer, text } => {
+            let chat_message = state.outgoing_direct_message(swarm, peer, text);
+            let chat_message = state.sign(chat_message)?;
+            let id = chat_message.id;
+
+            state.retries.sent(peer, chat_message.clone());
+            // The dial may still reach them, in which case they drop the
+            // forwarded copy as a duplicate.
+            if !swarm.is_connected(&peer) {
+                store_with_forwarders(swarm, state, peer, &chat_message);
+            }
+            state.store_message(chat_message.clone());
+            state
+                .local_chat_messages
+                .set_status(id, DeliveryStatus::Pending);
+            send_direct_message(swarm, state, peer, chat_message);
+            Ok(Reply::Sent { id })
+        }
+        Command::Block { peer } => {
+            state.rate_limiter.forget(&peer);
+            state.block(swarm, peer);
+            Ok(Reply::Blocked { peer })
+        }
+        Command::Unblock { peer } => {
+            state.unblock(swarm, peer);
+            Ok(Reply::Unblocked { peer })
+        }
+        Command::Nick { name } => {
+            validate_nickname(&name).map_err(|e| format!("Invalid nickname: {}", e))?;
+            state.nickname = Some(name.clone());
+
+            // Peers from before envelopes learn the name from our next
+            // message instead.
+            announce(
+                swarm,
+                state,
+                &GossipMessage::Nickname { name: name.clone() },
+            );
+            Ok(Reply::NickChanged { name })
+        }
+        Command::DoNotDisturb {
+            enabled,
+            away_message,
+        } => {
+            if enabled {
+                state.do_not_disturb.turn_on(away_message);
+            } else {
+                state.do_not_disturb.turn_off();
+            }
+            state
+                .do_not_disturb
+                .save(&state.dnd_path)
+                .map_err(|e| format!("Failed to save do not disturb: {}", e))?;
+            Ok(Reply::DoNotDisturb {
+                enabled,
+                away_message: state.do_not_disturb.away_message().map(str::to_string),
+            })
+        }
+        Command::Status => Ok(Reply::Status {
+            do_not_disturb: state.do_not_disturb.enabled(),
+            away_message: state.do_not_disturb.away_message().map(str::to_string),
+        }),
+        Command::NoEmoji => {
+            state.expand_emoji = !state.expand_emoji;
+            Ok(Reply::EmojiExpansion {
+                enabled: state.expand_emoji,
+            })
+        }
+        Command::Help { topic } => Ok(Reply::Help {
+            text: parser::help(topic.as_deref()),
+        }),
+        Command::Quit => {
+            state.quitting = true;
+            Ok(Reply::Quitting)
+        }
+        Command::Dial { address } => {
+            // Commands from the control socket skip the parser's check.
+            check_dial_address(&address).map_err(|e| format!("Can't dial {}: {}", address, e))?;
+            let opts = DialOpts::from(address.clone());
+            let connection_id = opts.connection_id();
+            swarm
+                .dial(opts)
+                .map_err(|e| format!("Failed to dial {}: {}", address, describe_dial_error(&e)))?;
+            state.pending_dials.insert(connection_id, address.clone());
+            Ok(Reply::Dialing { address })
+        }
+        Command::Peers => {
+            let peer_bandwidth = state.peer_bandwidth();
+            Ok(Reply::Peers {
+                peers: swarm
+                    .connected_peers()
+                    .map(|peer_id| ConnectedPeer {
+                        peer_id: *peer_id,
+                        protocol: state.peer_protocols.get(peer_id).copied(),
+                        security: swarm.behaviour().security.of_peer(peer_id),
+                        nickname: state
+                            .contacts
+                            .nickname(peer_id)
+                            .or_else(|| state.nicknames.get(peer_id))
+                            .map(str::to_string),
+                        verified: state.address_book.verified(peer_id).is_some(),
+                        bandwidth: peer_bandwidth.get(peer_id).copied().unwrap_or_default(),
+                        peering: if state.pinned_peers.contains_key(peer_id) {
+                            Peering::Pinned
+                        } else if state.explicit_peers.contains(peer_id) {
+                            Peering::Explicit
+                        } else {
+                            Peering::Mesh
+                        },
+                    })
+                    .collect(),
+            })
+        }
+        Command::Fingerprint { peer: None } => Ok(Reply::Fingerprint {
+            peer: None,
+            fingerprint: fingerprint(&state.keypair.public()),
+            verified: false,
+        }),
+        Command::Fingerprint { peer: Some(peer) } => {
+            let fingerprint = state.peer_fingerprint(swarm, &peer)?;
+            Ok(Reply::Fingerprint {
+                peer: Some(peer),
+                verified: state.address_book.verified(&peer) == Some(fingerprint.as_str()),
+                fingerprint,
+            })
+        }
+        Command::Verify { peer } => {
+            let fingerprint = state.peer_fingerprint(swarm, &peer)?;
+            state.address_book.verify(peer, fingerprint.clone());
+            state
+                .address_book
+                .save(&state.address_book_path)
+                .map_err(|e| format!("Failed to save address book: {}", e))?;
+            Ok(Reply::Verified { peer, fingerprint })
+        }
+        Command::PeerExchange { peer } => {
+            if state.peer_protocols.get(&peer) == Some(&ProtocolVersion::Legacy) {
+                return Err(format!(
+                    "{} runs an older version without peer exchange",
+                    peer
+                ));
+            }
+            state.exchanged.insert(peer);
+            send_direct(swarm, state, peer, DirectRequest::PeerExchange);
+            Ok(Reply::ExchangingPeers { peer })
+        }
+        Command::Addrs => {
+            let local_peer_id = *swarm.local_peer_id();
+            let addrs = state
+                .listen_addrs
+                .iter()
+                .chain(swarm.external_addresses())
+                .map(|address| {
+                    address
+                        .clone()
+                        .with_p2p(local_peer_id)
+                        .unwrap_or_else(|address| address)
+                })
+                .collect();
+            Ok(Reply::Addrs { addrs })
+        }
+        Command::Share { all } => Ok(state.share(swarm, all)),
+        Command::Known => Ok(Reply::Known {
+            peers: state
+                .address_book
+                .most_recent()
+                .into_iter()
+                .map(|(peer_id, entry)| KnownPeer {
+                    peer_id: *peer_id,
+                    entry: entry.clone(),
+                })
+                .collect(),
+        }),
+        Command::AddFriend { peer, nickname } => {
+            if peer == *swarm.local_peer_id() {
+                return Err("You can't add yourself as a friend".to_string());
+            }
+            if let Some(nickname) = &nickname {
+                validate_nickname(nickname).map_err(|e| format!("Invalid nickname: {}", e))?;
+            }
+            let new = state.contacts.add(peer, nickname.clone(), unix_now());
+            state
+                .contacts
+                .save(&state.contacts_path)
+                .map_err(|e| format!("Failed to save contacts: {}", e))?;
+            state.local_chat_messages.set_name(peer, nickname.clone());
+            Ok(Reply::FriendAdded {
+                peer,
+                nickname,
+                new,
+            })
+        }
+        Command::RemoveFriend { peer } => {
+            if state.contacts.remove(&peer).is_none() {
+                return Err(format!(
+                    "{} is not one of your friends",
+                    short_peer_id(&peer)
+                ));
+            }
+            state
+                .contacts
+                .save(&state.contacts_path)
+                .map_err(|e| format!("Failed to save contacts: {}", e))?;
+            state.local_chat_messages.set_name(peer, None);
+            Ok(Reply::FriendRemoved { peer })
+        }
+        Command::Friends => Ok(Reply::Friends {
+            friends: state
+                .contacts
+                .sorted()
+                .into_iter()
+                .map(|(peer, contact)| Friend {
+                    peer_id: *peer,
+                    nickname: contact.nickname.clone(),
+                    online: swarm.is_connected(peer),
+                })
+                .collect(),
+        }),
+        Command::Profile => Ok(state.profile_reply()),
+        Command::SetStatus { status } => {
+            if let Some(status) = &status {
+                validate_status(status).map_err(|e| format!("Invalid status: {}", e))?;
+            }
+            state.public_profile.status = status;
+            update_profile(swarm, state)
+        }
+        Command::SetAvatar { path } => {
+            let avatar = match path {
+                Some(path) => {
+                    let avatar = std::fs::read(&path)
+                        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+                    validate_avatar(&avatar).map_err(|e| format!("Invalid avatar: {}", e))?;
+                    Some(avatar)
+                }
+                None => None,
+            };
+            state.public_profile.avatar = avatar;
+            update_profile(swarm, state)
+        }
+        Command::Whois { peer } => {
+            let profile = state.peer_profiles.get(&peer);
+            let fetching = profile.is_none() && fetch_profile(swarm, state, peer);
+            let profile = state.peer_profiles.get(&peer);
+            let contact = state.contacts.get(&peer);
+            Ok(Reply::Whois(Box::new(Whois {
+                peer_id: peer,
+                nickname: state.nicknames.get(&peer).map(str::to_string),
+                friend: contact.is_some(),
+                friend_nickname: contact.and_then(|contact| contact.nickname.clone()),
+                online: swarm.is_connected(&peer),
+                protocol: state.peer_protocols.get(&peer).copied(),
+                verified: state.address_book.verified(&peer).is_some(),
+                last_seen: state.address_book.get(&peer).map(|entry| entry.last_seen),
+                status: profile.and_then(|profile| profile.status.clone()),
+                avatar: profile.and_then(Avatar::of),
+                profile_known: profile.is_some(),
+                fetching: fetching || state.fetching_profiles.contains(&peer),
+            })))
+        }
+        Command::Stats { .. } => Ok(Reply::Stats(Box::new(state.stats(swarm)))),
+        Command::Limits => Ok(Reply::Limits(state.limits(swarm))),
+        Command::Bandwidth { reset: false } => {
+            let mut peers: Vec<PeerTraffic> = state
+                .peer_bandwidth()
+                .into_iter()
+                .filter(|(_, bandwidth)| bandwidth.total() > 0)
+                .map(|(peer_id, bandwidth)| PeerTraffic { peer_id, bandwidth })
+                .collect();
+            peers.sort_by_key(|peer| std::cmp::Reverse(peer.bandwidth.total()));
+            Ok(Reply::Bandwidth { peers })
+        }
+        Command::Bandwidth { reset: true } => {
+            state.bandwidth_baseline = stats::peer_bandwidth(&state.metrics);
+            Ok(Reply::BandwidthReset)
+        }
+        Command::Metrics => {
+            let mut text = String::new();
+            prometheus_client::encoding::text::encode(&mut text, &state.metrics)
+                .map_err(|e| format!("Failed to encode metrics: {}", e))?;
+            Ok(Reply::Metrics { text })
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Response {
-    data: Value,
+/// Parses and runs a line typed on stdin, printing the outcome.
+fn handle_line(swarm: &mut Swarm<CustomBehaviour>, state: &mut AppState, line: &str) {
+    // Typing means the user has caught up with the output.
+    state.mentions.mark_read();
+    state.flush_output();
+
+    let command = match parser::parse(line) {
+        Ok(Some(command)) => command,
+        Ok(None) => return,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    let as_json = matches!(command, Command::Stats { json: true });
+    match execute_command(swarm, state, command) {
+        Ok(Reply::Stats(stats)) if as_json => match serde_json::to_string_pretty(&stats) {
+            Ok(stats) => println!("{}", stats),
+            Err(e) => println!("Failed to encode stats: {}", e),
+        },
+        Ok(reply) => println!("{}", reply),
+        Err(e) => println!("{}", e),
+    }
 }
 
-#[derive(NetworkBehaviour)]
-struct CustomBehaviour {
-    request_response: request_response::json::Behaviour<Request, Response>,
-    mdns: mdns::tokio::Behaviour,
-    gossipsub: gossipsub::Behaviour,
+/// Writes the key file at `path` from a new seed phrase, or with `recover`
+/// from one read from stdin.
+fn keygen(path: &Path, recover: bool, key_type: KeyType) -> Result<(), Box<dyn Error>> {
+    use std::io::Write;
+
+    if path.exists() {
+        return Err(format!(
+            "{} already holds an identity key; move it away first",
+            path.display()
+        )
+        .into());
+    }
+
+    let phrase = if recover {
+        print!("Seed phrase: ");
+        std::io::stdout().flush()?;
+        let mut phrase = String::new();
+        std::io::stdin().read_line(&mut phrase)?;
+        phrase
+    } else {
+        key::generate_mnemonic()
+    };
+
+    let keypair = key::from_mnemonic(&phrase, key_type)?;
+    key::save(path, &keypair)?;
+    if !recover {
+        println!("Seed phrase, to keep somewhere safe; anyone who has it can use this identity:");
+        println!();
+        println!("  {}", phrase);
+        println!();
+        if key_type != KeyType::Ed25519 {
+            println!("Recover it with keygen --recover --key-type {}", key_type);
+        }
+    }
+    println!(
+        "Wrote {} for peer id {}",
+        path.display(),
+        keypair.public().to_peer_id()
+    );
+    Ok(())
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct ChatMessage {
-    peer_id: PeerId,
-    message: String,
+/// Runs a `profiles` subcommand.
+fn run_profiles(
+    command: &ProfilesCommand,
+    key_type: Option<KeyType>,
+) -> Result<(), Box<dyn Error>> {
+    let root = cli::state_dir();
+    match command {
+        ProfilesCommand::List => {
+            let profiles = profile::list(&root)?;
+            if profiles.is_empty() {
+                println!(
+                    "No profiles yet; the {} one is made on first start",
+                    DEFAULT_PROFILE
+                );
+            }
+            for profile in profiles {
+                match profile.daemon {
+                    Some(pid) => println!("{} (daemon running, pid {})", profile.name, pid),
+                    None => println!("{}", profile.name),
+                }
+            }
+        }
+        ProfilesCommand::Create { name } => {
+            let dir = profile::create(&root, name)?;
+            let keypair = key::load_or_generate(&dir.join(KEY_FILE), key_type)?;
+            println!(
+                "Created profile {} in {} with peer id {}",
+                name,
+                dir.display(),
+                keypair.public().to_peer_id()
+            );
+        }
+        ProfilesCommand::Delete { name, yes } => {
+            if !yes {
+                return Err(format!(
+                    "deleting profile {} removes its identity key and history for good; \
+                     pass --yes to confirm",
+                    name
+                )
+                .into());
+            }
+            profile::delete(&root, name)?;
+            println!("Deleted profile {}", name);
+        }
+    }
+    Ok(())
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    let mut local_chat_messages: Vec<ChatMessage> = Vec::new();
-
-    let local_keypair = identity::Keypair::generate_ed25519();
-
-    let mut swarm = SwarmBuilder::with_existing_identity(local_keypair.clone())
-        .with_tokio()
-        .with_tcp(
-            tcp::Config::default(),
-            noise::Config::new,
-            yamux::Config::default,
-        )?
-        .with_behaviour(|key| {
-            let request_response_behaviour =
-                request_response::json::Behaviour::<Request, Response>::new(
-                    [(
-                        StreamProtocol::new("/my-json-protocol"),
-                        ProtocolSupport::Full,
-                    )],
-                    request_response::Config::default(),
-                );
+    let cli = Cli::parse();
+    peer_id::show_full_ids(cli.full_ids);
+    if let Some(CliCommand::Attach { socket }) = &cli.command {
+        control::attach(socket, BufReader::new(io::stdin())).await?;
+        return Ok(());
+    }
+    if let Some(CliCommand::Profiles { command }) = &cli.command {
+        return run_profiles(command, cli.key_type);
+    }
+
+    if cli.data_dir.is_none() && profile::migrate_legacy(&cli::state_dir())? {
+        println!(
+            "Moved the node state in {} to the {} profile",
+            cli::state_dir().display(),
+            DEFAULT_PROFILE
+        );
+    }
+    let data_dir = cli.data_dir();
+    std::fs::create_dir_all(&data_dir)?;
+    if let Some(CliCommand::Keygen {
+        recover, key_type, ..
+    }) = &cli.command
+    {
+        let key_type = key_type.or(cli.key_type).unwrap_or_default();
+        return keygen(&data_dir.join(KEY_FILE), *recover, key_type);
+    }
+    let config_path = cli
+        .config
+        .clone()
+        .unwrap_or_else(|| data_dir.join(CONFIG_FILE));
+    let mut config = Config::load(&config_path)?;
+    if cli.no_mdns {
+        config.mdns.enabled = false;
+    }
+    if cli.no_upnp {
+        config.swarm.upnp = false;
+    }
+    if cli.rendezvous_server {
+        config.rendezvous.server = true;
+    }
+    if let Some(security) = cli.security {
+        config.swarm.security = Some(security);
+    }
+    if let Some(muxer) = cli.muxer {
+        config.swarm.muxer = muxer;
+    }
+    if let Some(network_id) = &cli.network_id {
+        config.swarm.network_id = Some(network_id.clone());
+    }
+    if cli.security.is_some() || cli.network_id.is_some() {
+        // The config file was only checked against its own settings.
+        config.swarm.check()?;
+    }
+    if let Some(preset) = cli.gossip_preset {
+        config.gossipsub.preset = Some(preset);
+        // The config file was only checked against its own preset.
+        config.gossipsub.build().map_err(|e| {
+            format!(
+                "invalid gossipsub config in {} with --gossip-preset {}: {}",
+                config_path.display(),
+                preset,
+                e
+            )
+        })?;
+    }
+    if let Some(idle_timeout_secs) = cli.idle_timeout {
+        config.swarm.idle_timeout_secs = idle_timeout_secs;
+    }
+    if let Some(history_limit) = cli.history_limit {
+        config.history.max_messages = history_limit;
+    }
+    if let Some(max_connections) = cli.max_connections {
+        config.connection_limits.max_established = max_connections;
+    }
+    if let Some(max_connections_per_peer) = cli.max_connections_per_peer {
+        config.connection_limits.max_established_per_peer = max_connections_per_peer;
+    }
+    config
+        .connection_limits
+        .build()
+        .map_err(|e| format!("invalid connection limits: {}", e))?;
+
+    if cli.bench_mode {
+        let bench_config = BenchConfig {
+            messages: cli.bench_messages,
+            size: cli.bench_size,
+        };
+        if cli.bench_batching {
+            let unbatched = BatchConfig {
+                window_ms: 0,
+                ..config.batch.clone()
+            };
+            let report = bench::run_direct(bench_config, &unbatched).await?;
+            println!("Unbatched:\n{}", report);
+            let report = bench::run_direct(bench_config, &config.batch).await?;
+            println!("Batched:\n{}", report);
+        } else {
+            let report = bench::run(bench_config, &config).await?;
+            println!("{}", report);
+        }
+        return Ok(());
+    }
 
-            let mdns_behaviour =
-                mdns::tokio::Behaviour::new(mdns::Config::default(), key.public().to_peer_id())?;
+    let address_book_path = data_dir.join("address_book.json");
+    let sequences_path = data_dir.join(SEQUENCES_FILE);
+    let replays = ReplayGuard::load(&sequences_path, config.replay.window)?;
+    let contacts_path = data_dir.join(CONTACTS_FILE);
+    let contacts = Contacts::load(&contacts_path)?;
+    let public_profile_path = data_dir.join(PUBLIC_PROFILE_FILE);
+    let public_profile = PeerProfile::load(&public_profile_path)?;
+    let peer_profiles = ProfileCache::load(data_dir.join(PROFILE_CACHE_DIR))?;
+    let mut address_book = AddressBook::load(&address_book_path)?;
+    // Friends are dialed at their addresses however long ago they were seen.
+    address_book.prune(cli.peer_max_age, unix_now(), |peer| {
+        contacts.get(peer).is_some()
+    });
+    let history_path = data_dir.join(HISTORY_FILE);
+    let room_settings_path = data_dir.join(ROOM_SETTINGS_FILE);
+    let room_settings = RoomSettings::load(&room_settings_path)?;
+    let room_keys_path = data_dir.join(ROOM_KEYS_FILE);
+    let room_keys = RoomKeys::load(&room_keys_path)?;
+    let dnd_path = data_dir.join(DND_FILE);
+    let do_not_disturb = DoNotDisturb::load(&dnd_path)?;
 
-            let gossipsub_behaviour = gossipsub::Behaviour::new(
-                gossipsub::MessageAuthenticity::Signed(key.clone()),
-                gossipsub::Config::default(),
+    match cli.command {
+        Some(CliCommand::Export { out, room, since }) => {
+            let exported = export::export(
+                &history_path,
+                &address_book,
+                &ExportFilter { room, since },
+                std::fs::File::create(&out)?,
             )?;
+            println!("Exported {} messages to {}", exported, out.display());
+            return Ok(());
+        }
+        Some(CliCommand::Import { input }) => {
+            let summary = export::import(&input, &history_path, &mut address_book)?;
+            address_book.save(&address_book_path)?;
+            println!(
+                "Imported {} messages ({} already present, {} expired) and {} peers",
+                summary.imported, summary.duplicates, summary.expired, summary.peers
+            );
+            return Ok(());
+        }
+        Some(CliCommand::Stop) => {
+            let pid = daemon::stop(&data_dir.join(PID_FILE), STOP_TIMEOUT)?;
+            println!("Stopped the daemon (pid {})", pid);
+            return Ok(());
+        }
+        Some(
+            CliCommand::Attach { .. } | CliCommand::Keygen { .. } | CliCommand::Profiles { .. },
+        )
+        | None => {}
+    }
 
-            Ok(CustomBehaviour {
-                request_response: request_response_behaviour,
-                mdns: mdns_behaviour,
-                gossipsub: gossipsub_behaviour,
-            })
-        })?
-        .with_swarm_config(|cfg| cfg.with_idle_connection_timeout(Duration::from_secs(10)))
-        .build();
+    // Installed before the PID file exists, so `stop` never finds a daemon
+    // that SIGTERM would kill outright.
+    let mut terminate = signal(SignalKind::terminate())?;
+    let _pid_file = cli
+        .daemon
+        .then(|| PidFile::create(data_dir.join(PID_FILE)))
+        .transpose()?;
+    if let Some(log_file) = &cli.log_file {
+        daemon::redirect_output(log_file)?;
+    }
+
+    history::compact(&history_path, unix_now())?;
+    let mut local_chat_messages = MessageStore::new(
+        config.history.max_messages,
+        config.history.max_messages_per_room,
+    );
+    for (peer, contact) in contacts.sorted() {
+        local_chat_messages.set_name(*peer, contact.nickname.clone());
+    }
+    local_chat_messages.set_filter(config.filter.filter());
+    let mut expiries = Expiries::default();
+    for chat_message in history::read(&history_path)? {
+        let chat_message = chat_message?;
+        expiries.schedule(&chat_message, unix_now());
+        local_chat_messages.insert(chat_message);
+    }
+
+    let local_keypair = key::load_or_generate(&data_dir.join(KEY_FILE), cli.key_type)?;
+    local_chat_messages.set_local_peer(local_keypair.public().to_peer_id());
+
+    let mut metrics = Registry::default();
+    let mut swarm = behaviour::build_swarm(local_keypair.clone(), &config, &mut metrics).await?;
 
-    swarm.listen_on("/ip4/0.0.0.0/tcp/0".parse::<Multiaddr>()?)?;
+    let current_room = gossipsub::IdentTopic::new(CHAT_TOPIC);
+    swarm.behaviour_mut().gossipsub.subscribe(&current_room)?;
+
+    // Listeners given on the command line must all open. For the defaults it
+    // is enough that one does, since hosts without IPv6 are common.
+    let relisten = cli.listen.is_empty();
+    let listen_addrs = if relisten {
+        DEFAULT_LISTEN_ADDRS
+            .iter()
+            .map(|address| address.parse::<Multiaddr>())
+            .chain([format!("/ip4/0.0.0.0/tcp/{}/ws", cli.ws_port).parse()])
+            .collect::<Result<_, _>>()?
+    } else {
+        cli.listen.clone()
+    };
+    let mut listeners = HashMap::new();
+    for address in listen_addrs {
+        match swarm.listen_on(address.clone()) {
+            Ok(listener_id) => {
+                listeners.insert(listener_id, address);
+            }
+            Err(e) if relisten => {
+                println!(
+                    "Failed to listen on {}: {}",
+                    address,
+                    describe_transport_error(&address, &e)
+                )
+            }
+            Err(e) => {
+                return Err(format!(
+                    "Failed to listen on {}: {}",
+                    address,
+                    describe_transport_error(&address, &e)
+                )
+                .into())
+            }
+        }
+    }
+    if listeners.is_empty() {
+        return Err("Failed to open any listener".into());
+    }
 
     println!("Peer {} started", swarm.local_peer_id());
+    match config.swarm.idle_timeout_secs {
+        0 => println!("Idle connections are kept open"),
+        secs => println!("Idle connections are closed after {}s", secs),
+    }
 
-    loop {
-        match swarm.select_next_some().await {
-            SwarmEvent::Behaviour(CustomBehaviourEvent::Mdns(mdns::Event::Discovered(peers))) => {
-                for (peer, address) in peers {
-                    println!("Peer {} discovered", peer);
-                    if swarm
-                        .behaviour_mut()
-                        .request_response
-                        .add_address(&peer, address.clone())
-                    {
-                        println!(
-                            "Address {} added to the peer {}",
-                            address.clone(),
-                            swarm.local_peer_id()
-                        );
+    for address in &cli.external_address {
+        swarm.add_external_address(address.clone());
+        println!("Announcing external address {}", address);
+    }
 
-                        let chat_message = ChatMessage {
-                            peer_id: *swarm.local_peer_id(),
-                            message: format!("Hello I am {}", swarm.local_peer_id()),
-                        };
+    for address in &cli.dial {
+        if let Err(e) = swarm.dial(address.clone()) {
+            println!("Failed to dial {}: {}", address, describe_dial_error(&e));
+        }
+    }
 
-                        swarm.behaviour_mut().request_response.send_request(
-                            &peer,
-                            Request {
-                                data: json!(&chat_message),
-                            },
-                        );
-                    }
+    let pinned_peers: HashMap<PeerId, Multiaddr> = config
+        .peering
+        .pinned
+        .iter()
+        .map(|pinned| (pinned.peer_id, pinned.address.clone()))
+        .collect();
+    for (peer, address) in &pinned_peers {
+        swarm.add_peer_address(*peer, address.clone());
+        swarm.behaviour_mut().gossipsub.add_explicit_peer(peer);
+        dial_pinned(&mut swarm, *peer, address);
+    }
+
+    if config.rendezvous.server {
+        println!("Serving as a rendezvous point");
+    }
+    let mut rendezvous_servers = HashMap::new();
+    for address in &cli.rendezvous {
+        let server = server_peer_id(address)?;
+        rendezvous_servers.insert(server, address.clone());
+        if let Err(e) = swarm.dial(address.clone()) {
+            println!(
+                "Failed to dial rendezvous server {}: {}",
+                address,
+                describe_dial_error(&e)
+            );
+        }
+    }
+
+    // Friends are always dialed, on top of the most recently seen peers.
+    for (peer, _) in contacts.sorted() {
+        let addresses = address_book
+            .get(peer)
+            .map(|entry| entry.addresses.clone())
+            .unwrap_or_default();
+        let opts = DialOpts::peer_id(*peer).addresses(addresses).build();
+        if let Err(e) = swarm.dial(opts) {
+            println!(
+                "Failed to dial friend {}: {}",
+                contacts
+                    .nickname(peer)
+                    .map_or_else(|| short_peer_id(peer), str::to_string),
+                describe_dial_error(&e)
+            );
+        }
+    }
+    for (peer, entry) in address_book
+        .most_recent()
+        .into_iter()
+        .filter(|(peer, _)| contacts.get(peer).is_none())
+        .take(STARTUP_DIAL_LIMIT)
+    {
+        let opts = DialOpts::peer_id(*peer)
+            .addresses(entry.addresses.clone())
+            .build();
+        if let Err(e) = swarm.dial(opts) {
+            println!("Failed to dial known peer {}: {}", short_peer_id(peer), e);
+        }
+    }
+
+    let qr_pending = if cli.print_qr {
+        listeners.keys().copied().collect()
+    } else {
+        HashSet::new()
+    };
+    let (events_tx, _) = broadcast::channel(EVENT_BUFFER);
+    let mut state = AppState {
+        local_chat_messages,
+        expiries,
+        expired: HashSet::new(),
+        listen_addrs: HashSet::new(),
+        listeners,
+        relisten,
+        quitting: false,
+        closed_listeners: Vec::new(),
+        qr_pending,
+        pending_dials: HashMap::new(),
+        peer_protocols: HashMap::new(),
+        compressing_peers: HashSet::new(),
+        compress_above_bytes: config.protocol.compress_above_bytes,
+        seal_gossip: !config.protocol.legacy,
+        reached_from_outside: false,
+        port_mapping: if config.swarm.upnp {
+            PortMapping::Searching
+        } else {
+            PortMapping::Disabled
+        },
+        mapped_addrs: HashSet::new(),
+        rendezvous_servers,
+        explicit_peers: pinned_peers.keys().copied().collect(),
+        pinned_peers,
+        key_pins: config.key_pins.build()?,
+        unpinned_connections: HashSet::new(),
+        explicit_below: config.peering.explicit_below,
+        validates_gossip: config.gossipsub.validation == Validation::Strict,
+        registrations: Registrations::default(),
+        rendezvous_ttl: config.rendezvous.ttl_secs,
+        confirm_observed_addrs: !cli.rendezvous.is_empty() && cli.external_address.is_empty(),
+        reported_no_external_addr: false,
+        room_providers: RoomProviders::default(),
+        address_book,
+        address_book_path,
+        contacts,
+        contacts_path,
+        public_profile,
+        public_profile_path,
+        peer_profiles,
+        fetching_profiles: HashSet::new(),
+        history_path,
+        current_room,
+        room_settings,
+        room_settings_path,
+        room_keys,
+        room_keys_path,
+        keypair: local_keypair,
+        nickname: None,
+        nicknames: Nicknames::default(),
+        public_keys: HashMap::new(),
+        expand_emoji: true,
+        deleted_messages: config.history.deleted_messages,
+        mentions: Mentions::default(),
+        bell: !cli.no_bell,
+        interactive: !cli.daemon,
+        notifier: (!cli.no_notifications).then(Notifier::default),
+        do_not_disturb,
+        dnd_path,
+        output: OutputBatch::new(Duration::from_millis(config.display.flush_interval_ms)),
+        violations: ViolationTracker::new(
+            config.auto_ban.threshold,
+            Duration::from_secs(config.auto_ban.cooldown_secs),
+        ),
+        sequence: Sequence::start(),
+        replays,
+        sequences_path,
+        rate_limiter: RateLimiter::new(
+            config.rate_limit.messages_per_sec,
+            config.rate_limit.burst,
+            Duration::from_secs(config.rate_limit.block_after_secs),
+            Duration::from_secs(config.rate_limit.block_secs),
+        ),
+        blocked: HashSet::new(),
+        exchange_limiter: RateLimiter::new(
+            1.0 / config.peer_exchange.min_request_interval_secs as f64,
+            2,
+            // Requests over the limit get an empty answer; nobody is blocked.
+            Duration::MAX,
+            Duration::ZERO,
+        ),
+        exchanged: HashSet::new(),
+        exchange_dials: DialQueue::new(Duration::from_secs(
+            config.peer_exchange.max_dial_delay_secs,
+        )),
+        exchange_target_peers: config.peer_exchange.target_peers,
+        flood_guard: FloodGuard::new(
+            config.flood.shed_above_per_sec,
+            config.flood.recover_below_per_sec,
+            Duration::from_secs(config.flood.window_secs),
+        ),
+        forward_store: cli.forward.then(|| {
+            ForwardStore::new(
+                config.store_forward.max_per_target,
+                config.store_forward.max_per_sender,
+                config.store_forward.max_messages,
+                config.store_forward.ttl_secs,
+            )
+        }),
+        forwarding: HashMap::new(),
+        forward_ttl: config.store_forward.ttl_secs,
+        retries: RetryQueue::new(config.retry.policy()),
+        departures: Departures::new(DEPARTURE_GRACE),
+        members: config.members.members(),
+        direct_messages: HashMap::new(),
+        batcher: config.batch.batcher(),
+        events: events_tx.clone(),
+        counters: Counters::default(),
+        metrics,
+        bandwidth_baseline: HashMap::new(),
+        connection_limits: config.connection_limits.clone(),
+        unreported_denials: 0,
+        last_denial_report: None,
+    };
+    state.members.join(CHAT_TOPIC);
+
+    if cli.daemon {
+        match &cli.control_socket {
+            Some(path) => println!("Running as a daemon; control it through {}", path.display()),
+            None => println!("Running as a daemon without --control-socket; stop it with a signal"),
+        }
+    }
+    let (control_tx, mut control_rx) = mpsc::channel::<ControlRequest>(32);
+    let handle = ChatHandle::new(control_tx, events_tx);
+    if cli.ping_bot {
+        bot::spawn_handler(handle.clone(), PingBot);
+    }
+    let _control_socket = match cli.control_socket {
+        Some(path) => Some(ControlSocket::bind(path, handle.clone())?),
+        None => None,
+    };
+    #[cfg(feature = "http-api")]
+    if let Some(addr) = cli.http_addr {
+        let token = token::generate_token();
+        let bound = http::serve(addr, handle.clone(), token.clone()).await?;
+        println!("HTTP API on http://{}; bearer token {}", bound, token);
+    }
+    #[cfg(feature = "http-api")]
+    if let Some(addr) = cli.rpc_addr {
+        // Without a configured token, a new one is printed on every start.
+        let configured = config.rpc.token.clone();
+        let token = configured.clone().unwrap_or_else(token::generate_token);
+        let bound = rpc::serve(addr, handle.clone(), token.clone()).await?;
+        match configured {
+            Some(_) => println!("JSON-RPC API on http://{}", bound),
+            None => println!("JSON-RPC API on http://{}; bearer token {}", bound, token),
+        }
+    }
+    #[cfg(feature = "web-ui")]
+    if let Some(addr) = cli.web_ui_addr {
+        let token = token::generate_token();
+        let bound = web_ui::serve(addr, handle.clone(), token.clone()).await?;
+        println!("Web UI on ws://{}/?token={}", bound, token);
+    }
+
+    let mut stdin = BufReader::new(io::stdin()).lines();
+    let mut stdin_open = !cli.daemon;
+
+    let mut expiry_interval = tokio::time::interval(Duration::from_secs(1));
+    let mut output_interval =
+        tokio::time::interval(state.output.interval().max(Duration::from_millis(1)));
+    let mut batch_interval =
+        tokio::time::interval(state.batcher.window().max(Duration::from_millis(1)));
+    let mut relisten_interval = tokio::time::interval(RELISTEN_INTERVAL);
+    let mut pinned_interval = tokio::time::interval(PINNED_REDIAL_INTERVAL);
+    // Pinned peers were dialed at startup.
+    pinned_interval.reset();
+    let mut rendezvous_interval = tokio::time::interval(Duration::from_secs(
+        config.rendezvous.discover_interval_secs,
+    ));
+    let mut dht_interval =
+        tokio::time::interval(Duration::from_secs(config.dht.lookup_interval_secs));
+    let mut presence_interval =
+        tokio::time::interval(Duration::from_secs(config.members.heartbeat_secs));
+    // The first lookups start with the announcement below.
+    dht_interval.reset();
+    find_room_members(&mut swarm, &mut state, CHAT_TOPIC);
+    let external_address_deadline = tokio::time::sleep(EXTERNAL_ADDRESS_GRACE);
+    tokio::pin!(external_address_deadline);
+    let mut external_address_checked = cli.external_address.is_empty();
+
+    while !state.quitting {
+        let event = tokio::select! {
+            line = stdin.next_line(), if stdin_open => {
+                match line? {
+                    Some(line) => handle_line(&mut swarm, &mut state, &line),
+                    None => stdin_open = false,
                 }
+                continue;
             }
-            SwarmEvent::Behaviour(CustomBehaviourEvent::Mdns(mdns::Event::Expired(peers))) => {
-                for (peer, address) in peers {
-                    println!("Peer {} expired", peer);
+            Some((command, reply_tx)) = control_rx.recv() => {
+                let _ = reply_tx.send(execute_command(&mut swarm, &mut state, command));
+                continue;
+            }
+            Some(id) = state.expiries.next() => {
+                if state.local_chat_messages.remove(&id) {
+                    state.retries.cancel(&id);
+                    println!("Message [{}] expired", &id.simple().to_string()[..8]);
+                }
+                // Evicted or not, it is still in the history.
+                state.expired.insert(id);
+                continue;
+            }
+            _ = expiry_interval.tick() => {
+                state.local_chat_messages.drop_stale_changes();
+                purge_expired(&mut state);
+                retry_direct_messages(&mut swarm, &mut state);
+                peers_departed(&mut swarm, &mut state);
+                for (room, peer) in state.members.expire(Instant::now()) {
+                    handler::member_left(&state.events, room, peer);
+                }
+                state.violations.prune(Instant::now());
+                if let Some(forward_store) = &mut state.forward_store {
+                    forward_store.expire(unix_now());
+                }
+                if let Err(e) = state.replays.save(&state.sequences_path) {
+                    println!("Failed to save sequence numbers: {}", e);
+                }
+                if state.flood_guard.recover(Instant::now()) {
+                    println!("Message rate is back to normal; handling broadcasts again");
+                }
+                for peer in state.rate_limiter.expire(Instant::now()) {
+                    state.unblock(&mut swarm, peer);
+                    println!("Unblocked {} after its flooding cooldown", short_peer_id(&peer));
+                }
+                state.exchange_limiter.expire(Instant::now());
+                dial_exchanged_peers(&mut swarm, &mut state);
+                continue;
+            }
+            _ = output_interval.tick(), if !state.output.is_empty() => {
+                let lines = state.output.flush(Instant::now());
+                print_lines(&lines);
+                continue;
+            }
+            _ = batch_interval.tick(), if !state.batcher.is_empty() => {
+                for (peer, messages) in state.batcher.due(Instant::now()) {
+                    send_messages(&mut swarm, &mut state, peer, messages);
+                }
+                continue;
+            }
+            _ = dht_interval.tick() => {
+                let rooms: Vec<String> = swarm
+                    .behaviour()
+                    .gossipsub
+                    .topics()
+                    .map(|topic| topic.as_str().to_string())
+                    .collect();
+                for room in rooms {
+                    look_up_room(&mut swarm, &mut state, &room);
+                }
+                continue;
+            }
+            _ = presence_interval.tick() => {
+                let rooms = state.members.rooms();
+                announce(&mut swarm, &mut state, &GossipMessage::Presence { rooms });
+                continue;
+            }
+            _ = rendezvous_interval.tick(), if !state.rendezvous_servers.is_empty() => {
+                refresh_rendezvous(&mut swarm, &mut state);
+                continue;
+            }
+            _ = relisten_interval.tick(), if !state.closed_listeners.is_empty() => {
+                state.reopen_listeners(&mut swarm);
+                continue;
+            }
+            _ = pinned_interval.tick(), if !state.pinned_peers.is_empty() => {
+                redial_pinned(&mut swarm, &state);
+                continue;
+            }
+            _ = &mut external_address_deadline, if !external_address_checked => {
+                external_address_checked = true;
+                if !state.reached_from_outside {
+                    println!(
+                        "Warning: no peer has connected from outside this network in {}s; \
+                         check that the external address is forwarded",
+                        EXTERNAL_ADDRESS_GRACE.as_secs()
+                    );
+                }
+                continue;
+            }
+            _ = tokio::signal::ctrl_c() => break,
+            _ = terminate.recv() => break,
+            event = swarm.select_next_some() => event,
+        };
+
+        // A peer failing a key pin is cut off before anything else sees it.
+        if let SwarmEvent::ConnectionEstablished {
+            peer_id,
+            connection_id,
+            endpoint,
+            ..
+        } = &event
+        {
+            if let Some(warning) = key_pin::check_connection(
+                &mut swarm,
+                &state.key_pins,
+                *peer_id,
+                *connection_id,
+                endpoint,
+            ) {
+                println!("{}", warning);
+                let _ = state.events.send(warning);
+                state.pending_dials.remove(connection_id);
+                state.unpinned_connections.insert(*connection_id);
+                continue;
+            }
+        }
+
+        // Several arms below handle new connections for their own reasons.
+        if let SwarmEvent::ConnectionEstablished {
+            peer_id,
+            num_established,
+            ..
+        } = &event
+        {
+            if num_established.get() == 1 && !state.departures.reconnected(peer_id, Instant::now())
+            {
+                let _ = state.events.send(ChatEvent::PeerOnline { peer: *peer_id });
+            }
+        }
+
+        match event {
+            SwarmEvent::NewListenAddr {
+                listener_id,
+                address,
+            } => {
+                println!("Listening on {}", address);
+                state.listen_addrs.insert(address);
+                // Once every listener has an address, the best one is known.
+                if state.qr_pending.remove(&listener_id) && state.qr_pending.is_empty() {
+                    println!("{}", state.share(&swarm, false));
+                }
+            }
+            SwarmEvent::ExpiredListenAddr { address, .. } => {
+                state.listen_addrs.remove(&address);
+            }
+            SwarmEvent::ListenerClosed {
+                listener_id,
+                addresses,
+                reason,
+            } => {
+                for address in &addresses {
+                    state.listen_addrs.remove(address);
+                }
 
+                let Some(address) = state.listeners.remove(&listener_id) else {
+                    continue;
+                };
+                match reason {
+                    Ok(()) => println!("Stopped listening on {}", address),
+                    Err(e) => println!("Stopped listening on {}: {}", address, e),
+                }
+                if state.relisten {
+                    state.closed_listeners.push(address);
+                }
+            }
+            SwarmEvent::ConnectionEstablished {
+                peer_id,
+                endpoint: ConnectedPoint::Listener { send_back_addr, .. },
+                ..
+            } if !state.reached_from_outside && is_public(&send_back_addr) => {
+                state.reached_from_outside = true;
+                if !cli.external_address.is_empty() || !state.mapped_addrs.is_empty() {
+                    println!(
+                        "Peer {} reached us from {}; the external address is reachable",
+                        short_peer_id(&peer_id),
+                        send_back_addr
+                    );
+                }
+            }
+            SwarmEvent::ConnectionEstablished {
+                peer_id,
+                connection_id,
+                ..
+            } if state.pending_dials.contains_key(&connection_id) => {
+                if let Some(address) = state.pending_dials.remove(&connection_id) {
+                    match swarm.behaviour().security.of(connection_id) {
+                        Some(security) => println!(
+                            "Connected to {} at {} over {}",
+                            short_peer_id(&peer_id),
+                            address,
+                            security
+                        ),
+                        None => println!("Connected to {} at {}", short_peer_id(&peer_id), address),
+                    }
+                }
+            }
+            SwarmEvent::OutgoingConnectionError {
+                connection_id,
+                error,
+                ..
+            } if state.pending_dials.contains_key(&connection_id) => {
+                if let Some(address) = state.pending_dials.remove(&connection_id) {
+                    println!("{}", report_dial_error(&address.to_string(), &error));
+                }
+            }
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                connection_id,
+                num_established: 0,
+                cause,
+                ..
+            } => {
+                state.peer_protocols.remove(&peer_id);
+                state.compressing_peers.remove(&peer_id);
+                state.public_keys.remove(&peer_id);
+                state.registrations.disconnected(&peer_id);
+                state.exchanged.remove(&peer_id);
+                if !state.unpinned_connections.remove(&connection_id) {
+                    state.departures.closed(
+                        peer_id,
+                        describe_close(cause.as_ref()),
+                        Instant::now(),
+                    );
+                }
+                // Peers discovered while we had few are only explicit for
+                // as long as they stay, or the set would only grow.
+                remove_explicit_peer(&mut swarm, &mut state, peer_id);
+                if let Some(address) = state.pinned_peers.get(&peer_id) {
                     println!(
-                        "Address {} removed from the peer {}",
-                        address.clone(),
-                        swarm.local_peer_id()
+                        "Pinned peer {} disconnected; redialing",
+                        short_peer_id(&peer_id)
                     );
+                    dial_pinned(&mut swarm, peer_id, address);
+                }
+            }
+            SwarmEvent::ConnectionClosed { connection_id, .. } => {
+                state.unpinned_connections.remove(&connection_id);
+            }
+            SwarmEvent::IncomingConnectionError {
+                error: ListenError::Denied { cause },
+                ..
+            }
+            | SwarmEvent::OutgoingConnectionError {
+                error: DialError::Denied { cause },
+                ..
+            } if cause
+                .downcast_ref::<connection_limits::Exceeded>()
+                .is_some() =>
+            {
+                if let Some(exceeded) = cause.downcast_ref::<connection_limits::Exceeded>() {
+                    state.connection_denied(exceeded);
+                }
+            }
+            SwarmEvent::IncomingConnectionError {
+                send_back_addr,
+                error: ListenError::Transport(error),
+                ..
+            } if is_handshake_failure(&error) => {
+                println!(
+                    "Incoming connection from {} failed: {}",
+                    send_back_addr,
+                    describe_transport_error(&send_back_addr, &error)
+                );
+            }
+            SwarmEvent::OutgoingConnectionError { peer_id, error, .. } => match peer_id {
+                Some(peer) => println!("{}", report_dial_error(&short_peer_id(&peer), &error)),
+                None if is_security_failure(&error) => {
+                    println!("Handshake failed: {}", describe_dial_error(&error))
+                }
+                None => println!("Failed to dial {}", describe_dial_error(&error)),
+            },
+            SwarmEvent::NewExternalAddrCandidate { address }
+                if state.confirm_observed_addrs
+                    && !swarm.external_addresses().any(|known| *known == address) =>
+            {
+                println!("Peers see us at {}; registering it", address);
+                swarm.add_external_address(address);
+                register_again(&mut swarm, &mut state);
+            }
+            SwarmEvent::ListenerError { listener_id, error } => {
+                let address = state
+                    .listeners
+                    .get(&listener_id)
+                    .map(Multiaddr::to_string)
+                    .unwrap_or_else(|| listener_id.to_string());
+                println!("Listener on {} failed: {}", address, error);
+            }
+            SwarmEvent::Behaviour(CustomBehaviourEvent::Mdns(mdns::Event::Discovered(peers))) => {
+                handler::peers_discovered(&mut Live::new(&mut swarm, &mut state), peers);
+            }
+            SwarmEvent::Behaviour(CustomBehaviourEvent::Identify(identify::Event::Received {
+                peer_id,
+                info,
+            })) => {
+                let actual = fingerprint(&info.public_key);
+                match state.address_book.verified(&peer_id) {
+                    // The impostor's key isn't kept for /fingerprint.
+                    Some(expected) if actual != expected => {
+                        let event = ChatEvent::KeyMismatch {
+                            peer: peer_id,
+                            expected: expected.to_string(),
+                            actual,
+                        };
+                        println!("{}", event);
+                        let _ = state.events.send(event);
+                    }
+                    _ => {
+                        state.public_keys.insert(peer_id, info.public_key.clone());
+                    }
+                }
+                match ProtocolVersion::newest(&info.protocols) {
+                    Some(version) => state.peer_protocols.insert(peer_id, version),
+                    None => state.peer_protocols.remove(&peer_id),
+                };
+                if config.protocol.compression && info.protocols.contains(&COMPRESSED_CHAT_PROTOCOL)
+                {
+                    state.compressing_peers.insert(peer_id);
+                } else {
+                    state.compressing_peers.remove(&peer_id);
+                }
+                // Held messages carry their author's signature, which legacy
+                // peers can't check when it covers a sequence number.
+                if state.peer_protocols.get(&peer_id) == Some(&ProtocolVersion::V1) {
+                    deliver_stored(&mut swarm, &mut state, peer_id);
+                    // Legacy peers would take the request for a broken one.
+                    if state.exchanged.insert(peer_id) {
+                        send_direct(&mut swarm, &mut state, peer_id, DirectRequest::PeerExchange);
+                    }
+                    if state.peer_profiles.get(&peer_id).is_none() {
+                        fetch_profile(&mut swarm, &mut state, peer_id);
+                    }
+                }
+                // Identify runs again on every push and interval, but one
+                // registration per connection is enough. Without an address
+                // yet, wait until peers have told us one.
+                if state.rendezvous_servers.contains_key(&peer_id)
+                    && !state.registrations.knows(&peer_id)
+                    && (swarm.external_addresses().next().is_some()
+                        || !state.confirm_observed_addrs)
+                {
+                    meet_at(&mut swarm, &mut state, peer_id, None);
+                }
 
-                    swarm
-                        .behaviour_mut()
-                        .request_response
-                        .remove_address(&peer, &address);
+                if info.protocols.contains(&KAD_PROTOCOL) {
+                    for address in &info.listen_addrs {
+                        swarm
+                            .behaviour_mut()
+                            .kademlia
+                            .add_address(&peer_id, address.clone());
+                    }
+                }
+
+                let now = unix_now();
+                let mut changed = false;
+                for address in info.listen_addrs {
+                    changed |= state.address_book.record(peer_id, address, now);
+                }
+                if changed {
+                    state.save_address_book();
+                }
+            }
+            SwarmEvent::Behaviour(CustomBehaviourEvent::Mdns(mdns::Event::Expired(peers))) => {
+                // The swarm keeps the addresses; dials to them just fail.
+                for (peer, _) in peers {
+                    println!("Peer {} expired", short_peer_id(&peer));
+                    // One we never reached isn't redialed forever.
+                    remove_explicit_peer(&mut swarm, &mut state, peer);
                 }
             }
             SwarmEvent::Behaviour(CustomBehaviourEvent::RequestResponse(
@@ -127,36 +3628,419 @@ async fn main() -> Result<(), Box<dyn Error>> {
                         },
                 },
             )) => {
-                local_chat_messages.push(serde_json::from_str(&request.data.to_string())?);
+                if !within_rate_limit(&mut swarm, &mut state, peer)
+                    || !within_load(&mut state, Priority::Direct)
+                {
+                    continue;
+                }
+
+                let direct_request: DirectRequest = match envelope::open(request.data) {
+                    Ok(Opened::Known(direct_request)) => direct_request,
+                    Ok(Opened::Unknown { version, kind }) => {
+                        handler::report_unknown(&state.events, peer, version, kind.clone());
+                        send_response(
+                            &mut swarm,
+                            &mut state,
+                            peer,
+                            channel,
+                            DirectResponse::Unsupported { request_kind: kind },
+                        );
+                        continue;
+                    }
+                    Err(e) => {
+                        println!("Invalid request from {}: {}", short_peer_id(&peer), e);
+                        report_violation(&mut swarm, &mut state, peer, Violation::MalformedRequest);
+                        continue;
+                    }
+                };
+
+                // A message held by several forwarders, or also delivered
+                // directly, arrives more than once.
+                if let DirectRequest::Message(chat_message)
+                | DirectRequest::Forwarded(chat_message)
+                | DirectRequest::System(chat_message) = &direct_request
+                {
+                    if state.local_chat_messages.get(&chat_message.id).is_some() {
+                        let id = chat_message.id;
+                        send_response(
+                            &mut swarm,
+                            &mut state,
+                            peer,
+                            channel,
+                            DirectResponse::Ack { id },
+                        );
+                        continue;
+                    }
+                }
+
+                if let Some(chat_message) = direct_request.chat_message() {
+                    // Forwarders hand on others' messages; everything else
+                    // must come from its author, and be meant for us or the
+                    // peer a forwarder is to hold it for.
+                    let (recipient, forwarded) = match &direct_request {
+                        DirectRequest::StoreForward { target, .. } => (*target, false),
+                        DirectRequest::Forwarded(_) => (*swarm.local_peer_id(), true),
+                        _ => (*swarm.local_peer_id(), false),
+                    };
+                    let mut node = Live::new(&mut swarm, &mut state);
+                    if !handler::check_direct(&mut node, peer, chat_message, recipient, forwarded)
+                        || !handler::verify(&mut node, peer, chat_message)
+                    {
+                        continue;
+                    }
+                    state.counters.message_received(None);
+                }
+
+                let response = match direct_request {
+                    DirectRequest::Greeting(chat_message) => {
+                        println!(
+                            "{} greeted you: {}",
+                            short_peer_id(&peer),
+                            chat_message.display_text()
+                        );
+                        state.store_message(chat_message);
+
+                        let welcome = state.outgoing_direct_message(
+                            &swarm,
+                            peer,
+                            format!("Welcome {}!, I am {}", peer, swarm.local_peer_id()),
+                        );
+                        match state.sign(welcome) {
+                            Ok(welcome) => {
+                                state.counters.message_sent(None);
+                                DirectResponse::Welcome(Box::new(welcome))
+                            }
+                            Err(e) => {
+                                println!("{}", e);
+                                continue;
+                            }
+                        }
+                    }
+                    DirectRequest::Message(chat_message) => {
+                        let id = chat_message.id;
+                        receive_direct_message(&mut swarm, &mut state, peer, chat_message);
+                        DirectResponse::Ack { id }
+                    }
+                    // Each message is taken as if it came on its own, in
+                    // order. Those over the rate limit go unacknowledged, for
+                    // the sender to try again.
+                    DirectRequest::Batch { messages } => {
+                        let mut ids = Vec::new();
+                        for (index, chat_message) in messages.into_iter().enumerate() {
+                            // The first was counted with the request.
+                            if index > 0 && !within_rate_limit(&mut swarm, &mut state, peer) {
+                                break;
+                            }
+                            let id = chat_message.id;
+                            if state.local_chat_messages.get(&id).is_none() {
+                                if !verify_direct(&mut swarm, &mut state, peer, &chat_message) {
+                                    continue;
+                                }
+                                state.counters.message_received(None);
+                                receive_direct_message(&mut swarm, &mut state, peer, chat_message);
+                            }
+                            ids.push(id);
+                        }
+                        DirectResponse::Acks { ids }
+                    }
+                    // Neither notified nor handed to bots, which might answer.
+                    DirectRequest::System(chat_message) => {
+                        let id = chat_message.id;
+                        let line = format!(
+                            "(auto-reply) {}",
+                            state.local_chat_messages.format(&chat_message)
+                        );
+                        if state.store_message(chat_message.clone()) {
+                            state.print_batched(id, line);
+                        }
 
-                println!("{:?}", local_chat_messages);
+                        DirectResponse::Ack { id }
+                    }
+                    DirectRequest::Forwarded(mut chat_message) => {
+                        let id = chat_message.id;
+                        if !state.local_chat_messages.filter().drops(&chat_message) {
+                            chat_message.mentions_me = state.mentions_us(&chat_message.message);
+                            let line = format!(
+                                "(direct via {}) {}",
+                                short_peer_id(&peer),
+                                state.local_chat_messages.format(&chat_message)
+                            );
+                            if state.store_message(chat_message.clone()) {
+                                let _ = state.events.send(ChatEvent::MessageReceived {
+                                    message: chat_message.clone(),
+                                });
+                                state.print_incoming(&line, &chat_message);
+                            }
+                        }
 
-                let chat_message = ChatMessage {
-                    peer_id: *swarm.local_peer_id(),
-                    message: format!("Welcome {}!, I am {}", peer, swarm.local_peer_id()),
+                        DirectResponse::Ack { id }
+                    }
+                    DirectRequest::StoreForward {
+                        target,
+                        message,
+                        expires_at,
+                    } => {
+                        let id = message.id;
+                        let stored = match &mut state.forward_store {
+                            Some(forward_store) => {
+                                forward_store.store(target, message, expires_at, unix_now())
+                            }
+                            None => Err(StoreError::NotForwarding),
+                        };
+                        match stored {
+                            Ok(()) => {
+                                println!(
+                                    "Holding message [{}] from {} for {}",
+                                    &id.simple().to_string()[..8],
+                                    short_peer_id(&peer),
+                                    short_peer_id(&target)
+                                );
+                                if swarm.is_connected(&target)
+                                    && state.peer_protocols.get(&target)
+                                        == Some(&ProtocolVersion::V1)
+                                {
+                                    deliver_stored(&mut swarm, &mut state, target);
+                                }
+                                DirectResponse::Stored { id }
+                            }
+                            Err(reason) => DirectResponse::Refused { id, reason },
+                        }
+                    }
+                    DirectRequest::PeerExchange => {
+                        let peers = match state.exchange_limiter.check(peer, Instant::now()) {
+                            Decision::Allow => peer_exchange::share(
+                                &state.address_book,
+                                &peer,
+                                &state.blocked,
+                                unix_now(),
+                            ),
+                            // Still answered, so the peer doesn't wait for a
+                            // timeout, but with nothing to amplify.
+                            Decision::Drop { .. } | Decision::Block => Vec::new(),
+                        };
+                        DirectResponse::Peers { peers }
+                    }
+                    DirectRequest::Profile => DirectResponse::Profile(state.public_profile.clone()),
                 };
 
-                swarm
-                    .behaviour_mut()
-                    .request_response
-                    .send_response(
-                        channel,
-                        Response {
-                            data: json!(chat_message),
-                        },
-                    )
-                    .expect("Response failed to send");
+                send_response(&mut swarm, &mut state, peer, channel, response);
             }
             SwarmEvent::Behaviour(CustomBehaviourEvent::RequestResponse(
                 request_response::Event::Message {
                     peer,
-                    message: request_response::Message::Response { response, .. },
+                    message:
+                        request_response::Message::Response {
+                            request_id,
+                            response,
+                        },
+                },
+            )) => {
+                state.counters.response_received(request_id);
+                let sent = state
+                    .direct_messages
+                    .remove(&request_id)
+                    .unwrap_or_default();
+                let response = envelope::open::<DirectResponse>(response.data);
+                if let Ok(Opened::Known(DirectResponse::Acks { ids })) = &response {
+                    for id in sent {
+                        if !ids.contains(&id) {
+                            direct_message_failed(&mut swarm, &mut state, id, &"not acknowledged");
+                            continue;
+                        }
+                        state.retries.delivered(&id);
+                        state
+                            .local_chat_messages
+                            .set_status(id, DeliveryStatus::Delivered);
+                        let _ = state.events.send(ChatEvent::DeliveryConfirmed { peer, id });
+                    }
+                } else {
+                    // Whatever the answer, the message arrived.
+                    for id in sent {
+                        state.retries.delivered(&id);
+                    }
+                }
+                match response {
+                    Ok(Opened::Unknown { version, kind }) => {
+                        handler::report_unknown(&state.events, peer, version, kind)
+                    }
+                    Ok(Opened::Known(DirectResponse::Welcome(chat_message)))
+                        if verify_direct(&mut swarm, &mut state, peer, &chat_message) =>
+                    {
+                        state.counters.message_received(None);
+                        println!("Response data: {:?}", chat_message);
+                        println!("From: {}", short_peer_id(&peer));
+                    }
+                    Ok(Opened::Known(DirectResponse::Ack { id })) => {
+                        let recipient = match state.forwarding.remove(&request_id) {
+                            Some((target, _)) => {
+                                println!(
+                                    "Delivered held message [{}] to {}",
+                                    &id.simple().to_string()[..8],
+                                    short_peer_id(&target)
+                                );
+                                target
+                            }
+                            None => {
+                                state
+                                    .local_chat_messages
+                                    .set_status(id, DeliveryStatus::Delivered);
+                                peer
+                            }
+                        };
+                        let _ = state.events.send(ChatEvent::DeliveryConfirmed {
+                            peer: recipient,
+                            id,
+                        });
+                    }
+                    Ok(Opened::Known(DirectResponse::Stored { id })) => println!(
+                        "Message [{}] is held by {} until its recipient connects",
+                        &id.simple().to_string()[..8],
+                        short_peer_id(&peer)
+                    ),
+                    Ok(Opened::Known(DirectResponse::Refused {
+                        reason: StoreError::NotForwarding,
+                        ..
+                    })) => {}
+                    Ok(Opened::Known(DirectResponse::Refused { id, reason })) => println!(
+                        "{} won't hold message [{}]: {}",
+                        short_peer_id(&peer),
+                        &id.simple().to_string()[..8],
+                        reason
+                    ),
+                    Ok(Opened::Known(DirectResponse::Welcome(_) | DirectResponse::Acks { .. })) => {
+                    }
+                    Ok(Opened::Known(DirectResponse::Peers { peers })) => {
+                        learn_peers(&mut swarm, &mut state, peer, peers);
+                    }
+                    Ok(Opened::Known(DirectResponse::Profile(profile))) => {
+                        state.fetching_profiles.remove(&peer);
+                        if let Err(e) = state.peer_profiles.store(peer, profile) {
+                            println!("Ignoring the profile of {}: {}", short_peer_id(&peer), e);
+                        }
+                    }
+                    Ok(Opened::Known(DirectResponse::Unsupported { request_kind })) => {
+                        state.fetching_profiles.remove(&peer);
+                        println!(
+                            "{} doesn't support {} requests",
+                            short_peer_id(&peer),
+                            request_kind
+                        )
+                    }
+                    Err(e) => {
+                        println!("Invalid response from {}: {}", short_peer_id(&peer), e);
+                        report_violation(
+                            &mut swarm,
+                            &mut state,
+                            peer,
+                            Violation::MalformedResponse,
+                        );
+                    }
+                }
+            }
+            SwarmEvent::Behaviour(CustomBehaviourEvent::RequestResponse(
+                request_response::Event::OutboundFailure {
+                    peer,
+                    request_id,
+                    error,
                 },
             )) => {
-                println!("Response data: {:?}", response.data);
-                println!("From: {}", peer);
+                state.counters.outbound_failure(request_id);
+                state.fetching_profiles.remove(&peer);
+                if let Some(ids) = state.direct_messages.remove(&request_id) {
+                    for id in ids {
+                        direct_message_failed(&mut swarm, &mut state, id, &error);
+                    }
+                    continue;
+                }
+                println!("Request to {} failed: {}", short_peer_id(&peer), error);
+                // Keep the message for the peer's next connection.
+                if let (Some((target, stored)), Some(forward_store)) = (
+                    state.forwarding.remove(&request_id),
+                    &mut state.forward_store,
+                ) {
+                    let _ =
+                        forward_store.store(target, stored.message, stored.expires_at, unix_now());
+                }
+            }
+            SwarmEvent::Behaviour(CustomBehaviourEvent::RequestResponse(
+                request_response::Event::InboundFailure { peer, error, .. },
+            )) => {
+                println!("Request from {} failed: {}", short_peer_id(&peer), error);
+                state.counters.inbound_failure();
+            }
+            SwarmEvent::Behaviour(CustomBehaviourEvent::Gossipsub(gossipsub::Event::Message {
+                propagation_source,
+                message_id,
+                message,
+            })) => {
+                handler::gossip_message(
+                    &mut Live::new(&mut swarm, &mut state),
+                    propagation_source,
+                    message_id,
+                    message,
+                );
+            }
+            SwarmEvent::Behaviour(CustomBehaviourEvent::Gossipsub(
+                gossipsub::Event::Subscribed { peer_id, topic },
+            )) => {
+                handler::peer_subscribed(&mut Live::new(&mut swarm, &mut state), peer_id, topic);
+            }
+            SwarmEvent::Behaviour(CustomBehaviourEvent::Gossipsub(
+                gossipsub::Event::Unsubscribed { peer_id, topic },
+            )) => {
+                handler::peer_unsubscribed(&mut Live::new(&mut swarm, &mut state), peer_id, topic);
+            }
+            SwarmEvent::Behaviour(CustomBehaviourEvent::Upnp(event)) => {
+                handle_upnp_event(&mut swarm, &mut state, event);
+            }
+            SwarmEvent::Behaviour(CustomBehaviourEvent::Kademlia(event)) => {
+                handle_kad_event(&mut swarm, &mut state, event);
+            }
+            SwarmEvent::Behaviour(CustomBehaviourEvent::Rendezvous(event)) => {
+                handle_rendezvous_event(&mut swarm, &mut state, event);
+            }
+            SwarmEvent::Behaviour(CustomBehaviourEvent::RendezvousServer(
+                rendezvous::server::Event::PeerRegistered { peer, registration },
+            )) => {
+                println!(
+                    "{} registered in {} for {}s",
+                    short_peer_id(&peer),
+                    registration.namespace,
+                    registration.ttl
+                );
             }
             _ => {}
         }
     }
+
+    shut_down(&mut swarm, &mut state).await;
+    Ok(())
+}
+
+/// Closes every connection and saves what is only held in memory. Ctrl-C,
+/// SIGTERM and `/quit` all end here.
+async fn shut_down(swarm: &mut Swarm<CustomBehaviour>, state: &mut AppState) {
+    let peers: Vec<PeerId> = swarm.connected_peers().copied().collect();
+    for peer in &peers {
+        let _ = swarm.disconnect_peer_id(*peer);
+    }
+    // The connections only close as the swarm is polled.
+    let closing = async {
+        while swarm.network_info().num_peers() > 0 {
+            swarm.select_next_some().await;
+        }
+    };
+    if tokio::time::timeout(SHUTDOWN_GRACE, closing).await.is_err() {
+        println!("Some connections didn't close in time; dropping them");
+    }
+
+    state.flush_output();
+    if let Err(e) = state.replays.save(&state.sequences_path) {
+        println!("Failed to save sequence numbers: {}", e);
+    }
+    match peers.len() {
+        0 => println!("Goodbye!"),
+        1 => println!("Disconnected from 1 peer. Goodbye!"),
+        count => println!("Disconnected from {} peers. Goodbye!", count),
+    }
 }
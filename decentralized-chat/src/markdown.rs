@@ -0,0 +1,171 @@
+/// How a span of text is styled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Style {
+    pub bold: bool,
+    pub italic: bool,
+    pub code: bool,
+}
+
+impl Style {
+    pub const PLAIN: Style = Style {
+        bold: false,
+        italic: false,
+        code: false,
+    };
+}
+
+/// A run of text with a single style, shaped like `ratatui::text::Span` so
+/// interfaces that can style text can map it directly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub content: String,
+    pub style: Style,
+}
+
+impl Span {
+    pub fn styled(content: impl Into<String>, style: Style) -> Self {
+        Span {
+            content: content.into(),
+            style,
+        }
+    }
+
+    pub fn plain(content: impl Into<String>) -> Self {
+        Span::styled(content, Style::PLAIN)
+    }
+}
+
+/// Splits `text` into styled spans, understanding `**bold**`, `*italic*`,
+/// `_italic_` and `` `inline code` ``. Text with an unbalanced or empty marker
+/// is returned as a single plain span rather than guessing what was meant.
+pub fn parse(text: &str) -> Vec<Span> {
+    parse_styled(text).unwrap_or_else(|| {
+        if text.is_empty() {
+            Vec::new()
+        } else {
+            vec![Span::plain(text)]
+        }
+    })
+}
+
+/// Whether `c` has a meaning here and can be escaped with a backslash.
+fn is_marker(c: char) -> bool {
+    matches!(c, '*' | '_' | '`' | '\\')
+}
+
+/// Tracks whether one kind of emphasis marker is open.
+#[derive(Debug, Default)]
+struct Emphasis {
+    /// How many characters had been written when the marker opened, or
+    /// `None` while it is closed.
+    opened_at: Option<usize>,
+}
+
+impl Emphasis {
+    fn is_open(&self) -> bool {
+        self.opened_at.is_some()
+    }
+
+    /// Opens or closes the marker. Returns `None` when it would close
+    /// around nothing.
+    fn toggle(&mut self, written: usize) -> Option<()> {
+        match self.opened_at.take() {
+            Some(opened_at) if opened_at == written => None,
+            Some(_) => Some(()),
+            None => {
+                self.opened_at = Some(written);
+                Some(())
+            }
+        }
+    }
+}
+
+/// A single left-to-right pass; `None` means the markers don't balance.
+fn parse_styled(text: &str) -> Option<Vec<Span>> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut spans = Vec::new();
+    let mut current = String::new();
+    let mut written = 0;
+    let mut bold = Emphasis::default();
+    let mut star_italic = Emphasis::default();
+    let mut underscore_italic = Emphasis::default();
+
+    let mut i = 0;
+    while i < chars.len() {
+        let style = Style {
+            bold: bold.is_open(),
+            italic: star_italic.is_open() || underscore_italic.is_open(),
+            code: false,
+        };
+
+        match chars[i] {
+            '\\' if chars.get(i + 1).copied().is_some_and(is_marker) => {
+                current.push(chars[i + 1]);
+                written += 1;
+                i += 2;
+            }
+            '`' => {
+                let len = chars[i + 1..].iter().position(|&c| c == '`')?;
+                if len == 0 {
+                    return None;
+                }
+                flush(&mut spans, &mut current, style);
+                let code: String = chars[i + 1..i + 1 + len].iter().collect();
+                push(
+                    &mut spans,
+                    code,
+                    Style {
+                        code: true,
+                        ..style
+                    },
+                );
+                written += len;
+                i += len + 2;
+            }
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                flush(&mut spans, &mut current, style);
+                bold.toggle(written)?;
+                i += 2;
+            }
+            '*' => {
+                flush(&mut spans, &mut current, style);
+                star_italic.toggle(written)?;
+                i += 1;
+            }
+            // An underscore inside a word, as in snake_case, is just text.
+            '_' if !(i > 0
+                && chars[i - 1].is_alphanumeric()
+                && chars.get(i + 1).is_some_and(|c| c.is_alphanumeric())) =>
+            {
+                flush(&mut spans, &mut current, style);
+                underscore_italic.toggle(written)?;
+                i += 1;
+            }
+            c => {
+                current.push(c);
+                written += 1;
+                i += 1;
+            }
+        }
+    }
+
+    if bold.is_open() || star_italic.is_open() || underscore_italic.is_open() {
+        return None;
+    }
+    flush(&mut spans, &mut current, Style::PLAIN);
+    Some(spans)
+}
+
+fn flush(spans: &mut Vec<Span>, current: &mut String, style: Style) {
+    if !current.is_empty() {
+        push(spans, std::mem::take(current), style);
+    }
+}
+
+/// Appends a span, merging it into the previous one if they share a style.
+fn push(spans: &mut Vec<Span>, content: String, style: Style) {
+    match spans.last_mut() {
+        Some(last) if last.style == style => last.content.push_str(&content),
+        _ => spans.push(Span::styled(content, style)),
+    }
+}
@@ -0,0 +1,176 @@
+use crate::message::unix_now;
+use libp2p::PeerId;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// Whom we know to be in each of our rooms. Peers we are connected to show
+/// up through their gossipsub subscriptions; the others through the
+/// presence heartbeats, join and leave announcements and chat messages they
+/// publish. A peer we neither see subscribed nor hear from for a while is
+/// taken to have left.
+#[derive(Debug)]
+pub struct RoomMembers {
+    heartbeat: Duration,
+    stale_after: Duration,
+    rooms: HashMap<String, HashMap<PeerId, Member>>,
+}
+
+#[derive(Debug)]
+struct Member {
+    /// Unix time we first saw the peer in the room.
+    joined_at: u64,
+    last_heard: Instant,
+    /// Whether gossipsub reports the peer subscribed, which only connected
+    /// peers can be.
+    subscribed: bool,
+}
+
+/// A member of a room as `/members` lists it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Membership {
+    pub peer: PeerId,
+    pub joined_at: u64,
+    /// Whether the peer is subscribed or missed at most one heartbeat.
+    pub online: bool,
+}
+
+impl RoomMembers {
+    /// Members are expected to be heard from every `heartbeat`, and are
+    /// dropped once they weren't for `stale_after`.
+    pub fn new(heartbeat: Duration, stale_after: Duration) -> Self {
+        RoomMembers {
+            heartbeat,
+            stale_after,
+            rooms: HashMap::new(),
+        }
+    }
+
+    /// Starts tracking the members of `room`, which we joined.
+    pub fn join(&mut self, room: &str) {
+        self.rooms.entry(room.to_string()).or_default();
+    }
+
+    /// Forgets the members of `room`, which we left.
+    pub fn leave(&mut self, room: &str) {
+        self.rooms.remove(room);
+    }
+
+    /// The rooms we are in, sorted.
+    pub fn rooms(&self) -> Vec<String> {
+        let mut rooms: Vec<String> = self.rooms.keys().cloned().collect();
+        rooms.sort();
+        rooms
+    }
+
+    /// Records that `peer` showed it is in `room` at `now`. Returns whether
+    /// it is new there. Rooms we aren't in are ignored.
+    pub fn heard(&mut self, room: &str, peer: PeerId, now: Instant) -> bool {
+        let Some(members) = self.rooms.get_mut(room) else {
+            return false;
+        };
+        match members.get_mut(&peer) {
+            Some(member) => {
+                member.last_heard = now;
+                false
+            }
+            None => {
+                members.insert(
+                    peer,
+                    Member {
+                        joined_at: unix_now(),
+                        last_heard: now,
+                        subscribed: false,
+                    },
+                );
+                true
+            }
+        }
+    }
+
+    /// Records that gossipsub reports `peer` subscribed to `room`. Returns
+    /// whether it is new there.
+    pub fn subscribed(&mut self, room: &str, peer: PeerId, now: Instant) -> bool {
+        let new = self.heard(room, peer, now);
+        if let Some(member) = self
+            .rooms
+            .get_mut(room)
+            .and_then(|members| members.get_mut(&peer))
+        {
+            member.subscribed = true;
+        }
+        new
+    }
+
+    /// Records that `peer` left `room`. Returns whether it was a member.
+    pub fn left(&mut self, room: &str, peer: &PeerId) -> bool {
+        self.rooms
+            .get_mut(room)
+            .is_some_and(|members| members.remove(peer).is_some())
+    }
+
+    /// Takes in a heartbeat from `peer` listing every room it is in. Returns
+    /// the rooms of ours it is new in and those it left.
+    pub fn presence(
+        &mut self,
+        peer: PeerId,
+        rooms: &[String],
+        now: Instant,
+    ) -> (Vec<String>, Vec<String>) {
+        let (mut joined, mut left) = (Vec::new(), Vec::new());
+        for room in self.rooms() {
+            if rooms.contains(&room) {
+                if self.heard(&room, peer, now) {
+                    joined.push(room);
+                }
+            } else if self.left(&room, &peer) {
+                left.push(room);
+            }
+        }
+        (joined, left)
+    }
+
+    /// Records that our last connection to `peer` closed, so its
+    /// subscriptions no longer keep it a member.
+    pub fn disconnected(&mut self, peer: &PeerId) {
+        for members in self.rooms.values_mut() {
+            if let Some(member) = members.get_mut(peer) {
+                member.subscribed = false;
+            }
+        }
+    }
+
+    /// Drops the members that aren't subscribed and weren't heard from for
+    /// `stale_after` by `now`. Returns them with their rooms.
+    pub fn expire(&mut self, now: Instant) -> Vec<(String, PeerId)> {
+        let mut expired = Vec::new();
+        for (room, members) in &mut self.rooms {
+            members.retain(|peer, member| {
+                if member.subscribed || now.duration_since(member.last_heard) < self.stale_after {
+                    return true;
+                }
+                expired.push((room.clone(), *peer));
+                false
+            });
+        }
+        expired
+    }
+
+    /// The members of `room` as of `now`, longest there first, or `None` if
+    /// we aren't in it.
+    pub fn members(&self, room: &str, now: Instant) -> Option<Vec<Membership>> {
+        let members = self.rooms.get(room)?;
+        let mut members: Vec<Membership> = members
+            .iter()
+            .map(|(peer, member)| Membership {
+                peer: *peer,
+                joined_at: member.joined_at,
+                online: member.subscribed
+                    || now.duration_since(member.last_heard) < self.heartbeat * 2,
+            })
+            .collect();
+        members.sort_by_key(|member| (member.joined_at, member.peer.to_bytes()));
+        Some(members)
+    }
+}
@@ -0,0 +1,81 @@
+use libp2p::PeerId;
+
+/// Whether `text` mentions `nickname` as `@nickname`. The match ignores case
+/// but must cover the whole name, so `@bobby` and `alice@bob.com` don't
+/// mention `bob`.
+pub fn mentions(text: &str, nickname: &str) -> bool {
+    if nickname.is_empty() {
+        return false;
+    }
+
+    text.match_indices('@').any(|(at, _)| {
+        let before = text[..at].chars().next_back();
+        if before.is_some_and(is_name_char) {
+            return false;
+        }
+
+        let rest = &text[at + 1..];
+        let Some(name) = rest.get(..nickname.len()) else {
+            return false;
+        };
+        name.eq_ignore_ascii_case(nickname)
+            && !rest[nickname.len()..]
+                .chars()
+                .next()
+                .is_some_and(is_name_char)
+    })
+}
+
+/// Shortest peer id prefix taken for a mention, long enough to get past the
+/// `12D3KooW` every Ed25519 peer id starts with.
+const MIN_PEER_PREFIX: usize = 12;
+
+/// Whether `text` mentions `peer` as `@` followed by at least the first
+/// `MIN_PEER_PREFIX` characters of its id, for peers without a nickname.
+/// Peer ids are case-sensitive, unlike nicknames.
+pub fn mentions_peer(text: &str, peer: &PeerId) -> bool {
+    let id = peer.to_string();
+    text.match_indices('@').any(|(at, _)| {
+        if text[..at].chars().next_back().is_some_and(is_name_char) {
+            return false;
+        }
+        let rest = &text[at + 1..];
+        let end = rest.find(|c| !is_name_char(c)).unwrap_or(rest.len());
+        let prefix = &rest[..end];
+        prefix.len() >= MIN_PEER_PREFIX && id.starts_with(prefix)
+    })
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-'
+}
+
+/// Counts mentions that arrived since the user last typed something.
+#[derive(Debug, Default)]
+pub struct Mentions {
+    unread: usize,
+}
+
+impl Mentions {
+    pub fn received(&mut self) {
+        self.unread += 1;
+    }
+
+    pub fn mark_read(&mut self) {
+        self.unread = 0;
+    }
+
+    pub fn unread(&self) -> usize {
+        self.unread
+    }
+
+    /// A prompt showing the unread count, or an empty string when there are
+    /// none.
+    pub fn prompt(&self) -> String {
+        match self.unread {
+            0 => String::new(),
+            1 => "(1 unread mention) > ".to_string(),
+            unread => format!("({} unread mentions) > ", unread),
+        }
+    }
+}
@@ -0,0 +1,451 @@
+use crate::{
+    envelope::Kinds, fingerprint::embedded_key, forward::StoreError, peer_exchange::PeerRecord,
+    peer_id::short_peer_id, peer_profile::PeerProfile,
+};
+use libp2p::{
+    identity::{self, PublicKey, SigningError},
+    PeerId,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use uuid::Uuid;
+
+pub type MessageId = Uuid;
+
+/// Prefixed to the signed bytes so a message signature can't be passed off as
+/// a signature over anything else.
+const SIGNATURE_DOMAIN: &[u8] = b"decentralized-chat/message/v1:";
+
+/// Most seconds a message may be sent to expire in.
+pub const MAX_EXPIRES_IN_SECS: u64 = 365 * 24 * 60 * 60;
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub id: MessageId,
+    pub peer_id: PeerId,
+    /// Name the author chose with `/nick`, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nickname: Option<String>,
+    pub message: String,
+    /// Room the message was published to; unset for direct messages.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub room: Option<String>,
+    /// The peer a direct message was sent to; unset for room messages and
+    /// by peers that predate it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recipient: Option<PeerId>,
+    /// Unix timestamp (seconds) at which the author sent the message.
+    #[serde(default)]
+    pub timestamp: u64,
+    /// The message this one replies to, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<MessageId>,
+    /// Unix timestamp (seconds) after which the message must be discarded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    /// Seconds the message lives for: each receiver removes it this long
+    /// after taking it in, and it isn't handed on once this long has passed
+    /// since it was sent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl_secs: Option<u64>,
+    /// Where the message falls among the ones its author sent, so receivers
+    /// can reject replays. Unset by peers that predate it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<Sequence>,
+    /// Texts that replaced the original message, oldest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub edits: Vec<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub deleted: bool,
+    /// Whether the message mentions our nickname or peer id. Set by us on
+    /// receipt, whatever the sender put there.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub mentions_me: bool,
+    /// Signature by `peer_id` over the fields the author sets; see `sign`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub signature: Vec<u8>,
+    /// The author's public key, protobuf encoded, for peer ids that are a
+    /// hash of it rather than embedding it, such as RSA and ECDSA ones. It
+    /// is checked against `peer_id`, so it needn't be signed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub public_key: Vec<u8>,
+}
+
+/// Numbers the messages a node sends. `seq` counts up from 1 each time the
+/// node starts, which begins a new `session`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Sequence {
+    pub session: Uuid,
+    pub seq: u64,
+}
+
+impl Sequence {
+    /// The sequence before the first message of a new session.
+    pub fn start() -> Self {
+        Sequence {
+            session: Uuid::new_v4(),
+            seq: 0,
+        }
+    }
+
+    /// Moves on to the next message and returns its sequence.
+    pub fn advance(&mut self) -> Sequence {
+        self.seq += 1;
+        *self
+    }
+}
+
+/// The part of a message covered by its signature, including where it was
+/// sent so it can't be replayed into another room or to another peer. Edits
+/// and deletion change after sending and are authenticated separately.
+#[derive(Serialize)]
+struct SignedContent<'a> {
+    id: &'a MessageId,
+    peer_id: &'a PeerId,
+    nickname: &'a Option<String>,
+    message: &'a str,
+    timestamp: u64,
+    reply_to: &'a Option<MessageId>,
+    expires_at: &'a Option<u64>,
+    // Left out when unset so messages signed before sequences existed still
+    // verify.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sequence: &'a Option<Sequence>,
+    // Likewise for messages signed before TTLs existed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ttl_secs: &'a Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    room: &'a Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recipient: &'a Option<PeerId>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SignatureError {
+    Missing,
+    /// The peer id doesn't embed a public key to verify against.
+    UnknownKey,
+    Invalid,
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureError::Missing => write!(f, "message is not signed"),
+            SignatureError::UnknownKey => write!(f, "no public key for the sender's peer id"),
+            SignatureError::Invalid => write!(f, "signature does not match the sender"),
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {}
+
+impl ChatMessage {
+    pub fn new(peer_id: PeerId, message: String) -> Self {
+        ChatMessage {
+            id: Uuid::new_v4(),
+            peer_id,
+            nickname: None,
+            message,
+            room: None,
+            recipient: None,
+            timestamp: unix_now(),
+            reply_to: None,
+            expires_at: None,
+            ttl_secs: None,
+            sequence: None,
+            edits: Vec::new(),
+            deleted: false,
+            mentions_me: false,
+            signature: Vec::new(),
+            public_key: Vec::new(),
+        }
+    }
+
+    fn signed_bytes(&self) -> Vec<u8> {
+        let content = SignedContent {
+            id: &self.id,
+            peer_id: &self.peer_id,
+            nickname: &self.nickname,
+            message: &self.message,
+            timestamp: self.timestamp,
+            reply_to: &self.reply_to,
+            expires_at: &self.expires_at,
+            sequence: &self.sequence,
+            ttl_secs: &self.ttl_secs,
+            room: &self.room,
+            recipient: &self.recipient,
+        };
+        let mut bytes = SIGNATURE_DOMAIN.to_vec();
+        bytes.extend(serde_json::to_vec(&content).expect("message content serializes"));
+        bytes
+    }
+
+    /// Signs the message with `keypair`, which must belong to `peer_id`,
+    /// attaching its public key if `peer_id` doesn't embed it. Call this
+    /// last, once every signed field has its final value.
+    pub fn sign(&mut self, keypair: &identity::Keypair) -> Result<(), SigningError> {
+        self.signature = keypair.sign(&self.signed_bytes())?;
+        self.public_key = match embedded_key(&self.peer_id) {
+            Some(_) => Vec::new(),
+            None => keypair.public().encode_protobuf(),
+        };
+        Ok(())
+    }
+
+    /// Checks that the message was signed by the key behind its `peer_id`.
+    pub fn verify_signature(&self) -> Result<(), SignatureError> {
+        if self.signature.is_empty() {
+            return Err(SignatureError::Missing);
+        }
+
+        let public_key = embedded_key(&self.peer_id)
+            .or_else(|| PublicKey::try_decode_protobuf(&self.public_key).ok())
+            .ok_or(SignatureError::UnknownKey)?;
+
+        if public_key.to_peer_id() == self.peer_id
+            && public_key.verify(&self.signed_bytes(), &self.signature)
+        {
+            Ok(())
+        } else {
+            Err(SignatureError::Invalid)
+        }
+    }
+
+    /// Unix timestamp (seconds) from which the message is expired: its
+    /// `expires_at`, or its TTL past when it was sent, whichever is sooner.
+    pub fn expiry(&self) -> Option<u64> {
+        let by_ttl = self
+            .ttl_secs
+            .map(|ttl_secs| self.timestamp.saturating_add(ttl_secs));
+        match (self.expires_at, by_ttl) {
+            (Some(expires_at), Some(by_ttl)) => Some(expires_at.min(by_ttl)),
+            (expires_at, by_ttl) => expires_at.or(by_ttl),
+        }
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expiry().is_some_and(|expiry| expiry <= now)
+    }
+
+    /// How long after being taken in at `now` the message is to be removed:
+    /// its TTL, cut short if it expires sooner. None if it never does.
+    pub fn time_to_live(&self, now: u64) -> Option<Duration> {
+        let left = self.expiry()?.saturating_sub(now);
+        let secs = self.ttl_secs.map_or(left, |ttl_secs| ttl_secs.min(left));
+        Some(Duration::from_secs(secs))
+    }
+
+    /// The nickname if the author set one, otherwise their shortened peer id.
+    pub fn sender(&self) -> String {
+        match &self.nickname {
+            Some(nickname) => nickname.clone(),
+            None => short_peer_id(&self.peer_id),
+        }
+    }
+
+    /// First eight characters of the id, enough to reference a message from the prompt.
+    pub fn short_id(&self) -> String {
+        self.id.simple().to_string()[..8].to_string()
+    }
+
+    /// The latest edit, or the original text if the message was never edited.
+    pub fn text(&self) -> &str {
+        self.edits.last().unwrap_or(&self.message)
+    }
+
+    /// The text as it should be shown: the latest edit, or a tombstone once deleted.
+    pub fn display_text(&self) -> String {
+        if self.deleted {
+            return "[deleted]".to_string();
+        }
+
+        if self.edits.is_empty() {
+            self.message.clone()
+        } else {
+            format!("{} (edited)", self.text())
+        }
+    }
+
+    /// A single-line excerpt of the text, used when quoting this message.
+    pub fn excerpt(&self, max_chars: usize) -> String {
+        let text = self.display_text().replace('\n', " ");
+        if text.chars().count() <= max_chars {
+            return text;
+        }
+
+        let truncated: String = text.chars().take(max_chars).collect();
+        format!("{}…", truncated)
+    }
+}
+
+/// Everything published on the chat topic.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum GossipMessage {
+    Chat(Box<ChatMessage>),
+    Edit {
+        target_id: MessageId,
+        new_text: String,
+    },
+    Delete {
+        target_id: MessageId,
+    },
+    Reaction {
+        target_id: MessageId,
+        emoji: String,
+    },
+    /// Tells the rooms the author now goes by `name`, sent on `/nick`. It is
+    /// not a chat message and isn't kept in the history.
+    Nickname {
+        name: String,
+    },
+    /// Tells the rooms the author changed its status or avatar, sent on
+    /// `/profile`, so peers drop their cached copy and fetch it again.
+    ProfileUpdated,
+    /// Tells a room the author joined it, sent on `/join`.
+    Joined,
+    /// Tells a room the author is leaving it, sent on `/leave`.
+    Left,
+    /// Sent to every room the author is in at a regular interval, listing
+    /// them all, so members of the rooms know it is still there.
+    Presence {
+        rooms: Vec<String>,
+    },
+}
+
+impl Kinds for GossipMessage {
+    const KINDS: &'static [&'static str] = &[
+        "chat",
+        "edit",
+        "delete",
+        "reaction",
+        "nickname",
+        "profile_updated",
+        "joined",
+        "left",
+        "presence",
+    ];
+}
+
+/// Payload of a request on the direct request-response protocol.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DirectRequest {
+    /// Sent to every newly discovered peer.
+    Greeting(ChatMessage),
+    /// A message sent with `/msg`.
+    Message(ChatMessage),
+    /// Asks a forwarder to hold a `/msg` for `target`, who is offline, until
+    /// `expires_at`.
+    StoreForward {
+        target: PeerId,
+        message: ChatMessage,
+        expires_at: u64,
+    },
+    /// A message a forwarder held for us while we were offline. It is signed
+    /// by its author, not by the forwarder.
+    Forwarded(ChatMessage),
+    /// Asks for peers the receiver has seen recently, sent on every new
+    /// connection and with `/px`.
+    PeerExchange,
+    /// A message the author's node sent on its own, such as a do-not-disturb
+    /// auto-reply. It is shown like a direct message but never answered
+    /// automatically, so two nodes can't keep replying to each other.
+    System(ChatMessage),
+    /// Asks for the receiver's status and avatar, sent to peers whose
+    /// profile isn't cached.
+    Profile,
+    /// `/msg`s sent in quick succession, oldest first, each handled as if
+    /// it came in its own `Message` and answered in `DirectResponse::Acks`.
+    Batch { messages: Vec<ChatMessage> },
+}
+
+impl Kinds for DirectRequest {
+    const KINDS: &'static [&'static str] = &[
+        "greeting",
+        "message",
+        "store_forward",
+        "forwarded",
+        "peer_exchange",
+        "system",
+        "profile",
+        "batch",
+    ];
+}
+
+impl DirectRequest {
+    /// The signed message the request carries, if any.
+    pub fn chat_message(&self) -> Option<&ChatMessage> {
+        match self {
+            DirectRequest::Greeting(chat_message)
+            | DirectRequest::Message(chat_message)
+            | DirectRequest::StoreForward {
+                message: chat_message,
+                ..
+            }
+            | DirectRequest::Forwarded(chat_message)
+            | DirectRequest::System(chat_message) => Some(chat_message),
+            DirectRequest::PeerExchange | DirectRequest::Profile | DirectRequest::Batch { .. } => {
+                None
+            }
+        }
+    }
+}
+
+/// Payload of a response on the direct request-response protocol.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DirectResponse {
+    Welcome(Box<ChatMessage>),
+    /// Confirms a direct message was received.
+    Ack {
+        id: MessageId,
+    },
+    /// The forwarder holds the message until its target connects.
+    Stored {
+        id: MessageId,
+    },
+    Refused {
+        id: MessageId,
+        reason: StoreError,
+    },
+    /// The request was of a kind the peer doesn't know.
+    Unsupported {
+        request_kind: String,
+    },
+    /// Answers a peer exchange. Empty when the requester asks too often.
+    Peers {
+        peers: Vec<PeerRecord>,
+    },
+    /// Answers a profile request.
+    Profile(PeerProfile),
+    /// Confirms which messages of a batch were received. Those missing were
+    /// refused, e.g. over the rate limit, and are worth sending again.
+    Acks {
+        ids: Vec<MessageId>,
+    },
+}
+
+impl Kinds for DirectResponse {
+    const KINDS: &'static [&'static str] = &[
+        "welcome",
+        "ack",
+        "stored",
+        "refused",
+        "unsupported",
+        "peers",
+        "profile",
+        "acks",
+    ];
+}
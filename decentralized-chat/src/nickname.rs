@@ -0,0 +1,48 @@
+use libp2p::PeerId;
+use std::collections::HashMap;
+
+/// Longest nickname accepted, in characters.
+pub const MAX_NICKNAME_LEN: usize = 32;
+
+/// Checks a nickname set with `/nick` or announced by a peer.
+pub fn validate_nickname(name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("nickname is empty".to_string());
+    }
+    if name.chars().count() > MAX_NICKNAME_LEN {
+        return Err(format!(
+            "nickname is longer than {} characters",
+            MAX_NICKNAME_LEN
+        ));
+    }
+    if name.chars().any(char::is_control) {
+        return Err("nickname contains control characters".to_string());
+    }
+    if name.trim() != name {
+        return Err("nickname starts or ends with whitespace".to_string());
+    }
+    Ok(())
+}
+
+/// The nickname each peer last announced or sent a message with.
+#[derive(Debug, Default)]
+pub struct Nicknames {
+    names: HashMap<PeerId, String>,
+}
+
+impl Nicknames {
+    /// Records `name` for `peer` if it is valid. Returns whether the peer
+    /// was known by another name, or none, before.
+    pub fn record(&mut self, peer: PeerId, name: &str) -> Result<bool, String> {
+        validate_nickname(name)?;
+        if self.get(&peer) == Some(name) {
+            return Ok(false);
+        }
+        self.names.insert(peer, name.to_string());
+        Ok(true)
+    }
+
+    pub fn get(&self, peer: &PeerId) -> Option<&str> {
+        self.names.get(peer).map(String::as_str)
+    }
+}
@@ -0,0 +1,121 @@
+use libp2p::PeerId;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// Longest message body shown in a notification, in characters.
+pub const BODY_LENGTH: usize = 80;
+
+/// Shortest time between two notifications for messages from one sender.
+pub const NOTIFICATION_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Shows desktop notifications for new messages, at most one per sender
+/// every `interval` so a burst of messages doesn't flood the desktop.
+/// Messages from the sender that arrive in between are summed up in its
+/// next notification.
+#[derive(Debug)]
+pub struct Notifier {
+    interval: Duration,
+    senders: HashMap<PeerId, Sender>,
+}
+
+#[derive(Debug)]
+struct Sender {
+    last_shown: Instant,
+    suppressed: usize,
+}
+
+impl Default for Notifier {
+    fn default() -> Self {
+        Notifier::new(NOTIFICATION_INTERVAL)
+    }
+}
+
+impl Notifier {
+    pub fn new(interval: Duration) -> Self {
+        Notifier {
+            interval,
+            senders: HashMap::new(),
+        }
+    }
+
+    /// Decides whether a message from `peer`, shown as `sender`, arriving at
+    /// `now` gets a notification, and if so returns its summary and body.
+    pub fn next(
+        &mut self,
+        now: Instant,
+        peer: PeerId,
+        sender: &str,
+        text: &str,
+    ) -> Option<(String, String)> {
+        let interval = self.interval;
+        // Senders quiet for longer than the interval have nothing to sum up.
+        self.senders.retain(|_, sender| {
+            now.duration_since(sender.last_shown) < interval || sender.suppressed > 0
+        });
+
+        let suppressed = match self.senders.get_mut(&peer) {
+            Some(known) if now.duration_since(known.last_shown) < interval => {
+                known.suppressed += 1;
+                return None;
+            }
+            Some(known) => std::mem::take(&mut known.suppressed),
+            None => 0,
+        };
+        self.senders.insert(
+            peer,
+            Sender {
+                last_shown: now,
+                suppressed: 0,
+            },
+        );
+
+        let mut body = truncate(text, BODY_LENGTH);
+        match suppressed {
+            0 => {}
+            1 => body.push_str("\n(and 1 more message)"),
+            suppressed => body.push_str(&format!("\n(and {} more messages)", suppressed)),
+        }
+        Some((sender.to_string(), body))
+    }
+
+    /// Shows a notification for a message from `peer` unless one was shown
+    /// for them too recently.
+    pub fn notify(&mut self, peer: PeerId, sender: &str, text: &str) {
+        if let Some((summary, body)) = self.next(Instant::now(), peer, sender, text) {
+            show(summary, body);
+        }
+    }
+}
+
+/// Sent from a separate thread since talking to the desktop can block, and
+/// failures such as no notification service are ignored.
+#[cfg(feature = "notifications")]
+fn show(summary: String, body: String) {
+    std::thread::spawn(move || {
+        let _ = notify_rust::Notification::new()
+            .summary(&summary)
+            .body(&body)
+            .show();
+    });
+}
+
+/// Builds without the `notifications` feature have no desktop to talk to.
+#[cfg(not(feature = "notifications"))]
+fn show(_summary: String, _body: String) {}
+
+/// Shortens `text` to at most `max_chars` characters, ending in `…` when
+/// something was cut.
+pub fn truncate(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some(_) => {
+            let keep = text
+                .char_indices()
+                .nth(max_chars.saturating_sub(1))
+                .map_or(0, |(index, _)| index);
+            format!("{}…", &text[..keep])
+        }
+        None => text.to_string(),
+    }
+}
@@ -0,0 +1,74 @@
+use crate::message::MessageId;
+use std::time::{Duration, Instant};
+
+/// Batches the lines printed for incoming messages, so a burst of hundreds
+/// of them is written a batch at a time instead of line by line. Only the
+/// display waits; the messages themselves are stored as they arrive.
+#[derive(Debug)]
+pub struct OutputBatch {
+    interval: Duration,
+    /// Lines waiting to be written, oldest first, by the message they show.
+    pending: Vec<(MessageId, String)>,
+    last_write: Option<Instant>,
+}
+
+impl OutputBatch {
+    /// Writes at most once every `interval`; a zero interval writes every
+    /// line at once.
+    pub fn new(interval: Duration) -> Self {
+        OutputBatch {
+            interval,
+            pending: Vec::new(),
+            last_write: None,
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Adds the rendered line for message `id`, replacing a line for the
+    /// same message still waiting, so quick edits and reactions only show
+    /// their latest state. Returns the lines to write now: `line` itself
+    /// when nothing was written within the interval, so a lone message isn't
+    /// held back, and nothing otherwise.
+    pub fn push(&mut self, id: MessageId, line: String, now: Instant) -> Vec<String> {
+        if let Some((_, waiting)) = self.pending.iter_mut().find(|(known, _)| *known == id) {
+            *waiting = line;
+            return Vec::new();
+        }
+
+        let quiet = self
+            .last_write
+            .is_none_or(|last| now.duration_since(last) >= self.interval);
+        if self.pending.is_empty() && quiet {
+            self.last_write = Some(now);
+            return vec![line];
+        }
+        self.pending.push((id, line));
+        Vec::new()
+    }
+
+    /// The waiting lines, oldest first, once the interval since the last
+    /// write has passed.
+    pub fn flush(&mut self, now: Instant) -> Vec<String> {
+        match self.last_write {
+            Some(last) if now.duration_since(last) < self.interval => Vec::new(),
+            _ => self.take(now),
+        }
+    }
+
+    /// The waiting lines, oldest first, whenever the last write was. For
+    /// output that must not appear ahead of them.
+    pub fn take(&mut self, now: Instant) -> Vec<String> {
+        if self.pending.is_empty() {
+            return Vec::new();
+        }
+        self.last_write = Some(now);
+        self.pending.drain(..).map(|(_, line)| line).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
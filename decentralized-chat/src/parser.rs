@@ -0,0 +1,705 @@
+use crate::{
+    command::Command,
+    dial::{check_dial_address, parse_multiaddr},
+    invite::MAX_INVITE_SECS,
+    message::MAX_EXPIRES_IN_SECS,
+    search::parse_date,
+};
+use libp2p::{Multiaddr, PeerId};
+use std::{fmt, path::PathBuf};
+
+/// A command the prompt understands, used both for dispatch and for `/help`.
+pub struct CommandSpec {
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub description: &'static str,
+    pub details: &'static str,
+}
+
+pub const COMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "/msg",
+        usage: "/msg <peer> <text>",
+        description: "Send a direct message to a peer",
+        details: "Delivers the text over the request-response protocol instead of the room, so only <peer> receives it.",
+    },
+    CommandSpec {
+        name: "/join",
+        usage: "/join <room> | --invite <token>",
+        description: "Join a room and make it the current room",
+        details: "Subscribes to the room's gossipsub topic. Lines not starting with / are sent to the current room. With --invite, joins the private room of a token from /invite, which is refused if it expired or was altered, and keeps the room's key.",
+    },
+    CommandSpec {
+        name: "/invite",
+        usage: "/invite [--new] <room> [seconds]",
+        description: "Invite someone to a private room",
+        details: "Prints a token for whoever you invite to run /join --invite with. It carries the room's secret, from which its key is derived, and is signed by you and valid for <seconds>, a day by default and a year at most. Only private rooms take invites; with --new, <room> is made a private room and joined, which is refused if it already has members who would be cut off. What you send to a private room is encrypted with its key, and what is sent to it without the key is dropped. Anyone holding the token can join, so hand it over privately.",
+    },
+    CommandSpec {
+        name: "/leave",
+        usage: "/leave <room>",
+        description: "Leave a room",
+        details: "Unsubscribes from the room's gossipsub topic and stops announcing us as a member. The current room can't be left; /join another one first.",
+    },
+    CommandSpec {
+        name: "/rooms",
+        usage: "/rooms",
+        description: "List the rooms you are in",
+        details: "Prints every joined room with the number of other members the DHT knows of, and marks the current room.",
+    },
+    CommandSpec {
+        name: "/members",
+        usage: "/members [room]",
+        description: "List who is in a room",
+        details: "Prints the other members of <room>, or the current room, with their nickname, whether they are online and when they were first seen there. Members are learned from their subscriptions, the heartbeats every node sends its rooms, and the messages they publish. Those not heard from for a while are dropped.",
+    },
+    CommandSpec {
+        name: "/mute",
+        usage: "/mute <room> [--mentions-only]",
+        description: "Stop showing a room's messages",
+        details: "Messages of the room are still stored and kept in the history, but aren't printed or notified. With --mentions-only, those mentioning your nickname or peer id are still shown. Direct messages can't be muted. The setting is kept across restarts.",
+    },
+    CommandSpec {
+        name: "/unmute",
+        usage: "/unmute <room>",
+        description: "Show every message of a room again",
+        details: "Undoes /mute for the room.",
+    },
+    CommandSpec {
+        name: "/history",
+        usage: "/history [room] [--peer <id>] [--since <date>] [--limit n] [--page n]",
+        description: "Show the latest messages of a room",
+        details: "Prints the latest n messages (20 by default) of <room>, or the current room, with their time and sender, oldest first. --peer keeps only those from one peer and --since those sent on or after a YYYY-MM-DD date in UTC. Page 2 holds the n before those, and so on; pages older than what is held in memory are read from the history file.",
+    },
+    CommandSpec {
+        name: "/mentions",
+        usage: "/mentions",
+        description: "List recent messages that mention you",
+        details: "Prints the latest messages held in memory, from every room and direct, that mention @<your nickname> or @<your peer id prefix>.",
+    },
+    CommandSpec {
+        name: "/clear",
+        usage: "/clear [room] [--disk] [--yes]",
+        description: "Forget the messages of a room, or all of them",
+        details: "Drops the messages of <room>, or of every room and direct chat, from memory, and with --disk from the history file too, so they are gone after a restart as well. Other rooms are left alone. Without --yes only says how many messages would go; run it again with --yes to clear them.",
+    },
+    CommandSpec {
+        name: "/dial",
+        usage: "/dial <multiaddr>",
+        description: "Connect to a peer by address",
+        details: "Dials <multiaddr>, e.g. /ip4/192.0.2.1/tcp/4001 or /dns4/chat.example.com/tcp/4001/p2p/<peer id>, and reports whether the connection was established.",
+    },
+    CommandSpec {
+        name: "/peers",
+        usage: "/peers",
+        description: "List connected peers",
+        details: "Prints the id of every peer with an open connection, the version of the chat protocol it speaks and the bytes moved with it since /bw reset.",
+    },
+    CommandSpec {
+        name: "/fingerprint",
+        usage: "/fingerprint [peer]",
+        description: "Show a key fingerprint to compare out of band",
+        details: "Without an argument shows your own fingerprint; with one, that of a connected peer. Compare it with what the peer's owner reads out from their own, e.g. over the phone, then run /verify.",
+    },
+    CommandSpec {
+        name: "/verify",
+        usage: "/verify <peer>",
+        description: "Mark a connected peer as verified",
+        details: "Only run it once you have compared the peer's /fingerprint with its owner. Verified peers are marked with ✓ in /peers, and you are warned if one ever presents another key. The mark is kept across restarts.",
+    },
+    CommandSpec {
+        name: "/addfriend",
+        usage: "/addfriend <peer> [nickname]",
+        description: "Add a peer to your friends",
+        details: "Friends are kept across restarts and dialed on startup at their last known addresses. With a nickname, the peer is shown by it instead of the one it announces; adding a friend again replaces it.",
+    },
+    CommandSpec {
+        name: "/rmfriend",
+        usage: "/rmfriend <peer>",
+        description: "Remove a peer from your friends",
+        details: "The peer is shown by the nickname it announces again and no longer dialed on startup.",
+    },
+    CommandSpec {
+        name: "/friends",
+        usage: "/friends",
+        description: "List your friends",
+        details: "Shows each friend with the nickname you gave it and whether it is connected.",
+    },
+    CommandSpec {
+        name: "/profile",
+        usage: "/profile [set-status [text] | set-avatar [path]]",
+        description: "Show or change the status and avatar you share",
+        details: "Without arguments shows your profile. set-status sets a status line of at most 140 characters, and set-avatar a PNG image of at most 32 KiB; either clears it when given nothing. Peers are told in every room you are in and fetch it again.",
+    },
+    CommandSpec {
+        name: "/whois",
+        usage: "/whois <peer>",
+        description: "Show everything known about a peer",
+        details: "Prints the peer's nickname, whether it is a friend, connected or when it was last seen, whether it is verified, and the status and avatar from its profile. Profiles are fetched from peers on connection and cached until they change.",
+    },
+    CommandSpec {
+        name: "/px",
+        usage: "/px <peer>",
+        description: "Ask a peer for the peers it knows",
+        details: "Requests up to 16 recently seen peers from <peer>. They are added to the address book and dialed while we have few connections. This also happens on every new connection.",
+    },
+    CommandSpec {
+        name: "/block",
+        usage: "/block <peer>",
+        description: "Block a peer",
+        details: "Closes all connections to <peer> and refuses new ones until /unblock.",
+    },
+    CommandSpec {
+        name: "/unblock",
+        usage: "/unblock <peer>",
+        description: "Unblock a previously blocked peer",
+        details: "Allows connections to <peer> again.",
+    },
+    CommandSpec {
+        name: "/nick",
+        usage: "/nick <name>",
+        description: "Set the nickname sent with your messages",
+        details: "Announces <name> in every room you are in, and other peers show it instead of your peer id. Names are at most 32 characters, without control characters or surrounding spaces.",
+    },
+    CommandSpec {
+        name: "/noemoji",
+        usage: "/noemoji",
+        description: "Toggle emoji shortcode expansion",
+        details: "Shortcodes like :smile: in your messages are replaced with emoji unless this is turned off. Run it again to turn expansion back on.",
+    },
+    CommandSpec {
+        name: "/dnd",
+        usage: "/dnd on [message] | /dnd off",
+        description: "Turn do not disturb on or off",
+        details: "While on, desktop notifications, the bell and mention highlights are off, though messages are still printed, and each peer sending you a direct message gets one auto-reply an hour with the message. The mode is kept across restarts.",
+    },
+    CommandSpec {
+        name: "/status",
+        usage: "/status",
+        description: "Show whether do not disturb is on",
+        details: "Prints your current mode and the away message set with /dnd on.",
+    },
+    CommandSpec {
+        name: "/reply",
+        usage: "/reply <message_id_prefix> <text>",
+        description: "Reply to a message",
+        details: "Sends <text> to the room of the message whose id starts with the prefix, quoting it. Direct messages can't be replied to.",
+    },
+    CommandSpec {
+        name: "/thread",
+        usage: "/thread <message_id_prefix>",
+        description: "Show a message and its replies",
+        details: "Prints the message followed by the replies to it and to them, each indented under the message it answers.",
+    },
+    CommandSpec {
+        name: "/edit",
+        usage: "/edit <message_id_prefix> <text>",
+        description: "Edit one of your messages",
+        details: "Replaces the text of your message whose id starts with the prefix. Peers show the new text marked (edited), keeping the original time.",
+    },
+    CommandSpec {
+        name: "/delete",
+        usage: "/delete <message_id_prefix>",
+        description: "Delete one of your messages",
+        details: "Peers replace the message whose id starts with the prefix with [deleted], or hide it if configured to. The deletion is kept in the history.",
+    },
+    CommandSpec {
+        name: "/react",
+        usage: "/react <message_id_prefix> <emoji>",
+        description: "React to a message",
+        details: "The reaction goes to the room the message was sent to; direct messages can't be reacted to. Reacting again with the same emoji removes the reaction.",
+    },
+    CommandSpec {
+        name: "/ephemeral",
+        usage: "/ephemeral <seconds> <text>",
+        description: "Send a message that expires",
+        details: "Every peer removes the message <seconds> after it arrives, a year at most. Once <seconds> have passed since it was sent, it is left out of syncs, exports and imports.",
+    },
+    CommandSpec {
+        name: "/search",
+        usage: "/search [--regex] [--before <date>] [--after <date>] <term>",
+        description: "Search the message history",
+        details: "Lists up to 20 messages containing <term>, ignoring case, newest first. With --regex <term> is a regular expression. Dates are YYYY-MM-DD in UTC; --before excludes that day and later, --after excludes that day and earlier.",
+    },
+    CommandSpec {
+        name: "/addrs",
+        usage: "/addrs",
+        description: "Show our own addresses",
+        details: "Prints every listen and external address with our /p2p suffix, ready to share.",
+    },
+    CommandSpec {
+        name: "/share",
+        usage: "/share [--all]",
+        description: "Show a QR code of the address to dial you at",
+        details: "Prints the best address, with your peer id, as a QR code followed by the plain address, which --dial and /dial accept. Confirmed external addresses come first, then public IPs, then LAN addresses. With --all lists every address below the code.",
+    },
+    CommandSpec {
+        name: "/known",
+        usage: "/known",
+        description: "List the address book",
+        details: "Prints every peer we have learned about, most recently seen first.",
+    },
+    CommandSpec {
+        name: "/limits",
+        usage: "/limits",
+        description: "Show connections against the connection limits",
+        details: "Prints established and pending connections next to the caps set in the config's connection_limits section or with --max-connections and --max-connections-per-peer, and how many connections were refused for going over them.",
+    },
+    CommandSpec {
+        name: "/bw",
+        usage: "/bw [reset]",
+        description: "Show bandwidth used per peer",
+        details: "Lists the bytes received from and sent to every peer, most first. /bw reset starts counting afresh; the counters served as Prometheus metrics keep running.",
+    },
+    CommandSpec {
+        name: "/stats",
+        usage: "/stats [--json]",
+        description: "Show node statistics",
+        details: "Prints uptime, addresses, peers, message and byte counts, request failures, mesh sizes and history size. With --json prints the same report as JSON.",
+    },
+    CommandSpec {
+        name: "/help",
+        usage: "/help [command]",
+        description: "List commands, or describe one",
+        details: "Without an argument lists every command; with one shows its usage and details.",
+    },
+    CommandSpec {
+        name: "/quit",
+        usage: "/quit",
+        description: "Disconnect from every peer and exit",
+        details: "Closes every connection, saves what is kept in memory and exits, as Ctrl-C does. /exit does the same.",
+    },
+];
+
+/// Other names commands can be typed as.
+const ALIASES: &[(&str, &str)] = &[("/exit", "/quit")];
+
+pub fn find_command(name: &str) -> Option<&'static CommandSpec> {
+    let name = if name.starts_with('/') {
+        name.to_string()
+    } else {
+        format!("/{}", name)
+    };
+    let name = ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == name)
+        .map_or(name.as_str(), |(_, command)| command);
+    COMMANDS.iter().find(|spec| spec.name == name)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    UnknownCommand(String),
+    /// The arguments did not match the command; holds its usage line.
+    Usage(&'static str),
+    InvalidArgument {
+        usage: &'static str,
+        message: String,
+    },
+    UnterminatedQuote,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::UnknownCommand(name) => {
+                write!(f, "unknown command: {} (try /help)", name)
+            }
+            ParseError::Usage(usage) => write!(f, "usage: {}", usage),
+            ParseError::InvalidArgument { usage, message } => {
+                write!(f, "{} (usage: {})", message, usage)
+            }
+            ParseError::UnterminatedQuote => write!(f, "unterminated quote"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Splits a line on whitespace. Double quotes group words into one token and
+/// a backslash escapes the next character.
+pub fn tokenize(line: &str) -> Result<Vec<String>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut in_token = false;
+    let mut in_quotes = false;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some(escaped) = chars.next() {
+                    current.push(escaped);
+                }
+                in_token = true;
+            }
+            '"' => {
+                in_quotes = !in_quotes;
+                in_token = true;
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if in_token {
+                    tokens.push(std::mem::take(&mut current));
+                    in_token = false;
+                }
+            }
+            c => {
+                current.push(c);
+                in_token = true;
+            }
+        }
+    }
+
+    if in_quotes {
+        return Err(ParseError::UnterminatedQuote);
+    }
+    if in_token {
+        tokens.push(current);
+    }
+
+    Ok(tokens)
+}
+
+/// Messages `/history` shows when not told how many.
+pub const HISTORY_PAGE_SIZE: usize = 20;
+
+/// Parses a line typed at the prompt. Lines not starting with `/` are sent
+/// to the current room; blank lines yield `None`.
+pub fn parse(line: &str) -> Result<Option<Command>, ParseError> {
+    let line = line.trim();
+    if line.is_empty() {
+        return Ok(None);
+    }
+
+    if !line.starts_with('/') {
+        return Ok(Some(Command::Send {
+            text: line.to_string(),
+            room: None,
+            expires_in: None,
+        }));
+    }
+
+    let tokens = tokenize(line)?;
+    let (name, args) = tokens.split_first().ok_or(ParseError::UnterminatedQuote)?;
+    let spec = find_command(name).ok_or_else(|| ParseError::UnknownCommand(name.clone()))?;
+    let usage = ParseError::Usage(spec.usage);
+
+    let command = match (spec.name, args) {
+        ("/msg", [peer, text @ ..]) if !text.is_empty() => Command::Msg {
+            peer: parse_peer(peer, spec)?,
+            text: text.join(" "),
+        },
+        ("/join", [flag, token]) if flag == "--invite" => Command::JoinInvite {
+            token: token.clone(),
+        },
+        ("/join", [room]) => Command::Join { room: room.clone() },
+        ("/invite", [flag, room]) if flag == "--new" => Command::Invite {
+            room: room.clone(),
+            expires_in: None,
+            new: true,
+        },
+        ("/invite", [flag, room, seconds]) if flag == "--new" => Command::Invite {
+            room: room.clone(),
+            expires_in: Some(parse_seconds(seconds, MAX_INVITE_SECS, spec)?),
+            new: true,
+        },
+        ("/invite", [room]) => Command::Invite {
+            room: room.clone(),
+            expires_in: None,
+            new: false,
+        },
+        ("/invite", [room, seconds]) => Command::Invite {
+            room: room.clone(),
+            expires_in: Some(parse_seconds(seconds, MAX_INVITE_SECS, spec)?),
+            new: false,
+        },
+        ("/leave", [room]) => Command::Leave { room: room.clone() },
+        ("/rooms", []) => Command::Rooms,
+        ("/members", []) => Command::Members { room: None },
+        ("/members", [room]) => Command::Members {
+            room: Some(room.clone()),
+        },
+        ("/mute", [room]) => Command::Mute {
+            room: room.clone(),
+            mentions_only: false,
+        },
+        ("/mute", [room, flag]) if flag == "--mentions-only" => Command::Mute {
+            room: room.clone(),
+            mentions_only: true,
+        },
+        ("/history", args) => parse_history(args, spec)?,
+        ("/mentions", []) => Command::Mentions,
+        ("/clear", args) => parse_clear(args, spec)?,
+        ("/unmute", [room]) => Command::Unmute { room: room.clone() },
+        ("/dial", [address]) => Command::Dial {
+            address: parse_address(address, spec)?,
+        },
+        ("/peers", []) => Command::Peers,
+        ("/fingerprint", []) => Command::Fingerprint { peer: None },
+        ("/fingerprint", [peer]) => Command::Fingerprint {
+            peer: Some(parse_peer(peer, spec)?),
+        },
+        ("/verify", [peer]) => Command::Verify {
+            peer: parse_peer(peer, spec)?,
+        },
+        ("/addfriend", [peer, nickname @ ..]) => Command::AddFriend {
+            peer: parse_peer(peer, spec)?,
+            nickname: (!nickname.is_empty()).then(|| nickname.join(" ")),
+        },
+        ("/rmfriend", [peer]) => Command::RemoveFriend {
+            peer: parse_peer(peer, spec)?,
+        },
+        ("/friends", []) => Command::Friends,
+        ("/profile", []) => Command::Profile,
+        ("/profile", [action, status @ ..]) if action == "set-status" => Command::SetStatus {
+            status: (!status.is_empty()).then(|| status.join(" ")),
+        },
+        ("/profile", [action]) if action == "set-avatar" => Command::SetAvatar { path: None },
+        ("/profile", [action, path]) if action == "set-avatar" => Command::SetAvatar {
+            path: Some(PathBuf::from(path)),
+        },
+        ("/whois", [peer]) => Command::Whois {
+            peer: parse_peer(peer, spec)?,
+        },
+        ("/px", [peer]) => Command::PeerExchange {
+            peer: parse_peer(peer, spec)?,
+        },
+        ("/block", [peer]) => Command::Block {
+            peer: parse_peer(peer, spec)?,
+        },
+        ("/unblock", [peer]) => Command::Unblock {
+            peer: parse_peer(peer, spec)?,
+        },
+        ("/nick", [name]) => Command::Nick { name: name.clone() },
+        ("/noemoji", []) => Command::NoEmoji,
+        ("/dnd", [mode, away_message @ ..]) if mode == "on" => Command::DoNotDisturb {
+            enabled: true,
+            away_message: (!away_message.is_empty()).then(|| away_message.join(" ")),
+        },
+        ("/dnd", [mode]) if mode == "off" => Command::DoNotDisturb {
+            enabled: false,
+            away_message: None,
+        },
+        ("/status", []) => Command::Status,
+        ("/reply", [prefix, text @ ..]) if !text.is_empty() => Command::Reply {
+            message_id: prefix.clone(),
+            text: text.join(" "),
+        },
+        ("/thread", [prefix]) => Command::Thread {
+            message_id: prefix.clone(),
+        },
+        ("/edit", [prefix, text @ ..]) if !text.is_empty() => Command::Edit {
+            message_id: prefix.clone(),
+            text: text.join(" "),
+        },
+        ("/delete", [prefix]) => Command::Delete {
+            message_id: prefix.clone(),
+        },
+        ("/react", [prefix, emoji]) => Command::React {
+            message_id: prefix.clone(),
+            emoji: emoji.clone(),
+        },
+        ("/ephemeral", [seconds, text @ ..]) if !text.is_empty() => Command::Send {
+            text: text.join(" "),
+            room: None,
+            expires_in: Some(parse_seconds(seconds, MAX_EXPIRES_IN_SECS, spec)?),
+        },
+        ("/search", args) => parse_search(args, spec)?,
+        ("/addrs", []) => Command::Addrs,
+        ("/share", []) => Command::Share { all: false },
+        ("/share", [flag]) if flag == "--all" => Command::Share { all: true },
+        ("/known", []) => Command::Known,
+        ("/limits", []) => Command::Limits,
+        ("/bw", []) => Command::Bandwidth { reset: false },
+        ("/bw", [reset]) if reset == "reset" => Command::Bandwidth { reset: true },
+        ("/stats", []) => Command::Stats { json: false },
+        ("/stats", [flag]) if flag == "--json" => Command::Stats { json: true },
+        ("/help", []) => Command::Help { topic: None },
+        ("/help", [command]) => match find_command(command) {
+            Some(spec) => Command::Help {
+                topic: Some(spec.name.to_string()),
+            },
+            None => return Err(ParseError::UnknownCommand(command.clone())),
+        },
+        ("/quit", []) => Command::Quit,
+        _ => return Err(usage),
+    };
+
+    Ok(Some(command))
+}
+
+fn parse_peer(peer: &str, spec: &CommandSpec) -> Result<PeerId, ParseError> {
+    peer.parse().map_err(|_| ParseError::InvalidArgument {
+        usage: spec.usage,
+        message: format!("invalid peer id: {}", peer),
+    })
+}
+
+fn parse_address(address: &str, spec: &CommandSpec) -> Result<Multiaddr, ParseError> {
+    let invalid = |message| ParseError::InvalidArgument {
+        usage: spec.usage,
+        message,
+    };
+    let address = parse_multiaddr(address).map_err(invalid)?;
+    check_dial_address(&address).map_err(|e| invalid(format!("can't dial {}: {}", address, e)))?;
+    Ok(address)
+}
+
+/// Parses the flags of `/search` followed by the search term.
+fn parse_search(args: &[String], spec: &CommandSpec) -> Result<Command, ParseError> {
+    let mut regex = false;
+    let mut before = None;
+    let mut after = None;
+
+    let mut args = args.iter().peekable();
+    while let Some(flag) = args.next_if(|arg| arg.starts_with("--")) {
+        match flag.as_str() {
+            "--regex" => regex = true,
+            "--before" => before = Some(parse_date_arg(args.next(), spec)?),
+            // Messages from the given day itself are excluded, like `--before`.
+            "--after" => after = Some(parse_date_arg(args.next(), spec)? + SECONDS_PER_DAY),
+            _ => {
+                return Err(ParseError::InvalidArgument {
+                    usage: spec.usage,
+                    message: format!("unknown flag: {}", flag),
+                })
+            }
+        }
+    }
+
+    let term: Vec<&str> = args.map(String::as_str).collect();
+    if term.is_empty() {
+        return Err(ParseError::Usage(spec.usage));
+    }
+
+    Ok(Command::Search {
+        term: term.join(" "),
+        regex,
+        before,
+        after,
+    })
+}
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+fn parse_date_arg(date: Option<&String>, spec: &CommandSpec) -> Result<u64, ParseError> {
+    let date = date.ok_or(ParseError::Usage(spec.usage))?;
+    parse_date(date).ok_or_else(|| ParseError::InvalidArgument {
+        usage: spec.usage,
+        message: format!("invalid date: {} (expected YYYY-MM-DD)", date),
+    })
+}
+
+/// Parses the optional room and the flags of `/history`.
+fn parse_history(args: &[String], spec: &CommandSpec) -> Result<Command, ParseError> {
+    let mut args = args.iter().peekable();
+    let room = args.next_if(|arg| !arg.starts_with("--")).cloned();
+    let mut peer = None;
+    let mut since = None;
+    let mut limit = HISTORY_PAGE_SIZE;
+    let mut page = 1;
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "--peer" => {
+                let id = args.next().ok_or(ParseError::Usage(spec.usage))?;
+                peer = Some(parse_peer(id, spec)?);
+            }
+            "--since" => since = Some(parse_date_arg(args.next(), spec)?),
+            "--limit" => limit = parse_positive_arg(args.next(), spec)?,
+            "--page" => page = parse_positive_arg(args.next(), spec)?,
+            _ if flag.starts_with("--") => {
+                return Err(ParseError::InvalidArgument {
+                    usage: spec.usage,
+                    message: format!("unknown flag: {}", flag),
+                })
+            }
+            _ => return Err(ParseError::Usage(spec.usage)),
+        }
+    }
+
+    Ok(Command::History {
+        room,
+        peer,
+        since,
+        limit,
+        page: page - 1,
+    })
+}
+
+/// Parses the optional room and the flags of `/clear`.
+fn parse_clear(args: &[String], spec: &CommandSpec) -> Result<Command, ParseError> {
+    let mut args = args.iter().peekable();
+    let room = args.next_if(|arg| !arg.starts_with("--")).cloned();
+    let mut disk = false;
+    let mut confirmed = false;
+    for flag in args {
+        match flag.as_str() {
+            "--disk" => disk = true,
+            "--yes" => confirmed = true,
+            _ if flag.starts_with("--") => {
+                return Err(ParseError::InvalidArgument {
+                    usage: spec.usage,
+                    message: format!("unknown flag: {}", flag),
+                })
+            }
+            _ => return Err(ParseError::Usage(spec.usage)),
+        }
+    }
+
+    Ok(Command::Clear {
+        room,
+        disk,
+        confirmed,
+    })
+}
+
+/// Parses a number of seconds of at most `max`.
+fn parse_seconds(seconds: &str, max: u64, spec: &CommandSpec) -> Result<u64, ParseError> {
+    match seconds.parse::<u64>() {
+        Ok(parsed) if parsed <= max => Ok(parsed),
+        Ok(_) => Err(ParseError::InvalidArgument {
+            usage: spec.usage,
+            message: format!(
+                "{} seconds is too long; at most {} are allowed",
+                seconds, max
+            ),
+        }),
+        Err(_) => Err(ParseError::InvalidArgument {
+            usage: spec.usage,
+            message: format!("invalid number of seconds: {}", seconds),
+        }),
+    }
+}
+
+fn parse_positive_arg(number: Option<&String>, spec: &CommandSpec) -> Result<usize, ParseError> {
+    let number = number.ok_or(ParseError::Usage(spec.usage))?;
+    match number.parse::<usize>() {
+        Ok(number) if number > 0 => Ok(number),
+        _ => Err(ParseError::InvalidArgument {
+            usage: spec.usage,
+            message: format!("expected a positive number, not {}", number),
+        }),
+    }
+}
+
+/// Text shown by `/help`, either the command list or one command's details.
+pub fn help(command: Option<&str>) -> String {
+    match command.and_then(find_command) {
+        Some(spec) => format!(
+            "{}\n    {}\n    {}",
+            spec.usage, spec.description, spec.details
+        ),
+        None => {
+            let width = COMMANDS
+                .iter()
+                .map(|spec| spec.usage.len())
+                .max()
+                .unwrap_or_default();
+            COMMANDS
+                .iter()
+                .map(|spec| format!("{:width$}  {}", spec.usage, spec.description))
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+    }
+}
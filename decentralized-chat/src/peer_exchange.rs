@@ -0,0 +1,129 @@
+use crate::address_book::{AddressBook, Entry};
+use libp2p::{Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+/// Most peers shared in, or taken from, one peer exchange response.
+pub const MAX_PEERS: usize = 16;
+
+/// Most addresses shared, or taken, for each of those peers.
+pub const MAX_ADDRESSES: usize = 4;
+
+/// A peer shared in a peer exchange.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerRecord {
+    pub peer_id: PeerId,
+    pub addresses: Vec<Multiaddr>,
+    /// Seconds since the sharer last saw the peer. Relative, since the
+    /// clocks of the two sides may disagree.
+    pub seen_secs_ago: u64,
+}
+
+impl PeerRecord {
+    /// The address book entry for the peer, as seen from a clock at `now`.
+    /// Marked learned, since the sharer may claim any recency it likes.
+    pub fn entry(&self, now: u64) -> Entry {
+        Entry {
+            addresses: self.addresses.clone(),
+            last_seen: now.saturating_sub(self.seen_secs_ago),
+            learned: true,
+        }
+    }
+}
+
+/// The most recently seen peers in `book` to share with `requester`, leaving
+/// out the requester itself and peers in `blocked`.
+pub fn share(
+    book: &AddressBook,
+    requester: &PeerId,
+    blocked: &HashSet<PeerId>,
+    now: u64,
+) -> Vec<PeerRecord> {
+    book.most_recent()
+        .into_iter()
+        .filter(|(peer, entry)| {
+            *peer != requester && !blocked.contains(peer) && !entry.addresses.is_empty()
+        })
+        .take(MAX_PEERS)
+        .map(|(peer, entry)| PeerRecord {
+            peer_id: *peer,
+            addresses: entry
+                .addresses
+                .iter()
+                .take(MAX_ADDRESSES)
+                .cloned()
+                .collect(),
+            seen_secs_ago: now.saturating_sub(entry.last_seen),
+        })
+        .collect()
+}
+
+/// The part of a peer exchange response worth taking: at most `MAX_PEERS`
+/// peers with `MAX_ADDRESSES` addresses each, without us or peers in
+/// `blocked`.
+pub fn accept(
+    mut peers: Vec<PeerRecord>,
+    local_peer_id: &PeerId,
+    blocked: &HashSet<PeerId>,
+) -> Vec<PeerRecord> {
+    peers.retain(|record| {
+        record.peer_id != *local_peer_id
+            && !blocked.contains(&record.peer_id)
+            && !record.addresses.is_empty()
+    });
+    peers.truncate(MAX_PEERS);
+    for record in &mut peers {
+        record.addresses.truncate(MAX_ADDRESSES);
+    }
+    peers
+}
+
+/// Peers learned through peer exchange waiting to be dialed. Each dial is
+/// delayed by a random part of `max_delay`, so peers that hear of the same
+/// node at once don't all dial it together.
+#[derive(Debug)]
+pub struct DialQueue {
+    max_delay: Duration,
+    queued: HashMap<PeerId, (Instant, PeerRecord)>,
+}
+
+impl DialQueue {
+    pub fn new(max_delay: Duration) -> Self {
+        DialQueue {
+            max_delay,
+            queued: HashMap::new(),
+        }
+    }
+
+    /// Queues a dial to `record`, unless one is queued already.
+    pub fn schedule(&mut self, record: PeerRecord, now: Instant) {
+        let delay = self.max_delay.mul_f64(rand::random::<f64>());
+        self.queued
+            .entry(record.peer_id)
+            .or_insert((now + delay, record));
+    }
+
+    /// Dials due by `now`, which are taken off the queue.
+    pub fn due(&mut self, now: Instant) -> Vec<PeerRecord> {
+        let due: Vec<PeerId> = self
+            .queued
+            .iter()
+            .filter(|(_, (at, _))| *at <= now)
+            .map(|(peer, _)| *peer)
+            .collect();
+        due.into_iter()
+            .filter_map(|peer| self.queued.remove(&peer).map(|(_, record)| record))
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.queued.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queued.is_empty()
+    }
+}
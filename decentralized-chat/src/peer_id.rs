@@ -0,0 +1,34 @@
+use libp2p::PeerId;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Characters kept from each end of a shortened peer id.
+const KEPT: usize = 4;
+
+/// Whether peer ids are shown in full; set once at startup by `--full-ids`.
+static FULL_IDS: AtomicBool = AtomicBool::new(false);
+
+pub fn show_full_ids(full: bool) {
+    FULL_IDS.store(full, Ordering::Relaxed);
+}
+
+/// `peer` as shown in logs and next to messages: the first and last few
+/// characters, like `12D3…Wx9A`, or the whole id with `--full-ids`.
+pub fn short_peer_id(peer: &PeerId) -> String {
+    let id = peer.to_string();
+    if FULL_IDS.load(Ordering::Relaxed) {
+        return id;
+    }
+    shorten(&id)
+}
+
+/// Shortens `id` to its first and last `KEPT` characters. Ids too short to
+/// gain anything are returned whole.
+pub fn shorten(id: &str) -> String {
+    let chars: Vec<char> = id.chars().collect();
+    if chars.len() <= 2 * KEPT + 1 {
+        return id.to_string();
+    }
+    let head: String = chars[..KEPT].iter().collect();
+    let tail: String = chars[chars.len() - KEPT..].iter().collect();
+    format!("{}…{}", head, tail)
+}
@@ -0,0 +1,176 @@
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Name of the file inside the data directory holding the profile we share,
+/// set with `/profile`.
+pub const PUBLIC_PROFILE_FILE: &str = "public_profile.json";
+
+/// Directory inside the data directory caching the profile of each peer,
+/// one `<peer id>.json` per peer.
+pub const PROFILE_CACHE_DIR: &str = "peer_profiles";
+
+/// Longest status line accepted, in characters.
+pub const MAX_STATUS_LEN: usize = 140;
+
+/// Largest avatar accepted, in bytes.
+pub const MAX_AVATAR_BYTES: usize = 32 * 1024;
+
+/// The signature every PNG file starts with.
+const PNG_MAGIC: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Leading bytes of the avatar's SHA-256 shown by `/whois`.
+const AVATAR_HASH_BYTES: usize = 8;
+
+/// What a peer shares about itself beyond its nickname, answered to
+/// `DirectRequest::Profile`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerProfile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    /// A PNG image.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub avatar: Option<Vec<u8>>,
+}
+
+impl PeerProfile {
+    /// Loads our profile from `path`, starting with an empty one if the file
+    /// does not exist yet.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        match fs::read(path) {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(PeerProfile::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, serde_json::to_vec_pretty(self)?)
+    }
+
+    /// Checks a profile before it is stored, whether ours or a peer's.
+    pub fn check(&self) -> Result<(), String> {
+        if let Some(status) = &self.status {
+            validate_status(status)?;
+        }
+        if let Some(avatar) = &self.avatar {
+            validate_avatar(avatar)?;
+        }
+        Ok(())
+    }
+
+    /// The start of the avatar's SHA-256 in hex, to tell avatars apart.
+    pub fn avatar_hash(&self) -> Option<String> {
+        let digest = Sha256::digest(self.avatar.as_ref()?);
+        Some(
+            digest[..AVATAR_HASH_BYTES]
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect(),
+        )
+    }
+}
+
+/// Checks a status line set with `/profile set-status` or sent by a peer.
+pub fn validate_status(status: &str) -> Result<(), String> {
+    if status.chars().count() > MAX_STATUS_LEN {
+        return Err(format!(
+            "status is longer than {} characters",
+            MAX_STATUS_LEN
+        ));
+    }
+    if status.chars().any(char::is_control) {
+        return Err("status contains control characters".to_string());
+    }
+    Ok(())
+}
+
+/// Checks an avatar set with `/profile set-avatar` or sent by a peer: a PNG,
+/// judged by its signature, of at most `MAX_AVATAR_BYTES`.
+pub fn validate_avatar(avatar: &[u8]) -> Result<(), String> {
+    if avatar.len() > MAX_AVATAR_BYTES {
+        return Err(format!(
+            "avatar is {} bytes, more than the {} allowed",
+            avatar.len(),
+            MAX_AVATAR_BYTES
+        ));
+    }
+    if !avatar.starts_with(PNG_MAGIC) {
+        return Err("avatar is not a PNG image".to_string());
+    }
+    Ok(())
+}
+
+/// The last profile fetched from each peer, kept on disk until the peer
+/// announces a change.
+#[derive(Debug)]
+pub struct ProfileCache {
+    dir: PathBuf,
+    profiles: HashMap<PeerId, PeerProfile>,
+}
+
+impl ProfileCache {
+    /// Loads the profiles cached in `dir`, skipping any that no longer pass
+    /// `PeerProfile::check`.
+    pub fn load(dir: PathBuf) -> io::Result<Self> {
+        let mut profiles = HashMap::new();
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Ok(ProfileCache { dir, profiles })
+            }
+            Err(e) => return Err(e),
+        };
+
+        for entry in entries {
+            let path = entry?.path();
+            let Some(peer) = path
+                .file_stem()
+                .filter(|_| {
+                    path.extension()
+                        .is_some_and(|extension| extension == "json")
+                })
+                .and_then(|stem| stem.to_str()?.parse::<PeerId>().ok())
+            else {
+                continue;
+            };
+            let profile: PeerProfile = serde_json::from_slice(&fs::read(&path)?)?;
+            if profile.check().is_ok() {
+                profiles.insert(peer, profile);
+            }
+        }
+        Ok(ProfileCache { dir, profiles })
+    }
+
+    pub fn get(&self, peer: &PeerId) -> Option<&PeerProfile> {
+        self.profiles.get(peer)
+    }
+
+    /// Caches the profile `peer` sent, unless it fails `PeerProfile::check`.
+    pub fn store(&mut self, peer: PeerId, profile: PeerProfile) -> Result<(), String> {
+        profile.check()?;
+        fs::create_dir_all(&self.dir)
+            .and_then(|()| fs::write(self.path(&peer), serde_json::to_vec_pretty(&profile)?))
+            .map_err(|e| format!("failed to cache the profile: {}", e))?;
+        self.profiles.insert(peer, profile);
+        Ok(())
+    }
+
+    /// Forgets the profile of `peer`, so it is fetched again.
+    pub fn invalidate(&mut self, peer: &PeerId) -> io::Result<()> {
+        self.profiles.remove(peer);
+        match fs::remove_file(self.path(peer)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    fn path(&self, peer: &PeerId) -> PathBuf {
+        self.dir.join(format!("{}.json", peer))
+    }
+}
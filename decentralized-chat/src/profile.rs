@@ -0,0 +1,135 @@
+use crate::{
+    daemon::{self, PID_FILE},
+    key::KEY_FILE,
+};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// The profile used without `--profile`.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Directory inside the state directory holding one data directory per
+/// profile.
+pub const PROFILES_DIR: &str = "profiles";
+
+/// A profile as listed by `profiles list`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Profile {
+    pub name: String,
+    /// Process id of the daemon running on the profile.
+    pub daemon: Option<u32>,
+}
+
+/// Checks that `name` can name a profile: letters, digits, `-` and `_`, so
+/// it is a single path component on every system.
+pub fn check_name(name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("the profile name is empty".to_string());
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || *c == '-' || *c == '_'))
+    {
+        return Err(format!(
+            "invalid character {:?} in profile name {} (use letters, digits, - and _)",
+            c, name
+        ));
+    }
+    Ok(())
+}
+
+/// The data directory of profile `name`: its identity key, config, history
+/// and peer store.
+pub fn dir(root: &Path, name: &str) -> PathBuf {
+    root.join(PROFILES_DIR).join(name)
+}
+
+/// The profiles under `root`, by name.
+pub fn list(root: &Path) -> io::Result<Vec<Profile>> {
+    let entries = match fs::read_dir(root.join(PROFILES_DIR)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut profiles = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        if !entry.file_type()?.is_dir() || check_name(&name).is_err() {
+            continue;
+        }
+        profiles.push(Profile {
+            daemon: daemon::running_pid(&entry.path().join(PID_FILE)).ok(),
+            name,
+        });
+    }
+    profiles.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(profiles)
+}
+
+/// Creates the data directory of a new profile `name`, returning it.
+pub fn create(root: &Path, name: &str) -> io::Result<PathBuf> {
+    check_name(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let dir = dir(root, name);
+    fs::create_dir_all(root.join(PROFILES_DIR))?;
+    fs::create_dir(&dir).map_err(|e| match e.kind() {
+        io::ErrorKind::AlreadyExists => io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("profile {} already exists", name),
+        ),
+        _ => e,
+    })?;
+    Ok(dir)
+}
+
+/// Removes profile `name` with everything in it, unless a daemon is running
+/// on it.
+pub fn delete(root: &Path, name: &str) -> io::Result<()> {
+    check_name(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let dir = dir(root, name);
+    if !dir.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no profile named {}", name),
+        ));
+    }
+    if let Ok(pid) = daemon::running_pid(&dir.join(PID_FILE)) {
+        return Err(io::Error::new(
+            io::ErrorKind::ResourceBusy,
+            format!(
+                "a daemon (pid {}) is running on profile {}; stop it first",
+                pid, name
+            ),
+        ));
+    }
+    fs::remove_dir_all(&dir)
+}
+
+/// Moves the state of a node from before profiles, kept in `root` itself,
+/// into the default profile so it keeps its identity. Does nothing once the
+/// default profile exists, or while a daemon still runs on `root`. Returns
+/// whether anything was moved.
+pub fn migrate_legacy(root: &Path) -> io::Result<bool> {
+    let default = dir(root, DEFAULT_PROFILE);
+    if default.exists()
+        || !root.join(KEY_FILE).exists()
+        || daemon::running_pid(&root.join(PID_FILE)).is_ok()
+    {
+        return Ok(false);
+    }
+
+    fs::create_dir_all(&default)?;
+    for entry in fs::read_dir(root)? {
+        let entry = entry?;
+        if entry.file_name() == PROFILES_DIR {
+            continue;
+        }
+        fs::rename(entry.path(), default.join(entry.file_name()))?;
+    }
+    Ok(true)
+}
@@ -0,0 +1,115 @@
+use libp2p::PeerId;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// What to do with a message that just arrived from a peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Allow,
+    /// Drop the message. `first` is set for the first message dropped since
+    /// the peer was last within its limit.
+    Drop {
+        first: bool,
+    },
+    /// Drop the message and block the peer, who has been over its limit for
+    /// too long.
+    Block,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /// When the peer first went over its limit, if it hasn't since let the
+    /// bucket fill up again.
+    throttled_since: Option<Instant>,
+}
+
+/// A token bucket per peer: each message takes a token, and tokens come back
+/// at `rate` per second up to `burst`. A peer that stays over its limit for
+/// `block_after` is blocked for `block_for`.
+#[derive(Debug)]
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    block_after: Duration,
+    block_for: Duration,
+    buckets: HashMap<PeerId, Bucket>,
+    blocked_until: HashMap<PeerId, Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: f64, burst: u32, block_after: Duration, block_for: Duration) -> Self {
+        RateLimiter {
+            rate,
+            burst: f64::from(burst),
+            block_after,
+            block_for,
+            buckets: HashMap::new(),
+            blocked_until: HashMap::new(),
+        }
+    }
+
+    /// Takes a token for a message from `peer` arriving at `now`.
+    pub fn check(&mut self, peer: PeerId, now: Instant) -> Decision {
+        let bucket = self.buckets.entry(peer).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+            throttled_since: None,
+        });
+
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.updated = now;
+        if bucket.tokens >= self.burst {
+            bucket.throttled_since = None;
+        }
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Decision::Allow;
+        }
+
+        match bucket.throttled_since {
+            None => {
+                bucket.throttled_since = Some(now);
+                Decision::Drop { first: true }
+            }
+            Some(since) if now.duration_since(since) >= self.block_after => {
+                self.buckets.remove(&peer);
+                self.blocked_until.insert(peer, now + self.block_for);
+                Decision::Block
+            }
+            Some(_) => Decision::Drop { first: false },
+        }
+    }
+
+    /// Stops tracking `peer`, so a block the user placed by hand isn't lifted
+    /// when a rate limit block would have run out.
+    pub fn forget(&mut self, peer: &PeerId) {
+        self.buckets.remove(peer);
+        self.blocked_until.remove(peer);
+    }
+
+    /// Peers whose block has run out by `now`, and who should be unblocked.
+    /// Buckets that have filled up again are forgotten as well.
+    pub fn expire(&mut self, now: Instant) -> Vec<PeerId> {
+        let (rate, burst) = (self.rate, self.burst);
+        self.buckets.retain(|_, bucket| {
+            bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate < burst
+        });
+
+        let expired: Vec<PeerId> = self
+            .blocked_until
+            .iter()
+            .filter(|(_, until)| **until <= now)
+            .map(|(peer, _)| *peer)
+            .collect();
+        for peer in &expired {
+            self.blocked_until.remove(peer);
+        }
+        expired
+    }
+}
@@ -0,0 +1,117 @@
+use libp2p::{
+    multiaddr::Protocol,
+    rendezvous::{Cookie, Namespace},
+    Multiaddr, PeerId,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+/// Prefixed to room names to make rendezvous namespaces, so rooms don't mix
+/// with other applications sharing a server.
+const NAMESPACE_PREFIX: &str = "decentralized-chat/";
+
+/// How long to wait before retrying a registration that failed.
+pub const REGISTER_RETRY: Duration = Duration::from_secs(30);
+
+/// The rendezvous namespace for `room`.
+pub fn room_namespace(room: &str) -> Result<Namespace, String> {
+    Namespace::new(format!("{}{}", NAMESPACE_PREFIX, room))
+        .map_err(|_| format!("room name {} is too long for a rendezvous namespace", room))
+}
+
+/// The peer id at the end of a rendezvous server's address, which is needed
+/// to talk to it before connecting.
+pub fn server_peer_id(address: &Multiaddr) -> Result<PeerId, String> {
+    match address.iter().last() {
+        Some(Protocol::P2p(peer_id)) => Ok(peer_id),
+        _ => Err(format!(
+            "rendezvous address {} must end in /p2p/<peer id>",
+            address
+        )),
+    }
+}
+
+/// Keeps our rendezvous registrations alive. Servers drop a registration
+/// once its TTL runs out, so each is renewed halfway through, and failed
+/// ones are retried.
+#[derive(Debug, Default)]
+pub struct Registrations {
+    /// When each registration is next due, by server and namespace.
+    due: HashMap<(PeerId, Namespace), Instant>,
+    /// Registrations the server has accepted and that haven't failed since.
+    current: HashSet<(PeerId, Namespace)>,
+    /// Where the last discovery at each server left off, so the next one
+    /// only returns newcomers.
+    cookies: HashMap<(PeerId, Namespace), Cookie>,
+}
+
+impl Registrations {
+    /// Records a registration the server accepted for `ttl` seconds.
+    /// Returns whether it is new rather than a renewal.
+    pub fn registered(
+        &mut self,
+        server: PeerId,
+        namespace: Namespace,
+        ttl: u64,
+        now: Instant,
+    ) -> bool {
+        let key = (server, namespace);
+        self.due
+            .insert(key.clone(), now + Duration::from_secs(ttl / 2));
+        self.current.insert(key)
+    }
+
+    /// Records a registration that failed, or couldn't be sent.
+    pub fn failed(&mut self, server: PeerId, namespace: Namespace, now: Instant) {
+        let key = (server, namespace);
+        self.current.remove(&key);
+        self.due.insert(key, now + REGISTER_RETRY);
+    }
+
+    /// Whether we have tried to register at `server` since we connected.
+    pub fn knows(&self, server: &PeerId) -> bool {
+        self.due.keys().any(|(known, _)| known == server)
+    }
+
+    /// Forgets the registrations at `server`, which we lost the connection
+    /// to, so they are all sent again when it is back.
+    pub fn disconnected(&mut self, server: &PeerId) {
+        self.due.retain(|(known, _), _| known != server);
+        self.current.retain(|(known, _)| known != server);
+    }
+
+    /// Forgets the registrations in `namespace`, whose room we left.
+    pub fn left(&mut self, namespace: &Namespace) {
+        self.due.retain(|(_, known), _| known != namespace);
+        self.current.retain(|(_, known)| known != namespace);
+        self.cookies.retain(|(_, known), _| known != namespace);
+    }
+
+    /// Registrations due for renewal by `now`. They stay due until they are
+    /// recorded as registered or failed again.
+    pub fn due(&self, now: Instant) -> Vec<(PeerId, Namespace)> {
+        self.due
+            .iter()
+            .filter(|(_, due)| **due <= now)
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    /// Marks a renewal as sent, so it isn't sent again while the server
+    /// answers.
+    pub fn sent(&mut self, server: PeerId, namespace: Namespace, now: Instant) {
+        self.due.insert((server, namespace), now + REGISTER_RETRY);
+    }
+
+    pub fn cookie(&self, server: PeerId, namespace: &Namespace) -> Option<Cookie> {
+        self.cookies.get(&(server, namespace.clone())).cloned()
+    }
+
+    pub fn set_cookie(&mut self, server: PeerId, cookie: Cookie) {
+        if let Some(namespace) = cookie.namespace() {
+            self.cookies.insert((server, namespace.clone()), cookie);
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use crate::message::Sequence;
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt, fs, io,
+    path::Path,
+};
+use uuid::Uuid;
+
+/// Name of the file inside the data directory holding the sequence numbers
+/// seen from each peer.
+pub const SEQUENCES_FILE: &str = "sequences.json";
+
+/// Earlier sessions remembered per peer. Messages from them are replays; a
+/// session older than these can't be told apart from a new one.
+const RETIRED_SESSIONS: usize = 16;
+
+/// Why a message was taken for a replay.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Replay {
+    /// Further behind the latest sequence number than the window allows.
+    Stale { seq: u64, latest: u64 },
+    /// A sequence number within the window that was already seen.
+    Duplicate { seq: u64 },
+    /// From a session the sender has since replaced.
+    RetiredSession { session: Uuid },
+}
+
+impl fmt::Display for Replay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Replay::Stale { seq, latest } => {
+                write!(f, "sequence number {} is far behind {}", seq, latest)
+            }
+            Replay::Duplicate { seq } => write!(f, "sequence number {} was already seen", seq),
+            Replay::RetiredSession { session } => write!(f, "session {} has ended", session),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PeerSequences {
+    session: Uuid,
+    latest: u64,
+    /// Sequence numbers seen within the window below `latest`.
+    seen: BTreeSet<u64>,
+    /// Sessions the peer used before `session`, oldest first.
+    retired: Vec<Uuid>,
+}
+
+impl PeerSequences {
+    fn new(sequence: Sequence) -> Self {
+        PeerSequences {
+            session: sequence.session,
+            latest: sequence.seq,
+            seen: BTreeSet::from([sequence.seq]),
+            retired: Vec::new(),
+        }
+    }
+}
+
+/// Tracks the sequence numbers each peer has sent, persisted across
+/// restarts. Messages may arrive out of order by up to `window` sequence
+/// numbers; a sender that starts over must do so in a new session.
+#[derive(Debug)]
+pub struct ReplayGuard {
+    window: u64,
+    peers: HashMap<PeerId, PeerSequences>,
+    changed: bool,
+}
+
+impl ReplayGuard {
+    pub fn new(window: u64) -> Self {
+        ReplayGuard {
+            window,
+            peers: HashMap::new(),
+            changed: false,
+        }
+    }
+
+    /// Loads the sequence numbers saved at `path`, starting empty if the
+    /// file does not exist yet.
+    pub fn load(path: &Path, window: u64) -> io::Result<Self> {
+        let peers = match fs::read(path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(ReplayGuard {
+            peers,
+            ..ReplayGuard::new(window)
+        })
+    }
+
+    /// Saves the sequence numbers if any changed since the last save.
+    pub fn save(&mut self, path: &Path) -> io::Result<()> {
+        if !self.changed {
+            return Ok(());
+        }
+        fs::write(path, serde_json::to_vec(&self.peers)?)?;
+        self.changed = false;
+        Ok(())
+    }
+
+    /// Records a message `peer` sent with `sequence`, unless it is a replay.
+    pub fn check(&mut self, peer: PeerId, sequence: Sequence) -> Result<(), Replay> {
+        let Some(peer_sequences) = self.peers.get_mut(&peer) else {
+            self.peers.insert(peer, PeerSequences::new(sequence));
+            self.changed = true;
+            return Ok(());
+        };
+
+        let Sequence { session, seq } = sequence;
+        if session != peer_sequences.session {
+            if peer_sequences.retired.contains(&session) {
+                return Err(Replay::RetiredSession { session });
+            }
+
+            let mut retired = std::mem::take(&mut peer_sequences.retired);
+            retired.push(peer_sequences.session);
+            if retired.len() > RETIRED_SESSIONS {
+                retired.remove(0);
+            }
+            *peer_sequences = PeerSequences {
+                retired,
+                ..PeerSequences::new(sequence)
+            };
+            self.changed = true;
+            return Ok(());
+        }
+
+        let latest = peer_sequences.latest;
+        if latest.saturating_sub(seq) > self.window {
+            return Err(Replay::Stale { seq, latest });
+        }
+        if !peer_sequences.seen.insert(seq) {
+            return Err(Replay::Duplicate { seq });
+        }
+
+        if seq > latest {
+            peer_sequences.latest = seq;
+            let oldest = seq.saturating_sub(self.window);
+            peer_sequences.seen = peer_sequences.seen.split_off(&oldest);
+        }
+        self.changed = true;
+        Ok(())
+    }
+}
@@ -0,0 +1,159 @@
+use crate::message::{ChatMessage, MessageId};
+use libp2p::PeerId;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// How often a direct message is sent before it is given up on, and how
+/// long to wait between attempts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts in all, the first one included.
+    pub max_attempts: u32,
+    /// The wait before each retry: the first entry before the second
+    /// attempt, and so on. The last entry is repeated for further attempts.
+    pub backoff: Vec<Duration>,
+}
+
+impl RetryPolicy {
+    /// The wait after `attempts` failed attempts.
+    fn delay(&self, attempts: u32) -> Duration {
+        let index = (attempts as usize).saturating_sub(1);
+        self.backoff
+            .get(index)
+            .or(self.backoff.last())
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+/// What became of a direct message whose attempt failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Failed {
+    /// It is returned by `RetryQueue::due` after `delay`, to be sent for
+    /// the `attempt`th time.
+    Retrying { attempt: u32, delay: Duration },
+    /// Every attempt failed; the message is no longer tracked.
+    GaveUp {
+        peer: PeerId,
+        message: Box<ChatMessage>,
+        attempts: u32,
+    },
+}
+
+#[derive(Debug)]
+struct Pending {
+    peer: PeerId,
+    message: ChatMessage,
+    attempts: u32,
+    /// When the next attempt is due; unset while one is on its way.
+    retry_at: Option<Instant>,
+}
+
+/// Direct messages we sent that weren't answered yet, or are waiting to be
+/// sent again after failing.
+#[derive(Debug)]
+pub struct RetryQueue {
+    policy: RetryPolicy,
+    pending: HashMap<MessageId, Pending>,
+}
+
+impl RetryQueue {
+    pub fn new(policy: RetryPolicy) -> Self {
+        RetryQueue {
+            policy,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Tracks `message`, just sent to `peer` for the first time.
+    pub fn sent(&mut self, peer: PeerId, message: ChatMessage) {
+        self.pending.insert(
+            message.id,
+            Pending {
+                peer,
+                message,
+                attempts: 1,
+                retry_at: None,
+            },
+        );
+    }
+
+    /// Stops tracking message `id`, which its recipient answered. Returns
+    /// whether it was tracked.
+    pub fn delivered(&mut self, id: &MessageId) -> bool {
+        self.pending.remove(id).is_some()
+    }
+
+    /// Schedules the next attempt at message `id`, or gives up on it when
+    /// it had all of them. `None` if the message isn't tracked, e.g. because
+    /// it was cancelled meanwhile.
+    pub fn failed(&mut self, id: &MessageId, now: Instant) -> Option<Failed> {
+        let pending = self.pending.get_mut(id)?;
+        if pending.attempts >= self.policy.max_attempts {
+            let pending = self.pending.remove(id)?;
+            return Some(Failed::GaveUp {
+                peer: pending.peer,
+                message: Box::new(pending.message),
+                attempts: pending.attempts,
+            });
+        }
+
+        let delay = self.policy.delay(pending.attempts);
+        pending.retry_at = Some(now + delay);
+        Some(Failed::Retrying {
+            attempt: pending.attempts + 1,
+            delay,
+        })
+    }
+
+    /// The messages whose next attempt is due by `now`, soonest first, with
+    /// their recipients. Each counts as sent again.
+    pub fn due(&mut self, now: Instant) -> Vec<(PeerId, ChatMessage)> {
+        let mut due: Vec<&mut Pending> = self
+            .pending
+            .values_mut()
+            .filter(|pending| pending.retry_at.is_some_and(|at| at <= now))
+            .collect();
+        due.sort_by_key(|pending| pending.retry_at);
+        due.into_iter()
+            .map(|pending| {
+                pending.attempts += 1;
+                pending.retry_at = None;
+                (pending.peer, pending.message.clone())
+            })
+            .collect()
+    }
+
+    /// Stops retrying message `id`. Returns whether it was tracked.
+    pub fn cancel(&mut self, id: &MessageId) -> bool {
+        self.pending.remove(id).is_some()
+    }
+
+    /// Stops retrying every message to `peer`. Returns how many there were.
+    pub fn cancel_peer(&mut self, peer: &PeerId) -> usize {
+        let before = self.pending.len();
+        self.pending.retain(|_, pending| pending.peer != *peer);
+        before - self.pending.len()
+    }
+
+    /// Stops retrying every message to `peer` and returns them, oldest
+    /// first.
+    pub fn take_peer(&mut self, peer: &PeerId) -> Vec<ChatMessage> {
+        let mut taken = Vec::new();
+        self.pending.retain(|_, pending| {
+            if pending.peer != *peer {
+                return true;
+            }
+            taken.push(pending.message.clone());
+            false
+        });
+        taken.sort_by_key(|message| message.timestamp);
+        taken
+    }
+
+    pub fn max_attempts(&self) -> u32 {
+        self.policy.max_attempts
+    }
+}
@@ -0,0 +1,75 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt, fs, io, path::Path};
+
+/// Name of the file inside the data directory holding the notification
+/// setting of each room.
+pub const ROOM_SETTINGS_FILE: &str = "room_settings.json";
+
+/// Which messages of a room are shown as they arrive. Every message is
+/// still stored and kept in the history either way.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Notify {
+    #[default]
+    All,
+    /// Only messages mentioning us.
+    MentionsOnly,
+    Muted,
+}
+
+impl Notify {
+    /// Whether a message is shown, given whether it mentions us.
+    pub fn shows(self, mentioned: bool) -> bool {
+        match self {
+            Notify::All => true,
+            Notify::MentionsOnly => mentioned,
+            Notify::Muted => false,
+        }
+    }
+}
+
+impl fmt::Display for Notify {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Notify::All => write!(f, "all messages"),
+            Notify::MentionsOnly => write!(f, "mentions only"),
+            Notify::Muted => write!(f, "muted"),
+        }
+    }
+}
+
+/// The rooms set to anything other than `Notify::All` with `/mute`,
+/// persisted across restarts.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RoomSettings {
+    rooms: BTreeMap<String, Notify>,
+}
+
+impl RoomSettings {
+    /// Loads the settings from `path`, starting with every room shown if the
+    /// file does not exist yet.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        match fs::read(path) {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(RoomSettings::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, serde_json::to_vec_pretty(self)?)
+    }
+
+    pub fn get(&self, room: &str) -> Notify {
+        self.rooms.get(room).copied().unwrap_or_default()
+    }
+
+    /// Sets `room` to `notify`. Returns whether that changed anything.
+    pub fn set(&mut self, room: &str, notify: Notify) -> bool {
+        let previous = match notify {
+            Notify::All => self.rooms.remove(room),
+            notify => self.rooms.insert(room.to_string(), notify),
+        };
+        previous.unwrap_or_default() != notify
+    }
+}
@@ -0,0 +1,361 @@
+use crate::{
+    command::{Command, Reply},
+    handle::{ChatHandle, NODE_STOPPED},
+    parser::HISTORY_PAGE_SIZE,
+    token::same_token,
+};
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::post,
+    Json, Router,
+};
+use libp2p::{Multiaddr, PeerId};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Map, Value};
+use std::{io, net::SocketAddr, sync::Arc};
+use tokio::net::TcpListener;
+
+/// The body was not JSON.
+pub const PARSE_ERROR: i64 = -32700;
+/// The JSON was not a JSON-RPC 2.0 request.
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+/// The params were missing a field, had one of the wrong type, or one the
+/// method doesn't take.
+pub const INVALID_PARAMS: i64 = -32602;
+/// The node answered with something the method doesn't expect.
+pub const INTERNAL_ERROR: i64 = -32603;
+/// The node refused the command; the message says why.
+pub const COMMAND_FAILED: i64 = -32000;
+/// The bearer token was missing or wrong, or the call came from a web page.
+pub const UNAUTHORIZED: i64 = -32001;
+/// The node stopped, so nothing can be asked of it.
+pub const NODE_UNAVAILABLE: i64 = -32002;
+
+/// Most messages `getHistory` returns at once.
+const MAX_LIMIT: usize = 1000;
+
+/// Binds `addr` and serves JSON-RPC there in the background, running every
+/// call through `handle`. Calls need `Authorization: Bearer <token>`.
+/// Returns the address actually bound.
+pub async fn serve(addr: SocketAddr, handle: ChatHandle, token: String) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind(addr).await?;
+    let bound = listener.local_addr()?;
+    let app = router(handle, token);
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            println!("JSON-RPC server stopped: {}", e);
+        }
+    });
+    Ok(bound)
+}
+
+/// JSON-RPC 2.0 over `POST /`, taking a single call or a batch of them.
+/// Params are given by name; those marked `?` may be left out.
+///
+/// - `sendMessage {text, room?, peer?, expires_in?}` → `{id}`: publishes
+///   `text` to `room`, or the current room, or sends it directly to `peer`.
+///   `expires_in` is in seconds and only applies to rooms.
+/// - `listPeers {}` → `{peers: [{peer_id, protocol, nickname, verified,
+///   bandwidth: {inbound, outbound}}]}`: the connected peers.
+/// - `joinRoom {room}` → `{room}`.
+/// - `getHistory {room?, peer?, since?, limit?, page?}` → `{room, messages,
+///   first, total, statuses?}`: a page of up to `limit` (20 by default)
+///   messages oldest first, as `/history` shows them. Page 0 holds the
+///   latest; `since` is a unix timestamp.
+/// - `dial {address}` → `{address}`: starts dialing a multiaddr.
+///
+/// Errors carry one of the codes above and the node's message. Calls must
+/// be sent as `application/json` and without an `Origin`, which keeps web
+/// pages from making the browser call in.
+pub fn router(handle: ChatHandle, token: String) -> Router {
+    let rpc = Rpc {
+        handle,
+        token: token.into(),
+    };
+    Router::new().route("/", post(call)).with_state(rpc)
+}
+
+#[derive(Clone)]
+struct Rpc {
+    handle: ChatHandle,
+    token: Arc<str>,
+}
+
+#[derive(Debug)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError {
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn from_node(message: String) -> Self {
+        let code = if message == NODE_STOPPED {
+            NODE_UNAVAILABLE
+        } else {
+            COMMAND_FAILED
+        };
+        RpcError::new(code, message)
+    }
+
+    fn response(self, id: Value) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "error": { "code": self.code, "message": self.message },
+            "id": id,
+        })
+    }
+}
+
+async fn call(State(rpc): State<Rpc>, headers: HeaderMap, body: Bytes) -> Response {
+    // Browsers name the page behind every cross-site post, and can only send
+    // it as JSON after asking first, which goes unanswered here.
+    if headers.contains_key(header::ORIGIN) {
+        return refuse(
+            StatusCode::FORBIDDEN,
+            UNAUTHORIZED,
+            "calls from web pages are refused",
+        );
+    }
+    let json = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|media_type| media_type.trim().eq_ignore_ascii_case("application/json"));
+    if !json {
+        return refuse(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            INVALID_REQUEST,
+            "Content-Type must be application/json",
+        );
+    }
+    let presented = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if !presented.is_some_and(|presented| same_token(presented, &rpc.token)) {
+        return refuse(
+            StatusCode::UNAUTHORIZED,
+            UNAUTHORIZED,
+            "missing or wrong bearer token",
+        );
+    }
+
+    let request: Value = match serde_json::from_slice(&body) {
+        Ok(request) => request,
+        Err(e) => {
+            let error = RpcError::new(PARSE_ERROR, format!("invalid JSON: {}", e));
+            return Json(error.response(Value::Null)).into_response();
+        }
+    };
+    let response = match request {
+        Value::Array(calls) if calls.is_empty() => {
+            Some(RpcError::new(INVALID_REQUEST, "empty batch").response(Value::Null))
+        }
+        Value::Array(calls) => {
+            // Calls of a batch run in order, as if sent one after another.
+            let mut responses = Vec::new();
+            for request in calls {
+                responses.extend(answer(&rpc.handle, request).await);
+            }
+            (!responses.is_empty()).then_some(Value::Array(responses))
+        }
+        request => answer(&rpc.handle, request).await,
+    };
+    match response {
+        Some(response) => Json(response).into_response(),
+        // Only notifications were sent, and they get no response.
+        None => StatusCode::NO_CONTENT.into_response(),
+    }
+}
+
+/// Answers a call turned away before its body is read.
+fn refuse(status: StatusCode, code: i64, message: &str) -> Response {
+    let error = RpcError::new(code, message);
+    (status, Json(error.response(Value::Null))).into_response()
+}
+
+/// Runs one call. Returns its response, or `None` for a notification.
+async fn answer(handle: &ChatHandle, request: Value) -> Option<Value> {
+    let Value::Object(mut request) = request else {
+        return Some(RpcError::new(INVALID_REQUEST, "expected an object").response(Value::Null));
+    };
+    let id = request.remove("id");
+    let valid_id = match &id {
+        None | Some(Value::Null | Value::Number(_) | Value::String(_)) => true,
+        Some(_) => false,
+    };
+    if !valid_id {
+        let error = RpcError::new(INVALID_REQUEST, "id must be a string, number or null");
+        return Some(error.response(Value::Null));
+    }
+    let result = match (request.remove("jsonrpc"), request.remove("method")) {
+        (Some(version), Some(Value::String(method))) if version == "2.0" => {
+            run(handle, &method, request.remove("params")).await
+        }
+        _ => Err(RpcError::new(
+            INVALID_REQUEST,
+            "expected jsonrpc \"2.0\" and a method name",
+        )),
+    };
+
+    let id = id?;
+    Some(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "result": result, "id": id }),
+        Err(error) => error.response(id),
+    })
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NoParams {}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SendParams {
+    text: String,
+    room: Option<String>,
+    peer: Option<PeerId>,
+    expires_in: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JoinParams {
+    room: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct HistoryParams {
+    room: Option<String>,
+    peer: Option<PeerId>,
+    since: Option<u64>,
+    limit: Option<usize>,
+    #[serde(default)]
+    page: usize,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DialParams {
+    address: Multiaddr,
+}
+
+/// Turns a call into the command it stands for and runs it. The result is
+/// the reply, without its `type`.
+async fn run(handle: &ChatHandle, method: &str, params: Option<Value>) -> Result<Value, RpcError> {
+    let (command, expected): (Command, fn(&Reply) -> bool) = match method {
+        "sendMessage" => {
+            let params: SendParams = parse_params(params)?;
+            if params.text.trim().is_empty() {
+                return Err(RpcError::new(INVALID_PARAMS, "text is empty"));
+            }
+            let command = match (params.room, params.peer, params.expires_in) {
+                (room, None, expires_in) => Command::Send {
+                    text: params.text,
+                    room,
+                    expires_in,
+                },
+                (None, Some(peer), None) => Command::Msg {
+                    peer,
+                    text: params.text,
+                },
+                (Some(_), Some(_), _) => {
+                    return Err(RpcError::new(
+                        INVALID_PARAMS,
+                        "give a room or a peer, not both",
+                    ))
+                }
+                (None, Some(_), Some(_)) => {
+                    return Err(RpcError::new(
+                        INVALID_PARAMS,
+                        "expires_in only applies to rooms",
+                    ))
+                }
+            };
+            (command, |reply| matches!(reply, Reply::Sent { .. }))
+        }
+        "listPeers" => {
+            parse_params::<NoParams>(params)?;
+            (Command::Peers, |reply| matches!(reply, Reply::Peers { .. }))
+        }
+        "joinRoom" => {
+            let JoinParams { room } = parse_params(params)?;
+            if room.trim().is_empty() {
+                return Err(RpcError::new(INVALID_PARAMS, "room is empty"));
+            }
+            (Command::Join { room }, |reply| {
+                matches!(reply, Reply::Joined { .. })
+            })
+        }
+        "getHistory" => {
+            let params: HistoryParams = parse_params(params)?;
+            let limit = params.limit.unwrap_or(HISTORY_PAGE_SIZE);
+            if !(1..=MAX_LIMIT).contains(&limit) {
+                return Err(RpcError::new(
+                    INVALID_PARAMS,
+                    format!("limit must be between 1 and {}", MAX_LIMIT),
+                ));
+            }
+            let command = Command::History {
+                room: params.room,
+                peer: params.peer,
+                since: params.since,
+                limit,
+                page: params.page,
+            };
+            (command, |reply| matches!(reply, Reply::History { .. }))
+        }
+        "dial" => {
+            let DialParams { address } = parse_params(params)?;
+            (Command::Dial { address }, |reply| {
+                matches!(reply, Reply::Dialing { .. })
+            })
+        }
+        _ => {
+            return Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("no method named {}", method),
+            ))
+        }
+    };
+
+    let reply = handle.execute(command).await.map_err(RpcError::from_node)?;
+    if !expected(&reply) {
+        return Err(RpcError::new(
+            INTERNAL_ERROR,
+            format!("unexpected reply: {}", reply),
+        ));
+    }
+    let mut result = serde_json::to_value(&reply).expect("replies serialize");
+    if let Value::Object(fields) = &mut result {
+        fields.remove("type");
+    }
+    Ok(result)
+}
+
+/// Reads by-name params, treating none at all like `{}`.
+fn parse_params<T: DeserializeOwned>(params: Option<Value>) -> Result<T, RpcError> {
+    let params = match params {
+        None => Value::Object(Map::new()),
+        Some(params @ Value::Object(_)) => params,
+        Some(_) => {
+            return Err(RpcError::new(
+                INVALID_PARAMS,
+                "params must be an object of named values",
+            ))
+        }
+    };
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+}
@@ -0,0 +1,124 @@
+use crate::message::{ChatMessage, MessageId};
+use regex::{Regex, RegexBuilder};
+use serde::Serialize;
+use std::ops::Range;
+use time::{macros::format_description, Date, OffsetDateTime};
+
+/// Most hits returned by a single search.
+pub const MAX_SEARCH_RESULTS: usize = 20;
+
+/// Characters of context kept on either side of a match in an excerpt.
+const EXCERPT_CONTEXT: usize = 30;
+
+/// What `/search` looks for. Plain terms match case-insensitively as a
+/// substring; regular expressions are used as written.
+#[derive(Debug, Clone)]
+pub struct SearchQuery {
+    pattern: Regex,
+    /// Only messages sent before this unix timestamp match.
+    before: Option<u64>,
+    /// Only messages sent at or after this unix timestamp match.
+    after: Option<u64>,
+}
+
+impl SearchQuery {
+    pub fn new(
+        term: &str,
+        regex: bool,
+        before: Option<u64>,
+        after: Option<u64>,
+    ) -> Result<Self, String> {
+        let pattern = if regex {
+            Regex::new(term)
+        } else {
+            RegexBuilder::new(&regex::escape(term))
+                .case_insensitive(true)
+                .build()
+        }
+        .map_err(|e| format!("Invalid search pattern: {}", e))?;
+
+        Ok(SearchQuery {
+            pattern,
+            before,
+            after,
+        })
+    }
+
+    /// A hit for `message` if it matches the query. Deleted messages never match.
+    pub fn hit(&self, message: &ChatMessage) -> Option<SearchHit> {
+        if message.deleted
+            || self
+                .before
+                .is_some_and(|before| message.timestamp >= before)
+            || self.after.is_some_and(|after| message.timestamp < after)
+        {
+            return None;
+        }
+
+        let text = message.text();
+        let found = self.pattern.find(text)?;
+        Some(SearchHit {
+            id: message.id,
+            room: message.room.clone(),
+            sender: message.sender(),
+            timestamp: message.timestamp,
+            excerpt: highlight(text, found.range()),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchHit {
+    pub id: MessageId,
+    pub room: Option<String>,
+    pub sender: String,
+    pub timestamp: u64,
+    /// The text around the first match, with the match wrapped in `**`.
+    pub excerpt: String,
+}
+
+/// Cuts `text` down to the match in `range` plus some context, marking the match.
+fn highlight(text: &str, range: Range<usize>) -> String {
+    let start = text[..range.start]
+        .char_indices()
+        .rev()
+        .nth(EXCERPT_CONTEXT - 1)
+        .map_or(0, |(index, _)| index);
+    let end = text[range.end..]
+        .char_indices()
+        .nth(EXCERPT_CONTEXT)
+        .map_or(text.len(), |(index, _)| range.end + index);
+
+    let excerpt = format!(
+        "{}{}**{}**{}{}",
+        if start > 0 { "…" } else { "" },
+        &text[start..range.start],
+        &text[range.clone()],
+        &text[range.end..end],
+        if end < text.len() { "…" } else { "" },
+    );
+    excerpt.replace('\n', " ")
+}
+
+/// Parses a `YYYY-MM-DD` date into the unix timestamp of its first second, in UTC.
+pub fn parse_date(date: &str) -> Option<u64> {
+    let date = Date::parse(date, format_description!("[year]-[month]-[day]")).ok()?;
+    date.midnight()
+        .assume_utc()
+        .unix_timestamp()
+        .try_into()
+        .ok()
+}
+
+/// Renders a unix timestamp as `YYYY-MM-DD HH:MM` in UTC.
+pub fn format_timestamp(timestamp: u64) -> String {
+    i64::try_from(timestamp)
+        .ok()
+        .and_then(|timestamp| OffsetDateTime::from_unix_timestamp(timestamp).ok())
+        .and_then(|datetime| {
+            datetime
+                .format(format_description!("[year]-[month]-[day] [hour]:[minute]"))
+                .ok()
+        })
+        .unwrap_or_else(|| timestamp.to_string())
+}
@@ -0,0 +1,220 @@
+use libp2p::{
+    core::{
+        upgrade::{InboundConnectionUpgrade, OutboundConnectionUpgrade, UpgradeInfo},
+        Endpoint,
+    },
+    futures::{future::BoxFuture, FutureExt, TryFutureExt},
+    swarm::{
+        behaviour::ConnectionEstablished, dummy, ConnectionClosed, ConnectionDenied, ConnectionId,
+        FromSwarm, NetworkBehaviour, THandler, THandlerInEvent, THandlerOutEvent, ToSwarm,
+    },
+    Multiaddr, PeerId,
+};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    fmt,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+/// The protocol a connection was secured with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SecurityProtocol {
+    Noise,
+    Tls,
+}
+
+impl fmt::Display for SecurityProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecurityProtocol::Noise => write!(f, "noise"),
+            SecurityProtocol::Tls => write!(f, "tls"),
+        }
+    }
+}
+
+/// The protocol of each peer's latest handshake, written by the transport as
+/// handshakes complete and read back by `ConnectionSecurity` once the
+/// connection is established.
+#[derive(Debug, Clone, Default)]
+pub struct Handshakes {
+    latest: Arc<Mutex<HashMap<PeerId, SecurityProtocol>>>,
+}
+
+impl Handshakes {
+    /// Wraps the security upgrade `inner`, which speaks `protocol`, so the
+    /// handshakes it completes are recorded.
+    pub fn record<U>(&self, protocol: SecurityProtocol, inner: U) -> RecordHandshake<U> {
+        RecordHandshake {
+            inner,
+            protocol,
+            handshakes: self.clone(),
+        }
+    }
+
+    fn completed(&self, peer: PeerId, protocol: SecurityProtocol) {
+        self.latest
+            .lock()
+            .expect("handshakes lock poisoned")
+            .insert(peer, protocol);
+    }
+
+    fn take(&self, peer: &PeerId) -> Option<SecurityProtocol> {
+        self.latest
+            .lock()
+            .expect("handshakes lock poisoned")
+            .remove(peer)
+    }
+}
+
+/// A security upgrade whose handshakes are recorded in `Handshakes`.
+#[derive(Debug, Clone)]
+pub struct RecordHandshake<U> {
+    inner: U,
+    protocol: SecurityProtocol,
+    handshakes: Handshakes,
+}
+
+impl<U: UpgradeInfo> UpgradeInfo for RecordHandshake<U> {
+    type Info = U::Info;
+    type InfoIter = U::InfoIter;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        self.inner.protocol_info()
+    }
+}
+
+impl<T, U, S> InboundConnectionUpgrade<T> for RecordHandshake<U>
+where
+    U: InboundConnectionUpgrade<T, Output = (PeerId, S)>,
+    U::Future: Send + 'static,
+{
+    type Output = (PeerId, S);
+    type Error = U::Error;
+    type Future = BoxFuture<'static, Result<Self::Output, Self::Error>>;
+
+    fn upgrade_inbound(self, socket: T, info: Self::Info) -> Self::Future {
+        let (handshakes, protocol) = (self.handshakes, self.protocol);
+        self.inner
+            .upgrade_inbound(socket, info)
+            .inspect_ok(move |(peer, _)| handshakes.completed(*peer, protocol))
+            .boxed()
+    }
+}
+
+impl<T, U, S> OutboundConnectionUpgrade<T> for RecordHandshake<U>
+where
+    U: OutboundConnectionUpgrade<T, Output = (PeerId, S)>,
+    U::Future: Send + 'static,
+{
+    type Output = (PeerId, S);
+    type Error = U::Error;
+    type Future = BoxFuture<'static, Result<Self::Output, Self::Error>>;
+
+    fn upgrade_outbound(self, socket: T, info: Self::Info) -> Self::Future {
+        let (handshakes, protocol) = (self.handshakes, self.protocol);
+        self.inner
+            .upgrade_outbound(socket, info)
+            .inspect_ok(move |(peer, _)| handshakes.completed(*peer, protocol))
+            .boxed()
+    }
+}
+
+/// Which protocol secured each open connection, as recorded in its
+/// `Handshakes`. Connections of transports that don't record their
+/// handshakes, such as QUIC or the memory transport of tests, have none.
+///
+/// A handshake is matched to its connection by peer, so of two connections
+/// to one peer whose handshakes finish at the same moment, each may be given
+/// the other's protocol.
+#[derive(Debug, Default)]
+pub struct ConnectionSecurity {
+    handshakes: Handshakes,
+    connections: HashMap<ConnectionId, (PeerId, SecurityProtocol)>,
+}
+
+impl ConnectionSecurity {
+    /// Where the transport records its handshakes for this behaviour.
+    pub fn handshakes(&self) -> Handshakes {
+        self.handshakes.clone()
+    }
+
+    pub fn of(&self, connection_id: ConnectionId) -> Option<SecurityProtocol> {
+        self.connections
+            .get(&connection_id)
+            .map(|(_, protocol)| *protocol)
+    }
+
+    /// The protocols securing our connections to `peer`, each once and in
+    /// order.
+    pub fn of_peer(&self, peer: &PeerId) -> Vec<SecurityProtocol> {
+        let mut protocols: Vec<SecurityProtocol> = self
+            .connections
+            .values()
+            .filter(|(connected, _)| connected == peer)
+            .map(|(_, protocol)| *protocol)
+            .collect();
+        protocols.sort();
+        protocols.dedup();
+        protocols
+    }
+}
+
+impl NetworkBehaviour for ConnectionSecurity {
+    type ConnectionHandler = dummy::ConnectionHandler;
+    type ToSwarm = Infallible;
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        _: ConnectionId,
+        _: PeerId,
+        _: &Multiaddr,
+        _: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        _: ConnectionId,
+        _: PeerId,
+        _: &Multiaddr,
+        _: Endpoint,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        match event {
+            FromSwarm::ConnectionEstablished(ConnectionEstablished {
+                peer_id,
+                connection_id,
+                ..
+            }) => {
+                if let Some(protocol) = self.handshakes.take(&peer_id) {
+                    self.connections.insert(connection_id, (peer_id, protocol));
+                }
+            }
+            FromSwarm::ConnectionClosed(ConnectionClosed { connection_id, .. }) => {
+                self.connections.remove(&connection_id);
+            }
+            _ => {}
+        }
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        _: PeerId,
+        _: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        match event {}
+    }
+
+    fn poll(&mut self, _: &mut Context<'_>) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        Poll::Pending
+    }
+}
@@ -0,0 +1,54 @@
+use crate::dial::is_public;
+use libp2p::{multiaddr::Protocol, Multiaddr, PeerId};
+use qrcode::{render::unicode::Dense1x2, QrCode};
+
+/// Addresses peers can dial us at, best first and each ending in
+/// `/p2p/<peer>`: confirmed external addresses, then listen addresses on
+/// public IPs, then LAN ones, and loopback ones last, which only help on
+/// the same machine.
+pub fn shareable_addresses<'a>(
+    peer: PeerId,
+    external: impl IntoIterator<Item = &'a Multiaddr>,
+    listen: impl IntoIterator<Item = &'a Multiaddr>,
+) -> Vec<Multiaddr> {
+    let mut listen: Vec<&Multiaddr> = listen.into_iter().collect();
+    listen.sort_by_key(|address| (rank(address), address.to_string()));
+
+    let mut addresses = Vec::new();
+    for address in external.into_iter().chain(listen) {
+        let address = address
+            .clone()
+            .with_p2p(peer)
+            .unwrap_or_else(|address| address);
+        if !addresses.contains(&address) {
+            addresses.push(address);
+        }
+    }
+    addresses
+}
+
+/// Where a listen address falls in the order: public, LAN, loopback.
+fn rank(address: &Multiaddr) -> u8 {
+    if is_public(address) {
+        return 0;
+    }
+    match address.iter().next() {
+        Some(Protocol::Ip4(ip)) if ip.is_loopback() => 2,
+        Some(Protocol::Ip6(ip)) if ip.is_loopback() => 2,
+        _ => 1,
+    }
+}
+
+/// `text` as a QR code drawn with half blocks, two rows of modules per line
+/// of the terminal.
+pub fn qr_code(text: &str) -> Result<String, String> {
+    let code = QrCode::new(text).map_err(|e| format!("can't encode {}: {}", text, e))?;
+    // Most terminals draw light text on a dark background, so the blocks
+    // stand for the light modules, which keeps the code readable to
+    // scanners.
+    Ok(code
+        .render::<Dense1x2>()
+        .dark_color(Dense1x2::Light)
+        .light_color(Dense1x2::Dark)
+        .build())
+}
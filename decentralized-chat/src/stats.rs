@@ -0,0 +1,575 @@
+use crate::{bandwidth::PEER_BANDWIDTH_METRIC, compression::Compressed};
+use libp2p::{metrics::Registry, request_response::OutboundRequestId, Multiaddr, PeerId};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    time::{Duration, Instant},
+};
+
+/// Chat messages sent and received in one room, or directly.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct MessageCounts {
+    pub sent: u64,
+    pub received: u64,
+}
+
+/// Counters the event loop updates as traffic flows.
+#[derive(Debug)]
+pub struct Counters {
+    started: Instant,
+    rooms: BTreeMap<String, MessageCounts>,
+    direct: MessageCounts,
+    pending_requests: HashSet<OutboundRequestId>,
+    outbound_failures: u64,
+    inbound_failures: u64,
+    denied_connections: u64,
+    throttled_messages: u64,
+    shed_messages: u64,
+    compression: CompressionSavings,
+}
+
+impl Default for Counters {
+    fn default() -> Self {
+        Counters::new(Instant::now())
+    }
+}
+
+impl Counters {
+    pub fn new(started: Instant) -> Self {
+        Counters {
+            started,
+            rooms: BTreeMap::new(),
+            direct: MessageCounts::default(),
+            pending_requests: HashSet::new(),
+            outbound_failures: 0,
+            inbound_failures: 0,
+            denied_connections: 0,
+            throttled_messages: 0,
+            shed_messages: 0,
+            compression: CompressionSavings::default(),
+        }
+    }
+
+    /// Counts a message we sent to `room`, or directly to a peer if unset.
+    pub fn message_sent(&mut self, room: Option<&str>) {
+        self.counts(room).sent += 1;
+    }
+
+    /// Counts a message received in `room`, or directly from a peer if unset.
+    pub fn message_received(&mut self, room: Option<&str>) {
+        self.counts(room).received += 1;
+    }
+
+    fn counts(&mut self, room: Option<&str>) -> &mut MessageCounts {
+        match room {
+            Some(room) => self.rooms.entry(room.to_string()).or_default(),
+            None => &mut self.direct,
+        }
+    }
+
+    /// Tracks a request until its response or failure arrives.
+    pub fn request_sent(&mut self, request_id: OutboundRequestId) {
+        self.pending_requests.insert(request_id);
+    }
+
+    pub fn response_received(&mut self, request_id: OutboundRequestId) {
+        self.pending_requests.remove(&request_id);
+    }
+
+    pub fn outbound_failure(&mut self, request_id: OutboundRequestId) {
+        self.pending_requests.remove(&request_id);
+        self.outbound_failures += 1;
+    }
+
+    pub fn inbound_failure(&mut self) {
+        self.inbound_failures += 1;
+    }
+
+    /// Counts a connection refused because it would exceed a connection
+    /// limit.
+    pub fn connection_denied(&mut self) {
+        self.denied_connections += 1;
+    }
+
+    /// Counts a message dropped because its sender was over the rate limit.
+    pub fn message_throttled(&mut self) {
+        self.throttled_messages += 1;
+    }
+
+    /// Counts a broadcast dropped while the node was flooded.
+    pub fn message_shed(&mut self) {
+        self.shed_messages += 1;
+    }
+
+    /// Counts a direct request or response sent compressed.
+    pub fn payload_compressed(&mut self, compressed: Compressed) {
+        self.compression.payloads += 1;
+        self.compression.original_bytes += compressed.original_bytes as u64;
+        self.compression.compressed_bytes += compressed.compressed_bytes as u64;
+    }
+
+    pub fn uptime(&self, now: Instant) -> Duration {
+        now.duration_since(self.started)
+    }
+
+    pub fn rooms(&self) -> &BTreeMap<String, MessageCounts> {
+        &self.rooms
+    }
+
+    pub fn direct(&self) -> MessageCounts {
+        self.direct
+    }
+
+    pub fn pending_requests(&self) -> usize {
+        self.pending_requests.len()
+    }
+
+    pub fn denied_connections(&self) -> u64 {
+        self.denied_connections
+    }
+
+    pub fn throttled_messages(&self) -> u64 {
+        self.throttled_messages
+    }
+
+    pub fn shed_messages(&self) -> u64 {
+        self.shed_messages
+    }
+
+    pub fn compression(&self) -> CompressionSavings {
+        self.compression
+    }
+
+    pub fn request_failures(&self) -> RequestFailures {
+        RequestFailures {
+            outbound: self.outbound_failures,
+            inbound: self.inbound_failures,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct RequestFailures {
+    /// Requests we sent that failed, including ones that timed out.
+    pub outbound: u64,
+    /// Requests from peers we could not answer.
+    pub inbound: u64,
+}
+
+/// What compressing the direct payloads we sent saved.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct CompressionSavings {
+    pub payloads: u64,
+    /// Bytes of JSON the payloads had.
+    pub original_bytes: u64,
+    /// Bytes they took compressed.
+    pub compressed_bytes: u64,
+}
+
+impl fmt::Display for CompressionSavings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.payloads == 0 {
+            return write!(f, "nothing compressed yet");
+        }
+        let saved = self.original_bytes - self.compressed_bytes;
+        write!(
+            f,
+            "{} payloads, {} down to {} ({}% saved)",
+            self.payloads,
+            format_bytes(self.original_bytes),
+            format_bytes(self.compressed_bytes),
+            saved * 100 / self.original_bytes
+        )
+    }
+}
+
+/// Bytes moved over one transport protocol stack, such as `/ip4/tcp/p2p`,
+/// or with one peer.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Bandwidth {
+    pub inbound: u64,
+    pub outbound: u64,
+}
+
+impl Bandwidth {
+    /// The bytes moved since `earlier` was read from the same counters.
+    pub fn since(self, earlier: Bandwidth) -> Bandwidth {
+        Bandwidth {
+            inbound: self.inbound.saturating_sub(earlier.inbound),
+            outbound: self.outbound.saturating_sub(earlier.outbound),
+        }
+    }
+
+    pub fn total(self) -> u64 {
+        self.inbound + self.outbound
+    }
+}
+
+impl fmt::Display for Bandwidth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} in, {} out",
+            format_bytes(self.inbound),
+            format_bytes(self.outbound)
+        )
+    }
+}
+
+/// Reads the byte counters the swarm's bandwidth metrics record into
+/// `registry`, keyed by transport protocol stack.
+pub fn bandwidth(registry: &Registry) -> BTreeMap<String, Bandwidth> {
+    byte_counters(registry, "libp2p_bandwidth_bytes", "protocols")
+}
+
+/// Reads the byte counters `PeerBandwidth` records into `registry`, keyed by
+/// peer. They run from the peer's first connection on.
+pub fn peer_bandwidth(registry: &Registry) -> HashMap<PeerId, Bandwidth> {
+    byte_counters(registry, PEER_BANDWIDTH_METRIC, "peer")
+        .into_iter()
+        .filter_map(|(peer, bandwidth)| Some((peer.parse().ok()?, bandwidth)))
+        .collect()
+}
+
+/// Sums the inbound and outbound samples of the counter `metric` in
+/// `registry` by the value of their `key` label.
+fn byte_counters(registry: &Registry, metric: &str, key: &str) -> BTreeMap<String, Bandwidth> {
+    let mut encoded = String::new();
+    if prometheus_client::encoding::text::encode(&mut encoded, registry).is_err() {
+        return BTreeMap::new();
+    }
+
+    let mut bandwidth = BTreeMap::<String, Bandwidth>::new();
+    for line in encoded.lines() {
+        // Lines look like:
+        // libp2p_bandwidth_bytes_total{protocols="/ip4/tcp/p2p",direction="Inbound"} 1234
+        let Some(rest) = line
+            .strip_prefix(metric)
+            .and_then(|rest| rest.strip_prefix("_total{"))
+        else {
+            continue;
+        };
+        let Some((labels, value)) = rest.split_once("} ") else {
+            continue;
+        };
+        let Ok(bytes) = value.trim().parse::<u64>() else {
+            continue;
+        };
+
+        let mut keyed = None;
+        let mut direction = None;
+        for label in labels.split(',') {
+            match label.split_once('=') {
+                Some((name, value)) if name == key => keyed = Some(value.trim_matches('"')),
+                Some(("direction", value)) => direction = Some(value.trim_matches('"')),
+                _ => {}
+            }
+        }
+
+        let Some(keyed) = keyed else {
+            continue;
+        };
+        let entry = bandwidth.entry(keyed.to_string()).or_default();
+        match direction {
+            Some("Inbound") => entry.inbound += bytes,
+            Some("Outbound") => entry.outbound += bytes,
+            _ => {}
+        }
+    }
+
+    bandwidth
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HistoryStats {
+    pub in_memory_messages: usize,
+    /// Approximate bytes taken by the in-memory messages.
+    pub memory_bytes: usize,
+    /// Size of the history file on disk.
+    pub file_bytes: u64,
+}
+
+/// Whether peers outside our network can reach us.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NatStatus {
+    /// A peer with a public address has connected to us.
+    Reachable,
+    /// We announce an external address, but nobody from outside has used it
+    /// yet.
+    Unconfirmed,
+    /// We have no external address, so we are most likely behind a NAT.
+    Unknown,
+}
+
+impl fmt::Display for NatStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NatStatus::Reachable => write!(f, "reachable from outside"),
+            NatStatus::Unconfirmed => write!(f, "external address not yet confirmed"),
+            NatStatus::Unknown => write!(f, "unknown, no external address"),
+        }
+    }
+}
+
+/// Whether our listen ports are mapped on the gateway with UPnP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PortMapping {
+    /// Turned off by `--no-upnp` or `swarm.upnp`.
+    Disabled,
+    /// No gateway has answered yet.
+    Searching,
+    /// A port is mapped; its address is among the external ones.
+    Mapped,
+    /// The gateway stopped renewing every mapping we had.
+    Expired,
+    /// No gateway on the network speaks UPnP.
+    NoGateway,
+    /// The gateway is itself behind a NAT, so mapping a port on it
+    /// doesn't make us reachable.
+    NonRoutableGateway,
+}
+
+impl fmt::Display for PortMapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PortMapping::Disabled => write!(f, "off"),
+            PortMapping::Searching => write!(f, "looking for a UPnP gateway"),
+            PortMapping::Mapped => write!(f, "mapped on the gateway with UPnP"),
+            PortMapping::Expired => write!(f, "the gateway stopped renewing the mapping"),
+            PortMapping::NoGateway => write!(f, "no UPnP gateway found"),
+            PortMapping::NonRoutableGateway => write!(f, "the gateway is behind another NAT"),
+        }
+    }
+}
+
+/// A snapshot of the node's state, as shown by `/stats`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Stats {
+    pub uptime_secs: u64,
+    pub peer_id: PeerId,
+    pub listen_addrs: Vec<Multiaddr>,
+    /// Addresses confirmed to reach us from outside, which we advertise.
+    pub external_addrs: Vec<Multiaddr>,
+    pub connected_peers: usize,
+    pub nat: NatStatus,
+    pub port_mapping: PortMapping,
+    /// Connections refused because of the configured connection limits.
+    pub denied_connections: u64,
+    /// Messages per room the node has sent to or received from.
+    pub rooms: BTreeMap<String, MessageCounts>,
+    /// Rooms we are subscribed to, including ones without traffic yet.
+    pub joined_rooms: usize,
+    pub direct_messages: MessageCounts,
+    /// Messages dropped because their sender was over the rate limit.
+    pub throttled_messages: u64,
+    /// Broadcasts dropped while messages arrived faster than the node takes.
+    pub shed_messages: u64,
+    /// Bytes per transport protocol stack.
+    pub bandwidth: BTreeMap<String, Bandwidth>,
+    pub pending_outbound_requests: usize,
+    pub request_failures: RequestFailures,
+    pub compression: CompressionSavings,
+    /// Peers in our gossipsub mesh for each subscribed topic.
+    pub mesh_peers: BTreeMap<String, usize>,
+    pub history: HistoryStats,
+}
+
+impl Stats {
+    /// Messages sent and received across every room and directly.
+    pub fn total_messages(&self) -> MessageCounts {
+        self.rooms
+            .values()
+            .fold(self.direct_messages, |total, counts| MessageCounts {
+                sent: total.sent + counts.sent,
+                received: total.received + counts.received,
+            })
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.total_messages();
+        let summary = [
+            ("Peer", self.peer_id.to_string()),
+            ("Uptime", format_uptime(self.uptime_secs)),
+            (
+                "Connected peers",
+                format!(
+                    "{} ({} connections denied by limits)",
+                    self.connected_peers, self.denied_connections
+                ),
+            ),
+            (
+                "Messages",
+                format!("{} sent, {} received", total.sent, total.received),
+            ),
+            ("Joined rooms", self.joined_rooms.to_string()),
+            ("NAT", self.nat.to_string()),
+            ("Port mapping", self.port_mapping.to_string()),
+            (
+                "History",
+                format!(
+                    "{} messages in memory (~{}), {} on disk",
+                    self.history.in_memory_messages,
+                    format_bytes(self.history.memory_bytes as u64),
+                    format_bytes(self.history.file_bytes)
+                ),
+            ),
+        ];
+        let width = summary
+            .iter()
+            .map(|(label, _)| label.len())
+            .max()
+            .unwrap_or(0);
+        for (label, value) in &summary {
+            writeln!(f, "{:width$}  {}", label, value, width = width)?;
+        }
+
+        writeln!(f, "Listening on:")?;
+        for address in &self.listen_addrs {
+            writeln!(f, "    {}", address)?;
+        }
+        if !self.external_addrs.is_empty() {
+            writeln!(f, "External addresses:")?;
+            for address in &self.external_addrs {
+                writeln!(f, "    {}", address)?;
+            }
+        }
+
+        writeln!(f, "Messages (sent/received):")?;
+        for (room, counts) in &self.rooms {
+            writeln!(f, "    #{} {}/{}", room, counts.sent, counts.received)?;
+        }
+        writeln!(
+            f,
+            "    direct {}/{}",
+            self.direct_messages.sent, self.direct_messages.received
+        )?;
+        writeln!(
+            f,
+            "    {} dropped by the rate limit",
+            self.throttled_messages
+        )?;
+        writeln!(f, "    {} shed during floods", self.shed_messages)?;
+
+        writeln!(f, "Bandwidth (in/out):")?;
+        for (protocols, bandwidth) in &self.bandwidth {
+            writeln!(
+                f,
+                "    {} {} / {}",
+                protocols,
+                format_bytes(bandwidth.inbound),
+                format_bytes(bandwidth.outbound)
+            )?;
+        }
+
+        writeln!(
+            f,
+            "Requests: {} pending, {} outbound failures, {} inbound failures",
+            self.pending_outbound_requests,
+            self.request_failures.outbound,
+            self.request_failures.inbound
+        )?;
+        writeln!(f, "Compression: {}", self.compression)?;
+
+        write!(f, "Mesh peers:")?;
+        for (topic, peers) in &self.mesh_peers {
+            write!(f, "\n    #{} {}", topic, peers)?;
+        }
+        Ok(())
+    }
+}
+
+/// Open connections against the configured limits, as shown by `/limits`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Limits {
+    pub established_incoming: u32,
+    pub established_outgoing: u32,
+    pub max_established: u32,
+    /// What `max_established` leaves for inbound connections.
+    pub max_established_incoming: u32,
+    pub max_established_per_peer: u32,
+    pub pending_incoming: u32,
+    pub pending_outgoing: u32,
+    pub max_pending_incoming: Option<u32>,
+    pub max_pending_outgoing: Option<u32>,
+    /// Connections refused for going over a limit since the node started.
+    pub denied_connections: u64,
+}
+
+impl fmt::Display for Limits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let of = |count: u32, max: Option<u32>| match max {
+            Some(max) => format!("{} of {}", count, max),
+            None => format!("{}, unlimited", count),
+        };
+        let rows = [
+            (
+                "Established",
+                of(
+                    self.established_incoming + self.established_outgoing,
+                    Some(self.max_established),
+                ),
+            ),
+            (
+                "  inbound",
+                of(
+                    self.established_incoming,
+                    Some(self.max_established_incoming),
+                ),
+            ),
+            ("  outbound", self.established_outgoing.to_string()),
+            (
+                "Per peer",
+                format!("at most {}", self.max_established_per_peer),
+            ),
+            (
+                "Pending inbound",
+                of(self.pending_incoming, self.max_pending_incoming),
+            ),
+            (
+                "Pending outbound",
+                of(self.pending_outgoing, self.max_pending_outgoing),
+            ),
+            ("Refused", self.denied_connections.to_string()),
+        ];
+        let width = rows.iter().map(|(label, _)| label.len()).max().unwrap_or(0);
+        let lines: Vec<String> = rows
+            .iter()
+            .map(|(label, value)| format!("{:width$}  {}", label, value, width = width))
+            .collect();
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+/// Formats seconds as e.g. `1h 2m 3s`, leaving out leading zero units.
+fn format_uptime(secs: u64) -> String {
+    let (hours, minutes, seconds) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if hours > 0 {
+        format!("{}h {}m {}s", hours, minutes, seconds)
+    } else if minutes > 0 {
+        format!("{}m {}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
@@ -0,0 +1,523 @@
+use crate::{
+    delivery::DeliveryStatus,
+    filter::ContentFilter,
+    message::{ChatMessage, MessageId},
+    search::{SearchHit, SearchQuery, MAX_SEARCH_RESULTS},
+};
+use libp2p::PeerId;
+use std::{
+    collections::{hash_map::Entry, BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    mem,
+    time::{Duration, Instant},
+};
+
+/// How long an edit or delete of an unknown message is kept around in case
+/// the original arrives out of order.
+const PENDING_EDIT_TTL: Duration = Duration::from_secs(30);
+
+/// How long a reaction to an unknown message is kept around.
+const PENDING_REACTION_TTL: Duration = Duration::from_secs(60);
+
+/// Changes to unknown messages kept per author; beyond it, the author's
+/// oldest is dropped, so a peer flooding changes only crowds out its own.
+pub const MAX_PENDING_PER_AUTHOR: usize = 32;
+
+/// Changes to unknown messages kept in all; beyond it, the oldest is dropped.
+pub const MAX_PENDING_CHANGES: usize = 256;
+
+/// Characters of the parent message shown above a reply.
+const QUOTE_LENGTH: usize = 60;
+
+/// Messages kept in memory per room unless configured otherwise.
+pub const DEFAULT_ROOM_CAPACITY: usize = 5_000;
+
+/// Messages kept in memory across all rooms unless configured otherwise.
+pub const DEFAULT_HISTORY_LIMIT: usize = 10_000;
+
+#[derive(Debug, Clone)]
+pub enum Change {
+    Edit(String),
+    Delete,
+    /// Toggles the author's reaction with this emoji.
+    React(String),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ChangeOutcome {
+    Applied,
+    Pending,
+    /// The change did not come from the original author.
+    Rejected,
+    /// The target was evicted from memory; the change has to be applied to the
+    /// persisted copy instead.
+    Evicted,
+}
+
+#[derive(Debug)]
+struct PendingChange {
+    author: PeerId,
+    target_id: MessageId,
+    change: Change,
+    received_at: Instant,
+}
+
+impl PendingChange {
+    fn is_stale(&self) -> bool {
+        let ttl = match self.change {
+            Change::React(_) => PENDING_REACTION_TTL,
+            Change::Edit(_) | Change::Delete => PENDING_EDIT_TTL,
+        };
+        self.received_at.elapsed() >= ttl
+    }
+}
+
+/// The most recent messages of each room, oldest first. Once a room holds
+/// `capacity_per_room` messages, or all rooms together hold `limit`, the
+/// oldest is evicted; callers are expected to have persisted it.
+#[derive(Debug)]
+pub struct MessageStore {
+    messages: VecDeque<ChatMessage>,
+    /// Every id ever inserted, including evicted ones, so duplicates are still
+    /// recognised after their message left memory.
+    seen: HashSet<MessageId>,
+    capacity_per_room: usize,
+    limit: usize,
+    /// Whether any message was evicted, so older ones are only on disk.
+    evicted: bool,
+    /// Changes to messages we don't have yet, oldest first.
+    pending: VecDeque<PendingChange>,
+    reactions: HashMap<MessageId, BTreeMap<String, BTreeSet<PeerId>>>,
+    /// Ids of the stored replies to each message, which itself may be gone.
+    replies: HashMap<MessageId, Vec<MessageId>>,
+    /// Names we gave peers in our contacts, shown instead of the nicknames
+    /// their messages carry.
+    names: HashMap<PeerId, String>,
+    /// Masks blocked words in the messages formatted for display.
+    filter: ContentFilter,
+    /// Our own peer id, the author of the only messages with a status.
+    local_peer: Option<PeerId>,
+    /// How far each of our messages got, shown after it.
+    statuses: HashMap<MessageId, DeliveryStatus>,
+}
+
+impl Default for MessageStore {
+    fn default() -> Self {
+        MessageStore::new(DEFAULT_HISTORY_LIMIT, DEFAULT_ROOM_CAPACITY)
+    }
+}
+
+impl MessageStore {
+    pub fn new(limit: usize, capacity_per_room: usize) -> Self {
+        MessageStore {
+            messages: VecDeque::new(),
+            seen: HashSet::new(),
+            capacity_per_room: capacity_per_room.max(1),
+            limit: limit.max(1),
+            evicted: false,
+            pending: VecDeque::new(),
+            reactions: HashMap::new(),
+            replies: HashMap::new(),
+            names: HashMap::new(),
+            filter: ContentFilter::default(),
+            local_peer: None,
+            statuses: HashMap::new(),
+        }
+    }
+
+    pub fn messages(&self) -> &VecDeque<ChatMessage> {
+        &self.messages
+    }
+
+    /// Whether messages were evicted, so searches have to read the history
+    /// file to see all of them.
+    pub fn has_evicted(&self) -> bool {
+        self.evicted
+    }
+
+    pub fn get(&self, id: &MessageId) -> Option<&ChatMessage> {
+        self.messages.iter().find(|message| message.id == *id)
+    }
+
+    /// Every message whose id starts with `prefix`.
+    pub fn find_by_prefix(&self, prefix: &str) -> Vec<&ChatMessage> {
+        let prefix = prefix.to_lowercase();
+        self.messages
+            .iter()
+            .filter(|message| message.id.simple().to_string().starts_with(&prefix))
+            .collect()
+    }
+
+    /// Resolves a message id prefix typed by the user to a single stored message.
+    pub fn resolve_prefix(&self, prefix: &str) -> Result<MessageId, String> {
+        match self.find_by_prefix(prefix).as_slice() {
+            [message] => Ok(message.id),
+            [] => Err(format!("No message matches {}", prefix)),
+            _ => Err(format!("More than one message matches {}", prefix)),
+        }
+    }
+
+    /// A message followed by its replies and theirs, each after the message
+    /// it answers and siblings oldest first, with how deeply they are nested.
+    /// The parent is omitted if we never received it or it was evicted.
+    pub fn thread(&self, id: &MessageId) -> Vec<(usize, &ChatMessage)> {
+        let mut thread: Vec<(usize, &ChatMessage)> = self
+            .get(id)
+            .map(|message| (0, message))
+            .into_iter()
+            .collect();
+        let mut visited = HashSet::from([*id]);
+        self.push_replies(id, 1, &mut visited, &mut thread);
+        thread
+    }
+
+    fn push_replies<'a>(
+        &'a self,
+        id: &MessageId,
+        depth: usize,
+        visited: &mut HashSet<MessageId>,
+        thread: &mut Vec<(usize, &'a ChatMessage)>,
+    ) {
+        let mut replies: Vec<&ChatMessage> = self
+            .replies
+            .get(id)
+            .into_iter()
+            .flatten()
+            .filter_map(|reply_id| self.get(reply_id))
+            .collect();
+        replies.sort_by_key(|message| message.timestamp);
+
+        for reply in replies {
+            // Ids are chosen by senders, who could make two messages answer
+            // each other.
+            if visited.insert(reply.id) {
+                thread.push((depth, reply));
+                self.push_replies(&reply.id, depth + 1, visited, thread);
+            }
+        }
+    }
+
+    /// Up to `MAX_SEARCH_RESULTS` messages matching `query`, newest first.
+    /// The in-memory store has no index, so this scans every message.
+    pub fn search(&self, query: &SearchQuery) -> Vec<SearchHit> {
+        let mut hits: Vec<SearchHit> = self
+            .messages
+            .iter()
+            .filter_map(|message| query.hit(message))
+            .collect();
+        hits.sort_by_key(|hit| std::cmp::Reverse(hit.timestamp));
+        hits.truncate(MAX_SEARCH_RESULTS);
+        hits
+    }
+
+    /// Shows `peer` as `name` in rendered messages, or by the nickname of
+    /// each message again if unset.
+    pub fn set_name(&mut self, peer: PeerId, name: Option<String>) {
+        match name {
+            Some(name) => self.names.insert(peer, name),
+            None => self.names.remove(&peer),
+        };
+    }
+
+    pub fn set_filter(&mut self, filter: ContentFilter) {
+        self.filter = filter;
+    }
+
+    pub fn filter(&self) -> &ContentFilter {
+        &self.filter
+    }
+
+    pub fn set_local_peer(&mut self, peer: PeerId) {
+        self.local_peer = Some(peer);
+    }
+
+    /// Moves the status of our message `id` along with `status`; see
+    /// `DeliveryStatus::advance`. Messages of other peers, and ones no
+    /// longer in memory, get none. Returns the new status.
+    pub fn set_status(&mut self, id: MessageId, status: DeliveryStatus) -> Option<DeliveryStatus> {
+        let message = self.get(&id)?;
+        if Some(message.peer_id) != self.local_peer {
+            return None;
+        }
+        let status = match self.statuses.get(&id) {
+            Some(current) => current.advance(status),
+            None => status,
+        };
+        self.statuses.insert(id, status);
+        Some(status)
+    }
+
+    pub fn status(&self, id: &MessageId) -> Option<DeliveryStatus> {
+        self.statuses.get(id).copied()
+    }
+
+    /// The name `message` is shown with: the one we gave its author, or else
+    /// the nickname it carries.
+    pub fn sender(&self, message: &ChatMessage) -> String {
+        match self.names.get(&message.peer_id) {
+            Some(name) => name.clone(),
+            None => message.sender(),
+        }
+    }
+
+    /// Renders a message for the terminal, blocked words masked, followed by
+    /// its status if it is ours and its reaction counts. Replies are
+    /// preceded by a quote of their parent.
+    pub fn format(&self, message: &ChatMessage) -> String {
+        let mut line = String::new();
+        if let Some(parent_id) = message.reply_to {
+            match self.get(&parent_id) {
+                Some(parent) => {
+                    // Masked before it is cut short, which could split a word.
+                    let mut quoted = parent.clone();
+                    self.filter.filter_message(&mut quoted);
+                    line.push_str(&format!(
+                        "  > {}: {}\n",
+                        self.sender(parent),
+                        quoted.excerpt(QUOTE_LENGTH)
+                    ));
+                }
+                None => line.push_str("  > (in reply to an unavailable message)\n"),
+            }
+        }
+
+        line.push_str(&format!(
+            "[{}] {}: {}",
+            message.short_id(),
+            self.sender(message),
+            self.filter.filter_content(&message.display_text())
+        ));
+        if let Some(status) = self.status(&message.id) {
+            line.push_str(&format!(" {}", status.glyph()));
+        }
+
+        for (emoji, peers) in self.reactions.get(&message.id).into_iter().flatten() {
+            line.push_str(&format!("  {} {}", emoji, peers.len()));
+        }
+
+        line
+    }
+
+    /// Whether a message with this id was ever inserted, even if since evicted.
+    pub fn has_seen(&self, id: &MessageId) -> bool {
+        self.seen.contains(id)
+    }
+
+    /// Rough number of bytes held by the in-memory buffer.
+    pub fn memory_usage(&self) -> usize {
+        let messages: usize = self
+            .messages
+            .iter()
+            .map(|message| {
+                mem::size_of::<ChatMessage>()
+                    + message.message.len()
+                    + message.nickname.as_ref().map_or(0, String::len)
+                    + message.room.as_ref().map_or(0, String::len)
+                    + message.edits.iter().map(String::len).sum::<usize>()
+            })
+            .sum();
+        messages + self.seen.len() * mem::size_of::<MessageId>()
+    }
+
+    /// Stores a message, applying any buffered changes that were waiting for
+    /// it and evicting the oldest message of the room if it is full. Returns
+    /// false, storing nothing, if the id was seen before.
+    pub fn insert(&mut self, message: ChatMessage) -> bool {
+        if !self.seen.insert(message.id) {
+            return false;
+        }
+
+        let target_id = message.id;
+        let room = message.room.clone();
+        if let Some(parent_id) = message.reply_to {
+            self.replies.entry(parent_id).or_default().push(target_id);
+        }
+        self.messages.push_back(message);
+        self.evict(room.as_deref());
+
+        let (ready, pending) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|pending| pending.target_id == target_id);
+        self.pending = pending;
+
+        for PendingChange { author, change, .. } in ready {
+            self.apply(author, target_id, change);
+        }
+
+        true
+    }
+
+    /// Drops the oldest messages of `room` beyond the capacity, then the
+    /// oldest of any room beyond the limit.
+    fn evict(&mut self, room: Option<&str>) {
+        let in_room = |message: &ChatMessage| message.room.as_deref() == room;
+        let mut excess = self
+            .messages
+            .iter()
+            .filter(|message| in_room(message))
+            .count()
+            .saturating_sub(self.capacity_per_room);
+
+        let (reactions, replies, statuses) =
+            (&mut self.reactions, &mut self.replies, &mut self.statuses);
+        let before = self.messages.len();
+        self.messages.retain(|message| {
+            if excess > 0 && in_room(message) {
+                excess -= 1;
+                forget(reactions, replies, statuses, message);
+                return false;
+            }
+            true
+        });
+
+        while self.messages.len() > self.limit {
+            if let Some(oldest) = self.messages.pop_front() {
+                forget(
+                    &mut self.reactions,
+                    &mut self.replies,
+                    &mut self.statuses,
+                    &oldest,
+                );
+            }
+        }
+        self.evicted |= self.messages.len() < before;
+    }
+
+    /// Applies a change to a stored message. Edits and deletes are only
+    /// accepted from the message's author; anyone may react.
+    pub fn apply(&mut self, author: PeerId, target_id: MessageId, change: Change) -> ChangeOutcome {
+        let Some(message) = self
+            .messages
+            .iter_mut()
+            .find(|message| message.id == target_id)
+        else {
+            if self.seen.contains(&target_id) {
+                return ChangeOutcome::Evicted;
+            }
+
+            self.hold(PendingChange {
+                author,
+                target_id,
+                change,
+                received_at: Instant::now(),
+            });
+            return ChangeOutcome::Pending;
+        };
+
+        match change {
+            Change::React(emoji) => {
+                let reactions = self.reactions.entry(target_id).or_default();
+                let peers = reactions.entry(emoji.clone()).or_default();
+                if !peers.remove(&author) {
+                    peers.insert(author);
+                }
+                if peers.is_empty() {
+                    reactions.remove(&emoji);
+                }
+            }
+            change => return apply_to_message(message, author, change),
+        }
+
+        ChangeOutcome::Applied
+    }
+
+    /// Drops the message `id`, once it expired. Its id is still remembered,
+    /// so a copy relayed late isn't stored again. Returns whether it was
+    /// here.
+    pub fn remove(&mut self, id: &MessageId) -> bool {
+        let Some(index) = self.messages.iter().position(|message| message.id == *id) else {
+            return false;
+        };
+        let message = self.messages.remove(index).expect("found above");
+        forget(
+            &mut self.reactions,
+            &mut self.replies,
+            &mut self.statuses,
+            &message,
+        );
+        true
+    }
+
+    /// Drops the changes that waited too long for their message.
+    pub fn drop_stale_changes(&mut self) {
+        self.pending.retain(|pending| !pending.is_stale());
+    }
+
+    /// Buffers a change until its message arrives, making room by dropping
+    /// the author's oldest change, or else the oldest of all.
+    fn hold(&mut self, change: PendingChange) {
+        let by_author = self
+            .pending
+            .iter()
+            .filter(|pending| pending.author == change.author)
+            .count();
+        if by_author >= MAX_PENDING_PER_AUTHOR {
+            let oldest = self
+                .pending
+                .iter()
+                .position(|pending| pending.author == change.author);
+            if let Some(oldest) = oldest {
+                self.pending.remove(oldest);
+            }
+        } else if self.pending.len() >= MAX_PENDING_CHANGES {
+            self.pending.pop_front();
+        }
+        self.pending.push_back(change);
+    }
+
+    /// Drops every message of `room`, or every message at all if unset,
+    /// returning how many. Their ids are still remembered, so copies that
+    /// arrive again aren't shown anew.
+    pub fn clear(&mut self, room: Option<&str>) -> usize {
+        let (reactions, replies, statuses) =
+            (&mut self.reactions, &mut self.replies, &mut self.statuses);
+        let before = self.messages.len();
+        self.messages.retain(|message| {
+            let cleared = room.is_none_or(|room| message.room.as_deref() == Some(room));
+            if cleared {
+                forget(reactions, replies, statuses, message);
+            }
+            !cleared
+        });
+        before - self.messages.len()
+    }
+}
+
+/// Drops what the store keeps about `message` besides the message itself,
+/// once it leaves memory. Replies to it stay linked to its id.
+fn forget(
+    reactions: &mut HashMap<MessageId, BTreeMap<String, BTreeSet<PeerId>>>,
+    replies: &mut HashMap<MessageId, Vec<MessageId>>,
+    statuses: &mut HashMap<MessageId, DeliveryStatus>,
+    message: &ChatMessage,
+) {
+    reactions.remove(&message.id);
+    statuses.remove(&message.id);
+    let Some(parent_id) = message.reply_to else {
+        return;
+    };
+    if let Entry::Occupied(mut siblings) = replies.entry(parent_id) {
+        siblings.get_mut().retain(|id| *id != message.id);
+        if siblings.get().is_empty() {
+            siblings.remove();
+        }
+    }
+}
+
+/// Applies an edit or delete directly to a message, such as a copy read back
+/// from the history. Only the author may change a message; reactions are not
+/// part of the message and are ignored.
+pub fn apply_to_message(
+    message: &mut ChatMessage,
+    author: PeerId,
+    change: Change,
+) -> ChangeOutcome {
+    match change {
+        Change::React(_) => {}
+        _ if message.peer_id != author => return ChangeOutcome::Rejected,
+        Change::Edit(text) if !message.deleted => message.edits.push(text),
+        Change::Edit(_) => {}
+        Change::Delete => message.deleted = true,
+    }
+
+    ChangeOutcome::Applied
+}
@@ -0,0 +1,222 @@
+use crate::compression::{self, ContentEncoding};
+use libp2p::{
+    futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, StreamExt},
+    PeerId, Stream, StreamProtocol,
+};
+use libp2p_stream::{AlreadyRegistered, Control, IncomingStreams, OpenStreamError};
+use std::{fmt, io};
+
+/// The protocol large payloads are streamed over.
+pub const STREAM_PROTOCOL: StreamProtocol = StreamProtocol::new("/chat/stream/1");
+
+/// Payloads larger than this many bytes are sent with `Streams::send_stream`
+/// rather than inside a request: the JSON codec refuses requests of more
+/// than 1 MiB.
+pub const STREAM_THRESHOLD: usize = 1024 * 1024;
+
+/// Most bytes of the payload carried by one frame, and so the most either
+/// side holds in memory at once.
+pub const MAX_FRAME_LEN: usize = 64 * 1024;
+
+/// The byte a stream starts with for each encoding of its frames.
+const IDENTITY: u8 = 0;
+const ZSTD: u8 = 1;
+
+/// Why a payload could not be streamed.
+#[derive(Debug)]
+pub enum StreamError {
+    /// No stream could be opened to the peer, or it doesn't speak
+    /// `STREAM_PROTOCOL`.
+    Open(OpenStreamError),
+    /// The remote reset the stream, or it ended before the payload did.
+    Reset,
+    /// A frame claimed more than `MAX_FRAME_LEN` bytes, or more than a
+    /// compressed one can take.
+    FrameTooLarge(usize),
+    /// The stream started with an encoding this node doesn't know.
+    UnknownEncoding(u8),
+    /// A compressed frame was corrupt or inflated past `MAX_FRAME_LEN`.
+    Decompress(io::Error),
+    /// Reading the payload to send, or writing the one received, failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StreamError::Open(e) => write!(f, "{}", e),
+            StreamError::Reset => write!(f, "the stream was reset before the payload ended"),
+            StreamError::FrameTooLarge(len) => write!(
+                f,
+                "frame of {} bytes is larger than the {} allowed",
+                len, MAX_FRAME_LEN
+            ),
+            StreamError::UnknownEncoding(byte) => write!(f, "unknown stream encoding {}", byte),
+            StreamError::Decompress(e) => write!(f, "invalid compressed frame: {}", e),
+            StreamError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for StreamError {}
+
+/// Sends and receives payloads over `STREAM_PROTOCOL`, taken from the
+/// swarm's `libp2p_stream::Behaviour`. Its futures only make progress while
+/// the swarm is polled, so they run in a task of their own.
+///
+/// A stream starts with a byte naming how its frames are encoded: 0 as they
+/// are, 1 each compressed with zstd on its own. The payload follows as a
+/// series of frames, each a big-endian `u32` length and that many bytes,
+/// ended by an empty frame. A stream that ends without the empty frame was
+/// cut short.
+pub struct Streams {
+    control: Control,
+    incoming: IncomingStreams,
+}
+
+impl Streams {
+    /// Starts accepting payloads. Only one `Streams` can accept per swarm.
+    pub fn new(behaviour: &libp2p_stream::Behaviour) -> Result<Self, AlreadyRegistered> {
+        let mut control = behaviour.new_control();
+        let incoming = control.accept(STREAM_PROTOCOL)?;
+        Ok(Streams { control, incoming })
+    }
+
+    /// Streams everything `reader` yields to `peer`, dialing it if needed,
+    /// one frame at a time: each waits until the stream has room for it.
+    /// Frames are compressed with `encoding`, which only peers offering
+    /// `COMPRESSED_CHAT_PROTOCOL` read. If `reader` fails the stream is
+    /// reset, so the peer doesn't mistake what it got for the whole payload.
+    /// Returns how many bytes of the payload were sent.
+    pub async fn send_stream(
+        &mut self,
+        peer: PeerId,
+        mut reader: impl AsyncRead + Unpin,
+        encoding: Option<ContentEncoding>,
+    ) -> Result<u64, StreamError> {
+        let mut stream = self
+            .control
+            .open_stream(peer, STREAM_PROTOCOL)
+            .await
+            .map_err(StreamError::Open)?;
+        let header = match encoding {
+            None => IDENTITY,
+            Some(ContentEncoding::Zstd) => ZSTD,
+        };
+        stream.write_all(&[header]).await.map_err(stream_error)?;
+
+        let mut chunk = vec![0; MAX_FRAME_LEN];
+        let mut sent = 0;
+        loop {
+            let len = reader.read(&mut chunk).await.map_err(StreamError::Io)?;
+            if len == 0 {
+                write_frame(&mut stream, &[]).await?;
+                break;
+            }
+            match encoding {
+                None => write_frame(&mut stream, &chunk[..len]).await?,
+                Some(ContentEncoding::Zstd) => {
+                    let compressed =
+                        compression::compress(&chunk[..len]).map_err(StreamError::Io)?;
+                    write_frame(&mut stream, &compressed).await?;
+                }
+            }
+            sent += len as u64;
+        }
+        stream.close().await.map_err(stream_error)?;
+        Ok(sent)
+    }
+
+    /// Waits for the next peer to start sending a payload. Returns `None`
+    /// once the swarm is gone.
+    pub async fn accept_stream(&mut self) -> Option<IncomingPayload> {
+        let (peer, stream) = self.incoming.next().await?;
+        Some(IncomingPayload { peer, stream })
+    }
+}
+
+/// A payload a peer started sending, read with `IncomingPayload::read_to`.
+/// Dropping it resets the stream.
+pub struct IncomingPayload {
+    pub peer: PeerId,
+    stream: Stream,
+}
+
+impl IncomingPayload {
+    /// Copies the payload into `writer` as its frames arrive, decompressed.
+    /// Returns how many bytes it had.
+    pub async fn read_to(
+        mut self,
+        mut writer: impl AsyncWrite + Unpin,
+    ) -> Result<u64, StreamError> {
+        let mut header = [0];
+        self.stream
+            .read_exact(&mut header)
+            .await
+            .map_err(stream_error)?;
+        let (encoding, max_len) = match header[0] {
+            IDENTITY => (None, MAX_FRAME_LEN),
+            ZSTD => (
+                Some(ContentEncoding::Zstd),
+                zstd::zstd_safe::compress_bound(MAX_FRAME_LEN),
+            ),
+            byte => return Err(StreamError::UnknownEncoding(byte)),
+        };
+
+        let mut chunk = vec![0; max_len];
+        let mut received = 0;
+        loop {
+            let mut len = [0; 4];
+            self.stream
+                .read_exact(&mut len)
+                .await
+                .map_err(stream_error)?;
+            let len = u32::from_be_bytes(len) as usize;
+            if len == 0 {
+                break;
+            }
+            if len > max_len {
+                return Err(StreamError::FrameTooLarge(len));
+            }
+            self.stream
+                .read_exact(&mut chunk[..len])
+                .await
+                .map_err(stream_error)?;
+            let decompressed;
+            let bytes = match encoding {
+                None => &chunk[..len],
+                Some(ContentEncoding::Zstd) => {
+                    decompressed = compression::decompress(&chunk[..len], MAX_FRAME_LEN)
+                        .map_err(StreamError::Decompress)?;
+                    &decompressed
+                }
+            };
+            writer.write_all(bytes).await.map_err(StreamError::Io)?;
+            received += bytes.len() as u64;
+        }
+        writer.flush().await.map_err(StreamError::Io)?;
+        Ok(received)
+    }
+}
+
+async fn write_frame(stream: &mut Stream, bytes: &[u8]) -> Result<(), StreamError> {
+    stream
+        .write_all(&(bytes.len() as u32).to_be_bytes())
+        .await
+        .map_err(stream_error)?;
+    stream.write_all(bytes).await.map_err(stream_error)
+}
+
+/// Tells the stream giving out from other I/O failures. Whether the muxer
+/// reports an abrupt end as a reset, an early EOF or a closed pipe, it is a
+/// `StreamError::Reset`.
+fn stream_error(e: io::Error) -> StreamError {
+    match e.kind() {
+        io::ErrorKind::UnexpectedEof
+        | io::ErrorKind::ConnectionReset
+        | io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::BrokenPipe
+        | io::ErrorKind::WriteZero => StreamError::Reset,
+        _ => StreamError::Io(e),
+    }
+}
@@ -0,0 +1,642 @@
+use crate::{
+    address_book::AddressBook,
+    behaviour::{
+        build_swarm, build_test_swarm_with_config, CustomBehaviour, CustomBehaviourEvent, Response,
+        CHAT_PROTOCOL,
+    },
+    config::Config,
+    event::ChatEvent,
+    flood::Priority,
+    handler::{self, Action, Node},
+    invite::RoomKeys,
+    members::RoomMembers,
+    mention::{mentions, mentions_peer},
+    message::{ChatMessage, MessageId},
+    nickname::Nicknames,
+    replay::ReplayGuard,
+    stats::Counters,
+    store::{Change, ChangeOutcome, MessageStore},
+};
+use async_trait::async_trait;
+use libp2p::{
+    core::{transport::MemoryTransport, upgrade::Version},
+    futures::{future, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, FutureExt, StreamExt},
+    identity,
+    metrics::Registry,
+    noise,
+    request_response::{self, Codec, OutboundFailure, ProtocolSupport, ResponseChannel},
+    swarm::SwarmEvent,
+    yamux, Multiaddr, PeerId, StreamProtocol, Swarm, SwarmBuilder, Transport,
+};
+use serde_json::Value;
+use std::{collections::VecDeque, error::Error, io, time::Duration};
+use tokio::{
+    sync::{broadcast, mpsc},
+    task::JoinHandle,
+    time::{sleep_until, Instant},
+};
+
+/// How long `connect` waits for a connection.
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub type Event = SwarmEvent<CustomBehaviourEvent>;
+
+/// A swarm from `build_test_swarm` listening on a `/memory` address, for
+/// tests that run nodes in-process without opening sockets.
+pub struct TestNode {
+    pub swarm: Swarm<CustomBehaviour>,
+    pub address: Multiaddr,
+}
+
+impl TestNode {
+    /// Starts a node with a new identity and waits until it listens.
+    pub async fn new() -> Self {
+        TestNode::with_config(&Config::default()).await
+    }
+
+    /// Like `new`, with the behaviour configured from `config`.
+    pub async fn with_config(config: &Config) -> Self {
+        let mut swarm = build_test_swarm_with_config(identity::Keypair::generate_ed25519(), config)
+            .expect("memory swarms always build");
+        swarm
+            .listen_on("/memory/0".parse().expect("valid multiaddr"))
+            .expect("memory transport listens on any port");
+        let address = loop {
+            if let SwarmEvent::NewListenAddr { address, .. } = swarm.select_next_some().await {
+                break address;
+            }
+        };
+        TestNode { swarm, address }
+    }
+
+    pub fn peer_id(&self) -> PeerId {
+        *self.swarm.local_peer_id()
+    }
+}
+
+/// The peer id of a new identity, for tests that need one but no node
+/// behind it.
+pub fn peer() -> PeerId {
+    identity::Keypair::generate_ed25519().public().to_peer_id()
+}
+
+/// A node with a new identity built as the binary builds it from `config`,
+/// over real sockets, for tests of the transport itself. It doesn't listen
+/// until `listen_tcp` is called.
+pub async fn tcp_swarm(config: &Config) -> Swarm<CustomBehaviour> {
+    tcp_swarm_as(identity::Keypair::generate_ed25519(), config).await
+}
+
+/// Like `tcp_swarm`, with the identity `keypair`.
+pub async fn tcp_swarm_as(keypair: identity::Keypair, config: &Config) -> Swarm<CustomBehaviour> {
+    build_swarm(keypair, config, &mut Registry::default())
+        .await
+        .expect("swarms build from a checked config")
+}
+
+/// Has `swarm` listen on a free TCP port of localhost and returns the
+/// address once it does.
+pub async fn listen_tcp(swarm: &mut Swarm<CustomBehaviour>) -> Multiaddr {
+    swarm
+        .listen_on("/ip4/127.0.0.1/tcp/0".parse().expect("valid multiaddr"))
+        .expect("localhost listens on a free port");
+    loop {
+        if let SwarmEvent::NewListenAddr { address, .. } = swarm.select_next_some().await {
+            return address;
+        }
+    }
+}
+
+/// A `tcp_swarm` that listens with `listen_tcp`, and its address.
+pub async fn listening_tcp_swarm(config: &Config) -> (Swarm<CustomBehaviour>, Multiaddr) {
+    let mut swarm = tcp_swarm(config).await;
+    let address = listen_tcp(&mut swarm).await;
+    (swarm, address)
+}
+
+/// Polls every node in `nodes` until `matcher` returns a value for an event,
+/// or panics after `timeout`. The matcher gets the index of the node the
+/// event came from and every node, so it can act on any of them.
+pub async fn drive_until<T>(
+    nodes: &mut [TestNode],
+    timeout: Duration,
+    mut matcher: impl FnMut(usize, Event, &mut [TestNode]) -> Option<T>,
+) -> T {
+    let driving = async {
+        loop {
+            let (index, event) = {
+                let events = nodes.iter_mut().enumerate().map(|(index, node)| {
+                    node.swarm
+                        .select_next_some()
+                        .map(move |event| (index, event))
+                });
+                future::select_all(events).await.0
+            };
+            if let Some(value) = matcher(index, event, nodes) {
+                return value;
+            }
+        }
+    };
+    tokio::time::timeout(timeout, driving)
+        .await
+        .unwrap_or_else(|_| panic!("nothing matched within {:?}", timeout))
+}
+
+/// Dials `nodes[to]` from `nodes[from]` and waits until both have the
+/// connection.
+pub async fn connect(nodes: &mut [TestNode], from: usize, to: usize) {
+    let (from_id, to_id) = (nodes[from].peer_id(), nodes[to].peer_id());
+    let address = nodes[to].address.clone();
+    nodes[from]
+        .swarm
+        .dial(address)
+        .expect("dialing a memory address starts");
+
+    let (mut from_connected, mut to_connected) = (false, false);
+    drive_until(nodes, CONNECT_TIMEOUT, |index, event, _| {
+        if let SwarmEvent::ConnectionEstablished { peer_id, .. } = event {
+            if index == from && peer_id == to_id {
+                from_connected = true;
+            }
+            if index == to && peer_id == from_id {
+                to_connected = true;
+            }
+        }
+        (from_connected && to_connected).then_some(())
+    })
+    .await
+}
+
+/// Dials `sim` from `nodes[from]` and waits until the node has the
+/// connection.
+pub async fn connect_sim(nodes: &mut [TestNode], from: usize, sim: &SimPeer) {
+    nodes[from]
+        .swarm
+        .dial(sim.address.clone())
+        .expect("dialing a memory address starts");
+    let sim_id = sim.peer_id;
+    drive_until(nodes, CONNECT_TIMEOUT, |index, event, _| match event {
+        SwarmEvent::ConnectionEstablished { peer_id, .. } if index == from && peer_id == sim_id => {
+            Some(())
+        }
+        _ => None,
+    })
+    .await
+}
+
+/// Most bytes the JSON codec reads for a response; `SimReply::Oversized`
+/// sends one more.
+const RESPONSE_SIZE_MAXIMUM: usize = 10 * 1024 * 1024;
+
+/// How a `SimPeer` answers a request.
+#[derive(Debug, Clone)]
+pub enum SimReply {
+    /// Answers with `Response { data }`, like a well-behaved peer.
+    Respond(Value),
+    /// Answers with `Response { data }` after waiting.
+    Delay(Duration, Value),
+    /// Never answers, leaving the requester to time out.
+    Silent,
+    /// Closes the connection as soon as the request arrives.
+    Disconnect,
+    /// Answers with bytes that aren't JSON.
+    Corrupt,
+    /// Answers with more bytes than the codec reads for a response.
+    Oversized,
+}
+
+impl SimReply {
+    fn bytes(&self) -> Vec<u8> {
+        match self {
+            SimReply::Respond(data) | SimReply::Delay(_, data) => {
+                serde_json::to_vec(&Response { data: data.clone() }).expect("responses serialize")
+            }
+            SimReply::Corrupt => b"\x00\xffnot json".to_vec(),
+            SimReply::Oversized => {
+                let mut bytes = br#"{"data":""#.to_vec();
+                bytes.resize(RESPONSE_SIZE_MAXIMUM + 1 - 2, b'a');
+                bytes.extend_from_slice(br#""}"#);
+                bytes
+            }
+            SimReply::Silent | SimReply::Disconnect => Vec::new(),
+        }
+    }
+}
+
+/// Reads and writes requests and responses as raw bytes, so a `SimPeer` can
+/// send what a real peer never would.
+#[derive(Debug, Clone, Default)]
+pub struct RawCodec;
+
+#[async_trait]
+impl Codec for RawCodec {
+    type Protocol = StreamProtocol;
+    type Request = Vec<u8>;
+    type Response = Vec<u8>;
+
+    async fn read_request<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<Vec<u8>>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let mut bytes = Vec::new();
+        io.read_to_end(&mut bytes).await?;
+        Ok(bytes)
+    }
+
+    async fn read_response<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<Vec<u8>>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let mut bytes = Vec::new();
+        io.read_to_end(&mut bytes).await?;
+        Ok(bytes)
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+        request: Vec<u8>,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        io.write_all(&request).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+        response: Vec<u8>,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        io.write_all(&response).await
+    }
+}
+
+/// A peer that speaks the direct protocol on its own task and answers
+/// requests as scripted, to test how a `TestNode` copes with peers that
+/// misbehave. It stops when dropped.
+pub struct SimPeer {
+    pub peer_id: PeerId,
+    pub address: Multiaddr,
+    requests: mpsc::UnboundedSender<(PeerId, Vec<u8>)>,
+    outcomes: mpsc::UnboundedReceiver<Result<Vec<u8>, OutboundFailure>>,
+    task: JoinHandle<()>,
+}
+
+impl SimPeer {
+    /// Starts a peer speaking `/chat/json/1.0.0` that answers requests with
+    /// `replies` in order, repeating the last one once they run out.
+    pub async fn spawn(replies: impl IntoIterator<Item = SimReply>) -> Self {
+        SimPeer::speaking(CHAT_PROTOCOL, replies).await
+    }
+
+    /// Like `spawn`, speaking `protocol` instead.
+    pub async fn speaking(
+        protocol: StreamProtocol,
+        replies: impl IntoIterator<Item = SimReply>,
+    ) -> Self {
+        let replies: VecDeque<SimReply> = replies.into_iter().collect();
+        assert!(!replies.is_empty(), "a sim peer needs at least one reply");
+
+        let mut swarm = build_sim_swarm(protocol).expect("memory swarms always build");
+        swarm
+            .listen_on("/memory/0".parse().expect("valid multiaddr"))
+            .expect("memory transport listens on any port");
+        let address = loop {
+            if let SwarmEvent::NewListenAddr { address, .. } = swarm.select_next_some().await {
+                break address;
+            }
+        };
+        let peer_id = *swarm.local_peer_id();
+        let (requests, requests_rx) = mpsc::unbounded_channel();
+        let (outcomes_tx, outcomes) = mpsc::unbounded_channel();
+        let task = tokio::spawn(run_sim(swarm, replies, requests_rx, outcomes_tx));
+        SimPeer {
+            peer_id,
+            address,
+            requests,
+            outcomes,
+            task,
+        }
+    }
+
+    /// Sends `bytes` to `peer` as a request, whatever they hold, once the
+    /// two are connected.
+    pub fn send_raw(&self, peer: PeerId, bytes: Vec<u8>) {
+        self.requests
+            .send((peer, bytes))
+            .expect("the sim peer runs until dropped");
+    }
+
+    /// How the next of the requests sent with `send_raw` ended: the bytes of
+    /// the response, or why there was none.
+    pub async fn outcome(&mut self) -> Result<Vec<u8>, OutboundFailure> {
+        tokio::time::timeout(CONNECT_TIMEOUT, self.outcomes.recv())
+            .await
+            .expect("the request ends before the timeout")
+            .expect("the sim peer runs until dropped")
+    }
+}
+
+impl Drop for SimPeer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+fn build_sim_swarm(
+    protocol: StreamProtocol,
+) -> Result<Swarm<request_response::Behaviour<RawCodec>>, Box<dyn Error>> {
+    let swarm = SwarmBuilder::with_new_identity()
+        .with_tokio()
+        .with_other_transport(|key| {
+            Ok::<_, Box<dyn Error + Send + Sync>>(
+                MemoryTransport::default()
+                    .upgrade(Version::V1)
+                    .authenticate(noise::Config::new(key)?)
+                    .multiplex(yamux::Config::default()),
+            )
+        })?
+        .with_behaviour(|_| {
+            request_response::Behaviour::with_codec(
+                RawCodec,
+                [(protocol, ProtocolSupport::Full)],
+                // Outlasts the requester's timeout, so a silent peer stays
+                // silent.
+                request_response::Config::default().with_request_timeout(Duration::from_secs(60)),
+            )
+        })?
+        .with_swarm_config(|cfg| cfg.with_idle_connection_timeout(Duration::from_secs(60)))
+        .build();
+
+    Ok(swarm)
+}
+
+async fn run_sim(
+    mut swarm: Swarm<request_response::Behaviour<RawCodec>>,
+    mut replies: VecDeque<SimReply>,
+    mut requests: mpsc::UnboundedReceiver<(PeerId, Vec<u8>)>,
+    outcomes: mpsc::UnboundedSender<Result<Vec<u8>, OutboundFailure>>,
+) {
+    let mut delayed: Vec<(Instant, ResponseChannel<Vec<u8>>, Vec<u8>)> = Vec::new();
+    // Held so the streams stay open without an answer.
+    let mut silent: Vec<ResponseChannel<Vec<u8>>> = Vec::new();
+    // Requests to send once their peer is connected.
+    let mut unsent: Vec<(PeerId, Vec<u8>)> = Vec::new();
+
+    loop {
+        let next_delayed = delayed.iter().map(|(due, ..)| *due).min();
+        let event = tokio::select! {
+            Some((peer, bytes)) = requests.recv() => {
+                if swarm.is_connected(&peer) {
+                    swarm.behaviour_mut().send_request(&peer, bytes);
+                } else {
+                    unsent.push((peer, bytes));
+                }
+                continue;
+            }
+            _ = sleep_until(next_delayed.unwrap_or_else(Instant::now)), if next_delayed.is_some() => {
+                let now = Instant::now();
+                let (due, waiting): (Vec<_>, Vec<_>) =
+                    delayed.drain(..).partition(|(due, ..)| *due <= now);
+                delayed = waiting;
+                for (_, channel, bytes) in due {
+                    let _ = swarm.behaviour_mut().send_response(channel, bytes);
+                }
+                continue;
+            }
+            event = swarm.select_next_some() => event,
+        };
+
+        if let SwarmEvent::ConnectionEstablished { peer_id, .. } = event {
+            for (_, bytes) in unsent.extract_if(.., |(peer, _)| *peer == peer_id) {
+                swarm.behaviour_mut().send_request(&peer_id, bytes);
+            }
+            continue;
+        }
+        let (peer, channel) = match event {
+            SwarmEvent::Behaviour(request_response::Event::Message {
+                peer,
+                message: request_response::Message::Request { channel, .. },
+            }) => (peer, channel),
+            SwarmEvent::Behaviour(request_response::Event::Message {
+                message: request_response::Message::Response { response, .. },
+                ..
+            }) => {
+                let _ = outcomes.send(Ok(response));
+                continue;
+            }
+            SwarmEvent::Behaviour(request_response::Event::OutboundFailure { error, .. }) => {
+                let _ = outcomes.send(Err(error));
+                continue;
+            }
+            _ => continue,
+        };
+        let reply = if replies.len() > 1 {
+            replies.pop_front().expect("checked above")
+        } else {
+            replies[0].clone()
+        };
+        match reply {
+            SimReply::Silent => silent.push(channel),
+            SimReply::Disconnect => {
+                let _ = swarm.disconnect_peer_id(peer);
+            }
+            SimReply::Delay(delay, _) => {
+                delayed.push((Instant::now() + delay, channel, reply.bytes()))
+            }
+            reply => {
+                let _ = swarm.behaviour_mut().send_response(channel, reply.bytes());
+            }
+        }
+    }
+}
+
+/// A `handler::Node` without a network, to test what the handlers do with
+/// an event: feed it behaviour events with `handle`, then look at its state
+/// and at the `actions` the handlers asked of the swarm, which are only
+/// recorded. Every message is admitted, nothing is written to disk, and
+/// lines the node would print for messages are kept in `shown`.
+pub struct TestHarness {
+    pub keypair: identity::Keypair,
+    pub nickname: Option<String>,
+    pub messages: MessageStore,
+    pub nicknames: Nicknames,
+    pub address_book: AddressBook,
+    pub replays: ReplayGuard,
+    /// Tracks the members of no room until a test joins one.
+    pub members: RoomMembers,
+    /// The secrets of the private rooms the node is in; none until a test
+    /// adds some.
+    pub room_keys: RoomKeys,
+    pub counters: Counters,
+    pub events: broadcast::Sender<ChatEvent>,
+    /// How many peers the node counts as connected.
+    pub connected_peers: usize,
+    pub explicit_below: usize,
+    /// Whether the handlers report to gossipsub whether to relay each
+    /// message. Off unless a test turns it on, so those that don't care
+    /// aren't handed the reports among the `actions`.
+    pub validates_gossip: bool,
+    /// What the handlers asked of the swarm, oldest first.
+    pub actions: Vec<Action>,
+    /// The lines shown for messages that arrived or changed, oldest first.
+    pub shown: Vec<String>,
+}
+
+impl TestHarness {
+    /// A node with a new identity and the default config, connected to no
+    /// one.
+    pub fn new() -> Self {
+        let config = Config::default();
+        let keypair = identity::Keypair::generate_ed25519();
+        let mut messages = MessageStore::new(
+            config.history.max_messages,
+            config.history.max_messages_per_room,
+        );
+        messages.set_local_peer(keypair.public().to_peer_id());
+        TestHarness {
+            keypair,
+            nickname: None,
+            messages,
+            nicknames: Nicknames::default(),
+            address_book: AddressBook::default(),
+            replays: ReplayGuard::new(config.replay.window),
+            members: config.members.members(),
+            room_keys: RoomKeys::default(),
+            counters: Counters::default(),
+            events: broadcast::channel(16).0,
+            connected_peers: 0,
+            explicit_below: config.peering.explicit_below,
+            validates_gossip: false,
+            actions: Vec::new(),
+            shown: Vec::new(),
+        }
+    }
+
+    /// Runs the handler for `event`. Panics if there is none.
+    pub fn handle(&mut self, event: CustomBehaviourEvent) {
+        if let Some(event) = handler::handle(self, event) {
+            panic!("no handler for {:?}", event);
+        }
+    }
+}
+
+impl Default for TestHarness {
+    fn default() -> Self {
+        TestHarness::new()
+    }
+}
+
+impl Node for TestHarness {
+    fn local_peer_id(&self) -> PeerId {
+        self.keypair.public().to_peer_id()
+    }
+
+    fn connected_peers(&self) -> usize {
+        self.connected_peers
+    }
+
+    fn explicit_below(&self) -> usize {
+        self.explicit_below
+    }
+
+    /// Those the handlers asked to make explicit so far.
+    fn explicit_peers(&self) -> usize {
+        self.actions
+            .iter()
+            .filter(|action| matches!(action, Action::AddExplicitPeer { .. }))
+            .count()
+    }
+
+    fn validates_gossip(&self) -> bool {
+        self.validates_gossip
+    }
+
+    fn act(&mut self, action: Action) {
+        self.actions.push(action);
+    }
+
+    fn admit(&mut self, _peer: PeerId, _priority: Priority) -> bool {
+        true
+    }
+
+    fn messages(&mut self) -> &mut MessageStore {
+        &mut self.messages
+    }
+
+    fn room_key(&self, room: &str) -> Option<[u8; 32]> {
+        self.room_keys.key(room)
+    }
+
+    fn nicknames(&mut self) -> &mut Nicknames {
+        &mut self.nicknames
+    }
+
+    fn address_book(&mut self) -> &mut AddressBook {
+        &mut self.address_book
+    }
+
+    fn save_address_book(&mut self) {}
+
+    fn replays(&mut self) -> &mut ReplayGuard {
+        &mut self.replays
+    }
+
+    fn members(&mut self) -> &mut RoomMembers {
+        &mut self.members
+    }
+
+    fn counters(&mut self) -> &mut Counters {
+        &mut self.counters
+    }
+
+    fn events(&self) -> &broadcast::Sender<ChatEvent> {
+        &self.events
+    }
+
+    fn direct_message(&mut self, _peer: PeerId, text: String) -> Result<ChatMessage, String> {
+        let mut chat_message = ChatMessage::new(self.local_peer_id(), text);
+        chat_message
+            .sign(&self.keypair)
+            .map_err(|e| format!("Failed to sign message: {}", e))?;
+        Ok(chat_message)
+    }
+
+    fn mentions_us(&self, text: &str) -> bool {
+        self.nickname
+            .as_deref()
+            .is_some_and(|nickname| mentions(text, nickname))
+            || mentions_peer(text, &self.local_peer_id())
+    }
+
+    fn store_message(&mut self, chat_message: ChatMessage) -> bool {
+        self.messages.insert(chat_message)
+    }
+
+    fn apply_to_history(
+        &mut self,
+        _author: PeerId,
+        _target_id: MessageId,
+        _change: Change,
+    ) -> io::Result<ChangeOutcome> {
+        Ok(ChangeOutcome::Evicted)
+    }
+
+    fn persist(&mut self, _id: &MessageId) {}
+
+    fn print_incoming(&mut self, line: &str, _chat_message: &ChatMessage) {
+        self.shown.push(line.to_string());
+    }
+
+    fn print_changed(&mut self, id: &MessageId) {
+        if let Some(chat_message) = self.messages.get(id) {
+            self.shown.push(self.messages.format(chat_message));
+        }
+    }
+}
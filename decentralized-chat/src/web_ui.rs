@@ -0,0 +1,165 @@
+use crate::{
+    command::{Command, Reply},
+    event_socket::{self, EventSocket, Received},
+    handle::ChatHandle,
+    message::MessageId,
+    token::same_token,
+};
+use libp2p::{
+    futures::{SinkExt, StreamExt},
+    PeerId,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::{borrow::Cow, io, net::SocketAddr, sync::Arc, time::Duration};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::{
+    tungstenite::{
+        handshake::server::{ErrorResponse, Request, Response},
+        http::StatusCode,
+        protocol::{frame::coding::CloseCode, CloseFrame},
+        Message,
+    },
+    WebSocketStream,
+};
+
+/// Binds `addr` and serves browser frontends there in the background.
+/// Browsers can't set headers on a WebSocket, so they connect to
+/// `ws://<addr>/?token=<token>`; anyone else is refused during the
+/// handshake. Returns the address actually bound.
+///
+/// Every session speaks as this node, with its peer id. It gets every
+/// `ChatEvent` as a JSON text frame, received messages among them, and may
+/// send `{"text": .., "room": ..}` to publish to a room we are in, with
+/// the current room taken when `room` is left out, or
+/// `{"text": .., "peer": ..}` to message a peer directly. Each is answered
+/// with `{"id": ..}` or `{"error": ..}`.
+pub async fn serve(addr: SocketAddr, handle: ChatHandle, token: String) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind(addr).await?;
+    let bound = listener.local_addr()?;
+    let token: Arc<str> = token.into();
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(session(stream, handle.clone(), token.clone()));
+                }
+                Err(e) => {
+                    // Most likely out of file descriptors; some may free up.
+                    println!("Web UI failed to accept a connection: {}", e);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+            }
+        }
+    });
+    Ok(bound)
+}
+
+/// Runs one browser session, once it presents the token, until it goes away.
+async fn session(stream: TcpStream, handle: ChatHandle, token: Arc<str>) {
+    // The callback's signature is tungstenite's, refusal and all.
+    #[allow(clippy::result_large_err)]
+    let check = |request: &Request, response: Response| {
+        let presented = request.uri().query().and_then(|query| {
+            query
+                .split('&')
+                .find_map(|pair| pair.strip_prefix("token="))
+        });
+        if presented.is_some_and(|presented| same_token(presented, &token)) {
+            Ok(response)
+        } else {
+            let mut refusal = ErrorResponse::new(Some("missing or wrong token".to_string()));
+            *refusal.status_mut() = StatusCode::UNAUTHORIZED;
+            Err(refusal)
+        }
+    };
+    let Ok(socket) = tokio_tungstenite::accept_hdr_async(stream, check).await else {
+        return;
+    };
+
+    let events = handle.events();
+    event_socket::stream_events(events, socket, move |text| {
+        let handle = handle.clone();
+        async move {
+            let reply = match serde_json::from_str(&text) {
+                Ok(frame) => match send(&handle, frame).await {
+                    Ok(id) => json!({ "id": id }),
+                    Err(e) => json!({ "error": e }),
+                },
+                Err(e) => json!({ "error": e.to_string() }),
+            };
+            reply.to_string()
+        }
+    })
+    .await;
+}
+
+impl EventSocket for WebSocketStream<TcpStream> {
+    async fn receive(&mut self) -> Received {
+        match self.next().await {
+            Some(Ok(Message::Text(text))) => Received::Text(text),
+            Some(Ok(Message::Close(_))) | Some(Err(_)) | None => Received::Gone,
+            Some(Ok(_)) => Received::Other,
+        }
+    }
+
+    async fn send_text(&mut self, text: String) -> bool {
+        self.send(Message::Text(text)).await.is_ok()
+    }
+
+    async fn close(mut self, code: u16, reason: &'static str) {
+        let frame = CloseFrame {
+            code: CloseCode::from(code),
+            reason: Cow::Borrowed(reason),
+        };
+        let _ = WebSocketStream::close(&mut self, Some(frame)).await;
+    }
+}
+
+/// A message a browser sends: to a room, or directly to a peer.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SendFrame {
+    text: String,
+    room: Option<String>,
+    peer: Option<PeerId>,
+}
+
+async fn send(handle: &ChatHandle, frame: SendFrame) -> Result<MessageId, String> {
+    if frame.text.trim().is_empty() {
+        return Err("text is empty".to_string());
+    }
+    let command = match (frame.room, frame.peer) {
+        (Some(room), None) => {
+            if !in_room(handle, &room).await? {
+                return Err(format!("not in room {}; join it first", room));
+            }
+            Command::Send {
+                text: frame.text,
+                room: Some(room),
+                expires_in: None,
+            }
+        }
+        (None, Some(peer)) => Command::Msg {
+            peer,
+            text: frame.text,
+        },
+        (None, None) => Command::Send {
+            text: frame.text,
+            room: None,
+            expires_in: None,
+        },
+        (Some(_), Some(_)) => return Err("give a room or a peer, not both".to_string()),
+    };
+    match handle.execute(command).await? {
+        Reply::Sent { id } => Ok(id),
+        reply => Err(format!("unexpected reply: {}", reply)),
+    }
+}
+
+async fn in_room(handle: &ChatHandle, room: &str) -> Result<bool, String> {
+    match handle.execute(Command::Rooms).await? {
+        Reply::Rooms { rooms } => Ok(rooms.iter().any(|summary| summary.room == room)),
+        reply => Err(format!("unexpected reply: {}", reply)),
+    }
+}
@@ -0,0 +1,143 @@
+use libp2p::{futures::StreamExt, identity, request_response, swarm::SwarmEvent, Multiaddr};
+use libp2p_demo::{
+    ban::{Violation, ViolationTracker},
+    behaviour::{build_test_swarm, CustomBehaviourEvent, Request},
+    message::DirectRequest,
+    testing::peer,
+};
+use serde_json::json;
+use std::time::{Duration, Instant};
+
+const COOLDOWN: Duration = Duration::from_secs(60);
+
+#[test]
+fn threshold_violations_trigger_a_ban() {
+    let mut tracker = ViolationTracker::new(3, COOLDOWN);
+    let peer = peer();
+    let now = Instant::now();
+
+    assert_eq!(tracker.record(peer, Violation::MalformedRequest, now), None);
+    assert_eq!(tracker.record(peer, Violation::MalformedGossip, now), None);
+    let ban = tracker
+        .record(peer, Violation::UnauthorizedChange, now)
+        .expect("third violation should ban");
+    assert_eq!(ban.peer, peer);
+    assert_eq!(ban.violations, 3);
+    assert_eq!(ban.last, Violation::UnauthorizedChange);
+
+    // The count starts over after a ban.
+    assert_eq!(tracker.record(peer, Violation::MalformedRequest, now), None);
+}
+
+#[test]
+fn peers_are_counted_separately() {
+    let mut tracker = ViolationTracker::new(2, COOLDOWN);
+    let (alice, bob) = (peer(), peer());
+    let now = Instant::now();
+
+    assert_eq!(
+        tracker.record(alice, Violation::MalformedRequest, now),
+        None
+    );
+    assert_eq!(tracker.record(bob, Violation::MalformedRequest, now), None);
+    assert!(tracker
+        .record(alice, Violation::MalformedRequest, now)
+        .is_some());
+}
+
+#[test]
+fn count_resets_after_the_cooldown() {
+    let mut tracker = ViolationTracker::new(2, COOLDOWN);
+    let peer = peer();
+    let start = Instant::now();
+
+    assert_eq!(
+        tracker.record(peer, Violation::MalformedRequest, start),
+        None
+    );
+    let later = start + COOLDOWN;
+    assert_eq!(
+        tracker.record(peer, Violation::MalformedRequest, later),
+        None
+    );
+    assert!(tracker
+        .record(
+            peer,
+            Violation::MalformedRequest,
+            later + Duration::from_secs(1)
+        )
+        .is_some());
+
+    assert_eq!(
+        tracker.record(peer, Violation::MalformedRequest, later),
+        None
+    );
+    tracker.prune(later + COOLDOWN);
+    assert_eq!(
+        tracker.record(peer, Violation::MalformedRequest, later + COOLDOWN),
+        None
+    );
+}
+
+#[tokio::test]
+async fn malformed_requests_get_the_sender_disconnected() {
+    const THRESHOLD: u32 = 3;
+
+    let mut alice = build_test_swarm(identity::Keypair::generate_ed25519()).unwrap();
+    let mut bob = build_test_swarm(identity::Keypair::generate_ed25519()).unwrap();
+    let bob_id = *bob.local_peer_id();
+
+    alice
+        .listen_on("/memory/0".parse::<Multiaddr>().unwrap())
+        .unwrap();
+    let alice_addr = loop {
+        if let SwarmEvent::NewListenAddr { address, .. } = alice.select_next_some().await {
+            break address;
+        }
+    };
+    bob.dial(alice_addr).unwrap();
+
+    let mut tracker = ViolationTracker::new(THRESHOLD, COOLDOWN);
+    let ban = tokio::time::timeout(Duration::from_secs(10), async {
+        let mut ban = None;
+        loop {
+            tokio::select! {
+                event = alice.select_next_some() => match event {
+                    SwarmEvent::Behaviour(CustomBehaviourEvent::RequestResponse(
+                        request_response::Event::Message {
+                            peer,
+                            message: request_response::Message::Request { request, .. },
+                        },
+                    )) => {
+                        assert!(serde_json::from_value::<DirectRequest>(request.data).is_err());
+                        if let Some(new_ban) = tracker.record(peer, Violation::MalformedRequest, Instant::now()) {
+                            alice.behaviour_mut().block_list.block_peer(new_ban.peer);
+                            ban = Some(new_ban);
+                        }
+                    }
+                    SwarmEvent::ConnectionClosed { peer_id, .. } if ban.is_some() => {
+                        assert_eq!(peer_id, bob_id);
+                        break ban.unwrap();
+                    }
+                    _ => {}
+                },
+                event = bob.select_next_some() => {
+                    if let SwarmEvent::ConnectionEstablished { peer_id, .. } = event {
+                        for n in 0..THRESHOLD {
+                            bob.behaviour_mut().request_response.send_request(
+                                &peer_id,
+                                Request { data: json!({ "kind": "not_a_request", "n": n }) },
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    })
+    .await
+    .expect("bob was not disconnected in time");
+
+    assert_eq!(ban.peer, bob_id);
+    assert_eq!(ban.violations, THRESHOLD);
+    assert!(!alice.is_connected(&bob_id));
+}
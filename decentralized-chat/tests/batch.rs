@@ -0,0 +1,125 @@
+use libp2p_demo::{
+    batch::Batcher,
+    bench::{self, BenchConfig},
+    config::BatchConfig,
+    message::{ChatMessage, MessageId},
+    testing::peer,
+};
+use std::time::{Duration, Instant};
+
+const WINDOW: Duration = Duration::from_millis(50);
+
+fn message(text: &str) -> ChatMessage {
+    ChatMessage::new(peer(), text.to_string())
+}
+
+fn texts(messages: &[ChatMessage]) -> Vec<&str> {
+    messages
+        .iter()
+        .map(|message| message.message.as_str())
+        .collect()
+}
+
+#[test]
+fn a_lone_message_is_sent_at_once() {
+    let mut batcher = Batcher::new(WINDOW, 32);
+    let (alice, start) = (peer(), Instant::now());
+
+    assert_eq!(texts(&batcher.push(alice, message("one"), start)), ["one"]);
+    let later = start + WINDOW;
+    assert_eq!(texts(&batcher.push(alice, message("two"), later)), ["two"]);
+    assert!(batcher.is_empty());
+}
+
+#[test]
+fn a_burst_is_held_for_the_window_in_order() {
+    let mut batcher = Batcher::new(WINDOW, 32);
+    let (alice, bob, start) = (peer(), peer(), Instant::now());
+
+    assert_eq!(batcher.push(alice, message("1"), start).len(), 1);
+    for text in ["2", "3", "4"] {
+        assert!(batcher.push(alice, message(text), start).is_empty());
+    }
+    // Other peers aren't held back by it.
+    assert_eq!(batcher.push(bob, message("b"), start).len(), 1);
+    assert!(!batcher.is_empty());
+    assert!(batcher.due(start + WINDOW / 2).is_empty());
+
+    let due = batcher.due(start + WINDOW);
+    assert_eq!(due.len(), 1);
+    assert_eq!(due[0].0, alice);
+    assert_eq!(texts(&due[0].1), ["2", "3", "4"]);
+    assert!(batcher.is_empty());
+}
+
+#[test]
+fn a_full_batch_goes_out_without_waiting() {
+    let mut batcher = Batcher::new(WINDOW, 3);
+    let (alice, start) = (peer(), Instant::now());
+
+    batcher.push(alice, message("0"), start);
+    assert!(batcher.push(alice, message("1"), start).is_empty());
+    assert!(batcher.push(alice, message("2"), start).is_empty());
+    let full = batcher.push(alice, message("3"), start);
+    assert_eq!(texts(&full), ["1", "2", "3"]);
+    assert!(batcher.is_empty());
+}
+
+#[test]
+fn a_zero_window_sends_every_message_alone() {
+    let mut batcher = Batcher::new(Duration::ZERO, 32);
+    let (alice, start) = (peer(), Instant::now());
+    for i in 0..10 {
+        assert_eq!(batcher.push(alice, message(&i.to_string()), start).len(), 1);
+    }
+    assert!(batcher.is_empty());
+}
+
+#[test]
+fn blocking_a_peer_drops_its_batch() {
+    let mut batcher = Batcher::new(WINDOW, 32);
+    let (alice, start) = (peer(), Instant::now());
+    batcher.push(alice, message("1"), start);
+    batcher.push(alice, message("2"), start);
+    batcher.push(alice, message("3"), start);
+
+    assert_eq!(batcher.cancel_peer(&alice), 2);
+    assert!(batcher.is_empty());
+    assert!(batcher.due(start + WINDOW).is_empty());
+    assert_eq!(batcher.cancel_peer(&alice), 0);
+}
+
+#[test]
+fn messages_keep_their_ids_through_a_batch() {
+    let mut batcher = Batcher::new(WINDOW, 32);
+    let (alice, start) = (peer(), Instant::now());
+    batcher.push(alice, message("first"), start);
+    let ids: Vec<MessageId> = (0..5)
+        .map(|_| {
+            let message = message("burst");
+            let id = message.id;
+            batcher.push(alice, message, start);
+            id
+        })
+        .collect();
+    let due = batcher.due(start + WINDOW);
+    let batched: Vec<MessageId> = due[0].1.iter().map(|message| message.id).collect();
+    assert_eq!(batched, ids);
+}
+
+#[tokio::test]
+async fn direct_bursts_are_acknowledged_batched_or_not() {
+    let config = BenchConfig {
+        messages: 200,
+        size: 64,
+    };
+    let unbatched = BatchConfig {
+        window_ms: 0,
+        ..Default::default()
+    };
+    for batch in [unbatched, BatchConfig::default()] {
+        let report = bench::run_direct(config, &batch).await.unwrap();
+        assert_eq!(report.config.messages, 200);
+        assert!(report.p50 <= report.p99);
+    }
+}
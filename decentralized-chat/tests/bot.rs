@@ -0,0 +1,121 @@
+use async_trait::async_trait;
+use libp2p::PeerId;
+use libp2p_demo::{
+    bot::{spawn_handler, MessageHandler, PingBot},
+    command::{Command, Reply},
+    control::ControlRequest,
+    event::ChatEvent,
+    handle::ChatHandle,
+    message::ChatMessage,
+    testing::peer,
+};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+
+fn message(author: PeerId, room: Option<&str>, text: &str) -> ChatMessage {
+    ChatMessage {
+        room: room.map(str::to_string),
+        ..ChatMessage::new(author, text.to_string())
+    }
+}
+
+/// A stand-in for the node: the handle, the sender of its events, and the
+/// commands run through the handle.
+fn node() -> (
+    ChatHandle,
+    broadcast::Sender<ChatEvent>,
+    mpsc::Receiver<Command>,
+) {
+    let (requests_tx, mut requests_rx) = mpsc::channel::<ControlRequest>(8);
+    let (events_tx, _) = broadcast::channel(16);
+    let (commands_tx, commands_rx) = mpsc::channel(8);
+    tokio::spawn(async move {
+        while let Some((command, reply_tx)) = requests_rx.recv().await {
+            let _ = reply_tx.send(Ok(Reply::Sent {
+                id: uuid::Uuid::new_v4(),
+            }));
+            let _ = commands_tx.send(command).await;
+        }
+    });
+    (
+        ChatHandle::new(requests_tx, events_tx.clone()),
+        events_tx,
+        commands_rx,
+    )
+}
+
+async fn next_command(commands: &mut mpsc::Receiver<Command>) -> Command {
+    tokio::time::timeout(Duration::from_secs(5), commands.recv())
+        .await
+        .expect("no command in time")
+        .unwrap()
+}
+
+#[tokio::test]
+async fn replies_go_back_to_the_room_or_the_author() {
+    let (handle, events, mut commands) = node();
+    spawn_handler(handle, PingBot);
+
+    let author = peer();
+    for received in [
+        message(author, Some("rust"), "!ping"),
+        message(author, Some("rust"), "no command here"),
+        message(author, None, "!echo  hello there "),
+    ] {
+        events
+            .send(ChatEvent::MessageReceived { message: received })
+            .unwrap();
+    }
+
+    // Handlers run side by side, so the replies may come in either order.
+    let replies = [
+        next_command(&mut commands).await,
+        next_command(&mut commands).await,
+    ];
+    assert!(replies.iter().any(|command| matches!(
+        command,
+        Command::Send { text, room: Some(room), .. } if text == "pong" && room == "rust"
+    )));
+    assert!(replies.iter().any(|command| matches!(
+        command,
+        Command::Msg { peer, text } if *peer == author && text == "hello there"
+    )));
+    assert!(commands.try_recv().is_err());
+}
+
+/// Panics on `!panic`, takes its time on `!slow`, and answers everything
+/// else at once.
+struct Moody;
+
+#[async_trait]
+impl MessageHandler for Moody {
+    async fn on_message(&self, message: &ChatMessage, _ctx: &ChatHandle) -> Option<String> {
+        match message.message.as_str() {
+            "!panic" => panic!("handler bug"),
+            "!slow" => {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                Some("finally".to_string())
+            }
+            text => Some(format!("got {}", text)),
+        }
+    }
+}
+
+#[tokio::test]
+async fn a_panicking_or_slow_handler_keeps_serving_other_messages() {
+    let (handle, events, mut commands) = node();
+    spawn_handler(handle, Moody);
+
+    for text in ["!slow", "!panic", "after"] {
+        events
+            .send(ChatEvent::MessageReceived {
+                message: message(peer(), Some("chat"), text),
+            })
+            .unwrap();
+    }
+
+    match next_command(&mut commands).await {
+        Command::Send { text, .. } => assert_eq!(text, "got after"),
+        other => panic!("expected a room message, got {:?}", other),
+    }
+}
@@ -0,0 +1,582 @@
+use libp2p_demo::{
+    command::Command,
+    parser::{self, parse, tokenize, ParseError},
+    testing::peer,
+};
+use std::path::Path;
+
+#[test]
+fn plain_text_goes_to_the_current_room() {
+    let Ok(Some(Command::Send {
+        text,
+        room,
+        expires_in,
+    })) = parse("  hello \"world\"  ")
+    else {
+        panic!("expected a send command");
+    };
+    assert_eq!(text, "hello \"world\"");
+    assert_eq!(room, None);
+    assert_eq!(expires_in, None);
+}
+
+#[test]
+fn blank_lines_are_ignored() {
+    assert!(matches!(parse("   "), Ok(None)));
+}
+
+#[test]
+fn quotes_group_words_and_backslash_escapes() {
+    assert_eq!(
+        tokenize(r#"/msg "two words" a\ b \"q\""#).unwrap(),
+        vec!["/msg", "two words", "a b", "\"q\""]
+    );
+    assert_eq!(tokenize(r#"/join """#).unwrap(), vec!["/join", ""]);
+    assert_eq!(
+        tokenize(r#"/join "open"#),
+        Err(ParseError::UnterminatedQuote)
+    );
+}
+
+#[test]
+fn msg_takes_a_peer_and_the_rest_of_the_line() {
+    let peer = peer();
+    let Ok(Some(Command::Msg { peer: parsed, text })) =
+        parse(&format!("/msg {} hi there \"you two\"", peer))
+    else {
+        panic!("expected a msg command");
+    };
+    assert_eq!(parsed, peer);
+    assert_eq!(text, "hi there you two");
+}
+
+#[test]
+fn msg_rejects_a_malformed_peer_id() {
+    let Err(ParseError::InvalidArgument { usage, message }) = parse("/msg not-a-peer hi") else {
+        panic!("expected an invalid argument error");
+    };
+    assert_eq!(usage, "/msg <peer> <text>");
+    assert!(message.contains("not-a-peer"));
+}
+
+#[test]
+fn missing_arguments_report_usage() {
+    assert_eq!(
+        parse("/msg").unwrap_err().to_string(),
+        "usage: /msg <peer> <text>"
+    );
+    assert_eq!(
+        parse("/join").unwrap_err(),
+        ParseError::Usage("/join <room> | --invite <token>")
+    );
+    assert_eq!(
+        parse("/join a b").unwrap_err(),
+        ParseError::Usage("/join <room> | --invite <token>")
+    );
+    assert_eq!(
+        parse("/peers now").unwrap_err(),
+        ParseError::Usage("/peers")
+    );
+}
+
+#[test]
+fn ephemeral_seconds_must_be_a_number() {
+    assert!(matches!(
+        parse("/ephemeral soon hi"),
+        Err(ParseError::InvalidArgument { .. })
+    ));
+    assert!(matches!(
+        parse("/ephemeral 5 hi"),
+        Ok(Some(Command::Send {
+            expires_in: Some(5),
+            ..
+        }))
+    ));
+    assert!(matches!(
+        parse("/ephemeral 18446744073709551615 hi"),
+        Err(ParseError::InvalidArgument { .. })
+    ));
+}
+
+#[test]
+fn unknown_commands_are_rejected() {
+    assert_eq!(
+        parse("/frobnicate x").unwrap_err(),
+        ParseError::UnknownCommand("/frobnicate".to_string())
+    );
+    assert_eq!(
+        parse("/help frobnicate").unwrap_err(),
+        ParseError::UnknownCommand("frobnicate".to_string())
+    );
+}
+
+#[test]
+fn block_nick_and_join_parse_their_argument() {
+    let peer = peer();
+    assert!(matches!(
+        parse(&format!("/block {}", peer)),
+        Ok(Some(Command::Block { peer: parsed })) if parsed == peer
+    ));
+    assert!(matches!(
+        parse("/nick \"Ada L\""),
+        Ok(Some(Command::Nick { name })) if name == "Ada L"
+    ));
+    assert!(matches!(
+        parse("/join rust"),
+        Ok(Some(Command::Join { room })) if room == "rust"
+    ));
+    assert!(matches!(
+        parse("/leave rust"),
+        Ok(Some(Command::Leave { room })) if room == "rust"
+    ));
+    assert!(matches!(parse("/rooms"), Ok(Some(Command::Rooms))));
+    assert!(matches!(
+        parse("/join --invite AQID"),
+        Ok(Some(Command::JoinInvite { token })) if token == "AQID"
+    ));
+    assert!(matches!(
+        parse("/invite rust"),
+        Ok(Some(Command::Invite { room, expires_in: None, new: false })) if room == "rust"
+    ));
+    assert!(matches!(
+        parse("/invite rust 600"),
+        Ok(Some(Command::Invite { room, expires_in: Some(600), new: false })) if room == "rust"
+    ));
+    assert!(matches!(
+        parse("/invite --new plans"),
+        Ok(Some(Command::Invite { room, expires_in: None, new: true })) if room == "plans"
+    ));
+    assert!(matches!(
+        parse("/invite --new plans 600"),
+        Ok(Some(Command::Invite { room, expires_in: Some(600), new: true })) if room == "plans"
+    ));
+    // Past a year, and past u64, the expiry would make no sense.
+    for seconds in ["31536001", "18446744073709551615", "18446744073709551616"] {
+        assert!(matches!(
+            parse(&format!("/invite rust {}", seconds)),
+            Err(ParseError::InvalidArgument { .. })
+        ));
+    }
+    assert!(matches!(
+        parse("/members"),
+        Ok(Some(Command::Members { room: None }))
+    ));
+    assert!(matches!(
+        parse("/members rust"),
+        Ok(Some(Command::Members { room: Some(room) })) if room == "rust"
+    ));
+    assert!(matches!(
+        parse(&format!("/px {}", peer)),
+        Ok(Some(Command::PeerExchange { peer: parsed })) if parsed == peer
+    ));
+    assert_eq!(
+        parse("/leave").unwrap_err(),
+        ParseError::Usage("/leave <room>")
+    );
+}
+
+#[test]
+fn fingerprint_takes_an_optional_peer_and_verify_a_peer() {
+    let peer = peer();
+    assert!(matches!(
+        parse("/fingerprint"),
+        Ok(Some(Command::Fingerprint { peer: None }))
+    ));
+    assert!(matches!(
+        parse(&format!("/fingerprint {}", peer)),
+        Ok(Some(Command::Fingerprint { peer: Some(parsed) })) if parsed == peer
+    ));
+    assert!(matches!(
+        parse(&format!("/verify {}", peer)),
+        Ok(Some(Command::Verify { peer: parsed })) if parsed == peer
+    ));
+    assert_eq!(
+        parse("/verify").unwrap_err(),
+        ParseError::Usage("/verify <peer>")
+    );
+}
+
+#[test]
+fn friends_are_added_with_an_optional_nickname() {
+    let peer = peer();
+    assert!(matches!(
+        parse(&format!("/addfriend {}", peer)),
+        Ok(Some(Command::AddFriend { peer: parsed, nickname: None })) if parsed == peer
+    ));
+    assert!(matches!(
+        parse(&format!("/addfriend {} Sam from work", peer)),
+        Ok(Some(Command::AddFriend { peer: parsed, nickname: Some(nickname) }))
+            if parsed == peer && nickname == "Sam from work"
+    ));
+    assert!(matches!(
+        parse(&format!("/rmfriend {}", peer)),
+        Ok(Some(Command::RemoveFriend { peer: parsed })) if parsed == peer
+    ));
+    assert!(matches!(parse("/friends"), Ok(Some(Command::Friends))));
+    assert!(matches!(
+        parse("/addfriend sam"),
+        Err(ParseError::InvalidArgument { .. })
+    ));
+}
+
+#[test]
+fn profiles_are_changed_and_peers_looked_up() {
+    assert!(matches!(parse("/profile"), Ok(Some(Command::Profile))));
+    assert!(matches!(
+        parse("/profile set-status out for lunch"),
+        Ok(Some(Command::SetStatus { status: Some(status) })) if status == "out for lunch"
+    ));
+    assert!(matches!(
+        parse("/profile set-status"),
+        Ok(Some(Command::SetStatus { status: None }))
+    ));
+    assert!(matches!(
+        parse(r#"/profile set-avatar "my face.png""#),
+        Ok(Some(Command::SetAvatar { path: Some(path) })) if path == Path::new("my face.png")
+    ));
+    assert!(matches!(
+        parse("/profile set-avatar"),
+        Ok(Some(Command::SetAvatar { path: None }))
+    ));
+    assert!(matches!(parse("/profile nap"), Err(ParseError::Usage(_))));
+
+    let peer = peer();
+    assert!(matches!(
+        parse(&format!("/whois {}", peer)),
+        Ok(Some(Command::Whois { peer: parsed })) if parsed == peer
+    ));
+    assert!(matches!(parse("/whois"), Err(ParseError::Usage(_))));
+}
+
+#[test]
+fn bandwidth_can_be_shown_and_reset() {
+    assert!(matches!(
+        parse("/bw"),
+        Ok(Some(Command::Bandwidth { reset: false }))
+    ));
+    assert!(matches!(
+        parse("/bw reset"),
+        Ok(Some(Command::Bandwidth { reset: true }))
+    ));
+    assert!(matches!(parse("/bw clear"), Err(ParseError::Usage(_))));
+}
+
+#[test]
+fn help_lists_every_command_and_describes_one() {
+    let listing = parser::help(None);
+    for spec in parser::COMMANDS {
+        assert!(listing.contains(spec.usage), "{} missing", spec.name);
+    }
+    assert_eq!(listing.lines().count(), parser::COMMANDS.len());
+
+    assert!(matches!(
+        parse("/help msg"),
+        Ok(Some(Command::Help { topic: Some(topic) })) if topic == "/msg"
+    ));
+    let details = parser::help(Some("/msg"));
+    assert!(details.starts_with("/msg <peer> <text>"));
+}
+
+#[test]
+fn search_takes_flags_before_the_term() {
+    let Ok(Some(Command::Search {
+        term,
+        regex,
+        before,
+        after,
+    })) = parse("/search --regex --after 2024-01-01 --before 2024-02-01 foo bar")
+    else {
+        panic!("expected a search command");
+    };
+    assert_eq!(term, "foo bar");
+    assert!(regex);
+    assert_eq!(after, Some(1_704_153_600));
+    assert_eq!(before, Some(1_706_745_600));
+
+    assert!(matches!(
+        parse("/search --before yesterday foo"),
+        Err(ParseError::InvalidArgument { .. })
+    ));
+    assert!(matches!(
+        parse("/search --regex"),
+        Err(ParseError::Usage(_))
+    ));
+}
+
+#[test]
+fn limits_takes_no_arguments() {
+    assert!(matches!(parse("/limits"), Ok(Some(Command::Limits))));
+    assert_eq!(
+        parse("/limits all").unwrap_err(),
+        ParseError::Usage("/limits")
+    );
+}
+
+#[test]
+fn mute_takes_a_room_and_optionally_mentions_only() {
+    assert!(matches!(
+        parse("/mute random"),
+        Ok(Some(Command::Mute { room, mentions_only: false })) if room == "random"
+    ));
+    assert!(matches!(
+        parse("/mute random --mentions-only"),
+        Ok(Some(Command::Mute { room, mentions_only: true })) if room == "random"
+    ));
+    assert!(matches!(
+        parse("/unmute random"),
+        Ok(Some(Command::Unmute { room })) if room == "random"
+    ));
+    assert_eq!(
+        parse("/mute random --loud").unwrap_err(),
+        ParseError::Usage("/mute <room> [--mentions-only]")
+    );
+    assert_eq!(
+        parse("/unmute").unwrap_err(),
+        ParseError::Usage("/unmute <room>")
+    );
+}
+
+#[test]
+fn history_takes_an_optional_room_and_filters() {
+    const USAGE: &str = "/history [room] [--peer <id>] [--since <date>] [--limit n] [--page n]";
+    assert!(matches!(
+        parse("/history"),
+        Ok(Some(Command::History {
+            room: None,
+            peer: None,
+            since: None,
+            limit: parser::HISTORY_PAGE_SIZE,
+            page: 0
+        }))
+    ));
+    assert!(matches!(
+        parse("/history --limit 50"),
+        Ok(Some(Command::History {
+            room: None,
+            limit: 50,
+            page: 0,
+            ..
+        }))
+    ));
+    let peer = peer();
+    let Ok(Some(Command::History {
+        room,
+        peer: Some(parsed),
+        since: Some(since),
+        limit: 10,
+        page: 2,
+    })) = parse(&format!(
+        "/history lobby --peer {} --since 2024-05-01 --limit 10 --page 3",
+        peer
+    ))
+    else {
+        panic!("expected a filtered history command");
+    };
+    assert_eq!(room.as_deref(), Some("lobby"));
+    assert_eq!(parsed, peer);
+    assert_eq!(since, 1_714_521_600);
+
+    assert_eq!(
+        parse("/history --page 0").unwrap_err(),
+        ParseError::InvalidArgument {
+            usage: USAGE,
+            message: "expected a positive number, not 0".to_string(),
+        }
+    );
+    assert_eq!(
+        parse("/history --peer nope").unwrap_err(),
+        ParseError::InvalidArgument {
+            usage: USAGE,
+            message: "invalid peer id: nope".to_string(),
+        }
+    );
+    assert_eq!(
+        parse("/history --limit").unwrap_err(),
+        ParseError::Usage(USAGE)
+    );
+    assert_eq!(
+        parse("/history lobby other").unwrap_err(),
+        ParseError::Usage(USAGE)
+    );
+}
+
+#[test]
+fn clear_takes_an_optional_room_and_asks_for_confirmation() {
+    const USAGE: &str = "/clear [room] [--disk] [--yes]";
+    assert!(matches!(
+        parse("/clear"),
+        Ok(Some(Command::Clear {
+            room: None,
+            disk: false,
+            confirmed: false,
+        }))
+    ));
+    let Ok(Some(Command::Clear {
+        room,
+        disk: true,
+        confirmed: true,
+    })) = parse("/clear lobby --yes --disk")
+    else {
+        panic!("expected a confirmed clear of the history file");
+    };
+    assert_eq!(room.as_deref(), Some("lobby"));
+
+    assert_eq!(
+        parse("/clear --all").unwrap_err(),
+        ParseError::InvalidArgument {
+            usage: USAGE,
+            message: "unknown flag: --all".to_string(),
+        }
+    );
+    assert_eq!(
+        parse("/clear lobby other").unwrap_err(),
+        ParseError::Usage(USAGE)
+    );
+}
+
+#[test]
+fn share_optionally_takes_all() {
+    assert!(matches!(
+        parse("/share"),
+        Ok(Some(Command::Share { all: false }))
+    ));
+    assert!(matches!(
+        parse("/share --all"),
+        Ok(Some(Command::Share { all: true }))
+    ));
+    assert_eq!(
+        parse("/share everything").unwrap_err(),
+        ParseError::Usage("/share [--all]")
+    );
+}
+
+#[test]
+fn mentions_takes_no_arguments() {
+    assert!(matches!(parse("/mentions"), Ok(Some(Command::Mentions))));
+    assert_eq!(
+        parse("/mentions all").unwrap_err(),
+        ParseError::Usage("/mentions")
+    );
+}
+
+#[test]
+fn dnd_is_turned_on_with_an_optional_away_message_or_off() {
+    let Ok(Some(Command::DoNotDisturb {
+        enabled: true,
+        away_message,
+    })) = parse("/dnd on back at 5")
+    else {
+        panic!("expected /dnd on");
+    };
+    assert_eq!(away_message.as_deref(), Some("back at 5"));
+    assert!(matches!(
+        parse("/dnd on"),
+        Ok(Some(Command::DoNotDisturb {
+            enabled: true,
+            away_message: None
+        }))
+    ));
+    assert!(matches!(
+        parse("/dnd off"),
+        Ok(Some(Command::DoNotDisturb { enabled: false, .. }))
+    ));
+
+    let usage = ParseError::Usage("/dnd on [message] | /dnd off");
+    assert_eq!(parse("/dnd").unwrap_err(), usage);
+    assert_eq!(parse("/dnd off now").unwrap_err(), usage);
+    assert!(matches!(parse("/status"), Ok(Some(Command::Status))));
+}
+
+#[test]
+fn stats_optionally_takes_json() {
+    assert!(matches!(
+        parse("/stats"),
+        Ok(Some(Command::Stats { json: false }))
+    ));
+    assert!(matches!(
+        parse("/stats --json"),
+        Ok(Some(Command::Stats { json: true }))
+    ));
+    assert_eq!(
+        parse("/stats --yaml").unwrap_err(),
+        ParseError::Usage("/stats [--json]")
+    );
+}
+
+#[test]
+fn quit_can_be_typed_as_exit() {
+    assert!(matches!(parse("/quit"), Ok(Some(Command::Quit))));
+    assert!(matches!(parse("/exit"), Ok(Some(Command::Quit))));
+    assert_eq!(parse("/exit now").unwrap_err(), ParseError::Usage("/quit"));
+    assert!(matches!(
+        parse("/help exit"),
+        Ok(Some(Command::Help { topic: Some(topic) })) if topic == "/quit"
+    ));
+}
+
+#[test]
+fn noemoji_takes_no_arguments() {
+    assert!(matches!(parse("/noemoji"), Ok(Some(Command::NoEmoji))));
+    assert_eq!(
+        parse("/noemoji on").unwrap_err(),
+        ParseError::Usage("/noemoji")
+    );
+}
+
+#[test]
+fn edit_takes_a_prefix_and_the_new_text() {
+    let Ok(Some(Command::Edit { message_id, text })) = parse("/edit 1a2b3c fixed typo") else {
+        panic!("expected an edit command");
+    };
+    assert_eq!(message_id, "1a2b3c");
+    assert_eq!(text, "fixed typo");
+    assert_eq!(
+        parse("/edit 1a2b3c").unwrap_err(),
+        ParseError::Usage("/edit <message_id_prefix> <text>")
+    );
+}
+
+#[test]
+fn delete_takes_a_single_prefix() {
+    let Ok(Some(Command::Delete { message_id })) = parse("/delete 1a2b3c") else {
+        panic!("expected a delete command");
+    };
+    assert_eq!(message_id, "1a2b3c");
+    assert_eq!(
+        parse("/delete 1a2b3c extra").unwrap_err(),
+        ParseError::Usage("/delete <message_id_prefix>")
+    );
+}
+
+#[test]
+fn dial_accepts_addresses_with_or_without_a_peer_id() {
+    for address in [
+        "/ip4/192.0.2.1/tcp/4001",
+        "/dns4/chat.example.com/tcp/4001/p2p/12D3KooWDpJ7As7BWAwRMfu1VU2WCqNjvq387JEYKDBj4kx6nXTN",
+    ] {
+        let Ok(Some(Command::Dial { address: parsed })) = parse(&format!("/dial {}", address))
+        else {
+            panic!("expected a dial command for {}", address);
+        };
+        assert_eq!(parsed.to_string(), address);
+    }
+
+    assert_eq!(
+        parse("/dial 192.0.2.1:4001").unwrap_err(),
+        ParseError::InvalidArgument {
+            usage: "/dial <multiaddr>",
+            message: "invalid multiaddr: 192.0.2.1:4001 (multiaddrs start with /, e.g. /ip4/192.0.2.1/tcp/4001)".to_string(),
+        }
+    );
+    assert_eq!(
+        parse("/dial /ip4/192.0.2.1/udp/4001/quic-v1").unwrap_err(),
+        ParseError::InvalidArgument {
+            usage: "/dial <multiaddr>",
+            message: "can't dial /ip4/192.0.2.1/udp/4001/quic-v1: QUIC address, but the QUIC transport is not enabled; use a /tcp address".to_string(),
+        }
+    );
+    assert_eq!(
+        parse("/dial").unwrap_err(),
+        ParseError::Usage("/dial <multiaddr>")
+    );
+}
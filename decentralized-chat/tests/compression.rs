@@ -0,0 +1,147 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use libp2p::swarm::SwarmEvent;
+use libp2p_demo::{
+    behaviour::{CustomBehaviourEvent, CHAT_PROTOCOL, COMPRESSED_CHAT_PROTOCOL},
+    compression::{compress, decompress, ContentEncoding, MAX_DECOMPRESSED_LEN},
+    config::{Config, ProtocolConfig},
+    envelope::{self, open, seal, Envelope, Opened},
+    message::{ChatMessage, DirectRequest},
+    stats::{CompressionSavings, Counters},
+    testing::{connect, drive_until, peer, TestNode},
+};
+use serde_json::json;
+use std::time::Duration;
+
+fn pasted_log() -> DirectRequest {
+    let peer = peer();
+    let log = "2024-05-01T12:00:00Z INFO connection established\n".repeat(200);
+    DirectRequest::Message(ChatMessage::new(peer, log))
+}
+
+#[test]
+fn large_payloads_are_compressed_and_opened_transparently() {
+    let request = pasted_log();
+    let mut sealed = seal(&request);
+    let compressed = envelope::compress(&mut sealed, 1024).expect("a log compresses well");
+    assert!(compressed.compressed_bytes * 10 < compressed.original_bytes);
+
+    let envelope: Envelope = serde_json::from_value(sealed.clone()).unwrap();
+    assert_eq!(envelope.content_encoding, Some(ContentEncoding::Zstd));
+    assert_eq!(
+        envelope.payload.as_str().unwrap().len(),
+        compressed.compressed_bytes
+    );
+
+    let Ok(Opened::Known(DirectRequest::Message(opened))) = open::<DirectRequest>(sealed) else {
+        panic!("expected a direct message");
+    };
+    let DirectRequest::Message(original) = request else {
+        unreachable!()
+    };
+    assert_eq!(opened.message, original.message);
+    assert_eq!(opened.id, original.id);
+}
+
+#[test]
+fn small_payloads_are_left_alone() {
+    let peer = peer();
+    let request = DirectRequest::Message(ChatMessage::new(peer, "hi".to_string()));
+    let mut sealed = seal(&request);
+    assert_eq!(envelope::compress(&mut sealed, 1024), None);
+    assert_eq!(sealed, seal(&request));
+    assert!(sealed.get("content_encoding").is_none());
+
+    // Nor are payloads that compressing wouldn't shrink.
+    let mut sealed = seal(&request);
+    assert_eq!(envelope::compress(&mut sealed, 0), None);
+}
+
+#[test]
+fn payloads_inflating_past_the_limit_are_refused() {
+    let bomb = compress(&vec![b' '; MAX_DECOMPRESSED_LEN + 1]).unwrap();
+    assert!(bomb.len() < 1024);
+    let error = decompress(&bomb, MAX_DECOMPRESSED_LEN).unwrap_err();
+    assert!(error.to_string().contains("more than"), "{}", error);
+
+    let sealed = json!({
+        "version": 1,
+        "kind": "message",
+        "content_encoding": "zstd",
+        "payload": BASE64.encode(&bomb),
+    });
+    let error = open::<DirectRequest>(sealed).unwrap_err();
+    assert!(error.to_string().contains("more than"), "{}", error);
+}
+
+#[test]
+fn corrupt_compressed_payloads_are_errors() {
+    for payload in [
+        json!("not base64!"),
+        json!(BASE64.encode(b"not zstd")),
+        json!({}),
+    ] {
+        let sealed = json!({
+            "version": 1,
+            "kind": "message",
+            "content_encoding": "zstd",
+            "payload": payload,
+        });
+        assert!(open::<DirectRequest>(sealed).is_err());
+    }
+}
+
+#[test]
+fn savings_are_counted_for_stats() {
+    let mut counters = Counters::default();
+    assert_eq!(counters.compression().to_string(), "nothing compressed yet");
+    for _ in 0..2 {
+        let mut sealed = seal(&pasted_log());
+        counters.payload_compressed(envelope::compress(&mut sealed, 1024).unwrap());
+    }
+    let CompressionSavings {
+        payloads,
+        original_bytes,
+        compressed_bytes,
+    } = counters.compression();
+    assert_eq!(payloads, 2);
+    assert!(compressed_bytes < original_bytes);
+    assert!(counters
+        .compression()
+        .to_string()
+        .starts_with("2 payloads, "));
+}
+
+/// Which protocols `nodes[0]` hears `nodes[1]` offer in identify.
+async fn offered_protocols(config: &Config) -> Vec<libp2p::StreamProtocol> {
+    let mut nodes = vec![TestNode::new().await, TestNode::with_config(config).await];
+    connect(&mut nodes, 0, 1).await;
+    drive_until(
+        &mut nodes,
+        Duration::from_secs(5),
+        |index, event, _| match event {
+            SwarmEvent::Behaviour(CustomBehaviourEvent::Identify(
+                libp2p::identify::Event::Received { info, .. },
+            )) if index == 0 => Some(info.protocols),
+            _ => None,
+        },
+    )
+    .await
+}
+
+#[tokio::test]
+async fn peers_offer_compression_unless_turned_off() {
+    let protocols = offered_protocols(&Config::default()).await;
+    assert!(protocols.contains(&COMPRESSED_CHAT_PROTOCOL));
+    assert!(protocols.contains(&CHAT_PROTOCOL));
+
+    let config = Config {
+        protocol: ProtocolConfig {
+            compression: false,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let protocols = offered_protocols(&config).await;
+    assert!(!protocols.contains(&COMPRESSED_CHAT_PROTOCOL));
+    assert!(protocols.contains(&CHAT_PROTOCOL));
+}
@@ -0,0 +1,657 @@
+use libp2p::PeerId;
+use libp2p_demo::{
+    config::{
+        parse_idle_timeout, Config, DeletedMessages, FilterAction, GossipPreset, GossipsubConfig,
+        KeyPin, KeyPinsConfig, MdnsConfig, Muxer, PeeringConfig, PinnedPeer, Security,
+    },
+    testing::peer,
+    testing::tcp_swarm,
+};
+use std::{fs, path::PathBuf, time::Duration};
+
+fn write_config(contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("chat-config-{}.json", uuid::Uuid::new_v4()));
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+#[test]
+fn missing_file_uses_library_defaults() {
+    let config = Config::load(&std::env::temp_dir().join("no-such-chat-config.json")).unwrap();
+    let gossipsub = config.gossipsub.build().unwrap();
+    let defaults = libp2p::gossipsub::Config::default();
+    assert_eq!(gossipsub.mesh_n(), defaults.mesh_n());
+    assert_eq!(
+        gossipsub.heartbeat_interval(),
+        defaults.heartbeat_interval()
+    );
+}
+
+#[test]
+fn gossipsub_values_are_applied() {
+    let path = write_config(
+        r#"{"gossipsub": {"mesh_n": 3, "mesh_n_low": 2, "mesh_n_high": 4,
+            "heartbeat_interval_ms": 250, "history_length": 10}}"#,
+    );
+    let gossipsub = Config::load(&path).unwrap().gossipsub.build().unwrap();
+    assert_eq!(gossipsub.mesh_n(), 3);
+    assert_eq!(gossipsub.mesh_n_low(), 2);
+    assert_eq!(gossipsub.mesh_n_high(), 4);
+    assert_eq!(gossipsub.heartbeat_interval(), Duration::from_millis(250));
+    assert_eq!(gossipsub.history_length(), 10);
+}
+
+#[test]
+fn mesh_bounds_must_be_ordered() {
+    let error = GossipsubConfig {
+        mesh_n: Some(8),
+        mesh_n_high: Some(7),
+        ..Default::default()
+    }
+    .build()
+    .unwrap_err();
+    assert!(
+        error.contains("mesh_n_low <= mesh_n <= mesh_n_high"),
+        "{}",
+        error
+    );
+
+    let path = write_config(r#"{"gossipsub": {"mesh_n_low": 7}}"#);
+    let error = Config::load(&path).unwrap_err().to_string();
+    assert!(error.contains("invalid gossipsub config"), "{}", error);
+}
+
+#[test]
+fn gossip_validation_is_strict_unless_configured() {
+    let strict = GossipsubConfig::default().build().unwrap();
+    assert!(matches!(
+        strict.validation_mode(),
+        libp2p::gossipsub::ValidationMode::Strict
+    ));
+    assert!(strict.validate_messages());
+
+    let path = write_config(r#"{"gossipsub": {"validation": "permissive"}}"#);
+    let permissive = Config::load(&path).unwrap().gossipsub.build().unwrap();
+    assert!(matches!(
+        permissive.validation_mode(),
+        libp2p::gossipsub::ValidationMode::Permissive
+    ));
+    assert!(!permissive.validate_messages());
+
+    let path = write_config(r#"{"gossipsub": {"validation": "anonymous"}}"#);
+    assert!(Config::load(&path).is_err());
+}
+
+#[test]
+fn gossip_presets_are_overridden_by_explicit_values() {
+    let path = write_config(
+        r#"{"gossipsub": {"preset": "wan", "mesh_n_high": 16, "history_gossip": 2,
+            "max_transmit_size": 262144}}"#,
+    );
+    let gossipsub = Config::load(&path).unwrap().gossipsub.build().unwrap();
+    assert_eq!(gossipsub.mesh_n(), 8);
+    assert_eq!(gossipsub.mesh_n_high(), 16);
+    assert_eq!(gossipsub.history_gossip(), 2);
+    assert!(!gossipsub.flood_publish());
+    assert_eq!(gossipsub.max_transmit_size(), 262144);
+
+    let lan = GossipPreset::Lan.config().build().unwrap();
+    assert_eq!(lan.heartbeat_interval(), Duration::from_millis(500));
+    assert!(lan.flood_publish());
+    assert_eq!("wan".parse(), Ok(GossipPreset::Wan));
+    assert!("mesh"
+        .parse::<GossipPreset>()
+        .unwrap_err()
+        .contains("lan or wan"));
+}
+
+#[test]
+fn gossip_windows_and_sizes_are_checked() {
+    for (config, expected) in [
+        (
+            GossipsubConfig {
+                history_length: Some(2),
+                history_gossip: Some(3),
+                ..Default::default()
+            },
+            "history_length must be at least history_gossip (3)",
+        ),
+        (
+            GossipsubConfig {
+                history_gossip: Some(0),
+                ..Default::default()
+            },
+            "history_gossip must be at least 1",
+        ),
+        (
+            GossipsubConfig {
+                max_transmit_size: Some(100),
+                ..Default::default()
+            },
+            "max_transmit_size must be at least 1024 bytes",
+        ),
+        (
+            GossipsubConfig {
+                preset: Some(GossipPreset::Lan),
+                mesh_n_low: Some(5),
+                ..Default::default()
+            },
+            "but got 5 <= 4 <= 8",
+        ),
+    ] {
+        let error = config.build().unwrap_err();
+        assert!(error.contains(expected), "{}", error);
+    }
+}
+
+#[test]
+fn rpc_tokens_are_optional_but_not_empty() {
+    let path = write_config(r#"{"rpc": {"token": "s3cret"}}"#);
+    assert_eq!(
+        Config::load(&path).unwrap().rpc.token.as_deref(),
+        Some("s3cret")
+    );
+    assert_eq!(Config::default().rpc.token, None);
+
+    let path = write_config(r#"{"rpc": {"token": ""}}"#);
+    let error = Config::load(&path).unwrap_err().to_string();
+    assert!(error.contains("invalid rpc config"), "{}", error);
+}
+
+#[test]
+fn pinned_peers_are_checked() {
+    let (peer, other) = (peer(), peer());
+    let path = write_config(&format!(
+        r#"{{"peering": {{"pinned": [{{"peer_id": "{}", "address": "/ip4/10.0.0.2/tcp/4001"}}],
+            "explicit_below": 2}}}}"#,
+        peer
+    ));
+    let peering = Config::load(&path).unwrap().peering;
+    assert_eq!(peering.pinned[0].peer_id, peer);
+    assert_eq!(peering.explicit_below, 2);
+    assert_eq!(Config::default().peering.explicit_below, 4);
+
+    let pinned = |peer_id: PeerId, address: &str| PinnedPeer {
+        peer_id,
+        address: address.parse().unwrap(),
+    };
+    for (pinned, expected) in [
+        (
+            vec![pinned(
+                peer,
+                &format!("/ip4/10.0.0.2/tcp/4001/p2p/{}", other),
+            )],
+            format!("the address of pinned peer {} is for {}", peer, other),
+        ),
+        (
+            vec![pinned(peer, "/ip4/10.0.0.2/udp/4001")],
+            format!("can't dial pinned peer {}", peer),
+        ),
+        (
+            vec![
+                pinned(peer, "/ip4/10.0.0.2/tcp/4001"),
+                pinned(peer, "/ip4/10.0.0.3/tcp/4001"),
+            ],
+            format!("peer {} is pinned twice", peer),
+        ),
+    ] {
+        let error = PeeringConfig {
+            pinned,
+            ..Default::default()
+        }
+        .check()
+        .unwrap_err();
+        assert!(error.contains(&expected), "{}", error);
+    }
+    assert!(PeeringConfig {
+        pinned: vec![pinned(
+            peer,
+            &format!("/ip4/10.0.0.2/tcp/4001/p2p/{}", peer)
+        )],
+        ..Default::default()
+    }
+    .check()
+    .is_ok());
+}
+
+#[test]
+fn unknown_fields_are_rejected() {
+    let path = write_config(r#"{"gossipsub": {"mesh_size": 4}}"#);
+    let error = Config::load(&path).unwrap_err().to_string();
+    assert!(error.contains("mesh_size"), "{}", error);
+}
+
+#[test]
+fn history_length_and_heartbeat_are_checked() {
+    assert!(GossipsubConfig {
+        history_length: Some(1),
+        ..Default::default()
+    }
+    .build()
+    .is_err());
+    assert!(GossipsubConfig {
+        heartbeat_interval_ms: Some(0),
+        ..Default::default()
+    }
+    .build()
+    .is_err());
+}
+
+#[test]
+fn mdns_timings_are_applied_and_checked() {
+    let path = write_config(r#"{"mdns": {"query_interval_ms": 500, "ttl_secs": 30}}"#);
+    let config = Config::load(&path).unwrap();
+    assert!(config.mdns.enabled);
+    let mdns = config.mdns.build().unwrap();
+    assert_eq!(mdns.query_interval, Duration::from_millis(500));
+    assert_eq!(mdns.ttl, Duration::from_secs(30));
+
+    let path = write_config(r#"{"mdns": {"query_interval_ms": 0}}"#);
+    let error = Config::load(&path).unwrap_err().to_string();
+    assert!(error.contains("invalid mdns config"), "{}", error);
+}
+
+#[tokio::test]
+async fn mdns_can_be_disabled() {
+    let config = Config {
+        mdns: MdnsConfig {
+            enabled: false,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let swarm = tcp_swarm(&config).await;
+    assert!(!swarm.behaviour().mdns.is_enabled());
+
+    let swarm = tcp_swarm(&Config::default()).await;
+    assert!(swarm.behaviour().mdns.is_enabled());
+}
+
+#[tokio::test]
+async fn upnp_can_be_disabled() {
+    assert!(Config::default().swarm.upnp);
+    let path = write_config(r#"{"swarm": {"upnp": false}}"#);
+    let config = Config::load(&path).unwrap();
+    let swarm = tcp_swarm(&config).await;
+    assert!(!swarm.behaviour().upnp.is_enabled());
+
+    let swarm = tcp_swarm(&Config::default()).await;
+    assert!(swarm.behaviour().upnp.is_enabled());
+}
+
+#[test]
+fn deleted_messages_can_be_hidden() {
+    assert_eq!(
+        Config::default().history.deleted_messages,
+        DeletedMessages::Tombstone
+    );
+
+    let path = write_config(r#"{"history": {"deleted_messages": "hide"}}"#);
+    assert_eq!(
+        Config::load(&path).unwrap().history.deleted_messages,
+        DeletedMessages::Hide
+    );
+
+    let path = write_config(r#"{"history": {"deleted_messages": "shred"}}"#);
+    assert!(Config::load(&path).is_err());
+}
+
+#[test]
+fn blocked_words_are_read_and_must_be_single_words() {
+    let path = write_config(r#"{"filter": {"blocked_words": ["Spam", "darn"], "action": "drop"}}"#);
+    let config = Config::load(&path).unwrap().filter;
+    assert_eq!(config.action, FilterAction::Drop);
+    assert_eq!(config.filter().filter_content("spam? DARN"), "****? ****");
+    assert_eq!(Config::default().filter.action, FilterAction::Mask);
+
+    for words in [r#"[""]"#, r#"["free money"]"#, r#"["s.p.a.m"]"#] {
+        let path = write_config(&format!(r#"{{"filter": {{"blocked_words": {}}}}}"#, words));
+        let error = Config::load(&path).unwrap_err().to_string();
+        assert!(error.contains("invalid filter config"), "{}", error);
+    }
+}
+
+#[test]
+fn connection_limits_are_read_and_checked() {
+    let limits = Config::default().connection_limits;
+    assert_eq!(limits.max_established, 64);
+    assert_eq!(limits.max_established_per_peer, 2);
+    assert!(limits.build().is_ok());
+
+    let path = write_config(
+        r#"{"connection_limits": {"max_established": 16, "max_pending_incoming": 4}}"#,
+    );
+    let limits = Config::load(&path).unwrap().connection_limits;
+    assert_eq!(limits.max_established, 16);
+    assert_eq!(limits.max_pending_incoming, Some(4));
+
+    let path = write_config(r#"{"connection_limits": {"max_established": 8}}"#);
+    let error = Config::load(&path).unwrap_err().to_string();
+    assert!(
+        error.contains("reserved_outbound must be less than max_established (8), but got 8"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn idle_timeout_of_zero_or_none_never_expires() {
+    assert_eq!(
+        Config::default().swarm.idle_timeout(),
+        Duration::from_secs(10)
+    );
+
+    let path = write_config(r#"{"swarm": {"idle_timeout_secs": 0}}"#);
+    let config = Config::load(&path).unwrap();
+    assert_eq!(config.swarm.idle_timeout(), Duration::MAX);
+
+    assert_eq!(parse_idle_timeout("300"), Ok(300));
+    assert_eq!(parse_idle_timeout("none"), Ok(0));
+    assert_eq!(parse_idle_timeout("0"), Ok(0));
+    assert_eq!(
+        parse_idle_timeout("-1"),
+        Err("invalid idle timeout: -1 (expected seconds or none)".to_string())
+    );
+}
+
+#[test]
+fn security_defaults_to_both_and_can_be_either() {
+    assert_eq!(Config::default().swarm.security(), Security::Both);
+
+    let path = write_config(r#"{"swarm": {"security": "tls"}}"#);
+    assert_eq!(Config::load(&path).unwrap().swarm.security(), Security::Tls);
+    let path = write_config(r#"{"swarm": {"security": "noise"}}"#);
+    assert_eq!(
+        Config::load(&path).unwrap().swarm.security(),
+        Security::Noise
+    );
+
+    assert_eq!("tls".parse(), Ok(Security::Tls));
+    assert_eq!("both".parse(), Ok(Security::Both));
+    assert_eq!(
+        "quic".parse::<Security>(),
+        Err("unknown security protocol quic (expected noise, tls or both)".to_string())
+    );
+}
+
+#[test]
+fn network_ids_are_optional_and_need_noise() {
+    assert_eq!(Config::default().swarm.network_id, None);
+
+    // Noise alone is offered unless security says otherwise.
+    let path = write_config(r#"{"swarm": {"network_id": "staging"}}"#);
+    let config = Config::load(&path).unwrap();
+    assert_eq!(config.swarm.network_id.as_deref(), Some("staging"));
+    assert_eq!(config.swarm.security(), Security::Noise);
+
+    // TLS, even as a choice, would let peers of other networks in.
+    for contents in [
+        r#"{"swarm": {"network_id": ""}}"#,
+        r#"{"swarm": {"network_id": "staging", "security": "tls"}}"#,
+        r#"{"swarm": {"network_id": "staging", "security": "both"}}"#,
+    ] {
+        let error = Config::load(&write_config(contents))
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("invalid swarm config"), "{}", error);
+    }
+}
+
+#[test]
+fn muxer_defaults_to_yamux_and_can_be_mplex() {
+    assert_eq!(Config::default().swarm.muxer, Muxer::Yamux);
+
+    let path = write_config(r#"{"swarm": {"muxer": "mplex"}}"#);
+    assert_eq!(Config::load(&path).unwrap().swarm.muxer, Muxer::Mplex);
+
+    assert_eq!("mplex".parse(), Ok(Muxer::Mplex));
+    assert_eq!(
+        "quic".parse::<Muxer>(),
+        Err("unknown muxer quic (expected yamux or mplex)".to_string())
+    );
+}
+
+#[test]
+fn flood_recovery_must_be_below_the_shedding_rate() {
+    let flood = Config::default().flood;
+    assert_eq!(flood.shed_above_per_sec, 200.0);
+    assert_eq!(flood.recover_below_per_sec, 100.0);
+
+    let path =
+        write_config(r#"{"flood": {"shed_above_per_sec": 50, "recover_below_per_sec": 80}}"#);
+    let error = Config::load(&path).unwrap_err().to_string();
+    assert!(
+        error.contains("invalid flood config") && error.contains("at most shed_above_per_sec (50)"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn dht_lookups_need_an_interval() {
+    let dht = Config::default().dht;
+    assert_eq!(dht.provider_ttl_secs, 48 * 60 * 60);
+    assert_eq!(dht.lookup_interval_secs, 60);
+
+    let path = write_config(r#"{"dht": {"lookup_interval_secs": 0}}"#);
+    let error = Config::load(&path).unwrap_err().to_string();
+    assert!(
+        error.contains("invalid dht config") && error.contains("lookup_interval_secs"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn rate_limit_must_allow_some_messages() {
+    let limits = Config::default().rate_limit;
+    assert_eq!(limits.messages_per_sec, 10.0);
+    assert_eq!(limits.burst, 30);
+
+    let path = write_config(r#"{"rate_limit": {"messages_per_sec": 0}}"#);
+    let error = Config::load(&path).unwrap_err().to_string();
+    assert!(
+        error.contains("messages_per_sec must be greater than 0, but got 0"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn replay_window_must_be_positive() {
+    assert_eq!(Config::default().replay.window, 64);
+
+    let path = write_config(r#"{"replay": {"window": 0}}"#);
+    let error = Config::load(&path).unwrap_err().to_string();
+    assert!(error.contains("window must be at least 1"), "{}", error);
+}
+
+#[test]
+fn legacy_protocol_can_be_turned_off() {
+    assert!(Config::default().protocol.legacy);
+
+    let path = write_config(r#"{"protocol": {"legacy": false}}"#);
+    assert!(!Config::load(&path).unwrap().protocol.legacy);
+}
+
+#[test]
+fn history_limit_must_be_positive() {
+    assert_eq!(Config::default().history.max_messages, 10_000);
+
+    let path = write_config(r#"{"history": {"max_messages": 0}}"#);
+    let error = Config::load(&path).unwrap_err().to_string();
+    assert!(
+        error.contains("max_messages must be at least 1"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn request_timeout_must_be_positive() {
+    assert_eq!(Config::default().protocol.request_timeout_secs, 10);
+
+    let path = write_config(r#"{"protocol": {"request_timeout_secs": 0}}"#);
+    let error = Config::load(&path).unwrap_err().to_string();
+    assert!(
+        error.contains("invalid protocol config") && error.contains("request_timeout_secs"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn compression_is_on_above_a_kibibyte_by_default() {
+    let defaults = Config::default().protocol;
+    assert!(defaults.compression);
+    assert_eq!(defaults.compress_above_bytes, 1024);
+
+    let path = write_config(r#"{"protocol": {"compression": false, "compress_above_bytes": 0}}"#);
+    let config = Config::load(&path).unwrap();
+    assert!(!config.protocol.compression);
+    assert_eq!(config.protocol.compress_above_bytes, 0);
+}
+
+#[test]
+fn store_forward_limits_are_checked() {
+    let path = write_config(r#"{"store_forward": {"max_per_target": 4, "ttl_secs": 60}}"#);
+    let config = Config::load(&path).unwrap();
+    assert_eq!(config.store_forward.max_per_target, 4);
+    assert_eq!(config.store_forward.ttl_secs, 60);
+
+    let path = write_config(r#"{"store_forward": {"max_per_target": 0}}"#);
+    let error = Config::load(&path).unwrap_err().to_string();
+    assert!(
+        error.contains("max_per_target must be at least 1"),
+        "{}",
+        error
+    );
+
+    let path = write_config(r#"{"store_forward": {"max_per_sender": 0}}"#);
+    let error = Config::load(&path).unwrap_err().to_string();
+    assert!(
+        error.contains("max_per_sender must be at least 1"),
+        "{}",
+        error
+    );
+
+    let path = write_config(r#"{"store_forward": {"ttl_secs": 18446744073709551615}}"#);
+    let error = Config::load(&path).unwrap_err().to_string();
+    assert!(error.contains("ttl_secs"), "{}", error);
+}
+
+#[test]
+fn rendezvous_discovery_interval_must_be_positive() {
+    let path = write_config(r#"{"rendezvous": {"server": true, "discover_interval_secs": 0}}"#);
+    let error = Config::load(&path).unwrap_err().to_string();
+    assert!(
+        error.contains("invalid rendezvous config") && error.contains("discover_interval_secs"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn display_flush_interval_is_read() {
+    assert_eq!(Config::default().display.flush_interval_ms, 50);
+
+    let path = write_config(r#"{"display": {"flush_interval_ms": 0}}"#);
+    assert_eq!(Config::load(&path).unwrap().display.flush_interval_ms, 0);
+}
+
+#[test]
+fn retry_schedule_is_read_and_checked() {
+    let path = write_config(r#"{"retry": {"max_attempts": 5, "backoff_secs": [1, 4]}}"#);
+    let policy = Config::load(&path).unwrap().retry.policy();
+    assert_eq!(policy.max_attempts, 5);
+    assert_eq!(
+        policy.backoff,
+        [Duration::from_secs(1), Duration::from_secs(4)]
+    );
+
+    let path = write_config(r#"{"retry": {"max_attempts": 1, "backoff_secs": []}}"#);
+    assert!(Config::load(&path).is_ok());
+
+    for contents in [
+        r#"{"retry": {"max_attempts": 0}}"#,
+        r#"{"retry": {"max_attempts": 2, "backoff_secs": []}}"#,
+    ] {
+        let error = Config::load(&write_config(contents))
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("invalid retry config"), "{}", error);
+    }
+}
+
+#[test]
+fn batching_is_read_and_checked() {
+    let batch = Config::default().batch;
+    assert_eq!((batch.window_ms, batch.max_messages), (50, 32));
+
+    let path = write_config(r#"{"batch": {"window_ms": 0}}"#);
+    let batch = Config::load(&path).unwrap().batch;
+    assert_eq!((batch.window_ms, batch.max_messages), (0, 32));
+
+    let path = write_config(r#"{"batch": {"max_messages": 0}}"#);
+    let error = Config::load(&path).unwrap_err().to_string();
+    assert!(error.contains("invalid batch config"), "{}", error);
+}
+
+#[test]
+fn member_tracking_is_read_and_checked() {
+    let members = Config::default().members;
+    assert_eq!(
+        (members.heartbeat_secs, members.stale_after_secs),
+        (30, 120)
+    );
+
+    let path = write_config(r#"{"members": {"heartbeat_secs": 10, "stale_after_secs": 25}}"#);
+    let members = Config::load(&path).unwrap().members;
+    assert_eq!((members.heartbeat_secs, members.stale_after_secs), (10, 25));
+
+    for contents in [
+        r#"{"members": {"heartbeat_secs": 0}}"#,
+        r#"{"members": {"heartbeat_secs": 60, "stale_after_secs": 60}}"#,
+    ] {
+        let error = Config::load(&write_config(contents))
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("invalid members config"), "{}", error);
+    }
+}
+
+#[test]
+fn key_pins_are_read_and_their_patterns_checked() {
+    assert!(Config::default().key_pins.pins.is_empty());
+    let (peer, other) = (peer(), peer());
+
+    let path = write_config(&format!(
+        r#"{{"key_pins": {{"pins": [{{"address": "/ip4/10.0.0.2/tcp/*", "peer_id": "{}"}}]}}}}"#,
+        peer
+    ));
+    let pins = Config::load(&path).unwrap().key_pins.build().unwrap();
+    let address = "/ip4/10.0.0.2/tcp/4001".parse().unwrap();
+    assert_eq!(pins.verify(&address, &other), Err(peer));
+    let pattern_ending_in_the_pinned_peer = KeyPinsConfig {
+        pins: vec![KeyPin {
+            address: format!("/ip4/10.0.0.2/tcp/4001/p2p/{}", peer),
+            peer_id: peer,
+        }],
+    };
+    assert!(pattern_ending_in_the_pinned_peer.build().is_ok());
+
+    for (address, expected) in [
+        ("10.0.0.2:4001".to_string(), "patterns start with /"),
+        ("/ws/*".to_string(), "/ws can't be *"),
+        ("/ip4/10.0.0.2/tcp".to_string(), "/tcp needs a value"),
+        (format!("/ip4/10.0.0.2/tcp/4001/p2p/{}", other), "but names"),
+    ] {
+        let path = write_config(&format!(
+            r#"{{"key_pins": {{"pins": [{{"address": "{}", "peer_id": "{}"}}]}}}}"#,
+            address, peer
+        ));
+        let error = Config::load(&path).unwrap_err().to_string();
+        assert!(error.contains("invalid key_pins config"), "{}", error);
+        assert!(error.contains(expected), "{}", error);
+    }
+}
@@ -0,0 +1,60 @@
+use libp2p::PeerId;
+use libp2p_demo::{
+    contacts::{Contacts, CONTACTS_FILE},
+    testing::peer,
+};
+use std::{fs, path::PathBuf};
+
+fn contacts_path() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("chat-contacts-{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&dir).unwrap();
+    dir.join(CONTACTS_FILE)
+}
+
+#[test]
+fn contacts_survive_a_restart() {
+    let path = contacts_path();
+    let (alice, bob) = (peer(), peer());
+    let mut contacts = Contacts::load(&path).unwrap();
+    assert!(contacts.sorted().is_empty());
+
+    assert!(contacts.add(alice, Some("Alice".to_string()), 1_000));
+    assert!(contacts.add(bob, None, 1_001));
+    contacts.save(&path).unwrap();
+
+    let loaded = Contacts::load(&path).unwrap();
+    assert_eq!(loaded.nickname(&alice), Some("Alice"));
+    assert_eq!(loaded.get(&bob).unwrap().added_at, 1_001);
+    assert_eq!(loaded.nickname(&bob), None);
+}
+
+#[test]
+fn adding_a_contact_again_replaces_its_nickname() {
+    let alice = peer();
+    let mut contacts = Contacts::default();
+    contacts.add(alice, Some("Alice".to_string()), 1_000);
+
+    assert!(!contacts.add(alice, Some("Ally".to_string()), 2_000));
+    assert_eq!(contacts.nickname(&alice), Some("Ally"));
+    assert_eq!(contacts.get(&alice).unwrap().added_at, 1_000);
+
+    assert!(contacts.remove(&alice).is_some());
+    assert!(contacts.get(&alice).is_none());
+    assert!(contacts.remove(&alice).is_none());
+}
+
+#[test]
+fn named_contacts_are_listed_first_by_name() {
+    let (zoe, anna, unnamed) = (peer(), peer(), peer());
+    let mut contacts = Contacts::default();
+    contacts.add(unnamed, None, 0);
+    contacts.add(zoe, Some("Zoe".to_string()), 0);
+    contacts.add(anna, Some("Anna".to_string()), 0);
+
+    let order: Vec<PeerId> = contacts
+        .sorted()
+        .into_iter()
+        .map(|(peer, _)| *peer)
+        .collect();
+    assert_eq!(order, [anna, zoe, unnamed]);
+}
@@ -0,0 +1,181 @@
+use libp2p::PeerId;
+use libp2p_demo::{
+    command::{Command, Reply, RoomSummary},
+    control::{self, ControlRequest, ControlSocket},
+    event::ChatEvent,
+    handle::ChatHandle,
+    room_settings::Notify,
+    testing::peer,
+};
+use serde_json::Value;
+use std::{
+    io,
+    os::unix::fs::PermissionsExt,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    net::{unix::OwnedReadHalf, UnixStream},
+    sync::{broadcast, mpsc},
+};
+
+fn socket_path(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("control-socket-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+/// A stand-in for the node that is in the room "chat" and records the
+/// commands it runs.
+fn node(events_tx: broadcast::Sender<ChatEvent>) -> (ChatHandle, Arc<Mutex<Vec<Command>>>) {
+    let (requests_tx, mut requests_rx) = mpsc::channel::<ControlRequest>(8);
+    let commands = Arc::new(Mutex::new(Vec::new()));
+
+    let recorded = commands.clone();
+    tokio::spawn(async move {
+        while let Some((command, reply_tx)) = requests_rx.recv().await {
+            let reply = match &command {
+                Command::Rooms => Ok(Reply::Rooms {
+                    rooms: vec![RoomSummary {
+                        room: "chat".to_string(),
+                        current: true,
+                        private: false,
+                        providers: 0,
+                        notify: Notify::All,
+                    }],
+                }),
+                Command::Send { .. } => Ok(Reply::Sent {
+                    id: uuid::Uuid::new_v4(),
+                }),
+                other => Err(format!("unexpected command {:?}", other)),
+            };
+            recorded.lock().unwrap().push(command);
+            let _ = reply_tx.send(reply);
+        }
+    });
+
+    (ChatHandle::new(requests_tx, events_tx), commands)
+}
+
+async fn next(lines: &mut Lines<BufReader<OwnedReadHalf>>) -> Value {
+    let line = tokio::time::timeout(Duration::from_secs(5), lines.next_line())
+        .await
+        .expect("no line in time")
+        .unwrap()
+        .unwrap();
+    serde_json::from_str(&line).unwrap()
+}
+
+#[tokio::test]
+async fn the_socket_is_private_and_replaces_a_stale_one() {
+    let path = socket_path("stale.sock");
+    // A crashed node leaves its socket file behind.
+    drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+    assert!(path.exists());
+
+    let (handle, _) = node(broadcast::channel(1).0);
+    let socket = ControlSocket::bind(path.clone(), handle.clone()).unwrap();
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    let error = ControlSocket::bind(path.clone(), handle).err().unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::AddrInUse);
+
+    drop(socket);
+    assert!(!path.exists());
+}
+
+#[tokio::test]
+async fn a_file_that_is_not_a_socket_is_left_alone() {
+    let path = socket_path("notes.txt");
+    std::fs::write(&path, "keep me").unwrap();
+
+    let (handle, _) = node(broadcast::channel(1).0);
+    let error = ControlSocket::bind(path.clone(), handle).err().unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "keep me");
+}
+
+#[tokio::test]
+async fn binding_leaves_only_the_socket_behind() {
+    let dir = std::env::temp_dir().join(format!("control-socket-bind-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("node.sock");
+
+    let (handle, _) = node(broadcast::channel(1).0);
+    let _socket = ControlSocket::bind(path.clone(), handle).unwrap();
+    let entries: Vec<PathBuf> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    assert_eq!(entries, [path.as_path()]);
+    assert!(UnixStream::connect(&path).await.is_ok());
+}
+
+#[tokio::test]
+async fn subscribers_get_events_between_replies() {
+    let path = socket_path("subscribe.sock");
+    let (events_tx, _) = broadcast::channel(8);
+    let (handle, _) = node(events_tx.clone());
+    let _socket = ControlSocket::bind(path.clone(), handle).unwrap();
+
+    let (reader, mut writer) = UnixStream::connect(&path).await.unwrap().into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    writer
+        .write_all(b"{\"command\": \"subscribe\"}\n")
+        .await
+        .unwrap();
+    assert_eq!(next(&mut lines).await["reply"]["type"], "subscribed");
+
+    let peer: PeerId = peer();
+    events_tx.send(ChatEvent::PeerOnline { peer }).unwrap();
+    let event = next(&mut lines).await;
+    assert_eq!(
+        serde_json::from_value::<ChatEvent>(event).unwrap(),
+        ChatEvent::PeerOnline { peer }
+    );
+
+    writer
+        .write_all(b"{\"command\": \"rooms\"}\n")
+        .await
+        .unwrap();
+    let reply = next(&mut lines).await;
+    assert_eq!(reply["ok"], true);
+    assert_eq!(reply["reply"]["rooms"][0]["room"], "chat");
+}
+
+#[tokio::test]
+async fn attach_runs_input_lines_on_the_node() {
+    let path = socket_path("attach.sock");
+    let (handle, commands) = node(broadcast::channel(1).0);
+    let _socket = ControlSocket::bind(path.clone(), handle).unwrap();
+
+    let input = &b"hello everyone\n\n/rooms\n/nosuchcommand\n"[..];
+    tokio::time::timeout(Duration::from_secs(5), control::attach(&path, input))
+        .await
+        .expect("attach did not finish")
+        .unwrap();
+
+    let commands = commands.lock().unwrap();
+    assert_eq!(commands.len(), 2);
+    assert!(matches!(
+        &commands[0],
+        Command::Send { text, room: None, .. } if text == "hello everyone"
+    ));
+    assert!(matches!(commands[1], Command::Rooms));
+}
+
+#[tokio::test]
+async fn attach_reports_a_missing_socket() {
+    let path = socket_path("missing.sock");
+    let error = control::attach(&path, &b""[..]).await.unwrap_err();
+    assert!(error
+        .to_string()
+        .contains("can't connect to control socket"));
+}
@@ -0,0 +1,86 @@
+use libp2p_demo::daemon::{self, PidFile, PID_FILE};
+use std::{
+    io,
+    path::PathBuf,
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+fn data_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("daemon-test-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn a_second_daemon_is_refused() {
+    let path = data_dir("second").join(PID_FILE);
+    let pid_file = PidFile::create(path.clone()).unwrap();
+    assert_eq!(daemon::running_pid(&path).unwrap(), std::process::id());
+
+    let error = PidFile::create(path.clone()).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::ResourceBusy);
+    assert!(error
+        .to_string()
+        .contains(&format!("pid {}", std::process::id())));
+
+    drop(pid_file);
+    assert!(!path.exists());
+    assert_eq!(
+        daemon::running_pid(&path).unwrap_err().kind(),
+        io::ErrorKind::NotFound
+    );
+}
+
+#[test]
+fn a_pid_file_left_by_a_crash_is_taken_over() {
+    let path = data_dir("stale").join(PID_FILE);
+    std::fs::write(&path, "999999\n").unwrap();
+
+    assert_eq!(
+        daemon::stop(&path, Duration::from_secs(1))
+            .unwrap_err()
+            .kind(),
+        io::ErrorKind::NotFound
+    );
+    let _pid_file = PidFile::create(path.clone()).unwrap();
+    assert_eq!(daemon::running_pid(&path).unwrap(), std::process::id());
+}
+
+#[test]
+fn stop_shuts_the_daemon_down() {
+    let dir = data_dir("stop");
+    let binary = env!("CARGO_BIN_EXE_libp2p-demo");
+    let mut node = Command::new(binary)
+        .args(["--daemon", "--no-mdns", "--listen", "/ip4/127.0.0.1/tcp/0"])
+        .arg("--data-dir")
+        .arg(&dir)
+        .arg("--log-file")
+        .arg(dir.join("node.log"))
+        .stdin(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let pid_path = dir.join(PID_FILE);
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while daemon::running_pid(&pid_path).is_err() {
+        assert!(Instant::now() < deadline, "the daemon wrote no PID file");
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    assert_eq!(daemon::running_pid(&pid_path).unwrap(), node.id());
+
+    let stop = Command::new(binary)
+        .arg("--data-dir")
+        .arg(&dir)
+        .arg("stop")
+        .output()
+        .unwrap();
+    assert!(stop.status.success());
+    assert!(String::from_utf8_lossy(&stop.stdout).contains(&format!("pid {}", node.id())));
+
+    assert!(node.wait().unwrap().success());
+    assert!(!pid_path.exists());
+    let log = std::fs::read_to_string(dir.join("node.log")).unwrap();
+    assert!(log.contains("Running as a daemon"), "{}", log);
+}
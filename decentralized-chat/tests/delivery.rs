@@ -0,0 +1,85 @@
+use libp2p_demo::{
+    delivery::DeliveryStatus::{self, *},
+    message::ChatMessage,
+    store::MessageStore,
+    testing::peer,
+};
+
+fn through(statuses: &[DeliveryStatus]) -> DeliveryStatus {
+    statuses
+        .iter()
+        .skip(1)
+        .fold(statuses[0], |status, next| status.advance(*next))
+}
+
+#[test]
+fn statuses_move_forward_through_delivery() {
+    assert_eq!(through(&[Pending, Sent]), Sent);
+    assert_eq!(through(&[Pending, Sent, Delivered]), Delivered);
+    assert_eq!(through(&[Pending, Sent, Delivered, Read]), Read);
+    // A retry waits again, then goes out.
+    assert_eq!(through(&[Sent, Pending, Sent]), Sent);
+    assert_eq!(through(&[Sent, Pending, Failed]), Failed);
+}
+
+#[test]
+fn delivered_messages_stay_delivered() {
+    assert_eq!(through(&[Delivered, Pending]), Delivered);
+    assert_eq!(through(&[Delivered, Sent]), Delivered);
+    assert_eq!(through(&[Delivered, Failed]), Delivered);
+    assert_eq!(through(&[Read, Delivered]), Read);
+    assert_eq!(through(&[Read, Failed]), Read);
+}
+
+#[test]
+fn failed_messages_only_recover_once_delivered() {
+    assert_eq!(through(&[Failed, Pending]), Failed);
+    assert_eq!(through(&[Failed, Sent]), Failed);
+    assert_eq!(through(&[Failed, Delivered]), Delivered);
+}
+
+#[test]
+fn only_our_own_messages_get_a_status() {
+    let (me, them) = (peer(), peer());
+    let mut store = MessageStore::default();
+    store.set_local_peer(me);
+    let ours = ChatMessage::new(me, "hi".to_string());
+    let theirs = ChatMessage::new(them, "hello".to_string());
+    store.insert(ours.clone());
+    store.insert(theirs.clone());
+
+    assert_eq!(store.set_status(theirs.id, Sent), None);
+    assert_eq!(store.status(&theirs.id), None);
+    assert!(!store.format(&theirs).contains('✓'));
+
+    assert_eq!(store.set_status(ours.id, Pending), Some(Pending));
+    assert!(store.format(&ours).ends_with(": hi …"));
+    assert_eq!(store.set_status(ours.id, Delivered), Some(Delivered));
+    assert_eq!(store.set_status(ours.id, Failed), Some(Delivered));
+    assert!(store.format(&ours).ends_with(": hi ✓✓"));
+
+    // Not even ours before we know who we are, nor unknown ones.
+    let mut store = MessageStore::default();
+    store.insert(ours.clone());
+    assert_eq!(store.set_status(ours.id, Sent), None);
+    store.set_local_peer(me);
+    assert_eq!(
+        store.set_status(ChatMessage::new(me, "gone".to_string()).id, Sent),
+        None
+    );
+}
+
+#[test]
+fn statuses_are_dropped_with_evicted_messages() {
+    let me = peer();
+    let mut store = MessageStore::new(1, 1);
+    store.set_local_peer(me);
+    let first = ChatMessage::new(me, "first".to_string());
+    store.insert(first.clone());
+    store.set_status(first.id, Sent);
+
+    store.insert(ChatMessage::new(me, "second".to_string()));
+    assert_eq!(store.status(&first.id), None);
+    // A late ack for it changes nothing.
+    assert_eq!(store.set_status(first.id, Delivered), None);
+}
@@ -0,0 +1,41 @@
+use libp2p_demo::{departure::Departures, testing::peer};
+use std::time::{Duration, Instant};
+
+const GRACE: Duration = Duration::from_secs(10);
+
+#[test]
+fn peers_that_stay_away_depart_after_the_grace_period() {
+    let mut departures = Departures::new(GRACE);
+    let (alice, bob, start) = (peer(), peer(), Instant::now());
+    departures.closed(alice, "connection closed".to_string(), start);
+    departures.closed(bob, "idle timeout".to_string(), start + GRACE / 2);
+
+    assert!(departures.due(start + GRACE / 2).is_empty());
+    assert_eq!(
+        departures.due(start + GRACE),
+        [(alice, "connection closed".to_string())]
+    );
+    assert!(departures.due(start + GRACE).is_empty());
+    assert_eq!(
+        departures.due(start + GRACE * 2),
+        [(bob, "idle timeout".to_string())]
+    );
+    assert!(departures.is_empty());
+}
+
+#[test]
+fn reconnecting_within_the_grace_period_is_no_departure() {
+    let mut departures = Departures::new(GRACE);
+    let (alice, start) = (peer(), Instant::now());
+
+    departures.closed(alice, "connection closed".to_string(), start);
+    assert!(departures.reconnected(&alice, start + GRACE / 2));
+    assert!(departures.is_empty());
+    assert!(departures.due(start + GRACE).is_empty());
+
+    // A peer that was never held, or whose grace period ran out before its
+    // departure was taken, comes back as new.
+    assert!(!departures.reconnected(&peer(), start));
+    departures.closed(alice, "connection closed".to_string(), start);
+    assert!(!departures.reconnected(&alice, start + GRACE));
+}
@@ -0,0 +1,145 @@
+use libp2p::{
+    futures::StreamExt,
+    identity,
+    kad::{self, RecordKey},
+    swarm::SwarmEvent,
+    Multiaddr, Swarm,
+};
+use libp2p_demo::{
+    behaviour::{CustomBehaviour, CustomBehaviourEvent},
+    config::Config,
+    dht::{room_key, RoomProviders},
+    testing::{listening_tcp_swarm, peer},
+};
+use sha2::{Digest, Sha256};
+use std::{collections::HashSet, time::Duration};
+
+const TIMEOUT: Duration = Duration::from_secs(15);
+
+#[test]
+fn room_keys_hash_the_room_name() {
+    assert_eq!(
+        room_key("rust"),
+        RecordKey::new(&Sha256::digest(b"chat-room:rust"))
+    );
+    assert_ne!(room_key("rust"), room_key("Rust"));
+}
+
+/// A swarm listening on localhost that takes its listen address as external,
+/// so Kademlia answers queries from other peers.
+async fn dht_server() -> (Swarm<CustomBehaviour>, Multiaddr) {
+    let (mut swarm, address) = listening_tcp_swarm(&Config::default()).await;
+    swarm.add_external_address(address.clone());
+    (swarm, address)
+}
+
+#[tokio::test]
+async fn room_members_are_found_through_provider_records() {
+    let (mut hub, hub_addr) = dht_server().await;
+    let hub_id = *hub.local_peer_id();
+    let (mut alice, _) = dht_server().await;
+    let alice_id = *alice.local_peer_id();
+    let (mut bob, _) = dht_server().await;
+
+    for swarm in [&mut alice, &mut bob] {
+        swarm
+            .behaviour_mut()
+            .kademlia
+            .add_address(&hub_id, hub_addr.clone());
+    }
+    alice
+        .behaviour_mut()
+        .kademlia
+        .start_providing(room_key("rust"))
+        .unwrap();
+
+    let mut lookup = None;
+    let found = tokio::time::timeout(TIMEOUT, async {
+        loop {
+            tokio::select! {
+                _ = hub.select_next_some() => {}
+                event = alice.select_next_some() => {
+                    if let SwarmEvent::Behaviour(CustomBehaviourEvent::Kademlia(
+                        kad::Event::OutboundQueryProgressed {
+                            result: kad::QueryResult::StartProviding(result),
+                            ..
+                        },
+                    )) = event
+                    {
+                        result.unwrap();
+                        lookup = Some(bob.behaviour_mut().kademlia.get_providers(room_key("rust")));
+                    }
+                }
+                event = bob.select_next_some() => {
+                    if let SwarmEvent::Behaviour(CustomBehaviourEvent::Kademlia(
+                        kad::Event::OutboundQueryProgressed {
+                            id,
+                            result: kad::QueryResult::GetProviders(Ok(
+                                kad::GetProvidersOk::FoundProviders { providers, .. },
+                            )),
+                            ..
+                        },
+                    )) = event
+                    {
+                        if Some(id) == lookup {
+                            break providers;
+                        }
+                    }
+                }
+            }
+        }
+    })
+    .await
+    .expect("bob did not find alice in time");
+
+    assert_eq!(found, HashSet::from([alice_id]));
+}
+
+#[tokio::test]
+async fn lookups_report_new_providers_and_replace_the_count_when_done() {
+    let mut swarm =
+        libp2p_demo::behaviour::build_test_swarm(identity::Keypair::generate_ed25519()).unwrap();
+    let mut queries = || swarm.behaviour_mut().kademlia.get_providers(room_key("x"));
+    let (first, second, other) = (queries(), queries(), queries());
+
+    let mut providers = RoomProviders::default();
+    let (alice, bob, carol) = (peer(), peer(), peer());
+    providers.looking_up(first, "rust".to_string());
+    providers.looking_up(other, "go".to_string());
+
+    assert_eq!(
+        providers.found(first, [alice, bob]),
+        Some(("rust".to_string(), vec![alice, bob]))
+    );
+    // The same provider reported by a second peer during the lookup.
+    assert_eq!(
+        providers.found(first, [bob]),
+        Some(("rust".to_string(), vec![]))
+    );
+    assert_eq!(providers.count("rust"), 0);
+    providers.finished(first);
+    assert_eq!(providers.count("rust"), 2);
+
+    // A later lookup only reports newcomers, and its result replaces the
+    // earlier one, dropping members that left.
+    providers.looking_up(second, "rust".to_string());
+    assert_eq!(
+        providers.found(second, [bob, carol]),
+        Some(("rust".to_string(), vec![carol]))
+    );
+    providers.finished(second);
+    assert_eq!(providers.count("rust"), 2);
+
+    // A peer that disconnected stops counting at once.
+    providers.departed(&bob);
+    assert_eq!(providers.count("rust"), 1);
+
+    providers.left("go");
+    assert_eq!(providers.found(other, [alice]), None);
+
+    providers.unannounced("rust".to_string());
+    providers.unannounced("go".to_string());
+    providers.left("go");
+    assert_eq!(providers.take_unannounced(), ["rust"]);
+    assert!(providers.take_unannounced().is_empty());
+}
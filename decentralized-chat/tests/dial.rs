@@ -0,0 +1,217 @@
+use libp2p::{
+    core::transport::timeout::TransportTimeoutError,
+    dns::{self, ResolveError},
+    swarm::{ConnectionError, DialError},
+    Multiaddr, TransportError,
+};
+use libp2p_demo::dial::{
+    check_dial_address, check_external_address, check_listen_address, describe_close,
+    describe_dial_error, hostname, is_public, parse_multiaddr,
+};
+use std::io;
+
+/// A failed lookup wrapped the way the swarm's transport stack wraps it.
+fn resolve_failure(message: &'static str) -> TransportError<io::Error> {
+    TransportError::Other(io::Error::other(TransportTimeoutError::Other(
+        dns::Error::<io::Error>::ResolveError(ResolveError::from(message)),
+    )))
+}
+
+#[test]
+fn hostname_is_found_in_every_dns_protocol() {
+    for address in [
+        "/dns/chat.example.com/tcp/4001",
+        "/dns4/chat.example.com/tcp/4001",
+        "/dns6/chat.example.com/tcp/4001",
+        "/dnsaddr/chat.example.com",
+    ] {
+        let address: Multiaddr = address.parse().unwrap();
+        assert_eq!(hostname(&address).as_deref(), Some("chat.example.com"));
+    }
+
+    let address: Multiaddr = "/ip4/127.0.0.1/tcp/4001".parse().unwrap();
+    assert_eq!(hostname(&address), None);
+}
+
+#[test]
+fn resolution_failures_name_the_host() {
+    let address: Multiaddr = "/dns4/chat.example.com/tcp/4001".parse().unwrap();
+    let error = DialError::Transport(vec![(address, resolve_failure("lookup timed out"))]);
+
+    assert_eq!(
+        describe_dial_error(&error),
+        "/dns4/chat.example.com/tcp/4001: could not resolve chat.example.com: lookup timed out"
+    );
+}
+
+#[test]
+fn other_transport_errors_show_their_cause() {
+    let address: Multiaddr = "/ip4/127.0.0.1/tcp/1".parse().unwrap();
+    let refused = TransportError::Other(io::Error::new(
+        io::ErrorKind::ConnectionRefused,
+        "connection refused",
+    ));
+    let error = DialError::Transport(vec![(address, refused)]);
+
+    assert_eq!(
+        describe_dial_error(&error),
+        "/ip4/127.0.0.1/tcp/1: connection refused"
+    );
+}
+
+#[test]
+fn closed_connections_say_why() {
+    assert_eq!(describe_close(None), "connection closed");
+    assert_eq!(
+        describe_close(Some(&ConnectionError::KeepAliveTimeout)),
+        "idle for too long"
+    );
+    let reset = ConnectionError::IO(io::Error::new(
+        io::ErrorKind::ConnectionReset,
+        "connection reset by peer",
+    ));
+    assert_eq!(describe_close(Some(&reset)), "connection reset by peer");
+}
+
+#[test]
+fn external_addresses_need_a_concrete_host_and_port() {
+    for address in [
+        "/ip4/203.0.113.7/tcp/4001",
+        "/ip6/2001:db8::1/tcp/4001/ws",
+        "/dns4/chat.example.com/tcp/4001",
+    ] {
+        let address: Multiaddr = address.parse().unwrap();
+        assert_eq!(check_external_address(&address), Ok(()), "{}", address);
+    }
+
+    for (address, error) in [
+        ("/ip4/0.0.0.0/tcp/4001", "0.0.0.0 is not a concrete address"),
+        ("/ip4/203.0.113.7/tcp/0", "port 0 is not a concrete port"),
+        ("/ip4/203.0.113.7", "expected a TCP or UDP port"),
+        (
+            "/dnsaddr/chat.example.com",
+            "expected an IP address or DNS name",
+        ),
+    ] {
+        let address: Multiaddr = address.parse().unwrap();
+        assert_eq!(
+            check_external_address(&address),
+            Err(error.to_string()),
+            "{}",
+            address
+        );
+    }
+}
+
+#[test]
+fn only_internet_addresses_are_public() {
+    for (address, public) in [
+        ("/ip4/203.0.113.7/tcp/4001", true),
+        ("/ip4/192.168.1.20/tcp/4001", false),
+        ("/ip4/127.0.0.1/tcp/4001", false),
+        ("/ip6/2001:db8::1/tcp/4001", true),
+        ("/ip6/fd00::1/tcp/4001", false),
+        ("/ip6/fe80::1/tcp/4001", false),
+        ("/dns4/chat.example.com/tcp/4001", false),
+    ] {
+        let address: Multiaddr = address.parse().unwrap();
+        assert_eq!(is_public(&address), public, "{}", address);
+    }
+}
+
+#[test]
+fn dial_addresses_need_a_host_and_an_enabled_transport() {
+    let peer = "12D3KooWDpJ7As7BWAwRMfu1VU2WCqNjvq387JEYKDBj4kx6nXTN";
+    for address in [
+        "/ip4/192.0.2.1/tcp/4001".to_string(),
+        "/ip6/2001:db8::1/tcp/4001/ws".to_string(),
+        format!("/dns4/chat.example.com/tcp/443/wss/p2p/{}", peer),
+        format!("/dnsaddr/chat.example.com/p2p/{}", peer),
+    ] {
+        let address: Multiaddr = address.parse().unwrap();
+        assert_eq!(check_dial_address(&address), Ok(()), "{}", address);
+    }
+
+    for (address, error) in [
+        (
+            "/ip4/192.0.2.1/udp/4001/quic-v1".to_string(),
+            "QUIC address, but the QUIC transport is not enabled; use a /tcp address",
+        ),
+        (
+            "/ip4/192.0.2.1".to_string(),
+            "missing transport: add /tcp/<port> after the host",
+        ),
+        (
+            format!("/p2p/{}", peer),
+            "missing host: put /ip4, /ip6 or /dns4 and a port before /p2p",
+        ),
+        (
+            "/ip4/0.0.0.0/tcp/4001".to_string(),
+            "can't dial 0.0.0.0; use the peer's own IP",
+        ),
+        ("/ip4/192.0.2.1/tcp/0".to_string(), "can't dial port 0"),
+        (
+            "/tcp/4001".to_string(),
+            "expected an IP address or DNS name first, not /tcp",
+        ),
+        (
+            format!("/ip4/192.0.2.1/tcp/4001/p2p/{}/p2p-circuit", peer),
+            "relayed addresses are not supported; the relay transport is not enabled",
+        ),
+    ] {
+        let address: Multiaddr = address.parse().unwrap();
+        assert_eq!(
+            check_dial_address(&address),
+            Err(error.to_string()),
+            "{}",
+            address
+        );
+    }
+}
+
+#[test]
+fn listen_addresses_need_a_local_ip_and_no_peer_id() {
+    for address in ["/ip4/0.0.0.0/tcp/0", "/ip6/::/tcp/4001/ws"] {
+        let address: Multiaddr = address.parse().unwrap();
+        assert_eq!(check_listen_address(&address), Ok(()), "{}", address);
+    }
+
+    for (address, error) in [
+        (
+            "/dns4/chat.example.com/tcp/4001",
+            "can't listen on a DNS name; use an IP such as /ip4/0.0.0.0",
+        ),
+        (
+            "/ip4/0.0.0.0/tcp/443/wss",
+            "can't listen on /wss; TLS is only supported for dialing, use /ws",
+        ),
+        (
+            "/ip4/0.0.0.0/tcp/4001/p2p/12D3KooWDpJ7As7BWAwRMfu1VU2WCqNjvq387JEYKDBj4kx6nXTN",
+            "can't listen on an address with a /p2p peer id",
+        ),
+        (
+            "/ip4/0.0.0.0/udp/4001",
+            "UDP is not supported; use a /tcp address",
+        ),
+    ] {
+        let address: Multiaddr = address.parse().unwrap();
+        assert_eq!(
+            check_listen_address(&address),
+            Err(error.to_string()),
+            "{}",
+            address
+        );
+    }
+}
+
+#[test]
+fn addresses_without_a_leading_slash_get_a_hint() {
+    assert_eq!(
+        parse_multiaddr("192.0.2.1:4001").unwrap_err(),
+        "invalid multiaddr: 192.0.2.1:4001 (multiaddrs start with /, e.g. /ip4/192.0.2.1/tcp/4001)"
+    );
+    assert_eq!(
+        parse_multiaddr("/ip4/192.0.2.300/tcp/4001").unwrap_err(),
+        "invalid multiaddr: /ip4/192.0.2.300/tcp/4001"
+    );
+}
@@ -0,0 +1,70 @@
+use libp2p_demo::{
+    dnd::{DoNotDisturb, AUTO_REPLY_INTERVAL, DEFAULT_AWAY_MESSAGE, DND_FILE},
+    testing::peer,
+};
+use std::{
+    fs,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+fn dnd_path() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("chat-dnd-{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&dir).unwrap();
+    dir.join(DND_FILE)
+}
+
+#[test]
+fn each_sender_gets_one_auto_reply_an_hour() {
+    let mut dnd = DoNotDisturb::default();
+    let (alice, bob) = (peer(), peer());
+    let now = Instant::now();
+    assert_eq!(dnd.auto_reply(alice, now), None);
+
+    dnd.turn_on(Some("back at 5".to_string()));
+    assert_eq!(dnd.auto_reply(alice, now).as_deref(), Some("back at 5"));
+    assert_eq!(dnd.auto_reply(alice, now + Duration::from_secs(60)), None);
+    assert_eq!(dnd.auto_reply(bob, now).as_deref(), Some("back at 5"));
+    assert_eq!(
+        dnd.auto_reply(alice, now + AUTO_REPLY_INTERVAL).as_deref(),
+        Some("back at 5")
+    );
+
+    dnd.turn_off();
+    assert!(!dnd.enabled());
+    assert_eq!(dnd.auto_reply(bob, now + AUTO_REPLY_INTERVAL), None);
+}
+
+#[test]
+fn turning_dnd_on_again_replies_to_everyone_again() {
+    let mut dnd = DoNotDisturb::default();
+    let alice = peer();
+    let now = Instant::now();
+
+    dnd.turn_on(None);
+    assert_eq!(
+        dnd.auto_reply(alice, now).as_deref(),
+        Some(DEFAULT_AWAY_MESSAGE)
+    );
+    dnd.turn_on(Some("in a meeting".to_string()));
+    assert_eq!(dnd.auto_reply(alice, now).as_deref(), Some("in a meeting"));
+}
+
+#[test]
+fn dnd_survives_a_restart() {
+    let path = dnd_path();
+    assert!(!DoNotDisturb::load(&path).unwrap().enabled());
+
+    let mut dnd = DoNotDisturb::default();
+    dnd.turn_on(Some("on holiday".to_string()));
+    dnd.save(&path).unwrap();
+    let loaded = DoNotDisturb::load(&path).unwrap();
+    assert!(loaded.enabled());
+    assert_eq!(loaded.away_message(), Some("on holiday"));
+
+    dnd.turn_off();
+    dnd.save(&path).unwrap();
+    let loaded = DoNotDisturb::load(&path).unwrap();
+    assert!(!loaded.enabled());
+    assert_eq!(loaded.away_message(), None);
+}
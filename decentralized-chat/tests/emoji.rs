@@ -0,0 +1,34 @@
+use libp2p_demo::emoji::{expand_shortcodes, lookup};
+
+#[test]
+fn known_shortcodes_are_expanded() {
+    assert_eq!(expand_shortcodes("hi :smile:"), "hi 😄");
+    assert_eq!(expand_shortcodes(":+1: ship it :rocket:"), "👍 ship it 🚀");
+    assert_eq!(expand_shortcodes(":smile::wink:"), "😄😉");
+    assert_eq!(lookup("tada"), Some("🎉"));
+}
+
+#[test]
+fn unknown_shortcodes_are_left_untouched() {
+    assert_eq!(expand_shortcodes("a :nosuchcode: b"), "a :nosuchcode: b");
+    assert_eq!(expand_shortcodes(":Smile:"), ":Smile:");
+    assert_eq!(expand_shortcodes("meet at 12:30:45"), "meet at 12:30:45");
+    assert_eq!(lookup("nosuchcode"), None);
+}
+
+#[test]
+fn stray_colons_are_kept() {
+    assert_eq!(expand_shortcodes("::"), "::");
+    assert_eq!(expand_shortcodes(":::"), ":::");
+    assert_eq!(expand_shortcodes("::smile:"), ":😄");
+    assert_eq!(expand_shortcodes(":smile::"), "😄:");
+    assert_eq!(expand_shortcodes(":smile"), ":smile");
+    assert_eq!(expand_shortcodes("C::new() :smile:"), "C::new() 😄");
+    assert_eq!(expand_shortcodes(":nope:smile:"), ":nope😄");
+}
+
+#[test]
+fn text_without_shortcodes_is_unchanged() {
+    assert_eq!(expand_shortcodes(""), "");
+    assert_eq!(expand_shortcodes("héllo wörld 👋"), "héllo wörld 👋");
+}
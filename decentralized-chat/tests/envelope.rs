@@ -0,0 +1,238 @@
+use libp2p::PeerId;
+use libp2p_demo::{
+    envelope::{open, open_slice, seal, Envelope, Kinds, Opened, BARE_VERSION, ENVELOPE_VERSION},
+    forward::StoreError,
+    message::{ChatMessage, DirectRequest, DirectResponse, GossipMessage, MessageId, Sequence},
+    peer_profile::PeerProfile,
+    testing::peer,
+};
+use serde::Serialize;
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+/// `ChatMessage` as a later version might send it, with fields this node has
+/// never heard of.
+#[derive(Serialize)]
+struct FutureChatMessage {
+    id: MessageId,
+    peer_id: PeerId,
+    message: String,
+    timestamp: u64,
+    sequence: Sequence,
+    priority: u8,
+    attachments: Vec<Value>,
+    formatting: Option<String>,
+}
+
+/// A later version's gossip, with a kind this node doesn't know.
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum FutureGossipMessage {
+    Chat(FutureChatMessage),
+    Edit {
+        target_id: MessageId,
+        new_text: String,
+        edited_at: u64,
+    },
+    Poll {
+        question: String,
+        options: Vec<String>,
+    },
+}
+
+impl Kinds for FutureGossipMessage {
+    const KINDS: &'static [&'static str] = &["chat", "edit", "poll"];
+}
+
+fn future_chat(author: PeerId) -> FutureChatMessage {
+    FutureChatMessage {
+        id: Uuid::new_v4(),
+        peer_id: author,
+        message: "from the future".to_string(),
+        timestamp: 1_700_000_000,
+        sequence: Sequence::start(),
+        priority: 3,
+        attachments: vec![json!({"name": "a.png"})],
+        formatting: Some("markdown".to_string()),
+    }
+}
+
+/// The same message in an envelope, sent bare, and in an envelope of a later
+/// version.
+fn encodings<T: Serialize + Kinds>(message: &T) -> Vec<(u32, Value)> {
+    let mut newer = seal(message);
+    newer["version"] = json!(ENVELOPE_VERSION + 1);
+    vec![
+        (ENVELOPE_VERSION, seal(message)),
+        (BARE_VERSION, json!(message)),
+        (ENVELOPE_VERSION + 1, newer),
+    ]
+}
+
+#[test]
+fn messages_round_trip_through_an_envelope() {
+    let chat_message = ChatMessage::new(peer(), "hello".to_string());
+    let sealed = seal(&GossipMessage::Chat(Box::new(chat_message.clone())));
+
+    let envelope: Envelope = serde_json::from_value(sealed.clone()).unwrap();
+    assert_eq!(envelope.version, ENVELOPE_VERSION);
+    assert_eq!(envelope.kind, "chat");
+    assert!(envelope.payload.get("kind").is_none());
+
+    let Ok(Opened::Known(GossipMessage::Chat(opened))) = open(sealed) else {
+        panic!("expected a chat message");
+    };
+    assert_eq!(opened.id, chat_message.id);
+    assert_eq!(opened.message, chat_message.message);
+}
+
+#[test]
+fn replies_carry_the_id_of_their_parent() {
+    let parent = ChatMessage::new(peer(), "lunch?".to_string());
+    let reply = ChatMessage {
+        reply_to: Some(parent.id),
+        ..ChatMessage::new(peer(), "yes".to_string())
+    };
+
+    let encoded = serde_json::to_value(&reply).unwrap();
+    assert_eq!(encoded["reply_to"], json!(parent.id));
+    let decoded: ChatMessage = serde_json::from_value(encoded).unwrap();
+    assert_eq!(decoded.reply_to, Some(parent.id));
+
+    // Messages that answer nothing leave the field out, as peers from before
+    // replies expect.
+    assert!(serde_json::to_value(&parent)
+        .unwrap()
+        .get("reply_to")
+        .is_none());
+}
+
+#[test]
+fn every_variant_is_a_known_kind() {
+    let chat_message = ChatMessage::new(peer(), "hello".to_string());
+    let id = chat_message.id;
+    let kind = |value: Value| value["kind"].as_str().unwrap().to_string();
+
+    let gossip = [
+        GossipMessage::Chat(Box::new(chat_message.clone())),
+        GossipMessage::Edit {
+            target_id: id,
+            new_text: "edited".to_string(),
+        },
+        GossipMessage::Delete { target_id: id },
+        GossipMessage::Reaction {
+            target_id: id,
+            emoji: "👍".to_string(),
+        },
+        GossipMessage::Nickname {
+            name: "alice".to_string(),
+        },
+        GossipMessage::ProfileUpdated,
+        GossipMessage::Joined,
+        GossipMessage::Left,
+        GossipMessage::Presence {
+            rooms: vec!["chat".to_string()],
+        },
+    ];
+    let requests = [
+        DirectRequest::Greeting(chat_message.clone()),
+        DirectRequest::Message(chat_message.clone()),
+        DirectRequest::StoreForward {
+            target: peer(),
+            message: chat_message.clone(),
+            expires_at: 0,
+        },
+        DirectRequest::Forwarded(chat_message.clone()),
+        DirectRequest::PeerExchange,
+        DirectRequest::System(chat_message.clone()),
+        DirectRequest::Profile,
+        DirectRequest::Batch {
+            messages: vec![chat_message.clone()],
+        },
+    ];
+    let responses = [
+        DirectResponse::Welcome(Box::new(chat_message)),
+        DirectResponse::Ack { id },
+        DirectResponse::Stored { id },
+        DirectResponse::Refused {
+            id,
+            reason: StoreError::Expired,
+        },
+        DirectResponse::Unsupported {
+            request_kind: "poll".to_string(),
+        },
+        DirectResponse::Peers { peers: Vec::new() },
+        DirectResponse::Profile(PeerProfile::default()),
+        DirectResponse::Acks { ids: vec![id] },
+    ];
+
+    let gossip: Vec<String> = gossip.iter().map(|m| kind(seal(m))).collect();
+    assert_eq!(gossip, GossipMessage::KINDS);
+    let requests: Vec<String> = requests.iter().map(|m| kind(seal(m))).collect();
+    assert_eq!(requests, DirectRequest::KINDS);
+    let responses: Vec<String> = responses.iter().map(|m| kind(seal(m))).collect();
+    assert_eq!(responses, DirectResponse::KINDS);
+}
+
+#[test]
+fn future_fields_are_ignored() {
+    let author = peer();
+    let future = FutureGossipMessage::Chat(future_chat(author));
+
+    for (version, encoded) in encodings(&future) {
+        let Ok(Opened::Known(GossipMessage::Chat(chat_message))) = open(encoded.clone()) else {
+            panic!("version {} chat not read: {}", version, encoded);
+        };
+        assert_eq!(chat_message.peer_id, author);
+        assert_eq!(chat_message.message, "from the future");
+        assert!(chat_message.sequence.is_some());
+    }
+
+    let edit = FutureGossipMessage::Edit {
+        target_id: Uuid::new_v4(),
+        new_text: "fixed".to_string(),
+        edited_at: 1_700_000_000,
+    };
+    for (version, encoded) in encodings(&edit) {
+        let Ok(Opened::Known(GossipMessage::Edit { new_text, .. })) = open(encoded.clone()) else {
+            panic!("version {} edit not read: {}", version, encoded);
+        };
+        assert_eq!(new_text, "fixed");
+    }
+}
+
+#[test]
+fn future_kinds_are_reported_as_unknown() {
+    let poll = FutureGossipMessage::Poll {
+        question: "lunch?".to_string(),
+        options: vec!["yes".to_string(), "no".to_string()],
+    };
+
+    for (version, encoded) in encodings(&poll) {
+        let bytes = encoded.to_string().into_bytes();
+        let Ok(Opened::Unknown {
+            version: read_version,
+            kind,
+        }) = open_slice::<GossipMessage>(&bytes)
+        else {
+            panic!("version {} poll not reported as unknown", version);
+        };
+        assert_eq!((read_version, kind.as_str()), (version, "poll"));
+        assert!(matches!(
+            open::<DirectRequest>(encoded).unwrap(),
+            Opened::Unknown { .. }
+        ));
+    }
+}
+
+#[test]
+fn broken_messages_are_still_errors() {
+    // A known kind missing a required field.
+    let edit = json!({"version": 1, "kind": "edit", "payload": {"new_text": "x"}});
+    assert!(open::<GossipMessage>(edit).is_err());
+
+    let untagged = json!({"id": Uuid::new_v4(), "message": "no kind"});
+    assert!(open::<GossipMessage>(untagged).is_err());
+
+    assert!(open_slice::<GossipMessage>(b"not json").is_err());
+}
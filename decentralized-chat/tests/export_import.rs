@@ -0,0 +1,248 @@
+use libp2p::PeerId;
+use libp2p_demo::{
+    address_book::AddressBook,
+    export::{export, import, ExportFilter, ImportSummary},
+    history,
+    message::ChatMessage,
+    search::SearchQuery,
+    store::MessageStore,
+    testing::peer,
+};
+use std::{fs, path::PathBuf};
+
+/// A fresh directory under the system temp dir, standing in for a node's data dir.
+fn data_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("chat-export-{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn message(peer_id: PeerId, room: &str, text: &str, timestamp: u64) -> ChatMessage {
+    ChatMessage {
+        room: Some(room.to_string()),
+        timestamp,
+        nickname: Some("ada".to_string()),
+        ..ChatMessage::new(peer_id, text.to_string())
+    }
+}
+
+fn search(history_path: &std::path::Path, term: &str) -> Vec<String> {
+    let mut store = MessageStore::default();
+    for message in history::load(history_path).unwrap() {
+        store.insert(message);
+    }
+    let query = SearchQuery::new(term, false, None, None).unwrap();
+    store
+        .search(&query)
+        .into_iter()
+        .map(|hit| serde_json::to_string(&hit).unwrap())
+        .collect()
+}
+
+#[test]
+fn round_trip_reproduces_search_results() {
+    let peer_id = peer();
+    let source = data_dir();
+    let source_history = source.join(history::HISTORY_FILE);
+
+    let mut edited = message(peer_id, "chat", "hello there", 1_700_000_000);
+    edited.edits.push("hello everyone".to_string());
+    let messages = vec![
+        edited,
+        message(peer_id, "chat", "Hello again", 1_700_000_100),
+        message(peer_id, "rust", "hello from rust", 1_700_000_200),
+        message(peer_id, "chat", "goodbye", 1_700_000_300),
+    ];
+    history::save(&source_history, &messages).unwrap();
+
+    let mut address_book = AddressBook::default();
+    address_book.record(
+        peer_id,
+        "/ip4/127.0.0.1/tcp/4001".parse().unwrap(),
+        1_700_000_000,
+    );
+
+    let export_path = source.join("chat.json");
+    let exported = export(
+        &source_history,
+        &address_book,
+        &ExportFilter::default(),
+        fs::File::create(&export_path).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(exported, messages.len());
+
+    let target = data_dir();
+    let target_history = target.join(history::HISTORY_FILE);
+    let mut target_book = AddressBook::default();
+    let summary = import(&export_path, &target_history, &mut target_book).unwrap();
+    assert_eq!(
+        summary,
+        ImportSummary {
+            imported: 4,
+            duplicates: 0,
+            expired: 0,
+            peers: 1
+        }
+    );
+    assert_eq!(target_book.most_recent().len(), 1);
+
+    assert_eq!(
+        search(&source_history, "hello"),
+        search(&target_history, "hello")
+    );
+    assert_eq!(search(&target_history, "hello").len(), 3);
+
+    // Importing the same file again adds nothing.
+    let summary = import(&export_path, &target_history, &mut target_book).unwrap();
+    assert_eq!(summary.imported, 0);
+    assert_eq!(summary.duplicates, 4);
+    assert_eq!(history::load(&target_history).unwrap().len(), 4);
+}
+
+#[test]
+fn export_filters_by_room_and_date() {
+    let peer_id = peer();
+    let dir = data_dir();
+    let history_path = dir.join(history::HISTORY_FILE);
+    history::save(
+        &history_path,
+        &[
+            message(peer_id, "chat", "old", 1_000),
+            message(peer_id, "chat", "new", 2_000),
+            message(peer_id, "rust", "other room", 2_000),
+        ],
+    )
+    .unwrap();
+
+    let mut out = Vec::new();
+    let filter = ExportFilter {
+        room: Some("chat".to_string()),
+        since: Some(1_500),
+    };
+    let exported = export(&history_path, &AddressBook::default(), &filter, &mut out).unwrap();
+    assert_eq!(exported, 1);
+
+    let document: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(document["version"], 1);
+    assert_eq!(document["messages"][0]["message"], "new");
+}
+
+#[test]
+fn import_rejects_unknown_schema_versions() {
+    let dir = data_dir();
+    let export_path = dir.join("future.json");
+    fs::write(
+        &export_path,
+        r#"{"version": 99, "peers": [], "messages": [], "rooms": {}}"#,
+    )
+    .unwrap();
+
+    let error = import(
+        &export_path,
+        &dir.join(history::HISTORY_FILE),
+        &mut AddressBook::default(),
+    )
+    .unwrap_err();
+    assert!(
+        error.to_string().contains("schema version 99"),
+        "unexpected error: {}",
+        error
+    );
+}
+
+#[test]
+fn deleted_message_stays_deleted_after_syncing_from_a_peer_that_missed_the_delete() {
+    let peer_id = peer();
+    let original = message(peer_id, "chat", "oops", 1_700_000_000);
+
+    // Our history holds the tombstone; the other peer only ever saw the original.
+    let ours = data_dir();
+    let our_history = ours.join(history::HISTORY_FILE);
+    let mut tombstone = original.clone();
+    tombstone.deleted = true;
+    history::append(&our_history, [&original, &tombstone]).unwrap();
+
+    let theirs = data_dir();
+    let their_history = theirs.join(history::HISTORY_FILE);
+    history::append(&their_history, [&original]).unwrap();
+    let export_path = theirs.join("export.json");
+    export(
+        &their_history,
+        &AddressBook::default(),
+        &ExportFilter::default(),
+        fs::File::create(&export_path).unwrap(),
+    )
+    .unwrap();
+
+    let summary = import(&export_path, &our_history, &mut AddressBook::default()).unwrap();
+    assert_eq!(summary.imported, 0);
+    assert_eq!(summary.duplicates, 1);
+
+    // Restart: compact and reload the history as the node does on startup.
+    history::compact(&our_history, 1_700_000_100).unwrap();
+    let mut store = MessageStore::default();
+    for message in history::read(&our_history).unwrap() {
+        store.insert(message.unwrap());
+    }
+    // A live copy of the original arriving again is a duplicate.
+    assert!(!store.insert(original.clone()));
+
+    let stored = store.get(&original.id).unwrap();
+    assert!(stored.deleted);
+    assert_eq!(stored.display_text(), "[deleted]");
+    assert!(search(&our_history, "oops").is_empty());
+}
+
+#[test]
+fn expired_messages_are_not_synced() {
+    let peer_id = peer();
+    let now = libp2p_demo::message::unix_now();
+    let expired = ChatMessage {
+        expires_at: Some(now - 1),
+        ..message(peer_id, "chat", "already gone", 1_700_000_000)
+    };
+    let expiring = ChatMessage {
+        expires_at: Some(now + 3600),
+        ..message(peer_id, "chat", "still here", 1_700_000_100)
+    };
+    // Expired a minute after it was sent.
+    let outlived = ChatMessage {
+        ttl_secs: Some(60),
+        ..message(peer_id, "chat", "said in passing", 1_700_000_200)
+    };
+
+    let theirs = data_dir();
+    let their_history = theirs.join(history::HISTORY_FILE);
+    history::append(&their_history, [&expired, &expiring, &outlived]).unwrap();
+    let export_path = theirs.join("export.json");
+    let exported = export(
+        &their_history,
+        &AddressBook::default(),
+        &ExportFilter::default(),
+        fs::File::create(&export_path).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(exported, 1);
+
+    // An export written before the message expired still holds it.
+    let stale_export = theirs.join("stale.json");
+    fs::write(
+        &stale_export,
+        serde_json::json!({
+            "version": 1,
+            "exported_at": now - 10,
+            "peers": [],
+            "messages": [expired, expiring, outlived],
+        })
+        .to_string(),
+    )
+    .unwrap();
+
+    let our_history = data_dir().join(history::HISTORY_FILE);
+    let summary = import(&stale_export, &our_history, &mut AddressBook::default()).unwrap();
+    assert_eq!((summary.imported, summary.expired), (1, 2));
+    assert!(search(&our_history, "already gone").is_empty());
+    assert!(search(&our_history, "said in passing").is_empty());
+    assert_eq!(search(&our_history, "still here").len(), 1);
+}
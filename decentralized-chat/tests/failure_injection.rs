@@ -0,0 +1,310 @@
+use libp2p::{
+    request_response::{self, OutboundFailure, OutboundRequestId},
+    swarm::SwarmEvent,
+    PeerId, StreamProtocol,
+};
+use libp2p_demo::{
+    behaviour::{CustomBehaviourEvent, Request, Response},
+    config::{Config, ProtocolConfig},
+    envelope::{self, seal, Opened},
+    forward::ForwardStore,
+    message::{unix_now, ChatMessage, DirectRequest, DirectResponse},
+    testing::{connect, connect_sim, drive_until, SimPeer, SimReply, TestNode},
+};
+use std::{io, time::Duration};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A node that gives up on requests after a second, so timeouts are quick
+/// to hit.
+async fn impatient_node() -> TestNode {
+    TestNode::with_config(&Config {
+        protocol: ProtocolConfig {
+            request_timeout_secs: 1,
+            ..ProtocolConfig::default()
+        },
+        ..Config::default()
+    })
+    .await
+}
+
+fn send_message(node: &mut TestNode, peer: PeerId, message: &ChatMessage) -> OutboundRequestId {
+    node.swarm.behaviour_mut().request_response.send_request(
+        &peer,
+        Request {
+            data: seal(&DirectRequest::Message(message.clone())),
+        },
+    )
+}
+
+fn ack(message: &ChatMessage) -> SimReply {
+    SimReply::Respond(seal(&DirectResponse::Ack { id: message.id }))
+}
+
+/// Drives `nodes[0]` until the request `sent` is answered or fails.
+async fn outcome(
+    nodes: &mut [TestNode],
+    sent: OutboundRequestId,
+) -> Result<Response, OutboundFailure> {
+    drive_until(nodes, TIMEOUT, |_, event, _| match event {
+        SwarmEvent::Behaviour(CustomBehaviourEvent::RequestResponse(
+            request_response::Event::Message {
+                message:
+                    request_response::Message::Response {
+                        request_id,
+                        response,
+                    },
+                ..
+            },
+        )) if request_id == sent => Some(Ok(response)),
+        SwarmEvent::Behaviour(CustomBehaviourEvent::RequestResponse(
+            request_response::Event::OutboundFailure {
+                request_id, error, ..
+            },
+        )) if request_id == sent => Some(Err(error)),
+        _ => None,
+    })
+    .await
+}
+
+#[tokio::test]
+async fn a_peer_that_never_answers_times_out() {
+    let sim = SimPeer::spawn([SimReply::Silent]).await;
+    let mut nodes = [impatient_node().await];
+    connect_sim(&mut nodes, 0, &sim).await;
+
+    let message = ChatMessage::new(nodes[0].peer_id(), "anyone there?".to_string());
+    let sent = send_message(&mut nodes[0], sim.peer_id, &message);
+
+    assert!(matches!(
+        outcome(&mut nodes, sent).await,
+        Err(OutboundFailure::Timeout)
+    ));
+}
+
+#[tokio::test]
+async fn a_slow_answer_within_the_timeout_arrives() {
+    let mut nodes = [TestNode::new().await];
+    let message = ChatMessage::new(nodes[0].peer_id(), "take your time".to_string());
+    let sim = SimPeer::spawn([SimReply::Delay(
+        Duration::from_millis(300),
+        seal(&DirectResponse::Ack { id: message.id }),
+    )])
+    .await;
+    connect_sim(&mut nodes, 0, &sim).await;
+
+    let sent = send_message(&mut nodes[0], sim.peer_id, &message);
+    let response = outcome(&mut nodes, sent).await.unwrap();
+
+    assert!(matches!(
+        envelope::open(response.data),
+        Ok(Opened::Known(DirectResponse::Ack { id })) if id == message.id
+    ));
+}
+
+#[tokio::test]
+async fn a_slow_answer_past_the_timeout_is_a_timeout() {
+    let mut nodes = [impatient_node().await];
+    let message = ChatMessage::new(nodes[0].peer_id(), "too slow".to_string());
+    let sim = SimPeer::spawn([SimReply::Delay(
+        Duration::from_secs(3),
+        seal(&DirectResponse::Ack { id: message.id }),
+    )])
+    .await;
+    connect_sim(&mut nodes, 0, &sim).await;
+
+    let sent = send_message(&mut nodes[0], sim.peer_id, &message);
+
+    assert!(matches!(
+        outcome(&mut nodes, sent).await,
+        Err(OutboundFailure::Timeout)
+    ));
+}
+
+#[tokio::test]
+async fn a_connection_dropped_mid_request_fails_it() {
+    let sim = SimPeer::spawn([SimReply::Disconnect]).await;
+    let mut nodes = [TestNode::new().await];
+    connect_sim(&mut nodes, 0, &sim).await;
+
+    let message = ChatMessage::new(nodes[0].peer_id(), "cut off".to_string());
+    let sent = send_message(&mut nodes[0], sim.peer_id, &message);
+
+    assert!(matches!(
+        outcome(&mut nodes, sent).await,
+        Err(OutboundFailure::ConnectionClosed)
+    ));
+}
+
+#[tokio::test]
+async fn answering_a_requester_that_disconnected_is_not_fatal() {
+    let mut nodes = [TestNode::new().await, TestNode::new().await];
+    connect(&mut nodes, 0, 1).await;
+    let (requester, responder) = (nodes[0].peer_id(), nodes[1].peer_id());
+    let message = ChatMessage::new(requester, "bye".to_string());
+    send_message(&mut nodes[0], responder, &message);
+
+    let channel = drive_until(&mut nodes, TIMEOUT, |index, event, _| match event {
+        SwarmEvent::Behaviour(CustomBehaviourEvent::RequestResponse(
+            request_response::Event::Message {
+                message: request_response::Message::Request { channel, .. },
+                ..
+            },
+        )) if index == 1 => Some(channel),
+        _ => None,
+    })
+    .await;
+    nodes[0].swarm.disconnect_peer_id(responder).unwrap();
+    drive_until(&mut nodes, TIMEOUT, |index, event, _| match event {
+        SwarmEvent::ConnectionClosed { .. } if index == 1 => Some(()),
+        _ => None,
+    })
+    .await;
+
+    let response = Response {
+        data: seal(&DirectResponse::Ack { id: message.id }),
+    };
+    assert!(!nodes[1].swarm.behaviour_mut().respond(channel, response));
+}
+
+#[tokio::test]
+async fn a_response_that_is_not_json_is_an_io_error() {
+    let sim = SimPeer::spawn([SimReply::Corrupt]).await;
+    let mut nodes = [TestNode::new().await];
+    connect_sim(&mut nodes, 0, &sim).await;
+
+    let message = ChatMessage::new(nodes[0].peer_id(), "hello?".to_string());
+    let sent = send_message(&mut nodes[0], sim.peer_id, &message);
+
+    match outcome(&mut nodes, sent).await {
+        Err(OutboundFailure::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::InvalidData),
+        other => panic!("expected an io error, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn an_oversized_response_is_cut_off() {
+    let sim = SimPeer::spawn([SimReply::Oversized]).await;
+    let mut nodes = [TestNode::new().await];
+    connect_sim(&mut nodes, 0, &sim).await;
+
+    let message = ChatMessage::new(nodes[0].peer_id(), "say a lot".to_string());
+    let sent = send_message(&mut nodes[0], sim.peer_id, &message);
+
+    // Only the first 10 MiB are read, which ends mid-string.
+    match outcome(&mut nodes, sent).await {
+        Err(OutboundFailure::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof),
+        other => panic!("expected an io error, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn a_peer_without_the_chat_protocol_is_unsupported() {
+    let sim = SimPeer::speaking(
+        StreamProtocol::new("/other-app/1.0.0"),
+        [SimReply::Respond(serde_json::Value::Null)],
+    )
+    .await;
+    let mut nodes = [TestNode::new().await];
+    connect_sim(&mut nodes, 0, &sim).await;
+
+    let message = ChatMessage::new(nodes[0].peer_id(), "wrong number".to_string());
+    let sent = send_message(&mut nodes[0], sim.peer_id, &message);
+
+    assert!(matches!(
+        outcome(&mut nodes, sent).await,
+        Err(OutboundFailure::UnsupportedProtocols)
+    ));
+}
+
+#[tokio::test]
+async fn garbage_requests_are_dropped_without_an_answer() {
+    let mut sim =
+        SimPeer::speaking(StreamProtocol::new("/my-json-protocol"), [SimReply::Silent]).await;
+    let mut nodes = [TestNode::new().await];
+    connect_sim(&mut nodes, 0, &sim).await;
+
+    let node_id = nodes[0].peer_id();
+    sim.send_raw(node_id, b"\x00\x01garbage".to_vec());
+    let message = ChatMessage::new(sim.peer_id, "still friends?".to_string());
+    let request = Request {
+        data: seal(&DirectRequest::Message(message.clone())),
+    };
+    sim.send_raw(node_id, serde_json::to_vec(&request).unwrap());
+
+    // Only the well-formed request reaches the node; the garbage never shows
+    // up, not even as a failure.
+    drive_until(&mut nodes, TIMEOUT, |_, event, nodes| match event {
+        SwarmEvent::Behaviour(CustomBehaviourEvent::RequestResponse(
+            request_response::Event::Message {
+                message:
+                    request_response::Message::Request {
+                        request, channel, ..
+                    },
+                ..
+            },
+        )) => {
+            let Ok(Opened::Known(DirectRequest::Message(received))) = envelope::open(request.data)
+            else {
+                panic!("garbage was taken for a request");
+            };
+            let ack = seal(&DirectResponse::Ack { id: received.id });
+            nodes[0]
+                .swarm
+                .behaviour_mut()
+                .request_response
+                .send_response(channel, Response { data: ack })
+                .unwrap();
+            None
+        }
+        SwarmEvent::Behaviour(CustomBehaviourEvent::RequestResponse(
+            request_response::Event::ResponseSent { .. },
+        )) => Some(()),
+        SwarmEvent::Behaviour(CustomBehaviourEvent::RequestResponse(
+            request_response::Event::InboundFailure { error, .. },
+        )) => panic!("unexpected inbound failure: {}", error),
+        _ => None,
+    })
+    .await;
+
+    // The sender of the garbage sees its stream closed with nothing in it.
+    let mut outcomes = [sim.outcome().await.unwrap(), sim.outcome().await.unwrap()];
+    outcomes.sort_by_key(Vec::len);
+    assert!(outcomes[0].is_empty());
+    let response: Response = serde_json::from_slice(&outcomes[1]).unwrap();
+    assert!(matches!(
+        envelope::open(response.data),
+        Ok(Opened::Known(DirectResponse::Ack { id })) if id == message.id
+    ));
+}
+
+#[tokio::test]
+async fn an_undelivered_forward_is_kept_and_retried_after_reconnecting() {
+    let mut nodes = [TestNode::new().await];
+    let message = ChatMessage::new(nodes[0].peer_id(), "second time lucky".to_string());
+    let sim = SimPeer::spawn([SimReply::Disconnect, ack(&message)]).await;
+    connect_sim(&mut nodes, 0, &sim).await;
+    let mut forward_store = ForwardStore::new(10, 10, 1024, 3600);
+
+    let sent = send_message(&mut nodes[0], sim.peer_id, &message);
+    assert!(matches!(
+        outcome(&mut nodes, sent).await,
+        Err(OutboundFailure::ConnectionClosed)
+    ));
+    // As the node does when a delivery fails.
+    forward_store
+        .store(sim.peer_id, message.clone(), unix_now() + 60, unix_now())
+        .unwrap();
+
+    connect_sim(&mut nodes, 0, &sim).await;
+    let waiting = forward_store.take(&sim.peer_id, unix_now());
+    assert_eq!(waiting.len(), 1);
+    let sent = send_message(&mut nodes[0], sim.peer_id, &waiting[0].message);
+    let response = outcome(&mut nodes, sent).await.unwrap();
+
+    assert!(matches!(
+        envelope::open(response.data),
+        Ok(Opened::Known(DirectResponse::Ack { id })) if id == message.id
+    ));
+    assert_eq!(forward_store.waiting(&sim.peer_id), 0);
+}
@@ -0,0 +1,81 @@
+use libp2p_demo::{
+    testing::peer,
+    {config::FilterAction, filter::ContentFilter, message::ChatMessage},
+};
+
+fn filter(words: &[&str]) -> ContentFilter {
+    ContentFilter::new(words, FilterAction::Mask)
+}
+
+fn message(text: &str) -> ChatMessage {
+    let peer = peer();
+    ChatMessage::new(peer, text.to_string())
+}
+
+#[test]
+fn blocked_words_are_masked_ignoring_case() {
+    let filter = filter(&["darn", "SPAM"]);
+    assert_eq!(
+        filter.filter_content("Darn it, more spam. DARN!"),
+        "**** it, more ****. ****!"
+    );
+    assert_eq!(filter.filter_content("nothing to see"), "nothing to see");
+    assert!(filter.matches("Spam"));
+    assert!(!filter.matches("nothing to see"));
+}
+
+#[test]
+fn only_whole_words_match() {
+    let filter = filter(&["ass", "spam"]);
+    assert_eq!(
+        filter.filter_content("class, assess, spammer, antispam"),
+        "class, assess, spammer, antispam"
+    );
+    assert_eq!(
+        filter.filter_content("ass-kicking (spam) spam's"),
+        "***-kicking (****) ****'s"
+    );
+    assert!(!filter.matches("compass"));
+}
+
+#[test]
+fn masks_cover_every_character_of_non_ascii_words() {
+    let filter = filter(&["Scheiße"]);
+    assert_eq!(
+        filter.filter_content("so eine SCHEIßE."),
+        "so eine *******."
+    );
+    assert_eq!(filter.filter_content("Scheißegal"), "Scheißegal");
+}
+
+#[test]
+fn edits_of_a_displayed_copy_are_masked_too() {
+    let filter = filter(&["spam"]);
+    let mut message = ChatMessage {
+        edits: vec!["buy spam".to_string()],
+        ..message("hello")
+    };
+    filter.filter_message(&mut message);
+    assert_eq!(message.message, "hello");
+    assert_eq!(message.display_text(), "buy **** (edited)");
+}
+
+#[test]
+fn drop_mode_drops_messages_with_blocked_words() {
+    let spam = message("cheap SPAM here");
+    let clean = message("lunch?");
+
+    let dropping = ContentFilter::new(["spam"], FilterAction::Drop);
+    assert!(dropping.drops(&spam));
+    assert!(!dropping.drops(&clean));
+
+    let masking = filter(&["spam"]);
+    assert!(!masking.drops(&spam));
+}
+
+#[test]
+fn an_empty_list_filters_nothing() {
+    let filter = ContentFilter::default();
+    assert_eq!(filter.filter_content("anything at all"), "anything at all");
+    assert!(!filter.drops(&message("anything at all")));
+}
@@ -0,0 +1,49 @@
+use libp2p::identity;
+use libp2p_demo::{
+    address_book::AddressBook,
+    fingerprint::{embedded_key, fingerprint},
+    testing::peer,
+};
+use std::fs;
+
+#[test]
+fn fingerprints_are_grouped_base32_and_differ_per_key() {
+    let key = identity::Keypair::generate_ed25519().public();
+    let shown = fingerprint(&key);
+
+    let groups: Vec<&str> = shown.split(' ').collect();
+    assert_eq!(groups.len(), 8, "{}", shown);
+    assert!(groups.iter().all(|group| group.len() == 4
+        && group
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || ('2'..='7').contains(&c))));
+
+    assert_eq!(fingerprint(&key), shown);
+    let other = identity::Keypair::generate_ed25519().public();
+    assert_ne!(fingerprint(&other), shown);
+}
+
+#[test]
+fn ed25519_peer_ids_embed_their_key() {
+    let key = identity::Keypair::generate_ed25519().public();
+    assert_eq!(embedded_key(&key.to_peer_id()), Some(key));
+}
+
+#[test]
+fn verified_peers_survive_pruning_and_restarts() {
+    let dir = std::env::temp_dir().join(format!("chat-fingerprint-{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("address_book.json");
+
+    let (alice, bob) = (peer(), peer());
+    let mut book = AddressBook::default();
+    book.record(alice, "/ip4/127.0.0.1/tcp/4001".parse().unwrap(), 0);
+    book.verify(alice, "ABCD EFGH".to_string());
+    book.prune(60, 1_000, |_| false);
+    assert!(book.most_recent().is_empty());
+    book.save(&path).unwrap();
+
+    let book = AddressBook::load(&path).unwrap();
+    assert_eq!(book.verified(&alice), Some("ABCD EFGH"));
+    assert_eq!(book.verified(&bob), None);
+}
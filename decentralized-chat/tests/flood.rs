@@ -0,0 +1,72 @@
+use libp2p_demo::flood::{Admission, FloodGuard, Priority};
+use std::time::{Duration, Instant};
+
+fn guard() -> FloodGuard {
+    FloodGuard::new(100.0, 50.0, Duration::from_secs(5))
+}
+
+/// Feeds the guard `per_sec` messages a second for `secs` seconds, every
+/// tenth of them a direct message, and returns how many of each got through.
+fn storm(guard: &mut FloodGuard, start: Instant, per_sec: u32, secs: u32) -> (u32, u32) {
+    let (mut direct, mut broadcast) = (0, 0);
+    for i in 0..per_sec * secs {
+        let now = start + Duration::from_secs_f64(f64::from(i) / f64::from(per_sec));
+        let priority = if i % 10 == 0 {
+            Priority::Direct
+        } else {
+            Priority::Broadcast
+        };
+        if guard.admit(priority, now) == Admission::Accept {
+            match priority {
+                Priority::Direct => direct += 1,
+                Priority::Broadcast => broadcast += 1,
+            }
+        }
+    }
+    (direct, broadcast)
+}
+
+#[test]
+fn normal_traffic_is_left_alone() {
+    let mut guard = guard();
+    let (direct, broadcast) = storm(&mut guard, Instant::now(), 80, 30);
+    assert_eq!((direct, broadcast), (240, 2160));
+    assert!(!guard.shedding());
+}
+
+#[test]
+fn a_flood_sheds_broadcasts_but_not_direct_messages() {
+    let mut guard = guard();
+    let start = Instant::now();
+    let (direct, broadcast) = storm(&mut guard, start, 1000, 10);
+
+    assert!(guard.shedding());
+    assert_eq!(direct, 1000, "every direct message is handled");
+    // Only what arrived before the average went over the threshold.
+    assert!(broadcast < 600, "{} broadcasts got through", broadcast);
+}
+
+#[test]
+fn shedding_stops_once_the_flood_subsides() {
+    let mut guard = guard();
+    let start = Instant::now();
+    storm(&mut guard, start, 1000, 2);
+    assert!(guard.shedding());
+
+    // Still over the recovery rate a second later.
+    assert!(!guard.recover(start + Duration::from_secs(3)));
+    assert!(guard.shedding());
+    assert_eq!(
+        guard.admit(Priority::Broadcast, start + Duration::from_secs(3)),
+        Admission::Shed
+    );
+
+    // Quiet for the whole window.
+    assert!(guard.recover(start + Duration::from_secs(8)));
+    assert!(!guard.shedding());
+    assert!(!guard.recover(start + Duration::from_secs(9)));
+    assert_eq!(
+        guard.admit(Priority::Broadcast, start + Duration::from_secs(9)),
+        Admission::Accept
+    );
+}
@@ -0,0 +1,146 @@
+use libp2p::PeerId;
+use libp2p_demo::{
+    forward::{ForwardStore, StoreError},
+    message::{ChatMessage, DirectRequest, DirectResponse},
+    testing::peer,
+};
+
+fn message(author: PeerId, text: &str) -> ChatMessage {
+    ChatMessage::new(author, text.to_string())
+}
+
+#[test]
+fn held_messages_are_delivered_once_in_order() {
+    let mut store = ForwardStore::new(32, 32, 1024, 3600);
+    let (author, target, other) = (peer(), peer(), peer());
+    let first = message(author, "first");
+    let second = message(author, "second");
+
+    store.store(target, first.clone(), 2000, 1000).unwrap();
+    store.store(target, second.clone(), 2000, 1000).unwrap();
+    // Asking twice for the same message doesn't hold it twice.
+    store.store(target, first.clone(), 2000, 1000).unwrap();
+    assert_eq!(store.waiting(&target), 2);
+    assert!(store.take(&other, 1000).is_empty());
+
+    let delivered: Vec<_> = store
+        .take(&target, 1500)
+        .into_iter()
+        .map(|stored| stored.message.id)
+        .collect();
+    assert_eq!(delivered, [first.id, second.id]);
+    assert!(store.take(&target, 1500).is_empty());
+}
+
+#[test]
+fn expired_messages_are_dropped() {
+    let mut store = ForwardStore::new(32, 32, 1024, 100);
+    let (author, target) = (peer(), peer());
+
+    assert_eq!(
+        store.store(target, message(author, "late"), 1000, 1000),
+        Err(StoreError::Expired)
+    );
+
+    // The sender may not ask for longer than the forwarder's own limit.
+    store
+        .store(target, message(author, "long"), 5000, 1000)
+        .unwrap();
+    assert!(store.take(&target, 1100).is_empty());
+
+    store
+        .store(target, message(author, "short"), 1050, 1000)
+        .unwrap();
+    store.expire(1050);
+    assert_eq!(store.waiting(&target), 0);
+}
+
+#[test]
+fn storage_per_target_is_capped() {
+    let mut store = ForwardStore::new(2, 32, 1024, 3600);
+    let (author, target, other) = (peer(), peer(), peer());
+
+    store
+        .store(target, message(author, "1"), 2000, 1000)
+        .unwrap();
+    store
+        .store(target, message(author, "2"), 2000, 1000)
+        .unwrap();
+    assert_eq!(
+        store.store(target, message(author, "3"), 2000, 1000),
+        Err(StoreError::Full { limit: 2 })
+    );
+    store
+        .store(other, message(author, "3"), 2000, 1000)
+        .unwrap();
+}
+
+#[test]
+fn storage_per_sender_and_in_all_is_capped() {
+    let mut store = ForwardStore::new(32, 2, 3, 3600);
+    let (author, other_author) = (peer(), peer());
+
+    store
+        .store(peer(), message(author, "1"), 2000, 1000)
+        .unwrap();
+    store
+        .store(peer(), message(author, "2"), 2000, 1000)
+        .unwrap();
+    // Spreading messages over more targets doesn't get around the cap.
+    assert_eq!(
+        store.store(peer(), message(author, "3"), 2000, 1000),
+        Err(StoreError::SenderFull { limit: 2 })
+    );
+
+    store
+        .store(peer(), message(other_author, "3"), 2000, 1000)
+        .unwrap();
+    assert_eq!(
+        store.store(peer(), message(other_author, "4"), 2000, 1000),
+        Err(StoreError::StoreFull { limit: 3 })
+    );
+}
+
+#[test]
+fn a_huge_expiry_does_not_overflow() {
+    let mut store = ForwardStore::new(32, 32, 1024, u64::MAX);
+    let (author, target) = (peer(), peer());
+
+    store
+        .store(target, message(author, "forever"), u64::MAX, 1000)
+        .unwrap();
+    assert_eq!(store.waiting(&target), 1);
+}
+
+#[test]
+fn store_forward_round_trips_as_json() {
+    let (author, target) = (peer(), peer());
+    let held = message(author, "while you were away");
+
+    let request = serde_json::to_value(DirectRequest::StoreForward {
+        target,
+        message: held.clone(),
+        expires_at: 2000,
+    })
+    .unwrap();
+    assert_eq!(request["kind"], "store_forward");
+    let DirectRequest::StoreForward {
+        target: parsed_target,
+        message: parsed,
+        expires_at,
+    } = serde_json::from_value(request).unwrap()
+    else {
+        panic!("expected a store_forward request");
+    };
+    assert_eq!(
+        (parsed_target, parsed.id, expires_at),
+        (target, held.id, 2000)
+    );
+
+    let response = serde_json::to_value(DirectResponse::Refused {
+        id: held.id,
+        reason: StoreError::NotForwarding,
+    })
+    .unwrap();
+    assert_eq!(response["reason"], "not_forwarding");
+}
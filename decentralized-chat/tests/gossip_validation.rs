@@ -0,0 +1,140 @@
+use libp2p::{
+    core::{transport::MemoryTransport, upgrade::Version},
+    futures::StreamExt,
+    gossipsub, identity, noise,
+    swarm::SwarmEvent,
+    yamux, Swarm, SwarmBuilder, Transport,
+};
+use libp2p_demo::{
+    behaviour::CustomBehaviourEvent,
+    config::{Config, GossipsubConfig, Validation},
+    envelope::seal,
+    message::{ChatMessage, GossipMessage},
+    testing::TestNode,
+};
+use std::{error::Error, time::Duration};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long the strict node is given to wrongly take in the message after
+/// the permissive one got it.
+const GRACE: Duration = Duration::from_millis(500);
+
+/// A bare gossipsub node that publishes without an author, sequence number
+/// or signature, as peers publishing anonymously do.
+fn anonymous_publisher() -> Swarm<gossipsub::Behaviour> {
+    SwarmBuilder::with_new_identity()
+        .with_tokio()
+        .with_other_transport(|key| {
+            Ok::<_, Box<dyn Error + Send + Sync>>(
+                MemoryTransport::default()
+                    .upgrade(Version::V1)
+                    .authenticate(noise::Config::new(key)?)
+                    .multiplex(yamux::Config::default()),
+            )
+        })
+        .unwrap()
+        .with_behaviour(|_| {
+            let config = gossipsub::ConfigBuilder::default()
+                .validation_mode(gossipsub::ValidationMode::Anonymous)
+                .build()
+                .unwrap();
+            gossipsub::Behaviour::new(gossipsub::MessageAuthenticity::Anonymous, config).unwrap()
+        })
+        .unwrap()
+        .with_swarm_config(|cfg| cfg.with_idle_connection_timeout(Duration::from_secs(10)))
+        .build()
+}
+
+#[tokio::test]
+async fn unsigned_gossip_is_rejected_in_strict_mode() {
+    let topic = gossipsub::IdentTopic::new("chat");
+    let mut strict = TestNode::new().await;
+    let mut permissive = TestNode::with_config(&Config {
+        gossipsub: GossipsubConfig {
+            validation: Validation::Permissive,
+            ..GossipsubConfig::default()
+        },
+        ..Config::default()
+    })
+    .await;
+    let mut publisher = anonymous_publisher();
+    for node in [&mut strict, &mut permissive] {
+        node.swarm
+            .behaviour_mut()
+            .gossipsub
+            .subscribe(&topic)
+            .unwrap();
+        publisher.dial(node.address.clone()).unwrap();
+    }
+    publisher.behaviour_mut().subscribe(&topic).unwrap();
+
+    let author = identity::Keypair::generate_ed25519();
+    let mut sent = ChatMessage::new(author.public().to_peer_id(), "who am I".to_string());
+    sent.sign(&author).unwrap();
+    let payload = serde_json::to_vec(&seal(&GossipMessage::Chat(Box::new(sent)))).unwrap();
+
+    let (mut subscribed, mut strict_received) = (0, false);
+    let delivered = async {
+        loop {
+            tokio::select! {
+                event = publisher.select_next_some() => {
+                    if let SwarmEvent::Behaviour(gossipsub::Event::Subscribed { .. }) = event {
+                        subscribed += 1;
+                        // Published once both receivers are known to be in
+                        // the room, so each is sent the message.
+                        if subscribed == 2 {
+                            publisher
+                                .behaviour_mut()
+                                .publish(topic.clone(), payload.clone())
+                                .unwrap();
+                        }
+                    }
+                }
+                event = strict.swarm.select_next_some() => {
+                    if let SwarmEvent::Behaviour(CustomBehaviourEvent::Gossipsub(
+                        gossipsub::Event::Message { .. },
+                    )) = event
+                    {
+                        strict_received = true;
+                    }
+                }
+                event = permissive.swarm.select_next_some() => {
+                    if let SwarmEvent::Behaviour(CustomBehaviourEvent::Gossipsub(
+                        gossipsub::Event::Message { message, .. },
+                    )) = event
+                    {
+                        return message;
+                    }
+                }
+            }
+        }
+    };
+    let message = tokio::time::timeout(TIMEOUT, delivered)
+        .await
+        .expect("the permissive node takes in the unsigned message");
+    assert_eq!(message.source, None);
+    assert_eq!(message.sequence_number, None);
+
+    let _ = tokio::time::timeout(GRACE, async {
+        loop {
+            tokio::select! {
+                _ = publisher.select_next_some() => {}
+                _ = permissive.swarm.select_next_some() => {}
+                event = strict.swarm.select_next_some() => {
+                    if let SwarmEvent::Behaviour(CustomBehaviourEvent::Gossipsub(
+                        gossipsub::Event::Message { .. },
+                    )) = event
+                    {
+                        strict_received = true;
+                    }
+                }
+            }
+        }
+    })
+    .await;
+    assert!(
+        !strict_received,
+        "the strict node took in an unsigned message"
+    );
+}
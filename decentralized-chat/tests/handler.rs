@@ -0,0 +1,392 @@
+use libp2p::{gossipsub, identity, mdns, Multiaddr, PeerId};
+use libp2p_demo::{
+    ban::Violation,
+    behaviour::CustomBehaviourEvent,
+    envelope::seal,
+    event::ChatEvent,
+    handler::{check_direct, Action},
+    invite::{encrypt_for_room, room_key},
+    message::{ChatMessage, DirectRequest, GossipMessage},
+    testing::peer,
+    testing::TestHarness,
+};
+
+/// The event for `gossip_message`, published by `author` in `room`.
+fn gossip(author: PeerId, room: &str, data: Vec<u8>) -> CustomBehaviourEvent {
+    CustomBehaviourEvent::Gossipsub(gossipsub::Event::Message {
+        propagation_source: author,
+        message_id: gossipsub::MessageId::new(&data),
+        message: gossipsub::Message {
+            source: Some(author),
+            data,
+            sequence_number: None,
+            topic: gossipsub::IdentTopic::new(room).hash(),
+        },
+    })
+}
+
+fn sealed(gossip_message: &GossipMessage) -> Vec<u8> {
+    seal(gossip_message).to_string().into_bytes()
+}
+
+/// A chat message by a new peer for `room`, signed by it unless `signed` is
+/// false.
+fn chat(room: &str, text: &str, nickname: Option<&str>, signed: bool) -> (PeerId, GossipMessage) {
+    let keypair = identity::Keypair::generate_ed25519();
+    let author = keypair.public().to_peer_id();
+    let mut chat_message = ChatMessage {
+        nickname: nickname.map(str::to_string),
+        room: Some(room.to_string()),
+        ..ChatMessage::new(author, text.to_string())
+    };
+    if signed {
+        chat_message.sign(&keypair).unwrap();
+    }
+    (author, GossipMessage::Chat(Box::new(chat_message)))
+}
+
+#[test]
+fn chat_messages_are_stored_and_shown() {
+    let mut harness = TestHarness::new();
+    let mut events = harness.events.subscribe();
+    let (alice, message) = chat("rust", "hello", Some("alice"), true);
+
+    harness.handle(gossip(alice, "rust", sealed(&message)));
+    // A message relayed to us twice is only taken in once.
+    harness.handle(gossip(alice, "rust", sealed(&message)));
+
+    let stored = harness.messages.messages();
+    assert_eq!(stored.len(), 1);
+    assert_eq!(stored[0].message, "hello");
+    assert_eq!(stored[0].room.as_deref(), Some("rust"));
+    assert_eq!(harness.nicknames.get(&alice), Some("alice"));
+    assert_eq!(harness.shown.len(), 1);
+    assert!(harness.shown[0].contains("hello"));
+    assert!(matches!(
+        events.try_recv(),
+        Ok(ChatEvent::MessageReceived { message }) if message.message == "hello"
+    ));
+    assert!(harness.actions.is_empty());
+}
+
+#[test]
+fn bad_gossip_is_dropped_and_counted_against_its_author() {
+    let mut harness = TestHarness::new();
+    let (mallory, unsigned) = chat("rust", "trust me", None, false);
+
+    harness.handle(gossip(mallory, "rust", sealed(&unsigned)));
+    harness.handle(gossip(mallory, "rust", b"{not json".to_vec()));
+
+    assert!(harness.messages.messages().is_empty());
+    assert!(harness.shown.is_empty());
+    let violations: Vec<Violation> = harness
+        .actions
+        .iter()
+        .map(|action| match action {
+            Action::ReportViolation { peer, violation } if *peer == mallory => *violation,
+            other => panic!("unexpected action {:?}", other),
+        })
+        .collect();
+    assert_eq!(
+        violations,
+        [Violation::BadSignature, Violation::MalformedGossip]
+    );
+}
+
+#[test]
+fn gossip_is_only_taken_in_from_its_author_in_its_room() {
+    let mut harness = TestHarness::new();
+    let (alice, message) = chat("rust", "hello", None, true);
+    let mallory = peer();
+
+    // Republished by someone else, or in a room it wasn't signed for.
+    harness.handle(gossip(mallory, "rust", sealed(&message)));
+    harness.handle(gossip(alice, "go", sealed(&message)));
+
+    assert!(harness.messages.messages().is_empty());
+    assert!(matches!(
+        harness.actions[..],
+        [
+            Action::ReportViolation { peer: first, violation: Violation::Misattributed },
+            Action::ReportViolation { peer: second, violation: Violation::Misattributed },
+        ] if first == mallory && second == alice
+    ));
+}
+
+#[test]
+fn direct_messages_are_only_taken_from_their_author_for_us() {
+    let mut harness = TestHarness::new();
+    let us = harness.keypair.public().to_peer_id();
+    let (alice, forwarder) = (peer(), peer());
+    let message = |recipient| ChatMessage {
+        recipient,
+        ..ChatMessage::new(alice, "hello".to_string())
+    };
+
+    assert!(check_direct(
+        &mut harness,
+        alice,
+        &message(Some(us)),
+        us,
+        false
+    ));
+    // Peers that predate recipients name no one.
+    assert!(check_direct(&mut harness, alice, &message(None), us, false));
+    assert!(check_direct(
+        &mut harness,
+        forwarder,
+        &message(Some(us)),
+        us,
+        true
+    ));
+    assert!(harness.actions.is_empty());
+
+    assert!(!check_direct(
+        &mut harness,
+        forwarder,
+        &message(Some(us)),
+        us,
+        false
+    ));
+    assert!(!check_direct(
+        &mut harness,
+        alice,
+        &message(Some(peer())),
+        us,
+        false
+    ));
+    assert!(!check_direct(
+        &mut harness,
+        forwarder,
+        &message(Some(peer())),
+        us,
+        true
+    ));
+    assert_eq!(harness.actions.len(), 3);
+}
+
+#[test]
+fn gossip_is_only_relayed_once_it_checks_out() {
+    let mut harness = TestHarness::new();
+    harness.validates_gossip = true;
+    let (alice, signed) = chat("rust", "hello", None, true);
+    let (mallory, unsigned) = chat("rust", "trust me", None, false);
+
+    harness.handle(gossip(alice, "rust", sealed(&signed)));
+    harness.handle(gossip(mallory, "rust", sealed(&unsigned)));
+    harness.handle(gossip(mallory, "rust", b"{not json".to_vec()));
+
+    let reports: Vec<(PeerId, String)> = harness
+        .actions
+        .iter()
+        .filter_map(|action| match action {
+            Action::ReportValidation {
+                propagation_source,
+                acceptance,
+                ..
+            } => Some((*propagation_source, format!("{:?}", acceptance))),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        reports,
+        [
+            (alice, "Accept".to_string()),
+            (mallory, "Reject".to_string()),
+            (mallory, "Reject".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn only_gossip_encrypted_with_a_private_rooms_key_is_taken_in() {
+    let mut harness = TestHarness::new();
+    harness.validates_gossip = true;
+    harness.room_keys.insert("plans", [7; 32]);
+    let key = room_key("plans", &[7; 32]);
+    let (alice, member) = chat("plans", "in on it", None, true);
+    let (mallory, outsider) = chat("plans", "let me in", None, true);
+    let (eve, guesser) = chat("plans", "is this it", None, true);
+
+    harness.handle(gossip(
+        alice,
+        "plans",
+        encrypt_for_room(&key, &sealed(&member)),
+    ));
+    harness.handle(gossip(mallory, "plans", sealed(&outsider)));
+    harness.handle(gossip(
+        eve,
+        "plans",
+        encrypt_for_room(&room_key("plans", &[8; 32]), &sealed(&guesser)),
+    ));
+
+    let stored = harness.messages.messages();
+    assert_eq!(stored.len(), 1);
+    assert_eq!(stored[0].message, "in on it");
+    let ignored = harness
+        .actions
+        .iter()
+        .filter(|action| {
+            matches!(
+                action,
+                Action::ReportValidation {
+                    acceptance: gossipsub::MessageAcceptance::Ignore,
+                    ..
+                }
+            )
+        })
+        .count();
+    assert_eq!(ignored, 2);
+}
+
+#[test]
+fn announced_nicknames_and_profiles_are_taken_in() {
+    let mut harness = TestHarness::new();
+    let bob = peer();
+
+    let rename = GossipMessage::Nickname {
+        name: "bob".to_string(),
+    };
+    harness.handle(gossip(bob, "rust", sealed(&rename)));
+    harness.handle(gossip(bob, "rust", sealed(&GossipMessage::ProfileUpdated)));
+
+    assert_eq!(harness.nicknames.get(&bob), Some("bob"));
+    assert!(matches!(
+        harness.actions[..],
+        [Action::RefreshProfile { peer }] if peer == bob
+    ));
+}
+
+#[test]
+fn discovered_peers_are_remembered_and_greeted() {
+    let mut harness = TestHarness::new();
+    let (carol, dave) = (peer(), peer());
+    let address: Multiaddr = "/ip4/192.168.1.7/tcp/4001".parse().unwrap();
+
+    harness.handle(CustomBehaviourEvent::Mdns(mdns::Event::Discovered(vec![(
+        carol,
+        address.clone(),
+    )])));
+    // With enough peers connected, new ones are left to the mesh.
+    harness.connected_peers = harness.explicit_below;
+    harness.handle(CustomBehaviourEvent::Mdns(mdns::Event::Discovered(vec![(
+        dave,
+        address.clone(),
+    )])));
+
+    assert_eq!(
+        harness.address_book.get(&carol).unwrap().addresses,
+        std::slice::from_ref(&address)
+    );
+    assert!(harness.address_book.get(&dave).is_some());
+    let actions = &harness.actions;
+    assert_eq!(actions.len(), 5, "{:?}", actions);
+    assert!(
+        matches!(&actions[0], Action::AddAddress { peer, address: at } if *peer == carol && *at == address)
+    );
+    assert!(matches!(actions[1], Action::AddExplicitPeer { peer } if peer == carol));
+    let Action::SendRequest { peer, request } = &actions[2] else {
+        panic!("expected a greeting, got {:?}", actions[2]);
+    };
+    let DirectRequest::Greeting(greeting) = request.as_ref() else {
+        panic!("expected a greeting, got {:?}", request);
+    };
+    assert_eq!(*peer, carol);
+    assert!(greeting.verify_signature().is_ok());
+    assert_eq!(greeting.peer_id, harness.keypair.public().to_peer_id());
+    assert!(matches!(actions[3], Action::AddAddress { peer, .. } if peer == dave));
+    assert!(matches!(actions[4], Action::SendRequest { peer, .. } if peer == dave));
+}
+
+#[test]
+fn a_peer_found_at_several_addresses_is_greeted_once() {
+    let mut harness = TestHarness::new();
+    let carol = peer();
+    let addresses: Vec<Multiaddr> = ["/ip4/192.168.1.7/tcp/4001", "/ip6/fe80::7/tcp/4001"]
+        .iter()
+        .map(|address| address.parse().unwrap())
+        .collect();
+
+    harness.handle(CustomBehaviourEvent::Mdns(mdns::Event::Discovered(
+        addresses
+            .iter()
+            .map(|address| (carol, address.clone()))
+            .collect(),
+    )));
+
+    assert_eq!(
+        harness.address_book.get(&carol).unwrap().addresses,
+        addresses
+    );
+    let greetings = harness
+        .actions
+        .iter()
+        .filter(|action| matches!(action, Action::SendRequest { .. }))
+        .count();
+    assert_eq!(greetings, 1);
+}
+
+#[test]
+fn only_so_many_discovered_peers_are_made_explicit() {
+    let mut harness = TestHarness::new();
+    harness.explicit_below = 2;
+    let address: Multiaddr = "/ip4/192.168.1.7/tcp/4001".parse().unwrap();
+    let discovered = (0..4).map(|_| (peer(), address.clone())).collect();
+
+    harness.handle(CustomBehaviourEvent::Mdns(mdns::Event::Discovered(
+        discovered,
+    )));
+
+    let explicit = harness
+        .actions
+        .iter()
+        .filter(|action| matches!(action, Action::AddExplicitPeer { .. }))
+        .count();
+    assert_eq!(explicit, 2);
+}
+
+#[test]
+fn room_members_come_and_go() {
+    let mut harness = TestHarness::new();
+    let mut events = harness.events.subscribe();
+    harness.members.join("rust");
+    let (erin, frank) = (peer(), peer());
+    let rust = gossipsub::IdentTopic::new("rust").hash();
+
+    harness.handle(CustomBehaviourEvent::Gossipsub(
+        gossipsub::Event::Subscribed {
+            peer_id: erin,
+            topic: rust.clone(),
+        },
+    ));
+    let presence = GossipMessage::Presence {
+        rooms: vec!["rust".to_string(), "go".to_string()],
+    };
+    harness.handle(gossip(frank, "rust", sealed(&presence)));
+    harness.handle(gossip(frank, "rust", sealed(&presence)));
+    harness.handle(gossip(frank, "rust", sealed(&GossipMessage::Left)));
+    harness.handle(CustomBehaviourEvent::Gossipsub(
+        gossipsub::Event::Unsubscribed {
+            peer_id: erin,
+            topic: rust,
+        },
+    ));
+
+    let mut changes = Vec::new();
+    while let Ok(event) = events.try_recv() {
+        changes.push(event);
+    }
+    let joined = |peer| ChatEvent::MemberJoined {
+        room: "rust".to_string(),
+        peer,
+    };
+    let left = |peer| ChatEvent::MemberLeft {
+        room: "rust".to_string(),
+        peer,
+    };
+    assert_eq!(
+        changes,
+        [joined(erin), joined(frank), left(frank), left(erin)]
+    );
+    assert!(harness.actions.is_empty());
+}
@@ -0,0 +1,306 @@
+use libp2p::futures::StreamExt;
+use libp2p_demo::{
+    command::{Command, Reply},
+    handle::ChatHandle,
+    history::{self, HistoryFilter},
+    message::ChatMessage,
+    search::SearchQuery,
+    testing::peer,
+};
+use std::{collections::HashSet, path::PathBuf};
+
+fn history_path() -> PathBuf {
+    std::env::temp_dir().join(format!("chat-history-{}.jsonl", uuid::Uuid::new_v4()))
+}
+
+#[test]
+fn compaction_keeps_the_latest_copy_in_first_seen_order() {
+    let peer_id = peer();
+    let path = history_path();
+    let mut first = ChatMessage::new(peer_id, "first".to_string());
+    let second = ChatMessage::new(peer_id, "second".to_string());
+    let expired = ChatMessage {
+        expires_at: Some(10),
+        ..ChatMessage::new(peer_id, "gone".to_string())
+    };
+    history::append(&path, [&first, &second, &expired]).unwrap();
+    first.edits.push("first, edited".to_string());
+    history::append(&path, [&first]).unwrap();
+
+    assert_eq!(
+        history::find(&path, &first.id).unwrap().unwrap().text(),
+        "first, edited"
+    );
+
+    history::compact(&path, 20).unwrap();
+    let texts: Vec<String> = history::load(&path)
+        .unwrap()
+        .iter()
+        .map(|message| message.text().to_string())
+        .collect();
+    assert_eq!(texts, ["first, edited", "second"]);
+}
+
+#[test]
+fn clearing_removes_a_room_or_everything_from_the_file() {
+    let peer_id = peer();
+    let path = history_path();
+    let in_room = |room: &str, text: &str| ChatMessage {
+        room: Some(room.to_string()),
+        ..ChatMessage::new(peer_id, text.to_string())
+    };
+    let mut chat = in_room("chat", "in chat");
+    let rust = in_room("rust", "in rust");
+    let direct = ChatMessage::new(peer_id, "direct".to_string());
+    history::append(&path, [&chat, &rust, &direct]).unwrap();
+    chat.edits.push("in chat, edited".to_string());
+    history::append(&path, [&chat]).unwrap();
+
+    // Both copies of the edited message go, counted once.
+    assert_eq!(history::clear(&path, Some("chat")).unwrap(), 1);
+    let texts: Vec<String> = history::load(&path)
+        .unwrap()
+        .iter()
+        .map(|message| message.text().to_string())
+        .collect();
+    assert_eq!(texts, ["in rust", "direct"]);
+
+    assert_eq!(history::clear(&path, None).unwrap(), 2);
+    assert!(history::load(&path).unwrap().is_empty());
+    assert_eq!(history::clear(&history_path(), None).unwrap(), 0);
+}
+
+#[test]
+fn removing_drops_every_copy_of_the_given_messages() {
+    let path = history_path();
+    let mut ephemeral = ChatMessage {
+        ttl_secs: Some(60),
+        ..ChatMessage::new(peer(), "gone soon".to_string())
+    };
+    let lasting = ChatMessage::new(peer(), "here to stay".to_string());
+    history::append(&path, [&ephemeral, &lasting]).unwrap();
+    ephemeral.edits.push("gone soon, edited".to_string());
+    history::append(&path, [&ephemeral]).unwrap();
+
+    let ids = HashSet::from([ephemeral.id, uuid::Uuid::new_v4()]);
+    assert_eq!(history::remove(&path, &ids).unwrap(), 1);
+    let left: Vec<_> = history::load(&path)
+        .unwrap()
+        .into_iter()
+        .map(|message| message.id)
+        .collect();
+    assert_eq!(left, [lasting.id]);
+}
+
+#[test]
+fn page_before_returns_the_newest_older_messages() {
+    let peer_id = peer();
+    let path = history_path();
+    let messages: Vec<ChatMessage> = (0..5)
+        .map(|n| ChatMessage {
+            room: Some("chat".to_string()),
+            timestamp: 100 + n,
+            ..ChatMessage::new(peer_id, n.to_string())
+        })
+        .collect();
+    history::append(&path, &messages).unwrap();
+
+    let page = history::page_before(&path, &HistoryFilter::room("chat"), 103, 2).unwrap();
+    let texts: Vec<&str> = page.iter().map(|message| message.text()).collect();
+    assert_eq!(texts, ["1", "2"]);
+    assert!(
+        history::page_before(&path, &HistoryFilter::room("rust"), 200, 2)
+            .unwrap()
+            .is_empty()
+    );
+}
+
+#[test]
+fn search_matches_the_latest_copy_of_each_message() {
+    let peer_id = peer();
+    let path = history_path();
+    let mut edited = ChatMessage {
+        timestamp: 100,
+        ..ChatMessage::new(peer_id, "rust is fun".to_string())
+    };
+    let kept = ChatMessage {
+        timestamp: 200,
+        ..ChatMessage::new(peer_id, "more rust".to_string())
+    };
+    let expired = ChatMessage {
+        expires_at: Some(10),
+        ..ChatMessage::new(peer_id, "rust for a moment".to_string())
+    };
+    history::append(&path, [&edited, &kept, &expired]).unwrap();
+    edited.edits.push("go is fun".to_string());
+    history::append(&path, [&edited]).unwrap();
+
+    let query = SearchQuery::new("rust", false, None, None).unwrap();
+    let hits = history::search(&path, &query, 20).unwrap();
+    let ids: Vec<_> = hits.iter().map(|hit| hit.id).collect();
+    assert_eq!(ids, [kept.id]);
+}
+
+#[test]
+fn missing_history_is_empty() {
+    assert!(history::load(&history_path()).unwrap().is_empty());
+}
+
+fn room_messages(peer_id: libp2p::PeerId, texts: std::ops::Range<u64>) -> Vec<ChatMessage> {
+    texts
+        .map(|n| ChatMessage {
+            room: Some("chat".to_string()),
+            timestamp: 100 + n,
+            ..ChatMessage::new(peer_id, n.to_string())
+        })
+        .collect()
+}
+
+fn texts(page: &history::Page) -> Vec<&str> {
+    page.messages.iter().map(|message| message.text()).collect()
+}
+
+#[test]
+fn room_pages_count_back_from_the_newest_message() {
+    let peer_id = peer();
+    let in_memory = room_messages(peer_id, 0..5);
+
+    let page =
+        history::room_page(None, &HistoryFilter::room("chat"), in_memory.clone(), 0, 2).unwrap();
+    assert_eq!(texts(&page), ["3", "4"]);
+    assert_eq!((page.first, page.total), (4, 5));
+
+    let page =
+        history::room_page(None, &HistoryFilter::room("chat"), in_memory.clone(), 2, 2).unwrap();
+    assert_eq!(texts(&page), ["0"]);
+    assert_eq!((page.first, page.total), (1, 5));
+
+    let page = history::room_page(None, &HistoryFilter::room("chat"), in_memory, 3, 2).unwrap();
+    assert!(page.messages.is_empty());
+    assert_eq!(page.total, 5);
+}
+
+#[test]
+fn history_filters_combine() {
+    let alice = peer();
+    let bob = peer();
+    let path = history_path();
+    let mut messages = room_messages(alice, 0..4);
+    messages.extend(room_messages(bob, 4..8));
+    messages.push(ChatMessage {
+        room: Some("other".to_string()),
+        timestamp: 200,
+        ..ChatMessage::new(alice, "elsewhere".to_string())
+    });
+    history::append(&path, &messages).unwrap();
+    let filter = HistoryFilter {
+        room: "chat".to_string(),
+        peer: Some(alice),
+        since: Some(102),
+    };
+    let kept: Vec<&str> = messages
+        .iter()
+        .filter(|message| filter.matches(message))
+        .map(|message| message.text())
+        .collect();
+    assert_eq!(kept, ["2", "3"]);
+
+    // The same messages whether they are read from memory or the file.
+    let in_memory: Vec<ChatMessage> = messages[6..]
+        .iter()
+        .filter(|message| filter.matches(message))
+        .cloned()
+        .collect();
+    let page = history::room_page(Some(&path), &filter, in_memory, 0, 10).unwrap();
+    assert_eq!(texts(&page), ["2", "3"]);
+    assert_eq!((page.first, page.total), (1, 2));
+}
+
+#[tokio::test]
+async fn handle_pages_through_the_history() {
+    let (requests_tx, mut requests_rx) = tokio::sync::mpsc::channel(1);
+    let (events_tx, _) = tokio::sync::broadcast::channel(1);
+    let handle = ChatHandle::new(requests_tx, events_tx);
+    let peer_id = peer();
+    let messages = room_messages(peer_id, 0..5);
+
+    let node = tokio::spawn(async move {
+        while let Some((command, reply_tx)) = requests_rx.recv().await {
+            let Command::History {
+                room: Some(room),
+                limit,
+                page,
+                ..
+            } = command
+            else {
+                panic!("expected a history command, got {:?}", command);
+            };
+            let page = history::room_page(
+                None,
+                &HistoryFilter::room(room.clone()),
+                messages.clone(),
+                page,
+                limit,
+            )
+            .unwrap();
+            let reply = Reply::History {
+                room,
+                messages: page.messages,
+                first: page.first,
+                total: page.total,
+                statuses: Default::default(),
+            };
+            reply_tx.send(Ok(reply)).unwrap();
+        }
+    });
+
+    let pages: Vec<Vec<String>> = handle
+        .history(HistoryFilter::room("chat"), 2)
+        .map(|page| {
+            page.unwrap()
+                .iter()
+                .map(|message| message.text().to_string())
+                .collect()
+        })
+        .collect()
+        .await;
+    assert_eq!(pages, [vec!["3", "4"], vec!["1", "2"], vec!["0"]]);
+    drop(handle);
+    node.await.unwrap();
+}
+
+#[test]
+fn room_pages_past_memory_are_read_from_the_history() {
+    let peer_id = peer();
+    let path = history_path();
+    let messages = room_messages(peer_id, 0..6);
+    history::append(&path, &messages).unwrap();
+    // The three oldest were evicted from memory.
+    let in_memory = messages[3..].to_vec();
+
+    let page = history::room_page(
+        Some(&path),
+        &HistoryFilter::room("chat"),
+        in_memory.clone(),
+        0,
+        4,
+    )
+    .unwrap();
+    assert_eq!(texts(&page), ["2", "3", "4", "5"]);
+    assert_eq!((page.first, page.total), (3, 6));
+
+    let page = history::room_page(
+        Some(&path),
+        &HistoryFilter::room("chat"),
+        in_memory.clone(),
+        1,
+        4,
+    )
+    .unwrap();
+    assert_eq!(texts(&page), ["0", "1"]);
+    assert_eq!(page.first, 1);
+
+    let page =
+        history::room_page(Some(&path), &HistoryFilter::room("chat"), in_memory, 1, 2).unwrap();
+    assert_eq!(texts(&page), ["2", "3"]);
+}
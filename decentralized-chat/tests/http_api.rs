@@ -0,0 +1,367 @@
+#![cfg(feature = "http-api")]
+
+use libp2p::{
+    futures::{SinkExt, StreamExt},
+    PeerId,
+};
+use libp2p_demo::{
+    command::{Command, ConnectedPeer, Peering, Reply, RoomSummary},
+    event::ChatEvent,
+    handle::ChatHandle,
+    http,
+    message::ChatMessage,
+    room_settings::Notify,
+    testing::peer,
+};
+use serde_json::{json, Value};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::{broadcast, mpsc},
+};
+use tokio_tungstenite::{
+    tungstenite::{
+        client::IntoClientRequest,
+        protocol::{frame::coding::CloseCode, Message},
+    },
+    MaybeTlsStream, WebSocketStream,
+};
+
+const TOKEN: &str = "secret-token";
+
+/// Serves the API in front of a stand-in for the node that is connected to
+/// `connected`, is in the room "chat", and records the commands it runs.
+async fn serve(connected: PeerId) -> (SocketAddr, Arc<Mutex<Vec<Command>>>) {
+    serve_with_events(connected, broadcast::channel(1).0).await
+}
+
+/// Like `serve`, with the node broadcasting on `events_tx`.
+async fn serve_with_events(
+    connected: PeerId,
+    events_tx: broadcast::Sender<ChatEvent>,
+) -> (SocketAddr, Arc<Mutex<Vec<Command>>>) {
+    let (requests_tx, mut requests_rx) = mpsc::channel(8);
+    let handle = ChatHandle::new(requests_tx, events_tx);
+    let commands = Arc::new(Mutex::new(Vec::new()));
+
+    let recorded = commands.clone();
+    tokio::spawn(async move {
+        while let Some((command, reply_tx)) = requests_rx.recv().await {
+            let reply = match &command {
+                Command::Peers => Ok(Reply::Peers {
+                    peers: vec![ConnectedPeer {
+                        peer_id: connected,
+                        protocol: None,
+                        security: Vec::new(),
+                        nickname: None,
+                        verified: false,
+                        bandwidth: Default::default(),
+                        peering: Peering::Mesh,
+                    }],
+                }),
+                Command::Known => Ok(Reply::Known { peers: Vec::new() }),
+                Command::Rooms => Ok(Reply::Rooms {
+                    rooms: vec![RoomSummary {
+                        room: "chat".to_string(),
+                        current: true,
+                        private: false,
+                        providers: 0,
+                        notify: Notify::All,
+                    }],
+                }),
+                Command::Send { text, .. } | Command::Msg { text, .. } if text == "fail" => {
+                    Err("Failed to publish message: InsufficientPeers".to_string())
+                }
+                Command::Send { .. } | Command::Msg { .. } => Ok(Reply::Sent {
+                    id: uuid::Uuid::new_v4(),
+                }),
+                Command::History { room, limit, .. } => Ok(Reply::History {
+                    room: room.clone().unwrap_or_else(|| "chat".to_string()),
+                    messages: (0..*limit)
+                        .map(|n| ChatMessage::new(connected, n.to_string()))
+                        .collect(),
+                    first: 1,
+                    total: *limit,
+                    statuses: Default::default(),
+                }),
+                other => panic!("unexpected command {:?}", other),
+            };
+            recorded.lock().unwrap().push(command);
+            let _ = reply_tx.send(reply);
+        }
+    });
+
+    let addr = http::serve("127.0.0.1:0".parse().unwrap(), handle, TOKEN.to_string())
+        .await
+        .unwrap();
+    (addr, commands)
+}
+
+/// Sends one request and returns the status and JSON body of the response.
+async fn request(
+    addr: SocketAddr,
+    method: &str,
+    path: &str,
+    token: Option<&str>,
+    body: Option<&str>,
+) -> (u16, Value) {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let mut head = format!(
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n",
+        method, path
+    );
+    if let Some(token) = token {
+        head.push_str(&format!("Authorization: Bearer {}\r\n", token));
+    }
+    let body = body.unwrap_or("");
+    if !body.is_empty() {
+        head.push_str("Content-Type: application/json\r\n");
+    }
+    head.push_str(&format!("Content-Length: {}\r\n\r\n{}", body.len(), body));
+    stream.write_all(head.as_bytes()).await.unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let status = response[9..12].parse().unwrap();
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    (status, serde_json::from_str(body).unwrap_or(Value::Null))
+}
+
+#[tokio::test]
+async fn requests_without_the_token_are_refused() {
+    let (addr, commands) = serve(peer()).await;
+
+    for token in [None, Some("wrong-token")] {
+        let (status, body) = request(addr, "GET", "/peers", token, None).await;
+        assert_eq!(status, 401);
+        assert_eq!(body["error"], "missing or wrong bearer token");
+    }
+    assert!(commands.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn peers_and_messages_are_read_through_the_handle() {
+    let connected = peer();
+    let (addr, commands) = serve(connected).await;
+
+    let (status, body) = request(addr, "GET", "/peers", Some(TOKEN), None).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["peers"][0]["peer_id"], connected.to_string());
+
+    let (status, body) = request(
+        addr,
+        "GET",
+        "/messages?room=rust&limit=2",
+        Some(TOKEN),
+        None,
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(body["room"], "rust");
+    assert_eq!(body["messages"].as_array().unwrap().len(), 2);
+    assert!(matches!(
+        commands.lock().unwrap().last(),
+        Some(Command::History { room: Some(room), limit: 2, page: 0, .. }) if room == "rust"
+    ));
+
+    let (status, body) = request(addr, "GET", "/messages?limit=0", Some(TOKEN), None).await;
+    assert_eq!(status, 400);
+    assert_eq!(body["error"], "limit must be between 1 and 1000");
+}
+
+#[tokio::test]
+async fn messages_are_sent_to_joined_rooms_and_known_peers() {
+    let connected = peer();
+    let (addr, commands) = serve(connected).await;
+
+    let (status, body) = request(
+        addr,
+        "POST",
+        "/messages",
+        Some(TOKEN),
+        Some(r#"{"room": "chat", "text": "hello"}"#),
+    )
+    .await;
+    assert_eq!(status, 201);
+    assert!(body["id"].is_string());
+
+    let direct = format!(r#"{{"peer": "{}", "text": "hi"}}"#, connected);
+    let (status, _) = request(addr, "POST", "/messages", Some(TOKEN), Some(&direct)).await;
+    assert_eq!(status, 201);
+
+    let sent = commands
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|command| matches!(command, Command::Send { .. } | Command::Msg { .. }))
+        .count();
+    assert_eq!(sent, 2);
+}
+
+#[tokio::test]
+async fn node_errors_map_to_client_errors() {
+    let (addr, commands) = serve(peer()).await;
+
+    for (body, status, error) in [
+        (
+            r#"{"room": "rust", "text": "hello"}"#.to_string(),
+            409,
+            "not in room rust; join it first".to_string(),
+        ),
+        (
+            format!(r#"{{"peer": "{}", "text": "hi"}}"#, peer()),
+            404,
+            "unknown peer".to_string(),
+        ),
+        (
+            r#"{"text": "nowhere"}"#.to_string(),
+            400,
+            "give either a room or a peer".to_string(),
+        ),
+        (
+            r#"{"room": "chat", "text": "fail"}"#.to_string(),
+            400,
+            "Failed to publish message: InsufficientPeers".to_string(),
+        ),
+        (r#"{"room": "chat""#.to_string(), 400, String::new()),
+    ] {
+        let (got, response) = request(addr, "POST", "/messages", Some(TOKEN), Some(&body)).await;
+        assert_eq!(got, status, "{}", body);
+        let message = response["error"].as_str().unwrap();
+        assert!(message.contains(&error), "{}: {}", body, message);
+    }
+
+    // Only the request the node itself refused got as far as sending.
+    let sent = commands
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|command| matches!(command, Command::Send { .. } | Command::Msg { .. }))
+        .count();
+    assert_eq!(sent, 1);
+
+    let (status, body) = request(addr, "GET", "/nowhere", Some(TOKEN), None).await;
+    assert_eq!(status, 404);
+    assert_eq!(body["error"], "no such endpoint");
+}
+
+type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+async fn connect_websocket(addr: SocketAddr) -> Client {
+    let mut request = format!("ws://{}/ws", addr).into_client_request().unwrap();
+    request.headers_mut().insert(
+        "Authorization",
+        format!("Bearer {}", TOKEN).parse().unwrap(),
+    );
+    let (client, _) = tokio_tungstenite::connect_async(request).await.unwrap();
+    client
+}
+
+/// Waits until `count` WebSocket clients are reading the broadcast, as
+/// events sent before that are never seen by them.
+async fn until_subscribed(events_tx: &broadcast::Sender<ChatEvent>, count: usize) {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while events_tx.receiver_count() < count {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("clients did not subscribe in time");
+}
+
+async fn next_json(client: &mut Client) -> Value {
+    let frame = tokio::time::timeout(Duration::from_secs(5), client.next())
+        .await
+        .expect("no frame in time")
+        .unwrap()
+        .unwrap();
+    serde_json::from_str(frame.to_text().unwrap()).unwrap()
+}
+
+#[tokio::test]
+async fn every_websocket_client_gets_every_event() {
+    let connected = peer();
+    let (events_tx, _) = broadcast::channel(16);
+    let (addr, _) = serve_with_events(connected, events_tx.clone()).await;
+
+    let mut first = connect_websocket(addr).await;
+    let mut second = connect_websocket(addr).await;
+    until_subscribed(&events_tx, 2).await;
+
+    let message = ChatMessage::new(connected, "hello".to_string());
+    events_tx
+        .send(ChatEvent::PeerOnline { peer: connected })
+        .unwrap();
+    events_tx
+        .send(ChatEvent::MessageReceived {
+            message: message.clone(),
+        })
+        .unwrap();
+
+    for client in [&mut first, &mut second] {
+        let online = next_json(client).await;
+        assert_eq!(online["event"], "peer_online");
+        assert_eq!(online["peer"], connected.to_string());
+
+        let received = next_json(client).await;
+        assert_eq!(received["event"], "message_received");
+        assert_eq!(received["message"]["id"], message.id.to_string());
+        assert_eq!(received["message"]["message"], "hello");
+    }
+}
+
+#[tokio::test]
+async fn websocket_frames_are_sent_as_messages() {
+    let (addr, commands) = serve(peer()).await;
+    let mut client = connect_websocket(addr).await;
+
+    let frame = json!({ "room": "chat", "text": "hello" }).to_string();
+    client.send(Message::Text(frame)).await.unwrap();
+    assert!(next_json(&mut client).await["id"].is_string());
+
+    let frame = json!({ "room": "rust", "text": "hello" }).to_string();
+    client.send(Message::Text(frame)).await.unwrap();
+    assert_eq!(
+        next_json(&mut client).await["error"],
+        "not in room rust; join it first"
+    );
+
+    client.send(Message::Text("{".to_string())).await.unwrap();
+    assert!(next_json(&mut client).await["error"].is_string());
+
+    assert!(matches!(
+        commands.lock().unwrap().last(),
+        Some(Command::Rooms)
+    ));
+}
+
+#[tokio::test]
+async fn websocket_clients_that_fall_behind_are_disconnected() {
+    let (events_tx, _) = broadcast::channel(512);
+    let (addr, _) = serve_with_events(peer(), events_tx.clone()).await;
+    let mut client = connect_websocket(addr).await;
+    until_subscribed(&events_tx, 1).await;
+
+    // The server task doesn't run until this test yields, so all of these
+    // are waiting for it at once.
+    for _ in 0..500 {
+        events_tx
+            .send(ChatEvent::PeerOnline { peer: peer() })
+            .unwrap();
+    }
+
+    let frame = tokio::time::timeout(Duration::from_secs(5), client.next())
+        .await
+        .expect("not disconnected in time")
+        .unwrap()
+        .unwrap();
+    match frame {
+        Message::Close(Some(close)) => assert_eq!(close.code, CloseCode::Again),
+        other => panic!("expected a close frame, got {:?}", other),
+    }
+}
@@ -0,0 +1,130 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64, Engine};
+use libp2p::identity::Keypair;
+use libp2p_demo::invite::{
+    decrypt_for_room, encrypt_for_room, room_key, Invite, InviteError, RoomKeys,
+};
+use std::fs;
+
+const NOW: u64 = 1_700_000_000;
+
+fn invite(inviter: &Keypair, room: &str) -> Invite {
+    Invite {
+        room: room.to_string(),
+        secret: [7; 32],
+        inviter: inviter.public().to_peer_id(),
+        expires_at: NOW + 60,
+    }
+}
+
+#[test]
+fn invites_round_trip_and_give_the_same_key() {
+    let alice = Keypair::generate_ed25519();
+    let issued = invite(&alice, "secret-plans");
+    let token = issued.issue(&alice).unwrap();
+
+    let redeemed = Invite::redeem(&token, NOW).unwrap();
+    assert_eq!(redeemed, issued);
+
+    let mut keys = RoomKeys::default();
+    assert!(keys.insert(&redeemed.room, redeemed.secret));
+    assert!(keys.is_private("secret-plans"));
+    assert_eq!(
+        keys.key("secret-plans"),
+        Some(room_key("secret-plans", &issued.secret))
+    );
+    // The same secret gives another room another key.
+    assert_ne!(
+        room_key("other", &issued.secret),
+        room_key("secret-plans", &issued.secret)
+    );
+}
+
+#[test]
+fn expired_invites_are_refused() {
+    let alice = Keypair::generate_ed25519();
+    let token = invite(&alice, "secret-plans").issue(&alice).unwrap();
+
+    assert_eq!(
+        Invite::redeem(&token, NOW + 60),
+        Err(InviteError::Expired {
+            expired_at: NOW + 60
+        })
+    );
+}
+
+#[test]
+fn altered_and_forged_invites_are_refused() {
+    let (alice, mallory) = (Keypair::generate_ed25519(), Keypair::generate_ed25519());
+    let token = invite(&alice, "secret-plans").issue(&alice).unwrap();
+
+    // Pushing back the expiry, the first field after the version.
+    let mut bytes = BASE64.decode(&token).unwrap();
+    bytes[8] = bytes[8].wrapping_add(1);
+    assert_eq!(
+        Invite::redeem(&BASE64.encode(&bytes), NOW),
+        Err(InviteError::Tampered)
+    );
+
+    // Claiming to be from alice while signing with another key.
+    assert!(invite(&alice, "secret-plans").issue(&mallory).is_err());
+    let mut forged = BASE64
+        .decode(invite(&mallory, "secret-plans").issue(&mallory).unwrap())
+        .unwrap();
+    let signature_len = 64;
+    let alice_key = alice.public().encode_protobuf();
+    let key_start = forged.len() - signature_len - alice_key.len();
+    forged[key_start..key_start + alice_key.len()].copy_from_slice(&alice_key);
+    assert_eq!(
+        Invite::redeem(&BASE64.encode(&forged), NOW),
+        Err(InviteError::Tampered)
+    );
+
+    assert_eq!(
+        Invite::redeem("not an invite!", NOW),
+        Err(InviteError::Malformed("not base64"))
+    );
+    assert_eq!(
+        Invite::redeem(&token[..20], NOW),
+        Err(InviteError::Malformed("it is cut short"))
+    );
+    bytes[0] = 9;
+    assert_eq!(
+        Invite::redeem(&BASE64.encode(&bytes), NOW),
+        Err(InviteError::UnsupportedVersion(9))
+    );
+}
+
+#[test]
+fn room_secrets_are_made_once_and_persisted() {
+    let path = std::env::temp_dir().join(format!("chat-room-keys-{}.json", uuid::Uuid::new_v4()));
+    let mut keys = RoomKeys::load(&path).unwrap();
+    assert!(!keys.is_private("rust"));
+
+    let secret = keys.secret_or_new("rust");
+    assert_eq!(keys.secret_or_new("rust"), secret);
+    assert!(!keys.insert("rust", secret));
+    keys.save(&path).unwrap();
+
+    let loaded = RoomKeys::load(&path).unwrap();
+    assert_eq!(loaded.secret("rust"), Some(secret));
+    assert!(!loaded.is_private("go"));
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn only_the_room_key_decrypts_room_payloads() {
+    let key = room_key("secret-plans", &[7; 32]);
+    let encrypted = encrypt_for_room(&key, b"meet at noon");
+    assert_ne!(encrypted, b"meet at noon");
+    assert_eq!(
+        decrypt_for_room(&key, &encrypted).as_deref(),
+        Some(&b"meet at noon"[..])
+    );
+
+    let other = room_key("secret-plans", &[8; 32]);
+    assert_eq!(decrypt_for_room(&other, &encrypted), None);
+    let mut altered = encrypted.clone();
+    *altered.last_mut().unwrap() ^= 1;
+    assert_eq!(decrypt_for_room(&key, &altered), None);
+    assert_eq!(decrypt_for_room(&key, b"{\"kind\":\"chat\"}"), None);
+}
@@ -0,0 +1,163 @@
+use libp2p_demo::key::{
+    decode, from_mnemonic, generate, generate_mnemonic, load_or_generate, save, KeyType, KEY_FILE,
+    MNEMONIC_WORDS,
+};
+use std::{fs, path::PathBuf};
+
+/// A 2048-bit RSA test key from `openssl genrsa` converted with
+/// `openssl pkcs8 -topk8 -outform DER -nocrypt`.
+const RSA_PKCS8: &[u8] = include_bytes!("fixtures/rsa-2048.pk8");
+
+fn key_path() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("chat-key-{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&dir).unwrap();
+    dir.join(KEY_FILE)
+}
+
+#[test]
+fn generated_keys_round_trip_through_protobuf() {
+    for key_type in [KeyType::Ed25519, KeyType::Secp256k1, KeyType::Ecdsa] {
+        let keypair = generate(key_type).unwrap();
+        let decoded = decode(&keypair.to_protobuf_encoding().unwrap()).unwrap();
+
+        assert_eq!(KeyType::of(&decoded), key_type);
+        assert_eq!(decoded.public(), keypair.public());
+    }
+}
+
+#[test]
+fn keys_are_generated_once_and_reloaded() {
+    for key_type in [KeyType::Ed25519, KeyType::Secp256k1, KeyType::Ecdsa] {
+        let path = key_path();
+        let generated = load_or_generate(&path, Some(key_type)).unwrap();
+        // The type is detected from the file, so it needn't be given again.
+        let loaded = load_or_generate(&path, None).unwrap();
+
+        assert_eq!(KeyType::of(&loaded), key_type);
+        assert_eq!(
+            loaded.public().to_peer_id(),
+            generated.public().to_peer_id()
+        );
+    }
+
+    let path = key_path();
+    let keypair = load_or_generate(&path, None).unwrap();
+    assert_eq!(KeyType::of(&keypair), KeyType::Ed25519);
+}
+
+#[test]
+fn rsa_keys_are_loaded_from_pkcs8() {
+    assert!(generate(KeyType::Rsa).is_err());
+
+    let path = key_path();
+    fs::write(&path, RSA_PKCS8).unwrap();
+    let keypair = load_or_generate(&path, Some(KeyType::Rsa)).unwrap();
+    let reloaded = load_or_generate(&path, None).unwrap();
+
+    assert_eq!(KeyType::of(&keypair), KeyType::Rsa);
+    assert_eq!(
+        reloaded.public().to_peer_id(),
+        keypair.public().to_peer_id()
+    );
+}
+
+#[test]
+fn a_stored_key_of_another_type_is_not_replaced() {
+    let path = key_path();
+    load_or_generate(&path, Some(KeyType::Secp256k1)).unwrap();
+
+    let error = load_or_generate(&path, Some(KeyType::Ed25519)).unwrap_err();
+    assert!(
+        error
+            .to_string()
+            .contains("holds a secp256k1 key, not ed25519"),
+        "{}",
+        error
+    );
+
+    let path = key_path();
+    fs::write(&path, b"not a key").unwrap();
+    assert!(load_or_generate(&path, None).is_err());
+}
+
+#[test]
+fn key_types_parse_from_their_names() {
+    for key_type in [
+        KeyType::Ed25519,
+        KeyType::Secp256k1,
+        KeyType::Ecdsa,
+        KeyType::Rsa,
+    ] {
+        assert_eq!(key_type.to_string().parse::<KeyType>(), Ok(key_type));
+    }
+    assert!("dsa".parse::<KeyType>().is_err());
+}
+
+/// The all-zero entropy phrase from the BIP-39 test vectors.
+const PHRASE: &str = "abandon abandon abandon abandon abandon abandon abandon abandon \
+                      abandon abandon abandon abandon abandon abandon abandon abandon \
+                      abandon abandon abandon abandon abandon abandon abandon art";
+
+#[test]
+fn a_seed_phrase_always_makes_the_same_key() {
+    let keypair = from_mnemonic(PHRASE, KeyType::Ed25519).unwrap();
+    assert_eq!(KeyType::of(&keypair), KeyType::Ed25519);
+    assert_eq!(
+        keypair.public().to_peer_id().to_string(),
+        "12D3KooWBq33BJkcsZxNvhZwBwSBn1EHg5jMTh873Eg4eVLJqNLp"
+    );
+
+    let retyped = format!("  {}\n", PHRASE.to_uppercase().replace(' ', "   "));
+    assert_eq!(
+        from_mnemonic(&retyped, KeyType::Ed25519).unwrap().public(),
+        keypair.public()
+    );
+}
+
+#[test]
+fn generated_seed_phrases_recover_their_key() {
+    let phrase = generate_mnemonic();
+    assert_eq!(phrase.split(' ').count(), MNEMONIC_WORDS);
+    assert_eq!(
+        from_mnemonic(&phrase, KeyType::Ed25519).unwrap().public(),
+        from_mnemonic(&phrase, KeyType::Ed25519).unwrap().public()
+    );
+    assert_ne!(phrase, generate_mnemonic());
+
+    let path = key_path();
+    save(&path, &from_mnemonic(&phrase, KeyType::Ed25519).unwrap()).unwrap();
+    assert_eq!(
+        load_or_generate(&path, None).unwrap().public(),
+        from_mnemonic(&phrase, KeyType::Ed25519).unwrap().public()
+    );
+    assert!(save(&path, &from_mnemonic(PHRASE, KeyType::Ed25519).unwrap()).is_err());
+}
+
+#[test]
+fn mistyped_seed_phrases_are_rejected() {
+    // The last word carries the checksum.
+    let wrong_checksum = PHRASE.replace("art", "abandon");
+    assert!(from_mnemonic(&wrong_checksum, KeyType::Ed25519).is_err());
+    assert!(from_mnemonic(&PHRASE.replace("art", "notaword"), KeyType::Ed25519).is_err());
+    assert!(from_mnemonic("abandon art", KeyType::Ed25519).is_err());
+}
+
+#[test]
+fn seed_phrases_make_keys_of_every_generated_type() {
+    let mut peers = Vec::new();
+    for key_type in [KeyType::Ed25519, KeyType::Secp256k1, KeyType::Ecdsa] {
+        let keypair = from_mnemonic(PHRASE, key_type).unwrap();
+        assert_eq!(KeyType::of(&keypair), key_type);
+        assert_eq!(
+            from_mnemonic(PHRASE, key_type).unwrap().public(),
+            keypair.public()
+        );
+
+        let decoded = decode(&keypair.to_protobuf_encoding().unwrap()).unwrap();
+        assert_eq!(decoded.public(), keypair.public());
+        peers.push(keypair.public().to_peer_id());
+    }
+    peers.dedup();
+    assert_eq!(peers.len(), 3);
+    assert!(from_mnemonic(PHRASE, KeyType::Rsa).is_err());
+}
@@ -0,0 +1,176 @@
+use libp2p::{futures::StreamExt, multiaddr::Protocol, swarm::SwarmEvent, Multiaddr, PeerId};
+use libp2p_demo::{
+    config::Config,
+    dial::{is_security_failure, report_dial_error},
+    event::ChatEvent,
+    key_pin::{check_connection, AddressPattern, KeyPins},
+    testing::{listening_tcp_swarm, peer, tcp_swarm},
+};
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(15);
+
+fn pins(pins: &[(&str, PeerId)]) -> KeyPins {
+    KeyPins::new(
+        pins.iter()
+            .map(|(pattern, peer)| (pattern.parse().unwrap(), *peer))
+            .collect(),
+    )
+}
+
+#[test]
+fn patterns_match_the_addresses_starting_with_them() {
+    let pattern: AddressPattern = "/ip4/192.0.2.1/tcp/*".parse().unwrap();
+    for address in [
+        "/ip4/192.0.2.1/tcp/4001",
+        "/ip4/192.0.2.1/tcp/80/ws",
+        &format!("/ip4/192.0.2.1/tcp/4001/p2p/{}", peer()),
+    ] {
+        assert!(pattern.matches(&address.parse().unwrap()), "{}", address);
+    }
+    for address in [
+        "/ip4/192.0.2.2/tcp/4001",
+        "/ip4/192.0.2.1",
+        "/dns4/192.0.2.1/tcp/4001",
+    ] {
+        assert!(!pattern.matches(&address.parse().unwrap()), "{}", address);
+    }
+
+    let pattern: AddressPattern = "/dns4/*/tcp/4001/ws".parse().unwrap();
+    assert!(pattern.matches(&"/dns4/chat.example.com/tcp/4001/ws".parse().unwrap()));
+    assert!(!pattern.matches(&"/dns4/chat.example.com/tcp/4001".parse().unwrap()));
+    assert_eq!(pattern.to_string(), "/dns4/*/tcp/4001/ws");
+}
+
+#[test]
+fn every_pin_matching_an_address_has_to_agree() {
+    let (alice, bob) = (peer(), peer());
+    let pins = pins(&[("/ip4/192.0.2.1/tcp/*", alice), ("/ip4/*/tcp/4001", bob)]);
+
+    let alice_only = "/ip4/192.0.2.1/tcp/4002".parse().unwrap();
+    assert_eq!(pins.verify(&alice_only, &alice), Ok(()));
+    assert_eq!(pins.verify(&alice_only, &bob), Err(alice));
+    // Unpinned addresses take anyone.
+    assert_eq!(
+        pins.verify(&"/ip4/192.0.2.9/tcp/80".parse().unwrap(), &bob),
+        Ok(())
+    );
+    // Contradicting pins let no one through.
+    let both = "/ip4/192.0.2.1/tcp/4001".parse().unwrap();
+    assert!(pins.verify(&both, &alice).is_err());
+    assert!(pins.verify(&both, &bob).is_err());
+}
+
+/// Dials a new listener, checking the connection against `pins`. Returns
+/// the warning, if any, once the connection closed or checked out.
+async fn dial_pinned(pins: &KeyPins) -> Option<ChatEvent> {
+    let (mut listener, address) = listening_tcp_swarm(&Config::default()).await;
+    let mut dialer = tcp_swarm(&Config::default()).await;
+    dialer.dial(address).unwrap();
+
+    let mut warning = None;
+    tokio::time::timeout(TIMEOUT, async {
+        loop {
+            tokio::select! {
+                _ = listener.select_next_some() => {}
+                event = dialer.select_next_some() => match event {
+                    SwarmEvent::ConnectionEstablished {
+                        peer_id,
+                        connection_id,
+                        endpoint,
+                        ..
+                    } => {
+                        warning =
+                            check_connection(&mut dialer, pins, peer_id, connection_id, &endpoint);
+                        if warning.is_none() {
+                            break;
+                        }
+                    }
+                    SwarmEvent::ConnectionClosed { .. } => break,
+                    SwarmEvent::OutgoingConnectionError { error, .. } => {
+                        panic!("failed to connect: {}", report_dial_error("the listener", &error))
+                    }
+                    _ => {}
+                },
+            }
+        }
+    })
+    .await
+    .expect("the connection was not checked in time");
+    warning
+}
+
+#[tokio::test]
+async fn a_listener_with_the_wrong_identity_is_disconnected_with_a_warning() {
+    let expected = peer();
+    let loopback = pins(&[("/ip4/127.0.0.1/tcp/*", expected)]);
+    let Some(ChatEvent::KeyPinMismatch {
+        address,
+        expected: pinned,
+        actual,
+    }) = dial_pinned(&loopback).await
+    else {
+        panic!("the wrong identity was let through");
+    };
+    assert_eq!(pinned, expected);
+    assert_ne!(actual, expected);
+    assert!(address.to_string().starts_with("/ip4/127.0.0.1/tcp/"));
+
+    // Addresses no pin matches are left alone.
+    let elsewhere = pins(&[("/ip4/192.0.2.1/tcp/*", expected)]);
+    assert_eq!(dial_pinned(&elsewhere).await, None);
+}
+
+#[tokio::test]
+async fn a_key_mismatch_is_told_apart_from_an_unreachable_peer() {
+    let (mut listener, address) = listening_tcp_swarm(&Config::default()).await;
+    let mut dialer = tcp_swarm(&Config::default()).await;
+    // The listener authenticates as itself, not as the peer in the address.
+    dialer.dial(address.with(Protocol::P2p(peer()))).unwrap();
+    let error = tokio::time::timeout(TIMEOUT, async {
+        loop {
+            tokio::select! {
+                _ = listener.select_next_some() => {}
+                event = dialer.select_next_some() => {
+                    if let SwarmEvent::OutgoingConnectionError { error, .. } = event {
+                        return error;
+                    }
+                }
+            }
+        }
+    })
+    .await
+    .expect("the dial did not fail in time");
+    assert!(is_security_failure(&error));
+    let report = report_dial_error("the listener", &error);
+    assert!(report.contains("handshake failed"), "{}", report);
+
+    // Nothing listens on a port that was just freed.
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let mut dialer = tcp_swarm(&Config::default()).await;
+    dialer
+        .dial(
+            format!("/ip4/127.0.0.1/tcp/{}", port)
+                .parse::<Multiaddr>()
+                .unwrap(),
+        )
+        .unwrap();
+    let error = tokio::time::timeout(TIMEOUT, async {
+        loop {
+            if let SwarmEvent::OutgoingConnectionError { error, .. } =
+                dialer.select_next_some().await
+            {
+                return error;
+            }
+        }
+    })
+    .await
+    .expect("the dial did not fail in time");
+    assert!(!is_security_failure(&error));
+    let report = report_dial_error("the listener", &error);
+    assert!(report.starts_with("Failed to dial"), "{}", report);
+}
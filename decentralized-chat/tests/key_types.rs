@@ -0,0 +1,109 @@
+use libp2p::{futures::StreamExt, gossipsub, identity::Keypair, swarm::SwarmEvent};
+use libp2p_demo::{
+    behaviour::CustomBehaviourEvent,
+    config::Config,
+    dial::describe_dial_error,
+    key::{decode, generate, KeyType},
+    message::{ChatMessage, GossipMessage},
+    testing::{listen_tcp, tcp_swarm, tcp_swarm_as},
+};
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(15);
+
+/// See tests/key.rs.
+const RSA_PKCS8: &[u8] = include_bytes!("fixtures/rsa-2048.pk8");
+
+fn keypair(key_type: KeyType) -> Keypair {
+    match key_type {
+        KeyType::Rsa => decode(RSA_PKCS8).unwrap(),
+        key_type => generate(key_type).unwrap(),
+    }
+}
+
+/// Connects a node with a `key_type` identity to an ed25519 one over noise
+/// and has it publish a signed chat message, which the other receives.
+async fn gossip_from(key_type: KeyType) -> (Keypair, gossipsub::Message) {
+    let author_key = keypair(key_type);
+    let mut author = tcp_swarm_as(author_key.clone(), &Config::default()).await;
+    let mut reader = tcp_swarm(&Config::default()).await;
+    let reader_addr = listen_tcp(&mut reader).await;
+
+    let topic = gossipsub::IdentTopic::new("chat");
+    author.behaviour_mut().gossipsub.subscribe(&topic).unwrap();
+    reader.behaviour_mut().gossipsub.subscribe(&topic).unwrap();
+    author.dial(reader_addr).unwrap();
+
+    let mut message = ChatMessage::new(*author.local_peer_id(), format!("signed by {}", key_type));
+    message.sign(&author_key).unwrap();
+    let payload = serde_json::to_vec(&GossipMessage::Chat(Box::new(message))).unwrap();
+
+    let mut published = false;
+    let received = tokio::time::timeout(TIMEOUT, async {
+        loop {
+            tokio::select! {
+                event = reader.select_next_some() => {
+                    if let SwarmEvent::Behaviour(CustomBehaviourEvent::Gossipsub(
+                        gossipsub::Event::Message { message, .. },
+                    )) = event
+                    {
+                        break message;
+                    }
+                }
+                event = author.select_next_some() => match event {
+                    SwarmEvent::Behaviour(CustomBehaviourEvent::Gossipsub(
+                        gossipsub::Event::Subscribed { .. },
+                    )) if !published => {
+                        author
+                            .behaviour_mut()
+                            .gossipsub
+                            .publish(topic.clone(), payload.clone())
+                            .unwrap();
+                        published = true;
+                    }
+                    SwarmEvent::OutgoingConnectionError { error, .. } => {
+                        panic!("dial failed: {}", describe_dial_error(&error))
+                    }
+                    _ => {}
+                },
+            }
+        }
+    })
+    .await
+    .unwrap_or_else(|_| panic!("no gossip from a {} node in time", key_type));
+    (author_key, received)
+}
+
+async fn check_gossip_from(key_type: KeyType) {
+    let (author_key, received) = gossip_from(key_type).await;
+    let author = author_key.public().to_peer_id();
+    // Gossipsub signed the message with the author's key...
+    assert_eq!(received.source, Some(author));
+
+    // ...and so did we.
+    let Ok(GossipMessage::Chat(message)) = serde_json::from_slice(&received.data) else {
+        panic!("expected a chat message");
+    };
+    assert_eq!(message.peer_id, author);
+    assert_eq!(message.verify_signature(), Ok(()));
+}
+
+#[tokio::test]
+async fn ed25519_nodes_gossip_signed_messages() {
+    check_gossip_from(KeyType::Ed25519).await;
+}
+
+#[tokio::test]
+async fn secp256k1_nodes_gossip_signed_messages() {
+    check_gossip_from(KeyType::Secp256k1).await;
+}
+
+#[tokio::test]
+async fn ecdsa_nodes_gossip_signed_messages() {
+    check_gossip_from(KeyType::Ecdsa).await;
+}
+
+#[tokio::test]
+async fn rsa_nodes_gossip_signed_messages() {
+    check_gossip_from(KeyType::Rsa).await;
+}
@@ -0,0 +1,99 @@
+use libp2p_demo::markdown::{parse, Span, Style};
+
+const BOLD: Style = Style {
+    bold: true,
+    ..Style::PLAIN
+};
+const ITALIC: Style = Style {
+    italic: true,
+    ..Style::PLAIN
+};
+const CODE: Style = Style {
+    code: true,
+    ..Style::PLAIN
+};
+
+#[test]
+fn bold_text_is_styled() {
+    assert_eq!(
+        parse("a **big** deal"),
+        vec![
+            Span::plain("a "),
+            Span::styled("big", BOLD),
+            Span::plain(" deal")
+        ]
+    );
+}
+
+#[test]
+fn stars_and_underscores_make_italics() {
+    assert_eq!(
+        parse("*so* _very_"),
+        vec![
+            Span::styled("so", ITALIC),
+            Span::plain(" "),
+            Span::styled("very", ITALIC)
+        ]
+    );
+}
+
+#[test]
+fn inline_code_is_literal() {
+    assert_eq!(
+        parse("run `cargo **test**` now"),
+        vec![
+            Span::plain("run "),
+            Span::styled("cargo **test**", CODE),
+            Span::plain(" now")
+        ]
+    );
+}
+
+#[test]
+fn markers_nest() {
+    assert_eq!(
+        parse("**bold *both* `code`**"),
+        vec![
+            Span::styled("bold ", BOLD),
+            Span::styled(
+                "both",
+                Style {
+                    italic: true,
+                    ..BOLD
+                }
+            ),
+            Span::styled(" ", BOLD),
+            Span::styled("code", Style { code: true, ..BOLD }),
+        ]
+    );
+}
+
+#[test]
+fn unbalanced_markers_fall_back_to_plain_text() {
+    for text in [
+        "**never closed",
+        "a * b",
+        "`open code",
+        "****",
+        "**a *b**",
+        "``",
+    ] {
+        assert_eq!(parse(text), vec![Span::plain(text)], "{}", text);
+    }
+}
+
+#[test]
+fn underscores_inside_words_and_escapes_are_text() {
+    assert_eq!(
+        parse("snake_case_name"),
+        vec![Span::plain("snake_case_name")]
+    );
+    assert_eq!(parse(r"2 \* 3 \*\* 4"), vec![Span::plain("2 * 3 ** 4")]);
+    assert_eq!(parse(""), Vec::<Span>::new());
+}
+
+#[test]
+fn long_runs_of_markers_stay_plain() {
+    let text = "*".repeat(10_001);
+    assert_eq!(parse(&text), vec![Span::plain(text.as_str())]);
+}
@@ -0,0 +1,82 @@
+use libp2p_demo::{members::RoomMembers, testing::peer};
+use std::time::{Duration, Instant};
+
+const HEARTBEAT: Duration = Duration::from_secs(30);
+const STALE_AFTER: Duration = Duration::from_secs(120);
+
+fn rooms(rooms: &[&str]) -> Vec<String> {
+    rooms.iter().map(|room| room.to_string()).collect()
+}
+
+#[test]
+fn members_are_only_tracked_in_our_rooms() {
+    let mut members = RoomMembers::new(HEARTBEAT, STALE_AFTER);
+    let (alice, now) = (peer(), Instant::now());
+    members.join("rust");
+
+    assert!(members.heard("rust", alice, now));
+    assert!(!members.heard("rust", alice, now));
+    assert!(!members.heard("go", alice, now));
+    assert_eq!(members.members("go", now), None);
+
+    let listed = members.members("rust", now).unwrap();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].peer, alice);
+    assert!(listed[0].online);
+
+    assert!(members.left("rust", &alice));
+    assert!(!members.left("rust", &alice));
+    members.leave("rust");
+    assert_eq!(members.members("rust", now), None);
+}
+
+#[test]
+fn heartbeats_add_and_remove_rooms() {
+    let mut members = RoomMembers::new(HEARTBEAT, STALE_AFTER);
+    let (bob, now) = (peer(), Instant::now());
+    members.join("rust");
+    members.join("chat");
+
+    let (joined, left) = members.presence(bob, &rooms(&["chat", "rust", "go"]), now);
+    assert_eq!((joined, left), (rooms(&["chat", "rust"]), vec![]));
+
+    let (joined, left) = members.presence(bob, &rooms(&["chat"]), now);
+    assert_eq!((joined, left), (vec![], rooms(&["rust"])));
+    assert!(members.members("rust", now).unwrap().is_empty());
+}
+
+#[test]
+fn silent_members_go_offline_then_age_out() {
+    let mut members = RoomMembers::new(HEARTBEAT, STALE_AFTER);
+    let (carol, dave, start) = (peer(), peer(), Instant::now());
+    members.join("rust");
+    members.heard("rust", carol, start);
+    members.subscribed("rust", dave, start);
+
+    let later = start + HEARTBEAT * 2;
+    let listed = members.members("rust", later).unwrap();
+    let online = |peer| {
+        listed
+            .iter()
+            .find(|member| member.peer == peer)
+            .unwrap()
+            .online
+    };
+    assert!(!online(carol));
+    // Subscribed peers are connected, so they are online however quiet.
+    assert!(online(dave));
+
+    assert!(members.expire(start + STALE_AFTER / 2).is_empty());
+    assert_eq!(
+        members.expire(start + STALE_AFTER),
+        [("rust".to_string(), carol)]
+    );
+
+    // Once disconnected, a subscribed peer ages out like any other.
+    members.disconnected(&dave);
+    assert_eq!(
+        members.expire(start + STALE_AFTER),
+        [("rust".to_string(), dave)]
+    );
+    assert!(members.members("rust", start).unwrap().is_empty());
+}
@@ -0,0 +1,267 @@
+use libp2p::{gossipsub, request_response, swarm::SwarmEvent};
+use libp2p_demo::{
+    behaviour::{CustomBehaviourEvent, Request, Response},
+    envelope::{self, open_slice, seal, Opened},
+    message::{ChatMessage, DirectRequest, DirectResponse, GossipMessage},
+    testing::{connect, drive_until, TestNode},
+};
+use serde_json::json;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+async fn nodes<const N: usize>() -> [TestNode; N] {
+    let mut nodes = Vec::new();
+    for _ in 0..N {
+        nodes.push(TestNode::new().await);
+    }
+    nodes.try_into().ok().unwrap()
+}
+
+/// Subscribes every node to `topic`.
+fn subscribe(nodes: &mut [TestNode], topic: &gossipsub::IdentTopic) {
+    for node in nodes {
+        node.swarm
+            .behaviour_mut()
+            .gossipsub
+            .subscribe(topic)
+            .unwrap();
+    }
+}
+
+#[tokio::test]
+async fn two_memory_swarms_exchange_a_gossipsub_message() {
+    let mut nodes: [TestNode; 2] = nodes().await;
+    let topic = gossipsub::IdentTopic::new("chat");
+    subscribe(&mut nodes, &topic);
+    connect(&mut nodes, 1, 0).await;
+
+    let sent = ChatMessage::new(nodes[1].peer_id(), "hello over memory".to_string());
+    let payload = serde_json::to_vec(&seal(&GossipMessage::Chat(Box::new(sent.clone())))).unwrap();
+    let mut published = false;
+
+    let received = drive_until(&mut nodes, TIMEOUT, |index, event, nodes| match event {
+        SwarmEvent::Behaviour(CustomBehaviourEvent::Gossipsub(gossipsub::Event::Message {
+            message,
+            ..
+        })) if index == 0 => Some(open_slice::<GossipMessage>(&message.data).unwrap()),
+        SwarmEvent::Behaviour(CustomBehaviourEvent::Gossipsub(gossipsub::Event::Subscribed {
+            ..
+        })) if index == 1 && !published => {
+            nodes[1]
+                .swarm
+                .behaviour_mut()
+                .gossipsub
+                .publish(topic.clone(), payload.clone())
+                .unwrap();
+            published = true;
+            None
+        }
+        _ => None,
+    })
+    .await;
+
+    let Opened::Known(GossipMessage::Chat(received)) = received else {
+        panic!("unexpected message {:?}", received);
+    };
+    assert_eq!(received.id, sent.id);
+    assert_eq!(received.peer_id, sent.peer_id);
+    assert_eq!(received.message, sent.message);
+}
+
+#[tokio::test]
+async fn gossip_reaches_peers_we_are_not_connected_to() {
+    // alice - bob - carol, with carol only reachable through bob.
+    let mut nodes: [TestNode; 3] = nodes().await;
+    let topic = gossipsub::IdentTopic::new("chat");
+    subscribe(&mut nodes, &topic);
+    connect(&mut nodes, 0, 1).await;
+    connect(&mut nodes, 2, 1).await;
+
+    let sent = ChatMessage::new(nodes[0].peer_id(), "pass it on".to_string());
+    let payload = serde_json::to_vec(&seal(&GossipMessage::Chat(Box::new(sent.clone())))).unwrap();
+    let mut published = false;
+
+    let received = drive_until(&mut nodes, TIMEOUT, |index, event, nodes| {
+        // Once bob has both in his mesh, alice's message goes through him.
+        if !published
+            && nodes[1]
+                .swarm
+                .behaviour()
+                .gossipsub
+                .mesh_peers(&topic.hash())
+                .count()
+                == 2
+        {
+            published = nodes[0]
+                .swarm
+                .behaviour_mut()
+                .gossipsub
+                .publish(topic.clone(), payload.clone())
+                .is_ok();
+        }
+        match event {
+            SwarmEvent::Behaviour(CustomBehaviourEvent::Gossipsub(gossipsub::Event::Message {
+                message,
+                propagation_source,
+                ..
+            })) if index == 2 => Some((message, propagation_source)),
+            // Gossip is validated before it is forwarded, so bob has to
+            // vouch for the message as the node's handlers would.
+            SwarmEvent::Behaviour(CustomBehaviourEvent::Gossipsub(gossipsub::Event::Message {
+                message_id,
+                propagation_source,
+                ..
+            })) if index == 1 => {
+                nodes[1]
+                    .swarm
+                    .behaviour_mut()
+                    .gossipsub
+                    .report_message_validation_result(
+                        &message_id,
+                        &propagation_source,
+                        gossipsub::MessageAcceptance::Accept,
+                    )
+                    .expect("the message is still in the cache");
+                None
+            }
+            _ => None,
+        }
+    })
+    .await;
+
+    let (message, propagation_source) = received;
+    assert_eq!(propagation_source, nodes[1].peer_id());
+    assert_eq!(message.source, Some(nodes[0].peer_id()));
+    let Ok(Opened::Known(GossipMessage::Chat(received))) = open_slice(&message.data) else {
+        panic!("not a chat message");
+    };
+    assert_eq!(received.id, sent.id);
+}
+
+#[tokio::test]
+async fn greeting_request_gets_a_welcome_response() {
+    let mut nodes: [TestNode; 2] = nodes().await;
+    let (alice_id, bob_id) = (nodes[0].peer_id(), nodes[1].peer_id());
+    connect(&mut nodes, 1, 0).await;
+
+    let greeting = ChatMessage::new(bob_id, "Hello".to_string());
+    nodes[1]
+        .swarm
+        .behaviour_mut()
+        .request_response
+        .send_request(
+            &alice_id,
+            Request {
+                data: seal(&DirectRequest::Greeting(greeting.clone())),
+            },
+        );
+
+    let welcome = drive_until(&mut nodes, TIMEOUT, |index, event, nodes| {
+        let SwarmEvent::Behaviour(CustomBehaviourEvent::RequestResponse(
+            request_response::Event::Message { peer, message },
+        )) = event
+        else {
+            return None;
+        };
+        match message {
+            request_response::Message::Request {
+                request, channel, ..
+            } if index == 0 => {
+                let Ok(Opened::Known(DirectRequest::Greeting(received))) =
+                    envelope::open(request.data)
+                else {
+                    panic!("expected a greeting");
+                };
+                assert_eq!(received.id, greeting.id);
+                assert_eq!(received.peer_id, peer);
+
+                let welcome = ChatMessage::new(alice_id, format!("Welcome {}!", peer));
+                nodes[0]
+                    .swarm
+                    .behaviour_mut()
+                    .request_response
+                    .send_response(
+                        channel,
+                        Response {
+                            data: seal(&DirectResponse::Welcome(Box::new(welcome))),
+                        },
+                    )
+                    .unwrap();
+                None
+            }
+            request_response::Message::Response { response, .. } if index == 1 => {
+                assert_eq!(peer, alice_id);
+                match envelope::open(response.data) {
+                    Ok(Opened::Known(DirectResponse::Welcome(welcome))) => Some(welcome),
+                    other => panic!("expected a welcome, got {:?}", other),
+                }
+            }
+            _ => None,
+        }
+    })
+    .await;
+
+    assert_eq!(welcome.peer_id, alice_id);
+    assert_eq!(welcome.message, format!("Welcome {}!", bob_id));
+}
+
+#[tokio::test]
+async fn direct_messages_are_acknowledged_by_id() {
+    let mut nodes: [TestNode; 2] = nodes().await;
+    let alice_id = nodes[0].peer_id();
+    connect(&mut nodes, 1, 0).await;
+
+    let sent = ChatMessage::new(nodes[1].peer_id(), "just for you".to_string());
+    let request_id = nodes[1]
+        .swarm
+        .behaviour_mut()
+        .request_response
+        .send_request(
+            &alice_id,
+            Request {
+                data: json!(DirectRequest::Message(sent.clone())),
+            },
+        );
+
+    let (id, acked) = drive_until(&mut nodes, TIMEOUT, |index, event, nodes| {
+        let SwarmEvent::Behaviour(CustomBehaviourEvent::RequestResponse(
+            request_response::Event::Message { message, .. },
+        )) = event
+        else {
+            return None;
+        };
+        match message {
+            request_response::Message::Request {
+                request, channel, ..
+            } if index == 0 => {
+                // Sent bare, the way legacy peers do.
+                let Ok(Opened::Known(DirectRequest::Message(received))) =
+                    envelope::open(request.data)
+                else {
+                    panic!("expected a direct message");
+                };
+                let ack = DirectResponse::Ack { id: received.id };
+                nodes[0]
+                    .swarm
+                    .behaviour_mut()
+                    .request_response
+                    .send_response(channel, Response { data: json!(ack) })
+                    .unwrap();
+                None
+            }
+            request_response::Message::Response {
+                request_id,
+                response,
+            } if index == 1 => match envelope::open(response.data) {
+                Ok(Opened::Known(DirectResponse::Ack { id })) => Some((request_id, id)),
+                other => panic!("expected an ack, got {:?}", other),
+            },
+            _ => None,
+        }
+    })
+    .await;
+
+    assert_eq!(id, request_id);
+    assert_eq!(acked, sent.id);
+}
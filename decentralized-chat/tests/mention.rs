@@ -0,0 +1,86 @@
+use libp2p_demo::{
+    event::ChatEvent,
+    mention::{mentions, mentions_peer, Mentions},
+    message::ChatMessage,
+    testing::peer,
+};
+
+#[test]
+fn mentions_anywhere_in_the_message() {
+    assert!(mentions("@alice are you there?", "alice"));
+    assert!(mentions("what do you think, @alice?", "alice"));
+    assert!(mentions("thanks @alice", "alice"));
+    assert!(mentions("@alice", "alice"));
+    assert!(mentions("ping @ALICE", "alice"));
+}
+
+#[test]
+fn substrings_are_not_mentions() {
+    assert!(!mentions("@alicebob hi", "alice"));
+    assert!(!mentions("@alice_2 hi", "alice"));
+    assert!(!mentions("mail alice@example.com", "example"));
+    assert!(!mentions("alice, hi", "alice"));
+    assert!(!mentions("@ali", "alice"));
+    assert!(!mentions("@alice", ""));
+}
+
+#[test]
+fn mentions_survive_multibyte_text() {
+    assert!(mentions("héllo @zoë 👋", "zoë"));
+    assert!(!mentions("@zoëlle", "zoë"));
+    assert!(!mentions("@é", "ab"));
+}
+
+#[test]
+fn long_enough_peer_id_prefixes_are_mentions() {
+    let peer = peer();
+    let id = peer.to_string();
+
+    assert!(mentions_peer(&format!("@{} hi", &id[..12]), &peer));
+    assert!(mentions_peer(&format!("hi @{}!", id), &peer));
+    // Every Ed25519 peer id starts with 12D3KooW.
+    assert!(!mentions_peer("@12D3KooW hi", &peer));
+    assert!(!mentions_peer(&format!("{} hi", id), &peer));
+    assert!(!mentions_peer(
+        &format!("@{}", id[..12].to_lowercase()),
+        &peer
+    ));
+}
+
+#[test]
+fn unread_mentions_show_in_the_prompt_until_read() {
+    let mut mentions = Mentions::default();
+    assert_eq!(mentions.prompt(), "");
+
+    mentions.received();
+    assert_eq!(mentions.prompt(), "(1 unread mention) > ");
+    mentions.received();
+    assert_eq!(mentions.unread(), 2);
+    assert_eq!(mentions.prompt(), "(2 unread mentions) > ");
+
+    mentions.mark_read();
+    assert_eq!(mentions.unread(), 0);
+    assert_eq!(mentions.prompt(), "");
+}
+
+#[test]
+fn the_mention_flag_is_only_written_when_set() {
+    let peer = peer();
+    let mut message = ChatMessage::new(peer, "hi @alice".to_string());
+    let json = serde_json::to_value(&message).unwrap();
+    assert!(json.get("mentions_me").is_none());
+
+    message.mentions_me = true;
+    message.room = Some("chat".to_string());
+    let json = serde_json::to_string(&message).unwrap();
+    assert!(
+        serde_json::from_str::<ChatMessage>(&json)
+            .unwrap()
+            .mentions_me
+    );
+
+    let event = ChatEvent::Mentioned { message };
+    assert!(event
+        .to_string()
+        .ends_with("mentioned you in #chat: hi @alice"));
+}
@@ -0,0 +1,115 @@
+use libp2p::{identity, PeerId};
+use libp2p_demo::{
+    envelope::{self, Opened},
+    message::{ChatMessage, DirectRequest, GossipMessage, Sequence},
+};
+use proptest::{collection::vec, option, prelude::*};
+use uuid::Uuid;
+
+/// Text including the edge cases: empty, control characters, unicode from
+/// every plane, and strings far longer than anyone types.
+fn text() -> impl Strategy<Value = String> {
+    prop_oneof![
+        Just(String::new()),
+        any::<String>(),
+        "[\\x00-\\x1f\\x7f\"\\\\]{1,16}",
+        "\\PC{1,64}",
+        "[a-z ]{4000,8000}",
+    ]
+}
+
+fn peer_id() -> impl Strategy<Value = PeerId> {
+    any::<[u8; 32]>().prop_map(|seed| keypair(seed).public().to_peer_id())
+}
+
+fn keypair(seed: [u8; 32]) -> identity::Keypair {
+    identity::Keypair::ed25519_from_bytes(seed).expect("any 32 bytes are an ed25519 key")
+}
+
+fn uuid() -> impl Strategy<Value = Uuid> {
+    any::<u128>().prop_map(Uuid::from_u128)
+}
+
+prop_compose! {
+    fn chat_message()(
+        id in uuid(),
+        peer_id in peer_id(),
+        nickname in option::of(text()),
+        message in text(),
+        room in option::of(text()),
+        recipient in option::of(peer_id()),
+        timestamp in any::<u64>(),
+        reply_to in option::of(uuid()),
+        expires_at in option::of(any::<u64>()),
+        ttl_secs in option::of(any::<u64>()),
+        sequence in option::of((uuid(), any::<u64>())),
+        edits in vec(text(), 0..3),
+        deleted in any::<bool>(),
+        mentions_me in any::<bool>(),
+        signature in vec(any::<u8>(), 0..128),
+        public_key in vec(any::<u8>(), 0..64),
+    ) -> ChatMessage {
+        ChatMessage {
+            id,
+            peer_id,
+            nickname,
+            message,
+            room,
+            recipient,
+            timestamp,
+            reply_to,
+            expires_at,
+            ttl_secs,
+            sequence: sequence.map(|(session, seq)| Sequence { session, seq }),
+            edits,
+            deleted,
+            mentions_me,
+            signature,
+            public_key,
+        }
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn chat_messages_roundtrip_through_json(message in chat_message()) {
+        let bytes = serde_json::to_vec(&message).unwrap();
+        let read: ChatMessage = serde_json::from_slice(&bytes).unwrap();
+        prop_assert_eq!(read, message);
+    }
+
+    #[test]
+    fn chat_messages_roundtrip_through_gossip_envelopes(message in chat_message()) {
+        let sealed = envelope::seal(&GossipMessage::Chat(Box::new(message.clone())));
+        let bytes = serde_json::to_vec(&sealed).unwrap();
+        match envelope::open_slice::<GossipMessage>(&bytes).unwrap() {
+            Opened::Known(GossipMessage::Chat(read)) => prop_assert_eq!(*read, message),
+            other => prop_assert!(false, "opened as {:?}", other),
+        }
+    }
+
+    #[test]
+    fn chat_messages_roundtrip_through_direct_requests(message in chat_message()) {
+        let sealed = envelope::seal(&DirectRequest::Message(message.clone()));
+        match envelope::open::<DirectRequest>(sealed).unwrap() {
+            Opened::Known(DirectRequest::Message(read)) => prop_assert_eq!(read, message),
+            other => prop_assert!(false, "opened as {:?}", other),
+        }
+    }
+
+    #[test]
+    fn signatures_survive_a_roundtrip(seed in any::<[u8; 32]>(), message in chat_message()) {
+        let keypair = keypair(seed);
+        let mut message = ChatMessage {
+            peer_id: keypair.public().to_peer_id(),
+            ..message
+        };
+        message.sign(&keypair).unwrap();
+
+        let read: ChatMessage = serde_json::from_str(&serde_json::to_string(&message).unwrap())
+            .unwrap();
+        prop_assert_eq!(read.verify_signature(), Ok(()));
+    }
+}
@@ -0,0 +1,422 @@
+use libp2p::PeerId;
+use libp2p_demo::{
+    config::FilterAction,
+    expiry::Expiries,
+    filter::ContentFilter,
+    history,
+    message::{unix_now, ChatMessage, MessageId},
+    store::{
+        Change, ChangeOutcome, MessageStore, DEFAULT_HISTORY_LIMIT, MAX_PENDING_CHANGES,
+        MAX_PENDING_PER_AUTHOR,
+    },
+    testing::peer,
+};
+use std::{collections::HashSet, time::Duration};
+
+fn message(peer_id: PeerId, room: &str, text: &str) -> ChatMessage {
+    ChatMessage {
+        room: Some(room.to_string()),
+        ..ChatMessage::new(peer_id, text.to_string())
+    }
+}
+
+#[test]
+fn oldest_message_of_a_full_room_is_evicted() {
+    let author = peer();
+    let mut store = MessageStore::new(DEFAULT_HISTORY_LIMIT, 2);
+    let first = message(author, "chat", "one");
+    let other_room = message(author, "rust", "elsewhere");
+    store.insert(first.clone());
+    store.insert(other_room.clone());
+    store.insert(message(author, "chat", "two"));
+    store.insert(message(author, "chat", "three"));
+
+    let texts: Vec<&str> = store
+        .messages()
+        .iter()
+        .map(|message| message.message.as_str())
+        .collect();
+    assert_eq!(texts, ["elsewhere", "two", "three"]);
+    assert!(store.get(&first.id).is_none());
+    assert!(store.get(&other_room.id).is_some());
+}
+
+#[test]
+fn oldest_message_of_any_room_is_evicted_past_the_limit() {
+    let author = peer();
+    let mut store = MessageStore::new(3, 10);
+    assert!(!store.has_evicted());
+    for n in 0..5 {
+        let room = if n % 2 == 0 { "chat" } else { "rust" };
+        store.insert(message(author, room, &n.to_string()));
+        assert!(store.messages().len() <= 3);
+    }
+
+    let texts: Vec<&str> = store
+        .messages()
+        .iter()
+        .map(|message| message.message.as_str())
+        .collect();
+    assert_eq!(texts, ["2", "3", "4"]);
+    assert!(store.has_evicted());
+}
+
+#[test]
+fn evicted_messages_are_still_deduplicated() {
+    let author = peer();
+    let mut store = MessageStore::new(DEFAULT_HISTORY_LIMIT, 1);
+    let first = message(author, "chat", "one");
+    assert!(store.insert(first.clone()));
+    assert!(store.insert(message(author, "chat", "two")));
+
+    assert!(store.has_seen(&first.id));
+    assert!(!store.insert(first));
+    assert_eq!(store.messages().len(), 1);
+}
+
+#[test]
+fn changes_to_evicted_messages_are_not_left_pending() {
+    let author = peer();
+    let mut store = MessageStore::new(DEFAULT_HISTORY_LIMIT, 1);
+    let first = message(author, "chat", "one");
+    store.insert(first.clone());
+    store.insert(message(author, "chat", "two"));
+
+    assert_eq!(
+        store.apply(author, first.id, Change::Edit("uno".to_string())),
+        ChangeOutcome::Evicted
+    );
+}
+
+#[test]
+fn pending_changes_apply_before_eviction() {
+    let author = peer();
+    let mut store = MessageStore::new(DEFAULT_HISTORY_LIMIT, 1);
+    let late = message(author, "chat", "late");
+    assert_eq!(
+        store.apply(author, late.id, Change::Edit("edited".to_string())),
+        ChangeOutcome::Pending
+    );
+
+    store.insert(late.clone());
+    assert_eq!(store.get(&late.id).unwrap().text(), "edited");
+    assert!(store.memory_usage() > 0);
+}
+
+#[test]
+fn an_author_flooding_changes_only_crowds_out_its_own() {
+    let (author, mallory) = (peer(), peer());
+    let mut store = MessageStore::default();
+    let awaited = message(author, "chat", "late");
+    store.apply(author, awaited.id, Change::Edit("edited".to_string()));
+
+    let flooded: Vec<ChatMessage> = (0..=MAX_PENDING_PER_AUTHOR)
+        .map(|_| message(mallory, "chat", "spam"))
+        .collect();
+    for target in &flooded {
+        store.apply(mallory, target.id, Change::Delete);
+    }
+
+    // Mallory's oldest change made way for the newest one.
+    store.insert(flooded[0].clone());
+    assert_eq!(store.get(&flooded[0].id).unwrap().text(), "spam");
+    store.insert(flooded[MAX_PENDING_PER_AUTHOR].clone());
+    assert!(
+        store
+            .get(&flooded[MAX_PENDING_PER_AUTHOR].id)
+            .unwrap()
+            .deleted
+    );
+    store.insert(awaited.clone());
+    assert_eq!(store.get(&awaited.id).unwrap().text(), "edited");
+}
+
+#[test]
+fn the_oldest_pending_change_makes_way_when_the_buffer_is_full() {
+    let mut store = MessageStore::default();
+    let targets: Vec<ChatMessage> = (0..=MAX_PENDING_CHANGES)
+        .map(|_| message(peer(), "chat", "original"))
+        .collect();
+    for target in &targets {
+        store.apply(
+            target.peer_id,
+            target.id,
+            Change::Edit("edited".to_string()),
+        );
+    }
+
+    store.insert(targets[0].clone());
+    assert_eq!(store.get(&targets[0].id).unwrap().text(), "original");
+    store.insert(targets[1].clone());
+    assert_eq!(store.get(&targets[1].id).unwrap().text(), "edited");
+}
+
+#[test]
+fn edits_from_the_author_replace_the_text_and_keep_the_timestamp() {
+    let author = peer();
+    let mut store = MessageStore::default();
+    let original = message(author, "chat", "helo");
+    store.insert(original.clone());
+
+    assert_eq!(
+        store.apply(author, original.id, Change::Edit("hello".to_string())),
+        ChangeOutcome::Applied
+    );
+    let edited = store.get(&original.id).unwrap();
+    assert_eq!(edited.text(), "hello");
+    assert_eq!(edited.timestamp, original.timestamp);
+    assert!(store.format(edited).ends_with("hello (edited)"));
+
+    assert_eq!(
+        store.apply(peer(), original.id, Change::Edit("hijacked".to_string())),
+        ChangeOutcome::Rejected
+    );
+    assert_eq!(store.get(&original.id).unwrap().text(), "hello");
+}
+
+#[test]
+fn only_the_author_can_delete_and_the_slot_is_kept() {
+    let author = peer();
+    let mut store = MessageStore::default();
+    let first = message(author, "chat", "first");
+    let second = message(author, "chat", "second");
+    store.insert(first.clone());
+    store.insert(second.clone());
+
+    assert_eq!(
+        store.apply(peer(), first.id, Change::Delete),
+        ChangeOutcome::Rejected
+    );
+    assert!(!store.get(&first.id).unwrap().deleted);
+
+    assert_eq!(
+        store.apply(author, first.id, Change::Delete),
+        ChangeOutcome::Applied
+    );
+    let ids: Vec<_> = store.messages().iter().map(|message| message.id).collect();
+    assert_eq!(ids, [first.id, second.id]);
+    assert!(store
+        .format(store.get(&first.id).unwrap())
+        .ends_with("[deleted]"));
+
+    // Deleted messages can't be edited back to life.
+    store.apply(author, first.id, Change::Edit("revived".to_string()));
+    assert_eq!(store.get(&first.id).unwrap().display_text(), "[deleted]");
+}
+
+#[test]
+fn reacting_twice_with_the_same_emoji_removes_the_reaction() {
+    let (author, alice, bob) = (peer(), peer(), peer());
+    let mut store = MessageStore::default();
+    let original = message(author, "chat", "ship it");
+    store.insert(original.clone());
+
+    for reactor in [alice, bob] {
+        assert_eq!(
+            store.apply(reactor, original.id, Change::React("👍".to_string())),
+            ChangeOutcome::Applied
+        );
+    }
+    store.apply(alice, original.id, Change::React("🎉".to_string()));
+    assert!(store
+        .format(store.get(&original.id).unwrap())
+        .ends_with("ship it  🎉 1  👍 2"));
+
+    store.apply(alice, original.id, Change::React("👍".to_string()));
+    store.apply(alice, original.id, Change::React("🎉".to_string()));
+    assert!(store
+        .format(store.get(&original.id).unwrap())
+        .ends_with("ship it  👍 1"));
+
+    // Reactions are tallied on the message, not stored as messages.
+    assert_eq!(store.messages().len(), 1);
+}
+
+fn reply(peer_id: PeerId, parent: &ChatMessage, text: &str) -> ChatMessage {
+    ChatMessage {
+        reply_to: Some(parent.id),
+        ..message(peer_id, "chat", text)
+    }
+}
+
+#[test]
+fn replies_quote_a_snippet_of_their_parent() {
+    let author = peer();
+    let mut store = MessageStore::default();
+    let parent = message(author, "chat", &"long question ".repeat(10));
+    let answer = reply(peer(), &parent, "short answer");
+    store.insert(parent.clone());
+    store.insert(answer.clone());
+
+    let formatted = store.format(&answer);
+    let (quote, line) = formatted.split_once('\n').unwrap();
+    assert!(quote.starts_with(&format!("  > {}: long question long", parent.sender())));
+    assert!(quote.ends_with('…'));
+    assert!(line.ends_with("short answer"));
+
+    let orphan = reply(author, &message(author, "chat", "never received"), "what?");
+    assert!(store
+        .format(&orphan)
+        .starts_with("  > (in reply to an unavailable message)\n"));
+}
+
+#[test]
+fn threads_nest_replies_under_the_message_they_answer() {
+    let author = peer();
+    let mut store = MessageStore::default();
+    let root = message(author, "chat", "root");
+    let first = ChatMessage {
+        timestamp: root.timestamp + 1,
+        ..reply(author, &root, "first")
+    };
+    let nested = ChatMessage {
+        timestamp: root.timestamp + 3,
+        ..reply(author, &first, "nested")
+    };
+    let second = ChatMessage {
+        timestamp: root.timestamp + 2,
+        ..reply(author, &root, "second")
+    };
+    for message in [&root, &nested, &second, &first] {
+        store.insert(message.clone());
+    }
+
+    let thread: Vec<(usize, &str)> = store
+        .thread(&root.id)
+        .into_iter()
+        .map(|(depth, message)| (depth, message.message.as_str()))
+        .collect();
+    assert_eq!(
+        thread,
+        [(0, "root"), (1, "first"), (2, "nested"), (1, "second")]
+    );
+
+    let from_reply: Vec<MessageId> = store
+        .thread(&first.id)
+        .into_iter()
+        .map(|(_, message)| message.id)
+        .collect();
+    assert_eq!(from_reply, [first.id, nested.id]);
+}
+
+#[test]
+fn replies_outlive_their_evicted_parent_in_the_thread() {
+    let author = peer();
+    let mut store = MessageStore::new(DEFAULT_HISTORY_LIMIT, 2);
+    let root = message(author, "chat", "root");
+    store.insert(root.clone());
+    store.insert(reply(author, &root, "first"));
+    store.insert(message(author, "chat", "unrelated"));
+
+    let thread: Vec<(usize, &str)> = store
+        .thread(&root.id)
+        .into_iter()
+        .map(|(depth, message)| (depth, message.message.as_str()))
+        .collect();
+    assert_eq!(thread, [(1, "first")]);
+
+    store.insert(message(author, "chat", "newer"));
+    assert!(store.thread(&root.id).is_empty());
+}
+
+#[test]
+fn our_names_for_peers_replace_the_nicknames_they_send() {
+    let friend = peer();
+    let mut store = MessageStore::default();
+    let parent = ChatMessage {
+        nickname: Some("self-announced".to_string()),
+        ..message(friend, "chat", "hi")
+    };
+    let answer = reply(peer(), &parent, "hello");
+    store.insert(parent.clone());
+    store.insert(answer.clone());
+
+    store.set_name(friend, Some("Sam".to_string()));
+    assert!(store.format(&parent).ends_with("Sam: hi"));
+    assert!(store.format(&answer).starts_with("  > Sam: hi\n"));
+
+    store.set_name(friend, None);
+    assert!(store.format(&parent).ends_with("self-announced: hi"));
+}
+
+#[tokio::test]
+async fn expired_messages_are_pruned() {
+    let author = peer();
+    let mut store = MessageStore::default();
+    let mut expiries = Expiries::default();
+    let ephemeral = ChatMessage {
+        ttl_secs: Some(1),
+        ..message(author, "chat", "gone soon")
+    };
+    let lasting = message(author, "chat", "here to stay");
+    let history_path =
+        std::env::temp_dir().join(format!("chat-history-{}.jsonl", uuid::Uuid::new_v4()));
+    for chat_message in [&ephemeral, &lasting] {
+        expiries.schedule(chat_message, unix_now());
+        store.insert(chat_message.clone());
+    }
+    // Written twice, as an edit would be.
+    history::append(&history_path, [&ephemeral, &lasting, &ephemeral]).unwrap();
+
+    let expired = tokio::time::timeout(Duration::from_secs(5), expiries.next())
+        .await
+        .expect("the message expires after its TTL");
+    assert_eq!(expired, Some(ephemeral.id));
+    assert!(store.remove(&ephemeral.id));
+    let removed = history::remove(&history_path, &HashSet::from([ephemeral.id])).unwrap();
+    assert_eq!(removed, 1);
+    let on_disk: Vec<_> = history::load(&history_path)
+        .unwrap()
+        .into_iter()
+        .map(|chat_message| chat_message.id)
+        .collect();
+    assert_eq!(on_disk, [lasting.id]);
+    assert!(store.get(&ephemeral.id).is_none());
+    assert!(store.get(&lasting.id).is_some());
+    // A copy relayed late is still recognised and not stored again.
+    assert!(!store.insert(ephemeral));
+    // Nothing else was scheduled.
+    assert_eq!(expiries.next().await, None);
+}
+
+#[test]
+fn clearing_a_room_leaves_the_others_and_clearing_all_empties_the_store() {
+    let author = peer();
+    let mut store = MessageStore::default();
+    let chat = message(author, "chat", "in chat");
+    let rust = message(author, "rust", "in rust");
+    let direct = ChatMessage::new(author, "direct".to_string());
+    for message in [&chat, &rust, &direct] {
+        store.insert(message.clone());
+    }
+    store.apply(peer(), rust.id, Change::React("👍".to_string()));
+
+    assert_eq!(store.clear(Some("chat")), 1);
+    assert!(store.get(&chat.id).is_none());
+    assert!(store.get(&rust.id).is_some());
+    assert!(store.get(&direct.id).is_some());
+    assert!(store.format(&rust).ends_with("👍 1"));
+    assert_eq!(store.clear(Some("chat")), 0);
+
+    assert_eq!(store.clear(None), 2);
+    assert!(store.messages().is_empty());
+    // Cleared messages relayed again aren't stored anew.
+    assert!(!store.insert(chat));
+}
+
+#[test]
+fn blocked_words_are_masked_in_messages_and_quotes() {
+    let mut store = MessageStore::default();
+    store.set_filter(ContentFilter::new(["spam"], FilterAction::Mask));
+    let parent = message(peer(), "chat", &format!("{}spam", "x ".repeat(29)));
+    let answer = reply(peer(), &parent, "no SPAM please");
+    store.insert(parent.clone());
+    store.insert(answer.clone());
+
+    let formatted = store.format(&answer);
+    let (quote, line) = formatted.split_once('\n').unwrap();
+    // Cut short after the mask, not before it.
+    assert!(quote.ends_with("x **…"), "{}", quote);
+    assert!(line.ends_with(": no **** please"));
+    assert_eq!(store.get(&answer.id).unwrap().message, "no SPAM please");
+}
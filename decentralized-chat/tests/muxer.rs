@@ -0,0 +1,91 @@
+use libp2p::{futures::StreamExt, request_response, swarm::SwarmEvent};
+use libp2p_demo::{
+    behaviour::{CustomBehaviourEvent, Request, Response},
+    config::{Config, Muxer, SwarmConfig},
+    dial::describe_dial_error,
+    message::{ChatMessage, DirectRequest, DirectResponse},
+    testing::{listening_tcp_swarm, tcp_swarm},
+};
+use serde_json::json;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(15);
+
+fn muxed_with(muxer: Muxer) -> Config {
+    Config {
+        swarm: SwarmConfig {
+            muxer,
+            ..SwarmConfig::default()
+        },
+        ..Config::default()
+    }
+}
+
+/// Sends a greeting from a fresh node to a listening one, both using
+/// `muxer`, and waits for the welcome.
+async fn greet_over(muxer: Muxer) {
+    let (mut alice, alice_addr) = listening_tcp_swarm(&muxed_with(muxer)).await;
+    let mut bob = tcp_swarm(&muxed_with(muxer)).await;
+    let alice_id = *alice.local_peer_id();
+
+    bob.add_peer_address(alice_id, alice_addr);
+    let greeting = ChatMessage::new(*bob.local_peer_id(), "Hello".to_string());
+    bob.behaviour_mut().request_response.send_request(
+        &alice_id,
+        Request {
+            data: json!(DirectRequest::Greeting(greeting)),
+        },
+    );
+
+    let welcome = tokio::time::timeout(TIMEOUT, async {
+        loop {
+            tokio::select! {
+                event = alice.select_next_some() => {
+                    if let SwarmEvent::Behaviour(CustomBehaviourEvent::RequestResponse(
+                        request_response::Event::Message {
+                            message: request_response::Message::Request { channel, .. },
+                            ..
+                        },
+                    )) = event
+                    {
+                        let welcome = ChatMessage::new(alice_id, "Welcome!".to_string());
+                        alice
+                            .behaviour_mut()
+                            .request_response
+                            .send_response(channel, Response { data: json!(DirectResponse::Welcome(Box::new(welcome))) })
+                            .unwrap();
+                    }
+                }
+                event = bob.select_next_some() => match event {
+                    SwarmEvent::Behaviour(CustomBehaviourEvent::RequestResponse(
+                        request_response::Event::Message {
+                            message: request_response::Message::Response { response, .. },
+                            ..
+                        },
+                    )) => break serde_json::from_value::<DirectResponse>(response.data).unwrap(),
+                    SwarmEvent::OutgoingConnectionError { error, .. } => {
+                        panic!("dial failed: {}", describe_dial_error(&error))
+                    }
+                    _ => {}
+                },
+            }
+        }
+    })
+    .await
+    .unwrap_or_else(|_| panic!("no welcome over {} in time", muxer));
+
+    let DirectResponse::Welcome(welcome) = welcome else {
+        panic!("expected a welcome, got {:?}", welcome);
+    };
+    assert_eq!(welcome.peer_id, alice_id);
+}
+
+#[tokio::test]
+async fn yamux_carries_a_request_and_response() {
+    greet_over(Muxer::Yamux).await;
+}
+
+#[tokio::test]
+async fn mplex_carries_a_request_and_response() {
+    greet_over(Muxer::Mplex).await;
+}
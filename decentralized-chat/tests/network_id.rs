@@ -0,0 +1,92 @@
+use libp2p::{
+    futures::StreamExt,
+    swarm::{ListenError, SwarmEvent},
+};
+use libp2p_demo::{
+    config::{Config, SwarmConfig},
+    dial::{describe_dial_error, is_handshake_failure},
+    testing::{listening_tcp_swarm, tcp_swarm},
+};
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(15);
+
+fn on_network(network_id: Option<&str>) -> Config {
+    Config {
+        swarm: SwarmConfig {
+            network_id: network_id.map(str::to_string),
+            ..SwarmConfig::default()
+        },
+        ..Config::default()
+    }
+}
+
+#[tokio::test]
+async fn nodes_of_the_same_network_connect() {
+    let (mut alice, alice_addr) = listening_tcp_swarm(&on_network(Some("staging"))).await;
+    let mut bob = tcp_swarm(&on_network(Some("staging"))).await;
+    let alice_id = *alice.local_peer_id();
+    bob.dial(alice_addr).unwrap();
+
+    tokio::time::timeout(TIMEOUT, async {
+        loop {
+            tokio::select! {
+                _ = alice.select_next_some() => {}
+                event = bob.select_next_some() => match event {
+                    SwarmEvent::ConnectionEstablished { peer_id, .. } => {
+                        assert_eq!(peer_id, alice_id);
+                        break;
+                    }
+                    SwarmEvent::OutgoingConnectionError { error, .. } => {
+                        panic!("failed to connect: {}", describe_dial_error(&error))
+                    }
+                    _ => {}
+                },
+            }
+        }
+    })
+    .await
+    .expect("the nodes did not connect in time");
+}
+
+#[tokio::test]
+async fn nodes_of_other_networks_fail_the_handshake() {
+    // A node without a network id has an empty prologue, which differs too.
+    for (listener, dialer) in [
+        (Some("staging"), Some("production")),
+        (Some("staging"), None),
+    ] {
+        let (mut alice, alice_addr) = listening_tcp_swarm(&on_network(listener)).await;
+        let mut bob = tcp_swarm(&on_network(dialer)).await;
+        bob.dial(alice_addr).unwrap();
+
+        let (mut inbound, mut outbound) = (None, None);
+        tokio::time::timeout(TIMEOUT, async {
+            while inbound.is_none() || outbound.is_none() {
+                tokio::select! {
+                    event = alice.select_next_some() => match event {
+                        SwarmEvent::IncomingConnectionError {
+                            error: ListenError::Transport(error),
+                            ..
+                        } => inbound = Some(is_handshake_failure(&error)),
+                        SwarmEvent::ConnectionEstablished { .. } => {
+                            panic!("{:?} and {:?} connected", listener, dialer)
+                        }
+                        _ => {}
+                    },
+                    event = bob.select_next_some() => {
+                        if let SwarmEvent::OutgoingConnectionError { error, .. } = event {
+                            outbound = Some(describe_dial_error(&error));
+                        }
+                    }
+                }
+            }
+        })
+        .await
+        .expect("the handshake did not fail in time");
+
+        assert_eq!(inbound, Some(true));
+        let outbound = outbound.unwrap();
+        assert!(outbound.contains("--network-id"), "{}", outbound);
+    }
+}
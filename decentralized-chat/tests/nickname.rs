@@ -0,0 +1,91 @@
+use libp2p::{gossipsub, swarm::SwarmEvent};
+use libp2p_demo::{
+    behaviour::CustomBehaviourEvent,
+    envelope::{open_slice, seal, Opened},
+    message::GossipMessage,
+    nickname::{validate_nickname, Nicknames},
+    testing::{connect, drive_until, peer, TestNode},
+};
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[test]
+fn nicknames_are_checked() {
+    assert_eq!(validate_nickname("alice"), Ok(()));
+    assert_eq!(validate_nickname("zoë 👋"), Ok(()));
+    assert_eq!(validate_nickname(&"é".repeat(32)), Ok(()));
+
+    assert!(validate_nickname("").is_err());
+    assert!(validate_nickname(&"a".repeat(33)).is_err());
+    assert!(validate_nickname("ali\nce").is_err());
+    assert!(validate_nickname("\x1b[31malice").is_err());
+    assert!(validate_nickname(" alice").is_err());
+}
+
+#[tokio::test]
+async fn an_announcement_updates_the_remote_registry() {
+    let mut nodes = [TestNode::new().await, TestNode::new().await];
+    let topic = gossipsub::IdentTopic::new("chat");
+    for node in &mut nodes {
+        node.swarm
+            .behaviour_mut()
+            .gossipsub
+            .subscribe(&topic)
+            .unwrap();
+    }
+    connect(&mut nodes, 1, 0).await;
+
+    let alice = nodes[1].peer_id();
+    let announcement = GossipMessage::Nickname {
+        name: "alice".to_string(),
+    };
+    let payload = serde_json::to_vec(&seal(&announcement)).unwrap();
+    let mut nicknames = Nicknames::default();
+    let mut published = false;
+
+    drive_until(&mut nodes, TIMEOUT, |index, event, nodes| match event {
+        SwarmEvent::Behaviour(CustomBehaviourEvent::Gossipsub(gossipsub::Event::Message {
+            message,
+            ..
+        })) if index == 0 => {
+            let Ok(Opened::Known(GossipMessage::Nickname { name })) =
+                open_slice::<GossipMessage>(&message.data)
+            else {
+                panic!("expected a nickname announcement");
+            };
+            assert_eq!(message.source, Some(alice));
+            Some(nicknames.record(alice, &name).unwrap())
+        }
+        SwarmEvent::Behaviour(CustomBehaviourEvent::Gossipsub(gossipsub::Event::Subscribed {
+            ..
+        })) if index == 1 && !published => {
+            nodes[1]
+                .swarm
+                .behaviour_mut()
+                .gossipsub
+                .publish(topic.clone(), payload.clone())
+                .unwrap();
+            published = true;
+            None
+        }
+        _ => None,
+    })
+    .await;
+
+    assert_eq!(nicknames.get(&alice), Some("alice"));
+}
+
+#[test]
+fn invalid_or_repeated_names_change_nothing() {
+    let peer = peer();
+    let mut nicknames = Nicknames::default();
+
+    assert_eq!(nicknames.record(peer, "alice"), Ok(true));
+    assert_eq!(nicknames.record(peer, "alice"), Ok(false));
+    assert!(nicknames.record(peer, "bob\x07").is_err());
+    assert_eq!(nicknames.get(&peer), Some("alice"));
+
+    assert_eq!(nicknames.record(peer, "bob"), Ok(true));
+    assert_eq!(nicknames.get(&peer), Some("bob"));
+}
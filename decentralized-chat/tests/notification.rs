@@ -0,0 +1,68 @@
+use libp2p_demo::{
+    notification::{truncate, Notifier},
+    testing::peer,
+};
+use std::time::{Duration, Instant};
+
+#[test]
+fn long_bodies_are_truncated() {
+    assert_eq!(truncate("short", 10), "short");
+    assert_eq!(truncate("exactly10!", 10), "exactly10!");
+    assert_eq!(truncate("this is too long", 10), "this is t…");
+    assert_eq!(truncate("héllo wörld", 5), "héll…");
+}
+
+#[test]
+fn bursts_are_summed_up_in_the_senders_next_notification() {
+    let start = Instant::now();
+    let mut notifier = Notifier::new(Duration::from_secs(10));
+    let alice = peer();
+
+    assert_eq!(
+        notifier.next(start, alice, "alice", "first"),
+        Some(("alice".to_string(), "first".to_string()))
+    );
+    assert_eq!(
+        notifier.next(start + Duration::from_secs(1), alice, "alice", "second"),
+        None
+    );
+    assert_eq!(
+        notifier.next(start + Duration::from_secs(2), alice, "alice", "third"),
+        None
+    );
+
+    assert_eq!(
+        notifier.next(start + Duration::from_secs(11), alice, "alice", "fourth"),
+        Some((
+            "alice".to_string(),
+            "fourth\n(and 2 more messages)".to_string()
+        ))
+    );
+    assert_eq!(
+        notifier.next(start + Duration::from_secs(12), alice, "alice", "fifth"),
+        None
+    );
+    assert_eq!(
+        notifier.next(start + Duration::from_secs(22), alice, "alice", "sixth"),
+        Some((
+            "alice".to_string(),
+            "sixth\n(and 1 more message)".to_string()
+        ))
+    );
+}
+
+#[test]
+fn each_sender_has_its_own_limit() {
+    let start = Instant::now();
+    let mut notifier = Notifier::new(Duration::from_secs(10));
+    let (alice, bob) = (peer(), peer());
+
+    assert!(notifier.next(start, alice, "alice", "hi").is_some());
+    assert_eq!(
+        notifier.next(start + Duration::from_secs(1), bob, "bob", "hello"),
+        Some(("bob".to_string(), "hello".to_string()))
+    );
+    assert!(notifier
+        .next(start + Duration::from_secs(2), alice, "alice", "again")
+        .is_none());
+}
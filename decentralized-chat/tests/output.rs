@@ -0,0 +1,80 @@
+use libp2p_demo::output::OutputBatch;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+const INTERVAL: Duration = Duration::from_millis(50);
+
+#[test]
+fn a_lone_message_is_written_at_once() {
+    let mut output = OutputBatch::new(INTERVAL);
+    let start = Instant::now();
+
+    assert_eq!(
+        output.push(Uuid::new_v4(), "one".to_string(), start),
+        ["one"]
+    );
+    let later = start + INTERVAL;
+    assert_eq!(
+        output.push(Uuid::new_v4(), "two".to_string(), later),
+        ["two"]
+    );
+    assert!(output.is_empty());
+}
+
+#[test]
+fn a_burst_is_written_in_batches_without_losing_lines() {
+    let mut output = OutputBatch::new(INTERVAL);
+    let start = Instant::now();
+
+    let mut written = Vec::new();
+    for i in 0..500u64 {
+        let now = start + Duration::from_micros(i * 200);
+        written.extend(output.push(Uuid::new_v4(), i.to_string(), now));
+        written.extend(output.flush(now));
+    }
+    // The first line went straight out, and the next 250 a whole interval later.
+    assert_eq!(written.len(), 1 + 250);
+
+    written.extend(output.flush(start + Duration::from_millis(100)));
+    let expected: Vec<String> = (0..500).map(|i| i.to_string()).collect();
+    assert_eq!(written, expected);
+    assert!(output.is_empty());
+}
+
+#[test]
+fn updates_to_a_waiting_line_replace_it() {
+    let mut output = OutputBatch::new(INTERVAL);
+    let start = Instant::now();
+    let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+
+    output.push(Uuid::new_v4(), "earlier".to_string(), start);
+    output.push(first, "hello".to_string(), start);
+    output.push(second, "world".to_string(), start);
+    output.push(first, "hello (edited)".to_string(), start);
+
+    assert!(output.flush(start + INTERVAL / 2).is_empty());
+    assert_eq!(output.flush(start + INTERVAL), ["hello (edited)", "world"]);
+}
+
+#[test]
+fn take_writes_waiting_lines_early() {
+    let mut output = OutputBatch::new(INTERVAL);
+    let start = Instant::now();
+
+    output.push(Uuid::new_v4(), "first".to_string(), start);
+    output.push(Uuid::new_v4(), "second".to_string(), start);
+    assert_eq!(output.take(start), ["second"]);
+    assert!(output.take(start).is_empty());
+}
+
+#[test]
+fn a_zero_interval_never_batches() {
+    let mut output = OutputBatch::new(Duration::ZERO);
+    let now = Instant::now();
+    for i in 0..10 {
+        assert_eq!(
+            output.push(Uuid::new_v4(), i.to_string(), now),
+            [i.to_string()]
+        );
+    }
+}
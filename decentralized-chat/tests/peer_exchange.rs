@@ -0,0 +1,147 @@
+use libp2p::{Multiaddr, PeerId};
+use libp2p_demo::{
+    address_book::{self, AddressBook},
+    peer_exchange::{accept, share, DialQueue, PeerRecord, MAX_ADDRESSES, MAX_PEERS},
+    testing::peer,
+};
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
+
+fn address(port: u16) -> Multiaddr {
+    format!("/ip4/192.0.2.1/tcp/{}", port).parse().unwrap()
+}
+
+fn record(peer_id: PeerId, addresses: usize) -> PeerRecord {
+    PeerRecord {
+        peer_id,
+        addresses: (0..addresses as u16)
+            .map(|port| address(4000 + port))
+            .collect(),
+        seen_secs_ago: 0,
+    }
+}
+
+#[test]
+fn the_most_recent_peers_are_shared_without_the_requester_or_blocked_peers() {
+    let mut book = AddressBook::default();
+    let (requester, blocked, stale) = (peer(), peer(), peer());
+    book.record(requester, address(1), 1000);
+    book.record(blocked, address(2), 1000);
+    book.record(stale, address(3), 10);
+    let mut recent = Vec::new();
+    for i in 0..20 {
+        let peer = peer();
+        for port in 0..6 {
+            book.record(peer, address(100 * i + port), 500 + u64::from(i));
+        }
+        recent.push(peer);
+    }
+
+    let shared = share(&book, &requester, &HashSet::from([blocked]), 1000);
+    assert_eq!(shared.len(), MAX_PEERS);
+    let shared_peers: Vec<PeerId> = shared.iter().map(|record| record.peer_id).collect();
+    let newest: Vec<PeerId> = recent.iter().rev().take(MAX_PEERS).copied().collect();
+    assert_eq!(shared_peers, newest);
+    assert!(shared
+        .iter()
+        .all(|record| record.addresses.len() == MAX_ADDRESSES));
+    assert_eq!(shared[0].seen_secs_ago, 1000 - 519);
+}
+
+#[test]
+fn responses_are_capped_and_filtered() {
+    let (local, blocked) = (peer(), peer());
+    let mut peers = vec![record(local, 1), record(blocked, 1), record(peer(), 0)];
+    peers.extend((0..30).map(|_| record(peer(), 10)));
+
+    let accepted = accept(peers, &local, &HashSet::from([blocked]));
+    assert_eq!(accepted.len(), MAX_PEERS);
+    assert!(accepted
+        .iter()
+        .all(|record| record.peer_id != local && record.peer_id != blocked));
+    assert!(accepted
+        .iter()
+        .all(|record| record.addresses.len() == MAX_ADDRESSES));
+}
+
+#[test]
+fn shared_peers_keep_when_they_were_last_seen() {
+    let shared = PeerRecord {
+        seen_secs_ago: 60,
+        ..record(peer(), 2)
+    };
+    let entry = shared.entry(1000);
+    assert_eq!(entry.last_seen, 940);
+    assert_eq!(entry.addresses, shared.addresses);
+
+    let mut book = AddressBook::default();
+    book.record(shared.peer_id, address(4000), 990);
+    book.merge(shared.peer_id, &entry);
+    let (_, merged) = book.most_recent()[0];
+    assert_eq!(merged.last_seen, 990);
+    assert_eq!(merged.addresses, shared.addresses);
+}
+
+#[test]
+fn learned_peers_are_not_taken_as_more_recent_than_seen_ones() {
+    let mut book = AddressBook::default();
+    let (seen, heard_of) = (peer(), peer());
+    book.record(seen, address(1), 500);
+
+    // The sharer claims both were seen just now.
+    book.merge(seen, &record(seen, 1).entry(1000));
+    book.merge(heard_of, &record(heard_of, 1).entry(1000));
+
+    let order: Vec<(PeerId, u64, bool)> = book
+        .most_recent()
+        .into_iter()
+        .map(|(peer, entry)| (*peer, entry.last_seen, entry.learned))
+        .collect();
+    assert_eq!(order, [(seen, 500, false), (heard_of, 1000, true)]);
+
+    // Until we see it ourselves.
+    book.seen(&heard_of, 600);
+    assert_eq!(book.most_recent()[0].0, &heard_of);
+}
+
+#[test]
+fn dials_are_spread_over_the_delay() {
+    let mut queue = DialQueue::new(Duration::from_secs(10));
+    let now = Instant::now();
+    let peers: Vec<PeerId> = (0..50).map(|_| peer()).collect();
+    for peer in &peers {
+        queue.schedule(record(*peer, 1), now);
+    }
+    // Queued once however often the peer is shared.
+    queue.schedule(record(peers[0], 3), now);
+    assert_eq!(queue.len(), 50);
+
+    let early = queue.due(now + Duration::from_secs(5)).len();
+    assert!((1..50).contains(&early), "{} of 50 due halfway", early);
+    let mut rest = queue.due(now + Duration::from_secs(10));
+    assert_eq!(early + rest.len(), 50);
+    assert!(queue.is_empty());
+
+    rest.retain(|record| record.peer_id == peers[0]);
+    assert!(rest.iter().all(|record| record.addresses.len() == 1));
+}
+
+#[test]
+fn each_peer_keeps_only_its_newest_addresses() {
+    let mut book = AddressBook::default();
+    let known = peer();
+    assert!(book.record(known, address(1), 100));
+    assert!(!book.record(known, address(1), 200));
+    for port in 2..=address_book::MAX_ADDRESSES as u16 + 1 {
+        assert!(book.record(known, address(port), 300));
+    }
+    let addresses = book.get(&known).unwrap().addresses.clone();
+    assert_eq!(addresses.len(), address_book::MAX_ADDRESSES);
+    assert!(!addresses.contains(&address(1)));
+
+    // Shared addresses don't push out ones we've seen ourselves.
+    book.merge(known, &record(known, 4).entry(1000));
+    assert_eq!(book.get(&known).unwrap().addresses, addresses);
+}
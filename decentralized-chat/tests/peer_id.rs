@@ -0,0 +1,43 @@
+use libp2p_demo::{
+    peer_id::{short_peer_id, shorten, show_full_ids},
+    testing::peer,
+};
+
+#[test]
+fn ids_keep_their_first_and_last_four_characters() {
+    assert_eq!(
+        shorten("12D3KooWHcyuGBzpDQ7hNuwn6233QbS3eycbPngPFm8jHNx31m6d"),
+        "12D3…1m6d"
+    );
+    assert_eq!(
+        shorten("QmYyQSo1c1Ym7orWxLYvCrM2EmxFTANf8wXmmE7DWjhx5N"),
+        "QmYy…hx5N"
+    );
+}
+
+#[test]
+fn short_ids_are_left_alone() {
+    assert_eq!(shorten(""), "");
+    assert_eq!(shorten("12D3"), "12D3");
+    assert_eq!(shorten("123456789"), "123456789");
+    assert_eq!(shorten("1234567890"), "1234…7890");
+    // Counted in characters, not bytes.
+    assert_eq!(shorten("ééééééééé"), "ééééééééé");
+}
+
+// The only test touching the process-wide setting, so the others don't race
+// with it.
+#[test]
+fn full_ids_can_be_turned_back_on() {
+    let peer = peer();
+    let full = peer.to_string();
+
+    let short = short_peer_id(&peer);
+    assert_eq!(short, shorten(&full));
+    assert_eq!(short, short_peer_id(&peer), "shortening is deterministic");
+    assert!(full.starts_with(&short[..4]) && full.ends_with(&short[short.len() - 4..]));
+
+    show_full_ids(true);
+    assert_eq!(short_peer_id(&peer), full);
+    show_full_ids(false);
+}
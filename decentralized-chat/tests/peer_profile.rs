@@ -0,0 +1,190 @@
+use libp2p::{request_response, swarm::SwarmEvent};
+use libp2p_demo::{
+    behaviour::{CustomBehaviourEvent, Request, Response},
+    envelope::{open, seal, Opened},
+    message::{DirectRequest, DirectResponse},
+    peer_profile::{
+        validate_avatar, validate_status, PeerProfile, ProfileCache, MAX_AVATAR_BYTES,
+        MAX_STATUS_LEN,
+    },
+    testing::{connect, drive_until, peer, TestNode},
+};
+use std::{fs, path::PathBuf, time::Duration};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+const PNG_MAGIC: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+fn png(len: usize) -> Vec<u8> {
+    let mut png = PNG_MAGIC.to_vec();
+    png.resize(len, 0);
+    png
+}
+
+fn cache_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("chat-peer-profiles-{}", uuid::Uuid::new_v4()))
+}
+
+#[test]
+fn statuses_are_checked() {
+    assert_eq!(validate_status("out for lunch 🍜"), Ok(()));
+    assert_eq!(validate_status(&"é".repeat(MAX_STATUS_LEN)), Ok(()));
+
+    assert!(validate_status(&"a".repeat(MAX_STATUS_LEN + 1)).is_err());
+    assert!(validate_status("\x1b[31mbusy").is_err());
+}
+
+#[test]
+fn avatars_must_be_small_pngs() {
+    assert_eq!(validate_avatar(&png(MAX_AVATAR_BYTES)), Ok(()));
+
+    assert!(validate_avatar(&png(MAX_AVATAR_BYTES + 1)).is_err());
+    assert!(validate_avatar(b"GIF89a not a png").is_err());
+    assert!(validate_avatar(&PNG_MAGIC[..4]).is_err());
+}
+
+#[test]
+fn cached_profiles_survive_a_restart_until_invalidated() {
+    let dir = cache_dir();
+    let (alice, bob) = (peer(), peer());
+    let profile = PeerProfile {
+        status: Some("around".to_string()),
+        avatar: Some(png(64)),
+    };
+
+    let mut cache = ProfileCache::load(dir.clone()).unwrap();
+    cache.store(alice, profile.clone()).unwrap();
+    cache.store(bob, PeerProfile::default()).unwrap();
+
+    let mut cache = ProfileCache::load(dir.clone()).unwrap();
+    assert_eq!(cache.get(&alice), Some(&profile));
+    assert_eq!(cache.get(&bob), Some(&PeerProfile::default()));
+
+    cache.invalidate(&alice).unwrap();
+    assert_eq!(cache.get(&alice), None);
+    assert_eq!(ProfileCache::load(dir).unwrap().get(&alice), None);
+    // Forgetting a profile that was never fetched is fine.
+    cache.invalidate(&peer()).unwrap();
+}
+
+#[test]
+fn invalid_profiles_are_rejected_without_storing() {
+    let dir = cache_dir();
+    let peer = peer();
+    let mut cache = ProfileCache::load(dir.clone()).unwrap();
+
+    for profile in [
+        PeerProfile {
+            avatar: Some(png(MAX_AVATAR_BYTES + 1)),
+            ..PeerProfile::default()
+        },
+        PeerProfile {
+            avatar: Some(b"<svg/>".to_vec()),
+            ..PeerProfile::default()
+        },
+        PeerProfile {
+            status: Some("x".repeat(MAX_STATUS_LEN + 1)),
+            ..PeerProfile::default()
+        },
+    ] {
+        assert!(cache.store(peer, profile).is_err());
+        assert_eq!(cache.get(&peer), None);
+    }
+    assert!(!dir.join(format!("{}.json", peer)).exists());
+}
+
+#[test]
+fn tampered_cache_files_are_skipped() {
+    let dir = cache_dir();
+    let peer = peer();
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(
+        dir.join(format!("{}.json", peer)),
+        serde_json::to_vec(&PeerProfile {
+            avatar: Some(b"not an image".to_vec()),
+            ..PeerProfile::default()
+        })
+        .unwrap(),
+    )
+    .unwrap();
+    fs::write(dir.join("notes.txt"), b"ignored").unwrap();
+
+    assert_eq!(ProfileCache::load(dir).unwrap().get(&peer), None);
+}
+
+#[test]
+fn avatar_hashes_tell_avatars_apart() {
+    let with = |avatar: Vec<u8>| PeerProfile {
+        avatar: Some(avatar),
+        ..PeerProfile::default()
+    };
+    let hash = with(png(64)).avatar_hash().unwrap();
+    assert_eq!(hash.len(), 16);
+    assert_eq!(with(png(64)).avatar_hash().unwrap(), hash);
+    assert_ne!(with(png(65)).avatar_hash().unwrap(), hash);
+    assert_eq!(PeerProfile::default().avatar_hash(), None);
+}
+
+#[tokio::test]
+async fn the_largest_avatar_fits_in_a_profile_response() {
+    let mut nodes = [TestNode::new().await, TestNode::new().await];
+    connect(&mut nodes, 1, 0).await;
+
+    let profile = PeerProfile {
+        status: Some("s".repeat(MAX_STATUS_LEN)),
+        avatar: Some(png(MAX_AVATAR_BYTES)),
+    };
+    let alice = nodes[0].peer_id();
+    nodes[1]
+        .swarm
+        .behaviour_mut()
+        .request_response
+        .send_request(
+            &alice,
+            Request {
+                data: seal(&DirectRequest::Profile),
+            },
+        );
+
+    let received = drive_until(&mut nodes, TIMEOUT, |index, event, nodes| {
+        let SwarmEvent::Behaviour(CustomBehaviourEvent::RequestResponse(
+            request_response::Event::Message { message, .. },
+        )) = event
+        else {
+            return None;
+        };
+        match message {
+            request_response::Message::Request {
+                request, channel, ..
+            } if index == 0 => {
+                assert!(matches!(
+                    open(request.data),
+                    Ok(Opened::Known(DirectRequest::Profile))
+                ));
+                nodes[0]
+                    .swarm
+                    .behaviour_mut()
+                    .request_response
+                    .send_response(
+                        channel,
+                        Response {
+                            data: seal(&DirectResponse::Profile(profile.clone())),
+                        },
+                    )
+                    .unwrap();
+                None
+            }
+            request_response::Message::Response { response, .. } => {
+                let Ok(Opened::Known(DirectResponse::Profile(received))) = open(response.data)
+                else {
+                    panic!("expected a profile");
+                };
+                Some(received)
+            }
+            _ => None,
+        }
+    })
+    .await;
+    assert_eq!(received, profile);
+    assert_eq!(received.check(), Ok(()));
+}
@@ -0,0 +1,165 @@
+use libp2p_demo::{
+    daemon::{self, PidFile, PID_FILE},
+    key::KEY_FILE,
+    profile::{self, DEFAULT_PROFILE},
+};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    process::{Child, Command, Output, Stdio},
+    time::{Duration, Instant},
+};
+
+fn state_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("profile-test-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn names(root: &Path) -> Vec<String> {
+    profile::list(root)
+        .unwrap()
+        .into_iter()
+        .map(|profile| profile.name)
+        .collect()
+}
+
+#[test]
+fn profiles_are_created_listed_and_deleted() {
+    let root = state_dir("lifecycle");
+    assert!(names(&root).is_empty());
+
+    let work = profile::create(&root, "work").unwrap();
+    assert_eq!(work, profile::dir(&root, "work"));
+    profile::create(&root, "friends").unwrap();
+    assert_eq!(names(&root), ["friends", "work"]);
+    assert_eq!(
+        profile::create(&root, "work").unwrap_err().kind(),
+        io::ErrorKind::AlreadyExists
+    );
+
+    profile::delete(&root, "work").unwrap();
+    assert!(!work.exists());
+    assert_eq!(names(&root), ["friends"]);
+    assert_eq!(
+        profile::delete(&root, "work").unwrap_err().kind(),
+        io::ErrorKind::NotFound
+    );
+}
+
+#[test]
+fn profile_names_are_single_path_components() {
+    for name in ["work", "side-project", "team_2"] {
+        assert!(profile::check_name(name).is_ok(), "{}", name);
+    }
+    for name in ["", "..", "a/b", "with space"] {
+        assert!(profile::check_name(name).is_err(), "{}", name);
+    }
+}
+
+#[test]
+fn a_profile_with_a_running_daemon_is_not_deleted() {
+    let root = state_dir("running");
+    let dir = profile::create(&root, "work").unwrap();
+    let pid_file = PidFile::create(dir.join(PID_FILE)).unwrap();
+
+    let listed = profile::list(&root).unwrap();
+    assert_eq!(listed[0].daemon, Some(std::process::id()));
+    let error = profile::delete(&root, "work").unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::ResourceBusy);
+    assert!(dir.exists());
+
+    drop(pid_file);
+    profile::delete(&root, "work").unwrap();
+}
+
+#[test]
+fn state_from_before_profiles_moves_to_the_default_profile() {
+    let root = state_dir("legacy");
+    std::fs::write(root.join(KEY_FILE), b"key").unwrap();
+    std::fs::write(root.join("history.jsonl"), b"").unwrap();
+
+    assert!(profile::migrate_legacy(&root).unwrap());
+    let default = profile::dir(&root, DEFAULT_PROFILE);
+    assert_eq!(std::fs::read(default.join(KEY_FILE)).unwrap(), b"key");
+    assert!(default.join("history.jsonl").exists());
+    assert!(!root.join(KEY_FILE).exists());
+
+    assert!(!profile::migrate_legacy(&root).unwrap());
+}
+
+fn node(home: &Path, args: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_libp2p-demo"));
+    command.env("HOME", home).args(args);
+    command
+}
+
+fn start_daemon(home: &Path, name: &str) -> Child {
+    node(
+        home,
+        &[
+            "--profile",
+            name,
+            "--daemon",
+            "--no-mdns",
+            "--control-socket",
+        ],
+    )
+    .arg(home.join(format!("{}.sock", name)))
+    .arg("--log-file")
+    .arg(home.join(format!("{}.log", name)))
+    .stdin(Stdio::null())
+    .spawn()
+    .unwrap()
+}
+
+fn wait_for_daemon(pid_path: &Path) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while daemon::running_pid(pid_path).is_err() {
+        assert!(Instant::now() < deadline, "the daemon wrote no PID file");
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn two_profiles_run_at_once() {
+    let home = state_dir("simultaneous");
+    let root = home.join(".decentralized-chat");
+    let mut work = start_daemon(&home, "work");
+    let mut friends = start_daemon(&home, "friends");
+    for name in ["work", "friends"] {
+        wait_for_daemon(&profile::dir(&root, name).join(PID_FILE));
+    }
+
+    let list = node(&home, &["profiles", "list"]).output().unwrap();
+    assert!(stdout(&list).contains(&format!("work (daemon running, pid {})", work.id())));
+    let work_key = std::fs::read(profile::dir(&root, "work").join(KEY_FILE)).unwrap();
+    let friends_key = std::fs::read(profile::dir(&root, "friends").join(KEY_FILE)).unwrap();
+    assert_ne!(work_key, friends_key);
+
+    let unconfirmed = node(&home, &["profiles", "delete", "work"])
+        .output()
+        .unwrap();
+    assert!(!unconfirmed.status.success());
+    let refused = node(&home, &["profiles", "delete", "work", "--yes"])
+        .output()
+        .unwrap();
+    assert!(!refused.status.success());
+    assert!(profile::dir(&root, "work").exists());
+
+    for (name, daemon) in [("work", &mut work), ("friends", &mut friends)] {
+        let stop = node(&home, &["--profile", name, "stop"]).output().unwrap();
+        assert!(stop.status.success(), "{}", stdout(&stop));
+        assert!(daemon.wait().unwrap().success());
+    }
+    let deleted = node(&home, &["profiles", "delete", "work", "--yes"])
+        .output()
+        .unwrap();
+    assert!(deleted.status.success());
+    assert!(!profile::dir(&root, "work").exists());
+}
@@ -0,0 +1,92 @@
+use libp2p_demo::{
+    rate_limit::{Decision, RateLimiter},
+    testing::peer,
+};
+use std::time::{Duration, Instant};
+
+fn limiter() -> RateLimiter {
+    RateLimiter::new(10.0, 30, Duration::from_secs(60), Duration::from_secs(600))
+}
+
+#[test]
+fn a_burst_is_allowed_then_messages_are_dropped() {
+    let mut limiter = limiter();
+    let (alice, bob) = (peer(), peer());
+    let now = Instant::now();
+
+    for _ in 0..30 {
+        assert_eq!(limiter.check(alice, now), Decision::Allow);
+    }
+    assert_eq!(limiter.check(alice, now), Decision::Drop { first: true });
+    assert_eq!(limiter.check(alice, now), Decision::Drop { first: false });
+    // Each peer has its own bucket.
+    assert_eq!(limiter.check(bob, now), Decision::Allow);
+
+    // Tokens come back at the configured rate.
+    let later = now + Duration::from_millis(100);
+    assert_eq!(limiter.check(alice, later), Decision::Allow);
+    assert_eq!(limiter.check(alice, later), Decision::Drop { first: false });
+}
+
+#[test]
+fn peers_over_the_limit_for_a_minute_are_blocked_for_the_cooldown() {
+    let mut limiter = limiter();
+    let alice = peer();
+    let start = Instant::now();
+
+    // Twice the allowed rate, so the bucket never fills back up.
+    let mut now = start;
+    let mut blocked_at = None;
+    while now < start + Duration::from_secs(120) {
+        if limiter.check(alice, now) == Decision::Block {
+            blocked_at = Some(now);
+            break;
+        }
+        now += Duration::from_millis(50);
+    }
+    let blocked_at = blocked_at.expect("flooding peer was never blocked");
+    assert!(blocked_at >= start + Duration::from_secs(60));
+    assert!(blocked_at < start + Duration::from_secs(65));
+
+    assert!(limiter
+        .expire(blocked_at + Duration::from_secs(599))
+        .is_empty());
+    assert_eq!(
+        limiter.expire(blocked_at + Duration::from_secs(600)),
+        [alice]
+    );
+    assert!(limiter
+        .expire(blocked_at + Duration::from_secs(601))
+        .is_empty());
+}
+
+#[test]
+fn a_peer_that_calms_down_starts_over() {
+    let mut limiter = limiter();
+    let alice = peer();
+    let start = Instant::now();
+
+    for _ in 0..31 {
+        limiter.check(alice, start);
+    }
+    // Long enough for the bucket to fill up again.
+    let later = start + Duration::from_secs(59);
+    for _ in 0..30 {
+        assert_eq!(limiter.check(alice, later), Decision::Allow);
+    }
+    assert_eq!(limiter.check(alice, later), Decision::Drop { first: true });
+}
+
+#[test]
+fn forgotten_peers_are_not_unblocked() {
+    let mut limiter = RateLimiter::new(1.0, 1, Duration::ZERO, Duration::from_secs(10));
+    let alice = peer();
+    let now = Instant::now();
+
+    assert_eq!(limiter.check(alice, now), Decision::Allow);
+    assert_eq!(limiter.check(alice, now), Decision::Drop { first: true });
+    assert_eq!(limiter.check(alice, now), Decision::Block);
+
+    limiter.forget(&alice);
+    assert!(limiter.expire(now + Duration::from_secs(10)).is_empty());
+}
@@ -0,0 +1,150 @@
+use libp2p::{
+    futures::StreamExt,
+    rendezvous::{self, Namespace},
+    swarm::SwarmEvent,
+    Multiaddr,
+};
+use libp2p_demo::{
+    behaviour::CustomBehaviourEvent,
+    config::{Config, RendezvousConfig},
+    rendezvous::{room_namespace, server_peer_id, Registrations, REGISTER_RETRY},
+    testing::{listen_tcp, peer, tcp_swarm},
+};
+use std::time::{Duration, Instant};
+
+const TIMEOUT: Duration = Duration::from_secs(15);
+
+#[test]
+fn namespaces_come_from_room_names() {
+    assert_eq!(
+        room_namespace("chat").unwrap(),
+        Namespace::from_static("decentralized-chat/chat")
+    );
+    assert!(room_namespace(&"x".repeat(300)).is_err());
+}
+
+#[test]
+fn server_addresses_must_name_the_peer() {
+    let server = peer();
+    let address: Multiaddr = format!("/ip4/203.0.113.7/tcp/4001/p2p/{}", server)
+        .parse()
+        .unwrap();
+    assert_eq!(server_peer_id(&address).unwrap(), server);
+
+    let error = server_peer_id(&"/ip4/203.0.113.7/tcp/4001".parse().unwrap()).unwrap_err();
+    assert!(error.contains("must end in /p2p/<peer id>"), "{}", error);
+}
+
+#[test]
+fn registrations_are_renewed_halfway_and_failures_retried() {
+    let mut registrations = Registrations::default();
+    let (server, other) = (peer(), peer());
+    let chat = room_namespace("chat").unwrap();
+    let rust = room_namespace("rust").unwrap();
+    let now = Instant::now();
+
+    assert!(!registrations.knows(&server));
+    registrations.sent(server, chat.clone(), now);
+    registrations.sent(server, rust.clone(), now);
+    assert!(registrations.knows(&server));
+    assert!(!registrations.knows(&other));
+    assert!(registrations.due(now).is_empty());
+
+    assert!(registrations.registered(server, chat.clone(), 7200, now));
+    // The server confirming a renewal isn't a new registration.
+    assert!(!registrations.registered(server, chat.clone(), 7200, now));
+    registrations.failed(server, rust.clone(), now);
+
+    assert_eq!(
+        registrations.due(now + REGISTER_RETRY),
+        [(server, rust.clone())]
+    );
+    let mut due = registrations.due(now + Duration::from_secs(3600));
+    due.sort_by_key(|(_, namespace)| namespace.to_string());
+    assert_eq!(due, [(server, chat.clone()), (server, rust)]);
+
+    registrations.disconnected(&server);
+    assert!(!registrations.knows(&server));
+    assert!(registrations.registered(server, chat, 7200, now));
+}
+
+#[test]
+fn registration_ttl_must_be_one_servers_accept() {
+    let config = RendezvousConfig::default();
+    assert!(!config.server);
+    assert_eq!(config.ttl_secs, rendezvous::DEFAULT_TTL);
+    config.check().unwrap();
+
+    let short = RendezvousConfig {
+        ttl_secs: 60,
+        ..RendezvousConfig::default()
+    };
+    assert!(short.check().unwrap_err().contains("ttl_secs"));
+}
+
+#[tokio::test]
+async fn peers_in_a_room_find_each_other_through_a_server() {
+    let mut server = tcp_swarm(&Config {
+        rendezvous: RendezvousConfig {
+            server: true,
+            ..RendezvousConfig::default()
+        },
+        ..Config::default()
+    })
+    .await;
+    let server_id = *server.local_peer_id();
+    let server_addr = listen_tcp(&mut server).await;
+
+    let mut alice = tcp_swarm(&Config::default()).await;
+    let alice_id = *alice.local_peer_id();
+    let alice_addr = listen_tcp(&mut alice).await;
+    alice.add_external_address(alice_addr.clone());
+    alice.dial(server_addr.clone()).unwrap();
+
+    let mut bob = tcp_swarm(&Config::default()).await;
+    let namespace = room_namespace("chat").unwrap();
+
+    let found = tokio::time::timeout(TIMEOUT, async {
+        loop {
+            tokio::select! {
+                _ = server.select_next_some() => {}
+                event = alice.select_next_some() => match event {
+                    SwarmEvent::ConnectionEstablished { peer_id, .. } if peer_id == server_id => {
+                        alice
+                            .behaviour_mut()
+                            .rendezvous
+                            .register(namespace.clone(), server_id, None)
+                            .unwrap();
+                    }
+                    SwarmEvent::Behaviour(CustomBehaviourEvent::Rendezvous(
+                        rendezvous::client::Event::Registered { .. },
+                    )) => {
+                        bob.dial(server_addr.clone()).unwrap();
+                    }
+                    _ => {}
+                },
+                event = bob.select_next_some() => match event {
+                    SwarmEvent::ConnectionEstablished { peer_id, .. } if peer_id == server_id => {
+                        bob.behaviour_mut().rendezvous.discover(
+                            Some(namespace.clone()),
+                            None,
+                            None,
+                            server_id,
+                        );
+                    }
+                    SwarmEvent::Behaviour(CustomBehaviourEvent::Rendezvous(
+                        rendezvous::client::Event::Discovered { registrations, .. },
+                    )) => break registrations,
+                    _ => {}
+                },
+            }
+        }
+    })
+    .await
+    .expect("bob did not discover alice in time");
+
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].record.peer_id(), alice_id);
+    assert_eq!(found[0].record.addresses(), [alice_addr]);
+    assert_eq!(found[0].namespace, namespace);
+}
@@ -0,0 +1,108 @@
+use libp2p_demo::{
+    message::Sequence,
+    replay::{Replay, ReplayGuard, SEQUENCES_FILE},
+    testing::peer,
+};
+use std::{fs, path::PathBuf};
+
+fn at(session: &Sequence, seq: u64) -> Sequence {
+    Sequence {
+        session: session.session,
+        seq,
+    }
+}
+
+fn sequences_path() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("chat-replay-{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&dir).unwrap();
+    dir.join(SEQUENCES_FILE)
+}
+
+#[test]
+fn out_of_order_messages_within_the_window_are_accepted_once() {
+    let mut guard = ReplayGuard::new(4);
+    let alice = peer();
+    let session = Sequence::start();
+
+    assert_eq!(guard.check(alice, at(&session, 1)), Ok(()));
+    assert_eq!(guard.check(alice, at(&session, 5)), Ok(()));
+    assert_eq!(guard.check(alice, at(&session, 3)), Ok(()));
+    assert_eq!(guard.check(alice, at(&session, 2)), Ok(()));
+
+    assert_eq!(
+        guard.check(alice, at(&session, 3)),
+        Err(Replay::Duplicate { seq: 3 })
+    );
+    assert_eq!(
+        guard.check(alice, at(&session, 5)),
+        Err(Replay::Duplicate { seq: 5 })
+    );
+}
+
+#[test]
+fn messages_behind_the_window_are_replays() {
+    let mut guard = ReplayGuard::new(4);
+    let alice = peer();
+    let session = Sequence::start();
+
+    for seq in 1..=10 {
+        assert_eq!(guard.check(alice, at(&session, seq)), Ok(()));
+    }
+    assert_eq!(
+        guard.check(alice, at(&session, 5)),
+        Err(Replay::Stale { seq: 5, latest: 10 })
+    );
+    // Another peer's numbers are tracked separately.
+    assert_eq!(guard.check(peer(), at(&session, 5)), Ok(()));
+}
+
+#[test]
+fn a_new_session_resets_the_sequence_but_old_sessions_stay_closed() {
+    let mut guard = ReplayGuard::new(4);
+    let alice = peer();
+    let first = Sequence::start();
+    let second = Sequence::start();
+
+    assert_eq!(guard.check(alice, at(&first, 100)), Ok(()));
+    assert_eq!(guard.check(alice, at(&second, 1)), Ok(()));
+    assert_eq!(guard.check(alice, at(&second, 2)), Ok(()));
+
+    assert_eq!(
+        guard.check(alice, at(&first, 101)),
+        Err(Replay::RetiredSession {
+            session: first.session
+        })
+    );
+}
+
+#[test]
+fn sequence_numbers_survive_a_restart() {
+    let path = sequences_path();
+    let alice = peer();
+    let session = Sequence::start();
+
+    let mut guard = ReplayGuard::load(&path, 4).unwrap();
+    for seq in 1..=10 {
+        guard.check(alice, at(&session, seq)).unwrap();
+    }
+    guard.save(&path).unwrap();
+
+    let mut guard = ReplayGuard::load(&path, 4).unwrap();
+    assert_eq!(
+        guard.check(alice, at(&session, 9)),
+        Err(Replay::Duplicate { seq: 9 })
+    );
+    assert_eq!(
+        guard.check(alice, at(&session, 2)),
+        Err(Replay::Stale { seq: 2, latest: 10 })
+    );
+    assert_eq!(guard.check(alice, at(&session, 11)), Ok(()));
+}
+
+#[test]
+fn sequences_count_up_from_one() {
+    let mut sequence = Sequence::start();
+    assert_eq!(sequence.advance().seq, 1);
+    assert_eq!(sequence.advance().seq, 2);
+    assert_ne!(Sequence::start().session, sequence.session);
+}
@@ -0,0 +1,189 @@
+use libp2p::{
+    request_response::{self, OutboundFailure, OutboundRequestId},
+    swarm::SwarmEvent,
+    PeerId,
+};
+use libp2p_demo::{
+    behaviour::{CustomBehaviourEvent, Request},
+    envelope::seal,
+    message::{ChatMessage, DirectRequest, DirectResponse},
+    retry::{Failed, RetryPolicy, RetryQueue},
+    testing::{connect_sim, drive_until, peer, SimPeer, SimReply, TestNode},
+};
+use std::time::{Duration, Instant};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+fn policy(max_attempts: u32, backoff_secs: &[u64]) -> RetryPolicy {
+    RetryPolicy {
+        max_attempts,
+        backoff: backoff_secs
+            .iter()
+            .map(|secs| Duration::from_secs(*secs))
+            .collect(),
+    }
+}
+
+fn message() -> ChatMessage {
+    ChatMessage::new(peer(), "are you there?".to_string())
+}
+
+#[test]
+fn retries_follow_the_backoff_schedule_then_give_up() {
+    let mut queue = RetryQueue::new(policy(4, &[2, 10]));
+    let (peer, message) = (peer(), message());
+    let start = Instant::now();
+    queue.sent(peer, message.clone());
+
+    assert_eq!(
+        queue.failed(&message.id, start),
+        Some(Failed::Retrying {
+            attempt: 2,
+            delay: Duration::from_secs(2)
+        })
+    );
+    assert!(queue.due(start + Duration::from_secs(1)).is_empty());
+    assert_eq!(
+        queue.due(start + Duration::from_secs(2)),
+        [(peer, message.clone())]
+    );
+    // Nothing is due again while the retry is on its way.
+    assert!(queue.due(start + Duration::from_secs(60)).is_empty());
+
+    // The last step repeats.
+    for attempt in 3..=4 {
+        assert_eq!(
+            queue.failed(&message.id, start),
+            Some(Failed::Retrying {
+                attempt,
+                delay: Duration::from_secs(10)
+            })
+        );
+        assert_eq!(queue.due(start + Duration::from_secs(10)).len(), 1);
+    }
+
+    assert_eq!(
+        queue.failed(&message.id, start),
+        Some(Failed::GaveUp {
+            peer,
+            message: Box::new(message.clone()),
+            attempts: 4
+        })
+    );
+    assert_eq!(queue.failed(&message.id, start), None);
+}
+
+#[test]
+fn a_single_attempt_gives_up_at_once() {
+    let mut queue = RetryQueue::new(policy(1, &[]));
+    let message = message();
+    queue.sent(peer(), message.clone());
+
+    assert!(matches!(
+        queue.failed(&message.id, Instant::now()),
+        Some(Failed::GaveUp { attempts: 1, .. })
+    ));
+}
+
+#[test]
+fn cancelled_messages_are_not_retried() {
+    let mut queue = RetryQueue::new(policy(3, &[0]));
+    let (blocked, other) = (peer(), peer());
+    let (first, second, third) = (message(), message(), message());
+    queue.sent(blocked, first.clone());
+    queue.sent(blocked, second.clone());
+    queue.sent(other, third.clone());
+    for message in [&first, &second, &third] {
+        queue.failed(&message.id, Instant::now());
+    }
+
+    assert_eq!(queue.cancel_peer(&blocked), 2);
+    assert!(queue.cancel(&third.id));
+    assert!(queue.due(Instant::now()).is_empty());
+    assert_eq!(queue.failed(&first.id, Instant::now()), None);
+    assert!(!queue.delivered(&third.id));
+}
+
+#[test]
+fn messages_to_a_departed_peer_can_be_taken_over() {
+    let mut queue = RetryQueue::new(policy(3, &[0]));
+    let (departed, other) = (peer(), peer());
+    let (in_flight, retrying, third) = (message(), message(), message());
+    queue.sent(departed, in_flight.clone());
+    queue.sent(departed, retrying.clone());
+    queue.sent(other, third.clone());
+    queue.failed(&retrying.id, Instant::now());
+
+    let mut taken: Vec<_> = queue
+        .take_peer(&departed)
+        .into_iter()
+        .map(|message| message.id)
+        .collect();
+    taken.sort();
+    let mut expected = vec![in_flight.id, retrying.id];
+    expected.sort();
+    assert_eq!(taken, expected);
+
+    assert!(queue.due(Instant::now()).is_empty());
+    assert_eq!(queue.failed(&in_flight.id, Instant::now()), None);
+    assert!(queue.delivered(&third.id));
+}
+
+fn send(node: &mut TestNode, peer: PeerId, message: &ChatMessage) -> OutboundRequestId {
+    node.swarm.behaviour_mut().request_response.send_request(
+        &peer,
+        Request {
+            data: seal(&DirectRequest::Message(message.clone())),
+        },
+    )
+}
+
+/// Drives `nodes[0]` until the request `sent` is answered or fails.
+async fn answered(nodes: &mut [TestNode], sent: OutboundRequestId) -> Result<(), OutboundFailure> {
+    drive_until(nodes, TIMEOUT, |_, event, _| match event {
+        SwarmEvent::Behaviour(CustomBehaviourEvent::RequestResponse(
+            request_response::Event::Message {
+                message: request_response::Message::Response { request_id, .. },
+                ..
+            },
+        )) if request_id == sent => Some(Ok(())),
+        SwarmEvent::Behaviour(CustomBehaviourEvent::RequestResponse(
+            request_response::Event::OutboundFailure {
+                request_id, error, ..
+            },
+        )) if request_id == sent => Some(Err(error)),
+        _ => None,
+    })
+    .await
+}
+
+#[tokio::test]
+async fn a_message_arrives_on_the_second_attempt() {
+    let mut nodes = [TestNode::new().await];
+    let message = ChatMessage::new(nodes[0].peer_id(), "second time lucky".to_string());
+    let sim = SimPeer::spawn([
+        SimReply::Corrupt,
+        SimReply::Respond(seal(&DirectResponse::Ack { id: message.id })),
+    ])
+    .await;
+    connect_sim(&mut nodes, 0, &sim).await;
+    let mut queue = RetryQueue::new(RetryPolicy {
+        max_attempts: 3,
+        backoff: vec![Duration::from_millis(100)],
+    });
+
+    let sent = send(&mut nodes[0], sim.peer_id, &message);
+    queue.sent(sim.peer_id, message.clone());
+    assert!(answered(&mut nodes, sent).await.is_err());
+    let Some(Failed::Retrying { attempt: 2, delay }) = queue.failed(&message.id, Instant::now())
+    else {
+        panic!("the message should be retried");
+    };
+
+    tokio::time::sleep(delay).await;
+    let due = queue.due(Instant::now());
+    assert_eq!(due, [(sim.peer_id, message.clone())]);
+    let sent = send(&mut nodes[0], sim.peer_id, &due[0].1);
+    assert!(answered(&mut nodes, sent).await.is_ok());
+    assert!(queue.delivered(&message.id));
+}
@@ -0,0 +1,50 @@
+use libp2p_demo::room_settings::{Notify, RoomSettings, ROOM_SETTINGS_FILE};
+use std::{fs, path::PathBuf};
+
+fn settings_path() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("chat-room-settings-{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&dir).unwrap();
+    dir.join(ROOM_SETTINGS_FILE)
+}
+
+#[test]
+fn rooms_show_every_message_until_muted() {
+    let mut settings = RoomSettings::default();
+    assert_eq!(settings.get("random"), Notify::All);
+
+    assert!(settings.set("random", Notify::Muted));
+    assert!(!settings.set("random", Notify::Muted));
+    assert_eq!(settings.get("random"), Notify::Muted);
+    assert_eq!(settings.get("chat"), Notify::All);
+
+    assert!(settings.set("random", Notify::All));
+    assert!(!settings.set("random", Notify::All));
+    assert_eq!(settings.get("random"), Notify::All);
+}
+
+#[test]
+fn mentions_only_rooms_show_mentions() {
+    assert!(Notify::All.shows(false));
+    assert!(Notify::MentionsOnly.shows(true));
+    assert!(!Notify::MentionsOnly.shows(false));
+    assert!(!Notify::Muted.shows(true));
+}
+
+#[test]
+fn settings_survive_a_restart() {
+    let path = settings_path();
+    assert_eq!(
+        RoomSettings::load(&path).unwrap().get("random"),
+        Notify::All
+    );
+
+    let mut settings = RoomSettings::default();
+    settings.set("random", Notify::Muted);
+    settings.set("announcements", Notify::MentionsOnly);
+    settings.save(&path).unwrap();
+
+    let loaded = RoomSettings::load(&path).unwrap();
+    assert_eq!(loaded.get("random"), Notify::Muted);
+    assert_eq!(loaded.get("announcements"), Notify::MentionsOnly);
+    assert_eq!(loaded.get("chat"), Notify::All);
+}
@@ -0,0 +1,271 @@
+#![cfg(feature = "http-api")]
+
+use libp2p::PeerId;
+use libp2p_demo::{
+    command::{Command, ConnectedPeer, Peering, Reply},
+    handle::ChatHandle,
+    message::ChatMessage,
+    rpc::{self, COMMAND_FAILED, INVALID_PARAMS, INVALID_REQUEST, METHOD_NOT_FOUND, PARSE_ERROR},
+    testing::peer,
+};
+use serde_json::{json, Value};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::{broadcast, mpsc},
+};
+
+const TOKEN: &str = "rpc-token";
+
+/// Serves JSON-RPC with `TOKEN` in front of a stand-in for the node that is
+/// connected to `connected` and records the commands it runs.
+async fn serve(connected: PeerId) -> (SocketAddr, Arc<Mutex<Vec<Command>>>) {
+    let (requests_tx, mut requests_rx) = mpsc::channel(8);
+    let handle = ChatHandle::new(requests_tx, broadcast::channel(1).0);
+    let commands = Arc::new(Mutex::new(Vec::new()));
+
+    let recorded = commands.clone();
+    tokio::spawn(async move {
+        while let Some((command, reply_tx)) = requests_rx.recv().await {
+            let reply = match &command {
+                Command::Peers => Ok(Reply::Peers {
+                    peers: vec![ConnectedPeer {
+                        peer_id: connected,
+                        protocol: None,
+                        security: Vec::new(),
+                        nickname: None,
+                        verified: false,
+                        bandwidth: Default::default(),
+                        peering: Peering::Mesh,
+                    }],
+                }),
+                Command::Send { text, .. } if text == "fail" => {
+                    Err("Failed to publish message: InsufficientPeers".to_string())
+                }
+                Command::Send { .. } | Command::Msg { .. } => Ok(Reply::Sent {
+                    id: uuid::Uuid::new_v4(),
+                }),
+                Command::Join { room } => Ok(Reply::Joined { room: room.clone() }),
+                Command::Dial { address } => Ok(Reply::Dialing {
+                    address: address.clone(),
+                }),
+                Command::History { room, limit, .. } => Ok(Reply::History {
+                    room: room.clone().unwrap_or_else(|| "chat".to_string()),
+                    messages: (0..*limit)
+                        .map(|n| ChatMessage::new(connected, n.to_string()))
+                        .collect(),
+                    first: 1,
+                    total: *limit,
+                    statuses: Default::default(),
+                }),
+                other => panic!("unexpected command {:?}", other),
+            };
+            recorded.lock().unwrap().push(command);
+            let _ = reply_tx.send(reply);
+        }
+    });
+
+    let addr = rpc::serve("127.0.0.1:0".parse().unwrap(), handle, TOKEN.to_string())
+        .await
+        .unwrap();
+    (addr, commands)
+}
+
+/// Posts `body` as JSON with `TOKEN` and returns the status and JSON body of
+/// the response.
+async fn post(addr: SocketAddr, body: &str) -> (u16, Value) {
+    let headers = format!(
+        "Content-Type: application/json\r\nAuthorization: Bearer {}\r\n",
+        TOKEN
+    );
+    post_with(addr, &headers, body).await
+}
+
+/// Posts `body` with `headers`, each ending in CRLF.
+async fn post_with(addr: SocketAddr, headers: &str, body: &str) -> (u16, Value) {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let mut head = "POST / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n".to_string();
+    head.push_str(headers);
+    head.push_str(&format!("Content-Length: {}\r\n\r\n{}", body.len(), body));
+    stream.write_all(head.as_bytes()).await.unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let status = response[9..12].parse().unwrap();
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    (status, serde_json::from_str(body).unwrap_or(Value::Null))
+}
+
+/// Calls `method` and returns the response object.
+async fn call(addr: SocketAddr, method: &str, params: Value) -> Value {
+    let request = json!({ "jsonrpc": "2.0", "method": method, "params": params, "id": 7 });
+    let (status, response) = post(addr, &request.to_string()).await;
+    assert_eq!(status, 200);
+    assert_eq!(response["jsonrpc"], "2.0");
+    assert_eq!(response["id"], 7);
+    response
+}
+
+#[tokio::test]
+async fn methods_run_the_matching_commands() {
+    let connected = peer();
+    let (addr, commands) = serve(connected).await;
+
+    let response = call(addr, "listPeers", json!({})).await;
+    assert_eq!(
+        response["result"]["peers"][0]["peer_id"],
+        connected.to_string()
+    );
+
+    let response = call(addr, "sendMessage", json!({ "room": "chat", "text": "hi" })).await;
+    assert!(response["result"]["id"].is_string());
+    assert!(response["result"].get("type").is_none());
+    call(
+        addr,
+        "sendMessage",
+        json!({ "peer": connected.to_string(), "text": "psst" }),
+    )
+    .await;
+
+    let response = call(addr, "joinRoom", json!({ "room": "rust" })).await;
+    assert_eq!(response["result"], json!({ "room": "rust" }));
+
+    let response = call(
+        addr,
+        "getHistory",
+        json!({ "room": "rust", "peer": connected.to_string(), "since": 100, "limit": 3 }),
+    )
+    .await;
+    assert_eq!(response["result"]["room"], "rust");
+    assert_eq!(response["result"]["messages"].as_array().unwrap().len(), 3);
+    assert_eq!(response["result"]["total"], 3);
+
+    let response = call(
+        addr,
+        "dial",
+        json!({ "address": "/ip4/127.0.0.1/tcp/4001" }),
+    )
+    .await;
+    assert_eq!(response["result"]["address"], "/ip4/127.0.0.1/tcp/4001");
+
+    let commands = commands.lock().unwrap();
+    assert!(
+        matches!(commands[1], Command::Send { ref room, .. } if room.as_deref() == Some("chat"))
+    );
+    assert!(matches!(commands[2], Command::Msg { peer, .. } if peer == connected));
+    assert!(matches!(
+        commands[4],
+        Command::History { peer: Some(peer), since: Some(100), limit: 3, page: 0, .. }
+            if peer == connected
+    ));
+}
+
+#[tokio::test]
+async fn bad_calls_get_json_rpc_errors() {
+    let (addr, commands) = serve(peer()).await;
+
+    let response = call(addr, "shout", json!({})).await;
+    assert_eq!(response["error"]["code"], METHOD_NOT_FOUND);
+
+    for params in [
+        json!({}),
+        json!({ "text": "hi", "colour": "red" }),
+        json!({ "text": " " }),
+        json!({ "text": "hi", "room": "chat", "peer": peer().to_string() }),
+        json!(["hi"]),
+    ] {
+        let response = call(addr, "sendMessage", params).await;
+        assert_eq!(response["error"]["code"], INVALID_PARAMS, "{}", response);
+    }
+    let response = call(addr, "getHistory", json!({ "limit": 0 })).await;
+    assert_eq!(response["error"]["code"], INVALID_PARAMS);
+
+    let response = call(addr, "sendMessage", json!({ "text": "fail" })).await;
+    assert_eq!(response["error"]["code"], COMMAND_FAILED);
+    assert_eq!(
+        response["error"]["message"],
+        "Failed to publish message: InsufficientPeers"
+    );
+
+    let (_, response) = post(addr, "{not json").await;
+    assert_eq!(response["error"]["code"], PARSE_ERROR);
+    assert_eq!(response["id"], Value::Null);
+    let (_, response) = post(addr, r#"{"method": "listPeers", "id": 1}"#).await;
+    assert_eq!(response["error"]["code"], INVALID_REQUEST);
+    assert_eq!(response["id"], 1);
+
+    // Only the call the node refused got as far as it.
+    assert_eq!(commands.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn batches_are_answered_in_order_without_notifications() {
+    let (addr, commands) = serve(peer()).await;
+    let batch = json!([
+        { "jsonrpc": "2.0", "method": "joinRoom", "params": { "room": "a" }, "id": "first" },
+        { "jsonrpc": "2.0", "method": "joinRoom", "params": { "room": "b" } },
+        { "jsonrpc": "2.0", "method": "listPeers", "id": 2 },
+    ]);
+    let (status, response) = post(addr, &batch.to_string()).await;
+    assert_eq!(status, 200);
+    let ids: Vec<&Value> = response
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|response| &response["id"])
+        .collect();
+    assert_eq!(ids, [&json!("first"), &json!(2)]);
+    assert_eq!(commands.lock().unwrap().len(), 3);
+
+    let notification = json!({ "jsonrpc": "2.0", "method": "joinRoom", "params": { "room": "c" } });
+    let (status, _) = post(addr, &notification.to_string()).await;
+    assert_eq!(status, 204);
+
+    let (_, response) = post(addr, "[]").await;
+    assert_eq!(response["error"]["code"], INVALID_REQUEST);
+}
+
+#[tokio::test]
+async fn calls_need_the_token_and_a_json_body_and_no_origin() {
+    let (addr, commands) = serve(peer()).await;
+    let request = json!({ "jsonrpc": "2.0", "method": "listPeers", "id": 1 }).to_string();
+    let json = "Content-Type: application/json\r\n";
+    let authorized = format!("Authorization: Bearer {}\r\n", TOKEN);
+
+    for token in ["", "Authorization: Bearer wrong-token\r\n"] {
+        let (status, response) = post_with(addr, &format!("{}{}", json, token), &request).await;
+        assert_eq!(status, 401);
+        assert_eq!(
+            response["error"]["message"],
+            "missing or wrong bearer token"
+        );
+    }
+    // What a web page can make the browser send.
+    let (status, _) = post_with(
+        addr,
+        &format!("Content-Type: text/plain\r\n{}", authorized),
+        &request,
+    )
+    .await;
+    assert_eq!(status, 415);
+    let (status, _) = post_with(addr, &authorized, &request).await;
+    assert_eq!(status, 415);
+    let from_page = format!("{}{}Origin: https://example.com\r\n", json, authorized);
+    let (status, _) = post_with(addr, &from_page, &request).await;
+    assert_eq!(status, 403);
+    assert!(commands.lock().unwrap().is_empty());
+
+    let (status, response) = post(addr, &request).await;
+    assert_eq!(status, 200);
+    assert!(response["result"]["peers"].is_array());
+    let charset = format!(
+        "Content-Type: application/json; charset=utf-8\r\n{}",
+        authorized
+    );
+    let (status, _) = post_with(addr, &charset, &request).await;
+    assert_eq!(status, 200);
+}
@@ -0,0 +1,58 @@
+use libp2p::Multiaddr;
+use libp2p_demo::{
+    share::{qr_code, shareable_addresses},
+    testing::peer,
+};
+
+fn addr(address: &str) -> Multiaddr {
+    address.parse().unwrap()
+}
+
+#[test]
+fn external_addresses_come_first_then_public_lan_and_loopback() {
+    let peer = peer();
+    let external = [addr("/dns4/chat.example.com/tcp/4001")];
+    let listen = [
+        addr("/ip4/127.0.0.1/tcp/4001"),
+        addr("/ip4/192.168.1.20/tcp/4001"),
+        addr("/ip4/203.0.113.7/tcp/4001"),
+    ];
+
+    let shared: Vec<String> = shareable_addresses(peer, &external, &listen)
+        .iter()
+        .map(Multiaddr::to_string)
+        .collect();
+    assert_eq!(
+        shared,
+        [
+            format!("/dns4/chat.example.com/tcp/4001/p2p/{}", peer),
+            format!("/ip4/203.0.113.7/tcp/4001/p2p/{}", peer),
+            format!("/ip4/192.168.1.20/tcp/4001/p2p/{}", peer),
+            format!("/ip4/127.0.0.1/tcp/4001/p2p/{}", peer),
+        ]
+    );
+}
+
+#[test]
+fn an_address_both_external_and_listened_on_is_shared_once() {
+    let peer = peer();
+    let address = addr("/ip4/203.0.113.7/tcp/4001");
+    let with_peer = address.clone().with_p2p(peer).unwrap();
+
+    let shared = shareable_addresses(peer, [&with_peer], [&address]);
+    assert_eq!(shared, [with_peer]);
+    assert!(shareable_addresses(peer, [], []).is_empty());
+}
+
+#[test]
+fn qr_codes_are_square_blocks_of_text() {
+    let address = format!("/ip4/203.0.113.7/tcp/4001/p2p/{}", peer());
+    let code = qr_code(&address).unwrap();
+
+    let lines: Vec<&str> = code.lines().collect();
+    assert!(lines.len() > 10);
+    let width = lines[0].chars().count();
+    assert!(lines.iter().all(|line| line.chars().count() == width));
+    // Two rows of modules per line.
+    assert!(width.abs_diff(lines.len() * 2) <= 1, "{}", code);
+}
@@ -0,0 +1,171 @@
+use libp2p::identity::Keypair;
+use libp2p_demo::{
+    key::{decode, generate, KeyType},
+    message::{ChatMessage, Sequence, SignatureError},
+    testing::peer,
+};
+
+/// See tests/key.rs.
+const RSA_PKCS8: &[u8] = include_bytes!("fixtures/rsa-2048.pk8");
+
+fn signed_message(keypair: &Keypair) -> ChatMessage {
+    let mut message = ChatMessage {
+        nickname: Some("ada".to_string()),
+        reply_to: Some(uuid::Uuid::new_v4()),
+        ..ChatMessage::new(keypair.public().to_peer_id(), "hello".to_string())
+    };
+    message.sign(keypair).unwrap();
+    message
+}
+
+#[test]
+fn valid_signature_verifies() {
+    let keypair = Keypair::generate_ed25519();
+    let message = signed_message(&keypair);
+    assert_eq!(message.verify_signature(), Ok(()));
+
+    // The signature survives the trip over the wire.
+    let json = serde_json::to_string(&message).unwrap();
+    let received: ChatMessage = serde_json::from_str(&json).unwrap();
+    assert_eq!(received.verify_signature(), Ok(()));
+}
+
+#[test]
+fn tampered_message_is_rejected() {
+    let keypair = Keypair::generate_ed25519();
+
+    let mut message = signed_message(&keypair);
+    message.message = "goodbye".to_string();
+    assert_eq!(message.verify_signature(), Err(SignatureError::Invalid));
+
+    let mut message = signed_message(&keypair);
+    message.nickname = Some("eve".to_string());
+    assert_eq!(message.verify_signature(), Err(SignatureError::Invalid));
+
+    let mut message = signed_message(&keypair);
+    message.timestamp += 1;
+    assert_eq!(message.verify_signature(), Err(SignatureError::Invalid));
+}
+
+#[test]
+fn signature_from_another_key_is_rejected() {
+    let victim = Keypair::generate_ed25519();
+    let attacker = Keypair::generate_ed25519();
+
+    // The attacker claims the victim's peer id but can only sign with their own key.
+    let mut message = ChatMessage::new(victim.public().to_peer_id(), "spoofed".to_string());
+    message.sign(&attacker).unwrap();
+    assert_eq!(message.verify_signature(), Err(SignatureError::Invalid));
+
+    // Copying a genuine signature onto different content doesn't help either.
+    let mut forged = ChatMessage::new(victim.public().to_peer_id(), "forged".to_string());
+    forged.signature = signed_message(&victim).signature;
+    assert_eq!(forged.verify_signature(), Err(SignatureError::Invalid));
+}
+
+#[test]
+fn unsigned_message_is_rejected() {
+    let keypair = Keypair::generate_ed25519();
+    let message = ChatMessage::new(keypair.public().to_peer_id(), "hello".to_string());
+    assert_eq!(message.verify_signature(), Err(SignatureError::Missing));
+}
+
+#[test]
+fn sequence_numbers_are_signed() {
+    let keypair = Keypair::generate_ed25519();
+    let mut sequence = Sequence::start();
+    let mut message = ChatMessage {
+        sequence: Some(sequence.advance()),
+        ..ChatMessage::new(keypair.public().to_peer_id(), "hello".to_string())
+    };
+    message.sign(&keypair).unwrap();
+    assert_eq!(message.verify_signature(), Ok(()));
+
+    message.sequence = Some(sequence.advance());
+    assert_eq!(message.verify_signature(), Err(SignatureError::Invalid));
+}
+
+#[test]
+fn ttls_are_signed() {
+    let keypair = Keypair::generate_ed25519();
+    let mut message = ChatMessage {
+        ttl_secs: Some(60),
+        ..ChatMessage::new(keypair.public().to_peer_id(), "hello".to_string())
+    };
+    message.sign(&keypair).unwrap();
+    assert_eq!(message.verify_signature(), Ok(()));
+
+    message.ttl_secs = Some(3600);
+    assert_eq!(message.verify_signature(), Err(SignatureError::Invalid));
+}
+
+#[test]
+fn rooms_and_recipients_are_signed() {
+    let keypair = Keypair::generate_ed25519();
+    let mut message = ChatMessage {
+        room: Some("rust".to_string()),
+        ..ChatMessage::new(keypair.public().to_peer_id(), "hello".to_string())
+    };
+    message.sign(&keypair).unwrap();
+    assert_eq!(message.verify_signature(), Ok(()));
+
+    message.room = Some("go".to_string());
+    assert_eq!(message.verify_signature(), Err(SignatureError::Invalid));
+
+    let mut message = ChatMessage {
+        recipient: Some(peer()),
+        ..ChatMessage::new(keypair.public().to_peer_id(), "hello".to_string())
+    };
+    message.sign(&keypair).unwrap();
+    assert_eq!(message.verify_signature(), Ok(()));
+
+    message.recipient = Some(peer());
+    assert_eq!(message.verify_signature(), Err(SignatureError::Invalid));
+}
+
+#[test]
+fn every_key_type_signs_messages_that_verify_after_the_wire() {
+    let keypairs = [
+        generate(KeyType::Ed25519).unwrap(),
+        generate(KeyType::Secp256k1).unwrap(),
+        generate(KeyType::Ecdsa).unwrap(),
+        decode(RSA_PKCS8).unwrap(),
+    ];
+    for keypair in keypairs {
+        let message = signed_message(&keypair);
+        let json = serde_json::to_string(&message).unwrap();
+        let received: ChatMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            received.verify_signature(),
+            Ok(()),
+            "{}",
+            KeyType::of(&keypair)
+        );
+
+        let mut tampered = received;
+        tampered.message = "goodbye".to_string();
+        assert_eq!(tampered.verify_signature(), Err(SignatureError::Invalid));
+    }
+}
+
+#[test]
+fn only_keys_not_embedded_in_the_peer_id_are_attached() {
+    assert!(signed_message(&Keypair::generate_ed25519())
+        .public_key
+        .is_empty());
+
+    let ecdsa = Keypair::generate_ecdsa();
+    let message = signed_message(&ecdsa);
+    assert_eq!(message.public_key, ecdsa.public().encode_protobuf());
+
+    let mut keyless = message.clone();
+    keyless.public_key.clear();
+    assert_eq!(keyless.verify_signature(), Err(SignatureError::UnknownKey));
+
+    // An attacker can't vouch for a peer id with a key that isn't behind it.
+    let attacker = Keypair::generate_ecdsa();
+    let mut forged = ChatMessage::new(ecdsa.public().to_peer_id(), "forged".to_string());
+    forged.sign(&attacker).unwrap();
+    assert_eq!(forged.public_key, attacker.public().encode_protobuf());
+    assert_eq!(forged.verify_signature(), Err(SignatureError::Invalid));
+}
@@ -0,0 +1,334 @@
+use libp2p::{
+    futures::StreamExt, identify, identity, metrics::Registry, request_response, swarm::SwarmEvent,
+    Multiaddr,
+};
+use libp2p_demo::{
+    behaviour::{build_swarm, build_test_swarm, CustomBehaviourEvent, Request},
+    command::{Command, Reply},
+    config::Config,
+    envelope,
+    handle::ChatHandle,
+    message::{ChatMessage, DirectRequest},
+    stats::{
+        bandwidth, peer_bandwidth, CompressionSavings, Counters, HistoryStats, Limits,
+        MessageCounts, NatStatus, PortMapping, RequestFailures, Stats,
+    },
+    testing::peer,
+};
+use serde_json::json;
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+use tokio::sync::{broadcast, mpsc};
+
+#[test]
+fn messages_are_counted_per_room() {
+    let mut counters = Counters::default();
+    counters.message_sent(Some("chat"));
+    counters.message_received(Some("chat"));
+    counters.message_received(Some("chat"));
+    counters.message_received(Some("rust"));
+    counters.message_sent(None);
+
+    assert_eq!(
+        counters.rooms().get("chat"),
+        Some(&MessageCounts {
+            sent: 1,
+            received: 2
+        })
+    );
+    assert_eq!(
+        counters.rooms().get("rust"),
+        Some(&MessageCounts {
+            sent: 0,
+            received: 1
+        })
+    );
+    assert_eq!(
+        counters.direct(),
+        MessageCounts {
+            sent: 1,
+            received: 0
+        }
+    );
+}
+
+#[test]
+fn requests_stay_pending_until_answered_or_failed() {
+    let mut swarm = build_test_swarm(identity::Keypair::generate_ed25519()).unwrap();
+    let peer = peer();
+    let mut send = || {
+        swarm
+            .behaviour_mut()
+            .request_response
+            .send_request(&peer, Request { data: json!({}) })
+    };
+    let (answered, failed, waiting) = (send(), send(), send());
+
+    let start = Instant::now();
+    let mut counters = Counters::new(start);
+    for request_id in [answered, failed, waiting] {
+        counters.request_sent(request_id);
+    }
+    counters.response_received(answered);
+    counters.outbound_failure(failed);
+    counters.inbound_failure();
+
+    assert_eq!(counters.pending_requests(), 1);
+    assert_eq!(
+        counters.request_failures(),
+        RequestFailures {
+            outbound: 1,
+            inbound: 1
+        }
+    );
+    assert_eq!(
+        counters.uptime(start + Duration::from_secs(90)),
+        Duration::from_secs(90)
+    );
+}
+
+#[tokio::test]
+async fn bandwidth_is_counted_per_transport() {
+    let mut alice_metrics = Registry::default();
+    let mut bob_metrics = Registry::default();
+    let mut alice = build_swarm(
+        identity::Keypair::generate_ed25519(),
+        &Config::default(),
+        &mut alice_metrics,
+    )
+    .await
+    .unwrap();
+    let mut bob = build_swarm(
+        identity::Keypair::generate_ed25519(),
+        &Config::default(),
+        &mut bob_metrics,
+    )
+    .await
+    .unwrap();
+    assert!(bandwidth(&alice_metrics).is_empty());
+
+    alice
+        .listen_on("/ip4/127.0.0.1/tcp/0".parse::<Multiaddr>().unwrap())
+        .unwrap();
+    let alice_addr = loop {
+        if let SwarmEvent::NewListenAddr { address, .. } = alice.select_next_some().await {
+            break address;
+        }
+    };
+    bob.dial(alice_addr).unwrap();
+
+    // Identify runs once both sides are connected, so by the time bob has
+    // received alice's info both directions have carried data.
+    tokio::time::timeout(Duration::from_secs(15), async {
+        loop {
+            tokio::select! {
+                _ = alice.select_next_some() => {}
+                event = bob.select_next_some() => {
+                    if let SwarmEvent::Behaviour(CustomBehaviourEvent::Identify(
+                        identify::Event::Received { .. },
+                    )) = event
+                    {
+                        break;
+                    }
+                }
+            }
+        }
+    })
+    .await
+    .expect("identify did not complete in time");
+
+    let bob_bandwidth = bandwidth(&bob_metrics);
+    let tcp = bob_bandwidth
+        .iter()
+        .find(|(protocols, _)| protocols.starts_with("/ip4/tcp"))
+        .map(|(_, bandwidth)| *bandwidth)
+        .unwrap_or_else(|| panic!("no tcp bandwidth in {:?}", bob_bandwidth));
+    assert!(tcp.inbound > 0);
+    assert!(tcp.outbound > 0);
+}
+
+#[tokio::test]
+async fn bandwidth_is_counted_per_peer() {
+    let mut alice_metrics = Registry::default();
+    let mut bob_metrics = Registry::default();
+    let mut alice = build_swarm(
+        identity::Keypair::generate_ed25519(),
+        &Config::default(),
+        &mut alice_metrics,
+    )
+    .await
+    .unwrap();
+    let mut bob = build_swarm(
+        identity::Keypair::generate_ed25519(),
+        &Config::default(),
+        &mut bob_metrics,
+    )
+    .await
+    .unwrap();
+    let (alice_id, bob_id) = (*alice.local_peer_id(), *bob.local_peer_id());
+    assert!(peer_bandwidth(&bob_metrics).is_empty());
+
+    alice
+        .listen_on("/ip4/127.0.0.1/tcp/0".parse::<Multiaddr>().unwrap())
+        .unwrap();
+    let alice_addr = loop {
+        if let SwarmEvent::NewListenAddr { address, .. } = alice.select_next_some().await {
+            break address;
+        }
+    };
+    bob.dial(alice_addr).unwrap();
+    let connected = async {
+        loop {
+            tokio::select! {
+                event = alice.select_next_some() => {
+                    if let SwarmEvent::ConnectionEstablished { .. } = event {
+                        break;
+                    }
+                }
+                _ = bob.select_next_some() => {}
+            }
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(15), connected)
+        .await
+        .expect("bob did not connect in time");
+    let before = peer_bandwidth(&bob_metrics)
+        .get(&alice_id)
+        .copied()
+        .unwrap_or_default();
+
+    let text = "x".repeat(10_000);
+    let request = DirectRequest::Message(ChatMessage::new(bob_id, text));
+    bob.behaviour_mut().request_response.send_request(
+        &alice_id,
+        Request {
+            data: envelope::seal(&request),
+        },
+    );
+    tokio::time::timeout(Duration::from_secs(15), async {
+        loop {
+            tokio::select! {
+                _ = alice.select_next_some() => {}
+                event = bob.select_next_some() => {
+                    if let SwarmEvent::Behaviour(CustomBehaviourEvent::RequestResponse(
+                        request_response::Event::OutboundFailure { .. }
+                        | request_response::Event::Message { .. },
+                    )) = event
+                    {
+                        break;
+                    }
+                }
+            }
+        }
+    })
+    .await
+    .expect("alice did not answer in time");
+
+    // Alice never answered the request, so the stream was reset; the request
+    // still went out in full.
+    let after = peer_bandwidth(&bob_metrics)[&alice_id];
+    let sent = after.since(before);
+    assert!(sent.outbound > 10_000, "{:?}", sent);
+    let alice_bandwidth = peer_bandwidth(&alice_metrics)[&bob_id];
+    assert!(alice_bandwidth.inbound > 10_000, "{:?}", alice_bandwidth);
+}
+
+#[tokio::test]
+async fn handle_returns_the_stats_reply() {
+    let (requests_tx, mut requests_rx) = mpsc::channel(1);
+    let (events_tx, _) = broadcast::channel(1);
+    let handle = ChatHandle::new(requests_tx, events_tx);
+    let stats = Stats {
+        uptime_secs: 3723,
+        peer_id: peer(),
+        listen_addrs: vec!["/ip4/127.0.0.1/tcp/4001".parse().unwrap()],
+        external_addrs: vec!["/ip4/203.0.113.7/tcp/4001".parse().unwrap()],
+        connected_peers: 2,
+        nat: NatStatus::Unconfirmed,
+        port_mapping: PortMapping::NoGateway,
+        denied_connections: 1,
+        rooms: BTreeMap::from([(
+            "chat".to_string(),
+            MessageCounts {
+                sent: 3,
+                received: 5,
+            },
+        )]),
+        joined_rooms: 2,
+        direct_messages: MessageCounts {
+            sent: 1,
+            received: 0,
+        },
+        throttled_messages: 4,
+        shed_messages: 6,
+        bandwidth: BTreeMap::new(),
+        pending_outbound_requests: 0,
+        request_failures: RequestFailures::default(),
+        compression: CompressionSavings {
+            payloads: 3,
+            original_bytes: 4096,
+            compressed_bytes: 1024,
+        },
+        mesh_peers: BTreeMap::from([("chat".to_string(), 2)]),
+        history: HistoryStats {
+            in_memory_messages: 8,
+            memory_bytes: 2048,
+            file_bytes: 4096,
+        },
+    };
+
+    let node = {
+        let stats = stats.clone();
+        tokio::spawn(async move {
+            let (command, reply_tx) = requests_rx.recv().await.unwrap();
+            assert!(matches!(command, Command::Stats { .. }));
+            reply_tx.send(Ok(Reply::Stats(Box::new(stats)))).unwrap();
+        })
+    };
+
+    assert_eq!(handle.stats().await.unwrap(), stats);
+    node.await.unwrap();
+
+    let rendered = stats.to_string();
+    assert!(rendered.contains("\nUptime           1h 2m 3s\n"));
+    assert!(rendered.contains("\nConnected peers  2 (1 connections denied by limits)\n"));
+    assert!(rendered.contains("\nMessages         4 sent, 5 received\n"));
+    assert!(rendered.contains("\nPort mapping     no UPnP gateway found\n"));
+    assert!(rendered.contains("\nCompression: 3 payloads, 4.0 KiB down to 1.0 KiB (75% saved)\n"));
+    assert!(rendered.contains("\nJoined rooms     2\n"));
+    assert!(rendered.contains("\nNAT              external address not yet confirmed\n"));
+    assert!(rendered.contains("External addresses:\n    /ip4/203.0.113.7/tcp/4001\n"));
+    assert!(rendered.contains("#chat 3/5"));
+    assert!(rendered.contains("    4 dropped by the rate limit\n    6 shed during floods\n"));
+    assert!(
+        rendered.contains("\nHistory          8 messages in memory (~2.0 KiB), 4.0 KiB on disk\n")
+    );
+}
+
+#[test]
+fn limits_show_counts_against_their_caps() {
+    let limits = Limits {
+        established_incoming: 3,
+        established_outgoing: 9,
+        max_established: 64,
+        max_established_incoming: 56,
+        max_established_per_peer: 2,
+        pending_incoming: 1,
+        pending_outgoing: 0,
+        max_pending_incoming: Some(16),
+        max_pending_outgoing: None,
+        denied_connections: 5,
+    };
+    assert_eq!(
+        limits.to_string(),
+        "Established       12 of 64\n\
+         \x20 inbound         3 of 56\n\
+         \x20 outbound        9\n\
+         Per peer          at most 2\n\
+         Pending inbound   1 of 16\n\
+         Pending outbound  0, unlimited\n\
+         Refused           5"
+    );
+}
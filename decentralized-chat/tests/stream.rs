@@ -0,0 +1,179 @@
+use libp2p::{
+    futures::{io::Cursor, AsyncRead, StreamExt},
+    PeerId,
+};
+use libp2p_demo::{
+    compression::ContentEncoding,
+    stream::{StreamError, Streams, STREAM_PROTOCOL},
+    testing::{connect, TestNode},
+};
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Two connected nodes with their `Streams`, polled in the background so
+/// the streams make progress.
+async fn pair() -> (Streams, Streams, PeerId, PeerId) {
+    let mut nodes = vec![TestNode::new().await, TestNode::new().await];
+    connect(&mut nodes, 0, 1).await;
+    let (sender_id, receiver_id) = (nodes[0].peer_id(), nodes[1].peer_id());
+    let sender = Streams::new(&nodes[0].swarm.behaviour().stream).unwrap();
+    let receiver = Streams::new(&nodes[1].swarm.behaviour().stream).unwrap();
+    for mut node in nodes {
+        tokio::spawn(async move {
+            loop {
+                node.swarm.select_next_some().await;
+            }
+        });
+    }
+    (sender, receiver, sender_id, receiver_id)
+}
+
+/// Yields `payload` and then fails, like a file whose disk gave out.
+struct Failing {
+    payload: Cursor<Vec<u8>>,
+}
+
+impl AsyncRead for Failing {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match Pin::new(&mut self.payload).poll_read(cx, buf) {
+            Poll::Ready(Ok(0)) => Poll::Ready(Err(io::Error::other("disk on fire"))),
+            poll => poll,
+        }
+    }
+}
+
+#[tokio::test]
+async fn ten_megabytes_stream_between_memory_nodes() {
+    let (mut sender, mut receiver, sender_id, receiver_id) = pair().await;
+    let payload: Vec<u8> = (0..10 * 1024 * 1024u32).map(|i| (i % 251) as u8).collect();
+
+    let sending = tokio::spawn({
+        let payload = payload.clone();
+        async move {
+            sender
+                .send_stream(receiver_id, Cursor::new(payload), None)
+                .await
+        }
+    });
+    let incoming = tokio::time::timeout(TIMEOUT, receiver.accept_stream())
+        .await
+        .expect("no stream in time")
+        .unwrap();
+    assert_eq!(incoming.peer, sender_id);
+
+    let mut received = Cursor::new(Vec::new());
+    let len = tokio::time::timeout(TIMEOUT, incoming.read_to(&mut received))
+        .await
+        .expect("stream not read in time")
+        .unwrap();
+    assert_eq!(len, payload.len() as u64);
+    assert!(received.into_inner() == payload);
+    assert_eq!(sending.await.unwrap().unwrap(), payload.len() as u64);
+}
+
+#[tokio::test]
+async fn compressed_payloads_stream_with_fewer_frames_of_the_same_bytes() {
+    let (mut sender, mut receiver, _, receiver_id) = pair().await;
+    let payload = "a long pasted log line\n".repeat(200_000).into_bytes();
+    let sending = tokio::spawn({
+        let payload = payload.clone();
+        async move {
+            sender
+                .send_stream(
+                    receiver_id,
+                    Cursor::new(payload),
+                    Some(ContentEncoding::Zstd),
+                )
+                .await
+        }
+    });
+    let incoming = receiver.accept_stream().await.unwrap();
+    let mut received = Cursor::new(Vec::new());
+    let len = tokio::time::timeout(TIMEOUT, incoming.read_to(&mut received))
+        .await
+        .expect("stream not read in time")
+        .unwrap();
+    assert_eq!(len, payload.len() as u64);
+    assert!(received.into_inner() == payload);
+    assert_eq!(sending.await.unwrap().unwrap(), payload.len() as u64);
+}
+
+#[tokio::test]
+async fn an_empty_payload_streams() {
+    let (mut sender, mut receiver, _, receiver_id) = pair().await;
+    let sending = tokio::spawn(async move {
+        sender
+            .send_stream(receiver_id, Cursor::new(Vec::new()), None)
+            .await
+    });
+    let incoming = receiver.accept_stream().await.unwrap();
+    let mut received = Cursor::new(Vec::new());
+    assert_eq!(incoming.read_to(&mut received).await.unwrap(), 0);
+    assert_eq!(sending.await.unwrap().unwrap(), 0);
+}
+
+#[tokio::test]
+async fn a_stream_cut_short_is_a_reset_not_a_hang() {
+    let (mut sender, mut receiver, _, receiver_id) = pair().await;
+    let sending = tokio::spawn(async move {
+        let reader = Failing {
+            payload: Cursor::new(vec![1; 200 * 1024]),
+        };
+        sender.send_stream(receiver_id, reader, None).await
+    });
+    let incoming = receiver.accept_stream().await.unwrap();
+    let mut received = Cursor::new(Vec::new());
+    let result = tokio::time::timeout(TIMEOUT, incoming.read_to(&mut received))
+        .await
+        .expect("reading a reset stream hung");
+    assert!(matches!(result, Err(StreamError::Reset)), "{:?}", result);
+    assert!(matches!(sending.await.unwrap(), Err(StreamError::Io(_))));
+}
+
+#[tokio::test]
+async fn a_dropped_receiver_resets_the_sender() {
+    let (mut sender, mut receiver, _, receiver_id) = pair().await;
+    let sending = tokio::spawn(async move {
+        sender
+            .send_stream(receiver_id, Cursor::new(vec![0; 10 * 1024 * 1024]), None)
+            .await
+    });
+    drop(receiver.accept_stream().await.unwrap());
+    let result = tokio::time::timeout(TIMEOUT, sending)
+        .await
+        .expect("sending to a reset stream hung")
+        .unwrap();
+    assert!(matches!(result, Err(StreamError::Reset)), "{:?}", result);
+}
+
+#[tokio::test]
+async fn peers_not_accepting_streams_are_reported() {
+    let mut nodes = vec![TestNode::new().await, TestNode::new().await];
+    connect(&mut nodes, 0, 1).await;
+    let receiver_id = nodes[1].peer_id();
+    let mut sender = Streams::new(&nodes[0].swarm.behaviour().stream).unwrap();
+    for mut node in nodes {
+        tokio::spawn(async move {
+            loop {
+                node.swarm.select_next_some().await;
+            }
+        });
+    }
+    let result = sender
+        .send_stream(receiver_id, Cursor::new(vec![1]), None)
+        .await;
+    let Err(StreamError::Open(e)) = result else {
+        panic!("expected an open error, got {:?}", result);
+    };
+    assert!(e.to_string().contains(STREAM_PROTOCOL.as_ref()), "{}", e);
+}
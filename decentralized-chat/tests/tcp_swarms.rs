@@ -0,0 +1,297 @@
+use libp2p::{
+    connection_limits,
+    futures::StreamExt,
+    gossipsub, identify,
+    multiaddr::Protocol,
+    request_response,
+    swarm::{ListenError, SwarmEvent},
+    Multiaddr,
+};
+use libp2p_demo::{
+    behaviour::{
+        CustomBehaviourEvent, ProtocolVersion, Request, Response, CHAT_PROTOCOL,
+        LEGACY_CHAT_PROTOCOL,
+    },
+    config::{Config, ConnectionLimitsConfig, ProtocolConfig},
+    dial::describe_dial_error,
+    message::{ChatMessage, DirectRequest, DirectResponse, GossipMessage},
+    testing::{listening_tcp_swarm, tcp_swarm},
+};
+use serde_json::json;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(15);
+
+#[tokio::test]
+async fn gossipsub_chat_message_is_delivered_over_tcp() {
+    let (mut alice, alice_addr) = listening_tcp_swarm(&Config::default()).await;
+    let mut bob = tcp_swarm(&Config::default()).await;
+
+    let topic = gossipsub::IdentTopic::new("chat");
+    alice.behaviour_mut().gossipsub.subscribe(&topic).unwrap();
+    bob.behaviour_mut().gossipsub.subscribe(&topic).unwrap();
+    bob.dial(alice_addr).unwrap();
+
+    let sent = ChatMessage::new(*bob.local_peer_id(), "hello over tcp".to_string());
+    let payload = serde_json::to_vec(&GossipMessage::Chat(Box::new(sent.clone()))).unwrap();
+    let mut published = false;
+
+    let received = tokio::time::timeout(TIMEOUT, async {
+        loop {
+            tokio::select! {
+                event = alice.select_next_some() => {
+                    if let SwarmEvent::Behaviour(CustomBehaviourEvent::Gossipsub(
+                        gossipsub::Event::Message { message, .. },
+                    )) = event
+                    {
+                        break serde_json::from_slice::<GossipMessage>(&message.data).unwrap();
+                    }
+                }
+                event = bob.select_next_some() => {
+                    if let SwarmEvent::Behaviour(CustomBehaviourEvent::Gossipsub(
+                        gossipsub::Event::Subscribed { .. },
+                    )) = event
+                    {
+                        if !published {
+                            bob.behaviour_mut()
+                                .gossipsub
+                                .publish(topic.clone(), payload.clone())
+                                .unwrap();
+                            published = true;
+                        }
+                    }
+                }
+            }
+        }
+    })
+    .await
+    .expect("gossipsub message was not delivered in time");
+
+    let GossipMessage::Chat(received) = received else {
+        panic!("unexpected message {:?}", received);
+    };
+    assert_eq!(received.id, sent.id);
+    assert_eq!(received.peer_id, sent.peer_id);
+    assert_eq!(received.message, sent.message);
+    assert_eq!(received.timestamp, sent.timestamp);
+}
+
+#[tokio::test]
+async fn greeting_request_gets_a_welcome_response_over_tcp() {
+    let (mut alice, alice_addr) = listening_tcp_swarm(&Config::default()).await;
+    let mut bob = tcp_swarm(&Config::default()).await;
+    let alice_id = *alice.local_peer_id();
+
+    bob.add_peer_address(alice_id, alice_addr);
+    let greeting = ChatMessage::new(*bob.local_peer_id(), "Hello".to_string());
+    bob.behaviour_mut().request_response.send_request(
+        &alice_id,
+        Request {
+            data: json!(DirectRequest::Greeting(greeting.clone())),
+        },
+    );
+
+    let (from, welcome) = tokio::time::timeout(TIMEOUT, async {
+        loop {
+            tokio::select! {
+                event = alice.select_next_some() => {
+                    if let SwarmEvent::Behaviour(CustomBehaviourEvent::RequestResponse(
+                        request_response::Event::Message {
+                            peer,
+                            message: request_response::Message::Request { request, channel, .. },
+                        },
+                    )) = event
+                    {
+                        let Ok(DirectRequest::Greeting(received)) = serde_json::from_value(request.data) else {
+                            panic!("expected a greeting");
+                        };
+                        assert_eq!(received.id, greeting.id);
+                        assert_eq!(received.peer_id, peer);
+
+                        let welcome = ChatMessage::new(alice_id, format!("Welcome {}!", peer));
+                        alice
+                            .behaviour_mut()
+                            .request_response
+                            .send_response(channel, Response { data: json!(DirectResponse::Welcome(Box::new(welcome))) })
+                            .unwrap();
+                    }
+                }
+                event = bob.select_next_some() => {
+                    if let SwarmEvent::Behaviour(CustomBehaviourEvent::RequestResponse(
+                        request_response::Event::Message {
+                            peer,
+                            message: request_response::Message::Response { response, .. },
+                        },
+                    )) = event
+                    {
+                        let Ok(DirectResponse::Welcome(welcome)) = serde_json::from_value(response.data) else {
+                            panic!("expected a welcome");
+                        };
+                        break (peer, welcome);
+                    }
+                }
+            }
+        }
+    })
+    .await
+    .expect("greeting response was not delivered in time");
+
+    assert_eq!(from, alice_id);
+    assert_eq!(welcome.peer_id, alice_id);
+    assert_eq!(welcome.message, format!("Welcome {}!", bob.local_peer_id()));
+}
+
+#[tokio::test]
+async fn dns_multiaddr_is_resolved_when_dialing() {
+    let (mut alice, alice_addr) = listening_tcp_swarm(&Config::default()).await;
+    let mut bob = tcp_swarm(&Config::default()).await;
+    let alice_id = *alice.local_peer_id();
+
+    let port = alice_addr
+        .iter()
+        .find_map(|protocol| match protocol {
+            Protocol::Tcp(port) => Some(port),
+            _ => None,
+        })
+        .unwrap();
+    let dns_addr: Multiaddr = format!("/dns4/localhost/tcp/{}/p2p/{}", port, alice_id)
+        .parse()
+        .unwrap();
+    bob.dial(dns_addr).unwrap();
+
+    let connected = tokio::time::timeout(TIMEOUT, async {
+        loop {
+            tokio::select! {
+                _ = alice.select_next_some() => {}
+                event = bob.select_next_some() => match event {
+                    SwarmEvent::ConnectionEstablished { peer_id, .. } => break peer_id,
+                    SwarmEvent::OutgoingConnectionError { error, .. } => {
+                        panic!("dial failed: {}", describe_dial_error(&error))
+                    }
+                    _ => {}
+                },
+            }
+        }
+    })
+    .await
+    .expect("dns dial did not connect in time");
+
+    assert_eq!(connected, alice_id);
+}
+
+#[tokio::test]
+async fn inbound_connections_past_the_limit_are_denied() {
+    // One slot of two is kept for our own dials, so only one peer may dial in.
+    let config = Config {
+        connection_limits: ConnectionLimitsConfig {
+            max_established: 2,
+            reserved_outbound: 1,
+            ..ConnectionLimitsConfig::default()
+        },
+        ..Config::default()
+    };
+    let (mut alice, alice_addr) = listening_tcp_swarm(&config).await;
+
+    let (mut bob, _) = listening_tcp_swarm(&Config::default()).await;
+    let (mut carol, _) = listening_tcp_swarm(&Config::default()).await;
+    bob.dial(alice_addr.clone()).unwrap();
+
+    tokio::time::timeout(TIMEOUT, async {
+        let mut carol_dialed = false;
+        loop {
+            tokio::select! {
+                event = alice.select_next_some() => match event {
+                    SwarmEvent::ConnectionEstablished { peer_id, .. } => {
+                        assert_eq!(peer_id, *bob.local_peer_id());
+                        if !carol_dialed {
+                            carol.dial(alice_addr.clone()).unwrap();
+                            carol_dialed = true;
+                        }
+                    }
+                    SwarmEvent::IncomingConnectionError {
+                        error: ListenError::Denied { cause },
+                        ..
+                    } => {
+                        assert!(cause.downcast_ref::<connection_limits::Exceeded>().is_some());
+                        break;
+                    }
+                    _ => {}
+                },
+                _ = bob.select_next_some() => {}
+                _ = carol.select_next_some() => {}
+            }
+        }
+    })
+    .await
+    .expect("carol's connection was not denied");
+}
+
+#[test]
+fn newest_protocol_version_is_picked() {
+    let other = libp2p::StreamProtocol::new("/ipfs/id/1.0.0");
+    assert_eq!(ProtocolVersion::newest(std::slice::from_ref(&other)), None);
+    assert_eq!(
+        ProtocolVersion::newest(&[LEGACY_CHAT_PROTOCOL, other.clone()]),
+        Some(ProtocolVersion::Legacy)
+    );
+    assert_eq!(
+        ProtocolVersion::newest(&[LEGACY_CHAT_PROTOCOL, CHAT_PROTOCOL, other]),
+        Some(ProtocolVersion::V1)
+    );
+}
+
+#[tokio::test]
+async fn identify_reports_the_protocols_a_peer_speaks() {
+    let (mut alice, alice_addr) = listening_tcp_swarm(&Config::default()).await;
+    let mut bob = tcp_swarm(&Config {
+        protocol: ProtocolConfig {
+            legacy: false,
+            ..ProtocolConfig::default()
+        },
+        ..Config::default()
+    })
+    .await;
+    let alice_id = *alice.local_peer_id();
+    let bob_id = *bob.local_peer_id();
+    bob.dial(alice_addr).unwrap();
+
+    let (mut alice_sees, mut bob_sees) = (None, None);
+    tokio::time::timeout(TIMEOUT, async {
+        while alice_sees.is_none() || bob_sees.is_none() {
+            tokio::select! {
+                event = alice.select_next_some() => {
+                    if let SwarmEvent::Behaviour(CustomBehaviourEvent::Identify(
+                        identify::Event::Received { peer_id, info },
+                    )) = event
+                    {
+                        assert_eq!(peer_id, bob_id);
+                        alice_sees = Some(info.protocols);
+                    }
+                }
+                event = bob.select_next_some() => {
+                    if let SwarmEvent::Behaviour(CustomBehaviourEvent::Identify(
+                        identify::Event::Received { peer_id, info },
+                    )) = event
+                    {
+                        assert_eq!(peer_id, alice_id);
+                        bob_sees = Some(info.protocols);
+                    }
+                }
+            }
+        }
+    })
+    .await
+    .expect("peers did not identify each other in time");
+
+    let (alice_sees, bob_sees) = (alice_sees.unwrap(), bob_sees.unwrap());
+    assert!(bob_sees.contains(&LEGACY_CHAT_PROTOCOL));
+    assert_eq!(
+        ProtocolVersion::newest(&bob_sees),
+        Some(ProtocolVersion::V1)
+    );
+    assert!(!alice_sees.contains(&LEGACY_CHAT_PROTOCOL));
+    assert_eq!(
+        ProtocolVersion::newest(&alice_sees),
+        Some(ProtocolVersion::V1)
+    );
+}
@@ -0,0 +1,208 @@
+use libp2p::{
+    futures::StreamExt,
+    gossipsub, request_response,
+    swarm::{ListenError, SwarmEvent},
+};
+use libp2p_demo::{
+    behaviour::{CustomBehaviourEvent, Request, Response},
+    config::{Config, Security, SwarmConfig},
+    dial::{describe_dial_error, is_handshake_failure},
+    message::{ChatMessage, DirectRequest, DirectResponse, GossipMessage},
+    security::SecurityProtocol,
+    testing::{listening_tcp_swarm, tcp_swarm},
+};
+use serde_json::json;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(15);
+
+fn secured_with(security: Security) -> Config {
+    Config {
+        swarm: SwarmConfig {
+            security: Some(security),
+            ..SwarmConfig::default()
+        },
+        ..Config::default()
+    }
+}
+
+#[tokio::test]
+async fn tls_nodes_exchange_gossip_and_direct_messages() {
+    let (mut alice, alice_addr) = listening_tcp_swarm(&secured_with(Security::Tls)).await;
+    let mut bob = tcp_swarm(&secured_with(Security::Tls)).await;
+    let alice_id = *alice.local_peer_id();
+
+    let topic = gossipsub::IdentTopic::new("chat");
+    alice.behaviour_mut().gossipsub.subscribe(&topic).unwrap();
+    bob.behaviour_mut().gossipsub.subscribe(&topic).unwrap();
+    bob.add_peer_address(alice_id, alice_addr);
+
+    let greeting = ChatMessage::new(*bob.local_peer_id(), "Hello over TLS".to_string());
+    bob.behaviour_mut().request_response.send_request(
+        &alice_id,
+        Request {
+            data: json!(DirectRequest::Greeting(greeting.clone())),
+        },
+    );
+    let sent = ChatMessage::new(*bob.local_peer_id(), "gossip over TLS".to_string());
+    let payload = serde_json::to_vec(&GossipMessage::Chat(Box::new(sent.clone()))).unwrap();
+
+    let (mut gossiped, mut welcomed, mut published) = (None, None, false);
+    tokio::time::timeout(TIMEOUT, async {
+        while gossiped.is_none() || welcomed.is_none() {
+            tokio::select! {
+                event = alice.select_next_some() => match event {
+                    SwarmEvent::Behaviour(CustomBehaviourEvent::Gossipsub(
+                        gossipsub::Event::Message { message, .. },
+                    )) => {
+                        gossiped = Some(serde_json::from_slice::<GossipMessage>(&message.data).unwrap());
+                    }
+                    SwarmEvent::Behaviour(CustomBehaviourEvent::RequestResponse(
+                        request_response::Event::Message {
+                            message: request_response::Message::Request { request, channel, .. },
+                            ..
+                        },
+                    )) => {
+                        let Ok(DirectRequest::Greeting(received)) = serde_json::from_value(request.data) else {
+                            panic!("expected a greeting");
+                        };
+                        assert_eq!(received.id, greeting.id);
+                        let welcome = ChatMessage::new(alice_id, "Welcome!".to_string());
+                        alice
+                            .behaviour_mut()
+                            .request_response
+                            .send_response(channel, Response { data: json!(DirectResponse::Welcome(Box::new(welcome))) })
+                            .unwrap();
+                    }
+                    _ => {}
+                },
+                event = bob.select_next_some() => match event {
+                    SwarmEvent::Behaviour(CustomBehaviourEvent::Gossipsub(
+                        gossipsub::Event::Subscribed { .. },
+                    )) if !published => {
+                        bob.behaviour_mut()
+                            .gossipsub
+                            .publish(topic.clone(), payload.clone())
+                            .unwrap();
+                        published = true;
+                    }
+                    SwarmEvent::Behaviour(CustomBehaviourEvent::RequestResponse(
+                        request_response::Event::Message {
+                            message: request_response::Message::Response { response, .. },
+                            ..
+                        },
+                    )) => {
+                        let Ok(DirectResponse::Welcome(welcome)) = serde_json::from_value(response.data) else {
+                            panic!("expected a welcome");
+                        };
+                        welcomed = Some(welcome);
+                    }
+                    _ => {}
+                },
+            }
+        }
+    })
+    .await
+    .expect("messages were not delivered over TLS in time");
+
+    let Some(GossipMessage::Chat(received)) = gossiped else {
+        panic!("unexpected message {:?}", gossiped);
+    };
+    assert_eq!(received.id, sent.id);
+    assert_eq!(welcomed.unwrap().peer_id, alice_id);
+}
+
+#[tokio::test]
+async fn mismatched_security_fails_the_handshake_on_both_sides() {
+    let (mut alice, alice_addr) = listening_tcp_swarm(&secured_with(Security::Tls)).await;
+    let mut bob = tcp_swarm(&secured_with(Security::Noise)).await;
+    bob.dial(alice_addr).unwrap();
+
+    let (mut inbound, mut outbound) = (None, None);
+    tokio::time::timeout(TIMEOUT, async {
+        while inbound.is_none() || outbound.is_none() {
+            tokio::select! {
+                event = alice.select_next_some() => match event {
+                    SwarmEvent::IncomingConnectionError {
+                        error: ListenError::Transport(error),
+                        ..
+                    } => inbound = Some(is_handshake_failure(&error)),
+                    SwarmEvent::ConnectionEstablished { .. } => panic!("noise and TLS connected"),
+                    _ => {}
+                },
+                event = bob.select_next_some() => {
+                    if let SwarmEvent::OutgoingConnectionError { error, .. } = event {
+                        outbound = Some(describe_dial_error(&error));
+                    }
+                }
+            }
+        }
+    })
+    .await
+    .expect("the handshake did not fail in time");
+
+    assert_eq!(inbound, Some(true));
+    let outbound = outbound.unwrap();
+    assert!(outbound.contains("--security"), "{}", outbound);
+}
+
+/// Connects a node offering `dialer` to one offering `listener`, returning
+/// the protocol each side says secured the connection.
+async fn negotiate(
+    dialer: Security,
+    listener: Security,
+) -> (Option<SecurityProtocol>, Option<SecurityProtocol>) {
+    let (mut alice, alice_addr) = listening_tcp_swarm(&secured_with(listener)).await;
+    let mut bob = tcp_swarm(&secured_with(dialer)).await;
+    bob.dial(alice_addr).unwrap();
+
+    let (mut on_alice, mut on_bob) = (None, None);
+    tokio::time::timeout(TIMEOUT, async {
+        while on_alice.is_none() || on_bob.is_none() {
+            tokio::select! {
+                event = alice.select_next_some() => {
+                    if let SwarmEvent::ConnectionEstablished { connection_id, .. } = event {
+                        on_alice = Some(alice.behaviour().security.of(connection_id));
+                    }
+                }
+                event = bob.select_next_some() => match event {
+                    SwarmEvent::ConnectionEstablished { peer_id, connection_id, .. } => {
+                        on_bob = Some(bob.behaviour().security.of(connection_id));
+                        assert_eq!(
+                            bob.behaviour().security.of_peer(&peer_id),
+                            on_bob.flatten().into_iter().collect::<Vec<_>>()
+                        );
+                    }
+                    SwarmEvent::OutgoingConnectionError { error, .. } => {
+                        panic!("{} and {} failed to connect: {}", dialer, listener, describe_dial_error(&error))
+                    }
+                    _ => {}
+                },
+            }
+        }
+    })
+    .await
+    .expect("no connection in time");
+    (on_bob.unwrap(), on_alice.unwrap())
+}
+
+#[tokio::test]
+async fn nodes_offering_both_agree_with_either() {
+    use SecurityProtocol::{Noise, Tls};
+    for (dialer, listener, agreed) in [
+        (Security::Both, Security::Noise, Noise),
+        (Security::Both, Security::Tls, Tls),
+        (Security::Noise, Security::Both, Noise),
+        (Security::Tls, Security::Both, Tls),
+        // Noise is proposed first.
+        (Security::Both, Security::Both, Noise),
+    ] {
+        assert_eq!(
+            negotiate(dialer, listener).await,
+            (Some(agreed), Some(agreed)),
+            "{} dialing {}",
+            dialer,
+            listener
+        );
+    }
+}
@@ -0,0 +1,202 @@
+#![cfg(feature = "web-ui")]
+
+use libp2p::futures::{SinkExt, StreamExt};
+use libp2p_demo::{
+    command::{Command, Reply, RoomSummary},
+    event::ChatEvent,
+    handle::ChatHandle,
+    message::ChatMessage,
+    room_settings::Notify,
+    testing::peer,
+    web_ui,
+};
+use serde_json::{json, Value};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    net::TcpStream,
+    sync::{broadcast, mpsc},
+};
+use tokio_tungstenite::{
+    tungstenite::{
+        protocol::{frame::coding::CloseCode, Message},
+        Error,
+    },
+    MaybeTlsStream, WebSocketStream,
+};
+
+const TOKEN: &str = "web-token";
+
+type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Serves browser frontends in front of a stand-in for the node that is in
+/// the room "chat", broadcasts on `events_tx` and records the commands it
+/// runs.
+async fn serve(events_tx: broadcast::Sender<ChatEvent>) -> (SocketAddr, Arc<Mutex<Vec<Command>>>) {
+    let (requests_tx, mut requests_rx) = mpsc::channel(8);
+    let handle = ChatHandle::new(requests_tx, events_tx);
+    let commands = Arc::new(Mutex::new(Vec::new()));
+
+    let recorded = commands.clone();
+    tokio::spawn(async move {
+        while let Some((command, reply_tx)) = requests_rx.recv().await {
+            let reply = match &command {
+                Command::Rooms => Ok(Reply::Rooms {
+                    rooms: vec![RoomSummary {
+                        room: "chat".to_string(),
+                        current: true,
+                        private: false,
+                        providers: 0,
+                        notify: Notify::All,
+                    }],
+                }),
+                Command::Send { text, .. } if text == "fail" => {
+                    Err("Failed to publish message: InsufficientPeers".to_string())
+                }
+                Command::Send { .. } | Command::Msg { .. } => Ok(Reply::Sent {
+                    id: uuid::Uuid::new_v4(),
+                }),
+                other => panic!("unexpected command {:?}", other),
+            };
+            recorded.lock().unwrap().push(command);
+            let _ = reply_tx.send(reply);
+        }
+    });
+
+    let addr = web_ui::serve("127.0.0.1:0".parse().unwrap(), handle, TOKEN.to_string())
+        .await
+        .unwrap();
+    (addr, commands)
+}
+
+async fn connect(addr: SocketAddr, token: &str) -> Result<Client, Error> {
+    let url = format!("ws://{}/?token={}", addr, token);
+    let (client, _) = tokio_tungstenite::connect_async(url).await?;
+    Ok(client)
+}
+
+/// Waits until `count` browsers are reading the broadcast, as events sent
+/// before that are never seen by them.
+async fn until_subscribed(events_tx: &broadcast::Sender<ChatEvent>, count: usize) {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while events_tx.receiver_count() < count {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("browsers did not subscribe in time");
+}
+
+async fn next_frame(client: &mut Client) -> Message {
+    tokio::time::timeout(Duration::from_secs(5), client.next())
+        .await
+        .expect("no frame in time")
+        .unwrap()
+        .unwrap()
+}
+
+async fn next_json(client: &mut Client) -> Value {
+    let frame = next_frame(client).await;
+    serde_json::from_str(frame.to_text().unwrap()).unwrap()
+}
+
+async fn send(client: &mut Client, frame: Value) -> Value {
+    client.send(Message::Text(frame.to_string())).await.unwrap();
+    next_json(client).await
+}
+
+#[tokio::test]
+async fn browsers_without_the_token_are_refused() {
+    let (addr, _) = serve(broadcast::channel(1).0).await;
+    for token in ["", "wrong-token"] {
+        match connect(addr, token).await {
+            Err(Error::Http(response)) => assert_eq!(response.status(), 401),
+            other => panic!("expected a refusal, got {:?}", other.map(|_| ())),
+        }
+    }
+    assert!(connect(addr, TOKEN).await.is_ok());
+}
+
+#[tokio::test]
+async fn every_browser_gets_every_event() {
+    let connected = peer();
+    let (events_tx, _) = broadcast::channel(16);
+    let (addr, _) = serve(events_tx.clone()).await;
+
+    let mut first = connect(addr, TOKEN).await.unwrap();
+    let mut second = connect(addr, TOKEN).await.unwrap();
+    until_subscribed(&events_tx, 2).await;
+
+    let message = ChatMessage::new(connected, "hello".to_string());
+    events_tx
+        .send(ChatEvent::MessageReceived {
+            message: message.clone(),
+        })
+        .unwrap();
+
+    for client in [&mut first, &mut second] {
+        let received = next_json(client).await;
+        assert_eq!(received["event"], "message_received");
+        assert_eq!(received["message"]["id"], message.id.to_string());
+        assert_eq!(received["message"]["message"], "hello");
+    }
+}
+
+#[tokio::test]
+async fn frames_are_sent_as_this_node() {
+    let (addr, commands) = serve(broadcast::channel(1).0).await;
+    let mut client = connect(addr, TOKEN).await.unwrap();
+    let recipient = peer();
+
+    assert!(send(&mut client, json!({ "text": "hello" })).await["id"].is_string());
+    assert!(send(&mut client, json!({ "room": "chat", "text": "hello" })).await["id"].is_string());
+    let reply = send(
+        &mut client,
+        json!({ "peer": recipient.to_string(), "text": "psst" }),
+    )
+    .await;
+    assert!(reply["id"].is_string());
+
+    assert_eq!(
+        send(&mut client, json!({ "room": "rust", "text": "hello" })).await["error"],
+        "not in room rust; join it first"
+    );
+    assert_eq!(
+        send(&mut client, json!({ "text": "fail" })).await["error"],
+        "Failed to publish message: InsufficientPeers"
+    );
+    assert_eq!(
+        send(&mut client, json!({ "text": " " })).await["error"],
+        "text is empty"
+    );
+    client.send(Message::Text("{".to_string())).await.unwrap();
+    assert!(next_json(&mut client).await["error"].is_string());
+
+    let commands = commands.lock().unwrap();
+    assert!(matches!(commands[0], Command::Send { room: None, .. }));
+    assert!(matches!(commands[3], Command::Msg { peer, .. } if peer == recipient));
+}
+
+#[tokio::test]
+async fn browsers_that_fall_behind_are_disconnected() {
+    let (events_tx, _) = broadcast::channel(512);
+    let (addr, _) = serve(events_tx.clone()).await;
+    let mut client = connect(addr, TOKEN).await.unwrap();
+    until_subscribed(&events_tx, 1).await;
+
+    // The session doesn't run until this test yields, so all of these are
+    // waiting for it at once.
+    for _ in 0..500 {
+        events_tx
+            .send(ChatEvent::PeerOnline { peer: peer() })
+            .unwrap();
+    }
+
+    match next_frame(&mut client).await {
+        Message::Close(Some(close)) => assert_eq!(close.code, CloseCode::Again),
+        other => panic!("expected a close frame, got {:?}", other),
+    }
+}
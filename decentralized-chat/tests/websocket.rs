@@ -0,0 +1,46 @@
+use libp2p::{futures::StreamExt, swarm::SwarmEvent, Multiaddr};
+use libp2p_demo::{config::Config, dial::describe_dial_error, testing::tcp_swarm};
+use std::time::Duration;
+
+#[tokio::test]
+async fn node_listening_only_on_websocket_accepts_dials() {
+    let mut alice = tcp_swarm(&Config::default()).await;
+    let mut bob = tcp_swarm(&Config::default()).await;
+    let alice_id = *alice.local_peer_id();
+
+    alice
+        .listen_on("/ip4/127.0.0.1/tcp/0/ws".parse::<Multiaddr>().unwrap())
+        .unwrap();
+    let alice_addr = loop {
+        if let SwarmEvent::NewListenAddr { address, .. } = alice.select_next_some().await {
+            break address;
+        }
+    };
+    assert!(alice_addr.to_string().ends_with("/ws"), "{}", alice_addr);
+    bob.dial(alice_addr.with_p2p(alice_id).unwrap()).unwrap();
+
+    let (connected, endpoint) = tokio::time::timeout(Duration::from_secs(15), async {
+        loop {
+            tokio::select! {
+                _ = alice.select_next_some() => {}
+                event = bob.select_next_some() => match event {
+                    SwarmEvent::ConnectionEstablished { peer_id, endpoint, .. } => {
+                        break (peer_id, endpoint);
+                    }
+                    SwarmEvent::OutgoingConnectionError { error, .. } => {
+                        panic!("dial failed: {}", describe_dial_error(&error))
+                    }
+                    _ => {}
+                },
+            }
+        }
+    })
+    .await
+    .expect("websocket dial did not connect in time");
+
+    assert_eq!(connected, alice_id);
+    assert!(endpoint
+        .get_remote_address()
+        .iter()
+        .any(|protocol| protocol.tag() == "ws"));
+}